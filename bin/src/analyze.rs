@@ -0,0 +1,696 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use roaring::RoaringTreemap;
+
+use crate::export_graph::DEFAULT_HIGHWAYS;
+use crate::region::{read_regions, Area, Bbox};
+use crate::scan::ScanArgs;
+use crate::sorter::Sorter;
+
+/// Number of way IDs printed for each island
+const SAMPLE_SIZE: usize = 10;
+
+/// Largest height in pixels of a density image
+const MAX_IMAGE_HEIGHT: u32 = 8192;
+
+#[derive(Parser)]
+/// Analyze the data in an OSMX database
+pub struct CliArgs {
+    #[command(subcommand)]
+    analysis: Analysis,
+}
+
+#[derive(Subcommand)]
+enum Analysis {
+    Connectivity(ConnectivityArgs),
+    DuplicateNodes(DuplicateNodesArgs),
+    OrphanNodes(OrphanNodesArgs),
+    Density(DensityArgs),
+    Edits(EditsArgs),
+}
+
+#[derive(Parser)]
+/// Find parts of the road network which aren't connected to the rest of it (islands),
+/// which are often data errors. Prints one tab-separated line per island, with its
+/// number of ways and nodes, the location of one of its nodes, and some of its way IDs.
+///
+/// Components with a node outside the region (or missing from the locations table) are
+/// assumed to connect to the rest of the network beyond it, and aren't reported.
+struct ConnectivityArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Only analyze ways within this bounding box, given as west,south,east,north in
+    /// degrees (default: the whole database)
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<Bbox>,
+    /// Values of the highway tag to treat as routable (default: roads which cars can use)
+    #[arg(long, value_delimiter = ',')]
+    highway: Vec<String>,
+    /// Only report islands with at most this many ways
+    #[arg(long)]
+    max_ways: Option<usize>,
+}
+
+#[derive(Parser)]
+/// Find distinct nodes with exactly the same coordinates. Prints one tab-separated line
+/// per location shared by more than one node, with its coordinates, the number of nodes
+/// and their IDs.
+struct DuplicateNodesArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Only consider nodes which have tags
+    #[arg(long)]
+    tagged: bool,
+    /// Only consider nodes which are part of a way
+    #[arg(long)]
+    in_ways: bool,
+    /// Directory for temporary files used while sorting (default: a new directory in the
+    /// system's temporary directory)
+    #[arg(long)]
+    tempdir: Option<PathBuf>,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Parser)]
+/// Find untagged nodes which aren't part of any way or relation. These carry no
+/// information, so are usually left behind by editing mistakes and can be deleted.
+/// Prints one tab-separated line per orphan node, with its ID and coordinates.
+struct OrphanNodesArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Only print the number of orphan nodes
+    #[arg(long)]
+    count: bool,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DensityFormat {
+    Geojson,
+    Png,
+}
+
+#[derive(Parser)]
+/// Count the nodes in each S2 cell at a given level, using the spatial index, to show
+/// where the data is dense or sparse.
+///
+/// The GeoJSON format has one polygon feature per cell containing nodes, with the cell's
+/// token, level and node count as properties. The PNG format is a grayscale image of the
+/// area covered by the data (in plate carrée projection), with each pixel's brightness
+/// proportional to the logarithm of its node count.
+struct DensityArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the file to create
+    output_file: PathBuf,
+    /// S2 cell level to count nodes at, from 0 (the largest cells) to 16 (the level of
+    /// the spatial index)
+    #[arg(long, default_value_t = 12, value_parser = clap::value_parser!(u64).range(0..=16))]
+    level: u64,
+    /// Output file format
+    #[arg(long, value_enum, default_value = "geojson")]
+    format: DensityFormat,
+    /// Width of the PNG image in pixels (its height follows from the data's extent)
+    #[arg(long, default_value_t = 1024)]
+    width: u32,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum EditsGrouping {
+    Month,
+    Year,
+    User,
+    Cell,
+    Region,
+}
+
+#[derive(Parser)]
+/// Count the elements last edited in each month or year, by each user, or in each S2
+/// cell or region, from the element metadata (which the database must have been expanded
+/// with --metadata to store). Prints one tab-separated line per group, with the number of
+/// elements and of distinct changesets and users (or, by user, the user's ID and name
+/// and the times of their first and last edits).
+///
+/// Only the current version of each element is counted, so an element edited many times
+/// counts once, for its latest edit, and untagged nodes (whose metadata isn't stored)
+/// aren't counted. Nodes are located by their coordinates and ways by
+/// their first node; relations have no location, so aren't counted by cell or region.
+struct EditsArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// How to group the elements
+    #[arg(long, value_enum, default_value = "month")]
+    by: EditsGrouping,
+    /// S2 cell level to group elements at, with --by cell
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u64).range(0..=30))]
+    level: u64,
+    /// GeoJSON FeatureCollection of the regions to group elements by, with --by region,
+    /// each named by its name property (or id)
+    #[arg(long, required_if_eq("by", "region"))]
+    regions: Option<PathBuf>,
+    /// Only count elements within this bounding box, given as west,south,east,north in
+    /// degrees: nodes located in it, ways with a node in it, and relations with a node or
+    /// way member in it
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<Bbox>,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+/// A disjoint-set forest over node IDs, used to find connected components
+#[derive(Default)]
+struct Components {
+    indices: HashMap<u64, usize>,
+    parents: Vec<usize>,
+}
+
+impl Components {
+    fn index(&mut self, node: u64) -> usize {
+        let next = self.parents.len();
+        let index = *self.indices.entry(node).or_insert(next);
+        if index == next {
+            self.parents.push(next);
+        }
+        index
+    }
+
+    fn find(&mut self, mut index: usize) -> usize {
+        while self.parents[index] != index {
+            // path halving
+            self.parents[index] = self.parents[self.parents[index]];
+            index = self.parents[index];
+        }
+        index
+    }
+
+    fn union(&mut self, a: u64, b: u64) {
+        let a = self.index(a);
+        let b = self.index(b);
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents[a] = b;
+        }
+    }
+}
+
+/// Summary of one connected component of the road network
+#[derive(Default)]
+struct Island {
+    ways: Vec<u64>,
+    nodes: usize,
+    location: Option<(f64, f64)>,
+    /// Whether the component has a node outside the region
+    open: bool,
+}
+
+fn connectivity(args: &ConnectivityArgs) -> Result<(), Box<dyn Error>> {
+    let highways: Vec<&str> = if args.highway.is_empty() {
+        DEFAULT_HIGHWAYS.to_vec()
+    } else {
+        args.highway.iter().map(|h| h.as_str()).collect()
+    };
+
+    let db = osmx::Database::open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let ways = txn.ways()?;
+    let locations = txn.locations()?;
+
+    let in_bbox = |lon: f64, lat: f64| match args.bbox {
+        Some(bbox) => bbox.contains(lon, lat),
+        None => true,
+    };
+
+    // find the candidate ways, using the spatial index if there's a region
+    let way_ids: RoaringTreemap = match args.bbox {
+        Some(bbox) => {
+            let region = bbox.region();
+            let cell_nodes = txn.cell_nodes()?;
+            let node_ways = txn.node_ways()?;
+            let mut way_ids = RoaringTreemap::new();
+            for node_id in cell_nodes.find_in_region(&region) {
+                way_ids.extend(node_ways.get(node_id));
+            }
+            way_ids
+        }
+        _ => ways.ids().collect(),
+    };
+
+    // join the nodes of each routable way into components
+    let mut components = Components::default();
+    let mut routable_ways = vec![];
+    for way_id in way_ids {
+        let Some(way) = ways.get(way_id)? else {
+            continue;
+        };
+        match way.tag("highway") {
+            Some(highway) if highways.contains(&highway) => (),
+            _ => continue,
+        }
+
+        let nodes: Vec<u64> = way.nodes().collect();
+        for pair in nodes.windows(2) {
+            components.union(pair[0], pair[1]);
+        }
+        if let Some(&first) = nodes.first() {
+            components.index(first);
+            routable_ways.push((way_id, first));
+        }
+    }
+
+    let mut islands: HashMap<usize, Island> = HashMap::new();
+    let nodes: Vec<(u64, usize)> = components.indices.iter().map(|(&n, &i)| (n, i)).collect();
+    for (node, index) in nodes {
+        let island = islands.entry(components.find(index)).or_default();
+        island.nodes += 1;
+        match locations.get(node)? {
+            Some(loc) if in_bbox(loc.lon(), loc.lat()) => {
+                island.location.get_or_insert((loc.lon(), loc.lat()));
+            }
+            _ => island.open = true,
+        }
+    }
+    for (way_id, first) in routable_ways {
+        let index = components.index(first);
+        islands
+            .get_mut(&components.find(index))
+            .unwrap()
+            .ways
+            .push(way_id);
+    }
+
+    // the largest component is the main network; everything else is an island
+    let mut islands: Vec<Island> = islands.into_values().collect();
+    islands.sort_by_key(|island| std::cmp::Reverse(island.ways.len()));
+    let component_count = islands.len();
+    let islands: Vec<Island> = islands
+        .into_iter()
+        .skip(1)
+        .filter(|island| !island.open)
+        .filter(|island| args.max_ways.is_none_or(|max| island.ways.len() <= max))
+        .collect();
+
+    println!("ways\tnodes\tlon\tlat\tway_ids");
+    for island in islands.iter() {
+        let (lon, lat) = island.location.unwrap_or_default();
+        let sample: Vec<String> = island
+            .ways
+            .iter()
+            .take(SAMPLE_SIZE)
+            .map(|id| id.to_string())
+            .collect();
+        println!(
+            "{}\t{}\t{:.7}\t{:.7}\t{}",
+            island.ways.len(),
+            island.nodes,
+            lon,
+            lat,
+            sample.join(",")
+        );
+    }
+
+    eprintln!(
+        "{} connected components, {} islands reported",
+        component_count,
+        islands.len()
+    );
+
+    Ok(())
+}
+
+fn duplicate_nodes(args: &DuplicateNodesArgs) -> Result<(), Box<dyn Error>> {
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let locations = txn.locations()?;
+    let nodes = txn.nodes()?;
+    let node_ways = txn.node_ways()?;
+
+    let tempdir = match &args.tempdir {
+        Some(tempdir) => tempdir.clone(),
+        None => std::env::temp_dir().join(format!("osmx-analyze-{}", std::process::id())),
+    };
+    std::fs::create_dir_all(&tempdir)?;
+
+    // sort the nodes by their coordinates (in units of 1e-7 degrees, as stored), so that
+    // nodes at the same location are adjacent
+    let mut sorter: Sorter<(i32, i32, u64)> = Sorter::new(&tempdir, "duplicate_nodes");
+    for (node_id, location) in locations.iter() {
+        if args.tagged && !nodes.contains(node_id)? {
+            continue;
+        }
+        if args.in_ways && node_ways.get(node_id).next().is_none() {
+            continue;
+        }
+        let lon = (location.lon() * 1e7).round() as i32;
+        let lat = (location.lat() * 1e7).round() as i32;
+        sorter.push((lon, lat, node_id));
+    }
+
+    println!("lon\tlat\tcount\tnode_ids");
+    let mut location_count = 0;
+    let mut node_count = 0;
+    let mut print_group = |coords: (i32, i32), group: &[u64]| {
+        if group.len() < 2 {
+            return;
+        }
+        let ids: Vec<String> = group.iter().map(|id| id.to_string()).collect();
+        println!(
+            "{:.7}\t{:.7}\t{}\t{}",
+            coords.0 as f64 / 1e7,
+            coords.1 as f64 / 1e7,
+            group.len(),
+            ids.join(",")
+        );
+        location_count += 1;
+        node_count += group.len();
+    };
+
+    let mut current = None;
+    let mut group = vec![];
+    for (lon, lat, node_id) in sorter.sorted() {
+        if current != Some((lon, lat)) {
+            if let Some(coords) = current {
+                print_group(coords, &group);
+            }
+            current = Some((lon, lat));
+            group.clear();
+        }
+        group.push(node_id);
+    }
+    if let Some(coords) = current {
+        print_group(coords, &group);
+    }
+
+    std::fs::remove_dir_all(&tempdir)?;
+
+    eprintln!(
+        "{} nodes share {} locations with other nodes",
+        node_count, location_count
+    );
+    args.scan.report_skipped(&db);
+
+    Ok(())
+}
+
+fn orphan_nodes(args: &OrphanNodesArgs) -> Result<(), Box<dyn Error>> {
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let locations = txn.locations()?;
+    let nodes = txn.nodes()?;
+    let node_ways = txn.node_ways()?;
+    let node_relations = txn.node_relations()?;
+
+    if !args.count {
+        println!("node_id\tlon\tlat");
+    }
+    let mut node_count: u64 = 0;
+    let mut orphan_count: u64 = 0;
+    for (node_id, location) in locations.iter() {
+        node_count += 1;
+        // untagged nodes are only stored in the locations table
+        if nodes.contains(node_id)? {
+            continue;
+        }
+        if node_ways.get(node_id).next().is_some() || node_relations.get(node_id).next().is_some() {
+            continue;
+        }
+        if !args.count {
+            println!("{}\t{:.7}\t{:.7}", node_id, location.lon(), location.lat());
+        }
+        orphan_count += 1;
+    }
+
+    if args.count {
+        println!("{}", orphan_count);
+    }
+    eprintln!("{} of {} nodes are orphans", orphan_count, node_count);
+    args.scan.report_skipped(&db);
+
+    Ok(())
+}
+
+/// The GeoJSON geometry of an S2 cell, as a polygon with the cell's four vertices
+pub fn cell_polygon_geojson(cell_id: s2::cellid::CellID) -> String {
+    let cell = s2::cell::Cell::from(cell_id);
+    // the vertices are counterclockwise, as GeoJSON requires for exterior rings
+    let ring: Vec<String> = (0..=4)
+        .map(|k| {
+            let vertex = s2::latlng::LatLng::from(cell.vertex(k % 4));
+            format!("[{:.7},{:.7}]", vertex.lng.deg(), vertex.lat.deg())
+        })
+        .collect();
+    format!(
+        "{{\"type\":\"Polygon\",\"coordinates\":[[{}]]}}",
+        ring.join(",")
+    )
+}
+
+fn write_density_geojson(
+    writer: &mut impl Write,
+    counts: &BTreeMap<u64, u64>,
+) -> Result<(), std::io::Error> {
+    writeln!(writer, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+    for (i, (&cell_id, &count)) in counts.iter().enumerate() {
+        let cell_id = s2::cellid::CellID(cell_id);
+        let separator = if i + 1 < counts.len() { "," } else { "" };
+        writeln!(
+            writer,
+            "{{\"type\":\"Feature\",\"properties\":{{\"cell\":\"{}\",\"level\":{},\"count\":{}}},\"geometry\":{}}}{}",
+            cell_id.to_token(),
+            cell_id.level(),
+            count,
+            cell_polygon_geojson(cell_id),
+            separator
+        )?;
+    }
+    writeln!(writer, "]}}")
+}
+
+fn write_png_chunk(
+    writer: &mut impl Write,
+    kind: &[u8; 4],
+    data: &[u8],
+) -> Result<(), std::io::Error> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc.finalize().to_be_bytes())
+}
+
+fn write_density_png(
+    writer: &mut impl Write,
+    counts: &BTreeMap<u64, u64>,
+    width: u32,
+) -> Result<(), Box<dyn Error>> {
+    let centers: Vec<((f64, f64), u64)> = counts
+        .iter()
+        .map(|(&cell_id, &count)| {
+            let center = s2::latlng::LatLng::from(s2::cellid::CellID(cell_id));
+            ((center.lng.deg(), center.lat.deg()), count)
+        })
+        .collect();
+
+    // the image covers the extent of the cell centers
+    let (mut west, mut south) = (f64::INFINITY, f64::INFINITY);
+    let (mut east, mut north) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for &((lon, lat), _) in centers.iter() {
+        (west, east) = (west.min(lon), east.max(lon));
+        (south, north) = (south.min(lat), north.max(lat));
+    }
+    let lon_span = (east - west).max(1e-9);
+    let lat_span = (north - south).max(1e-9);
+    let height = ((width as f64 * lat_span / lon_span).round() as u32).clamp(1, MAX_IMAGE_HEIGHT);
+
+    let mut pixels = vec![0u64; width as usize * height as usize];
+    for ((lon, lat), count) in centers {
+        let x = ((lon - west) / lon_span * (width - 1) as f64).round() as usize;
+        let y = ((north - lat) / lat_span * (height - 1) as f64).round() as usize;
+        pixels[y * width as usize + x] += count;
+    }
+    let max = pixels.iter().copied().max().unwrap_or(0);
+    let scale = 255.0 / (max as f64).ln_1p().max(1e-9);
+
+    // each row of a PNG starts with a filter type byte (0 for none)
+    let mut raw = Vec::with_capacity(pixels.len() + height as usize);
+    for row in pixels.chunks(width as usize) {
+        raw.push(0);
+        raw.extend(
+            row.iter()
+                .map(|&count| ((count as f64).ln_1p() * scale).round() as u8),
+        );
+    }
+    let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+    encoder.write_all(&raw)?;
+    let compressed = encoder.finish()?;
+
+    // 8-bit grayscale, with default compression, filtering and no interlacing
+    let mut header = vec![];
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    header.extend([8, 0, 0, 0, 0]);
+
+    writer.write_all(&[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'])?;
+    write_png_chunk(writer, b"IHDR", &header)?;
+    write_png_chunk(writer, b"IDAT", &compressed)?;
+    write_png_chunk(writer, b"IEND", &[])?;
+    Ok(())
+}
+
+fn density(args: &DensityArgs) -> Result<(), Box<dyn Error>> {
+    if args.width == 0 {
+        return Err("--width must be at least 1".into());
+    }
+
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let cell_nodes = txn.cell_nodes()?;
+
+    let mut counts: BTreeMap<u64, u64> = BTreeMap::new();
+    let mut node_count: u64 = 0;
+    for (cell_id, _) in cell_nodes.iter() {
+        let cell_id = s2::cellid::CellID(cell_id).parent(args.level);
+        *counts.entry(cell_id.0).or_default() += 1;
+        node_count += 1;
+    }
+
+    let mut writer = BufWriter::new(File::create(&args.output_file)?);
+    match args.format {
+        DensityFormat::Geojson => write_density_geojson(&mut writer, &counts)?,
+        DensityFormat::Png => write_density_png(&mut writer, &counts, args.width)?,
+    }
+    writer.flush()?;
+
+    eprintln!("{} nodes in {} cells", node_count, counts.len());
+    args.scan.report_skipped(&db);
+
+    Ok(())
+}
+
+fn edits(args: &EditsArgs) -> Result<(), Box<dyn Error>> {
+    let region = args.bbox.map(|bbox| bbox.region());
+
+    // the polygons of the regions are joined separately, and their counts added up
+    let mut names = vec![];
+    let mut polygon_regions = vec![];
+    let mut polygons = vec![];
+    if let (EditsGrouping::Region, Some(path)) = (args.by, &args.regions) {
+        for (index, (name, area)) in read_regions(path)?.into_iter().enumerate() {
+            names.push(name);
+            if let Area::Polygons(area_polygons) = area {
+                polygon_regions.extend(std::iter::repeat_n(index, area_polygons.len()));
+                polygons.extend(area_polygons);
+            }
+        }
+    }
+    let join = osmx::SpatialJoin::new(polygons);
+
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let options = osmx::edits::EditStatsOptions {
+        region: region.as_ref(),
+        cell_level: (args.by == EditsGrouping::Cell).then_some(args.level),
+        regions: (args.by == EditsGrouping::Region).then_some(&join),
+        ..Default::default()
+    };
+    let stats = osmx::edits::edit_stats(&txn, &options)?;
+    if stats.elements == 0 && stats.without_metadata > 0 {
+        return Err(
+            "database has no element metadata (see the --metadata option of expand)".into(),
+        );
+    }
+
+    let counts = |counts: &osmx::edits::EditCounts| {
+        format!(
+            "{}\t{}\t{}",
+            counts.elements,
+            counts.changesets.len(),
+            counts.users.len()
+        )
+    };
+    match args.by {
+        EditsGrouping::Month => {
+            println!("month\telements\tchangesets\tusers");
+            for ((year, month), month_counts) in stats.months.iter() {
+                println!("{:04}-{:02}\t{}", year, month, counts(month_counts));
+            }
+        }
+        EditsGrouping::Year => {
+            println!("year\telements\tchangesets\tusers");
+            for (year, year_counts) in stats.years().iter() {
+                println!("{:04}\t{}", year, counts(year_counts));
+            }
+        }
+        EditsGrouping::User => {
+            let mut users: Vec<_> = stats.users.iter().collect();
+            users.sort_by_key(|(&uid, edits)| (std::cmp::Reverse(edits.elements), uid));
+            println!("uid\tuser\telements\tchangesets\tfirst_edit\tlast_edit");
+            for (uid, edits) in users {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    uid,
+                    edits.user,
+                    edits.elements,
+                    edits.changesets.len(),
+                    osmx::interop::format_timestamp(edits.first_timestamp as i64),
+                    osmx::interop::format_timestamp(edits.last_timestamp as i64)
+                );
+            }
+        }
+        EditsGrouping::Cell => {
+            println!("cell\tlon\tlat\telements\tchangesets\tusers");
+            for (&cell_id, cell_counts) in stats.cells.iter() {
+                let cell_id = s2::cellid::CellID(cell_id);
+                let center = s2::latlng::LatLng::from(cell_id);
+                println!(
+                    "{}\t{:.7}\t{:.7}\t{}",
+                    cell_id.to_token(),
+                    center.lng.deg(),
+                    center.lat.deg(),
+                    counts(cell_counts)
+                );
+            }
+        }
+        EditsGrouping::Region => {
+            let mut region_counts = vec![osmx::edits::EditCounts::default(); names.len()];
+            for (&polygon, polygon_counts) in stats.regions.iter() {
+                let region = &mut region_counts[polygon_regions[polygon]];
+                region.elements += polygon_counts.elements;
+                region.changesets |= &polygon_counts.changesets;
+                region.users |= &polygon_counts.users;
+            }
+            println!("region\telements\tchangesets\tusers");
+            for (name, region) in names.iter().zip(region_counts.iter()) {
+                println!("{}\t{}", name, counts(region));
+            }
+        }
+    }
+
+    eprintln!(
+        "{} elements counted ({} without metadata)",
+        stats.elements, stats.without_metadata
+    );
+    args.scan.report_skipped(&db);
+
+    Ok(())
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    match &args.analysis {
+        Analysis::Connectivity(args) => connectivity(args),
+        Analysis::DuplicateNodes(args) => duplicate_nodes(args),
+        Analysis::OrphanNodes(args) => orphan_nodes(args),
+        Analysis::Density(args) => density(args),
+        Analysis::Edits(args) => edits(args),
+    }
+}
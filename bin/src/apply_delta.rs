@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::PathBuf;
+
+use clap::Parser;
+use lmdb::{Cursor, Transaction};
+use osmx::KeyByteOrder;
+
+use crate::delta::{BaseIdentity, DeltaHeader, DeltaRecord, DELTA_MAGIC};
+use crate::environment::{check_readers, open_env, require_database, with_writer_lock};
+use crate::trace;
+
+#[derive(Parser)]
+/// Apply a delta created by the delta subcommand to (a copy of) the old database it was
+/// computed from, turning it into the new database
+pub struct CliArgs {
+    /// Path to the .osmx file to update in place
+    file: PathBuf,
+    /// Path to the delta file
+    delta_file: PathBuf,
+    /// Before writing, clear reader slots left behind by processes which crashed, which
+    /// would otherwise keep old pages from being reused
+    #[arg(long)]
+    check_readers: bool,
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    // taking the writer lock would create an empty database
+    require_database(&args.file)?;
+    with_writer_lock(&args.file, "apply-delta", || apply_delta(args))
+}
+
+fn apply_delta(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let mut file = BufReader::new(File::open(&args.delta_file)?);
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    if &magic != DELTA_MAGIC {
+        return Err("not an OSMX delta file (or one made by an older version of osmx)".into());
+    }
+
+    let mut decoder = zstd::stream::Decoder::with_buffer(file)?;
+    let header: DeltaHeader = bincode::deserialize_from(&mut decoder)?;
+    if KeyByteOrder::from_bytes(header.key_byte_order.as_bytes())? != KeyByteOrder::NATIVE {
+        return Err("delta was created on a host with a different byte order".into());
+    }
+
+    let env = open_env(&args.file, false)?;
+    if let Some(difference) = header.base.difference(&BaseIdentity::of(&env)?) {
+        return Err(format!(
+            "delta does not apply to this database, which isn't the one it was computed from ({})",
+            difference
+        )
+        .into());
+    }
+    if args.check_readers {
+        let cleared = check_readers(&env)?;
+        eprintln!("cleared {} stale reader slots", cleared);
+    }
+
+    // tables must be opened (or created) before the transaction begins, since opening a
+    // table uses a transaction of its own
+    let mut tables = HashMap::new();
+    for (name, flags) in header.tables.iter() {
+        let flags = lmdb::DatabaseFlags::from_bits_truncate(*flags);
+        tables.insert(name.as_str(), env.create_db(Some(name), flags)?);
+    }
+    let mut dropped_tables = vec![];
+    for name in header.dropped_tables.iter() {
+        match env.open_db(Some(name)) {
+            Ok(db) => dropped_tables.push(db),
+            Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let mut txn = env.begin_rw_txn()?;
+
+    for db in dropped_tables {
+        // the handle isn't used again after the table is dropped
+        unsafe { txn.drop_db(db)? };
+    }
+
+    // all changes are made in a single transaction, so a delta which doesn't apply to this
+    // database (e.g. because it deletes an entry which doesn't exist) leaves it unchanged
+    let mut table = None;
+    let mut table_phase: Option<trace::Phase> = None;
+    let mut count: u64 = 0;
+    loop {
+        let record: DeltaRecord = bincode::deserialize_from(&mut decoder)?;
+        match record {
+            DeltaRecord::Table(name) => {
+                // end the previous table's phase before starting the next one
+                if let Some(phase) = table_phase.take() {
+                    phase.end();
+                }
+                table_phase = Some(trace::phase(&name));
+                if let Some(previous) = table.replace(name) {
+                    eprintln!("applied {}: {} changes", previous, count);
+                }
+                count = 0;
+            }
+            DeltaRecord::Put(raw_key, raw_val) => {
+                let db = current_table(&tables, &table)?;
+                txn.put(db, &raw_key, &raw_val, lmdb::WriteFlags::empty())?;
+                count += 1;
+            }
+            DeltaRecord::Delete(raw_key, raw_val) => {
+                let db = current_table(&tables, &table)?;
+                match delete(&mut txn, db, &raw_key, raw_val.as_deref()) {
+                    Ok(()) => (),
+                    Err(lmdb::Error::NotFound) => {
+                        return Err(format!(
+                        "delta does not apply to this database (table {} has no entry to delete)",
+                        table.as_deref().unwrap_or_default()
+                    )
+                        .into())
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+                count += 1;
+            }
+            DeltaRecord::End => break,
+        }
+    }
+    if let Some(phase) = table_phase {
+        phase.end();
+    }
+    if let Some(name) = table {
+        eprintln!("applied {}: {} changes", name, count);
+    }
+
+    let commit_phase = trace::phase("commit");
+    txn.commit()?;
+    commit_phase.end();
+
+    eprintln!("committed transaction.");
+
+    Ok(())
+}
+
+/// Delete a key, or one of its values in a table with duplicate keys. Values are deleted
+/// through a cursor, because the lmdb crate's `del` passes mdb_del a dangling pointer to
+/// the value, which crashes LMDB's comparators.
+fn delete(
+    txn: &mut lmdb::RwTransaction,
+    db: lmdb::Database,
+    raw_key: &[u8],
+    raw_val: Option<&[u8]>,
+) -> Result<(), lmdb::Error> {
+    let Some(raw_val) = raw_val else {
+        return txn.del(db, &raw_key, None);
+    };
+    let mut cursor = txn.open_rw_cursor(db)?;
+    cursor.get(Some(raw_key), Some(raw_val), lmdb_sys::MDB_GET_BOTH)?;
+    cursor.del(lmdb::WriteFlags::empty())
+}
+
+/// Look up the table which changes currently apply to
+fn current_table(
+    tables: &HashMap<&str, lmdb::Database>,
+    table: &Option<String>,
+) -> Result<lmdb::Database, Box<dyn Error>> {
+    let name = table
+        .as_deref()
+        .ok_or("delta has a change outside of any table")?;
+    tables
+        .get(name)
+        .copied()
+        .ok_or_else(|| format!("delta changes table {} which isn't in its header", name).into())
+}
@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use crate::environment::environment_flags;
+
+/// Size of the chunks in which a rate-limited backup is written
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Parser)]
+/// Copy an OSMX database to a new file. The copy is a consistent snapshot, even if
+/// another process is writing to the database at the same time.
+pub struct CliArgs {
+    /// Path to the .osmx file to back up
+    input_file: PathBuf,
+    /// Path of the backup file to create (which must not already exist)
+    output_file: PathBuf,
+    /// Omit free pages from the copy (making it smaller, but the copy slower)
+    #[arg(long)]
+    compact: bool,
+    /// Limit the rate at which the backup is written, in MiB per second
+    #[arg(long)]
+    max_rate: Option<u64>,
+}
+
+/// Copy everything from reader to the file, writing no more than max_rate MiB per second
+fn copy_rate_limited(
+    mut reader: impl Read,
+    file: &mut File,
+    max_rate: u64,
+) -> Result<(), std::io::Error> {
+    let bytes_per_second = (max_rate * 1024 * 1024) as f64;
+    let start = Instant::now();
+    let mut total: u64 = 0;
+    let mut buf = vec![0; CHUNK_SIZE];
+
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 {
+            return Ok(());
+        }
+        file.write_all(&buf[..len])?;
+        total += len as u64;
+
+        // sleep until the average rate since the start is back under the limit
+        let target = Duration::from_secs_f64(total as f64 / bytes_per_second);
+        if let Some(delay) = target.checked_sub(start.elapsed()) {
+            thread::sleep(delay);
+        }
+    }
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let env = lmdb::Environment::new()
+        .set_flags(
+            environment_flags(args.input_file.as_ref())
+                | lmdb::EnvironmentFlags::NO_READAHEAD
+                | lmdb::EnvironmentFlags::READ_ONLY,
+        )
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
+        .open(args.input_file.as_ref())?;
+
+    let mut file = File::create_new(&args.output_file)?;
+
+    let copy_flags = if args.compact {
+        lmdb_sys::MDB_CP_COMPACT
+    } else {
+        0
+    };
+
+    // LMDB copies the database within a read transaction, so writers can continue while
+    // the copy is made. With a rate limit, it writes into a pipe and a second thread
+    // copies from the pipe to the file at the limited rate.
+    let result = match args.max_rate {
+        Some(max_rate) => {
+            let (reader, writer) = std::io::pipe()?;
+            let copier = thread::spawn(move || {
+                copy_rate_limited(reader, &mut file, max_rate)?;
+                file.sync_all()
+            });
+            let result =
+                unsafe { lmdb_sys::mdb_env_copyfd2(env.env(), writer.as_raw_fd(), copy_flags) };
+            // closing the write end of the pipe tells the copier thread that the copy is done
+            drop(writer);
+            copier.join().expect("copier thread panicked")?;
+            result
+        }
+        None => {
+            let result =
+                unsafe { lmdb_sys::mdb_env_copyfd2(env.env(), file.as_raw_fd(), copy_flags) };
+            file.sync_all()?;
+            result
+        }
+    };
+
+    if result != 0 {
+        return Err(lmdb::Error::from_err_code(result).into());
+    }
+
+    eprintln!("backup complete.");
+
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+use osmx::geometry::MissingNodePolicy;
+use serde::Serialize;
+
+use crate::sample::Rng;
+use crate::scan::ScanArgs;
+
+#[derive(Parser)]
+/// Measure how fast a database can be read on this machine: the latency of random
+/// element lookups and spatial queries, the throughput of full table scans, and the rate
+/// at which way geometries can be built from node locations. The results are printed as
+/// JSON, for comparing hardware, settings (like --read-ahead) and encodings.
+///
+/// Run it twice to compare cold and warm page cache performance, or drop the cache
+/// between runs to measure the disk.
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Number of random lookups of each element type
+    #[arg(long, default_value_t = 10000)]
+    gets: u64,
+    /// Number of random spatial queries
+    #[arg(long, default_value_t = 100)]
+    queries: u64,
+    /// Width and height of each spatial query, in degrees
+    #[arg(long, default_value_t = 0.01)]
+    query_size: f64,
+    /// Number of ways to build geometries for
+    #[arg(long, default_value_t = 10000)]
+    geometries: u64,
+    /// Don't scan the element tables (which reads the whole database)
+    #[arg(long)]
+    no_scan: bool,
+    /// Seed for the random number generator, to repeat the same lookups and queries
+    /// (default: the current time)
+    #[arg(long)]
+    seed: Option<u64>,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+/// Latency percentiles of a repeated operation, in microseconds
+#[derive(Serialize)]
+struct Latency {
+    count: usize,
+    mean_us: f64,
+    p50_us: f64,
+    p90_us: f64,
+    p99_us: f64,
+    max_us: f64,
+}
+
+impl Latency {
+    fn from_samples(mut samples: Vec<Duration>) -> Self {
+        samples.sort();
+        let micros = |d: Duration| d.as_secs_f64() * 1e6;
+        let percentile = |p: f64| match samples.len() {
+            0 => 0.0,
+            n => micros(samples[((n - 1) as f64 * p).round() as usize]),
+        };
+        let total: Duration = samples.iter().sum();
+        Self {
+            count: samples.len(),
+            mean_us: if samples.is_empty() {
+                0.0
+            } else {
+                micros(total) / samples.len() as f64
+            },
+            p50_us: percentile(0.5),
+            p90_us: percentile(0.9),
+            p99_us: percentile(0.99),
+            max_us: percentile(1.0),
+        }
+    }
+}
+
+/// The rate of a bulk operation
+#[derive(Serialize)]
+struct Throughput {
+    count: u64,
+    seconds: f64,
+    per_second: f64,
+}
+
+impl Throughput {
+    fn new(count: u64, elapsed: Duration) -> Self {
+        let seconds = elapsed.as_secs_f64();
+        Self {
+            count,
+            seconds,
+            per_second: if seconds > 0.0 {
+                count as f64 / seconds
+            } else {
+                0.0
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RandomGets {
+    locations: Latency,
+    ways: Latency,
+    relations: Latency,
+}
+
+#[derive(Serialize)]
+struct Scans {
+    locations: Throughput,
+    nodes: Throughput,
+    ways: Throughput,
+    relations: Throughput,
+}
+
+#[derive(Serialize)]
+struct SpatialQueries {
+    size_degrees: f64,
+    latency: Latency,
+    mean_nodes_found: f64,
+}
+
+#[derive(Serialize)]
+struct Geometries {
+    ways: Throughput,
+    nodes_per_second: f64,
+}
+
+#[derive(Serialize)]
+struct Report {
+    file: PathBuf,
+    seed: u64,
+    random_get: RandomGets,
+    scan: Option<Scans>,
+    spatial_query: SpatialQueries,
+    geometry: Geometries,
+}
+
+/// Shuffle IDs so that they are read in random order (Fisher-Yates)
+fn shuffle(ids: &mut [u64], rng: &mut Rng) {
+    for i in (1..ids.len()).rev() {
+        ids.swap(i, rng.below(i as u64 + 1) as usize);
+    }
+}
+
+/// Time a scan of a table, which returns the number of elements scanned
+fn scan_table(
+    name: &str,
+    scan: impl FnOnce() -> Result<u64, Box<dyn Error>>,
+) -> Result<Throughput, Box<dyn Error>> {
+    eprintln!("scanning {}", name);
+    let start = Instant::now();
+    let count = scan()?;
+    Ok(Throughput::new(count, start.elapsed()))
+}
+
+/// Time a function of each ID
+fn time_each(
+    ids: &[u64],
+    mut f: impl FnMut(u64) -> Result<(), Box<dyn Error>>,
+) -> Result<Latency, Box<dyn Error>> {
+    let mut samples = Vec::with_capacity(ids.len());
+    for &id in ids {
+        let start = Instant::now();
+        f(id)?;
+        samples.push(start.elapsed());
+    }
+    Ok(Latency::from_samples(samples))
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let locations = txn.locations()?;
+    let nodes = txn.nodes()?;
+    let ways = txn.ways()?;
+    let relations = txn.relations()?;
+
+    let seed = match args.seed {
+        Some(seed) => seed,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+    };
+    let mut rng = Rng(seed);
+    let mut random_ids = |ids: Vec<u64>| {
+        let mut ids = ids;
+        shuffle(&mut ids, &mut rng);
+        ids
+    };
+
+    eprintln!("timing random lookups");
+    let location_ids = random_ids(locations.sample_ids(args.gets));
+    let way_ids = random_ids(ways.sample_ids(args.gets));
+    let relation_ids = random_ids(relations.sample_ids(args.gets));
+    let random_get = RandomGets {
+        locations: time_each(&location_ids, |id| {
+            std::hint::black_box(locations.get(id)?);
+            Ok(())
+        })?,
+        ways: time_each(&way_ids, |id| {
+            std::hint::black_box(ways.get(id)?.map(|way| way.nodes().count()));
+            Ok(())
+        })?,
+        relations: time_each(&relation_ids, |id| {
+            std::hint::black_box(relations.get(id)?.map(|r| r.members().count()));
+            Ok(())
+        })?,
+    };
+
+    let scan = if args.no_scan {
+        None
+    } else {
+        Some(Scans {
+            locations: scan_table("locations", || {
+                let mut count = 0;
+                locations.for_each_ref(|_, location| {
+                    std::hint::black_box(location.lon());
+                    count += 1;
+                })?;
+                Ok(count)
+            })?,
+            nodes: scan_table("nodes", || {
+                let mut count = 0;
+                nodes.for_each_ref(|_, node| {
+                    std::hint::black_box(node.tags().count());
+                    count += 1;
+                })?;
+                Ok(count)
+            })?,
+            ways: scan_table("ways", || {
+                let mut count = 0;
+                ways.for_each_ref(|_, way| {
+                    std::hint::black_box(way.nodes().count());
+                    count += 1;
+                })?;
+                Ok(count)
+            })?,
+            relations: scan_table("relations", || {
+                let mut count = 0;
+                relations.for_each_ref(|_, relation| {
+                    std::hint::black_box(relation.members().count());
+                    count += 1;
+                })?;
+                Ok(count)
+            })?,
+        })
+    };
+
+    // queries are centered on random nodes, so that they fall where the data is
+    eprintln!("timing spatial queries");
+    let mut centers: Vec<(f64, f64)> = vec![];
+    for id in random_ids(locations.sample_ids(args.queries)) {
+        if let Some(location) = locations.get(id)? {
+            centers.push((location.lon(), location.lat()));
+        }
+    }
+    let cell_nodes = txn.cell_nodes()?;
+    let half = args.query_size / 2.0;
+    let mut found = 0;
+    let mut samples = Vec::with_capacity(centers.len());
+    for &(lon, lat) in &centers {
+        let start = Instant::now();
+        let region = osmx::Region::from_bbox(lon - half, lat - half, lon + half, lat + half);
+        found += cell_nodes.find_in_region(&region).count();
+        samples.push(start.elapsed());
+    }
+    let spatial_query = SpatialQueries {
+        size_degrees: args.query_size,
+        mean_nodes_found: if centers.is_empty() {
+            0.0
+        } else {
+            found as f64 / centers.len() as f64
+        },
+        latency: Latency::from_samples(samples),
+    };
+
+    eprintln!("timing way geometries");
+    let way_ids = random_ids(ways.sample_ids(args.geometries));
+    let start = Instant::now();
+    let mut node_count = 0;
+    for &id in &way_ids {
+        if let Some(way) = ways.get(id)? {
+            node_count += way.coordinates(&locations, MissingNodePolicy::Skip)?.len();
+        }
+    }
+    let ways_built = Throughput::new(way_ids.len() as u64, start.elapsed());
+    let geometry = Geometries {
+        nodes_per_second: if ways_built.seconds > 0.0 {
+            node_count as f64 / ways_built.seconds
+        } else {
+            0.0
+        },
+        ways: ways_built,
+    };
+
+    let report = Report {
+        file: args.input_file.clone(),
+        seed,
+        random_get,
+        scan,
+        spatial_query,
+        geometry,
+    };
+    serde_json::to_writer_pretty(std::io::stdout(), &report)?;
+    println!();
+    args.scan.report_skipped(&db);
+
+    Ok(())
+}
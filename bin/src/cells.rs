@@ -0,0 +1,68 @@
+use std::error::Error;
+
+use clap::Parser;
+
+use crate::analyze::cell_polygon_geojson;
+use crate::region::Bbox;
+
+#[derive(Parser)]
+/// Print the S2 cells which a spatial query for a region searches, to help debug queries
+/// which return unexpected results. Prints one tab-separated line per cell, with its
+/// token, level and ID.
+pub struct CliArgs {
+    /// The region, as a bounding box given as west,south,east,north in degrees
+    #[arg(
+        long,
+        allow_hyphen_values = true,
+        required_unless_present = "tile",
+        conflicts_with = "tile"
+    )]
+    bbox: Option<Bbox>,
+    /// The region, as a web map tile given as z/x/y
+    #[arg(long)]
+    tile: Option<String>,
+    /// Print the cells as a GeoJSON FeatureCollection of polygons instead
+    #[arg(long)]
+    geojson: bool,
+}
+
+fn parse_tile(tile: &str) -> Result<(u8, u32, u32), Box<dyn Error>> {
+    match tile.split('/').collect::<Vec<_>>()[..] {
+        [z, x, y] => Ok((z.parse()?, x.parse()?, y.parse()?)),
+        _ => Err(format!("invalid tile {} (expected z/x/y)", tile).into()),
+    }
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let region = match (args.bbox, args.tile.as_deref()) {
+        (Some(bbox), _) => bbox.region(),
+        (None, Some(tile)) => {
+            let (z, x, y) = parse_tile(tile)?;
+            osmx::Region::from_tile(z, x, y)?
+        }
+        (None, None) => unreachable!("clap requires --bbox or --tile"),
+    };
+
+    let cell_ids: Vec<s2::cellid::CellID> = region.cell_ids().map(s2::cellid::CellID).collect();
+
+    if args.geojson {
+        println!("{{\"type\":\"FeatureCollection\",\"features\":[");
+        for (i, &cell_id) in cell_ids.iter().enumerate() {
+            let separator = if i + 1 < cell_ids.len() { "," } else { "" };
+            println!(
+                "{{\"type\":\"Feature\",\"properties\":{{\"cell\":\"{}\",\"level\":{}}},\"geometry\":{}}}{}",
+                cell_id.to_token(),
+                cell_id.level(),
+                cell_polygon_geojson(cell_id),
+                separator
+            );
+        }
+        println!("]}}");
+    } else {
+        for cell_id in cell_ids {
+            println!("{}\t{}\t{}", cell_id.to_token(), cell_id.level(), cell_id.0);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,360 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::Parser;
+use lmdb::{Cursor, Transaction};
+use osmx::interop;
+
+use crate::checksum::file_sha256;
+use crate::environment::environment_flags;
+
+/// Number of entries sampled from each table when checking key and value encodings
+const SAMPLE_SIZE: usize = 10_000;
+
+#[derive(Parser)]
+/// Check that an OSMX database is well-formed
+pub struct CliArgs {
+    /// Path to the .osmx file to check
+    input_file: PathBuf,
+    /// Also check that the file conforms to the reference OSMExpress (C++) format, and
+    /// doesn't use any format extensions that the C++ implementation can't read
+    #[arg(long)]
+    interop: bool,
+    /// Also check that every node referenced by a way is in the locations table (this
+    /// reads every way in the database)
+    #[arg(long)]
+    missing_refs: bool,
+    /// Also check that every relation member refers to an element of its declared type
+    /// (this reads every relation in the database)
+    #[arg(long)]
+    relation_members: bool,
+    /// Also check for relations which are members of each other in a cycle, and for
+    /// relations nested more than --max-relation-depth levels deep (this reads every
+    /// relation in the database)
+    #[arg(long)]
+    relation_nesting: bool,
+    /// The deepest relations may be nested in each other, with --relation-nesting
+    #[arg(long, default_value_t = osmx::MAX_RELATION_DEPTH, requires = "relation_nesting")]
+    max_relation_depth: usize,
+    /// Also check ways for degenerate geometry: repeated nodes, zero-length segments,
+    /// self-intersections and rings with too few nodes (this reads every way in the
+    /// database)
+    #[arg(long)]
+    geometry: bool,
+    /// Also check that the database was imported from this .osm.pbf file, by comparing
+    /// the file's SHA-256 digest with the one recorded by expand
+    #[arg(long)]
+    source: Option<PathBuf>,
+}
+
+/// Get the flags that an LMDB table was created with
+fn table_flags(txn: &lmdb::RoTransaction, db: lmdb::Database) -> lmdb::DatabaseFlags {
+    let mut flags: u32 = 0;
+    unsafe {
+        lmdb_sys::mdb_dbi_flags(txn.txn(), db.dbi(), &mut flags);
+    }
+    lmdb::DatabaseFlags::from_bits_truncate(flags)
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let mut problems: Vec<String> = vec![];
+
+    // opening the database checks the format version and that all the tables exist
+    let db = match osmx::Database::open(&args.input_file) {
+        Ok(db) => db,
+        Err(e) => return Err(format!("cannot open database: {}", e).into()),
+    };
+
+    if args.interop {
+        // format extensions which the reference implementation doesn't understand
+
+        if db.value_encoding() != osmx::ValueEncoding::Standard {
+            problems.push("element values use packed encoding".to_string());
+        }
+        if db.value_compression() != osmx::ValueCompression::None {
+            problems.push("element values are compressed".to_string());
+        }
+        if !db.strings().is_empty() {
+            problems.push("tags use the interned string table".to_string());
+        }
+        if !db.tag_sets().is_empty() {
+            problems.push("tags use the shared tag_sets table".to_string());
+        }
+    }
+
+    if args.missing_refs {
+        let txn = osmx::Transaction::begin(&db)?;
+        let validator = osmx::Validator::new(&txn)?;
+
+        let mut way_count = 0;
+        let mut node_count = 0;
+        for missing in validator.missing_node_refs() {
+            let missing = missing?;
+            let sample: Vec<String> = missing.sample.iter().map(|id| id.to_string()).collect();
+            problems.push(format!(
+                "way {} references {} missing nodes (of {}), e.g. {}",
+                missing.way_id,
+                missing.missing_count,
+                missing.node_count,
+                sample.join(", ")
+            ));
+            way_count += 1;
+            node_count += missing.missing_count;
+        }
+        if way_count > 0 {
+            eprintln!(
+                "{} ways reference {} nodes missing from the locations table",
+                way_count, node_count
+            );
+        }
+    }
+
+    if args.relation_members {
+        let txn = osmx::Transaction::begin(&db)?;
+        let validator = osmx::Validator::new(&txn)?;
+
+        for invalid in validator.invalid_relation_members() {
+            let invalid = invalid?;
+            let sample: Vec<String> = invalid
+                .sample
+                .iter()
+                .map(|member| {
+                    let description = match &member.problem {
+                        osmx::MemberProblem::Missing => "missing".to_string(),
+                        osmx::MemberProblem::TypeMismatch { found } => {
+                            format!("missing, but found {:?}", found)
+                        }
+                    };
+                    format!(
+                        "#{} {:?} role {:?} ({})",
+                        member.index, member.member, member.role, description
+                    )
+                })
+                .collect();
+            problems.push(format!(
+                "relation {} has {} invalid members (of {}): {}",
+                invalid.relation_id,
+                invalid.invalid_count,
+                invalid.member_count,
+                sample.join("; ")
+            ));
+        }
+    }
+
+    if args.relation_nesting {
+        let txn = osmx::Transaction::begin(&db)?;
+        let validator = osmx::Validator::new(&txn)?;
+
+        for problem in validator.nesting_problems(args.max_relation_depth)? {
+            problems.push(match problem {
+                osmx::NestingProblem::Cycle { relation_ids } => {
+                    let ids: Vec<String> = relation_ids.iter().map(|id| id.to_string()).collect();
+                    match ids.as_slice() {
+                        [id] => format!("relation {} is a member of itself", id),
+                        _ => format!("relations {} are members of each other", ids.join(", ")),
+                    }
+                }
+                osmx::NestingProblem::TooDeep { relation_id, depth } => format!(
+                    "relation {} has relations nested {} levels deep (more than {})",
+                    relation_id, depth, args.max_relation_depth
+                ),
+            });
+        }
+    }
+
+    if args.geometry {
+        let txn = osmx::Transaction::begin(&db)?;
+        let validator = osmx::Validator::new(&txn)?;
+
+        let mut way_count = 0;
+        for invalid in validator.geometry_problems() {
+            let invalid = invalid?;
+            let descriptions: Vec<String> = invalid
+                .problems
+                .iter()
+                .map(|problem| match problem {
+                    osmx::GeometryProblem::TooFewNodes => "too few nodes".to_string(),
+                    osmx::GeometryProblem::RepeatedNode { index, node_id } => {
+                        format!("node {} repeated at position {}", node_id, index)
+                    }
+                    osmx::GeometryProblem::ZeroLengthSegment { index } => {
+                        format!("zero-length segment at position {}", index)
+                    }
+                    osmx::GeometryProblem::SelfIntersection { segments, location } => format!(
+                        "segments {} and {} intersect at {:.7} {:.7}",
+                        segments.0, segments.1, location.0, location.1
+                    ),
+                })
+                .collect();
+            problems.push(format!(
+                "way {} has degenerate geometry: {}",
+                invalid.way_id,
+                descriptions.join("; ")
+            ));
+            way_count += 1;
+        }
+        if way_count > 0 {
+            eprintln!("{} ways have degenerate geometry", way_count);
+        }
+    }
+
+    if let Some(source) = &args.source {
+        let txn = osmx::Transaction::begin(&db)?;
+        let digest = file_sha256(source)?;
+        match txn.source_sha256()? {
+            Some(recorded) if recorded == digest => (),
+            Some(recorded) => problems.push(format!(
+                "database was imported from a file with SHA-256 {}, but {} has SHA-256 {}",
+                recorded,
+                source.display(),
+                digest
+            )),
+            None => problems.push(format!(
+                "database doesn't record the SHA-256 of the file it was imported from (metadata key {})",
+                osmx::SOURCE_SHA256_KEY
+            )),
+        }
+    }
+
+    // the recorded counts should match the element tables (nodes are counted by their
+    // locations, since untagged nodes aren't in the Nodes table)
+    let txn = osmx::Transaction::begin(&db)?;
+    if let Some(counts) = txn.counts()? {
+        for (name, recorded, actual) in [
+            ("nodes", counts.nodes, txn.locations()?.len()),
+            ("ways", counts.ways, txn.ways()?.len()),
+            ("relations", counts.relations, txn.relations()?.len()),
+        ] {
+            if recorded != actual {
+                problems.push(format!(
+                    "metadata records {} {}, but the database has {}",
+                    recorded, name, actual
+                ));
+            }
+        }
+    }
+    drop(txn);
+
+    // LMDB doesn't allow a file to be opened twice in the same process
+    drop(db);
+
+    let env = lmdb::Environment::new()
+        .set_flags(
+            environment_flags(args.input_file.as_ref())
+                | lmdb::EnvironmentFlags::NO_READAHEAD
+                | lmdb::EnvironmentFlags::READ_ONLY,
+        )
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
+        .open(args.input_file.as_ref())?;
+    // tables must be opened before the read transaction begins, since opening a table
+    // uses a transaction of its own
+    let metadata = env.open_db(Some(interop::METADATA_TABLE))?;
+    let element_tables = interop::ELEMENT_TABLES
+        .iter()
+        .map(|&name| Ok((name, env.open_db(Some(name))?)))
+        .collect::<Result<Vec<_>, lmdb::Error>>()?;
+    let index_tables = interop::INDEX_TABLES
+        .iter()
+        .map(|&name| Ok((name, env.open_db(Some(name))?)))
+        .collect::<Result<Vec<_>, lmdb::Error>>()?;
+
+    let txn = env.begin_ro_txn()?;
+
+    let element_flags = lmdb::DatabaseFlags::INTEGER_KEY;
+    let index_flags = lmdb::DatabaseFlags::INTEGER_KEY
+        | lmdb::DatabaseFlags::INTEGER_DUP
+        | lmdb::DatabaseFlags::DUP_SORT
+        | lmdb::DatabaseFlags::DUP_FIXED;
+
+    // check table flags, and the lengths of a sample of keys and values
+
+    for (name, db) in element_tables {
+        if table_flags(&txn, db) != element_flags {
+            problems.push(format!("table {} has unexpected flags", name));
+        }
+
+        let mut cursor = txn.open_ro_cursor(db)?;
+        for (raw_key, raw_val) in cursor.iter().take(SAMPLE_SIZE) {
+            if raw_key.len() != 8 {
+                problems.push(format!(
+                    "table {} has a key of length {}",
+                    name,
+                    raw_key.len()
+                ));
+                break;
+            }
+            if name == "locations" && raw_val.len() != interop::LOCATION_VALUE_SIZE {
+                problems.push(format!(
+                    "table locations has a value of length {} (expected {})",
+                    raw_val.len(),
+                    interop::LOCATION_VALUE_SIZE
+                ));
+                break;
+            }
+        }
+    }
+
+    for (name, db) in index_tables {
+        if table_flags(&txn, db) != index_flags {
+            problems.push(format!("table {} has unexpected flags", name));
+        }
+
+        let mut cursor = txn.open_ro_cursor(db)?;
+        for (raw_key, raw_val) in cursor.iter().take(SAMPLE_SIZE) {
+            if raw_key.len() != 8 || raw_val.len() != 8 {
+                problems.push(format!("table {} has an entry of incorrect length", name));
+                break;
+            }
+            if name == "cell_node" {
+                let cell = s2::cellid::CellID(
+                    osmx::decode_key(raw_key).ok_or("key with incorrect length")?,
+                );
+                if !cell.is_valid() || cell.level() != osmx::CELL_INDEX_LEVEL {
+                    problems.push(format!(
+                        "table cell_node has a key which is not a level {} cell",
+                        osmx::CELL_INDEX_LEVEL
+                    ));
+                    break;
+                }
+            }
+        }
+    }
+
+    if args.interop {
+        // metadata values are stored as strings by the reference implementation
+
+        let get_str = |key: &str| match txn.get(metadata, &key.as_bytes()) {
+            Ok(raw_val) => Some(std::str::from_utf8(raw_val).map_err(|_| ())),
+            Err(_) => None,
+        };
+
+        match get_str(interop::REPLICATION_TIMESTAMP_KEY) {
+            Some(Ok(s)) if interop::parse_timestamp(s).is_some() => (),
+            Some(_) => problems.push(format!(
+                "metadata key {} is not an ISO 8601 timestamp",
+                interop::REPLICATION_TIMESTAMP_KEY
+            )),
+            None => (),
+        }
+
+        match get_str(interop::REPLICATION_SEQUENCE_NUMBER_KEY) {
+            Some(Ok(s)) if s.parse::<u64>().is_ok() => (),
+            Some(_) => problems.push(format!(
+                "metadata key {} is not a decimal number",
+                interop::REPLICATION_SEQUENCE_NUMBER_KEY
+            )),
+            None => (),
+        }
+    }
+
+    if problems.is_empty() {
+        println!("ok");
+        Ok(())
+    } else {
+        for problem in problems.iter() {
+            println!("FAIL: {}", problem);
+        }
+        Err(format!("{} problems found", problems.len()).into())
+    }
+}
@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+
+/// A SHA-256 digest of the bytes read through its readers. The digest is shared with
+/// the readers, so it can be read after a reader has been moved into something that
+/// consumes it (like a BlobReader).
+#[derive(Clone, Default)]
+pub struct Checksum(Arc<Mutex<Sha256>>);
+
+pub struct HashingReader<R> {
+    inner: R,
+    checksum: Checksum,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.checksum.0.lock().unwrap().update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl Checksum {
+    /// Wrap a reader so that everything read through it is added to the digest
+    pub fn reader<R: Read>(&self, inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            checksum: self.clone(),
+        }
+    }
+
+    /// The digest of everything read so far, as a lowercase hex string
+    pub fn hex(&self) -> String {
+        let digest = self.0.lock().unwrap().clone().finalize();
+        digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+}
+
+/// Compute the SHA-256 digest of a file, as a lowercase hex string
+pub fn file_sha256(path: &Path) -> Result<String, Box<dyn Error>> {
+    let checksum = Checksum::default();
+    io::copy(&mut checksum.reader(File::open(path)?), &mut io::sink())?;
+    Ok(checksum.hex())
+}
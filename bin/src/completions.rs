@@ -0,0 +1,18 @@
+use std::error::Error;
+
+use clap::{CommandFactory, Parser};
+
+#[derive(Parser)]
+/// Print a script which completes the commands and options of osmx-rs in a shell. For
+/// example, with bash: `osmx-rs completions bash > ~/.local/share/bash-completion/completions/osmx-rs`
+pub struct CliArgs {
+    /// The shell to print the script for
+    shell: clap_complete::Shell,
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let mut command = crate::CliArgs::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut std::io::stdout());
+    Ok(())
+}
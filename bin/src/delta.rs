@@ -0,0 +1,314 @@
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::mem::MaybeUninit;
+use std::path::PathBuf;
+
+use clap::Parser;
+use lmdb::{Cursor, Transaction};
+use osmx::interop::METADATA_TABLE;
+use osmx::KeyByteOrder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::environment::{open_env, table_names};
+
+/// Bytes at the start of every delta file, identifying the file type and version
+pub const DELTA_MAGIC: &[u8; 8] = b"OSMXDLT2";
+/// zstd compression level used for delta files
+const COMPRESSION_LEVEL: i32 = 9;
+
+/// Describes the database a delta applies to, and the tables it touches, so that they
+/// can all be opened (or created) before the delta is applied
+#[derive(Serialize, Deserialize)]
+pub struct DeltaHeader {
+    /// Byte order of the integer keys in the delta (and the databases it was made from)
+    pub key_byte_order: String,
+    /// The database the delta was computed from
+    pub base: BaseIdentity,
+    /// Names and LMDB flags of the tables in the new database
+    pub tables: Vec<(String, u32)>,
+    /// Names of the tables which exist in the old database but not in the new one
+    pub dropped_tables: Vec<String>,
+}
+
+/// Identifies a database, so that a delta is only applied to the one it was computed
+/// from: the number of entries in each of its tables, and a digest of its metadata table
+/// (which holds its replication state, element counts and bounds). The writer lock, which
+/// apply-delta takes before checking, is left out of the digest.
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct BaseIdentity {
+    /// Names of the tables other than metadata, and their numbers of entries
+    pub entries: Vec<(String, u64)>,
+    /// SHA-256 digest of the metadata table's keys and values, as a lowercase hex string
+    pub metadata_sha256: String,
+}
+
+impl BaseIdentity {
+    /// Identify the database in an environment. Opens all its tables, so it must be
+    /// called before a transaction begins.
+    pub fn of(env: &lmdb::Environment) -> Result<Self, Box<dyn Error>> {
+        let names = table_names(env)?;
+        let tables = names
+            .iter()
+            .map(|name| Ok((name, env.open_db(Some(name))?)))
+            .collect::<Result<Vec<_>, lmdb::Error>>()?;
+
+        let txn = env.begin_ro_txn()?;
+        let mut entries = vec![];
+        let mut digest = Sha256::new();
+        for (name, db) in tables {
+            if name != METADATA_TABLE {
+                let mut stat = MaybeUninit::<lmdb_sys::MDB_stat>::uninit();
+                let rc = unsafe { lmdb_sys::mdb_stat(txn.txn(), db.dbi(), stat.as_mut_ptr()) };
+                if rc != 0 {
+                    return Err(lmdb::Error::from_err_code(rc).into());
+                }
+                let stat = unsafe { stat.assume_init() };
+                entries.push((name.clone(), stat.ms_entries as u64));
+                continue;
+            }
+            let mut cursor = txn.open_ro_cursor(db)?;
+            for (raw_key, raw_val) in cursor.iter() {
+                if raw_key == osmx::WRITER_LOCK_KEY.as_bytes() {
+                    continue;
+                }
+                for bytes in [raw_key, raw_val] {
+                    digest.update((bytes.len() as u64).to_le_bytes());
+                    digest.update(bytes);
+                }
+            }
+        }
+        let metadata_sha256 = digest
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Ok(Self {
+            entries,
+            metadata_sha256,
+        })
+    }
+
+    /// Describe how a database differs from this one, if it does
+    pub fn difference(&self, other: &BaseIdentity) -> Option<String> {
+        for (name, count) in self.entries.iter() {
+            match other
+                .entries
+                .iter()
+                .find(|(other_name, _)| other_name == name)
+            {
+                Some((_, other_count)) if other_count == count => (),
+                Some((_, other_count)) => {
+                    return Some(format!(
+                        "table {} has {} entries rather than {}",
+                        name, other_count, count
+                    ))
+                }
+                None => return Some(format!("table {} is missing", name)),
+            }
+        }
+        if let Some((name, _)) = other
+            .entries
+            .iter()
+            .find(|(name, _)| !self.entries.iter().any(|(n, _)| n == name))
+        {
+            return Some(format!(
+                "it has a table {} which the delta's base doesn't",
+                name
+            ));
+        }
+        if self.metadata_sha256 != other.metadata_sha256 {
+            return Some("its metadata (such as its replication state) is different".to_string());
+        }
+        None
+    }
+}
+
+/// One change in a delta file. Puts and deletes apply to the table named by the most
+/// recent Table record.
+#[derive(Serialize, Deserialize)]
+pub enum DeltaRecord {
+    Table(String),
+    /// Insert or replace a value (or, in tables with duplicate keys, add a value)
+    Put(Vec<u8>, Vec<u8>),
+    /// Delete a key, or in tables with duplicate keys, one of its values
+    Delete(Vec<u8>, Option<Vec<u8>>),
+    End,
+}
+
+#[derive(Parser)]
+/// Compute a binary diff between two OSMX databases, which apply-delta can apply to a
+/// copy of the old database to turn it into the new one
+pub struct CliArgs {
+    /// Path to the old .osmx file
+    old_file: PathBuf,
+    /// Path to the new .osmx file
+    new_file: PathBuf,
+    /// Path of the delta file to create
+    output_file: PathBuf,
+}
+
+/// Compare two keys (or with `dup`, two values of the same key) using the table's own
+/// ordering, which for integer key tables is numeric rather than bytewise
+fn compare(txn: &impl Transaction, db: lmdb::Database, dup: bool, a: &[u8], b: &[u8]) -> Ordering {
+    let a = lmdb_sys::MDB_val {
+        mv_size: a.len(),
+        mv_data: a.as_ptr() as *mut _,
+    };
+    let b = lmdb_sys::MDB_val {
+        mv_size: b.len(),
+        mv_data: b.as_ptr() as *mut _,
+    };
+    let result = unsafe {
+        if dup {
+            lmdb_sys::mdb_dcmp(txn.txn(), db.dbi(), &a, &b)
+        } else {
+            lmdb_sys::mdb_cmp(txn.txn(), db.dbi(), &a, &b)
+        }
+    };
+    result.cmp(&0)
+}
+
+/// Writes delta records, emitting a Table record before the first change to each table
+struct DeltaWriter<W: Write> {
+    writer: W,
+    pending_table: Option<String>,
+    count: u64,
+}
+
+impl<W: Write> DeltaWriter<W> {
+    fn begin_table(&mut self, name: &str) {
+        self.pending_table = Some(name.to_string());
+        self.count = 0;
+    }
+
+    fn write(&mut self, record: DeltaRecord) -> Result<(), Box<dyn Error>> {
+        if let Some(name) = self.pending_table.take() {
+            bincode::serialize_into(&mut self.writer, &DeltaRecord::Table(name))?;
+        }
+        bincode::serialize_into(&mut self.writer, &record)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Write the end of the delta, returning the underlying writer
+    fn finish(mut self) -> Result<W, Box<dyn Error>> {
+        bincode::serialize_into(&mut self.writer, &DeltaRecord::End)?;
+        Ok(self.writer)
+    }
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let old_env = open_env(&args.old_file, true)?;
+    let new_env = open_env(&args.new_file, true)?;
+
+    // tables must be opened before the transactions begin, since opening a table uses a
+    // transaction of its own
+    let old_names = table_names(&old_env)?;
+    let new_names = table_names(&new_env)?;
+
+    let mut tables = vec![];
+    let mut header_tables = vec![];
+    for name in new_names.iter() {
+        let new_db = new_env.open_db(Some(name))?;
+        let flags = new_env.get_db_flags(new_db)?;
+        let old_db = if old_names.contains(name) {
+            let old_db = old_env.open_db(Some(name))?;
+            if old_env.get_db_flags(old_db)? != flags {
+                return Err(
+                    format!("table {} has different flags in the two databases", name).into(),
+                );
+            }
+            Some(old_db)
+        } else {
+            None
+        };
+        tables.push((name, old_db, new_db, flags));
+        header_tables.push((name.clone(), flags.bits()));
+    }
+
+    let header = DeltaHeader {
+        key_byte_order: KeyByteOrder::NATIVE.as_str().to_string(),
+        base: BaseIdentity::of(&old_env)?,
+        tables: header_tables,
+        dropped_tables: old_names
+            .iter()
+            .filter(|name| !new_names.contains(name))
+            .cloned()
+            .collect(),
+    };
+
+    let old_txn = old_env.begin_ro_txn()?;
+    let new_txn = new_env.begin_ro_txn()?;
+
+    let mut file = BufWriter::new(File::create(&args.output_file)?);
+    file.write_all(DELTA_MAGIC)?;
+    let mut encoder = zstd::stream::Encoder::new(file, COMPRESSION_LEVEL)?;
+    bincode::serialize_into(&mut encoder, &header)?;
+
+    let mut writer = DeltaWriter {
+        writer: encoder,
+        pending_table: None,
+        count: 0,
+    };
+
+    for (name, old_db, new_db, flags) in tables {
+        let dup = flags.contains(lmdb::DatabaseFlags::DUP_SORT);
+        writer.begin_table(name);
+
+        let mut old_cursor = old_db.map(|db| old_txn.open_ro_cursor(db)).transpose()?;
+        let mut old_iter = old_cursor
+            .as_mut()
+            .map(|cursor| cursor.iter())
+            .into_iter()
+            .flatten()
+            .peekable();
+        let mut new_cursor = new_txn.open_ro_cursor(new_db)?;
+        let mut new_iter = new_cursor.iter().peekable();
+
+        // merge the two tables' entries (which LMDB returns in table order), emitting a
+        // change for each entry which was added, removed or modified
+        loop {
+            let ordering = match (old_iter.peek(), new_iter.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((old_key, old_val)), Some((new_key, new_val))) => {
+                    match compare(&new_txn, new_db, false, old_key, new_key) {
+                        Ordering::Equal if dup => compare(&new_txn, new_db, true, old_val, new_val),
+                        ordering => ordering,
+                    }
+                }
+            };
+
+            match ordering {
+                Ordering::Less => {
+                    let (raw_key, raw_val) = old_iter.next().unwrap();
+                    let raw_val = if dup { Some(raw_val.to_vec()) } else { None };
+                    writer.write(DeltaRecord::Delete(raw_key.to_vec(), raw_val))?;
+                }
+                Ordering::Greater => {
+                    let (raw_key, raw_val) = new_iter.next().unwrap();
+                    writer.write(DeltaRecord::Put(raw_key.to_vec(), raw_val.to_vec()))?;
+                }
+                Ordering::Equal => {
+                    let (_, old_val) = old_iter.next().unwrap();
+                    let (raw_key, new_val) = new_iter.next().unwrap();
+                    if old_val != new_val {
+                        writer.write(DeltaRecord::Put(raw_key.to_vec(), new_val.to_vec()))?;
+                    }
+                }
+            }
+        }
+
+        eprintln!("diffed {}: {} changes", name, writer.count);
+    }
+
+    writer.finish()?.finish()?.flush()?;
+
+    eprintln!("wrote delta.");
+
+    Ok(())
+}
@@ -0,0 +1,418 @@
+use std::error::Error;
+use std::ffi::CString;
+use std::fmt;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use lmdb::Transaction;
+use osmx::KeyByteOrder;
+
+use crate::environment::{check_readers, open_env};
+
+/// The map size which the commands open databases with
+const MAP_SIZE: u64 = 50 * 1024 * 1024 * 1024;
+
+/// The open file limit below which sorting the index tables of a large import may fail
+/// (the sorter keeps one file open per segment of each table while merging them)
+const MIN_OPEN_FILES: u64 = 8192;
+
+/// The fraction of reader slots in use above which new readers may soon be refused
+const READERS_BUSY: f64 = 0.9;
+
+#[derive(Parser)]
+/// Check the host and a database for common problems which make imports fail or reads
+/// slow: too little free disk space for the map size, a network or in-memory
+/// filesystem, a low open file limit, transparent huge pages, stale locks and reader
+/// slots, and a byte order or format version this build can't read. Each problem is
+/// printed with a suggestion for fixing it.
+///
+/// The path may also be one where a database doesn't exist yet (such as the output of
+/// `expand`), to check the host and filesystem before importing.
+pub struct CliArgs {
+    /// Path to the .osmx file to check
+    path: PathBuf,
+}
+
+#[derive(PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warning,
+    Problem,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Problem => "PROBLEM",
+        })
+    }
+}
+
+/// The outcome of one check
+struct Finding {
+    status: Status,
+    check: &'static str,
+    message: String,
+    suggestion: Option<String>,
+}
+
+#[derive(Default)]
+struct Findings(Vec<Finding>);
+
+impl Findings {
+    fn add(&mut self, status: Status, check: &'static str, message: String) {
+        self.0.push(Finding {
+            status,
+            check,
+            message,
+            suggestion: None,
+        });
+    }
+
+    fn suggest(&mut self, status: Status, check: &'static str, message: String, suggestion: &str) {
+        self.0.push(Finding {
+            status,
+            check,
+            message,
+            suggestion: Some(suggestion.to_string()),
+        });
+    }
+}
+
+fn gib(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1024 * 1024 * 1024) as f64)
+}
+
+/// The directory a database is (or would be) in
+fn parent_dir(path: &Path) -> &Path {
+    if path.is_dir() {
+        return path;
+    }
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+fn c_path(path: &Path) -> Result<CString, Box<dyn Error>> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// The space available to unprivileged users on the filesystem containing a directory
+fn free_space(dir: &Path) -> Result<u64, Box<dyn Error>> {
+    let path = c_path(dir)?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// The name of the type of the filesystem containing a directory, and whether LMDB's
+/// file locking works on it
+#[cfg(target_os = "linux")]
+fn filesystem_type(dir: &Path) -> Result<Option<(&'static str, bool)>, Box<dyn Error>> {
+    let path = c_path(dir)?;
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    // magic numbers from linux/magic.h
+    Ok(match stat.f_type as u32 {
+        0xef53 => Some(("ext4", true)),
+        0x58465342 => Some(("xfs", true)),
+        0x9123683e => Some(("btrfs", true)),
+        0x2fc12fc1 => Some(("zfs", true)),
+        0x01021994 => Some(("tmpfs", true)),
+        0x794c7630 => Some(("overlayfs", true)),
+        0x6969 => Some(("nfs", false)),
+        0xff534d42 | 0xfe534d42 | 0x517b => Some(("smb", false)),
+        0x65735546 => Some(("fuse", false)),
+        0x01021997 => Some(("9p", false)),
+        _ => None,
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn filesystem_type(_dir: &Path) -> Result<Option<(&'static str, bool)>, Box<dyn Error>> {
+    Ok(None)
+}
+
+fn check_host(findings: &mut Findings, path: &Path) -> Result<(), Box<dyn Error>> {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    findings.add(Status::Ok, "page size", format!("{} bytes", page_size));
+
+    findings.add(
+        Status::Ok,
+        "byte order",
+        format!("{}-endian host", KeyByteOrder::NATIVE.as_str()),
+    );
+
+    let mut limit: libc::rlimit = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } == 0 {
+        let message = format!("{} (hard limit {})", limit.rlim_cur, limit.rlim_max);
+        if (limit.rlim_cur as u64) < MIN_OPEN_FILES {
+            findings.suggest(
+                Status::Warning,
+                "open files",
+                message,
+                "expand may run out of file descriptors while sorting the indexes of a large \
+                 input; raise the limit with `ulimit -n 65536` before running it",
+            );
+        } else {
+            findings.add(Status::Ok, "open files", message);
+        }
+    }
+
+    match std::fs::read_to_string("/sys/kernel/mm/transparent_hugepage/enabled") {
+        Ok(setting) if setting.contains("[always]") => findings.suggest(
+            Status::Warning,
+            "huge pages",
+            "transparent huge pages are always enabled".to_string(),
+            "random reads of a memory-mapped database are slower, and use more memory, with \
+             huge pages; run `echo madvise > /sys/kernel/mm/transparent_hugepage/enabled` as \
+             root",
+        ),
+        Ok(setting) => findings.add(
+            Status::Ok,
+            "huge pages",
+            format!("transparent huge pages: {}", setting.trim()),
+        ),
+        Err(_) => (),
+    }
+
+    let dir = parent_dir(path);
+    if !dir.exists() {
+        findings.add(
+            Status::Problem,
+            "directory",
+            format!("{} doesn't exist", dir.display()),
+        );
+        return Ok(());
+    }
+
+    match filesystem_type(dir)? {
+        Some((name, false)) => findings.suggest(
+            Status::Problem,
+            "filesystem",
+            format!("{} is on a {} filesystem", dir.display(), name),
+            "LMDB's locks and memory maps don't work reliably on network filesystems; keep \
+             the database on a local disk",
+        ),
+        Some(("tmpfs", _)) => findings.suggest(
+            Status::Warning,
+            "filesystem",
+            format!("{} is on tmpfs", dir.display()),
+            "the database is kept in memory (or swap) and lost on reboot; use a local disk \
+             unless that's intended",
+        ),
+        Some((name, true)) => findings.add(Status::Ok, "filesystem", name.to_string()),
+        None => (),
+    }
+
+    // the file can grow up to the map size, and needs room on disk to do so
+    let file_size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    let free = free_space(dir)?;
+    let growth = MAP_SIZE.saturating_sub(file_size);
+    let message = format!(
+        "{} free; the database can grow by {} before filling its {} map",
+        gib(free),
+        gib(growth),
+        gib(MAP_SIZE)
+    );
+    if free < growth {
+        findings.suggest(
+            Status::Warning,
+            "disk space",
+            message,
+            "imports and updates may fail with \"No space left on device\" before the map is \
+             full; free up space or move the database to a larger disk",
+        );
+    } else {
+        findings.add(Status::Ok, "disk space", message);
+    }
+    if file_size > MAP_SIZE {
+        findings.suggest(
+            Status::Problem,
+            "map size",
+            format!("the file ({}) is larger than the map size", gib(file_size)),
+            "the commands can't open it; compact it with `backup --compact`",
+        );
+    }
+    Ok(())
+}
+
+fn check_database(findings: &mut Findings, path: &Path) -> Result<(), Box<dyn Error>> {
+    let env = match open_env(path, true) {
+        Ok(env) => env,
+        Err(e) => {
+            findings.add(
+                Status::Problem,
+                "database",
+                format!("can't open {}: {}", path.display(), e),
+            );
+            return Ok(());
+        }
+    };
+    let metadata = match env.open_db(Some(osmx::interop::METADATA_TABLE)) {
+        Ok(metadata) => metadata,
+        Err(_) => {
+            findings.add(
+                Status::Problem,
+                "database",
+                format!(
+                    "{} has no metadata table; it isn't an OSMX database",
+                    path.display()
+                ),
+            );
+            return Ok(());
+        }
+    };
+
+    let page_size = env.stat()?.page_size();
+    let host_page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if page_size as libc::c_long != host_page_size {
+        findings.add(
+            Status::Warning,
+            "page size",
+            format!(
+                "the database has {}-byte pages, which differs from this host's",
+                page_size
+            ),
+        );
+    }
+
+    let txn = env.begin_ro_txn()?;
+    let get = |key: &str| match txn.get(metadata, &key.as_bytes()) {
+        Ok(raw_val) => Ok(Some(raw_val)),
+        Err(lmdb::Error::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    };
+
+    let format_version = match get(osmx::FORMAT_VERSION_KEY)? {
+        Some(raw_val) => raw_val.try_into().map(u32::from_le_bytes).unwrap_or(0),
+        None => 1,
+    };
+    if format_version > osmx::FORMAT_VERSION {
+        findings.suggest(
+            Status::Problem,
+            "format version",
+            format!(
+                "{} (this build reads up to {})",
+                format_version,
+                osmx::FORMAT_VERSION
+            ),
+            "upgrade osmx-rs to read this database",
+        );
+    } else if format_version < osmx::MIN_FORMAT_VERSION {
+        findings.suggest(
+            Status::Problem,
+            "format version",
+            format!("{} is malformed or too old", format_version),
+            "re-create the database with `expand`",
+        );
+    } else {
+        findings.add(Status::Ok, "format version", format_version.to_string());
+    }
+
+    match get(KeyByteOrder::METADATA_KEY)?.map(KeyByteOrder::from_bytes) {
+        Some(Ok(order)) if order != KeyByteOrder::NATIVE => findings.suggest(
+            Status::Problem,
+            "byte order",
+            format!(
+                "the database was created on a {}-endian host",
+                order.as_str()
+            ),
+            "LMDB files can't be moved between hosts with different byte orders; re-create \
+             the database on this host with `expand`",
+        ),
+        Some(Err(e)) => findings.add(Status::Problem, "byte order", e.to_string()),
+        _ => (),
+    }
+
+    match get(osmx::WRITER_LOCK_KEY)?.map(osmx::WriterLock::from_bytes) {
+        Some(Some(lock)) if lock.is_stale() => findings.suggest(
+            Status::Warning,
+            "writer lock",
+            format!("held by {}, which has exited", lock),
+            "the writer was interrupted, so the database may be missing its last update; \
+             the next command which writes to it will take over the lock",
+        ),
+        Some(Some(lock)) => findings.add(Status::Ok, "writer lock", format!("held by {}", lock)),
+        Some(None) => findings.add(Status::Warning, "writer lock", "malformed".to_string()),
+        None => findings.add(Status::Ok, "writer lock", "not held".to_string()),
+    }
+    drop(txn);
+
+    let cleared = check_readers(&env)?;
+    if cleared > 0 {
+        findings.suggest(
+            Status::Warning,
+            "reader slots",
+            format!("cleared {} stale reader slots", cleared),
+            "readers which exit without ending their transactions keep old pages from being \
+             reused, so the file grows as it's updated; check that readers aren't being \
+             killed",
+        );
+    }
+    let mut info = lmdb_sys::MDB_envinfo {
+        me_mapaddr: std::ptr::null_mut(),
+        me_mapsize: 0,
+        me_last_pgno: 0,
+        me_last_txnid: 0,
+        me_maxreaders: 0,
+        me_numreaders: 0,
+    };
+    if unsafe { lmdb_sys::mdb_env_info(env.env(), &mut info) } == 0 {
+        let message = format!("{} of {} used", info.me_numreaders, info.me_maxreaders);
+        if info.me_numreaders as f64 >= info.me_maxreaders as f64 * READERS_BUSY {
+            findings.suggest(
+                Status::Warning,
+                "reader slots",
+                message,
+                "new readers will fail when all slots are in use; open the database with a \
+                 larger OpenOptions::max_readers",
+            );
+        } else if cleared == 0 {
+            findings.add(Status::Ok, "reader slots", message);
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let mut findings = Findings::default();
+    check_host(&mut findings, &args.path)?;
+    if args.path.exists() {
+        check_database(&mut findings, &args.path)?;
+    } else {
+        findings.add(
+            Status::Ok,
+            "database",
+            format!("{} doesn't exist yet", args.path.display()),
+        );
+    }
+
+    for finding in &findings.0 {
+        println!(
+            "{:<8} {:<15} {}",
+            finding.status, finding.check, finding.message
+        );
+        if let Some(suggestion) = &finding.suggestion {
+            println!("{:<8} {:<15} -> {}", "", "", suggestion);
+        }
+    }
+
+    let problems = findings
+        .0
+        .iter()
+        .filter(|finding| finding.status == Status::Problem)
+        .count();
+    if problems > 0 {
+        return Err(format!("found {} problems", problems).into());
+    }
+    Ok(())
+}
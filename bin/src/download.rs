@@ -0,0 +1,108 @@
+use std::error::Error;
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+/// How many times a download is resumed after an error before giving up
+const MAX_RETRIES: u32 = 10;
+
+/// The URL of an input "file" which should be downloaded, if it is an http(s) URL
+pub fn remote_url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|s| s.starts_with("https://") || s.starts_with("http://"))
+}
+
+/// A streaming HTTP download. If the connection fails partway through, the download is
+/// resumed from the current position with a Range request, so the caller sees one
+/// uninterrupted stream.
+pub struct Download {
+    agent: ureq::Agent,
+    url: String,
+    etag: Option<String>,
+    length: Option<u64>,
+    position: u64,
+    body: Box<dyn Read + Send + Sync>,
+}
+
+impl Download {
+    pub fn open(url: &str) -> Result<Self, Box<dyn Error>> {
+        // a read timeout turns a stalled connection into an error, so it gets retried
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(30))
+            .timeout_read(Duration::from_secs(60))
+            .build();
+        let response = agent.get(url).call()?;
+        Ok(Download {
+            etag: response.header("ETag").map(str::to_string),
+            length: response
+                .header("Content-Length")
+                .and_then(|len| len.parse().ok()),
+            agent,
+            url: url.to_string(),
+            position: 0,
+            body: response.into_reader(),
+        })
+    }
+
+    /// Request the rest of the file from the current position. Returns false if the
+    /// download can't be resumed.
+    fn resume(&mut self) -> Result<bool, Box<dyn Error>> {
+        let mut request = self
+            .agent
+            .get(&self.url)
+            .set("Range", &format!("bytes={}-", self.position));
+        // if the file has changed, the server ignores the range and sends all of it
+        if let Some(etag) = &self.etag {
+            request = request.set("If-Range", etag);
+        }
+        let response = request.call()?;
+        if response.status() != 206 {
+            return Ok(false);
+        }
+        self.body = response.into_reader();
+        Ok(true)
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut retries = 0;
+        loop {
+            let error = match self.body.read(buf) {
+                // a connection closed early looks like the end of the file
+                Ok(0) if self.length.is_some_and(|len| self.position < len) && !buf.is_empty() => {
+                    io::Error::from(io::ErrorKind::UnexpectedEof)
+                }
+                Ok(n) => {
+                    self.position += n as u64;
+                    return Ok(n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => e,
+            };
+            // resuming can fail too (e.g. while the network is down), so keep trying with
+            // increasing delays
+            loop {
+                if retries == MAX_RETRIES {
+                    return Err(error);
+                }
+                retries += 1;
+                eprintln!(
+                    "download of {} interrupted at byte {} ({}), retrying ({}/{})",
+                    self.url, self.position, error, retries, MAX_RETRIES
+                );
+                std::thread::sleep(Duration::from_secs(1 << retries.min(6)));
+                match self.resume() {
+                    Ok(true) => break,
+                    Ok(false) => {
+                        return Err(io::Error::other(format!(
+                            "{} can't be resumed (it has changed, or the server doesn't support range requests)",
+                            self.url
+                        )));
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use std::error::Error;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::Parser;
+use lmdb::{Cursor, Transaction};
+use osmx::{
+    decode_element_ref, decode_key, decode_string_id, decode_version_key, encode_key,
+    encode_version_key, ValueCompression, ValueEncoding,
+};
+
+use crate::environment::open_env;
+use crate::repack::Format;
+
+/// How the keys and values of a table are decoded
+#[derive(Clone, Copy)]
+enum TableKind {
+    /// String keys, and values which are strings or binary data
+    Metadata,
+    /// Interned string IDs to strings
+    Strings,
+    /// Tag set hashes to tag sets
+    TagSets,
+    /// Node IDs (or node IDs and versions) to locations
+    Locations { history: bool },
+    /// IDs (or IDs and versions) to Cap'n Proto messages, stored in the database's value
+    /// format, with the dictionary of the given element table
+    Messages {
+        element_table: &'static str,
+        history: bool,
+    },
+    /// Changeset IDs to Cap'n Proto messages, always stored in the standard format
+    Changesets,
+    /// IDs to IDs
+    Index,
+    /// IDs to element references
+    ElementRefs,
+    /// Way IDs to bounding boxes
+    BoundingBoxes,
+    /// Element references to packed coordinate arrays
+    Geometries,
+    /// Normalized street names, names and cells to locations and way counts
+    StreetNames,
+    /// Anything else, such as extension tables, which is written as hex
+    Unknown,
+}
+
+impl TableKind {
+    fn of(name: &str) -> Self {
+        match name {
+            "metadata" => Self::Metadata,
+            "strings" => Self::Strings,
+            "tag_sets" => Self::TagSets,
+            "locations" => Self::Locations { history: false },
+            "location_history" => Self::Locations { history: true },
+            "nodes" => Self::Messages {
+                element_table: "nodes",
+                history: false,
+            },
+            "ways" => Self::Messages {
+                element_table: "ways",
+                history: false,
+            },
+            "relations" => Self::Messages {
+                element_table: "relations",
+                history: false,
+            },
+            "node_history" => Self::Messages {
+                element_table: "nodes",
+                history: true,
+            },
+            "way_history" => Self::Messages {
+                element_table: "ways",
+                history: true,
+            },
+            "relation_history" => Self::Messages {
+                element_table: "relations",
+                history: true,
+            },
+            "changesets" => Self::Changesets,
+            "cell_node" | "node_way" | "node_relation" | "way_relation" | "relation_relation"
+            | "tag_node" | "tag_way" | "tag_relation" | "cell_way" | "cell_relation" => Self::Index,
+            "uid_element" | "changeset_element" => Self::ElementRefs,
+            "way_bbox" => Self::BoundingBoxes,
+            "geometries" => Self::Geometries,
+            "street_names" => Self::StreetNames,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Whether keys are element IDs (or IDs and versions), as opposed to byte strings
+    fn has_id_keys(&self) -> bool {
+        !matches!(self, Self::Metadata | Self::StreetNames | Self::Unknown)
+    }
+
+    /// Whether keys are IDs and versions, ordered bytewise
+    fn has_version_keys(&self) -> bool {
+        matches!(
+            self,
+            Self::Locations { history: true } | Self::Messages { history: true, .. }
+        )
+    }
+}
+
+#[derive(Parser)]
+/// Print the raw key/value pairs of a table of an OSMX database, one pair per line
+/// separated by a tab, for debugging. Keys and values are decoded according to the
+/// table (element values are printed as Cap'n Proto messages), or printed as hex with
+/// --hex. Tables which aren't part of the OSMX format are printed as hex.
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Name of the table to dump, e.g. cell_node or metadata
+    table: String,
+    /// Start at this key: an ID (or S2 cell ID, for cell_node), or a string for the
+    /// metadata table
+    #[arg(long)]
+    from: Option<String>,
+    /// Stop after this key (given like --from)
+    #[arg(long)]
+    to: Option<String>,
+    /// Print at most this many pairs
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Print keys and values as hex instead of decoding them
+    #[arg(long)]
+    hex: bool,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The position of a key in the range options, as an ID or a byte string
+fn key_position(kind: TableKind, raw_key: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !kind.has_id_keys() {
+        return Ok(raw_key.to_vec());
+    }
+    let id = if kind.has_version_keys() {
+        decode_version_key(raw_key).map(|(id, _)| id)
+    } else {
+        decode_key(raw_key)
+    };
+    let id = id.ok_or("key with incorrect length")?;
+    Ok(id.to_be_bytes().to_vec())
+}
+
+/// Parse a --from or --to option into a position comparable with key_position
+fn parse_position(kind: TableKind, arg: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !kind.has_id_keys() {
+        return Ok(arg.as_bytes().to_vec());
+    }
+    let id: u64 = arg
+        .parse()
+        .map_err(|_| format!("invalid key {:?} (expected an ID)", arg))?;
+    Ok(id.to_be_bytes().to_vec())
+}
+
+fn decode_message(table: &str, format: &Format, raw_val: &[u8]) -> Result<String, Box<dyn Error>> {
+    Ok(osmx::build::debug_message(table, &format.read(raw_val))?)
+}
+
+fn decode_pair(
+    kind: TableKind,
+    formats: &Formats,
+    raw_key: &[u8],
+    raw_val: &[u8],
+) -> Result<(String, String), Box<dyn Error>> {
+    let key = match kind {
+        TableKind::Metadata => String::from_utf8_lossy(raw_key).into_owned(),
+        TableKind::Strings => decode_string_id(raw_key)
+            .ok_or("key with incorrect length")?
+            .to_string(),
+        _ if kind.has_version_keys() => {
+            let (id, version) = decode_version_key(raw_key).ok_or("key with incorrect length")?;
+            format!("{} v{}", id, version)
+        }
+        TableKind::Unknown => hex(raw_key),
+        TableKind::StreetNames => {
+            let (normalized, name, cell) =
+                osmx::streets::decode_street_name_key(raw_key).ok_or("malformed key")?;
+            format!(
+                "{} | {} | {}",
+                normalized,
+                name,
+                s2::cellid::CellID(cell).to_token()
+            )
+        }
+        TableKind::Geometries => format!(
+            "{:?}",
+            decode_element_ref(raw_key).ok_or("invalid element reference")?
+        ),
+        _ => decode_key(raw_key)
+            .ok_or("key with incorrect length")?
+            .to_string(),
+    };
+
+    let value = match kind {
+        // metadata values are mostly strings, but some (like bitmaps) are binary
+        TableKind::Metadata => match std::str::from_utf8(raw_val) {
+            Ok(s) if !s.chars().any(char::is_control) => s.to_string(),
+            _ => format!("0x{}", hex(raw_val)),
+        },
+        TableKind::Strings => String::from_utf8_lossy(raw_val).into_owned(),
+        TableKind::TagSets => {
+            let tags = osmx::decode_tag_set(raw_val).ok_or("malformed tag set")?;
+            let tags: Vec<String> = tags.chunks(2).map(|tag| tag.join("=")).collect();
+            tags.join(" ")
+        }
+        TableKind::Locations { .. } => {
+            // longitude and latitude, followed by the version of the node
+            if raw_val.len() != 12 {
+                return Err("location value with incorrect length".into());
+            }
+            let location = osmx::Location::try_from(raw_val).unwrap();
+            let version = u32::from_le_bytes(raw_val[8..12].try_into().unwrap());
+            format!("{:.7} {:.7} v{}", location.lon(), location.lat(), version)
+        }
+        TableKind::Messages { element_table, .. } => {
+            decode_message(element_table, &formats.get(element_table), raw_val)?
+        }
+        TableKind::Changesets => {
+            let format = Format {
+                encoding: ValueEncoding::Standard,
+                dictionary: None,
+            };
+            decode_message("changesets", &format, raw_val)?
+        }
+        TableKind::Index => decode_key(raw_val)
+            .ok_or("value with incorrect length")?
+            .to_string(),
+        TableKind::ElementRefs => {
+            format!(
+                "{:?}",
+                decode_element_ref(raw_val).ok_or("invalid element reference")?
+            )
+        }
+        TableKind::BoundingBoxes => {
+            // west, south, east and north
+            if raw_val.len() != 16 {
+                return Err("bounding box value with incorrect length".into());
+            }
+            let degrees: Vec<String> = raw_val
+                .chunks(4)
+                .map(|raw| i32::from_le_bytes(raw.try_into().unwrap()) as f64 / 1e7)
+                .map(|degrees| format!("{:.7}", degrees))
+                .collect();
+            degrees.join(" ")
+        }
+        TableKind::Geometries => {
+            // parts (a way's nodes, or a multipolygon's rings) separated by commas
+            let parts = osmx::geometry::decode_geometry(raw_val)
+                .ok_or("geometry value with incorrect length")?;
+            let parts: Vec<String> = parts
+                .iter()
+                .map(|part| {
+                    let points: Vec<String> = part
+                        .iter()
+                        .map(|&(lon, lat)| {
+                            format!("{:.7} {:.7}", lon as f64 / 1e7, lat as f64 / 1e7)
+                        })
+                        .collect();
+                    points.join(" ")
+                })
+                .collect();
+            parts.join(", ")
+        }
+        TableKind::StreetNames => {
+            let (lon, lat, way_count) = osmx::streets::decode_street_name_value(raw_val)
+                .ok_or("street name value with incorrect length")?;
+            format!(
+                "{:.7} {:.7} {} ways",
+                lon as f64 / 1e7,
+                lat as f64 / 1e7,
+                way_count
+            )
+        }
+        TableKind::Unknown => hex(raw_val),
+    };
+
+    Ok((key, value))
+}
+
+/// The value format of the element tables, read from the metadata table
+struct Formats {
+    encoding: ValueEncoding,
+    /// The dictionary of each element table, if values are compressed
+    dictionaries: Vec<(&'static str, Vec<u8>)>,
+}
+
+impl Formats {
+    fn read(txn: &lmdb::RoTransaction, metadata: lmdb::Database) -> Result<Self, Box<dyn Error>> {
+        let get = |key: &str| match txn.get(metadata, &key.as_bytes()) {
+            Ok(raw_val) => Ok(Some(raw_val)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e),
+        };
+        let encoding = match get(ValueEncoding::METADATA_KEY)? {
+            Some(raw_val) => ValueEncoding::from_bytes(raw_val)?,
+            None => ValueEncoding::Standard,
+        };
+        let compression = match get(ValueCompression::METADATA_KEY)? {
+            Some(raw_val) => ValueCompression::from_bytes(raw_val)?,
+            None => ValueCompression::None,
+        };
+        let mut dictionaries = vec![];
+        if compression == ValueCompression::Zstd {
+            for table in ["nodes", "ways", "relations"] {
+                let key = ValueCompression::dictionary_key(table);
+                let dictionary = get(&key)?.ok_or_else(|| format!("missing {}", key))?;
+                dictionaries.push((table, dictionary.to_vec()));
+            }
+        }
+        Ok(Self {
+            encoding,
+            dictionaries,
+        })
+    }
+
+    fn get(&self, element_table: &str) -> Format<'_> {
+        Format {
+            encoding: self.encoding,
+            dictionary: self
+                .dictionaries
+                .iter()
+                .find(|(table, _)| *table == element_table)
+                .map(|(_, dictionary)| dictionary.as_slice()),
+        }
+    }
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let kind = TableKind::of(&args.table);
+    let from = args
+        .from
+        .as_deref()
+        .map(|arg| parse_position(kind, arg))
+        .transpose()?;
+    let to = args
+        .to
+        .as_deref()
+        .map(|arg| parse_position(kind, arg))
+        .transpose()?;
+
+    let env = open_env(&args.input_file, true)?;
+    let metadata = env.open_db(Some("metadata"))?;
+    let table = match env.open_db(Some(&args.table)) {
+        Ok(table) => table,
+        Err(lmdb::Error::NotFound) => return Err(format!("no table named {}", args.table).into()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let txn = env.begin_ro_txn()?;
+    let formats = Formats::read(&txn, metadata)?;
+    let mut cursor = txn.open_ro_cursor(table)?;
+
+    // the first key at or after --from, if any
+    let start_key = match (&args.from, kind) {
+        (None, _) => None,
+        (Some(_), TableKind::Metadata | TableKind::StreetNames | TableKind::Unknown) => {
+            from.clone()
+        }
+        (Some(arg), _) => {
+            let id: u64 = arg.parse()?;
+            if kind.has_version_keys() {
+                Some(encode_version_key(id, 0).to_vec())
+            } else {
+                Some(encode_key(id).to_vec())
+            }
+        }
+    };
+    // iter_start and iter_from panic if there are no keys at or after the start key
+    let found = match &start_key {
+        Some(start_key) => cursor.get(Some(start_key), None, lmdb_sys::MDB_SET_RANGE),
+        None => cursor.get(None, None, lmdb_sys::MDB_FIRST),
+    };
+    let iter = match found {
+        Ok(_) => match start_key {
+            Some(start_key) => Some(cursor.iter_from(start_key)),
+            None => Some(cursor.iter_start()),
+        },
+        Err(lmdb::Error::NotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut writer = BufWriter::new(std::io::stdout().lock());
+    for (raw_key, raw_val) in iter
+        .into_iter()
+        .flatten()
+        .take(args.limit.unwrap_or(usize::MAX))
+    {
+        if let Some(to) = &to {
+            if key_position(kind, raw_key)? > *to {
+                break;
+            }
+        }
+        if args.hex {
+            writeln!(writer, "{}\t{}", hex(raw_key), hex(raw_val))?;
+        } else {
+            let (key, value) = decode_pair(kind, &formats, raw_key, raw_val)?;
+            writeln!(writer, "{}\t{}", key, value)?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}
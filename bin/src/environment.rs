@@ -0,0 +1,125 @@
+//! Opening the LMDB environments of .osmx files directly, for the commands which work on
+//! their tables rather than through the library
+
+use std::error::Error;
+use std::path::Path;
+
+use lmdb::{Cursor, Transaction};
+
+/// The flags to open an existing environment with: `NO_SUB_DIR` for OSMX files, or none
+/// for directory-style environments (a directory holding `data.mdb` and `lock.mdb`)
+pub fn environment_flags(path: &Path) -> lmdb::EnvironmentFlags {
+    if path.is_dir() {
+        lmdb::EnvironmentFlags::empty()
+    } else {
+        lmdb::EnvironmentFlags::NO_SUB_DIR
+    }
+}
+
+/// Open the LMDB environment of an .osmx file
+pub fn open_env(path: &Path, read_only: bool) -> Result<lmdb::Environment, Box<dyn Error>> {
+    let mut flags = environment_flags(path) | lmdb::EnvironmentFlags::NO_READAHEAD;
+    if read_only {
+        flags |= lmdb::EnvironmentFlags::READ_ONLY;
+    }
+
+    Ok(lmdb::Environment::new()
+        .set_flags(flags)
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
+        .open(path)?)
+}
+
+/// Clear the reader slots held by dead processes (see `osmx::Database::check_readers`),
+/// and return how many were cleared
+pub fn check_readers(env: &lmdb::Environment) -> Result<usize, Box<dyn Error>> {
+    let mut cleared = 0;
+    let rc = unsafe { lmdb_sys::mdb_reader_check(env.env(), &mut cleared) };
+    if rc != 0 {
+        return Err(lmdb::Error::from_err_code(rc).into());
+    }
+    Ok(cleared as usize)
+}
+
+/// Check that there is a database at `path`, for commands which would otherwise create
+/// an empty one there (as opening an environment does)
+pub fn require_database(path: &Path) -> Result<(), Box<dyn Error>> {
+    let data_file = if path.is_dir() {
+        path.join("data.mdb")
+    } else {
+        path.to_path_buf()
+    };
+    if !data_file.exists() {
+        return Err(format!("{} does not exist", data_file.display()).into());
+    }
+    Ok(())
+}
+
+/// Run `write` while holding the writer lock of the database at `path` (creating the
+/// database if it doesn't exist), so that commands which write to the same database
+/// don't interleave. Returns an error without running `write` if another process holds
+/// the lock. The environment is closed while `write` runs, so that it can open the
+/// database itself.
+pub fn with_writer_lock<T>(
+    path: &Path,
+    operation: &str,
+    write: impl FnOnce() -> Result<T, Box<dyn Error>>,
+) -> Result<T, Box<dyn Error>> {
+    let lock = osmx::WriterLock::for_current_process(operation);
+    {
+        let env = open_env(path, false)?;
+        let metadata = env.create_db(
+            Some(osmx::interop::METADATA_TABLE),
+            lmdb::DatabaseFlags::empty(),
+        )?;
+        let mut txn = env.begin_rw_txn()?;
+        match txn.get(metadata, &osmx::WRITER_LOCK_KEY.as_bytes()) {
+            Ok(raw_val) => match osmx::WriterLock::from_bytes(raw_val) {
+                Some(holder) if !holder.is_stale() => {
+                    return Err(format!("{} is locked by {}", path.display(), holder).into());
+                }
+                _ => (),
+            },
+            Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+        txn.put(
+            metadata,
+            &osmx::WRITER_LOCK_KEY.as_bytes(),
+            &lock.to_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+        txn.commit()?;
+    }
+
+    let result = write();
+
+    // the lock is released even if writing failed, unless another process has taken it
+    // over in the meantime (e.g. because it was broken)
+    let env = open_env(path, false)?;
+    let metadata = env.open_db(Some(osmx::interop::METADATA_TABLE))?;
+    let mut txn = env.begin_rw_txn()?;
+    match txn.get(metadata, &osmx::WRITER_LOCK_KEY.as_bytes()) {
+        Ok(raw_val) if osmx::WriterLock::from_bytes(raw_val).as_ref() == Some(&lock) => {
+            txn.del(metadata, &osmx::WRITER_LOCK_KEY.as_bytes(), None)?;
+            txn.commit()?;
+            env.sync(true)?;
+        }
+        Ok(_) | Err(lmdb::Error::NotFound) => (),
+        Err(e) => return Err(e.into()),
+    }
+    result
+}
+
+/// List the names of the tables in an LMDB environment (which are the keys of its
+/// unnamed main table)
+pub fn table_names(env: &lmdb::Environment) -> Result<Vec<String>, Box<dyn Error>> {
+    let main = env.open_db(None)?;
+    let txn = env.begin_ro_txn()?;
+    let mut cursor = txn.open_ro_cursor(main)?;
+    let names = cursor
+        .iter()
+        .map(|(raw_key, _)| String::from_utf8(raw_key.to_vec()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(names)
+}
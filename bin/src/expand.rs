@@ -1,233 +1,490 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fs::File;
-use std::io::BufReader;
-use std::path::PathBuf;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
 use clap::Parser;
-use indicatif::{ProgressBar, ProgressStyle};
-use lmdb::Transaction;
+use lmdb::{Cursor, Transaction};
+use osmx::build::{
+    ChangesetBuilder, ElementMetadata, ElementType, LocationBuilder, NodeBuilder, RelationBuilder,
+    WayBuilder,
+};
+use osmx::{
+    decode_key, encode_element_ref, encode_key, encode_string_id, encode_version_key, geometry,
+    interop, Decode, ElementId, KeyByteOrder, ProgressSink,
+};
+use roaring::RoaringTreemap;
 use serde::{Deserialize, Serialize};
 
-use crate::builders::{ElementType, LocationBuilder, NodeBuilder, RelationBuilder, WayBuilder};
+use crate::checksum::Checksum;
+use crate::download::{remote_url, Download};
+use crate::environment::with_writer_lock;
+use crate::interner::Interner;
+use crate::progress::ProgressBars;
 use crate::sorter::Sorter;
+use crate::tag_sets::TagSets;
+use crate::trace;
 
 #[derive(Parser)]
 /// Convert an OSM PBF file to an OSMX database
 pub struct CliArgs {
-    /// Path of an .osm.pbf file to read
+    /// Path of an .osm.pbf file to read, or an http(s) URL to stream it from (interrupted
+    /// downloads are resumed, and the file isn't saved to disk)
     input_file: PathBuf,
     /// Path of the .osmx file to create
     output_file: PathBuf,
+    /// Store bitmaps of all node, way and relation IDs in the metadata table
+    #[arg(long)]
+    id_bitmaps: bool,
+    /// Store element values using Cap'n Proto packed encoding (smaller, slightly slower to read)
+    #[arg(long)]
+    packed: bool,
+    /// Store common tag keys and values once in a string table, instead of inline in each
+    /// element (requires an extra partial pass over the input file)
+    #[arg(long)]
+    intern_strings: bool,
+    /// Store tag sets which are common in the input (like building=yes) once in a
+    /// tag_sets table, and refer to them by hash from element values instead of storing
+    /// their tags inline (requires an extra partial pass over the input file)
+    #[arg(long)]
+    share_tag_sets: bool,
+    /// Keep every version of each element in history tables, for reading past versions
+    /// of elements (the input should be an OSM history file, e.g. .osh.pbf)
+    #[arg(long)]
+    history: bool,
+    /// Store element metadata (version, timestamp, changeset and author) in element values,
+    /// and build a changesets table summarizing each changeset. Implied by --history.
+    #[arg(long)]
+    metadata: bool,
+    /// Build an index of the elements edited by each user (see
+    /// Transaction::elements_by_uid). Databases with it can't be updated, since element
+    /// values don't store the user of each version without --metadata
+    #[arg(long)]
+    uid_index: bool,
+    /// Build an index of the elements edited in each changeset (see
+    /// Transaction::elements_in_changeset). Databases with it can't be updated, since
+    /// element values don't store the changeset of each version without --metadata
+    #[arg(long)]
+    changeset_index: bool,
+    /// Store the bounding box of each way in a way_bbox table (see Transaction::way_bbox),
+    /// so that ways can be filtered by location without looking up their nodes
+    #[arg(long)]
+    way_bbox: bool,
+    /// Store the coordinates of each way, and the rings of each multipolygon and boundary
+    /// relation, in a geometries table, so that geometries can be read without looking up
+    /// the location of every node
+    #[arg(long)]
+    with_geometry: bool,
+    /// Build an index of the names of highway ways in a street_names table, for
+    /// completing street names as they're typed (see Transaction::complete_street)
+    #[arg(long)]
+    street_names: bool,
+    /// Build tag_node, tag_way and tag_relation tables indexing the elements with each
+    /// of these tag keys (comma-separated), by key and by key and value, so that
+    /// Transaction::find_by_tag can find them without reading every element
+    #[arg(long, value_delimiter = ',')]
+    index_tags: Vec<String>,
+    /// Build cell_way and cell_relation tables indexing the cells covering the bounding
+    /// box of each way and of each relation's members, so that
+    /// Transaction::ways_in_region and relations_in_region find them directly (implies
+    /// --way-bbox)
+    #[arg(long)]
+    cell_index: bool,
+    /// Maintain a custom index with the plugin of this name (see osmx::PluginRegistry), in
+    /// an extension table of the same name. Can be given more than once.
+    #[arg(
+        long,
+        value_parser = clap::builder::PossibleValuesParser::new(osmx::PluginRegistry::new().names())
+    )]
+    index: Vec<String>,
+    /// Import the input into an existing database (e.g. to add a region missing from a
+    /// multi-region database) instead of creating one. New elements are added and the
+    /// index tables, ID bitmaps, way_bbox, geometries and street_names tables (and the
+    /// indexes of any plugins) are updated. Nodes in
+    /// both are replaced if the input has a newer version; ways and relations already in
+    /// the database are kept, since their versions aren't stored. Databases with history,
+    /// metadata, interned strings, compression or the uid and changeset indexes aren't
+    /// supported.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "id_bitmaps", "packed", "intern_strings", "share_tag_sets", "history", "metadata",
+            "uid_index", "changeset_index", "way_bbox", "with_geometry", "street_names",
+            "index_tags", "cell_index", "index",
+        ]
+    )]
+    append: bool,
+    /// Number of threads decoding and encoding the elements of the input (defaults to the
+    /// number of CPUs). Elements are still written in the order of the input.
+    #[arg(long)]
+    threads: Option<usize>,
 }
 
+/// Number of blobs per decoding thread which can be waiting to be decoded, or decoded
+/// and waiting to be written
+const BLOBS_AHEAD: usize = 4;
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
-struct IDPair(u64, u64);
+pub struct IDPair(pub u64, pub u64);
 
 /// Reads sorted tuples from a Sorter and appends them to an LMDB table
-fn insert_sorted_tuples(
+pub fn insert_sorted_tuples(
     sorter: Sorter<IDPair>,
     txn: &mut lmdb::RwTransaction,
     table: lmdb::Database,
+    progress: Option<&dyn ProgressSink>,
 ) {
-    let bar = ProgressBar::new(sorter.count());
-    bar.set_style(
-        ProgressStyle::with_template("[{elapsed_precise}] {msg:>20} [{bar:40}] {pos}/{len}")
-            .unwrap()
-            .progress_chars("=> "),
-    );
-    bar.set_message(sorter.name().to_string());
+    put_sorted_tuples(sorter, txn, table, lmdb::WriteFlags::APPEND_DUP, progress)
+}
+
+/// Reads sorted tuples from a Sorter and adds them to an LMDB table which already has
+/// entries, skipping tuples which are already in it
+fn merge_sorted_tuples(
+    sorter: Sorter<IDPair>,
+    txn: &mut lmdb::RwTransaction,
+    table: lmdb::Database,
+    progress: Option<&dyn ProgressSink>,
+) {
+    put_sorted_tuples(sorter, txn, table, lmdb::WriteFlags::NO_DUP_DATA, progress)
+}
+
+fn put_sorted_tuples(
+    sorter: Sorter<IDPair>,
+    txn: &mut lmdb::RwTransaction,
+    table: lmdb::Database,
+    flags: lmdb::WriteFlags,
+    progress: Option<&dyn ProgressSink>,
+) {
+    let _phase = trace::phase(sorter.name());
+    let (name, total) = (sorter.name().to_string(), sorter.count());
+    let mut done = 0;
+    if let Some(progress) = progress {
+        progress.progress(&name, done, Some(total));
+    }
 
     for IDPair(key, val) in sorter.sorted() {
-        match txn.put(
-            table,
-            &key.to_le_bytes(),
-            &val.to_le_bytes(),
-            lmdb::WriteFlags::APPEND_DUP,
-        ) {
+        match txn.put(table, &encode_key(key), &encode_key(val), flags) {
             Ok(_) => {
                 // eprintln!("Ok       {} {}", node, way);
             }
+            Err(lmdb::Error::KeyExist) if flags.contains(lmdb::WriteFlags::NO_DUP_DATA) => {}
             Err(e) => {
                 eprintln!("{:?} {} {}", e, key, val);
             }
         }
         // eprintln!("{} {}", node, way);
-        bar.inc(1);
+        done += 1;
+        if let Some(progress) = progress {
+            progress.progress(&name, done, Some(total));
+        }
+    }
+    if let Some(progress) = progress {
+        progress.finish(&name);
     }
-    bar.finish();
 }
 
-pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
-    let env = lmdb::Environment::new()
-        .set_flags(
-            lmdb::EnvironmentFlags::NO_SUB_DIR
-                | lmdb::EnvironmentFlags::NO_READAHEAD
-                | lmdb::EnvironmentFlags::NO_SYNC,
-        )
-        .set_max_dbs(10)
-        .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
-        .open(args.output_file.as_ref())?;
-
-    let element_flags = lmdb::DatabaseFlags::INTEGER_KEY;
-    let index_flags = lmdb::DatabaseFlags::INTEGER_KEY
-        | lmdb::DatabaseFlags::INTEGER_DUP
-        | lmdb::DatabaseFlags::DUP_SORT
-        | lmdb::DatabaseFlags::DUP_FIXED;
-
-    let metadata = env.create_db(Some("metadata"), lmdb::DatabaseFlags::empty())?;
-    let locations = env.create_db(Some("locations"), element_flags)?;
-    let nodes = env.create_db(Some("nodes"), element_flags)?;
-    let ways = env.create_db(Some("ways"), element_flags)?;
-    let relations = env.create_db(Some("relations"), element_flags)?;
-    let cell_node = env.create_db(Some("cell_node"), index_flags)?;
-    let node_way = env.create_db(Some("node_way"), index_flags)?;
-    let node_relation = env.create_db(Some("node_relation"), index_flags)?;
-    let way_relation = env.create_db(Some("way_relation"), index_flags)?;
-    let relation_relation = env.create_db(Some("relation_relation"), index_flags)?;
-
-    let mut txn = env.begin_rw_txn()?;
+/// An element version created in a changeset, collected to build the changesets table.
+/// Sorting groups the versions created in each changeset together.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+struct ChangesetEdit {
+    changeset: u32,
+    // element type (0 = node, 1 = way, 2 = relation), ID and version
+    element: (u8, u64, u32),
+    timestamp: u64,
+    uid: u32,
+    coordinates: Option<(i32, i32)>,
+}
 
-    let tempdir = PathBuf::from(format!("{}-tmp", args.output_file.to_str().unwrap()));
-    std::fs::create_dir_all(&tempdir).unwrap();
+/// Reads sorted element versions from a Sorter, and appends a summary of each changeset
+/// to the changesets table
+fn insert_changesets(
+    sorter: Sorter<ChangesetEdit>,
+    users: &HashMap<u32, String>,
+    txn: &mut lmdb::RwTransaction,
+    table: lmdb::Database,
+    progress: Option<&dyn ProgressSink>,
+) {
+    let _phase = trace::phase(sorter.name());
+    let (name, total) = (sorter.name().to_string(), sorter.count());
+    let mut done = 0;
+    if let Some(progress) = progress {
+        progress.progress(&name, done, Some(total));
+    }
 
-    let mut cell_node_sorter: Sorter<IDPair> = Sorter::new(&tempdir, "cell_node");
-    let mut node_way_sorter: Sorter<IDPair> = Sorter::new(&tempdir, "node_way");
-    let mut node_relation_sorter: Sorter<IDPair> = Sorter::new(&tempdir, "node_relation");
-    let mut way_relation_sorter: Sorter<IDPair> = Sorter::new(&tempdir, "way_relation");
-    let mut relation_relation_sorter: Sorter<IDPair> = Sorter::new(&tempdir, "relation_relation");
+    let write = |txn: &mut lmdb::RwTransaction, edits: &[ChangesetEdit]| {
+        let first = &edits[0];
+        let count = |t: u8| edits.iter().filter(|e| e.element.0 == t).count() as u32;
 
-    // write metadata table
+        let mut builder = ChangesetBuilder::new();
+        builder.set_user(
+            first.uid,
+            users.get(&first.uid).map(|s| s.as_str()).unwrap_or(""),
+        );
+        builder.set_timestamps(
+            edits.iter().map(|e| e.timestamp).min().unwrap(),
+            edits.iter().map(|e| e.timestamp).max().unwrap(),
+        );
+        builder.set_counts(count(0), count(1), count(2));
 
-    let header = osmpbf::BlobReader::new(BufReader::new(File::open(&args.input_file)?))
-        .map(|r| r.unwrap())
-        .filter(|blob| match blob.get_type() {
-            osmpbf::BlobType::OsmHeader => true,
-            _ => false,
-        })
-        .next()
-        .unwrap()
-        .to_headerblock()?;
+        let coordinates: Vec<(i32, i32)> = edits.iter().filter_map(|e| e.coordinates).collect();
+        if !coordinates.is_empty() {
+            builder.set_bbox(
+                coordinates.iter().map(|c| c.0).min().unwrap(),
+                coordinates.iter().map(|c| c.1).min().unwrap(),
+                coordinates.iter().map(|c| c.0).max().unwrap(),
+                coordinates.iter().map(|c| c.1).max().unwrap(),
+            );
+        }
 
-    if let Some(timestamp) = header.osmosis_replication_timestamp() {
         txn.put(
-            metadata,
-            &"osmosis_replication_timestamp".as_bytes(),
-            &timestamp.to_ne_bytes(),
-            lmdb::WriteFlags::empty(),
-        )?;
-    }
+            table,
+            &encode_key(first.changeset as u64),
+            &builder.build(),
+            lmdb::WriteFlags::APPEND,
+        )
+        .unwrap();
+    };
 
-    if let Some(seqno) = header.osmosis_replication_timestamp() {
-        txn.put(
-            metadata,
-            &"osmosis_replication_timestamp".as_bytes(),
-            &seqno.to_ne_bytes(),
-            lmdb::WriteFlags::empty(),
-        )?;
+    let mut edits: Vec<ChangesetEdit> = vec![];
+    for edit in sorter.sorted() {
+        if edits.first().is_some_and(|e| e.changeset != edit.changeset) {
+            write(txn, &edits);
+            edits.clear();
+        }
+        edits.push(edit);
+        done += 1;
+        if let Some(progress) = progress {
+            progress.progress(&name, done, Some(total));
+        }
     }
+    if !edits.is_empty() {
+        write(txn, &edits);
+    }
+    if let Some(progress) = progress {
+        progress.finish(&name);
+    }
+}
 
-    txn.put(
-        metadata,
-        &"import_filename".as_bytes(),
-        &args.input_file.as_os_str().as_encoded_bytes(),
-        lmdb::WriteFlags::empty(),
-    )?;
+/// One version of an element read from the input file, encoded and ready to be
+/// written to the element tables
+pub struct Record {
+    pub element_type: ElementType,
+    pub id: u64,
+    metadata: ElementMetadata,
+    /// The element's Cap'n Proto message
+    value: Vec<u8>,
+    /// For nodes: the encoded location, and the S2 cell containing it
+    location: Option<(Vec<u8>, u64)>,
+    /// For nodes: the coordinates, in units of 1e-7 degrees
+    pub coordinates: Option<(i32, i32)>,
+    /// For nodes: whether the node has any tags (the nodes table omits untagged nodes)
+    tagged: bool,
+    /// For ways and relations: the distinct elements which this element refers to
+    pub refs: HashSet<(ElementType, u64)>,
+    /// For ways: the nodes, in order. For multipolygon and boundary relations: the
+    /// member ways which make up the rings.
+    geometry_refs: Vec<u64>,
+    /// For ways tagged highway=*: the name tag
+    street_name: Option<String>,
+    /// The tags, if they were kept for index plugins (and otherwise empty)
+    tags: Vec<(String, String)>,
+}
 
-    // read .osm.pbf file and process each element
+impl Record {
+    pub fn element_id(&self) -> ElementId {
+        match self.element_type {
+            ElementType::Node => ElementId::Node(self.id),
+            ElementType::Way => ElementId::Way(self.id),
+            ElementType::Relation => ElementId::Relation(self.id),
+        }
+    }
+}
 
-    let reader = osmpbf::ElementReader::from_path(&args.input_file)?;
-    reader.for_each(|elem| match elem {
-        osmpbf::Element::Node(node) => {
-            let id = node.id() as u64;
+fn info_metadata(info: &osmpbf::Info) -> ElementMetadata {
+    ElementMetadata {
+        version: info.version().unwrap_or(0) as u32,
+        timestamp: (info.milli_timestamp().unwrap_or(0) / 1000) as u64,
+        changeset: info.changeset().unwrap_or(0) as u32,
+        uid: info.uid().unwrap_or(0) as u32,
+        user: info.user().and_then(|u| u.ok()).unwrap_or("").to_string(),
+        deleted: !info.visible(),
+    }
+}
 
-            let location = LocationBuilder {
-                longitude: node.lon(),
-                latitude: node.lat(),
-                version: node.info().version().unwrap() as u32,
-            };
+fn dense_info_metadata(info: Option<&osmpbf::DenseNodeInfo>) -> ElementMetadata {
+    match info {
+        Some(info) => ElementMetadata {
+            version: info.version() as u32,
+            timestamp: (info.milli_timestamp() / 1000) as u64,
+            changeset: info.changeset() as u32,
+            uid: info.uid() as u32,
+            user: info.user().unwrap_or("").to_string(),
+            deleted: !info.visible(),
+        },
+        None => ElementMetadata {
+            version: 0,
+            timestamp: 0,
+            changeset: 0,
+            uid: 0,
+            user: String::new(),
+            deleted: false,
+        },
+    }
+}
 
-            txn.put(
-                locations,
-                &id.to_ne_bytes(),
-                &location.build(),
-                lmdb::WriteFlags::APPEND,
+/// Describe the first negative ID in an element, if any: either its own ID, or the ID of
+/// a node or member it references. Negative IDs are used for new elements in files
+/// written by editors, but OSMX keys are unsigned (and ordered as such by LMDB), so such
+/// files can't be imported.
+pub fn negative_id(elem: &osmpbf::Element) -> Option<String> {
+    match elem {
+        osmpbf::Element::Node(node) if node.id() < 0 => Some(format!("node {}", node.id())),
+        osmpbf::Element::DenseNode(node) if node.id() < 0 => Some(format!("node {}", node.id())),
+        osmpbf::Element::Way(way) if way.id() < 0 => Some(format!("way {}", way.id())),
+        osmpbf::Element::Way(way) => way
+            .refs()
+            .find(|&id| id < 0)
+            .map(|id| format!("node {} (in way {})", id, way.id())),
+        osmpbf::Element::Relation(rel) if rel.id() < 0 => Some(format!("relation {}", rel.id())),
+        osmpbf::Element::Relation(rel) => rel.members().find(|m| m.member_id < 0).map(|m| {
+            format!(
+                "{:?} {} (in relation {})",
+                m.member_type,
+                m.member_id,
+                rel.id()
             )
-            .unwrap();
-
-            let latlng = s2::latlng::LatLng::from_degrees(node.lat(), node.lon());
-            let cell = s2::cellid::CellID::from(latlng).parent(osmx::CELL_INDEX_LEVEL);
-            cell_node_sorter.push(IDPair(cell.0, id));
-
-            if node.tags().len() == 0 {
-                return;
-            }
-
-            let tags: Vec<&str> = node.tags().map(|(k, v)| [k, v]).flatten().collect();
-
-            let buf = NodeBuilder::new().set_tags(&tags[..]).build();
+            .to_lowercase()
+        }),
+        _ => None,
+    }
+}
 
-            txn.put(nodes, &id.to_ne_bytes(), &buf, lmdb::WriteFlags::APPEND)
-                .unwrap();
-        }
-        osmpbf::Element::DenseNode(node) => {
-            let id = node.id() as u64;
+/// Encode an element read from the input file. Element metadata is only stored in
+/// element values if `with_metadata` is set, and the tags are only kept in the Record
+/// (for index plugins) if `keep_tags` is set.
+pub fn read_element(
+    elem: &osmpbf::Element,
+    interner: Option<&Interner>,
+    tag_sets: Option<&TagSets>,
+    encoding: osmx::ValueEncoding,
+    with_metadata: bool,
+    keep_tags: bool,
+) -> Record {
+    let kept_tags = |tags: &[&str]| match keep_tags {
+        true => tags
+            .chunks(2)
+            .map(|tag| (tag[0].to_string(), tag[1].to_string()))
+            .collect(),
+        false => vec![],
+    };
+    match elem {
+        osmpbf::Element::Node(_) | osmpbf::Element::DenseNode(_) => {
+            let (id, lon, lat, metadata, tags): (u64, f64, f64, ElementMetadata, Vec<&str>) =
+                match elem {
+                    osmpbf::Element::Node(node) => (
+                        node.id() as u64,
+                        node.lon(),
+                        node.lat(),
+                        info_metadata(&node.info()),
+                        node.tags().flat_map(|(k, v)| [k, v]).collect(),
+                    ),
+                    osmpbf::Element::DenseNode(node) => (
+                        node.id() as u64,
+                        node.lon(),
+                        node.lat(),
+                        dense_info_metadata(node.info()),
+                        node.tags().flat_map(|(k, v)| [k, v]).collect(),
+                    ),
+                    _ => unreachable!(),
+                };
 
-            let location = LocationBuilder {
-                longitude: node.lon(),
-                latitude: node.lat(),
-                version: node.info().unwrap().version() as u32,
+            // deleted versions of nodes don't have a meaningful location
+            let location = if metadata.deleted {
+                None
+            } else {
+                let location = LocationBuilder {
+                    longitude: lon,
+                    latitude: lat,
+                    version: metadata.version,
+                };
+                let latlng = s2::latlng::LatLng::from_degrees(lat, lon);
+                let cell = s2::cellid::CellID::from(latlng).parent(osmx::CELL_INDEX_LEVEL);
+                Some((location.build(), cell.0))
             };
 
-            txn.put(
-                locations,
-                &id.to_ne_bytes(),
-                &location.build(),
-                lmdb::WriteFlags::APPEND,
-            )
-            .unwrap();
+            let tagged = !tags.is_empty();
+            let value = if tagged || with_metadata {
+                let mut builder = NodeBuilder::new();
+                match (tag_sets.and_then(|sets| sets.get(&tags)), interner) {
+                    (Some(hash), _) => builder.set_tag_set(hash),
+                    (None, Some(interner)) => {
+                        builder.set_interned_tags(&tags[..], |s| interner.get(s))
+                    }
+                    (None, None) => builder.set_tags(&tags[..]),
+                };
+                if with_metadata {
+                    builder.set_metadata(&metadata);
+                }
+                builder.build(encoding)
+            } else {
+                vec![]
+            };
 
-            let latlng = s2::latlng::LatLng::from_degrees(node.lat(), node.lon());
-            let cell = s2::cellid::CellID::from(latlng).parent(osmx::CELL_INDEX_LEVEL);
-            cell_node_sorter.push(IDPair(cell.0, id));
+            let coordinates = (!metadata.deleted)
+                .then(|| ((lon * 1e7).round() as i32, (lat * 1e7).round() as i32));
 
-            if node.tags().len() == 0 {
-                return;
+            Record {
+                element_type: ElementType::Node,
+                id,
+                metadata,
+                value,
+                location,
+                coordinates,
+                tagged,
+                refs: HashSet::new(),
+                geometry_refs: vec![],
+                street_name: None,
+                tags: kept_tags(&tags),
             }
-
-            let tags: Vec<&str> = node.tags().map(|(k, v)| [k, v]).flatten().collect();
-
-            let buf = NodeBuilder::new().set_tags(&tags[..]).build();
-
-            txn.put(nodes, &id.to_ne_bytes(), &buf, lmdb::WriteFlags::APPEND)
-                .unwrap();
         }
         osmpbf::Element::Way(way) => {
-            let way_id = way.id() as u64;
-            let tags: Vec<&str> = way.tags().map(|(k, v)| [k, v]).flatten().collect();
+            let metadata = info_metadata(&way.info());
+            let tags: Vec<&str> = way.tags().flat_map(|(k, v)| [k, v]).collect();
             let nodes: Vec<u64> = way.refs().map(|id| id as u64).collect();
 
             let mut builder = WayBuilder::new();
-
-            builder.set_tags(&tags[..]);
+            match (tag_sets.and_then(|sets| sets.get(&tags)), interner) {
+                (Some(hash), _) => builder.set_tag_set(hash),
+                (None, Some(interner)) => builder.set_interned_tags(&tags[..], |s| interner.get(s)),
+                (None, None) => builder.set_tags(&tags[..]),
+            };
             builder.set_nodes(&nodes[..]);
+            let tag = |key: &str| tags.chunks(2).find(|tag| tag[0] == key).map(|tag| tag[1]);
+            let street_name = tag("highway").and(tag("name")).map(|name| name.to_string());
+            if with_metadata {
+                builder.set_metadata(&metadata);
+            }
 
-            txn.put(
-                ways,
-                &way_id.to_ne_bytes(),
-                &builder.build(),
-                lmdb::WriteFlags::APPEND,
-            )
-            .unwrap();
-
-            let nodes_set: HashSet<u64> = nodes.iter().cloned().collect();
-            for node_id in nodes_set {
-                node_way_sorter.push(IDPair(node_id, way_id));
+            Record {
+                element_type: ElementType::Way,
+                id: way.id() as u64,
+                value: builder.build(encoding),
+                metadata,
+                location: None,
+                coordinates: None,
+                tagged: !tags.is_empty(),
+                refs: nodes.iter().map(|&id| (ElementType::Node, id)).collect(),
+                geometry_refs: nodes,
+                street_name,
+                tags: kept_tags(&tags),
             }
         }
         osmpbf::Element::Relation(rel) => {
-            let rel_id = rel.id() as u64;
-            let tags: Vec<&str> = rel.tags().map(|(k, v)| [k, v]).flatten().collect();
+            let metadata = info_metadata(&rel.info());
+            let tags: Vec<&str> = rel.tags().flat_map(|(k, v)| [k, v]).collect();
 
             let members: Vec<(ElementType, u64, String)> = rel
                 .members()
@@ -245,60 +502,1566 @@ pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
                 })
                 .collect();
 
-            let mut builder = RelationBuilder::new();
+            let area = rel
+                .tags()
+                .any(|(k, v)| k == "type" && (v == "multipolygon" || v == "boundary"));
+            let geometry_refs = match area {
+                true => members
+                    .iter()
+                    .filter(|(t, _, role)| {
+                        *t == ElementType::Way && matches!(role.as_str(), "outer" | "inner" | "")
+                    })
+                    .map(|&(_, id, _)| id)
+                    .collect(),
+                false => vec![],
+            };
 
-            builder.set_tags(&tags[..]);
+            let mut builder = RelationBuilder::new();
+            match (tag_sets.and_then(|sets| sets.get(&tags)), interner) {
+                (Some(hash), _) => builder.set_tag_set(hash),
+                (None, Some(interner)) => builder.set_interned_tags(&tags[..], |s| interner.get(s)),
+                (None, None) => builder.set_tags(&tags[..]),
+            };
             builder.set_members(&members[..]);
+            if with_metadata {
+                builder.set_metadata(&metadata);
+            }
 
+            Record {
+                element_type: ElementType::Relation,
+                id: rel.id() as u64,
+                value: builder.build(encoding),
+                metadata,
+                location: None,
+                coordinates: None,
+                tagged: !tags.is_empty(),
+                refs: members.iter().map(|&(t, id, _)| (t, id)).collect(),
+                geometry_refs,
+                street_name: None,
+                tags: kept_tags(&tags),
+            }
+        }
+    }
+}
+
+/// The elements of one blob of the input file, encoded by a decoding thread
+#[derive(Default)]
+struct DecodedBlob {
+    records: Vec<Record>,
+    /// The first element of the blob with a negative ID, which stops the import: the
+    /// records are the elements before it
+    negative: Option<String>,
+}
+
+/// Decode the data blobs of an .osm.pbf file with `encode` on several threads, and pass
+/// the results to `write` on the calling thread in the order of the file, so that
+/// elements can still be appended to tables in ID order. A separate thread reads the
+/// blobs, so reading (or downloading) the file overlaps with decoding. Stops early if
+/// `write` returns false.
+fn decode_blobs_parallel<R: Read + Send>(
+    blobs: osmpbf::BlobReader<R>,
+    threads: usize,
+    encode: impl Fn(&osmpbf::PrimitiveBlock) -> DecodedBlob + Sync,
+    mut write: impl FnMut(DecodedBlob) -> bool,
+) -> Result<(), Box<dyn Error>> {
+    let threads = threads.max(1);
+    let (blob_sender, blob_receiver) = mpsc::sync_channel(threads * BLOBS_AHEAD);
+    let (decoded_sender, decoded_receiver) = mpsc::sync_channel(threads * BLOBS_AHEAD);
+    // shared by the decoding threads, so that it's closed (stopping the reading thread)
+    // once they have all stopped
+    let blob_receiver = Arc::new(Mutex::new(blob_receiver));
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            for (index, blob) in blobs.enumerate() {
+                // the decoders have stopped if the channel is closed
+                if blob_sender
+                    .send((index, blob.map_err(|e| e.to_string())))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        for _ in 0..threads {
+            let (blob_receiver, decoded_sender, encode) =
+                (Arc::clone(&blob_receiver), decoded_sender.clone(), &encode);
+            scope.spawn(move || loop {
+                let next = blob_receiver.lock().unwrap().recv();
+                let Ok((index, blob)) = next else {
+                    break;
+                };
+                let decoded = blob.and_then(|blob: osmpbf::Blob| match blob.decode() {
+                    Ok(osmpbf::BlobDecode::OsmData(block)) => Ok(encode(&block)),
+                    Ok(_) => Ok(DecodedBlob::default()),
+                    Err(e) => Err(e.to_string()),
+                });
+                if decoded_sender.send((index, decoded)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(blob_receiver);
+        drop(decoded_sender);
+
+        // blobs decoded ahead of the next one to write, by index. Dropping the receiver
+        // when returning early stops the other threads.
+        let mut waiting = BTreeMap::new();
+        let mut next = 0;
+        for (index, decoded) in decoded_receiver {
+            waiting.insert(index, decoded);
+            while let Some(decoded) = waiting.remove(&next) {
+                next += 1;
+                if !write(decoded?) {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// The tables which every database has
+pub struct Tables {
+    pub metadata: lmdb::Database,
+    pub locations: lmdb::Database,
+    pub nodes: lmdb::Database,
+    pub ways: lmdb::Database,
+    pub relations: lmdb::Database,
+    pub cell_node: lmdb::Database,
+    pub node_way: lmdb::Database,
+    pub node_relation: lmdb::Database,
+    pub way_relation: lmdb::Database,
+    pub relation_relation: lmdb::Database,
+}
+
+impl Tables {
+    /// Create the tables, or open them if they already exist
+    pub fn create(env: &lmdb::Environment) -> Result<Self, lmdb::Error> {
+        let element_flags = lmdb::DatabaseFlags::INTEGER_KEY;
+        let index_flags = lmdb::DatabaseFlags::INTEGER_KEY
+            | lmdb::DatabaseFlags::INTEGER_DUP
+            | lmdb::DatabaseFlags::DUP_SORT
+            | lmdb::DatabaseFlags::DUP_FIXED;
+        Ok(Self {
+            metadata: env.create_db(Some("metadata"), lmdb::DatabaseFlags::empty())?,
+            locations: env.create_db(Some("locations"), element_flags)?,
+            nodes: env.create_db(Some("nodes"), element_flags)?,
+            ways: env.create_db(Some("ways"), element_flags)?,
+            relations: env.create_db(Some("relations"), element_flags)?,
+            cell_node: env.create_db(Some("cell_node"), index_flags)?,
+            node_way: env.create_db(Some("node_way"), index_flags)?,
+            node_relation: env.create_db(Some("node_relation"), index_flags)?,
+            way_relation: env.create_db(Some("way_relation"), index_flags)?,
+            relation_relation: env.create_db(Some("relation_relation"), index_flags)?,
+        })
+    }
+}
+
+/// The tables being written, plus sorters which collect the entries of the index
+/// tables (which must be inserted in key order, once all elements have been read)
+pub struct Output {
+    locations: lmdb::Database,
+    nodes: lmdb::Database,
+    ways: lmdb::Database,
+    relations: lmdb::Database,
+    // history tables (only created in history mode)
+    location_history: Option<lmdb::Database>,
+    node_history: Option<lmdb::Database>,
+    way_history: Option<lmdb::Database>,
+    relation_history: Option<lmdb::Database>,
+    // bounding box of each way (only created with --way-bbox)
+    way_bbox: Option<lmdb::Database>,
+    // geometry of each way and multipolygon (only created with --with-geometry)
+    geometries: Option<lmdb::Database>,
+    // index of the names of highway ways (only created with --street-names)
+    street_names: Option<lmdb::Database>,
+
+    cell_node_sorter: Sorter<IDPair>,
+    node_way_sorter: Sorter<IDPair>,
+    node_relation_sorter: Sorter<IDPair>,
+    way_relation_sorter: Sorter<IDPair>,
+    relation_relation_sorter: Sorter<IDPair>,
+
+    // element versions and usernames, used to build the changesets table (only collected
+    // when storing metadata)
+    changeset_sorter: Option<Sorter<ChangesetEdit>>,
+    users: HashMap<u32, String>,
+    // entries of the uid_element index (only collected with --uid-index)
+    uid_element_sorter: Option<Sorter<IDPair>>,
+    // entries of the changeset_element index (only collected with --changeset-index)
+    changeset_element_sorter: Option<Sorter<IDPair>>,
+    // entries of the tag index (only collected with --index-tags)
+    tag_index: Option<TagIndex>,
+    // entries of the cell_way and cell_relation tables (only collected with --cell-index)
+    cell_index: Option<CellIndex>,
+
+    // bitmaps of the IDs of all current elements (only built with --id-bitmaps)
+    node_ids: Option<RoaringTreemap>,
+    way_ids: Option<RoaringTreemap>,
+    relation_ids: Option<RoaringTreemap>,
+
+    // bounding box of the current nodes, as (west, south, east, north) in 1e-7 degrees
+    bounds: Option<(i32, i32, i32, i32)>,
+    // number of current elements of each type
+    counts: osmx::ElementCounts,
+
+    // whether elements are being added to an existing database (with --append), so that
+    // they can't be appended to the tables
+    append: bool,
+    // the spatial index, for removing the entries of replaced nodes (with --append)
+    cell_node: lmdb::Database,
+    // replaced nodes whose location changed, so that the way_bbox and geometries entries
+    // of their ways and relations must be updated (with --append)
+    moved_nodes: RoaringTreemap,
+
+    // index plugins, and their extension tables
+    plugins: Vec<(Box<dyn osmx::IndexPlugin>, lmdb::Database)>,
+}
+
+impl Output {
+    /// An Output which writes a new database with none of the optional tables (only the
+    /// ID bitmaps, if `id_bitmaps` is set), keeping its sorters' files in `tempdir`
+    pub fn new(tables: &Tables, tempdir: &Path, id_bitmaps: bool) -> Self {
+        Self {
+            locations: tables.locations,
+            nodes: tables.nodes,
+            ways: tables.ways,
+            relations: tables.relations,
+            location_history: None,
+            node_history: None,
+            way_history: None,
+            relation_history: None,
+            way_bbox: None,
+            geometries: None,
+            street_names: None,
+            cell_node_sorter: Sorter::new(tempdir, "cell_node"),
+            node_way_sorter: Sorter::new(tempdir, "node_way"),
+            node_relation_sorter: Sorter::new(tempdir, "node_relation"),
+            way_relation_sorter: Sorter::new(tempdir, "way_relation"),
+            relation_relation_sorter: Sorter::new(tempdir, "relation_relation"),
+            changeset_sorter: None,
+            users: HashMap::new(),
+            uid_element_sorter: None,
+            changeset_element_sorter: None,
+            tag_index: None,
+            cell_index: None,
+            node_ids: id_bitmaps.then(RoaringTreemap::new),
+            way_ids: id_bitmaps.then(RoaringTreemap::new),
+            relation_ids: id_bitmaps.then(RoaringTreemap::new),
+            bounds: None,
+            counts: osmx::ElementCounts::default(),
+            append: false,
+            cell_node: tables.cell_node,
+            moved_nodes: RoaringTreemap::new(),
+            plugins: vec![],
+        }
+    }
+
+    /// Once all elements have been written, write the ID bitmaps (if any), the bounds of
+    /// the nodes and the counts of elements to the metadata table
+    fn write_summary(
+        &self,
+        txn: &mut lmdb::RwTransaction,
+        metadata: lmdb::Database,
+        counts: &osmx::ElementCounts,
+    ) -> Result<(), Box<dyn Error>> {
+        for (key, bitmap) in [
+            ("node_ids", &self.node_ids),
+            ("way_ids", &self.way_ids),
+            ("relation_ids", &self.relation_ids),
+        ] {
+            if let Some(bitmap) = bitmap {
+                let mut buf = vec![];
+                bitmap.serialize_into(&mut buf)?;
+                txn.put(metadata, &key.as_bytes(), &buf, lmdb::WriteFlags::empty())?;
+            }
+        }
+
+        if let Some((west, south, east, north)) = self.bounds {
+            let buf: Vec<u8> = [west, south, east, north]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect();
+            txn.put(
+                metadata,
+                &osmx::BOUNDS_KEY.as_bytes(),
+                &buf,
+                lmdb::WriteFlags::empty(),
+            )?;
+        }
+
+        txn.put(
+            metadata,
+            &osmx::COUNTS_KEY.as_bytes(),
+            &counts.to_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+        Ok(())
+    }
+
+    /// Finish writing a new database created with [Output::new], once all elements have
+    /// been written: write the summary metadata, and insert the collected entries of the
+    /// index tables. Returns the number of elements written.
+    pub fn finish(
+        self,
+        txn: &mut lmdb::RwTransaction,
+        tables: &Tables,
+        progress: Option<&dyn ProgressSink>,
+    ) -> Result<osmx::ElementCounts, Box<dyn Error>> {
+        self.write_summary(txn, tables.metadata, &self.counts)?;
+        insert_sorted_tuples(self.cell_node_sorter, txn, tables.cell_node, progress);
+        insert_sorted_tuples(self.node_way_sorter, txn, tables.node_way, progress);
+        insert_sorted_tuples(
+            self.node_relation_sorter,
+            txn,
+            tables.node_relation,
+            progress,
+        );
+        insert_sorted_tuples(self.way_relation_sorter, txn, tables.way_relation, progress);
+        insert_sorted_tuples(
+            self.relation_relation_sorter,
+            txn,
+            tables.relation_relation,
+            progress,
+        );
+        Ok(self.counts)
+    }
+
+    /// Record the user and changeset which created an element version, in the
+    /// uid_element and changeset_element indexes
+    fn record_edit(&mut self, record: &Record) {
+        let element_ref = decode_key(&encode_element_ref(&record.element_id())).unwrap();
+
+        if let Some(sorter) = self.uid_element_sorter.as_mut() {
+            sorter.push(IDPair(record.metadata.uid as u64, element_ref));
+        }
+        if let Some(sorter) = self.changeset_element_sorter.as_mut() {
+            sorter.push(IDPair(record.metadata.changeset as u64, element_ref));
+        }
+    }
+
+    /// Record the changeset which created an element version
+    fn record_changeset(&mut self, record: &Record) {
+        let sorter = match self.changeset_sorter.as_mut() {
+            Some(sorter) => sorter,
+            None => return,
+        };
+        let metadata = &record.metadata;
+        let element_type = match record.element_type {
+            ElementType::Node => 0,
+            ElementType::Way => 1,
+            ElementType::Relation => 2,
+        };
+        sorter.push(ChangesetEdit {
+            changeset: metadata.changeset,
+            element: (element_type, record.id, metadata.version),
+            timestamp: metadata.timestamp,
+            uid: metadata.uid,
+            coordinates: record.coordinates,
+        });
+        self.users
+            .entry(metadata.uid)
+            .or_insert_with(|| metadata.user.clone());
+    }
+
+    /// Write an element version to the history tables
+    fn write_history(&mut self, txn: &mut lmdb::RwTransaction, record: &Record) {
+        let key = encode_version_key(record.id, record.metadata.version);
+        let table = match record.element_type {
+            ElementType::Node => self.node_history,
+            ElementType::Way => self.way_history,
+            ElementType::Relation => self.relation_history,
+        };
+        txn.put(
+            table.unwrap(),
+            &key,
+            &record.value,
+            lmdb::WriteFlags::APPEND,
+        )
+        .unwrap();
+
+        if let Some((location, _)) = &record.location {
             txn.put(
-                relations,
-                &rel_id.to_ne_bytes(),
-                &builder.build(),
+                self.location_history.unwrap(),
+                &key,
+                location,
                 lmdb::WriteFlags::APPEND,
             )
             .unwrap();
+        }
+    }
 
-            let node_members: HashSet<u64> = rel
-                .members()
-                .filter(|m| m.member_type == osmpbf::RelMemberType::Node)
-                .map(|m| m.member_id as u64)
-                .collect();
+    /// With --append, decide whether an element should be written: if it isn't in the
+    /// database yet, or if it is a node (whose version is recorded in the locations
+    /// table) and the record is a newer version of it. The old version's index entries
+    /// are removed.
+    fn merge(&mut self, txn: &mut lmdb::RwTransaction, record: &Record) -> bool {
+        let key = encode_key(record.id);
+        let table = match record.element_type {
+            ElementType::Node => self.locations,
+            ElementType::Way => self.ways,
+            ElementType::Relation => self.relations,
+        };
+        let old = match txn.get(table, &key) {
+            Ok(raw_val) => raw_val.to_vec(),
+            Err(_) => return true,
+        };
+        if record.element_type != ElementType::Node {
+            return false;
+        }
+
+        let version = u32::from_le_bytes(old[8..12].try_into().unwrap());
+        if record.metadata.version <= version {
+            return false;
+        }
+        let location = osmx::Location::try_from(&old[..]).unwrap();
+        let latlng = s2::latlng::LatLng::from_degrees(location.lat(), location.lon());
+        let cell = s2::cellid::CellID::from(latlng).parent(osmx::CELL_INDEX_LEVEL);
+        delete_dup(txn, self.cell_node, &encode_key(cell.0), &key).unwrap();
+        // the new version may be untagged, and so not in the nodes table
+        let _ = txn.del(self.nodes, &key, None);
+        if record
+            .location
+            .as_ref()
+            .map(|(location, _)| &location[0..8])
+            != Some(&old[0..8])
+        {
+            self.moved_nodes.insert(record.id);
+        }
+        true
+    }
+
+    /// Write the current version of an element to the element tables, and collect its
+    /// index table entries. Deleted elements are skipped.
+    pub fn write_current(&mut self, txn: &mut lmdb::RwTransaction, record: &Record) {
+        if record.metadata.deleted {
+            return;
+        }
+        if self.append && !self.merge(txn, record) {
+            return;
+        }
+        if let Some(tag_index) = self.tag_index.as_mut() {
+            tag_index.push(record);
+        }
+        if !self.plugins.is_empty() {
+            let element = osmx::PluginElement {
+                id: record.element_id(),
+                tags: &record.tags,
+                replacing: self.append,
+                deleted: false,
+            };
+            for (plugin, table) in self.plugins.iter_mut() {
+                let mut table = osmx::PluginTable::new(txn, *table);
+                plugin.index(&element, &mut table).unwrap();
+            }
+        }
+        // elements added to an existing database may come before those already in it
+        let flags = if self.append {
+            lmdb::WriteFlags::empty()
+        } else {
+            lmdb::WriteFlags::APPEND
+        };
+
+        let id = record.id;
+        match record.element_type {
+            ElementType::Node => {
+                self.counts.nodes += 1;
+                if let Some(ids) = self.node_ids.as_mut() {
+                    ids.insert(id);
+                }
+
+                if let Some((location, cell)) = &record.location {
+                    txn.put(self.locations, &encode_key(id), location, flags)
+                        .unwrap();
+                    self.cell_node_sorter.push(IDPair(*cell, id));
+                }
 
-            for member_id in node_members {
-                node_relation_sorter.push(IDPair(member_id, rel_id));
+                if let Some((lon, lat)) = record.coordinates {
+                    let (west, south, east, north) = self.bounds.unwrap_or((lon, lat, lon, lat));
+                    self.bounds =
+                        Some((west.min(lon), south.min(lat), east.max(lon), north.max(lat)));
+                }
+
+                if record.tagged {
+                    txn.put(self.nodes, &encode_key(id), &record.value, flags)
+                        .unwrap();
+                }
             }
+            ElementType::Way => {
+                self.counts.ways += 1;
+                if let Some(ids) = self.way_ids.as_mut() {
+                    ids.insert(id);
+                }
 
-            let way_members: HashSet<u64> = rel
-                .members()
-                .filter(|m| m.member_type == osmpbf::RelMemberType::Way)
-                .map(|m| m.member_id as u64)
-                .collect();
+                txn.put(self.ways, &encode_key(id), &record.value, flags)
+                    .unwrap();
 
-            for member_id in way_members {
-                way_relation_sorter.push(IDPair(member_id, rel_id));
+                if let Some(way_bbox) = self.way_bbox {
+                    // ways come after nodes in PBF files, so the nodes' locations have
+                    // already been written
+                    let bbox = record.refs.iter().fold(None, |bbox, &(_, node_id)| {
+                        match txn.get(self.locations, &encode_key(node_id)) {
+                            Ok(location) => extend_bbox(bbox, location),
+                            Err(_) => bbox,
+                        }
+                    });
+                    if let Some((west, south, east, north)) = bbox {
+                        let buf: Vec<u8> = [west, south, east, north]
+                            .iter()
+                            .flat_map(|v| v.to_le_bytes())
+                            .collect();
+                        txn.put(way_bbox, &encode_key(id), &buf, flags).unwrap();
+                        if let Some(cell_index) = self.cell_index.as_mut() {
+                            push_cells(&mut cell_index.way_sorter, id, (west, south, east, north));
+                        }
+                    }
+                }
+
+                if let Some(geometries) = self.geometries {
+                    write_way_geometry(txn, self.locations, geometries, id, &record.geometry_refs);
+                }
+
+                if let (Some(street_names), Some(name)) = (self.street_names, &record.street_name) {
+                    write_street_name(
+                        txn,
+                        self.locations,
+                        street_names,
+                        name,
+                        &record.geometry_refs,
+                    );
+                }
+
+                for &(_, node_id) in &record.refs {
+                    self.node_way_sorter.push(IDPair(node_id, id));
+                }
             }
+            ElementType::Relation => {
+                self.counts.relations += 1;
+                if let Some(ids) = self.relation_ids.as_mut() {
+                    ids.insert(id);
+                }
 
-            let relation_members: HashSet<u64> = rel
-                .members()
-                .filter(|m| m.member_type == osmpbf::RelMemberType::Relation)
-                .map(|m| m.member_id as u64)
+                txn.put(self.relations, &encode_key(id), &record.value, flags)
+                    .unwrap();
+
+                if let (Some(cell_index), Some(way_bbox)) =
+                    (self.cell_index.as_mut(), self.way_bbox)
+                {
+                    cell_index.push_relation(txn, self.locations, way_bbox, id, &record.refs);
+                }
+
+                if let Some(geometries) = self.geometries {
+                    if !record.geometry_refs.is_empty() {
+                        write_relation_geometry(txn, geometries, id, &record.geometry_refs);
+                    }
+                }
+
+                for &(member_type, member_id) in &record.refs {
+                    let sorter = match member_type {
+                        ElementType::Node => &mut self.node_relation_sorter,
+                        ElementType::Way => &mut self.way_relation_sorter,
+                        ElementType::Relation => &mut self.relation_relation_sorter,
+                    };
+                    sorter.push(IDPair(member_id, id));
+                }
+            }
+        }
+    }
+}
+
+/// The entries of the tag_node, tag_way and tag_relation tables, which map the hash (see
+/// osmx::tag_set_hash) of each indexed key, and of each tag with an indexed key, to the
+/// IDs of the elements with them
+struct TagIndex {
+    keys: HashSet<String>,
+    // for nodes, ways and relations
+    sorters: [Sorter<IDPair>; 3],
+}
+
+impl TagIndex {
+    fn new(keys: &[String], tempdir: &Path) -> Self {
+        Self {
+            keys: keys.iter().cloned().collect(),
+            sorters: ["tag_node", "tag_way", "tag_relation"].map(|name| Sorter::new(tempdir, name)),
+        }
+    }
+
+    /// Index the tags of an element (which must have been read with its tags kept)
+    fn push(&mut self, record: &Record) {
+        let sorter = match record.element_type {
+            ElementType::Node => &mut self.sorters[0],
+            ElementType::Way => &mut self.sorters[1],
+            ElementType::Relation => &mut self.sorters[2],
+        };
+        for (key, value) in record.tags.iter() {
+            if self.keys.contains(key) {
+                sorter.push(IDPair(osmx::tag_set_hash(&[key]), record.id));
+                sorter.push(IDPair(osmx::tag_set_hash(&[key, value]), record.id));
+            }
+        }
+    }
+}
+
+/// The entries of the cell_way and cell_relation tables, which map the cells covering the
+/// bounding box of each way, and of the members of each relation, to their IDs
+struct CellIndex {
+    way_sorter: Sorter<IDPair>,
+    // the bounding boxes of the relations (in 1e-7 degrees), which are only indexed at the
+    // end, since they include those of member relations which may come later
+    relation_bboxes: HashMap<u64, (i32, i32, i32, i32)>,
+    // (relation ID, member relation ID) pairs
+    relation_members: Vec<(u64, u64)>,
+}
+
+impl CellIndex {
+    fn new(tempdir: &Path) -> Self {
+        Self {
+            way_sorter: Sorter::new(tempdir, "cell_way"),
+            relation_bboxes: HashMap::new(),
+            relation_members: vec![],
+        }
+    }
+
+    /// Record the bounding box of a relation's member nodes and ways, which have already
+    /// been written (with the ways' bounding boxes)
+    fn push_relation(
+        &mut self,
+        txn: &lmdb::RwTransaction,
+        locations: lmdb::Database,
+        way_bbox: lmdb::Database,
+        id: u64,
+        members: &HashSet<(ElementType, u64)>,
+    ) {
+        let mut bbox = None;
+        for &(member_type, member_id) in members {
+            match member_type {
+                ElementType::Node => {
+                    if let Ok(location) = txn.get(locations, &encode_key(member_id)) {
+                        bbox = extend_bbox(bbox, location);
+                    }
+                }
+                ElementType::Way => {
+                    if let Ok(raw) = txn.get(way_bbox, &encode_key(member_id)) {
+                        // the south-west and north-east corners are encoded like locations
+                        bbox = extend_bbox(extend_bbox(bbox, &raw[0..8]), &raw[8..16]);
+                    }
+                }
+                ElementType::Relation => self.relation_members.push((id, member_id)),
+            }
+        }
+        if let Some(bbox) = bbox {
+            self.relation_bboxes.insert(id, bbox);
+        }
+    }
+
+    /// Grow the bounding box of each relation to include those of its member relations,
+    /// and return the entries of the cell_way and cell_relation tables
+    fn finish(mut self, tempdir: &Path) -> (Sorter<IDPair>, Sorter<IDPair>) {
+        // relations can be nested several levels deep (and even contain each other), so
+        // this is repeated until no bounding box changes
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &(id, member_id) in &self.relation_members {
+                let Some(&(w, s, e, n)) = self.relation_bboxes.get(&member_id) else {
+                    continue;
+                };
+                let bbox = match self.relation_bboxes.get(&id) {
+                    Some(&(west, south, east, north)) => {
+                        (west.min(w), south.min(s), east.max(e), north.max(n))
+                    }
+                    None => (w, s, e, n),
+                };
+                if self.relation_bboxes.insert(id, bbox) != Some(bbox) {
+                    changed = true;
+                }
+            }
+        }
+
+        let mut sorter = Sorter::new(tempdir, "cell_relation");
+        for (&id, &bbox) in &self.relation_bboxes {
+            push_cells(&mut sorter, id, bbox);
+        }
+        (self.way_sorter, sorter)
+    }
+}
+
+/// Add the cells covering a bounding box (in 1e-7 degrees) to a spatial index, at levels
+/// up to CELL_INDEX_LEVEL
+fn push_cells(
+    sorter: &mut Sorter<IDPair>,
+    id: u64,
+    (west, south, east, north): (i32, i32, i32, i32),
+) {
+    let region = osmx::Region::from_bbox(
+        west as f64 / 1e7,
+        south as f64 / 1e7,
+        east as f64 / 1e7,
+        north as f64 / 1e7,
+    );
+    for cell in region.cell_ids() {
+        sorter.push(IDPair(cell, id));
+    }
+}
+
+/// Count a way in the street_names index, under its name and the cell of its middle node.
+/// Ways whose middle node is missing (such as those cut by the edge of an extract) are
+/// skipped.
+fn write_street_name(
+    txn: &mut lmdb::RwTransaction,
+    locations: lmdb::Database,
+    street_names: lmdb::Database,
+    name: &str,
+    nodes: &[u64],
+) {
+    let Some(&node_id) = nodes.get(nodes.len() / 2) else {
+        return;
+    };
+    // ways come after nodes in PBF files, so the node's location has already been written
+    let Ok(location) = txn.get(locations, &encode_key(node_id)) else {
+        return;
+    };
+    let lon = i32::from_le_bytes(location[0..4].try_into().unwrap());
+    let lat = i32::from_le_bytes(location[4..8].try_into().unwrap());
+    let latlng = s2::latlng::LatLng::from_degrees(lat as f64 / 1e7, lon as f64 / 1e7);
+    let cell = s2::cellid::CellID::from(latlng).parent(osmx::streets::STREET_NAME_CELL_LEVEL);
+    let Some(key) = osmx::streets::encode_street_name_key(name, cell.0) else {
+        return;
+    };
+    // the first way with the name in the cell is its representative location
+    let value = match txn.get(street_names, &key) {
+        Ok(raw_val) => match osmx::streets::decode_street_name_value(raw_val) {
+            Some((lon, lat, count)) => {
+                osmx::streets::encode_street_name_value(lon, lat, count.saturating_add(1))
+            }
+            None => return,
+        },
+        Err(_) => osmx::streets::encode_street_name_value(lon, lat, 1),
+    };
+    txn.put(street_names, &key, &value, lmdb::WriteFlags::empty())
+        .unwrap();
+}
+
+/// Write the coordinates of a way's nodes to the geometries table. Ways with missing
+/// nodes (such as those cut by the edge of an extract) are skipped.
+fn write_way_geometry(
+    txn: &mut lmdb::RwTransaction,
+    locations: lmdb::Database,
+    geometries: lmdb::Database,
+    id: u64,
+    nodes: &[u64],
+) {
+    // ways come after nodes in PBF files, so the nodes' locations have already been
+    // written
+    let coordinates: Option<Vec<(i32, i32)>> = nodes
+        .iter()
+        .map(|&node_id| {
+            let location = txn.get(locations, &encode_key(node_id)).ok()?;
+            let lon = i32::from_le_bytes(location[0..4].try_into().unwrap());
+            let lat = i32::from_le_bytes(location[4..8].try_into().unwrap());
+            Some((lon, lat))
+        })
+        .collect();
+    if let Some(coordinates) = coordinates {
+        let key = encode_element_ref(&ElementId::Way(id));
+        let value = geometry::encode_geometry(&[coordinates]);
+        // keys of ways and relations interleave, so they can't be appended
+        txn.put(geometries, &key, &value, lmdb::WriteFlags::empty())
+            .unwrap();
+    }
+}
+
+/// Assemble the rings of a multipolygon relation from the geometries of its member
+/// ways, and write them to the geometries table. Relations whose rings aren't complete
+/// are skipped.
+fn write_relation_geometry(
+    txn: &mut lmdb::RwTransaction,
+    geometries: lmdb::Database,
+    id: u64,
+    ways: &[u64],
+) {
+    // relations come after ways, so the member ways' geometries have already been written
+    let member_ways: Option<Vec<Vec<(i32, i32)>>> = ways
+        .iter()
+        .map(|&way_id| {
+            let raw = txn
+                .get(geometries, &encode_element_ref(&ElementId::Way(way_id)))
+                .ok()?;
+            geometry::decode_geometry(raw)?.pop()
+        })
+        .collect();
+    if let Some(rings) = member_ways.and_then(geometry::assemble_rings) {
+        let key = encode_element_ref(&ElementId::Relation(id));
+        let value = geometry::encode_geometry(&rings);
+        txn.put(geometries, &key, &value, lmdb::WriteFlags::empty())
+            .unwrap();
+    }
+}
+
+/// Grow a bounding box (in 1e-7 degrees) to include an encoded location
+fn extend_bbox(
+    bbox: Option<(i32, i32, i32, i32)>,
+    location: &[u8],
+) -> Option<(i32, i32, i32, i32)> {
+    let lon = i32::from_le_bytes(location[0..4].try_into().unwrap());
+    let lat = i32::from_le_bytes(location[4..8].try_into().unwrap());
+    let (west, south, east, north) = bbox.unwrap_or((lon, lat, lon, lat));
+    Some((west.min(lon), south.min(lat), east.max(lon), north.max(lat)))
+}
+
+/// Check that elements can be added to an existing database with --append, and return
+/// the encoding of its element values
+fn appendable_encoding(env: &lmdb::Environment) -> Result<osmx::ValueEncoding, Box<dyn Error>> {
+    let metadata = match env.open_db(Some("metadata")) {
+        Ok(db) => db,
+        Err(lmdb::Error::NotFound) => return Err("output file isn't an OSMX database".into()),
+        Err(e) => return Err(e.into()),
+    };
+    for name in [
+        "strings",
+        "location_history",
+        "changesets",
+        "uid_element",
+        "changeset_element",
+        "tag_node",
+        "cell_way",
+    ] {
+        match env.open_db(Some(name)) {
+            Ok(_) => {
+                return Err(
+                    format!("--append doesn't support databases with a {} table", name).into(),
+                )
+            }
+            Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let txn = env.begin_ro_txn()?;
+    let get = |key: &str| match txn.get(metadata, &key.as_bytes()) {
+        Ok(raw_val) => Ok(Some(raw_val)),
+        Err(lmdb::Error::NotFound) => Ok(None),
+        Err(e) => Err(e),
+    };
+    if get(osmx::FORMAT_VERSION_KEY)? != Some(&osmx::FORMAT_VERSION.to_le_bytes()[..]) {
+        return Err(format!(
+            "--append needs a database in format version {}",
+            osmx::FORMAT_VERSION
+        )
+        .into());
+    }
+    if let Some(raw_val) = get(KeyByteOrder::METADATA_KEY)? {
+        if KeyByteOrder::from_bytes(raw_val)? != KeyByteOrder::NATIVE {
+            return Err("database was created on a host with a different byte order".into());
+        }
+    }
+    if let Some(raw_val) = get(osmx::ValueCompression::METADATA_KEY)? {
+        if osmx::ValueCompression::from_bytes(raw_val)? != osmx::ValueCompression::None {
+            return Err("--append doesn't support databases with compressed values".into());
+        }
+    }
+    match get(osmx::ValueEncoding::METADATA_KEY)? {
+        Some(raw_val) => Ok(osmx::ValueEncoding::from_bytes(raw_val)?),
+        None => Ok(osmx::ValueEncoding::Standard),
+    }
+}
+
+/// The names of the index plugins of an existing database
+fn index_plugins(env: &lmdb::Environment) -> Result<Vec<String>, Box<dyn Error>> {
+    let metadata = env.open_db(Some("metadata"))?;
+    let txn = env.begin_ro_txn()?;
+    match txn.get(metadata, &osmx::INDEX_PLUGINS_KEY.as_bytes()) {
+        Ok(raw_val) => Ok(std::str::from_utf8(raw_val)?
+            .split_terminator('\0')
+            .map(String::from)
+            .collect()),
+        Err(lmdb::Error::NotFound) => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The number of entries in a table
+fn table_entries(txn: &lmdb::RwTransaction, table: lmdb::Database) -> Result<u64, Box<dyn Error>> {
+    let mut stat = lmdb_sys::MDB_stat {
+        ms_psize: 0,
+        ms_depth: 0,
+        ms_branch_pages: 0,
+        ms_leaf_pages: 0,
+        ms_overflow_pages: 0,
+        ms_entries: 0,
+    };
+    let rc = unsafe { lmdb_sys::mdb_stat(txn.txn(), table.dbi(), &mut stat) };
+    if rc != 0 {
+        return Err(lmdb::Error::from_err_code(rc).into());
+    }
+    Ok(stat.ms_entries as u64)
+}
+
+/// Delete a value of a key in an index table, if it's there. Goes through a cursor,
+/// because mdb_del with a value can crash on DUP_FIXED tables.
+fn delete_dup(
+    txn: &mut lmdb::RwTransaction,
+    table: lmdb::Database,
+    key: &[u8],
+    value: &[u8],
+) -> Result<(), lmdb::Error> {
+    let mut cursor = txn.open_rw_cursor(table)?;
+    match cursor.get(Some(key), Some(value), lmdb_sys::MDB_GET_BOTH) {
+        Ok(_) => cursor.del(lmdb::WriteFlags::empty()),
+        Err(lmdb::Error::NotFound) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The values of a key in an index table
+fn dup_values(
+    txn: &lmdb::RwTransaction,
+    table: lmdb::Database,
+    key: u64,
+) -> Result<Vec<u64>, Box<dyn Error>> {
+    let mut cursor = txn.open_ro_cursor(table)?;
+    let values = match cursor.iter_dup_of(&encode_key(key)) {
+        Ok(iter) => iter
+            .map(|(_, raw_val)| decode_key(raw_val).unwrap())
+            .collect(),
+        Err(lmdb::Error::NotFound) => vec![],
+        Err(e) => return Err(e.into()),
+    };
+    Ok(values)
+}
+
+/// The tables read and written by update_moved_nodes
+struct MovedNodeTables {
+    locations: lmdb::Database,
+    ways: lmdb::Database,
+    relations: lmdb::Database,
+    node_way: lmdb::Database,
+    way_relation: lmdb::Database,
+    way_bbox: Option<lmdb::Database>,
+    geometries: Option<lmdb::Database>,
+}
+
+/// With --append, recompute the bounding boxes and geometries of the ways whose nodes
+/// were moved by newer versions, and the geometries of the relations with those ways as
+/// members. Must be called once the index tables are complete.
+fn update_moved_nodes(
+    txn: &mut lmdb::RwTransaction,
+    moved_nodes: &RoaringTreemap,
+    tables: &MovedNodeTables,
+    encoding: osmx::ValueEncoding,
+) -> Result<(), Box<dyn Error>> {
+    let context = osmx::DecodeContext {
+        encoding,
+        ..Default::default()
+    };
+
+    let mut way_ids = RoaringTreemap::new();
+    for node_id in moved_nodes {
+        way_ids.extend(dup_values(txn, tables.node_way, node_id)?);
+    }
+
+    let mut relation_ids = RoaringTreemap::new();
+    for way_id in &way_ids {
+        let nodes: Vec<u64> = match txn.get(tables.ways, &encode_key(way_id)) {
+            Ok(raw_val) => osmx::Way::decode(raw_val, context)?.nodes().collect(),
+            Err(lmdb::Error::NotFound) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if let Some(way_bbox) = tables.way_bbox {
+            let bbox = nodes.iter().fold(None, |bbox, &node_id| {
+                match txn.get(tables.locations, &encode_key(node_id)) {
+                    Ok(location) => extend_bbox(bbox, location),
+                    Err(_) => bbox,
+                }
+            });
+            if let Some((west, south, east, north)) = bbox {
+                let buf: Vec<u8> = [west, south, east, north]
+                    .iter()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect();
+                txn.put(
+                    way_bbox,
+                    &encode_key(way_id),
+                    &buf,
+                    lmdb::WriteFlags::empty(),
+                )?;
+            }
+        }
+        if let Some(geometries) = tables.geometries {
+            write_way_geometry(txn, tables.locations, geometries, way_id, &nodes);
+            relation_ids.extend(dup_values(txn, tables.way_relation, way_id)?);
+        }
+    }
+
+    if let Some(geometries) = tables.geometries {
+        for relation_id in &relation_ids {
+            let member_ways: Vec<u64> = match txn.get(tables.relations, &encode_key(relation_id)) {
+                Ok(raw_val) => {
+                    let relation = osmx::Relation::decode(raw_val, context)?;
+                    if !matches!(relation.tag("type"), Some("multipolygon" | "boundary")) {
+                        continue;
+                    }
+                    relation
+                        .members()
+                        .filter_map(|member| match (member.id(), member.role()) {
+                            (ElementId::Way(id), "outer" | "inner" | "") => Some(id),
+                            _ => None,
+                        })
+                        .collect()
+                }
+                Err(lmdb::Error::NotFound) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            write_relation_geometry(txn, geometries, relation_id, &member_ways);
+        }
+    }
+    Ok(())
+}
+
+/// Write the replication state from the input's header, and the format of the database,
+/// to the metadata table
+pub fn write_header_metadata(
+    txn: &mut lmdb::RwTransaction,
+    metadata: lmdb::Database,
+    header: &osmpbf::HeaderBlock,
+    input_file: &Path,
+    encoding: osmx::ValueEncoding,
+) -> Result<(), Box<dyn Error>> {
+    // replication metadata is stored as strings, matching the reference implementation
+    if let Some(timestamp) = header.osmosis_replication_timestamp() {
+        txn.put(
+            metadata,
+            &interop::REPLICATION_TIMESTAMP_KEY.as_bytes(),
+            &interop::format_timestamp(timestamp).as_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+    }
+
+    if let Some(seqno) = header.osmosis_replication_sequence_number() {
+        txn.put(
+            metadata,
+            &interop::REPLICATION_SEQUENCE_NUMBER_KEY.as_bytes(),
+            &seqno.to_string().as_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+    }
+
+    if let Some(base_url) = header.osmosis_replication_base_url() {
+        txn.put(
+            metadata,
+            &interop::REPLICATION_BASE_URL_KEY.as_bytes(),
+            &base_url.as_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+    }
+
+    txn.put(
+        metadata,
+        &osmx::FORMAT_VERSION_KEY.as_bytes(),
+        &osmx::FORMAT_VERSION.to_le_bytes(),
+        lmdb::WriteFlags::empty(),
+    )?;
+
+    txn.put(
+        metadata,
+        &KeyByteOrder::METADATA_KEY.as_bytes(),
+        &KeyByteOrder::NATIVE.as_str().as_bytes(),
+        lmdb::WriteFlags::empty(),
+    )?;
+
+    txn.put(
+        metadata,
+        &osmx::ValueEncoding::METADATA_KEY.as_bytes(),
+        &encoding.as_str().as_bytes(),
+        lmdb::WriteFlags::empty(),
+    )?;
+
+    txn.put(
+        metadata,
+        &interop::IMPORT_FILENAME_KEY.as_bytes(),
+        &input_file.as_os_str().as_encoded_bytes(),
+        lmdb::WriteFlags::empty(),
+    )?;
+    Ok(())
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    if args.append && !args.output_file.exists() {
+        return Err(format!("{} doesn't exist", args.output_file.display()).into());
+    }
+    with_writer_lock(&args.output_file, "expand", || expand(args))
+}
+
+fn expand(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let url = remote_url(&args.input_file);
+    if url.is_some() && args.intern_strings {
+        return Err("--intern-strings reads the input twice, so it needs a local file".into());
+    }
+    if url.is_some() && args.share_tag_sets {
+        return Err("--share-tag-sets reads the input twice, so it needs a local file".into());
+    }
+
+    let env = lmdb::Environment::new()
+        .set_flags(
+            lmdb::EnvironmentFlags::NO_SUB_DIR
+                | lmdb::EnvironmentFlags::NO_READAHEAD
+                | lmdb::EnvironmentFlags::NO_SYNC,
+        )
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
+        .open(args.output_file.as_ref())?;
+
+    // with --append, elements are encoded like those already in the database
+    let existing_encoding = if args.append {
+        Some(appendable_encoding(&env)?)
+    } else {
+        None
+    };
+
+    let element_flags = lmdb::DatabaseFlags::INTEGER_KEY;
+    let index_flags = lmdb::DatabaseFlags::INTEGER_KEY
+        | lmdb::DatabaseFlags::INTEGER_DUP
+        | lmdb::DatabaseFlags::DUP_SORT
+        | lmdb::DatabaseFlags::DUP_FIXED;
+
+    let Tables {
+        metadata,
+        locations,
+        nodes,
+        ways,
+        relations,
+        cell_node,
+        node_way,
+        node_relation,
+        way_relation,
+        relation_relation,
+    } = Tables::create(&env)?;
+    let strings = if args.intern_strings {
+        Some(env.create_db(Some("strings"), element_flags)?)
+    } else {
+        None
+    };
+    let tag_set_table = if args.share_tag_sets {
+        Some(env.create_db(Some("tag_sets"), element_flags)?)
+    } else {
+        None
+    };
+
+    // history tables are keyed by ID and version (see osmx::encode_version_key), and use
+    // LMDB's default bytewise key comparison
+    let create_history_table = |name| {
+        if args.history {
+            env.create_db(Some(name), lmdb::DatabaseFlags::empty())
+                .map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    let changesets = if args.metadata || args.history {
+        Some(env.create_db(Some("changesets"), element_flags)?)
+    } else {
+        None
+    };
+
+    let uid_element = if args.uid_index {
+        Some(env.create_db(Some("uid_element"), index_flags)?)
+    } else {
+        None
+    };
+
+    let changeset_element = if args.changeset_index {
+        Some(env.create_db(Some("changeset_element"), index_flags)?)
+    } else {
+        None
+    };
+
+    // with --append, the optional tables which the database already has are updated
+    let optional_table = |name, enabled| {
+        if args.append {
+            match env.open_db(Some(name)) {
+                Ok(db) => Ok(Some(db)),
+                Err(lmdb::Error::NotFound) => Ok(None),
+                Err(e) => Err(e),
+            }
+        } else if enabled {
+            env.create_db(Some(name), element_flags).map(Some)
+        } else {
+            Ok(None)
+        }
+    };
+    let way_bbox = optional_table("way_bbox", args.way_bbox || args.cell_index)?;
+    let geometries = optional_table("geometries", args.with_geometry)?;
+    let tag_tables = if args.index_tags.is_empty() {
+        None
+    } else {
+        Some([
+            env.create_db(Some("tag_node"), index_flags)?,
+            env.create_db(Some("tag_way"), index_flags)?,
+            env.create_db(Some("tag_relation"), index_flags)?,
+        ])
+    };
+    let cell_tables = if args.cell_index {
+        Some((
+            env.create_db(Some("cell_way"), index_flags)?,
+            env.create_db(Some("cell_relation"), index_flags)?,
+        ))
+    } else {
+        None
+    };
+    // unlike the other optional tables, street_names is keyed by strings
+    let street_names = if args.append {
+        match env.open_db(Some("street_names")) {
+            Ok(db) => Some(db),
+            Err(lmdb::Error::NotFound) => None,
+            Err(e) => return Err(e.into()),
+        }
+    } else if args.street_names {
+        Some(env.create_db(Some("street_names"), lmdb::DatabaseFlags::empty())?)
+    } else {
+        None
+    };
+    // so are the extension tables of index plugins; with --append, the plugins which the
+    // database records are run
+    let registry = osmx::PluginRegistry::new();
+    let plugin_names = match args.append {
+        true => index_plugins(&env)?,
+        false => registry
+            .names()
+            .into_iter()
+            .filter(|name| args.index.iter().any(|index| index == name))
+            .map(String::from)
+            .collect(),
+    };
+    let mut plugins = vec![];
+    for name in plugin_names.iter() {
+        let plugin = registry.make(name).ok_or_else(|| {
+            format!(
+                "--append doesn't support databases with the {} index plugin",
+                name
+            )
+        })?;
+        let table_name = format!("{}{}", osmx::EXTENSION_TABLE_PREFIX, name);
+        let table = match args.append {
+            true => env.open_db(Some(&table_name))?,
+            false => env.create_db(Some(&table_name), lmdb::DatabaseFlags::empty())?,
+        };
+        plugins.push((plugin, table));
+    }
+
+    let location_history = create_history_table("location_history")?;
+    let node_history = create_history_table("node_history")?;
+    let way_history = create_history_table("way_history")?;
+    let relation_history = create_history_table("relation_history")?;
+
+    let mut txn = env.begin_rw_txn()?;
+
+    let tempdir = PathBuf::from(format!("{}-tmp", args.output_file.to_str().unwrap()));
+    std::fs::create_dir_all(&tempdir).unwrap();
+
+    // the input is read in a single pass, so that it can be streamed from a URL
+    let input: Box<dyn Read + Send> = match url {
+        Some(url) => Box::new(Download::open(url)?),
+        None => Box::new(File::open(&args.input_file)?),
+    };
+    let checksum = Checksum::default();
+    let mut blobs = osmpbf::BlobReader::new(BufReader::new(checksum.reader(input)));
+
+    // write metadata table
+
+    let header = match blobs.next().transpose()? {
+        Some(blob) if blob.get_type() == osmpbf::BlobType::OsmHeader => blob.to_headerblock()?,
+        _ => return Err(format!("{} has no header block", args.input_file.display()).into()),
+    };
+
+    let encoding = match existing_encoding {
+        Some(encoding) => encoding,
+        None if args.packed => osmx::ValueEncoding::Packed,
+        None => osmx::ValueEncoding::Standard,
+    };
+    // an existing database keeps its own replication state and format metadata
+    if !args.append {
+        write_header_metadata(&mut txn, metadata, &header, &args.input_file, encoding)?;
+        if !plugin_names.is_empty() {
+            let names: String = plugin_names
+                .iter()
+                .map(|name| format!("{}\0", name))
                 .collect();
+            txn.put(
+                metadata,
+                &osmx::INDEX_PLUGINS_KEY.as_bytes(),
+                &names.as_bytes(),
+                lmdb::WriteFlags::empty(),
+            )?;
+        }
+    }
+
+    let interner = if let Some(strings) = strings {
+        let interner = Interner::from_sample(&args.input_file)?;
+        for (id, string) in interner.strings() {
+            txn.put(
+                strings,
+                &encode_string_id(id),
+                &string.as_bytes(),
+                lmdb::WriteFlags::APPEND,
+            )?;
+        }
+        Some(interner)
+    } else {
+        None
+    };
+
+    let tag_sets = if let Some(table) = tag_set_table {
+        let tag_sets = TagSets::from_sample(&args.input_file)?;
+        for (hash, tags) in tag_sets.sets() {
+            let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+            txn.put(
+                table,
+                &encode_key(hash),
+                &osmx::encode_tag_set(&tags),
+                lmdb::WriteFlags::APPEND,
+            )?;
+        }
+        Some(tag_sets)
+    } else {
+        None
+    };
+
+    // with --append, the ID bitmaps and bounds of the database are extended, if it has
+    // them
+    let bitmap = |key: &str| -> Result<Option<RoaringTreemap>, Box<dyn Error>> {
+        if !args.append {
+            return Ok(args.id_bitmaps.then(RoaringTreemap::new));
+        }
+        match txn.get(metadata, &key.as_bytes()) {
+            Ok(raw_val) => Ok(Some(RoaringTreemap::deserialize_from(raw_val)?)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    };
+    let (node_ids, way_ids, relation_ids) = (
+        bitmap("node_ids")?,
+        bitmap("way_ids")?,
+        bitmap("relation_ids")?,
+    );
+    let bounds = match txn.get(metadata, &osmx::BOUNDS_KEY.as_bytes()) {
+        Ok(raw_val) if args.append && raw_val.len() == 16 => {
+            let value =
+                |i: usize| i32::from_le_bytes(raw_val[i * 4..(i + 1) * 4].try_into().unwrap());
+            Some((value(0), value(1), value(2), value(3)))
+        }
+        _ => None,
+    };
 
-            for member_id in relation_members {
-                relation_relation_sorter.push(IDPair(member_id, rel_id));
+    let mut output = Output {
+        locations,
+        nodes,
+        ways,
+        relations,
+        location_history,
+        node_history,
+        way_history,
+        relation_history,
+        way_bbox,
+        geometries,
+        street_names,
+        cell_node_sorter: Sorter::new(&tempdir, "cell_node"),
+        node_way_sorter: Sorter::new(&tempdir, "node_way"),
+        node_relation_sorter: Sorter::new(&tempdir, "node_relation"),
+        way_relation_sorter: Sorter::new(&tempdir, "way_relation"),
+        relation_relation_sorter: Sorter::new(&tempdir, "relation_relation"),
+        changeset_sorter: changesets.map(|_| Sorter::new(&tempdir, "changesets")),
+        users: HashMap::new(),
+        uid_element_sorter: uid_element.map(|_| Sorter::new(&tempdir, "uid_element")),
+        changeset_element_sorter: changeset_element
+            .map(|_| Sorter::new(&tempdir, "changeset_element")),
+        tag_index: tag_tables.map(|_| TagIndex::new(&args.index_tags, &tempdir)),
+        cell_index: cell_tables.map(|_| CellIndex::new(&tempdir)),
+        node_ids,
+        way_ids,
+        relation_ids,
+        bounds,
+        counts: osmx::ElementCounts::default(),
+        append: args.append,
+        cell_node,
+        moved_nodes: RoaringTreemap::new(),
+        plugins,
+    };
+
+    // read .osm.pbf file and process each element
+
+    let bars = ProgressBars::new();
+    let read_phase = trace::phase("read");
+    let mut pending: Option<Record> = None;
+
+    let mut negative = None;
+    let keep_tags = !output.plugins.is_empty() || output.tag_index.is_some();
+    let threads = match args.threads {
+        Some(threads) => threads,
+        None => thread::available_parallelism()?.get(),
+    };
+
+    let encode = |block: &osmpbf::PrimitiveBlock| {
+        let mut decoded = DecodedBlob::default();
+        for elem in block.elements() {
+            // the rest of the file is skipped once an element can't be imported
+            decoded.negative = negative_id(&elem);
+            if decoded.negative.is_some() {
+                break;
+            }
+            decoded.records.push(read_element(
+                &elem,
+                interner.as_ref(),
+                tag_sets.as_ref(),
+                encoding,
+                args.metadata || args.history,
+                keep_tags,
+            ));
+        }
+        decoded
+    };
+    let write = |decoded: DecodedBlob| {
+        for record in decoded.records {
+            output.record_changeset(&record);
+            output.record_edit(&record);
+
+            if !args.history {
+                output.write_current(&mut txn, &record);
+                continue;
+            }
+
+            output.write_history(&mut txn, &record);
+
+            // history files contain every version of each element in order, so we only
+            // know that a version is the current one once the next element has been read
+            if let Some(prev) = pending.take() {
+                if prev.element_type != record.element_type || prev.id != record.id {
+                    output.write_current(&mut txn, &prev);
+                }
             }
+            pending = Some(record);
         }
-    })?;
+        negative = decoded.negative;
+        negative.is_none()
+    };
+    decode_blobs_parallel(blobs, threads, encode, write)?;
+
+    if let Some(description) = negative {
+        return Err(format!(
+            "{} has a negative ID, which OSMX databases can't store",
+            description
+        )
+        .into());
+    }
 
+    if let Some(record) = pending {
+        output.write_current(&mut txn, &record);
+    }
+
+    read_phase.end();
     eprintln!("done reading {}", args.input_file.to_str().unwrap());
 
-    insert_sorted_tuples(cell_node_sorter, &mut txn, cell_node);
-    insert_sorted_tuples(node_way_sorter, &mut txn, node_way);
-    insert_sorted_tuples(node_relation_sorter, &mut txn, node_relation);
-    insert_sorted_tuples(way_relation_sorter, &mut txn, way_relation);
-    insert_sorted_tuples(relation_relation_sorter, &mut txn, relation_relation);
+    // the whole input has been read, so the checksum is of the complete file. A database
+    // with data appended to it no longer comes from a single file.
+    if args.append {
+        match txn.del(metadata, &osmx::SOURCE_SHA256_KEY.as_bytes(), None) {
+            Ok(()) | Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        txn.put(
+            metadata,
+            &osmx::SOURCE_SHA256_KEY.as_bytes(),
+            &checksum.hex().as_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+    }
+
+    // with --append, elements which were already in the database weren't counted
+    let counts = if args.append {
+        osmx::ElementCounts {
+            nodes: table_entries(&txn, locations)?,
+            ways: table_entries(&txn, ways)?,
+            relations: table_entries(&txn, relations)?,
+        }
+    } else {
+        output.counts
+    };
+    output.write_summary(&mut txn, metadata, &counts)?;
+
+    let insert = if args.append {
+        merge_sorted_tuples
+    } else {
+        insert_sorted_tuples
+    };
+    insert(output.cell_node_sorter, &mut txn, cell_node, Some(&bars));
+    insert(output.node_way_sorter, &mut txn, node_way, Some(&bars));
+    insert(
+        output.node_relation_sorter,
+        &mut txn,
+        node_relation,
+        Some(&bars),
+    );
+    insert(
+        output.way_relation_sorter,
+        &mut txn,
+        way_relation,
+        Some(&bars),
+    );
+    insert(
+        output.relation_relation_sorter,
+        &mut txn,
+        relation_relation,
+        Some(&bars),
+    );
+
+    if !output.moved_nodes.is_empty() && (way_bbox.is_some() || geometries.is_some()) {
+        let tables = MovedNodeTables {
+            locations,
+            ways,
+            relations,
+            node_way,
+            way_relation,
+            way_bbox,
+            geometries,
+        };
+        update_moved_nodes(&mut txn, &output.moved_nodes, &tables, encoding)?;
+    }
+
+    if let (Some(sorter), Some(table)) = (output.uid_element_sorter, uid_element) {
+        insert_sorted_tuples(sorter, &mut txn, table, Some(&bars));
+    }
+    if let (Some(sorter), Some(table)) = (output.changeset_element_sorter, changeset_element) {
+        insert_sorted_tuples(sorter, &mut txn, table, Some(&bars));
+    }
+    if let (Some(tag_index), Some(tables)) = (output.tag_index, tag_tables) {
+        for (sorter, table) in tag_index.sorters.into_iter().zip(tables) {
+            insert_sorted_tuples(sorter, &mut txn, table, Some(&bars));
+        }
+        let keys: String = args
+            .index_tags
+            .iter()
+            .map(|key| format!("{}\0", key))
+            .collect();
+        txn.put(
+            metadata,
+            &osmx::TAG_INDEX_KEYS_KEY.as_bytes(),
+            &keys.as_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+    }
+    if let (Some(cell_index), Some((cell_way, cell_relation))) = (output.cell_index, cell_tables) {
+        let (way_sorter, relation_sorter) = cell_index.finish(&tempdir);
+        insert_sorted_tuples(way_sorter, &mut txn, cell_way, Some(&bars));
+        insert_sorted_tuples(relation_sorter, &mut txn, cell_relation, Some(&bars));
+    }
+    if let (Some(sorter), Some(table)) = (output.changeset_sorter, changesets) {
+        insert_changesets(sorter, &output.users, &mut txn, table, Some(&bars));
+    }
 
+    let commit_phase = trace::phase("commit");
     txn.commit()?;
+    commit_phase.end();
 
     eprintln!("committed transaction.");
 
@@ -0,0 +1,1384 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use osmx::geometry::MissingNodePolicy;
+use osmx::ElementId;
+use roaring::RoaringTreemap;
+use serde_json::{json, Map, Value};
+
+use crate::export_graph::{haversine_distance, oneway, parse_maxspeed, DEFAULT_HIGHWAYS};
+use crate::ids::ElementType;
+use crate::region::{
+    closure_of_area, ids_in_area, ring_contains, simplify_polygons, Area, AreaArgs, Bbox, Ring,
+    Strategy,
+};
+use crate::scan::ScanArgs;
+
+/// Metres per foot, for converting heights given in feet
+const FOOT: f64 = 0.3048;
+
+/// The columns of an exported address, and the tags each is read from (the first one
+/// the element has)
+const ADDRESS_COLUMNS: &[(&str, &[&str])] = &[
+    ("housenumber", &["addr:housenumber"]),
+    ("housename", &["addr:housename"]),
+    ("unit", &["addr:unit", "addr:flats"]),
+    ("street", &["addr:street"]),
+    ("place", &["addr:place"]),
+    ("suburb", &["addr:suburb", "addr:district"]),
+    ("city", &["addr:city"]),
+    ("postcode", &["addr:postcode", "postal_code"]),
+    ("state", &["addr:state", "addr:province"]),
+    ("country", &["addr:country"]),
+    ("name", &["name"]),
+];
+
+/// The columns of an exported road segment
+const SEGMENT_COLUMNS: &[&str] = &[
+    "way_id",
+    "from_node",
+    "to_node",
+    "length_m",
+    "highway",
+    "oneway",
+    "maxspeed_kmh",
+    "maxspeed_forward_kmh",
+    "maxspeed_backward_kmh",
+    "surface",
+    "access",
+    "motor_vehicle",
+    "bicycle",
+    "foot",
+];
+
+/// The columns of an exported building
+const BUILDING_COLUMNS: &[&str] = &[
+    "building",
+    "part",
+    "name",
+    "levels",
+    "min_level",
+    "height",
+    "min_height",
+    "height_estimated",
+    "roof_shape",
+    "roof_height",
+];
+
+#[derive(Parser)]
+/// Export a common selection of the elements in an OSMX database, with columns derived
+/// from their tags, for loading into other tools
+pub struct CliArgs {
+    #[command(subcommand)]
+    preset: Preset,
+}
+
+#[derive(Subcommand)]
+enum Preset {
+    Addresses(AddressesArgs),
+    Boundaries(BoundariesArgs),
+    Buildings(BuildingsArgs),
+    Coastline(CoastlineArgs),
+    Intersections(IntersectionsArgs),
+    Pbf(PbfArgs),
+    Routing(RoutingArgs),
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Geojson,
+}
+
+#[derive(Parser)]
+/// Export every element with an address (any addr:* tag) as a point, for building a
+/// geocoder. Nodes are exported at their location, closed ways (such as buildings) and
+/// multipolygons at their centroid, and other ways at the middle of their nodes.
+/// Address interpolation lines (ways tagged addr:interpolation) are left out.
+///
+/// The columns are osm_type, osm_id, lon, lat, housenumber, housename, unit, street,
+/// place, suburb, city, postcode, state, country and name. In GeoJSON, the columns are
+/// the properties of each Point feature, omitting those the element doesn't have.
+struct AddressesArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the file to create
+    output_file: PathBuf,
+    /// Output file format
+    #[arg(long, value_enum, default_value = "csv")]
+    format: Format,
+    #[command(flatten)]
+    area: AreaArgs,
+    #[command(flatten)]
+    columns: ColumnArgs,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Parser)]
+/// Export the administrative areas (boundary=administrative relations) as polygons, with
+/// the hierarchy of which area each one is in. Relations whose rings don't close (such as
+/// those with members missing from an extract), or which have rings without any area,
+/// are reported and left out.
+///
+/// The columns are osm_type, osm_id, the geometry (as WKT in CSV), name, admin_level,
+/// parent (the ID of the relation of the area it's in, with the highest admin_level
+/// below its own) and children (the IDs of the areas whose parent it is, ;-separated).
+/// The hierarchy is found by testing whether a point inside each area is inside the
+/// others, so it only includes the areas being exported, and areas without an
+/// admin_level have no parent or children.
+struct BoundariesArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the file to create
+    output_file: PathBuf,
+    /// Output file format
+    #[arg(long, value_enum, default_value = "geojson")]
+    format: Format,
+    /// Only export the areas with at most this admin_level (e.g. 4 for countries and
+    /// their states or provinces)
+    #[arg(long)]
+    max_admin_level: Option<u8>,
+    /// Simplify polygons with the Douglas–Peucker algorithm, leaving out points within
+    /// this distance (in degrees, e.g. 0.0001 for about 10 metres) of the simplified
+    /// outline. Rings left with fewer than four points are dropped, and so are the
+    /// polygons whose exterior rings are.
+    #[arg(long, value_name = "TOLERANCE")]
+    simplify: Option<f64>,
+    #[command(flatten)]
+    area: AreaArgs,
+    #[command(flatten)]
+    columns: ColumnArgs,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Parser)]
+/// Export the footprint of every building (closed ways and multipolygon relations tagged
+/// building=*, other than building=no) as a polygon, for 3D visualization and solar
+/// analysis. Multipolygons with more than one outer ring are exported as MultiPolygons.
+///
+/// The columns are osm_type, osm_id, the geometry (as WKT in CSV), building (the value
+/// of the building tag), part, name, levels, min_level, height, min_height,
+/// height_estimated, roof_shape and roof_height. Heights are in metres, converted from
+/// feet where the tag says so. Buildings without a height tag get one estimated from
+/// their number of levels (and height_estimated is true).
+struct BuildingsArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the file to create
+    output_file: PathBuf,
+    /// Output file format
+    #[arg(long, value_enum, default_value = "geojson")]
+    format: Format,
+    /// Also export building parts (building:part=*), which 3D renderers draw in place of
+    /// the outline of the building they're in
+    #[arg(long)]
+    parts: bool,
+    /// Height in metres of one level, for estimating the height of buildings from their
+    /// building:levels tag
+    #[arg(long, default_value_t = 3.0)]
+    level_height: f64,
+    /// Simplify polygons with the Douglas–Peucker algorithm, leaving out points within
+    /// this distance (in degrees, e.g. 0.0001 for about 10 metres) of the simplified
+    /// outline. Rings left with fewer than four points are dropped, and so are the
+    /// polygons whose exterior rings are.
+    #[arg(long, value_name = "TOLERANCE")]
+    simplify: Option<f64>,
+    #[command(flatten)]
+    area: AreaArgs,
+    #[command(flatten)]
+    columns: ColumnArgs,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Parser)]
+/// Export land polygons, assembled by joining the natural=coastline ways end to end, as
+/// renderers need for drawing land and sea. Each landmass is a polygon, with any seas
+/// enclosed by it as holes.
+///
+/// The columns are the geometry (as WKT in CSV), ways (the number of coastline ways the
+/// landmass was assembled from), first_way and holes; osm_type and osm_id are empty.
+/// Coastlines which don't close into a ring, such as those cut by the edge of an
+/// extract, are reported and left out.
+struct CoastlineArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the file to create
+    output_file: PathBuf,
+    /// Output file format
+    #[arg(long, value_enum, default_value = "geojson")]
+    format: Format,
+    /// Only assemble the coastlines with a node in this bounding box, given as
+    /// west,south,east,north in degrees. Each one is followed beyond the box until it
+    /// closes, so the polygons aren't clipped to it.
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<Bbox>,
+    /// Simplify polygons with the Douglas–Peucker algorithm, leaving out points within
+    /// this distance (in degrees, e.g. 0.0001 for about 10 metres) of the simplified
+    /// outline. Rings left with fewer than four points are dropped, and so are the
+    /// polygons whose exterior rings are.
+    #[arg(long, value_name = "TOLERANCE")]
+    simplify: Option<f64>,
+    #[command(flatten)]
+    columns: ColumnArgs,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Parser)]
+/// Export street intersections: the nodes shared by highway ways with at least two
+/// different names, as points, for geocoders ("Main St & 2nd Ave") and navigation.
+/// Ways with the same name which meet end to end are parts of one street, so aren't an
+/// intersection.
+///
+/// The columns are osm_type and osm_id (of the node), lon, lat, streets (the distinct
+/// names, sorted and ;-separated), street_count and way_ids (;-separated).
+struct IntersectionsArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the file to create
+    output_file: PathBuf,
+    /// Output file format
+    #[arg(long, value_enum, default_value = "csv")]
+    format: Format,
+    /// Values of the highway tag to treat as streets (default: any)
+    #[arg(long, value_delimiter = ',')]
+    highway: Vec<String>,
+    #[command(flatten)]
+    area: AreaArgs,
+    #[command(flatten)]
+    columns: ColumnArgs,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+#[derive(Parser)]
+/// Export the elements of an OSMX database (or those in a bounding box or polygon) to an
+/// OSM PBF file, for other tools to read. Elements are sorted by type and ID, nodes are
+/// written as dense nodes, and the header records the database's replication state (and
+/// its bounds, when exporting all of it). Element metadata is included if the database
+/// stores it.
+struct PbfArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the .osm.pbf file to create
+    output_file: PathBuf,
+    #[command(flatten)]
+    area: AreaArgs,
+    /// Which related elements to add to the elements in --bbox or --polygon
+    #[arg(long, value_enum, default_value = "complete-ways")]
+    strategy: Strategy,
+}
+
+#[derive(Parser)]
+/// Export the attributes routers use of each segment of the road network (each pair of
+/// consecutive nodes of a routable way) as CSV, for custom routing stacks (like OSRM
+/// segment speed files or Valhalla's way attributes) which would otherwise parse them out
+/// of a PBF.
+///
+/// Each segment is keyed by way_id, from_node and to_node, in the order of the way's
+/// nodes, followed by length_m, highway, oneway (1 if it can only be traversed from
+/// from_node to to_node, -1 if only the other way, 0 if both), maxspeed_kmh,
+/// maxspeed_forward_kmh and maxspeed_backward_kmh (parsed from km/h or mph, and empty if
+/// untagged or not a number), and the raw values of the surface, access, motor_vehicle,
+/// bicycle and foot tags.
+///
+/// With --restrictions, the turn restrictions between the exported ways are written to a
+/// second CSV file, with the columns relation_id, restriction (e.g. no_left_turn), kind
+/// (prohibitory or mandatory), mode, except (;-separated), from_way, from_node, via_node,
+/// via_ways (;-separated), to_way and to_node.
+struct RoutingArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the CSV file of segments to create
+    output_file: PathBuf,
+    /// Path of a CSV file of turn restrictions to create
+    #[arg(long)]
+    restrictions: Option<PathBuf>,
+    /// Values of the highway tag to treat as routable (default: roads which cars can use)
+    #[arg(long, value_delimiter = ',')]
+    highway: Vec<String>,
+    /// Only export routable ways whose tags also match this filter expression (see the
+    /// README for the syntax)
+    #[arg(long)]
+    filter: Option<osmx::Filter>,
+    #[command(flatten)]
+    area: AreaArgs,
+    #[command(flatten)]
+    columns: ColumnArgs,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+/// The columns to write, for shaping the output without post-processing it
+#[derive(Args)]
+struct ColumnArgs {
+    /// Columns to write, in order (comma-separated), instead of all of them. They're
+    /// chosen from the columns listed above; in GeoJSON, they're the properties, and the
+    /// geometry is always written.
+    #[arg(long, value_delimiter = ',')]
+    columns: Vec<String>,
+}
+
+impl ColumnArgs {
+    /// The indexes of the chosen columns among all of an export's columns
+    fn select(&self, all: &[&str]) -> Result<Vec<usize>, Box<dyn Error>> {
+        if self.columns.is_empty() {
+            return Ok((0..all.len()).collect());
+        }
+        self.columns
+            .iter()
+            .map(|column| {
+                all.iter().position(|c| c == column).ok_or_else(|| {
+                    format!(
+                        "unknown column {} (the columns are {})",
+                        column,
+                        all.join(",")
+                    )
+                    .into()
+                })
+            })
+            .collect()
+    }
+}
+
+/// The geometry of an exported feature
+enum Shape {
+    Point((f64, f64)),
+    /// Polygons, each given as its exterior ring followed by any holes
+    Polygons(Vec<Vec<Ring>>),
+}
+
+impl Shape {
+    fn to_geojson(&self) -> Value {
+        match self {
+            Shape::Point((lon, lat)) => json!({"type": "Point", "coordinates": [lon, lat]}),
+            Shape::Polygons(polygons) if polygons.len() == 1 => {
+                json!({"type": "Polygon", "coordinates": polygons[0]})
+            }
+            Shape::Polygons(polygons) => json!({"type": "MultiPolygon", "coordinates": polygons}),
+        }
+    }
+
+    /// The geometry as CSV fields: lon and lat for points, and WKT for polygons
+    fn to_csv(&self) -> io::Result<Vec<String>> {
+        match self {
+            Shape::Point((lon, lat)) => Ok(vec![format!("{:.7}", lon), format!("{:.7}", lat)]),
+            Shape::Polygons(polygons) => {
+                let mut wkt = vec![];
+                if polygons.len() == 1 {
+                    osmx::wkt::write_polygon(&mut wkt, &polygons[0])?;
+                } else {
+                    osmx::wkt::write_multipolygon(&mut wkt, polygons)?;
+                }
+                Ok(vec![csv_field(&String::from_utf8_lossy(&wkt))])
+            }
+        }
+    }
+}
+
+/// The centroid and signed area of a ring of (lon, lat) points, treated as planar
+/// coordinates, or None if the ring has no area
+fn ring_centroid(ring: &[(f64, f64)]) -> Option<((f64, f64), f64)> {
+    let (mut area, mut x, mut y) = (0.0, 0.0, 0.0);
+    for edge in ring.windows(2) {
+        let ((x1, y1), (x2, y2)) = (edge[0], edge[1]);
+        let cross = x1 * y2 - x2 * y1;
+        area += cross;
+        x += (x1 + x2) * cross;
+        y += (y1 + y2) * cross;
+    }
+    if area == 0.0 {
+        return None;
+    }
+    Some(((x / (3.0 * area), y / (3.0 * area)), area / 2.0))
+}
+
+/// The mean of a list of points
+fn mean_point(points: &[(f64, f64)]) -> Option<(f64, f64)> {
+    if points.is_empty() {
+        return None;
+    }
+    let n = points.len() as f64;
+    let (x, y) = points
+        .iter()
+        .fold((0.0, 0.0), |(x, y), &(lon, lat)| (x + lon, y + lat));
+    Some((x / n, y / n))
+}
+
+/// A representative point of a way: the centroid of a closed way, or the mean of the
+/// nodes of an open one (or of a closed one with no area)
+fn way_point(coordinates: &[(f64, f64)]) -> Option<(f64, f64)> {
+    let closed = coordinates.len() >= 4 && coordinates.first() == coordinates.last();
+    if closed {
+        if let Some((centroid, _)) = ring_centroid(coordinates) {
+            return Some(centroid);
+        }
+        return mean_point(&coordinates[1..]);
+    }
+    mean_point(coordinates)
+}
+
+/// The centroid of the largest ring of a multipolygon (so holes don't move it)
+fn multipolygon_point(rings: &[Vec<(f64, f64)>]) -> Option<(f64, f64)> {
+    rings
+        .iter()
+        .filter_map(|ring| ring_centroid(ring))
+        .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))
+        .map(|(centroid, _)| centroid)
+}
+
+/// Group the rings of a multipolygon into polygons: each outer ring followed by the
+/// holes in it. A ring is a hole if it's inside an odd number of the other rings, and
+/// belongs to the smallest outer ring it's inside. Outer rings are oriented
+/// counterclockwise and holes clockwise, as GeoJSON recommends.
+pub(crate) fn group_rings(rings: Vec<Ring>) -> Vec<Vec<Ring>> {
+    let rings: Vec<(Ring, f64)> = rings
+        .into_iter()
+        .filter_map(|ring| ring_centroid(&ring).map(|(_, area)| (ring, area)))
+        .collect();
+    let inside = |i: usize, j: usize| i != j && ring_contains(&rings[j].0, rings[i].0[0]);
+    let is_hole: Vec<bool> = (0..rings.len())
+        .map(|i| (0..rings.len()).filter(|&j| inside(i, j)).count() % 2 == 1)
+        .collect();
+
+    let mut polygons: Vec<Vec<Ring>> = vec![];
+    let mut outer_index = vec![None; rings.len()];
+    for (i, (ring, area)) in rings.iter().enumerate() {
+        if !is_hole[i] {
+            let mut ring = ring.clone();
+            if *area < 0.0 {
+                ring.reverse();
+            }
+            outer_index[i] = Some(polygons.len());
+            polygons.push(vec![ring]);
+        }
+    }
+    for (i, (ring, area)) in rings.iter().enumerate() {
+        if !is_hole[i] {
+            continue;
+        }
+        let outer = (0..rings.len())
+            .filter(|&j| !is_hole[j] && inside(i, j))
+            .min_by(|&a, &b| rings[a].1.abs().total_cmp(&rings[b].1.abs()));
+        if let Some(polygon) = outer.and_then(|j| outer_index[j]) {
+            let mut ring = ring.clone();
+            if *area > 0.0 {
+                ring.reverse();
+            }
+            polygons[polygon].push(ring);
+        }
+    }
+    polygons
+}
+
+/// The bounding box of some rings, as (west, south, east, north)
+fn rings_bounds(rings: &[Ring]) -> (f64, f64, f64, f64) {
+    rings.iter().flatten().fold(
+        (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+        |(west, south, east, north), &(lon, lat)| {
+            (west.min(lon), south.min(lat), east.max(lon), north.max(lat))
+        },
+    )
+}
+
+/// Whether a point is inside some rings, using the even-odd rule (so that holes are
+/// excluded)
+fn rings_contain(rings: &[Ring], point: (f64, f64)) -> bool {
+    rings
+        .iter()
+        .filter(|ring| ring_contains(ring, point))
+        .count()
+        % 2
+        == 1
+}
+
+/// A point inside some rings (using the even-odd rule): the middle of the widest span
+/// inside them along the line of latitude halfway between their southern and northern
+/// extents. Unlike the centroid, it's inside even concave shapes.
+fn interior_point(rings: &[Ring]) -> Option<(f64, f64)> {
+    let (_, south, _, north) = rings_bounds(rings);
+    let lat = (south + north) / 2.0;
+    let mut crossings: Vec<f64> = rings
+        .iter()
+        .flat_map(|ring| ring.windows(2))
+        .filter(|edge| (edge[0].1 > lat) != (edge[1].1 > lat))
+        .map(|edge| {
+            let (a, b) = (edge[0], edge[1]);
+            a.0 + (lat - a.1) * (b.0 - a.0) / (b.1 - a.1)
+        })
+        .collect();
+    crossings.sort_by(f64::total_cmp);
+    crossings
+        .chunks_exact(2)
+        .max_by(|a, b| (a[1] - a[0]).total_cmp(&(b[1] - b[0])))
+        .map(|span| ((span[0] + span[1]) / 2.0, lat))
+}
+
+/// Parse a length tag value (like height or roof:height) into metres. Values are metres
+/// unless they end in "ft" or are given in feet and inches, like 12'6".
+fn parse_length(value: &str) -> Option<f64> {
+    let value = value.trim().replace(',', ".");
+    if let Some((feet, inches)) = value.split_once('\'') {
+        let inches = inches.trim().trim_end_matches('"').trim();
+        let inches: f64 = if inches.is_empty() {
+            0.0
+        } else {
+            inches.parse().ok()?
+        };
+        return Some((feet.trim().parse::<f64>().ok()? + inches / 12.0) * FOOT);
+    }
+    let metres = match value.strip_suffix("ft") {
+        Some(feet) => feet.trim().parse::<f64>().ok()? * FOOT,
+        None => value.trim_end_matches('m').trim().parse().ok()?,
+    };
+    (metres.is_finite() && metres >= 0.0).then_some(metres)
+}
+
+/// Parse a number of levels
+fn parse_levels(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|levels| levels.is_finite() && *levels >= 0.0)
+}
+
+/// Whether an element is a building (or, with `parts`, a building part), from its tags
+fn is_building<'a>(tag: impl Fn(&str) -> Option<&'a str>, parts: bool) -> bool {
+    match tag("building") {
+        Some("no") => false,
+        Some(_) => true,
+        None => parts && tag("building:part").is_some_and(|part| part != "no"),
+    }
+}
+
+/// The column values of a building, from its tags
+fn building_columns<'a>(
+    tag: impl Fn(&str) -> Option<&'a str>,
+    level_height: f64,
+) -> Vec<Option<Value>> {
+    let (building, part) = match tag("building") {
+        Some(building) => (building, false),
+        None => (tag("building:part").unwrap_or("yes"), true),
+    };
+    let levels = tag("building:levels").and_then(parse_levels);
+    let min_level = tag("building:min_level").and_then(parse_levels);
+    let roof_height = tag("roof:height").and_then(parse_length);
+    let tagged_height = tag("height").and_then(parse_length);
+    // the levels don't include the roof
+    let estimated_height = levels.map(|levels| levels * level_height + roof_height.unwrap_or(0.0));
+    let height = tagged_height.or(estimated_height);
+    let min_height = tag("min_height")
+        .and_then(parse_length)
+        .or(min_level.map(|levels| levels * level_height));
+    vec![
+        Some(building.into()),
+        Some(part.into()),
+        tag("name").map(Value::from),
+        levels.map(Value::from),
+        min_level.map(Value::from),
+        height.map(Value::from),
+        min_height.map(Value::from),
+        height.map(|_| tagged_height.is_none().into()),
+        tag("roof:shape").map(Value::from),
+        roof_height.map(Value::from),
+    ]
+}
+
+/// The column values of an element's address
+fn address_columns<'a>(tag: impl Fn(&str) -> Option<&'a str>) -> Vec<Option<Value>> {
+    ADDRESS_COLUMNS
+        .iter()
+        .map(|(_, keys)| keys.iter().find_map(|key| tag(key)).map(Value::from))
+        .collect()
+}
+
+/// Quote a CSV field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Writes features to a CSV or GeoJSON file
+struct FeatureWriter {
+    writer: BufWriter<File>,
+    format: Format,
+    // all of the columns: osm_type, osm_id, the geometry columns, and the export's own
+    columns: Vec<&'static str>,
+    geometry_columns: usize,
+    // the indexes of the columns written, in order
+    selected: Vec<usize>,
+    // the tolerance polygons are simplified with, and the number of features left out
+    // because they were simplified away
+    simplify: Option<f64>,
+    simplified_away: u64,
+    count: u64,
+}
+
+impl FeatureWriter {
+    /// Create a file for features with the given columns, of which those chosen with
+    /// --columns are written. In CSV, the geometry is in the given geometry columns.
+    fn create(
+        path: &Path,
+        format: Format,
+        geometry_columns: &'static str,
+        columns: Vec<&'static str>,
+        column_args: &ColumnArgs,
+    ) -> Result<Self, Box<dyn Error>> {
+        let geometry_columns: Vec<&str> = geometry_columns.split(',').collect();
+        let mut all = vec!["osm_type", "osm_id"];
+        all.extend(&geometry_columns);
+        all.extend(columns);
+        let selected = column_args.select(&all)?;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        match format {
+            Format::Csv => {
+                let header: Vec<&str> = selected.iter().map(|&i| all[i]).collect();
+                writeln!(writer, "{}", header.join(","))?
+            }
+            Format::Geojson => writeln!(writer, "{{\"type\":\"FeatureCollection\",\"features\":[")?,
+        }
+        Ok(Self {
+            writer,
+            format,
+            columns: all,
+            geometry_columns: geometry_columns.len(),
+            selected,
+            simplify: None,
+            simplified_away: 0,
+            count: 0,
+        })
+    }
+
+    /// Simplify the polygons written with the given tolerance (see --simplify)
+    fn simplify(mut self, tolerance: Option<f64>) -> Self {
+        self.simplify = tolerance;
+        self
+    }
+
+    /// Write a feature, which is an OSM element unless `element` is None (leaving the
+    /// osm_type and osm_id columns empty)
+    fn write(
+        &mut self,
+        element: Option<ElementId>,
+        shape: &Shape,
+        values: &[Option<Value>],
+    ) -> Result<(), Box<dyn Error>> {
+        let simplified;
+        let shape = match (shape, self.simplify) {
+            (Shape::Polygons(polygons), Some(tolerance)) => {
+                let polygons = simplify_polygons(polygons.clone(), tolerance);
+                if polygons.is_empty() {
+                    self.simplified_away += 1;
+                    return Ok(());
+                }
+                simplified = Shape::Polygons(polygons);
+                &simplified
+            }
+            _ => shape,
+        };
+        let element = element.map(|element| match element {
+            ElementId::Node(id) => ("node", id),
+            ElementId::Way(id) => ("way", id),
+            ElementId::Relation(id) => ("relation", id),
+        });
+        // the values of all of the columns but the geometry ones
+        let mut row: Vec<Option<Value>> = match element {
+            Some((osm_type, osm_id)) => vec![Some(osm_type.into()), Some(osm_id.into())],
+            None => vec![None, None],
+        };
+        row.extend(std::iter::repeat_n(None, self.geometry_columns));
+        row.extend(values.iter().cloned());
+        match self.format {
+            Format::Csv => {
+                let geometry = shape.to_csv()?;
+                let fields: Vec<String> = self
+                    .selected
+                    .iter()
+                    .map(|&i| match &row[i] {
+                        _ if (2..2 + self.geometry_columns).contains(&i) => geometry[i - 2].clone(),
+                        Some(Value::String(value)) => csv_field(value),
+                        Some(value) => value.to_string(),
+                        None => String::new(),
+                    })
+                    .collect();
+                writeln!(self.writer, "{}", fields.join(","))?;
+            }
+            Format::Geojson => {
+                let mut properties = Map::new();
+                for &i in &self.selected {
+                    if let Some(value) = &row[i] {
+                        properties.insert(self.columns[i].to_string(), value.clone());
+                    }
+                }
+                let feature = json!({
+                    "type": "Feature",
+                    "geometry": shape.to_geojson(),
+                    "properties": properties,
+                });
+                if self.count > 0 {
+                    writeln!(self.writer, ",")?;
+                }
+                serde_json::to_writer(&mut self.writer, &feature)?;
+            }
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<u64, Box<dyn Error>> {
+        if self.simplified_away > 0 {
+            eprintln!(
+                "left out {} features which were simplified away",
+                self.simplified_away
+            );
+        }
+        if let Format::Geojson = self.format {
+            writeln!(self.writer, "\n]}}")?;
+        }
+        self.writer.flush()?;
+        Ok(self.count)
+    }
+}
+
+/// The IDs of the elements of a type in an area, or None if there's no area (so that
+/// all elements are included)
+fn area_ids(
+    txn: &osmx::Transaction,
+    area: &Option<Area>,
+    element_type: ElementType,
+) -> Result<Option<RoaringTreemap>, Box<dyn Error>> {
+    match area {
+        Some(area) => Ok(Some(ids_in_area(txn, element_type, area)?)),
+        None => Ok(None),
+    }
+}
+
+fn in_area(ids: &Option<RoaringTreemap>, id: u64) -> bool {
+    ids.as_ref().is_none_or(|ids| ids.contains(id))
+}
+
+fn export_addresses(args: &AddressesArgs) -> Result<(), Box<dyn Error>> {
+    let area = args.area.area()?;
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let locations = txn.locations()?;
+    let nodes = txn.nodes()?;
+    let ways = txn.ways()?;
+    let relations = txn.relations()?;
+
+    // with an area, only the elements in it are exported
+    let node_ids = area_ids(&txn, &area, ElementType::Node)?;
+    let way_ids = area_ids(&txn, &area, ElementType::Way)?;
+    let relation_ids = area_ids(&txn, &area, ElementType::Relation)?;
+    let has_address = |tags: &mut dyn Iterator<Item = (&str, &str)>| {
+        let mut address = false;
+        for (key, _) in tags {
+            if key == "addr:interpolation" {
+                return false;
+            }
+            address |= key.starts_with("addr:");
+        }
+        address
+    };
+
+    let columns = ADDRESS_COLUMNS.iter().map(|(column, _)| *column).collect();
+    let mut writer = FeatureWriter::create(
+        &args.output_file,
+        args.format,
+        "lon,lat",
+        columns,
+        &args.columns,
+    )?;
+    let mut skipped = 0;
+
+    for (id, node) in nodes.iter() {
+        if !in_area(&node_ids, id) || !has_address(&mut node.tags()) {
+            continue;
+        }
+        match locations.get(id)? {
+            Some(location) => writer.write(
+                Some(ElementId::Node(id)),
+                &Shape::Point((location.lon(), location.lat())),
+                &address_columns(|key| node.tag(key)),
+            )?,
+            None => skipped += 1,
+        }
+    }
+
+    for (id, way) in ways.iter() {
+        if !in_area(&way_ids, id) || !has_address(&mut way.tags()) {
+            continue;
+        }
+        let coordinates = way.coordinates(&locations, MissingNodePolicy::Skip)?;
+        match way_point(&coordinates) {
+            Some(point) => writer.write(
+                Some(ElementId::Way(id)),
+                &Shape::Point(point),
+                &address_columns(|key| way.tag(key)),
+            )?,
+            None => skipped += 1,
+        }
+    }
+
+    for (id, relation) in relations.iter() {
+        if !in_area(&relation_ids, id) || !has_address(&mut relation.tags()) {
+            continue;
+        }
+        let point = txn
+            .geometry(ElementId::Relation(id))?
+            .and_then(|rings| multipolygon_point(&rings));
+        match point {
+            Some(point) => writer.write(
+                Some(ElementId::Relation(id)),
+                &Shape::Point(point),
+                &address_columns(|key| relation.tag(key)),
+            )?,
+            None => skipped += 1,
+        }
+    }
+
+    let count = writer.finish()?;
+    eprintln!(
+        "wrote {} addresses ({} skipped because their geometry couldn't be resolved)",
+        count, skipped
+    );
+    args.scan.report_skipped(&db);
+    Ok(())
+}
+
+/// An administrative area being exported
+struct AdminArea {
+    id: u64,
+    name: Option<String>,
+    admin_level: Option<u8>,
+    polygons: Vec<Vec<Ring>>,
+    bounds: (f64, f64, f64, f64),
+    /// A point inside the area, for testing which areas it's in
+    point: (f64, f64),
+    /// The area in square degrees, for choosing the smallest of equally ranked parents
+    size: f64,
+}
+
+impl AdminArea {
+    fn contains(&self, (lon, lat): (f64, f64)) -> bool {
+        let (west, south, east, north) = self.bounds;
+        west <= lon
+            && lon <= east
+            && south <= lat
+            && lat <= north
+            && self
+                .polygons
+                .iter()
+                .any(|rings| rings_contain(rings, (lon, lat)))
+    }
+}
+
+/// Read the geometry of a boundary relation into an AdminArea, or explain why it isn't
+/// valid
+fn admin_area(
+    txn: &osmx::Transaction,
+    id: u64,
+    relation: &osmx::Relation,
+    admin_level: Option<u8>,
+) -> Result<Result<AdminArea, &'static str>, Box<dyn Error>> {
+    let Some(rings) = txn.geometry(ElementId::Relation(id))? else {
+        return Ok(Err("its rings don't close, or members are missing"));
+    };
+    if rings.iter().any(|ring| ring.len() < 4) {
+        return Ok(Err("it has a ring with fewer than four points"));
+    }
+    if rings.iter().any(|ring| ring_centroid(ring).is_none()) {
+        return Ok(Err("it has a ring with no area"));
+    }
+    let polygons = group_rings(rings);
+    let size = polygons
+        .iter()
+        .flatten()
+        .map(|ring| ring_centroid(ring).map_or(0.0, |(_, area)| area))
+        .sum::<f64>()
+        .abs();
+    let all_rings: Vec<Ring> = polygons.iter().flatten().cloned().collect();
+    let Some(point) = interior_point(&all_rings) else {
+        return Ok(Err("it has no outer ring"));
+    };
+    Ok(Ok(AdminArea {
+        id,
+        name: relation.tag("name").map(|name| name.to_string()),
+        admin_level,
+        bounds: rings_bounds(&all_rings),
+        polygons,
+        point,
+        size,
+    }))
+}
+
+fn export_boundaries(args: &BoundariesArgs) -> Result<(), Box<dyn Error>> {
+    let area = args.area.area()?;
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let relations = txn.relations()?;
+    let relation_ids = area_ids(&txn, &area, ElementType::Relation)?;
+
+    let mut areas = vec![];
+    let mut invalid = 0;
+    for (id, relation) in relations.iter() {
+        if !in_area(&relation_ids, id) || relation.tag("boundary") != Some("administrative") {
+            continue;
+        }
+        let admin_level: Option<u8> = relation
+            .tag("admin_level")
+            .and_then(|l| l.trim().parse().ok());
+        if let (Some(max), Some(level)) = (args.max_admin_level, admin_level) {
+            if level > max {
+                continue;
+            }
+        }
+        match admin_area(&txn, id, &relation, admin_level)? {
+            Ok(area) => areas.push(area),
+            Err(reason) => {
+                eprintln!("skipping relation {}: {}", id, reason);
+                invalid += 1;
+            }
+        }
+    }
+
+    // each area's parent is the area containing it with the highest admin_level below
+    // its own, and the smallest of those if several overlap
+    let parents: Vec<Option<usize>> = areas
+        .iter()
+        .map(|child| {
+            let level = child.admin_level?;
+            areas
+                .iter()
+                .enumerate()
+                .filter(|(_, parent)| {
+                    parent.admin_level.is_some_and(|l| l < level) && parent.contains(child.point)
+                })
+                .max_by(|(_, a), (_, b)| {
+                    a.admin_level
+                        .cmp(&b.admin_level)
+                        .then(b.size.total_cmp(&a.size))
+                })
+                .map(|(index, _)| index)
+        })
+        .collect();
+    let mut children: Vec<Vec<String>> = vec![vec![]; areas.len()];
+    for (index, parent) in parents.iter().enumerate() {
+        if let Some(parent) = parent {
+            children[*parent].push(areas[index].id.to_string());
+        }
+    }
+
+    let columns = vec!["name", "admin_level", "parent", "children"];
+    let mut writer = FeatureWriter::create(
+        &args.output_file,
+        args.format,
+        "wkt",
+        columns,
+        &args.columns,
+    )?
+    .simplify(args.simplify);
+    for ((area, parent), children) in areas.iter().zip(&parents).zip(children) {
+        let values = [
+            area.name.as_deref().map(Value::from),
+            area.admin_level.map(Value::from),
+            parent.map(|parent| areas[parent].id.into()),
+            (!children.is_empty()).then(|| children.join(";").into()),
+        ];
+        writer.write(
+            Some(ElementId::Relation(area.id)),
+            &Shape::Polygons(area.polygons.clone()),
+            &values,
+        )?;
+    }
+
+    let count = writer.finish()?;
+    eprintln!(
+        "wrote {} administrative areas ({} skipped because their geometry isn't valid)",
+        count, invalid
+    );
+    args.scan.report_skipped(&db);
+    Ok(())
+}
+
+fn export_buildings(args: &BuildingsArgs) -> Result<(), Box<dyn Error>> {
+    let area = args.area.area()?;
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let locations = txn.locations()?;
+    let ways = txn.ways()?;
+    let relations = txn.relations()?;
+
+    // with an area, only the buildings in it are exported
+    let way_ids = area_ids(&txn, &area, ElementType::Way)?;
+    let relation_ids = area_ids(&txn, &area, ElementType::Relation)?;
+    let columns = BUILDING_COLUMNS.to_vec();
+    let mut writer = FeatureWriter::create(
+        &args.output_file,
+        args.format,
+        "wkt",
+        columns,
+        &args.columns,
+    )?
+    .simplify(args.simplify);
+    let mut skipped = 0;
+
+    for (id, way) in ways.iter() {
+        if !in_area(&way_ids, id) || !is_building(|key| way.tag(key), args.parts) {
+            continue;
+        }
+        // buildings with missing nodes would have the wrong shape
+        let ring = match way.coordinates(&locations, MissingNodePolicy::Error) {
+            Ok(ring) if ring.len() >= 4 && ring.first() == ring.last() => ring,
+            Ok(_) | Err(osmx::Error::Geometry(_)) => {
+                skipped += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let polygons = group_rings(vec![ring]);
+        if polygons.is_empty() {
+            skipped += 1;
+            continue;
+        }
+        writer.write(
+            Some(ElementId::Way(id)),
+            &Shape::Polygons(polygons),
+            &building_columns(|key| way.tag(key), args.level_height),
+        )?;
+    }
+
+    for (id, relation) in relations.iter() {
+        if !in_area(&relation_ids, id)
+            || relation.tag("type") != Some("multipolygon")
+            || !is_building(|key| relation.tag(key), args.parts)
+        {
+            continue;
+        }
+        let polygons = txn
+            .geometry(ElementId::Relation(id))?
+            .map(group_rings)
+            .unwrap_or_default();
+        if polygons.is_empty() {
+            skipped += 1;
+            continue;
+        }
+        writer.write(
+            Some(ElementId::Relation(id)),
+            &Shape::Polygons(polygons),
+            &building_columns(|key| relation.tag(key), args.level_height),
+        )?;
+    }
+
+    let count = writer.finish()?;
+    eprintln!(
+        "wrote {} buildings ({} skipped because their geometry couldn't be resolved)",
+        count, skipped
+    );
+    args.scan.report_skipped(&db);
+    Ok(())
+}
+
+fn export_coastline(args: &CoastlineArgs) -> Result<(), Box<dyn Error>> {
+    let region = args.bbox.map(|bbox| bbox.region());
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let coastline = txn.coastline(region.as_ref())?;
+    for problem in &coastline.problems {
+        eprintln!("{}", problem);
+    }
+
+    let columns = vec!["ways", "first_way", "holes"];
+    let mut writer = FeatureWriter::create(
+        &args.output_file,
+        args.format,
+        "wkt",
+        columns,
+        &args.columns,
+    )?
+    .simplify(args.simplify);
+    for land in coastline.land {
+        let values = [
+            Some(land.way_ids.len().into()),
+            land.way_ids.first().map(|&id| id.into()),
+            Some(land.holes.len().into()),
+        ];
+        let mut rings = vec![land.exterior];
+        rings.extend(land.holes);
+        writer.write(None, &Shape::Polygons(vec![rings]), &values)?;
+    }
+
+    let count = writer.finish()?;
+    eprintln!(
+        "wrote {} land polygons ({} coastlines couldn't be assembled)",
+        count,
+        coastline.problems.len()
+    );
+    args.scan.report_skipped(&db);
+    Ok(())
+}
+
+fn export_intersections(args: &IntersectionsArgs) -> Result<(), Box<dyn Error>> {
+    let area = args.area.area()?;
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let locations = txn.locations()?;
+    let ways = txn.ways()?;
+    let node_ways = txn.node_ways()?;
+    let node_ids = area_ids(&txn, &area, ElementType::Node)?;
+    let way_ids = area_ids(&txn, &area, ElementType::Way)?;
+
+    // the name of a way, if it's a named street
+    let street_name = |way: &osmx::Way<'_>| {
+        let highway = way.tag("highway")?;
+        if !args.highway.is_empty() && !args.highway.iter().any(|h| h == highway) {
+            return None;
+        }
+        way.tag("name").map(|name| name.to_string())
+    };
+
+    let columns = vec!["streets", "street_count", "way_ids"];
+    let mut writer = FeatureWriter::create(
+        &args.output_file,
+        args.format,
+        "lon,lat",
+        columns,
+        &args.columns,
+    )?;
+    let mut visited = RoaringTreemap::new();
+    for (way_id, way) in ways.iter() {
+        if !in_area(&way_ids, way_id) || street_name(&way).is_none() {
+            continue;
+        }
+        for node_id in way.nodes() {
+            if !visited.insert(node_id) || !in_area(&node_ids, node_id) {
+                continue;
+            }
+            let mut names = vec![];
+            let mut street_ways = vec![];
+            for other_id in node_ways.get(node_id) {
+                if let Some(name) = ways.get(other_id)?.as_ref().and_then(street_name) {
+                    names.push(name);
+                    street_ways.push(other_id.to_string());
+                }
+            }
+            names.sort();
+            names.dedup();
+            if names.len() < 2 {
+                continue;
+            }
+            let Some(location) = locations.get(node_id)? else {
+                continue;
+            };
+            let values = [
+                Some(names.join(";").into()),
+                Some(names.len().into()),
+                Some(street_ways.join(";").into()),
+            ];
+            writer.write(
+                Some(ElementId::Node(node_id)),
+                &Shape::Point((location.lon(), location.lat())),
+                &values,
+            )?;
+        }
+    }
+
+    let count = writer.finish()?;
+    eprintln!("wrote {} intersections", count);
+    args.scan.report_skipped(&db);
+    Ok(())
+}
+
+fn export_pbf(args: &PbfArgs) -> Result<(), Box<dyn Error>> {
+    let db = osmx::Database::open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let elements = match args.area.area()? {
+        Some(area) => Some(closure_of_area(&txn, &area, args.strategy)?),
+        None => None,
+    };
+
+    let writer = BufWriter::new(File::create(&args.output_file)?);
+    let program = format!("osmx-rs {}", env!("CARGO_PKG_VERSION"));
+    let (_, counts) = osmx::pbf::write_database(&txn, writer, elements.as_ref(), Some(&program))?;
+    eprintln!(
+        "exported {} nodes, {} ways and {} relations.",
+        counts.nodes, counts.ways, counts.relations
+    );
+    Ok(())
+}
+
+fn export_routing(args: &RoutingArgs) -> Result<(), Box<dyn Error>> {
+    let highways: Vec<&str> = if args.highway.is_empty() {
+        DEFAULT_HIGHWAYS.to_vec()
+    } else {
+        args.highway.iter().map(|h| h.as_str()).collect()
+    };
+    let area = args.area.area()?;
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let locations = txn.locations()?;
+    let ways = txn.ways()?;
+    let way_ids = area_ids(&txn, &area, ElementType::Way)?;
+
+    let selected = args.columns.select(SEGMENT_COLUMNS)?;
+    let mut writer = BufWriter::new(File::create(&args.output_file)?);
+    let header: Vec<&str> = selected.iter().map(|&i| SEGMENT_COLUMNS[i]).collect();
+    writeln!(writer, "{}", header.join(","))?;
+
+    let mut exported = RoaringTreemap::new();
+    let mut segment_count: u64 = 0;
+    let mut skipped_ways: u64 = 0;
+    for (way_id, way) in ways.iter() {
+        let Some(highway) = way.tag("highway").filter(|h| highways.contains(h)) else {
+            continue;
+        };
+        let matches_filter = args.filter.as_ref().is_none_or(|filter| {
+            let tags: Vec<(&str, &str)> = way.tags().collect();
+            filter.matches(&tags)
+        });
+        if !in_area(&way_ids, way_id) || !matches_filter {
+            continue;
+        }
+        // segments with a node missing from the locations table can't be measured
+        let coordinates = match way.coordinates(&locations, MissingNodePolicy::Error) {
+            Ok(coordinates) => coordinates,
+            Err(osmx::Error::Geometry(_)) => {
+                skipped_ways += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        exported.insert(way_id);
+
+        let (is_oneway, reverse) = oneway(&way, highway);
+        let direction = match (is_oneway, reverse) {
+            (false, _) => 0,
+            (true, false) => 1,
+            (true, true) => -1,
+        };
+        let speed = |key: &str| match way.tag(key).and_then(parse_maxspeed) {
+            Some(speed) => speed.to_string(),
+            None => String::new(),
+        };
+        let tag = |key: &str| way.tag(key).map(csv_field).unwrap_or_default();
+        let attributes = [
+            csv_field(highway),
+            direction.to_string(),
+            speed("maxspeed"),
+            speed("maxspeed:forward"),
+            speed("maxspeed:backward"),
+            tag("surface"),
+            tag("access"),
+            tag("motor_vehicle"),
+            tag("bicycle"),
+            tag("foot"),
+        ];
+
+        let nodes: Vec<u64> = way.nodes().collect();
+        for i in 1..nodes.len() {
+            let length = haversine_distance(coordinates[i - 1], coordinates[i]);
+            let mut row = vec![
+                way_id.to_string(),
+                nodes[i - 1].to_string(),
+                nodes[i].to_string(),
+                format!("{:.1}", length),
+            ];
+            row.extend(attributes.iter().cloned());
+            let fields: Vec<&str> = selected.iter().map(|&i| row[i].as_str()).collect();
+            writeln!(writer, "{}", fields.join(","))?;
+            segment_count += 1;
+        }
+    }
+    writer.flush()?;
+    eprintln!(
+        "wrote {} segments ({} ways skipped because of missing node locations)",
+        segment_count, skipped_ways
+    );
+
+    if let Some(path) = &args.restrictions {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writeln!(
+            writer,
+            "relation_id,restriction,kind,mode,except,from_way,from_node,via_node,via_ways,\
+             to_way,to_node"
+        )?;
+        let restrictions = osmx::TurnRestrictions::new(txn.relations()?, txn.ways()?);
+        let (mut count, mut invalid) = (0, 0);
+        for restriction in restrictions.iter() {
+            let restriction = match restriction {
+                Ok(restriction) => restriction,
+                Err(osmx::Error::InvalidTurnRestriction(_)) => {
+                    invalid += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if !exported.contains(restriction.from) || !exported.contains(restriction.to) {
+                continue;
+            }
+            let join = |ids: &[u64]| {
+                let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+                ids.join(";")
+            };
+            let (via_node, via_ways) = match &restriction.via {
+                osmx::RestrictionVia::Node(id) => (id.to_string(), String::new()),
+                osmx::RestrictionVia::Ways(ids) => (String::new(), join(ids)),
+            };
+            let kind = match restriction.kind {
+                osmx::RestrictionKind::Prohibitory => "prohibitory",
+                osmx::RestrictionKind::Mandatory => "mandatory",
+            };
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{}",
+                restriction.relation_id,
+                csv_field(&restriction.restriction),
+                kind,
+                restriction
+                    .mode
+                    .as_deref()
+                    .map(csv_field)
+                    .unwrap_or_default(),
+                csv_field(&restriction.except.join(";")),
+                restriction.from,
+                restriction.from_node,
+                via_node,
+                via_ways,
+                restriction.to,
+                restriction.to_node
+            )?;
+            count += 1;
+        }
+        writer.flush()?;
+        eprintln!(
+            "wrote {} turn restrictions ({} invalid ones skipped)",
+            count, invalid
+        );
+    }
+
+    args.scan.report_skipped(&db);
+    Ok(())
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    match &args.preset {
+        Preset::Addresses(args) => export_addresses(args),
+        Preset::Boundaries(args) => export_boundaries(args),
+        Preset::Buildings(args) => export_buildings(args),
+        Preset::Coastline(args) => export_coastline(args),
+        Preset::Intersections(args) => export_intersections(args),
+        Preset::Pbf(args) => export_pbf(args),
+        Preset::Routing(args) => export_routing(args),
+    }
+}
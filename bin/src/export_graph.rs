@@ -0,0 +1,299 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use roaring::RoaringTreemap;
+
+use crate::ids::ElementType;
+use crate::region::{ids_in_area, AreaArgs};
+use crate::scan::ScanArgs;
+
+/// Highway types exported by default: those which cars can use
+pub const DEFAULT_HIGHWAYS: &[&str] = &[
+    "motorway",
+    "motorway_link",
+    "trunk",
+    "trunk_link",
+    "primary",
+    "primary_link",
+    "secondary",
+    "secondary_link",
+    "tertiary",
+    "tertiary_link",
+    "unclassified",
+    "residential",
+    "living_street",
+    "service",
+];
+
+/// Mean radius of the Earth in meters, used to compute edge lengths
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Csv,
+    Binary,
+}
+
+#[derive(Parser)]
+/// Export the road network in an OSMX database as a graph for routing. Each routable way
+/// is split into edges at the nodes it shares with other routable ways (and at its
+/// endpoints).
+///
+/// The CSV format has a header row, followed by one row per edge with the columns
+/// way_id, from_node, to_node, length_m, speed_kmh and oneway (0 or 1). The binary format
+/// is a sequence of 31-byte little-endian records with the same fields: way_id, from_node
+/// and to_node as u64, length_m as f32, speed_kmh as u16 and oneway as u8.
+///
+/// Edges point in the direction of the way's nodes (reversed for ways tagged oneway=-1).
+/// Edges which aren't oneway can be traversed in both directions.
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the graph file to create
+    output_file: PathBuf,
+    /// Values of the highway tag to treat as routable (default: roads which cars can use)
+    #[arg(long, value_delimiter = ',')]
+    highway: Vec<String>,
+    /// Output file format
+    #[arg(long, value_enum, default_value = "csv")]
+    format: Format,
+    #[command(flatten)]
+    area: AreaArgs,
+    /// Only export routable ways whose tags also match this filter expression, e.g.
+    /// "not (access=no,private or area=yes)" (see the README for the syntax)
+    #[arg(long)]
+    filter: Option<osmx::Filter>,
+    #[command(flatten)]
+    scan: ScanArgs,
+}
+
+/// One edge of the exported graph
+struct Edge {
+    way_id: u64,
+    from_node: u64,
+    to_node: u64,
+    length: f64,
+    speed: u16,
+    oneway: bool,
+}
+
+impl Edge {
+    fn write(&self, writer: &mut impl Write, format: Format) -> Result<(), std::io::Error> {
+        match format {
+            Format::Csv => writeln!(
+                writer,
+                "{},{},{},{:.1},{},{}",
+                self.way_id,
+                self.from_node,
+                self.to_node,
+                self.length,
+                self.speed,
+                self.oneway as u8
+            ),
+            Format::Binary => {
+                writer.write_all(&self.way_id.to_le_bytes())?;
+                writer.write_all(&self.from_node.to_le_bytes())?;
+                writer.write_all(&self.to_node.to_le_bytes())?;
+                writer.write_all(&(self.length as f32).to_le_bytes())?;
+                writer.write_all(&self.speed.to_le_bytes())?;
+                writer.write_all(&[self.oneway as u8])
+            }
+        }
+    }
+}
+
+/// Great-circle distance in meters between two (lon, lat) points
+pub fn haversine_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lon1, lat1) = (a.0.to_radians(), a.1.to_radians());
+    let (lon2, lat2) = (b.0.to_radians(), b.1.to_radians());
+    let h = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().asin()
+}
+
+/// Parse a maxspeed tag value (in km/h, or in mph with a "mph" suffix) into km/h
+pub fn parse_maxspeed(value: &str) -> Option<u16> {
+    let value = value.trim();
+    match value.strip_suffix("mph") {
+        Some(mph) => mph
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .map(|mph| (mph * 1.609344).round() as u16),
+        None => value.parse::<f64>().ok().map(|kmh| kmh.round() as u16),
+    }
+}
+
+/// Typical speed in km/h on a road of the given highway type, used when the way has no
+/// (parseable) maxspeed tag
+fn default_speed(highway: &str) -> u16 {
+    match highway.trim_end_matches("_link") {
+        "motorway" => 110,
+        "trunk" => 90,
+        "primary" => 70,
+        "secondary" => 60,
+        "tertiary" => 50,
+        "unclassified" | "residential" => 30,
+        "living_street" => 10,
+        "service" | "track" => 15,
+        _ => 5,
+    }
+}
+
+/// Whether the way can only be traversed in one direction, and if so, whether that is
+/// against the direction of its nodes
+pub fn oneway(way: &osmx::Way, highway: &str) -> (bool, bool) {
+    match way.tag("oneway") {
+        Some("yes" | "true" | "1") => (true, false),
+        Some("-1" | "reverse") => (true, true),
+        Some("no" | "false" | "0") => (false, false),
+        _ => {
+            let implied = highway == "motorway" || way.tag("junction") == Some("roundabout");
+            (implied, false)
+        }
+    }
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let highways: Vec<&str> = if args.highway.is_empty() {
+        DEFAULT_HIGHWAYS.to_vec()
+    } else {
+        args.highway.iter().map(|h| h.as_str()).collect()
+    };
+
+    let area = args.area.area()?;
+
+    let db = args.scan.open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let ways = txn.ways()?;
+    let locations = txn.locations()?;
+
+    // with an area, only the ways in it are exported (including the parts of them which
+    // extend outside it)
+    let way_ids = match &area {
+        Some(area) => Some(ids_in_area(&txn, ElementType::Way, area)?),
+        None => None,
+    };
+    let candidate_ways = || -> Box<dyn Iterator<Item = Result<(u64, osmx::Way), osmx::Error>>> {
+        match &way_ids {
+            Some(way_ids) => Box::new(
+                way_ids
+                    .iter()
+                    .filter_map(|id| ways.get(id).map(|way| way.map(|way| (id, way))).transpose()),
+            ),
+            None => Box::new(ways.try_iter()),
+        }
+    };
+
+    let is_routable = |way: &osmx::Way| {
+        let routable = match way.tag("highway") {
+            Some(highway) => highways.contains(&highway),
+            None => false,
+        };
+        routable
+            && args.filter.as_ref().is_none_or(|filter| {
+                let tags: Vec<(&str, &str)> = way.tags().collect();
+                filter.matches(&tags)
+            })
+    };
+
+    // first pass: find the nodes where edges start and end, which are the endpoints of
+    // routable ways and the nodes used more than once by them
+    let mut seen = RoaringTreemap::new();
+    let mut vertices = RoaringTreemap::new();
+    for entry in candidate_ways() {
+        let (_, way) = entry?;
+        if !is_routable(&way) {
+            continue;
+        }
+        let nodes: Vec<u64> = way.nodes().collect();
+        if let (Some(&first), Some(&last)) = (nodes.first(), nodes.last()) {
+            vertices.insert(first);
+            vertices.insert(last);
+        }
+        for node in nodes {
+            if !seen.insert(node) {
+                vertices.insert(node);
+            }
+        }
+    }
+    drop(seen);
+
+    // second pass: split the routable ways into edges
+    let mut writer = BufWriter::new(File::create(&args.output_file)?);
+    if let Format::Csv = args.format {
+        writeln!(writer, "way_id,from_node,to_node,length_m,speed_kmh,oneway")?;
+    }
+
+    let mut edge_count: u64 = 0;
+    let mut skipped_ways: u64 = 0;
+    for entry in candidate_ways() {
+        let (way_id, way) = entry?;
+        if !is_routable(&way) {
+            continue;
+        }
+        let highway = way.tag("highway").unwrap();
+        let speed = way
+            .tag("maxspeed")
+            .and_then(parse_maxspeed)
+            .unwrap_or_else(|| default_speed(highway));
+        let (oneway, reverse) = oneway(&way, highway);
+
+        // ways with nodes missing from the locations table (e.g. at the edge of an
+        // extract) can't be measured, so are left out of the graph
+        let coords: Option<Vec<(u64, (f64, f64))>> = way
+            .nodes()
+            .map(|node| {
+                let location = locations.get(node)?;
+                Ok(location.map(|loc| (node, (loc.lon(), loc.lat()))))
+            })
+            .collect::<Result<_, osmx::Error>>()?;
+        let Some(coords) = coords else {
+            skipped_ways += 1;
+            continue;
+        };
+
+        let mut edges = vec![];
+        let mut from = 0;
+        let mut length = 0.0;
+        for i in 1..coords.len() {
+            length += haversine_distance(coords[i - 1].1, coords[i].1);
+            if vertices.contains(coords[i].0) {
+                edges.push((coords[from].0, coords[i].0, length));
+                from = i;
+                length = 0.0;
+            }
+        }
+
+        for (from_node, to_node, length) in edges {
+            let (from_node, to_node) = if reverse {
+                (to_node, from_node)
+            } else {
+                (from_node, to_node)
+            };
+            let edge = Edge {
+                way_id,
+                from_node,
+                to_node,
+                length,
+                speed,
+                oneway,
+            };
+            edge.write(&mut writer, args.format)?;
+            edge_count += 1;
+        }
+    }
+
+    writer.flush()?;
+
+    eprintln!(
+        "wrote {} edges ({} ways skipped because of missing node locations)",
+        edge_count, skipped_ways
+    );
+    args.scan.report_skipped(&db);
+
+    Ok(())
+}
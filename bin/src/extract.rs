@@ -0,0 +1,700 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use clap::Parser;
+use lmdb::{Cursor, Transaction};
+use osmx::interop::ELEMENT_TABLES;
+use osmx::{
+    decode_element_ref, decode_key, decode_version_key, encode_key, encode_version_key,
+    ClosureStrategy, ElementId, ElementIds,
+};
+use roaring::RoaringTreemap;
+
+use crate::environment::open_env;
+use crate::region::{closure_of_area, read_regions, Area, AreaArgs, Strategy};
+
+// history tables (which only exist in some databases), and the element tables whose
+// versions they store
+const HISTORY_TABLES: &[(&str, &str)] = &[
+    ("location_history", "locations"),
+    ("node_history", "nodes"),
+    ("way_history", "ways"),
+    ("relation_history", "relations"),
+];
+
+/// The constructor of an ElementId of a particular type, e.g. ElementId::Way
+type IdType = fn(u64) -> ElementId;
+
+// index tables mapping element IDs to the IDs of the relations or ways containing them,
+// and the types of their keys and values
+const JOIN_TABLES: &[(&str, IdType, IdType)] = &[
+    ("node_way", ElementId::Node, ElementId::Way),
+    ("node_relation", ElementId::Node, ElementId::Relation),
+    ("way_relation", ElementId::Way, ElementId::Relation),
+    (
+        "relation_relation",
+        ElementId::Relation,
+        ElementId::Relation,
+    ),
+];
+
+// index tables mapping other keys (user IDs, changeset IDs) to element references, which
+// only exist in some databases
+const ELEMENT_REF_TABLES: &[&str] = &["uid_element", "changeset_element"];
+
+// metadata keys describing the contents of the database, which are recomputed for the
+// extract rather than copied
+const RECOMPUTED_METADATA: &[&str] = &[
+    "node_ids",
+    "way_ids",
+    "relation_ids",
+    osmx::BOUNDS_KEY,
+    osmx::COUNTS_KEY,
+];
+
+/// Number of values which reader threads send to the writer at a time
+const BATCH_SIZE: usize = 1024;
+
+/// Number of batches each reader thread can read ahead of the writer
+const BATCHES_AHEAD: usize = 64;
+
+#[derive(Parser)]
+/// Copy a subset of an OSMX database to a new database, with its spatial index and join
+/// tables rebuilt: either the elements listed in an ID file, plus the elements they refer
+/// to (the nodes of ways, and the members of relations, recursively), so that the
+/// extract is referentially complete; or the elements in a bounding box or polygon,
+/// found with the spatial index and join tables, plus the related elements chosen with
+/// --strategy.
+///
+/// The ID file has one typed element ID per line, such as `n123`, `w456` or
+/// `relation/789`. Blank lines and lines starting with `#` are ignored.
+///
+/// Element history, and the uid and changeset indexes, are copied for the extracted
+/// elements. The changesets table (if any) is copied in full. The metadata table is
+/// copied too, so the extract keeps the replication state of the input, and records the
+/// input's path.
+///
+/// If the output file's name ends in .pbf (like out.osm.pbf), the extract is instead
+/// written straight to an OSM PBF file, like `export pbf` writes one, without building an
+/// OSMX database.
+///
+/// With --regions, many regions are extracted at once: their closures are found in one
+/// read transaction, and the values of the elements in any of them are read from the
+/// input once, and copied to each extract which has them.
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the .osmx (or .osm.pbf) file to create
+    output_file: PathBuf,
+    /// Path to a file listing the IDs of the elements to extract
+    #[arg(long, required_unless_present_any = ["bbox", "polygon", "regions"], conflicts_with_all = ["bbox", "polygon", "regions"])]
+    ids: Option<PathBuf>,
+    #[command(flatten)]
+    area: AreaArgs,
+    /// GeoJSON FeatureCollection of regions (as for `split`), each a Polygon or
+    /// MultiPolygon feature named by its `name` property (or its `id`), to extract each
+    /// of them in one run. The output path is then a directory (created if it doesn't
+    /// exist), and each region's extract is written to it named after the region, e.g.
+    /// `berlin.osmx`
+    #[arg(long, conflicts_with_all = ["bbox", "polygon"])]
+    regions: Option<PathBuf>,
+    /// With --regions, write each region to an OSM PBF file (e.g. `berlin.osm.pbf`)
+    /// instead of an OSMX database
+    #[arg(long, requires = "regions")]
+    pbf: bool,
+    /// Which related elements to add to the elements in --bbox or --polygon
+    #[arg(long, value_enum, default_value = "complete-ways")]
+    strategy: Strategy,
+    /// Number of threads reading element values from the input (defaults to the number
+    /// of CPUs)
+    #[arg(long)]
+    threads: Option<usize>,
+}
+
+/// Read a file of typed element IDs, one per line
+fn read_ids(path: &Path) -> Result<ElementIds, Box<dyn Error>> {
+    let mut ids = ElementIds::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let id: ElementId = line
+            .parse()
+            .map_err(|e| format!("{}, line {}: {}", path.display(), i + 1, e))?;
+        ids.insert(id);
+    }
+    Ok(ids)
+}
+
+/// Open a table of the input database which only exists in some databases
+fn open_optional_table(
+    env: &lmdb::Environment,
+    name: &str,
+) -> Result<Option<lmdb::Database>, Box<dyn Error>> {
+    match env.open_db(Some(name)) {
+        Ok(table) => Ok(Some(table)),
+        Err(lmdb::Error::NotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The tables of the input database which are copied to extracts
+struct InputTables {
+    metadata: lmdb::Database,
+    elements: Vec<(&'static str, lmdb::Database)>,
+    joins: Vec<(&'static str, IdType, IdType, lmdb::Database)>,
+    history: Vec<(&'static str, &'static str, lmdb::Database)>,
+    element_refs: Vec<(&'static str, lmdb::Database)>,
+    // tables which are copied in full
+    whole: Vec<(&'static str, lmdb::Database)>,
+}
+
+impl InputTables {
+    fn open(env: &lmdb::Environment) -> Result<Self, Box<dyn Error>> {
+        let mut tables = InputTables {
+            metadata: env.open_db(Some("metadata"))?,
+            elements: vec![],
+            joins: vec![],
+            history: vec![],
+            element_refs: vec![],
+            whole: vec![],
+        };
+        for &name in ELEMENT_TABLES {
+            tables.elements.push((name, env.open_db(Some(name))?));
+        }
+        for &(name, key_type, val_type) in JOIN_TABLES {
+            tables
+                .joins
+                .push((name, key_type, val_type, env.open_db(Some(name))?));
+        }
+        for &(name, element_table) in HISTORY_TABLES {
+            if let Some(table) = open_optional_table(env, name)? {
+                tables.history.push((name, element_table, table));
+            }
+        }
+        for &name in ELEMENT_REF_TABLES {
+            if let Some(table) = open_optional_table(env, name)? {
+                tables.element_refs.push((name, table));
+            }
+        }
+        for name in ["changesets", "strings", "tag_sets"] {
+            if let Some(table) = open_optional_table(env, name)? {
+                tables.whole.push((name, table));
+            }
+        }
+        Ok(tables)
+    }
+}
+
+/// The tables of an extract, each in the same position as the input table it's copied
+/// from
+struct OutputTables {
+    metadata: lmdb::Database,
+    elements: Vec<lmdb::Database>,
+    cell_node: lmdb::Database,
+    joins: Vec<lmdb::Database>,
+    history: Vec<lmdb::Database>,
+    element_refs: Vec<lmdb::Database>,
+    whole: Vec<lmdb::Database>,
+}
+
+impl OutputTables {
+    fn create(env: &lmdb::Environment, input: &InputTables) -> Result<Self, Box<dyn Error>> {
+        let element_flags = lmdb::DatabaseFlags::INTEGER_KEY;
+        let index_flags = lmdb::DatabaseFlags::INTEGER_KEY
+            | lmdb::DatabaseFlags::INTEGER_DUP
+            | lmdb::DatabaseFlags::DUP_SORT
+            | lmdb::DatabaseFlags::DUP_FIXED;
+        let create = |name: &str, flags| env.create_db(Some(name), flags);
+        Ok(OutputTables {
+            metadata: create("metadata", lmdb::DatabaseFlags::empty())?,
+            elements: input
+                .elements
+                .iter()
+                .map(|(name, _)| create(name, element_flags))
+                .collect::<Result<_, _>>()?,
+            cell_node: create("cell_node", index_flags)?,
+            joins: input
+                .joins
+                .iter()
+                .map(|(name, ..)| create(name, index_flags))
+                .collect::<Result<_, _>>()?,
+            history: input
+                .history
+                .iter()
+                .map(|(name, ..)| create(name, lmdb::DatabaseFlags::empty()))
+                .collect::<Result<_, _>>()?,
+            element_refs: input
+                .element_refs
+                .iter()
+                .map(|(name, _)| create(name, index_flags))
+                .collect::<Result<_, _>>()?,
+            whole: input
+                .whole
+                .iter()
+                .map(|(name, _)| create(name, element_flags))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+/// An extract being written to a new database
+struct Extract<'env> {
+    path: PathBuf,
+    closure: ElementIds,
+    tables: OutputTables,
+    txn: lmdb::RwTransaction<'env>,
+    // the elements which were actually found (the closure may include references to
+    // elements missing from the input)
+    copied: ElementIds,
+    cell_nodes: Vec<(u64, u64)>,
+    bounds: Option<(i32, i32, i32, i32)>,
+}
+
+/// The IDs of an element table's elements in a set
+fn table_ids<'a>(ids: &'a ElementIds, table: &str) -> &'a RoaringTreemap {
+    match table {
+        "locations" | "nodes" => &ids.nodes,
+        "ways" => &ids.ways,
+        _ => &ids.relations,
+    }
+}
+
+/// A batch of element IDs and their raw values, read by a reader thread
+type Batch = Vec<(u64, Vec<u8>)>;
+
+/// Read the values of the given IDs from a table, in ascending order of ID, and pass
+/// them to `write`. The IDs are split into contiguous ranges which are read by separate
+/// threads, each with its own read transaction on the same snapshot as `snapshot`,
+/// while the calling thread writes the ranges in order. IDs missing from the table are
+/// skipped.
+fn read_values_parallel(
+    env: &lmdb::Environment,
+    snapshot: &lmdb::RoTransaction,
+    table: lmdb::Database,
+    ids: &RoaringTreemap,
+    threads: usize,
+    mut write: impl FnMut(u64, &[u8]) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    let snapshot_id = unsafe { lmdb_sys::mdb_txn_id(snapshot.txn()) };
+    // the first ID of each range, and the end of the last range
+    let threads = (threads.max(1) as u64).min(ids.len().max(1));
+    let mut bounds: Vec<Option<u64>> = (0..threads)
+        .map(|i| ids.select(ids.len() * i / threads))
+        .collect();
+    bounds.push(None);
+
+    thread::scope(|scope| {
+        let receivers: Vec<mpsc::Receiver<Result<Batch, String>>> = bounds
+            .windows(2)
+            .map(|range| {
+                let (start, end) = (range[0], range[1]);
+                let (sender, receiver) = mpsc::sync_channel(BATCHES_AHEAD);
+                scope.spawn(move || {
+                    let read = || -> Result<(), Box<dyn Error>> {
+                        let txn = env.begin_ro_txn()?;
+                        if unsafe { lmdb_sys::mdb_txn_id(txn.txn()) } != snapshot_id {
+                            return Err("the input database changed during the extract".into());
+                        }
+                        let mut batch = Vec::with_capacity(BATCH_SIZE);
+                        let range = ids
+                            .iter()
+                            .skip_while(|&id| start.is_none_or(|start| id < start))
+                            .take_while(|&id| end.is_none_or(|end| id < end));
+                        for id in range {
+                            match txn.get(table, &encode_key(id)) {
+                                Ok(raw_val) => batch.push((id, raw_val.to_vec())),
+                                Err(lmdb::Error::NotFound) => continue,
+                                Err(e) => return Err(e.into()),
+                            }
+                            if batch.len() == BATCH_SIZE {
+                                // the writer has stopped if the channel is closed
+                                if sender.send(Ok(std::mem::take(&mut batch))).is_err() {
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        let _ = sender.send(Ok(batch));
+                        Ok(())
+                    };
+                    if let Err(e) = read() {
+                        let _ = sender.send(Err(e.to_string()));
+                    }
+                });
+                receiver
+            })
+            .collect();
+
+        // dropping the receivers when returning early stops the readers
+        for receiver in receivers {
+            for batch in receiver {
+                for (id, raw_val) in batch? {
+                    write(id, &raw_val)?;
+                }
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Write the elements of an extract to a PBF file, reading them from the input in order
+/// of ID
+fn write_pbf(
+    txn: &osmx::Transaction,
+    elements: &ElementIds,
+    path: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let writer = BufWriter::new(File::create(path)?);
+    let program = format!("osmx-rs {}", env!("CARGO_PKG_VERSION"));
+    let (_, counts) = osmx::pbf::write_database(txn, writer, Some(elements), Some(&program))?;
+    eprintln!(
+        "wrote {}: {} nodes, {} ways, {} relations.",
+        path.display(),
+        counts.nodes,
+        counts.ways,
+        counts.relations
+    );
+    Ok(())
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let threads = match args.threads {
+        Some(threads) => threads,
+        None => thread::available_parallelism()?.get(),
+    };
+
+    // the files to create, and the areas to extract to them (or None for the IDs of
+    // --ids)
+    let targets: Vec<(PathBuf, Option<Area>)> = match &args.regions {
+        Some(path) => {
+            std::fs::create_dir_all(&args.output_file)?;
+            let extension = if args.pbf { "osm.pbf" } else { "osmx" };
+            read_regions(path)?
+                .into_iter()
+                .map(|(name, area)| {
+                    let path = args.output_file.join(format!("{}.{}", name, extension));
+                    (path, Some(area))
+                })
+                .collect()
+        }
+        None => vec![(args.output_file.clone(), args.area.area()?)],
+    };
+    if let Some((path, _)) = targets.iter().find(|(path, _)| path.exists()) {
+        return Err(format!("{} already exists", path.display()).into());
+    }
+    let pbf = args.pbf
+        || args.regions.is_none() && args.output_file.extension().is_some_and(|ext| ext == "pbf");
+
+    // the Database is closed before the input is opened again to copy raw values, since
+    // LMDB environments must not be opened twice in one process
+    let (closures, has_id_bitmaps) = {
+        let db = osmx::Database::open(&args.input_file)?;
+        let txn = osmx::Transaction::begin(&db)?;
+        let mut closures = vec![];
+        for (path, area) in targets {
+            let closure = match (&args.ids, &area) {
+                (Some(ids), _) => txn.closure(&read_ids(ids)?, ClosureStrategy::REFERENCES)?,
+                (None, Some(area)) => closure_of_area(&txn, area, args.strategy)?,
+                (None, None) => unreachable!("clap requires --ids, --bbox, --polygon or --regions"),
+            };
+            if pbf {
+                write_pbf(&txn, &closure, &path)?;
+            } else {
+                closures.push((path, closure));
+            }
+        }
+        (closures, txn.node_id_bitmap()?.is_some())
+    };
+    if pbf {
+        return Ok(());
+    }
+
+    let input_env = open_env(&args.input_file, true)?;
+    let output_envs = closures
+        .iter()
+        .map(|(path, _)| open_env(path, false))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // tables must be opened (or created) before the transactions begin, since opening a
+    // table uses a transaction of its own
+    let input_tables = InputTables::open(&input_env)?;
+    let mut output_tables = vec![];
+    for env in &output_envs {
+        output_tables.push(OutputTables::create(env, &input_tables)?);
+    }
+
+    let input_txn = input_env.begin_ro_txn()?;
+    let mut extracts = vec![];
+    for (((path, closure), tables), env) in
+        closures.into_iter().zip(output_tables).zip(&output_envs)
+    {
+        extracts.push(Extract {
+            path,
+            closure,
+            tables,
+            txn: env.begin_rw_txn()?,
+            copied: ElementIds::new(),
+            cell_nodes: vec![],
+            bounds: None,
+        });
+    }
+
+    // copy the metadata table, including the value encoding and any compression
+    // dictionaries, since values are copied verbatim (but not the input's writer lock)
+    let source = std::fs::canonicalize(&args.input_file)?;
+    let mut cursor = input_txn.open_ro_cursor(input_tables.metadata)?;
+    for (raw_key, raw_val) in cursor.iter() {
+        if !RECOMPUTED_METADATA
+            .iter()
+            .chain([&osmx::WRITER_LOCK_KEY])
+            .any(|key| raw_key == key.as_bytes())
+        {
+            for extract in extracts.iter_mut() {
+                extract.txn.put(
+                    extract.tables.metadata,
+                    &raw_key,
+                    &raw_val,
+                    lmdb::WriteFlags::empty(),
+                )?;
+            }
+        }
+    }
+    drop(cursor);
+    for extract in extracts.iter_mut() {
+        extract.txn.put(
+            extract.tables.metadata,
+            &osmx::EXTRACT_SOURCE_KEY.as_bytes(),
+            &source.as_os_str().as_encoded_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+    }
+
+    // each element value is read once, and written to every extract whose closure has it
+    for (index, &(name, input_db)) in input_tables.elements.iter().enumerate() {
+        let ids = extracts
+            .iter()
+            .map(|extract| table_ids(&extract.closure, name))
+            .fold(RoaringTreemap::new(), |union, ids| union | ids);
+        let mut count: u64 = 0;
+        read_values_parallel(
+            &input_env,
+            &input_txn,
+            input_db,
+            &ids,
+            threads,
+            |id, raw_val| {
+                count += 1;
+                let location = match name {
+                    "locations" => Some(
+                        osmx::Location::try_from(raw_val)
+                            .map_err(|_| "location value with incorrect length")?,
+                    ),
+                    _ => None,
+                };
+                for extract in extracts.iter_mut() {
+                    if !table_ids(&extract.closure, name).contains(id) {
+                        continue;
+                    }
+                    extract.txn.put(
+                        extract.tables.elements[index],
+                        &encode_key(id),
+                        &raw_val,
+                        lmdb::WriteFlags::APPEND,
+                    )?;
+                    match name {
+                        "locations" => {
+                            let location = location.as_ref().unwrap();
+                            let (lon, lat) = (location.lon(), location.lat());
+                            let latlng = s2::latlng::LatLng::from_degrees(lat, lon);
+                            let cell =
+                                s2::cellid::CellID::from(latlng).parent(osmx::CELL_INDEX_LEVEL);
+                            extract.cell_nodes.push((cell.0, id));
+
+                            let (x, y) = ((lon * 1e7).round() as i32, (lat * 1e7).round() as i32);
+                            extract.bounds = Some(match extract.bounds {
+                                Some((west, south, east, north)) => {
+                                    (west.min(x), south.min(y), east.max(x), north.max(y))
+                                }
+                                None => (x, y, x, y),
+                            });
+                            extract.copied.nodes.insert(id);
+                        }
+                        "ways" => {
+                            extract.copied.ways.insert(id);
+                        }
+                        "relations" => {
+                            extract.copied.relations.insert(id);
+                        }
+                        _ => (),
+                    }
+                }
+                Ok(())
+            },
+        )?;
+        eprintln!("copied {}: {} elements", name, count);
+    }
+
+    for extract in extracts.iter_mut() {
+        extract.cell_nodes.sort_unstable();
+        for &(cell, node) in &extract.cell_nodes {
+            extract.txn.put(
+                extract.tables.cell_node,
+                &encode_key(cell),
+                &encode_key(node),
+                lmdb::WriteFlags::APPEND_DUP,
+            )?;
+        }
+    }
+    eprintln!("built cell_node");
+
+    // entries are only copied if both elements are in the extract
+    for (index, &(name, key_type, val_type, input_db)) in input_tables.joins.iter().enumerate() {
+        let mut cursor = input_txn.open_ro_cursor(input_db)?;
+        for extract in extracts.iter_mut() {
+            let keys = match key_type(0) {
+                ElementId::Node(_) => &extract.copied.nodes,
+                ElementId::Way(_) => &extract.copied.ways,
+                ElementId::Relation(_) => &extract.copied.relations,
+            };
+            for key in keys.iter() {
+                let iter = match cursor.iter_dup_of(&encode_key(key)) {
+                    Ok(iter) => iter,
+                    Err(lmdb::Error::NotFound) => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                for (_, raw_val) in iter {
+                    let val = decode_key(raw_val).ok_or("value with incorrect length")?;
+                    if extract.copied.contains(val_type(val)) {
+                        extract.txn.put(
+                            extract.tables.joins[index],
+                            &encode_key(key),
+                            &raw_val,
+                            lmdb::WriteFlags::APPEND_DUP,
+                        )?;
+                    }
+                }
+            }
+        }
+        eprintln!("copied {}", name);
+    }
+
+    for (index, &(name, element_table, input_db)) in input_tables.history.iter().enumerate() {
+        let mut cursor = input_txn.open_ro_cursor(input_db)?;
+        for extract in extracts.iter_mut() {
+            for id in table_ids(&extract.copied, element_table).iter() {
+                // iter_from panics if there are no keys at or after the start key
+                let start = encode_version_key(id, 0);
+                match cursor.get(Some(&start), None, lmdb_sys::MDB_SET_RANGE) {
+                    Ok(_) => (),
+                    Err(lmdb::Error::NotFound) => break,
+                    Err(e) => return Err(e.into()),
+                }
+                for (raw_key, raw_val) in cursor.iter_from(start) {
+                    match decode_version_key(raw_key) {
+                        Some((key_id, _)) if key_id == id => (),
+                        _ => break,
+                    }
+                    extract.txn.put(
+                        extract.tables.history[index],
+                        &raw_key,
+                        &raw_val,
+                        lmdb::WriteFlags::APPEND,
+                    )?;
+                }
+            }
+        }
+        eprintln!("copied {}", name);
+    }
+
+    for (index, &(name, input_db)) in input_tables.element_refs.iter().enumerate() {
+        let mut cursor = input_txn.open_ro_cursor(input_db)?;
+        for (raw_key, raw_val) in cursor.iter() {
+            let element = decode_element_ref(raw_val).ok_or("invalid element reference")?;
+            for extract in extracts.iter_mut() {
+                if extract.copied.contains(element) {
+                    extract.txn.put(
+                        extract.tables.element_refs[index],
+                        &raw_key,
+                        &raw_val,
+                        lmdb::WriteFlags::APPEND_DUP,
+                    )?;
+                }
+            }
+        }
+        eprintln!("copied {}", name);
+    }
+
+    for (index, &(name, input_db)) in input_tables.whole.iter().enumerate() {
+        let mut cursor = input_txn.open_ro_cursor(input_db)?;
+        for (raw_key, raw_val) in cursor.iter() {
+            for extract in extracts.iter_mut() {
+                extract.txn.put(
+                    extract.tables.whole[index],
+                    &raw_key,
+                    &raw_val,
+                    lmdb::WriteFlags::APPEND,
+                )?;
+            }
+        }
+        eprintln!("copied {}", name);
+    }
+
+    for mut extract in extracts {
+        let (metadata, copied) = (extract.tables.metadata, &extract.copied);
+        if has_id_bitmaps {
+            for (key, bitmap) in [
+                ("node_ids", &copied.nodes),
+                ("way_ids", &copied.ways),
+                ("relation_ids", &copied.relations),
+            ] {
+                let mut buf = vec![];
+                bitmap.serialize_into(&mut buf)?;
+                extract
+                    .txn
+                    .put(metadata, &key.as_bytes(), &buf, lmdb::WriteFlags::empty())?;
+            }
+        }
+        if let Some((west, south, east, north)) = extract.bounds {
+            let buf: Vec<u8> = [west, south, east, north]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect();
+            extract.txn.put(
+                metadata,
+                &osmx::BOUNDS_KEY.as_bytes(),
+                &buf,
+                lmdb::WriteFlags::empty(),
+            )?;
+        }
+
+        let counts = osmx::ElementCounts {
+            nodes: copied.nodes.len(),
+            ways: copied.ways.len(),
+            relations: copied.relations.len(),
+        };
+        extract.txn.put(
+            metadata,
+            &osmx::COUNTS_KEY.as_bytes(),
+            &counts.to_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+
+        extract.txn.commit()?;
+
+        eprintln!(
+            "committed {}: {} nodes, {} ways, {} relations.",
+            extract.path.display(),
+            counts.nodes,
+            counts.ways,
+            counts.relations
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use roaring::RoaringTreemap;
+
+use crate::region::{ids_in_area, AreaArgs};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ElementType {
+    Node,
+    Way,
+    Relation,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Text,
+    Bitmap,
+}
+
+#[derive(Parser)]
+/// Write the IDs of the elements of one type in an OSMX database to a file, without
+/// decoding the elements (unless filtering by tag).
+///
+/// The text format has one ID per line, in ascending order. The bitmap format is a
+/// serialized 64-bit roaring bitmap (in the format used by the RoaringTreemap type of the
+/// Rust roaring crate), as stored by `expand --id-bitmaps`.
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the file to create
+    output_file: PathBuf,
+    /// Type of the elements whose IDs to write
+    #[arg(long = "type", value_enum)]
+    element_type: ElementType,
+    #[command(flatten)]
+    area: AreaArgs,
+    /// Only include elements with this tag, given as KEY (for any value) or KEY=VALUE.
+    /// May be repeated, in which case elements must have all of the tags.
+    #[arg(long)]
+    tag: Vec<String>,
+    /// Only include elements whose tags match this filter expression, e.g.
+    /// "building and not building=no" (see the README for the syntax)
+    #[arg(long)]
+    filter: Option<osmx::Filter>,
+    /// Output file format
+    #[arg(long, value_enum, default_value = "text")]
+    format: Format,
+}
+
+/// A tag filter: a key, and optionally the value it must have
+pub type TagFilter = (String, Option<String>);
+
+pub fn parse_tag_filter(arg: &str) -> TagFilter {
+    match arg.split_once('=') {
+        Some((key, value)) => (key.to_string(), Some(value.to_string())),
+        None => (arg.to_string(), None),
+    }
+}
+
+pub fn tags_match<'a>(
+    filters: &[TagFilter],
+    filter: Option<&osmx::Filter>,
+    tags: impl Iterator<Item = (&'a str, &'a str)>,
+) -> bool {
+    let tags: Vec<(&str, &str)> = tags.collect();
+    filters.iter().all(|(key, value)| {
+        tags.iter()
+            .any(|(k, v)| k == key && value.as_deref().is_none_or(|value| value == *v))
+    }) && filter.is_none_or(|filter| filter.matches(&tags))
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let area = args.area.area()?;
+    let filters: Vec<TagFilter> = args.tag.iter().map(|t| parse_tag_filter(t)).collect();
+
+    let db = osmx::Database::open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let nodes = txn.nodes()?;
+    let ways = txn.ways()?;
+    let relations = txn.relations()?;
+
+    let candidates: Box<dyn Iterator<Item = u64>> = match (&area, args.element_type) {
+        (Some(area), element_type) => Box::new(ids_in_area(&txn, element_type, area)?.into_iter()),
+        // untagged nodes are only in the locations table
+        (_, ElementType::Node) if filters.is_empty() && args.filter.is_none() => {
+            Box::new(txn.locations()?.ids())
+        }
+        (_, ElementType::Node) => Box::new(nodes.ids()),
+        (_, ElementType::Way) => Box::new(ways.ids()),
+        (_, ElementType::Relation) => Box::new(relations.ids()),
+    };
+
+    let matches = |id: u64| -> Result<bool, osmx::Error> {
+        if filters.is_empty() && args.filter.is_none() {
+            return Ok(true);
+        }
+        Ok(match args.element_type {
+            ElementType::Node => nodes
+                .get(id)?
+                .is_some_and(|n| tags_match(&filters, args.filter.as_ref(), n.tags())),
+            ElementType::Way => ways
+                .get(id)?
+                .is_some_and(|w| tags_match(&filters, args.filter.as_ref(), w.tags())),
+            ElementType::Relation => relations
+                .get(id)?
+                .is_some_and(|r| tags_match(&filters, args.filter.as_ref(), r.tags())),
+        })
+    };
+
+    let mut writer = BufWriter::new(File::create(&args.output_file)?);
+    let mut count: u64 = 0;
+    match args.format {
+        Format::Text => {
+            for id in candidates {
+                if matches(id)? {
+                    writeln!(writer, "{}", id)?;
+                    count += 1;
+                }
+            }
+        }
+        Format::Bitmap => {
+            let mut bitmap = RoaringTreemap::new();
+            for id in candidates {
+                if matches(id)? {
+                    bitmap.insert(id);
+                }
+            }
+            bitmap.serialize_into(&mut writer)?;
+            count = bitmap.len();
+        }
+    }
+    writer.flush()?;
+
+    eprintln!("wrote {} IDs", count);
+
+    Ok(())
+}
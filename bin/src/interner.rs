@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Maximum number of strings in the string table
+const MAX_STRINGS: usize = 65536;
+/// Only every Nth data blob of the input is read when counting string frequencies
+const SAMPLE_INTERVAL: usize = 16;
+/// Strings must occur at least this many times in the sample to be interned
+const MIN_COUNT: u64 = 8;
+/// Longer strings are never interned (they are unlikely to be common, and would bloat the table)
+const MAX_LENGTH: usize = 64;
+/// When the frequency table grows past this size, strings seen only once are dropped from it
+const PRUNE_THRESHOLD: usize = 4_000_000;
+
+/// A table of commonly occurring tag keys and values, which are stored by ID in
+/// element values instead of inline.
+pub struct Interner {
+    ids: HashMap<String, u32>,
+    strings: Vec<String>,
+}
+
+impl Interner {
+    /// Choose which strings to intern by counting the tag keys and values in a sample
+    /// of the data blobs in the given .osm.pbf file.
+    pub fn from_sample(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut counts: HashMap<String, u64> = HashMap::new();
+
+        let mut count = |s: &str| {
+            if s.len() > MAX_LENGTH {
+                return;
+            }
+            match counts.get_mut(s) {
+                Some(n) => *n += 1,
+                None => {
+                    if counts.len() >= PRUNE_THRESHOLD {
+                        counts.retain(|_, n| *n > 1);
+                    }
+                    counts.insert(s.to_string(), 1);
+                }
+            }
+        };
+
+        let blobs = osmpbf::BlobReader::from_path(path)?
+            .map(|r| r.unwrap())
+            .filter(|blob| blob.get_type() == osmpbf::BlobType::OsmData)
+            .step_by(SAMPLE_INTERVAL);
+
+        for blob in blobs {
+            let block = blob.to_primitiveblock()?;
+            for elem in block.elements() {
+                let tags: Vec<(&str, &str)> = match elem {
+                    osmpbf::Element::Node(node) => node.tags().collect(),
+                    osmpbf::Element::DenseNode(node) => node.tags().collect(),
+                    osmpbf::Element::Way(way) => way.tags().collect(),
+                    osmpbf::Element::Relation(rel) => rel.tags().collect(),
+                };
+                for (key, val) in tags {
+                    count(key);
+                    count(val);
+                }
+            }
+        }
+
+        let mut common: Vec<(String, u64)> = counts
+            .into_iter()
+            .filter(|&(_, n)| n >= MIN_COUNT)
+            .collect();
+        // most common first, so that the most frequent strings get the smallest IDs
+        common.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        common.truncate(MAX_STRINGS);
+
+        let strings: Vec<String> = common.into_iter().map(|(s, _)| s).collect();
+        let ids = strings
+            .iter()
+            .enumerate()
+            .map(|(idx, s)| (s.clone(), idx as u32 + 1))
+            .collect();
+
+        Ok(Self { ids, strings })
+    }
+
+    /// Get the ID of an interned string, or None if it was not interned. IDs start at 1.
+    pub fn get(&self, s: &str) -> Option<u32> {
+        self.ids.get(s).copied()
+    }
+
+    /// Returns the interned strings and their IDs, in ID order.
+    pub fn strings(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.strings
+            .iter()
+            .enumerate()
+            .map(|(idx, s)| (idx as u32 + 1, s.as_str()))
+    }
+}
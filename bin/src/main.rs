@@ -2,10 +2,40 @@ use std::error::Error;
 
 use clap::{Parser, Subcommand};
 
-mod builders;
+mod analyze;
+mod apply_delta;
+mod backup;
+mod bench;
+mod cells;
+mod check;
+mod checksum;
+mod completions;
+mod delta;
+mod doctor;
+mod download;
+mod dump;
+mod environment;
 mod expand;
+mod export;
+mod export_graph;
+mod extract;
+mod ids;
+mod interner;
+mod man_pages;
+mod progress;
+mod query;
+mod region;
+mod reindex;
+mod repack;
+mod sample;
+mod scan;
 mod sorter;
+mod split;
 mod stat;
+mod tag_sets;
+mod timestamp;
+mod trace;
+mod update;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -16,15 +46,60 @@ struct CliArgs {
 
 #[derive(Subcommand)]
 enum Command {
+    Analyze(analyze::CliArgs),
+    ApplyDelta(apply_delta::CliArgs),
+    Backup(backup::CliArgs),
+    Bench(bench::CliArgs),
+    Cells(cells::CliArgs),
+    Check(check::CliArgs),
+    Completions(completions::CliArgs),
+    Delta(delta::CliArgs),
+    Doctor(doctor::CliArgs),
+    Dump(dump::CliArgs),
     Expand(expand::CliArgs),
+    Export(export::CliArgs),
+    ExportGraph(export_graph::CliArgs),
+    Extract(extract::CliArgs),
+    Ids(ids::CliArgs),
+    ManPages(man_pages::CliArgs),
+    Query(query::CliArgs),
+    Reindex(reindex::CliArgs),
+    Repack(repack::CliArgs),
+    Sample(sample::CliArgs),
+    Split(split::CliArgs),
     Stat(stat::CliArgs),
+    Timestamp(timestamp::CliArgs),
+    Update(update::CliArgs),
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args = CliArgs::parse();
+    trace::init();
     match args.subcommand {
         Command::Stat(args) => stat::run(&args)?,
         Command::Expand(args) => expand::run(&args)?,
+        Command::Repack(args) => repack::run(&args)?,
+        Command::Check(args) => check::run(&args)?,
+        Command::Delta(args) => delta::run(&args)?,
+        Command::ApplyDelta(args) => apply_delta::run(&args)?,
+        Command::Backup(args) => backup::run(&args)?,
+        Command::Timestamp(args) => timestamp::run(&args)?,
+        Command::ExportGraph(args) => export_graph::run(&args)?,
+        Command::Analyze(args) => analyze::run(&args)?,
+        Command::Ids(args) => ids::run(&args)?,
+        Command::Sample(args) => sample::run(&args)?,
+        Command::Cells(args) => cells::run(&args)?,
+        Command::Extract(args) => extract::run(&args)?,
+        Command::Reindex(args) => reindex::run(&args)?,
+        Command::Dump(args) => dump::run(&args)?,
+        Command::Split(args) => split::run(&args)?,
+        Command::Bench(args) => bench::run(&args)?,
+        Command::Doctor(args) => doctor::run(&args)?,
+        Command::Export(args) => export::run(&args)?,
+        Command::Completions(args) => completions::run(&args)?,
+        Command::ManPages(args) => man_pages::run(&args)?,
+        Command::Update(args) => update::run(&args)?,
+        Command::Query(args) => query::run(&args)?,
     };
 
     Ok(())
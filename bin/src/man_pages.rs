@@ -0,0 +1,20 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::{CommandFactory, Parser};
+
+#[derive(Parser)]
+/// Write a man page for osmx-rs and one for each of its commands (named like
+/// osmx-rs-export-boundaries.1) to a directory, for packaging. Run this after building
+/// the binary, and install the pages in a man1 directory.
+pub struct CliArgs {
+    /// The directory to write the pages to, which is created if it doesn't exist
+    output_dir: PathBuf,
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(&args.output_dir)?;
+    let command = crate::CliArgs::command();
+    clap_mangen::generate_to(command, &args.output_dir)?;
+    Ok(())
+}
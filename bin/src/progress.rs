@@ -0,0 +1,44 @@
+use std::cell::RefCell;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Shows the progress of each phase of an operation as an indicatif progress bar
+pub struct ProgressBars {
+    bar: RefCell<Option<ProgressBar>>,
+}
+
+impl ProgressBars {
+    pub fn new() -> Self {
+        Self {
+            bar: RefCell::new(None),
+        }
+    }
+}
+
+impl osmx::ProgressSink for ProgressBars {
+    fn progress(&self, phase: &str, done: u64, total: Option<u64>) {
+        let mut bar = self.bar.borrow_mut();
+        let bar = bar.get_or_insert_with(|| {
+            let bar = match total {
+                Some(total) => ProgressBar::new(total),
+                None => ProgressBar::new_spinner(),
+            };
+            bar.set_style(
+                ProgressStyle::with_template(
+                    "[{elapsed_precise}] {msg:>20} [{bar:40}] {pos}/{len}",
+                )
+                .unwrap()
+                .progress_chars("=> "),
+            );
+            bar.set_message(phase.to_string());
+            bar
+        });
+        bar.set_position(done);
+    }
+
+    fn finish(&self, _phase: &str) {
+        if let Some(bar) = self.bar.borrow_mut().take() {
+            bar.finish();
+        }
+    }
+}
@@ -0,0 +1,434 @@
+use std::error::Error;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use osmx::geometry::MissingNodePolicy;
+use osmx::ElementId;
+use serde_json::{json, Map, Value};
+
+use crate::export::group_rings;
+use crate::ids::{parse_tag_filter, tags_match, TagFilter};
+use crate::region::{simplify_polygons, Bbox, Ring};
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Geojson,
+    Wkt,
+}
+
+#[derive(Parser)]
+/// Write the geometries of the ways in a bounding box to stdout, for inspecting them or
+/// piping them into GIS tools. Ways are found with the spatial index (see `expand
+/// --cell-index`), and those whose bounding boxes don't overlap the box are left out.
+/// Ways with nodes missing from the database (as in extracts) are skipped.
+///
+/// The geojson format has one GeoJSON Feature per line (newline-delimited GeoJSON), with
+/// a LineString geometry and the osm_id and the tags as properties. The wkt format has
+/// one tab-separated line per way: its ID, the values of the --properties tags (empty
+/// if the way doesn't have them), and its WKT LINESTRING.
+///
+/// With --stdin, the elements are instead read as typed IDs (n123, w123 and r123, or
+/// node/123, way/123 and relation/123), one per line, and each is written as soon as
+/// it's read, all in one transaction, for use in pipelines which produce lists of IDs.
+/// Nodes are points, ways linestrings, and multipolygon and boundary relations
+/// (multi)polygons; GeoJSON features also have an osm_type property, and WKT lines start
+/// with the typed ID instead of the ID. Elements which aren't found or whose geometry
+/// can't be resolved (including other kinds of relations) are reported and skipped.
+///
+/// With --template, each element is instead written as a line made from the template,
+/// in which {type} is replaced by the element's type (node, way or relation), {id} by
+/// its ID, {tag:KEY} by the value of one of its tags (or nothing if it doesn't have it),
+/// {wkt} by its WKT geometry and {geojson} by its GeoJSON geometry. {{ and }} stand for
+/// literal braces. For example, --template '{id},{tag:name},{wkt}'.
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Bounding box to query, given as west,south,east,north in degrees
+    #[arg(long, allow_hyphen_values = true, required_unless_present = "stdin")]
+    bbox: Option<Bbox>,
+    /// Read typed element IDs from stdin, one per line, instead of querying a bounding
+    /// box
+    #[arg(long, conflicts_with = "bbox")]
+    stdin: bool,
+    /// Only include elements with this tag, given as KEY (for any value) or KEY=VALUE. May
+    /// be repeated, in which case elements must have all of the tags.
+    #[arg(long)]
+    tag: Vec<String>,
+    /// Only include elements whose tags match this filter expression, e.g.
+    /// "highway and not highway=footway" (see the README for the syntax)
+    #[arg(long)]
+    filter: Option<osmx::Filter>,
+    /// Tags to write as properties (comma-separated). By default, GeoJSON features have
+    /// all of their tags as properties, and WKT lines have none.
+    #[arg(long, value_delimiter = ',')]
+    properties: Vec<String>,
+    /// Output format
+    #[arg(long, value_enum, default_value = "geojson")]
+    format: Format,
+    /// Template of the line written for each element, instead of the output format (see
+    /// above)
+    #[arg(long, conflicts_with_all = ["format", "properties"])]
+    template: Option<Template>,
+    /// Simplify linestrings and polygons with the Douglas–Peucker algorithm, leaving out
+    /// points within this distance (in degrees, e.g. 0.0001 for about 10 metres) of the
+    /// simplified line. Polygons whose exterior rings are left with fewer than four
+    /// points are skipped.
+    #[arg(long, value_name = "TOLERANCE")]
+    simplify: Option<f64>,
+}
+
+/// A value substituted into a --template
+#[derive(Clone)]
+enum Field {
+    Type,
+    Id,
+    Tag(String),
+    Wkt,
+    Geojson,
+}
+
+/// A --template: the text of the line written for each element, and the fields in it
+#[derive(Clone)]
+struct Template {
+    parts: Vec<(String, Option<Field>)>,
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // each part is some text followed by a field (or the end of the template)
+        let mut parts = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err("unclosed { in template".into()),
+                        }
+                    }
+                    let field = match name.as_str() {
+                        "type" => Field::Type,
+                        "id" => Field::Id,
+                        "wkt" => Field::Wkt,
+                        "geojson" => Field::Geojson,
+                        _ => match name.strip_prefix("tag:") {
+                            Some(key) => Field::Tag(key.to_string()),
+                            None => return Err(format!("unknown template field {{{}}}", name)),
+                        },
+                    };
+                    parts.push((std::mem::take(&mut text), Some(field)));
+                }
+                '}' => return Err("unmatched } in template (write }} for a brace)".into()),
+                c => text.push(c),
+            }
+        }
+        parts.push((text, None));
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    fn write(
+        &self,
+        writer: &mut impl Write,
+        element: ElementId,
+        tags: &[(&str, &str)],
+        shape: &Shape,
+    ) -> Result<(), Box<dyn Error>> {
+        let (osm_type, id) = match element {
+            ElementId::Node(id) => ("node", id),
+            ElementId::Way(id) => ("way", id),
+            ElementId::Relation(id) => ("relation", id),
+        };
+        for (text, field) in &self.parts {
+            write!(writer, "{}", text)?;
+            match field {
+                Some(Field::Type) => write!(writer, "{}", osm_type)?,
+                Some(Field::Id) => write!(writer, "{}", id)?,
+                Some(Field::Tag(key)) => {
+                    let value = tags.iter().find(|(k, _)| k == key).map(|(_, v)| *v);
+                    write!(writer, "{}", value.unwrap_or(""))?
+                }
+                Some(Field::Wkt) => shape.write_wkt(writer)?,
+                Some(Field::Geojson) => serde_json::to_writer(&mut *writer, &shape.to_geojson())?,
+                None => (),
+            }
+        }
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+/// The geometry of an element written by the query
+enum Shape {
+    Point((f64, f64)),
+    LineString(Vec<(f64, f64)>),
+    /// Polygons, each given as its exterior ring followed by any holes
+    Polygons(Vec<Vec<Ring>>),
+}
+
+impl Shape {
+    fn to_geojson(&self) -> Value {
+        match self {
+            Shape::Point((lon, lat)) => json!({"type": "Point", "coordinates": [lon, lat]}),
+            Shape::LineString(coordinates) => {
+                let coordinates: Vec<[f64; 2]> =
+                    coordinates.iter().map(|&(lon, lat)| [lon, lat]).collect();
+                json!({"type": "LineString", "coordinates": coordinates})
+            }
+            Shape::Polygons(polygons) if polygons.len() == 1 => {
+                json!({"type": "Polygon", "coordinates": polygons[0]})
+            }
+            Shape::Polygons(polygons) => json!({"type": "MultiPolygon", "coordinates": polygons}),
+        }
+    }
+
+    /// The shape simplified with the given tolerance (see --simplify), or None if
+    /// nothing is left of it
+    fn simplify(self, tolerance: Option<f64>) -> Option<Shape> {
+        let Some(tolerance) = tolerance else {
+            return Some(self);
+        };
+        match self {
+            Shape::Point(_) => Some(self),
+            Shape::LineString(coordinates) => Some(Shape::LineString(osmx::geometry::simplify(
+                &coordinates,
+                tolerance,
+            ))),
+            Shape::Polygons(polygons) => {
+                let polygons = simplify_polygons(polygons, tolerance);
+                (!polygons.is_empty()).then_some(Shape::Polygons(polygons))
+            }
+        }
+    }
+
+    fn write_wkt(&self, writer: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            Shape::Point(point) => osmx::wkt::write_point(writer, *point),
+            Shape::LineString(coordinates) => osmx::wkt::write_linestring(writer, coordinates),
+            Shape::Polygons(polygons) if polygons.len() == 1 => {
+                osmx::wkt::write_polygon(writer, &polygons[0])
+            }
+            Shape::Polygons(polygons) => osmx::wkt::write_multipolygon(writer, polygons),
+        }
+    }
+}
+
+/// Write an element as a GeoJSON feature or WKT line, identifying it by its typed ID if
+/// `typed` (as in --stdin mode, where elements of any type are written) or otherwise by
+/// its ID
+fn write_element(
+    writer: &mut impl Write,
+    args: &CliArgs,
+    element: ElementId,
+    tags: &[(&str, &str)],
+    shape: &Shape,
+    typed: bool,
+) -> Result<(), Box<dyn Error>> {
+    let (osm_type, prefix, id) = match element {
+        ElementId::Node(id) => ("node", "n", id),
+        ElementId::Way(id) => ("way", "w", id),
+        ElementId::Relation(id) => ("relation", "r", id),
+    };
+    if let Some(template) = &args.template {
+        return template.write(writer, element, tags, shape);
+    }
+    let tag = |key: &str| tags.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+    match args.format {
+        Format::Geojson => {
+            let mut properties = Map::new();
+            if typed {
+                properties.insert("osm_type".into(), osm_type.into());
+            }
+            properties.insert("osm_id".into(), id.into());
+            if args.properties.is_empty() {
+                for (key, value) in tags {
+                    properties.insert(key.to_string(), (*value).into());
+                }
+            } else {
+                for key in &args.properties {
+                    if let Some(value) = tag(key) {
+                        properties.insert(key.clone(), value.into());
+                    }
+                }
+            }
+            let feature = json!({
+                "type": "Feature",
+                "geometry": shape.to_geojson(),
+                "properties": Value::Object(properties),
+            });
+            serde_json::to_writer(&mut *writer, &feature)?;
+            writeln!(writer)?;
+        }
+        Format::Wkt => {
+            if typed {
+                write!(writer, "{}{}", prefix, id)?;
+            } else {
+                write!(writer, "{}", id)?;
+            }
+            for key in &args.properties {
+                write!(writer, "\t{}", tag(key).unwrap_or(""))?;
+            }
+            write!(writer, "\t")?;
+            shape.write_wkt(writer)?;
+            writeln!(writer)?;
+        }
+    }
+    Ok(())
+}
+
+fn owned<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<(String, String)> {
+    tags.map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Write the elements whose typed IDs are read from stdin
+fn run_stdin(args: &CliArgs, filters: &[TagFilter]) -> Result<(), Box<dyn Error>> {
+    let db = osmx::Database::open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let nodes = txn.nodes()?;
+    let ways = txn.ways()?;
+    let relations = txn.relations()?;
+
+    let mut writer = BufWriter::new(std::io::stdout().lock());
+    let (mut count, mut skipped) = (0u64, 0u64);
+    for line in std::io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let element: ElementId = line.parse()?;
+        let owned_tags: Vec<(String, String)> = match element {
+            ElementId::Node(id) => match nodes.get(id)? {
+                Some(node) => owned(node.tags()),
+                None => vec![],
+            },
+            ElementId::Way(id) => match ways.get(id)? {
+                Some(way) => owned(way.tags()),
+                None => vec![],
+            },
+            ElementId::Relation(id) => match relations.get(id)? {
+                Some(relation) => owned(relation.tags()),
+                None => vec![],
+            },
+        };
+        let tags: Vec<(&str, &str)> = owned_tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        let Some(mut parts) = txn.geometry(element)? else {
+            eprintln!(
+                "skipping {}: not found, or its geometry can't be resolved",
+                line
+            );
+            skipped += 1;
+            continue;
+        };
+        if !tags_match(filters, args.filter.as_ref(), tags.iter().copied()) {
+            continue;
+        }
+        let shape = match element {
+            ElementId::Node(_) => Shape::Point(parts[0][0]),
+            ElementId::Way(_) => Shape::LineString(parts.remove(0)),
+            ElementId::Relation(_) => Shape::Polygons(group_rings(parts)),
+        };
+        let Some(shape) = shape.simplify(args.simplify) else {
+            eprintln!("skipping {}: simplified away", line);
+            skipped += 1;
+            continue;
+        };
+        write_element(&mut writer, args, element, &tags, &shape, true)?;
+        // flushed line by line, so that each result follows its ID through a pipeline
+        writer.flush()?;
+        count += 1;
+    }
+
+    eprintln!("wrote {} elements", count);
+    if skipped > 0 {
+        eprintln!("skipped {} elements", skipped);
+    }
+    Ok(())
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let filters: Vec<TagFilter> = args.tag.iter().map(|t| parse_tag_filter(t)).collect();
+    // clap requires --bbox without --stdin
+    let Some(bbox) = args.bbox else {
+        return run_stdin(args, &filters);
+    };
+    let Bbox {
+        west,
+        south,
+        east,
+        north,
+    } = bbox;
+
+    let db = osmx::Database::open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let ways = txn.ways()?;
+    let locations = txn.locations()?;
+
+    let region = bbox.region();
+    let mut writer = BufWriter::new(std::io::stdout().lock());
+    let (mut count, mut skipped) = (0u64, 0u64);
+    for id in txn.ways_in_region(&region)? {
+        // the cells covering the box extend beyond it
+        match txn.way_bbox(id)? {
+            Some((w, s, e, n)) if w <= east && west <= e && s <= north && south <= n => (),
+            _ => continue,
+        }
+        let Some(way) = ways.get(id)? else {
+            continue;
+        };
+        if !tags_match(&filters, args.filter.as_ref(), way.tags()) {
+            continue;
+        }
+        let coordinates = match way.coordinates(&locations, MissingNodePolicy::Error) {
+            Ok(coordinates) => coordinates,
+            Err(osmx::Error::Geometry(_)) => {
+                skipped += 1;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let coordinates = match args.simplify {
+            Some(tolerance) => osmx::geometry::simplify(&coordinates, tolerance),
+            None => coordinates,
+        };
+        let tags: Vec<(&str, &str)> = way.tags().collect();
+        write_element(
+            &mut writer,
+            args,
+            ElementId::Way(id),
+            &tags,
+            &Shape::LineString(coordinates),
+            false,
+        )?;
+        count += 1;
+    }
+    writer.flush()?;
+
+    eprintln!("wrote {} ways", count);
+    if skipped > 0 {
+        eprintln!("skipped {} ways with missing nodes", skipped);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,361 @@
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use clap::{Args, ValueEnum};
+use osmx::{ClosureStrategy, ElementIds};
+use roaring::RoaringTreemap;
+use serde_json::Value;
+
+use crate::ids::ElementType;
+
+/// A ring of (longitude, latitude) points in degrees
+pub type Ring = Vec<(f64, f64)>;
+
+/// A bounding box given on the command line as west,south,east,north in degrees
+#[derive(Clone, Copy)]
+pub struct Bbox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl Bbox {
+    pub fn region(&self) -> osmx::Region {
+        osmx::Region::from_bbox(self.west, self.south, self.east, self.north)
+    }
+
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        self.west <= lon && lon <= self.east && self.south <= lat && lat <= self.north
+    }
+}
+
+impl FromStr for Bbox {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid coordinate ({})", e))?;
+        let &[west, south, east, north] = values.as_slice() else {
+            return Err("expected four values: west,south,east,north".to_string());
+        };
+        if ![west, east]
+            .iter()
+            .all(|lon| (-180.0..=180.0).contains(lon))
+        {
+            return Err("longitudes must be between -180 and 180".to_string());
+        }
+        if ![south, north]
+            .iter()
+            .all(|lat| (-90.0..=90.0).contains(lat))
+        {
+            return Err("latitudes must be between -90 and 90".to_string());
+        }
+        if west > east || south > north {
+            return Err("west must not be greater than east, nor south than north".to_string());
+        }
+        Ok(Bbox {
+            west,
+            south,
+            east,
+            north,
+        })
+    }
+}
+
+/// An area which a command is restricted to
+pub enum Area {
+    /// West, south, east and north, in degrees
+    BoundingBox(f64, f64, f64, f64),
+    /// Polygons, each given as its exterior ring followed by any holes
+    Polygons(Vec<Vec<Ring>>),
+}
+
+/// Which elements related to those in an area to include with them (see
+/// osmx::ClosureStrategy)
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Strategy {
+    /// The ways of the nodes in the area, and the relations of those nodes and ways.
+    /// Ways and relations crossing the edge of the area are clipped: the result lacks
+    /// their nodes and members outside it
+    Simple,
+    /// Like simple, but with all the nodes of the ways, so that their geometry is complete
+    CompleteWays,
+    /// Like complete-ways, but with all the members of the relations (recursively), so
+    /// that the result is referentially complete
+    CompleteRelations,
+}
+
+impl Strategy {
+    fn closure_strategy(self) -> ClosureStrategy {
+        match self {
+            Strategy::Simple => ClosureStrategy::SIMPLE,
+            Strategy::CompleteWays => ClosureStrategy::COMPLETE_WAYS,
+            Strategy::CompleteRelations => ClosureStrategy::COMPLETE_RELATIONS,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct AreaArgs {
+    /// Only include elements within this bounding box, given as west,south,east,north in
+    /// degrees: nodes located in it, ways with a node in it, and relations with a node or
+    /// way member in it
+    #[arg(long, allow_hyphen_values = true)]
+    bbox: Option<Bbox>,
+    /// Only include elements within the polygons in this GeoJSON file (a Polygon or
+    /// MultiPolygon geometry, or a Feature or FeatureCollection of them), in the same way
+    /// as --bbox
+    #[arg(long, conflicts_with = "bbox")]
+    polygon: Option<PathBuf>,
+}
+
+impl AreaArgs {
+    /// The area given by the options, if any
+    pub fn area(&self) -> Result<Option<Area>, Box<dyn Error>> {
+        if let Some(bbox) = self.bbox {
+            return Ok(Some(Area::BoundingBox(
+                bbox.west, bbox.south, bbox.east, bbox.north,
+            )));
+        }
+        match &self.polygon {
+            Some(path) => Ok(Some(read_geojson(path)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+fn parse_ring(value: &Value) -> Result<Ring, Box<dyn Error>> {
+    let points = value.as_array().ok_or("polygon ring is not an array")?;
+    let ring = points
+        .iter()
+        .map(|point| match point.as_array().map(|p| p.as_slice()) {
+            Some([lon, lat, ..]) => match (lon.as_f64(), lat.as_f64()) {
+                (Some(lon), Some(lat)) => Ok((lon, lat)),
+                _ => Err("polygon coordinates are not numbers"),
+            },
+            _ => Err("polygon point is not an array of coordinates"),
+        })
+        .collect::<Result<Ring, _>>()?;
+    if ring.len() < 4 {
+        return Err("polygon ring has fewer than four points".into());
+    }
+    Ok(ring)
+}
+
+fn parse_polygon(value: &Value) -> Result<Vec<Ring>, Box<dyn Error>> {
+    let rings = value.as_array().ok_or("polygon is not an array of rings")?;
+    rings.iter().map(parse_ring).collect()
+}
+
+/// Collect the polygons of a GeoJSON object
+fn collect_polygons(value: &Value, polygons: &mut Vec<Vec<Ring>>) -> Result<(), Box<dyn Error>> {
+    let coordinates = || {
+        value
+            .get("coordinates")
+            .ok_or("geometry has no coordinates")
+    };
+    match value.get("type").and_then(Value::as_str) {
+        Some("Polygon") => polygons.push(parse_polygon(coordinates()?)?),
+        Some("MultiPolygon") => {
+            let parts = coordinates()?
+                .as_array()
+                .ok_or("multipolygon is not an array of polygons")?;
+            for part in parts {
+                polygons.push(parse_polygon(part)?);
+            }
+        }
+        Some("Feature") => collect_polygons(&value["geometry"], polygons)?,
+        Some("FeatureCollection") => {
+            let features = value["features"]
+                .as_array()
+                .ok_or("feature collection has no features")?;
+            for feature in features {
+                collect_polygons(feature, polygons)?;
+            }
+        }
+        Some(other) => return Err(format!("unsupported GeoJSON type {}", other).into()),
+        None => return Err("GeoJSON object has no type".into()),
+    }
+    Ok(())
+}
+
+/// Read the polygons in a GeoJSON file
+pub fn read_geojson(path: &Path) -> Result<Area, Box<dyn Error>> {
+    let value: Value = serde_json::from_reader(std::fs::File::open(path)?)?;
+    let mut polygons = vec![];
+    collect_polygons(&value, &mut polygons).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if polygons.is_empty() {
+        return Err(format!("{} has no polygons", path.display()).into());
+    }
+    Ok(Area::Polygons(polygons))
+}
+
+/// Read named regions from a GeoJSON FeatureCollection: one region per feature, named
+/// by its `name` property (or its `id`, if it has no name)
+pub fn read_regions(path: &Path) -> Result<Vec<(String, Area)>, Box<dyn Error>> {
+    let value: Value = serde_json::from_reader(std::fs::File::open(path)?)?;
+    let features = value["features"]
+        .as_array()
+        .ok_or_else(|| format!("{} is not a feature collection", path.display()))?;
+    let mut regions: Vec<(String, Area)> = vec![];
+    for (idx, feature) in features.iter().enumerate() {
+        let name = match (&feature["properties"]["name"], &feature["id"]) {
+            (Value::String(name), _) | (Value::Null, Value::String(name)) => name.clone(),
+            (Value::Null, Value::Number(id)) => id.to_string(),
+            _ => return Err(format!("{}: feature {} has no name", path.display(), idx).into()),
+        };
+        // names are used as file names
+        if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+            return Err(format!(
+                "{}: {:?} can't be used as a file name",
+                path.display(),
+                name
+            )
+            .into());
+        }
+        if regions.iter().any(|(other, _)| *other == name) {
+            return Err(
+                format!("{}: more than one region is named {}", path.display(), name).into(),
+            );
+        }
+        let mut polygons = vec![];
+        collect_polygons(feature, &mut polygons)
+            .map_err(|e| format!("{}: {}: {}", path.display(), name, e))?;
+        if polygons.is_empty() {
+            return Err(format!("{}: {} has no polygons", path.display(), name).into());
+        }
+        regions.push((name, Area::Polygons(polygons)));
+    }
+    if regions.is_empty() {
+        return Err(format!("{} has no features", path.display()).into());
+    }
+    Ok(regions)
+}
+
+/// Whether a point is inside a ring, by counting the ring's edges which a ray from the
+/// point crosses
+pub fn ring_contains(ring: &Ring, (lon, lat): (f64, f64)) -> bool {
+    let mut inside = false;
+    for edge in ring.windows(2) {
+        let ((x1, y1), (x2, y2)) = (edge[0], edge[1]);
+        if (y1 > lat) != (y2 > lat) && lon < x1 + (lat - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Simplify polygons (each an exterior ring followed by its holes) with
+/// osmx::geometry::simplify, leaving out the rings which are simplified to fewer than
+/// four points, and the polygons whose exterior rings are
+pub fn simplify_polygons(polygons: Vec<Vec<Ring>>, tolerance: f64) -> Vec<Vec<Ring>> {
+    polygons
+        .into_iter()
+        .filter_map(|rings| {
+            let mut rings = rings
+                .iter()
+                .map(|ring| osmx::geometry::simplify(ring, tolerance));
+            let exterior = rings.next().filter(|ring| ring.len() >= 4)?;
+            let holes = rings.filter(|ring| ring.len() >= 4);
+            Some(std::iter::once(exterior).chain(holes).collect())
+        })
+        .collect()
+}
+
+impl Area {
+    /// The bounding box of the area, as west, south, east and north
+    pub fn bounds(&self) -> (f64, f64, f64, f64) {
+        match self {
+            Area::BoundingBox(west, south, east, north) => (*west, *south, *east, *north),
+            Area::Polygons(polygons) => polygons
+                .iter()
+                .flat_map(|rings| rings.first())
+                .flatten()
+                .fold(
+                    (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+                    |(west, south, east, north), &(lon, lat)| {
+                        (west.min(lon), south.min(lat), east.max(lon), north.max(lat))
+                    },
+                ),
+        }
+    }
+
+    /// Whether a point is in the area. A point is in a polygon if it is inside an odd
+    /// number of its rings, so holes are excluded.
+    pub fn contains(&self, lon: f64, lat: f64) -> bool {
+        match self {
+            Area::BoundingBox(west, south, east, north) => {
+                *west <= lon && lon <= *east && *south <= lat && lat <= *north
+            }
+            Area::Polygons(polygons) => polygons.iter().any(|rings| {
+                rings
+                    .iter()
+                    .filter(|ring| ring_contains(ring, (lon, lat)))
+                    .count()
+                    % 2
+                    == 1
+            }),
+        }
+    }
+}
+
+/// Find the IDs of the elements of the given type within the area
+pub fn ids_in_area(
+    txn: &osmx::Transaction,
+    element_type: ElementType,
+    area: &Area,
+) -> Result<RoaringTreemap, Box<dyn Error>> {
+    let locations = txn.locations()?;
+    let (west, south, east, north) = area.bounds();
+    let region = osmx::Region::from_bbox(west, south, east, north);
+
+    // the spatial index may return nodes near the region, so check their locations
+    let mut node_ids = RoaringTreemap::new();
+    for node_id in txn.cell_nodes()?.find_in_region(&region) {
+        if let Some(loc) = locations.get(node_id)? {
+            if area.contains(loc.lon(), loc.lat()) {
+                node_ids.insert(node_id);
+            }
+        }
+    }
+    if element_type == ElementType::Node {
+        return Ok(node_ids);
+    }
+
+    let node_ways = txn.node_ways()?;
+    let mut way_ids = RoaringTreemap::new();
+    for node_id in node_ids.iter() {
+        way_ids.extend(node_ways.get(node_id));
+    }
+    if element_type == ElementType::Way {
+        return Ok(way_ids);
+    }
+
+    let node_relations = txn.node_relations()?;
+    let way_relations = txn.way_relations()?;
+    let mut relation_ids = RoaringTreemap::new();
+    for node_id in node_ids.iter() {
+        relation_ids.extend(node_relations.get(node_id));
+    }
+    for way_id in way_ids.iter() {
+        relation_ids.extend(way_relations.get(way_id));
+    }
+    Ok(relation_ids)
+}
+
+/// Find the nodes within the area, and the elements related to them which the strategy
+/// adds
+pub fn closure_of_area(
+    txn: &osmx::Transaction,
+    area: &Area,
+    strategy: Strategy,
+) -> Result<ElementIds, Box<dyn Error>> {
+    let mut seeds = ElementIds::new();
+    seeds.nodes = ids_in_area(txn, ElementType::Node, area)?;
+    Ok(txn.closure(&seeds, strategy.closure_strategy())?)
+}
@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::Parser;
+use lmdb::Transaction;
+use osmx::ElementId;
+
+use crate::environment::{check_readers, open_env, with_writer_lock};
+use crate::expand::{insert_sorted_tuples, IDPair};
+use crate::progress::ProgressBars;
+use crate::sorter::Sorter;
+
+#[derive(Parser)]
+/// Rebuild the index tables of an OSMX database (cell_node, node_way, node_relation,
+/// way_relation and relation_relation) from its locations, ways and relations tables,
+/// replacing their current contents. Use this to repair a corrupted index.
+pub struct CliArgs {
+    /// Path to the .osmx file to reindex in place
+    file: PathBuf,
+    /// Before writing, clear reader slots left behind by processes which crashed, which
+    /// would otherwise keep old pages from being reused
+    #[arg(long)]
+    check_readers: bool,
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    with_writer_lock(&args.file, "reindex", || reindex(args))
+}
+
+fn reindex(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let tempdir = PathBuf::from(format!("{}-tmp", args.file.to_str().unwrap()));
+    std::fs::create_dir_all(&tempdir)?;
+
+    let mut cell_node = Sorter::new(&tempdir, "cell_node");
+    let mut node_way = Sorter::new(&tempdir, "node_way");
+    let mut node_relation = Sorter::new(&tempdir, "node_relation");
+    let mut way_relation = Sorter::new(&tempdir, "way_relation");
+    let mut relation_relation = Sorter::new(&tempdir, "relation_relation");
+
+    // the index entries are collected before the database is opened for writing, since
+    // LMDB environments must not be opened twice in one process
+    {
+        let db = osmx::Database::open(&args.file)?;
+        let txn = osmx::Transaction::begin(&db)?;
+
+        for (node_id, location) in txn.locations()?.iter() {
+            let latlng = s2::latlng::LatLng::from_degrees(location.lat(), location.lon());
+            let cell = s2::cellid::CellID::from(latlng).parent(osmx::CELL_INDEX_LEVEL);
+            cell_node.push(IDPair(cell.0, node_id));
+        }
+        eprintln!("read locations");
+
+        // each pair is only added once, even if a way refers to a node more than once
+        for (way_id, way) in txn.ways()?.iter() {
+            let mut node_ids: Vec<u64> = way.nodes().collect();
+            node_ids.sort_unstable();
+            node_ids.dedup();
+            for node_id in node_ids {
+                node_way.push(IDPair(node_id, way_id));
+            }
+        }
+        eprintln!("read ways");
+
+        for (relation_id, relation) in txn.relations()?.iter() {
+            let mut members: Vec<ElementId> = relation.members().map(|m| m.id()).collect();
+            members.sort_unstable_by_key(|member| match *member {
+                ElementId::Node(id) => (0, id),
+                ElementId::Way(id) => (1, id),
+                ElementId::Relation(id) => (2, id),
+            });
+            members.dedup();
+            for member in members {
+                match member {
+                    ElementId::Node(id) => node_relation.push(IDPair(id, relation_id)),
+                    ElementId::Way(id) => way_relation.push(IDPair(id, relation_id)),
+                    ElementId::Relation(id) => relation_relation.push(IDPair(id, relation_id)),
+                }
+            }
+        }
+        eprintln!("read relations");
+    }
+
+    let env = open_env(&args.file, false)?;
+    if args.check_readers {
+        let cleared = check_readers(&env)?;
+        eprintln!("cleared {} stale reader slots", cleared);
+    }
+    let mut tables = vec![];
+    for sorter in [
+        cell_node,
+        node_way,
+        node_relation,
+        way_relation,
+        relation_relation,
+    ] {
+        let table = env.open_db(Some(sorter.name()))?;
+        tables.push((sorter, table));
+    }
+
+    let mut txn = env.begin_rw_txn()?;
+    let bars = ProgressBars::new();
+    for (sorter, table) in tables {
+        txn.clear_db(table)?;
+        insert_sorted_tuples(sorter, &mut txn, table, Some(&bars));
+    }
+
+    txn.commit()?;
+
+    eprintln!("committed transaction.");
+
+    std::fs::remove_dir_all(&tempdir)?;
+
+    Ok(())
+}
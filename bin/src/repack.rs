@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use lmdb::{Cursor, Transaction};
+use osmx::build::encode_message;
+use osmx::interop::{ELEMENT_TABLES, INDEX_TABLES};
+use osmx::{KeyByteOrder, ValueCompression, ValueEncoding};
+
+use crate::environment::{environment_flags, table_names, with_writer_lock};
+
+// tables whose values are Cap'n Proto messages (and so depend on the value encoding)
+const MESSAGE_TABLES: &[&str] = &["nodes", "ways", "relations"];
+
+// history tables (which only exist in some databases), and the element tables whose
+// values they store versions of (and whose dictionaries they share)
+const HISTORY_TABLES: &[(&str, &str)] = &[
+    ("location_history", "locations"),
+    ("node_history", "nodes"),
+    ("way_history", "ways"),
+    ("relation_history", "relations"),
+];
+
+// index tables which only exist in some databases, and are copied verbatim
+const OPTIONAL_INDEX_TABLES: &[&str] = &[
+    "uid_element",
+    "changeset_element",
+    "tag_node",
+    "tag_way",
+    "tag_relation",
+    "cell_way",
+    "cell_relation",
+];
+
+/// Maximum size of a trained zstd dictionary
+const DICTIONARY_SIZE: usize = 112 * 1024;
+/// Total size of the values sampled from each table to train its dictionary
+const DICTIONARY_SAMPLE_SIZE: usize = 64 * 1024 * 1024;
+/// Only every Nth value of a table is used as a dictionary training sample
+const DICTIONARY_SAMPLE_INTERVAL: usize = 64;
+/// zstd compression level used when compressing values
+const COMPRESSION_LEVEL: i32 = 9;
+/// Upper bound on the decompressed size of a single value
+const MAX_VALUE_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Encoding {
+    Standard,
+    Packed,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Compression {
+    None,
+    Zstd,
+}
+
+#[derive(Parser)]
+/// Rewrite an OSMX database using a different element value encoding or compression
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Path of the .osmx file to create
+    output_file: PathBuf,
+    /// How to serialize the Cap'n Proto messages in the nodes, ways and relations tables
+    #[arg(long, value_enum, default_value = "packed")]
+    encoding: Encoding,
+    /// How to compress the values in the nodes, ways and relations tables
+    #[arg(long, value_enum, default_value = "none")]
+    compression: Compression,
+}
+
+/// The encoding and compression of the values in a message table
+pub struct Format<'a> {
+    pub encoding: ValueEncoding,
+    pub dictionary: Option<&'a [u8]>,
+}
+
+impl Format<'_> {
+    /// Decode a raw value into a standalone Cap'n Proto message
+    pub fn read(&self, raw_val: &[u8]) -> capnp::message::Builder<capnp::message::HeapAllocator> {
+        let decompressed;
+        let mut slice = match self.dictionary {
+            Some(dictionary) => {
+                decompressed = zstd::bulk::Decompressor::with_dictionary(dictionary)
+                    .unwrap()
+                    .decompress(raw_val, MAX_VALUE_SIZE)
+                    .unwrap();
+                &decompressed[..]
+            }
+            None => raw_val,
+        };
+
+        let options = osmx::DEFAULT_READER_OPTIONS;
+        let reader = match self.encoding {
+            ValueEncoding::Standard => capnp::serialize::read_message(&mut slice, options),
+            ValueEncoding::Packed => capnp::serialize_packed::read_message(&mut slice, options),
+        }
+        .unwrap();
+
+        let mut message = capnp::message::Builder::new_default();
+        message
+            .set_root(reader.get_root::<capnp::any_pointer::Reader>().unwrap())
+            .unwrap();
+        message
+    }
+}
+
+/// Train a zstd dictionary on a sample of the values in a message table, re-encoded
+/// using the target encoding
+fn train_dictionary(
+    txn: &lmdb::RoTransaction,
+    table: lmdb::Database,
+    from: &Format,
+    encoding: ValueEncoding,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut samples = vec![];
+    let mut total_size = 0;
+
+    let mut cursor = txn.open_ro_cursor(table)?;
+    for (_, raw_val) in cursor.iter().step_by(DICTIONARY_SAMPLE_INTERVAL) {
+        let sample = encode_message(&from.read(raw_val), encoding);
+        total_size += sample.len();
+        samples.push(sample);
+
+        if total_size >= DICTIONARY_SAMPLE_SIZE {
+            break;
+        }
+    }
+
+    // training fails if there are too few samples (e.g. for a small extract), in which
+    // case values are compressed without a dictionary
+    match zstd::dict::from_samples(&samples, DICTIONARY_SIZE) {
+        Ok(dictionary) => Ok(dictionary),
+        Err(e) => {
+            eprintln!("could not train dictionary ({}), continuing without one", e);
+            Ok(vec![])
+        }
+    }
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    with_writer_lock(&args.output_file, "repack", || repack(args))
+}
+
+fn repack(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let (from_encoding, from_compression) = {
+        let db = osmx::Database::open(&args.input_file)?;
+        (db.value_encoding(), db.value_compression())
+    };
+
+    let to_encoding = match args.encoding {
+        Encoding::Standard => ValueEncoding::Standard,
+        Encoding::Packed => ValueEncoding::Packed,
+    };
+    let to_compression = match args.compression {
+        Compression::None => ValueCompression::None,
+        Compression::Zstd => ValueCompression::Zstd,
+    };
+
+    let flags = lmdb::EnvironmentFlags::NO_READAHEAD | lmdb::EnvironmentFlags::NO_SYNC;
+
+    let input_env = lmdb::Environment::new()
+        .set_flags(
+            flags | environment_flags(args.input_file.as_ref()) | lmdb::EnvironmentFlags::READ_ONLY,
+        )
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
+        .open(args.input_file.as_ref())?;
+
+    let output_env = lmdb::Environment::new()
+        .set_flags(flags | lmdb::EnvironmentFlags::NO_SUB_DIR)
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
+        .open(args.output_file.as_ref())?;
+
+    let element_flags = lmdb::DatabaseFlags::INTEGER_KEY;
+    let index_flags = lmdb::DatabaseFlags::INTEGER_KEY
+        | lmdb::DatabaseFlags::INTEGER_DUP
+        | lmdb::DatabaseFlags::DUP_SORT
+        | lmdb::DatabaseFlags::DUP_FIXED;
+
+    // tables must be opened (or created) before the transactions begin, since opening a
+    // table uses a transaction of its own
+    let input_metadata = input_env.open_db(Some("metadata"))?;
+    let output_metadata = output_env.create_db(Some("metadata"), lmdb::DatabaseFlags::empty())?;
+
+    let mut tables = vec![];
+    for &name in ELEMENT_TABLES {
+        let input_db = input_env.open_db(Some(name))?;
+        let output_db = output_env.create_db(Some(name), element_flags)?;
+        tables.push((name, name, input_db, output_db, lmdb::WriteFlags::APPEND));
+    }
+    for &name in INDEX_TABLES {
+        let input_db = input_env.open_db(Some(name))?;
+        let output_db = output_env.create_db(Some(name), index_flags)?;
+        tables.push((
+            name,
+            name,
+            input_db,
+            output_db,
+            lmdb::WriteFlags::APPEND_DUP,
+        ));
+    }
+    for &(name, element_table) in HISTORY_TABLES {
+        match input_env.open_db(Some(name)) {
+            Ok(input_db) => {
+                let output_db = output_env.create_db(Some(name), lmdb::DatabaseFlags::empty())?;
+                tables.push((
+                    name,
+                    element_table,
+                    input_db,
+                    output_db,
+                    lmdb::WriteFlags::APPEND,
+                ));
+            }
+            Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    // the uid_element, changeset_element, tag and way and relation spatial indexes only
+    // exist in databases created with those indexes
+    for &name in OPTIONAL_INDEX_TABLES {
+        match input_env.open_db(Some(name)) {
+            Ok(input_db) => {
+                let output_db = output_env.create_db(Some(name), index_flags)?;
+                tables.push((
+                    name,
+                    name,
+                    input_db,
+                    output_db,
+                    lmdb::WriteFlags::APPEND_DUP,
+                ));
+            }
+            Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    // the changesets table only exists in databases created with metadata, and the
+    // way_bbox and geometries tables in those created with them. Their values don't depend
+    // on the value format (changesets are always stored uncompressed, using the standard
+    // encoding), so they are copied verbatim.
+    for name in ["changesets", "way_bbox", "geometries"] {
+        match input_env.open_db(Some(name)) {
+            Ok(input_db) => {
+                let output_db = output_env.create_db(Some(name), element_flags)?;
+                tables.push((name, name, input_db, output_db, lmdb::WriteFlags::APPEND));
+            }
+            Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    // as is the street_names index, which is keyed by strings
+    match input_env.open_db(Some("street_names")) {
+        Ok(input_db) => {
+            let output_db =
+                output_env.create_db(Some("street_names"), lmdb::DatabaseFlags::empty())?;
+            tables.push((
+                "street_names",
+                "street_names",
+                input_db,
+                output_db,
+                lmdb::WriteFlags::APPEND,
+            ));
+        }
+        Err(lmdb::Error::NotFound) => (),
+        Err(e) => return Err(e.into()),
+    }
+    // and so are the tables created by applications
+    let extension_tables: Vec<String> = table_names(&input_env)?
+        .into_iter()
+        .filter(|name| name.starts_with(osmx::EXTENSION_TABLE_PREFIX))
+        .collect();
+    for name in extension_tables.iter() {
+        let input_db = input_env.open_db(Some(name))?;
+        let output_db = output_env.create_db(Some(name), lmdb::DatabaseFlags::empty())?;
+        tables.push((name, name, input_db, output_db, lmdb::WriteFlags::APPEND));
+    }
+
+    // the strings and tag_sets tables only exist in databases created with string
+    // interning or shared tag sets
+    let mut side_tables = vec![];
+    for name in ["strings", "tag_sets"] {
+        match input_env.open_db(Some(name)) {
+            Ok(input_db) => side_tables.push((
+                name,
+                input_db,
+                output_env.create_db(Some(name), element_flags)?,
+            )),
+            Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let input_txn = input_env.begin_ro_txn()?;
+    let mut output_txn = output_env.begin_rw_txn()?;
+
+    // copy the metadata table (except for any old dictionaries, and the input's writer
+    // lock), then record the new format
+    let mut input_dictionaries: HashMap<&str, &[u8]> = HashMap::new();
+    let mut cursor = input_txn.open_ro_cursor(input_metadata)?;
+    for (raw_key, raw_val) in cursor.iter() {
+        if raw_key == osmx::WRITER_LOCK_KEY.as_bytes() {
+            continue;
+        }
+        let dictionary_table = MESSAGE_TABLES
+            .iter()
+            .find(|table| raw_key == ValueCompression::dictionary_key(table).as_bytes());
+
+        match dictionary_table {
+            Some(table) => {
+                input_dictionaries.insert(table, raw_val);
+            }
+            None => output_txn.put(
+                output_metadata,
+                &raw_key,
+                &raw_val,
+                lmdb::WriteFlags::empty(),
+            )?,
+        }
+    }
+    drop(cursor);
+
+    output_txn.put(
+        output_metadata,
+        &osmx::FORMAT_VERSION_KEY.as_bytes(),
+        &osmx::FORMAT_VERSION.to_le_bytes(),
+        lmdb::WriteFlags::empty(),
+    )?;
+    // keys are copied verbatim, and Database::open has checked that they are in this
+    // host's byte order; record it for databases created before it was tracked
+    output_txn.put(
+        output_metadata,
+        &KeyByteOrder::METADATA_KEY.as_bytes(),
+        &KeyByteOrder::NATIVE.as_str().as_bytes(),
+        lmdb::WriteFlags::empty(),
+    )?;
+    output_txn.put(
+        output_metadata,
+        &ValueEncoding::METADATA_KEY.as_bytes(),
+        &to_encoding.as_str().as_bytes(),
+        lmdb::WriteFlags::empty(),
+    )?;
+    output_txn.put(
+        output_metadata,
+        &ValueCompression::METADATA_KEY.as_bytes(),
+        &to_compression.as_str().as_bytes(),
+        lmdb::WriteFlags::empty(),
+    )?;
+
+    // dictionaries trained for the output, by element table
+    let mut output_dictionaries: HashMap<&str, Vec<u8>> = HashMap::new();
+
+    for (name, element_table, input_db, output_db, write_flags) in tables {
+        // values in non-message tables, and message tables whose format is not changing
+        // (uncompressed, with the same encoding), are copied verbatim
+        let unchanged = from_encoding == to_encoding
+            && from_compression == ValueCompression::None
+            && to_compression == ValueCompression::None;
+
+        if !MESSAGE_TABLES.contains(&element_table) || unchanged {
+            let mut cursor = input_txn.open_ro_cursor(input_db)?;
+            for (raw_key, raw_val) in cursor.iter() {
+                output_txn.put(output_db, &raw_key, &raw_val, write_flags)?;
+            }
+            eprintln!("copied {}", name);
+            continue;
+        }
+
+        let from = Format {
+            encoding: from_encoding,
+            dictionary: input_dictionaries.get(element_table).copied(),
+        };
+
+        let mut compressor = match to_compression {
+            ValueCompression::Zstd => {
+                // history tables share the dictionary of their element table, which is
+                // always copied first
+                if !output_dictionaries.contains_key(element_table) {
+                    let dictionary = train_dictionary(&input_txn, input_db, &from, to_encoding)?;
+                    output_txn.put(
+                        output_metadata,
+                        &ValueCompression::dictionary_key(element_table).as_bytes(),
+                        &dictionary,
+                        lmdb::WriteFlags::empty(),
+                    )?;
+                    eprintln!("trained dictionary for {}", element_table);
+                    output_dictionaries.insert(element_table, dictionary);
+                }
+                Some(zstd::bulk::Compressor::with_dictionary(
+                    COMPRESSION_LEVEL,
+                    &output_dictionaries[element_table],
+                )?)
+            }
+            ValueCompression::None => None,
+        };
+
+        let mut cursor = input_txn.open_ro_cursor(input_db)?;
+        for (raw_key, raw_val) in cursor.iter() {
+            let val = encode_message(&from.read(raw_val), to_encoding);
+            let val = match compressor.as_mut() {
+                Some(compressor) => compressor.compress(&val)?,
+                None => val,
+            };
+            output_txn.put(output_db, &raw_key, &val, write_flags)?;
+        }
+
+        eprintln!("copied {}", name);
+    }
+
+    // copy the strings and tag_sets tables, if the input database has them
+    for (name, input_db, output_db) in side_tables {
+        let mut cursor = input_txn.open_ro_cursor(input_db)?;
+        for (raw_key, raw_val) in cursor.iter() {
+            output_txn.put(output_db, &raw_key, &raw_val, lmdb::WriteFlags::APPEND)?;
+        }
+        eprintln!("copied {}", name);
+    }
+
+    output_txn.commit()?;
+
+    eprintln!("committed transaction.");
+
+    Ok(())
+}
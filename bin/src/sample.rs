@@ -0,0 +1,143 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+
+use crate::ids::ElementType;
+
+/// Number of random draws made per requested element before giving up, when sampling
+/// without an ID bitmap (the table may have fewer elements than requested)
+const MAX_DRAWS_PER_ELEMENT: usize = 100;
+
+#[derive(Parser)]
+/// Print a random sample of the elements of one type in an OSMX database, one per line
+/// with the element's ID followed by its tags (as tab-separated key=value pairs).
+///
+/// If the database stores ID bitmaps (see `expand --id-bitmaps`), the sample is uniform.
+/// Otherwise, elements are found by seeking to random IDs between the smallest and
+/// largest in the table, which favors elements that follow gaps in the ID space.
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Type of the elements to sample
+    #[arg(long = "type", value_enum)]
+    element_type: ElementType,
+    /// Number of elements to sample
+    #[arg(short = 'n', long, default_value_t = 10)]
+    count: usize,
+    /// Seed for the random number generator, to make the sample reproducible (default: the
+    /// current time)
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+/// A small, fast pseudorandom number generator (SplitMix64)
+pub struct Rng(pub u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in 0..n (n must be nonzero)
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let db = osmx::Database::open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+    let locations = txn.locations()?;
+    let nodes = txn.nodes()?;
+    let ways = txn.ways()?;
+    let relations = txn.relations()?;
+
+    let seed = match args.seed {
+        Some(seed) => seed,
+        None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64,
+    };
+    let mut rng = Rng(seed);
+
+    let bitmap = match args.element_type {
+        ElementType::Node => txn.node_id_bitmap()?,
+        ElementType::Way => txn.way_id_bitmap()?,
+        ElementType::Relation => txn.relation_id_bitmap()?,
+    };
+
+    let mut sample = BTreeSet::new();
+    match bitmap {
+        Some(bitmap) => {
+            // pick distinct ranks, and look up the ID with each rank
+            let count = (args.count as u64).min(bitmap.len());
+            let mut ranks = BTreeSet::new();
+            while (ranks.len() as u64) < count {
+                ranks.insert(rng.below(bitmap.len()));
+            }
+            sample.extend(ranks.into_iter().filter_map(|rank| bitmap.select(rank)));
+        }
+        None => {
+            eprintln!("no ID bitmap in database, so the sample won't be uniform");
+
+            // all nodes are in the locations table, but only tagged nodes in the nodes table
+            let (first, last, next_id_from): (_, _, Box<dyn Fn(u64) -> Option<u64>>) =
+                match args.element_type {
+                    ElementType::Node => (
+                        locations.next_id_from(0),
+                        locations.last_id(),
+                        Box::new(|id| locations.next_id_from(id)),
+                    ),
+                    ElementType::Way => (
+                        ways.next_id_from(0),
+                        ways.last_id(),
+                        Box::new(|id| ways.next_id_from(id)),
+                    ),
+                    ElementType::Relation => (
+                        relations.next_id_from(0),
+                        relations.last_id(),
+                        Box::new(|id| relations.next_id_from(id)),
+                    ),
+                };
+
+            if let (Some(first), Some(last)) = (first, last) {
+                let span = (last - first).saturating_add(1);
+                for _ in 0..args.count * MAX_DRAWS_PER_ELEMENT {
+                    if sample.len() >= args.count {
+                        break;
+                    }
+                    let id = first + rng.below(span);
+                    sample.insert(next_id_from(id).unwrap_or(last));
+                }
+            }
+        }
+    }
+
+    for id in sample {
+        let tags: Vec<String> = match args.element_type {
+            ElementType::Node => nodes
+                .get(id)?
+                .map(|n| n.tags().map(|(k, v)| format!("{}={}", k, v)).collect()),
+            ElementType::Way => ways
+                .get(id)?
+                .map(|w| w.tags().map(|(k, v)| format!("{}={}", k, v)).collect()),
+            ElementType::Relation => relations
+                .get(id)?
+                .map(|r| r.tags().map(|(k, v)| format!("{}={}", k, v)).collect()),
+        }
+        .unwrap_or_default();
+
+        if tags.is_empty() {
+            println!("{}", id);
+        } else {
+            println!("{}\t{}", id, tags.join("\t"));
+        }
+    }
+
+    Ok(())
+}
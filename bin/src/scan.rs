@@ -0,0 +1,44 @@
+use std::error::Error;
+use std::path::Path;
+
+use clap::Args;
+
+#[derive(Args)]
+pub struct ScanArgs {
+    /// Let the operating system read ahead of the scan (see osmx::OpenOptions). Much
+    /// faster on spinning disks and network storage when the database isn't cached.
+    #[arg(long)]
+    read_ahead: bool,
+
+    /// Skip elements whose values can't be decoded (reporting how many there were)
+    /// instead of stopping at the first one, for reading possibly damaged files.
+    #[arg(long)]
+    lenient: bool,
+}
+
+impl ScanArgs {
+    /// Open a database to scan, with read-ahead and lenient decoding enabled if they
+    /// were asked for
+    pub fn open(&self, path: &Path) -> Result<osmx::Database, Box<dyn Error>> {
+        let options = osmx::OpenOptions {
+            read_ahead: self.read_ahead,
+            ..Default::default()
+        };
+        let mut db = osmx::Database::open_with(path, &options)?;
+        if self.lenient {
+            db.set_decode_mode(osmx::DecodeMode::Lenient);
+        }
+        Ok(db)
+    }
+
+    /// Print a warning if the scan skipped any values
+    pub fn report_skipped(&self, db: &osmx::Database) {
+        let skipped = db.skipped_values();
+        if skipped > 0 {
+            eprintln!(
+                "warning: skipped {} elements whose values couldn't be decoded",
+                skipped
+            );
+        }
+    }
+}
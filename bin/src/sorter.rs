@@ -97,9 +97,11 @@ impl<T: Clone + Ord + DeserializeOwned> SortReader<T> {
                 readers.push(BufReader::new(File::open(filename).unwrap()));
             }
 
+            // segments may be empty (e.g. if nothing was ever pushed to the sorter)
             for ridx in 0..readers.len() {
-                let val = bincode::deserialize_from(&mut readers[ridx]).unwrap();
-                pqueue.push(Reverse((val, ridx)));
+                if let Ok(val) = bincode::deserialize_from(&mut readers[ridx]) {
+                    pqueue.push(Reverse((val, ridx)));
+                }
             }
 
             let mut prev: Option<T> = None;
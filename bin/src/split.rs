@@ -0,0 +1,233 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use lmdb::Transaction;
+use roaring::RoaringTreemap;
+
+use crate::checksum::Checksum;
+use crate::download::{remote_url, Download};
+use crate::expand::{negative_id, read_element, write_header_metadata, Output, Record, Tables};
+use crate::progress::ProgressBars;
+use crate::region::{read_regions, Area};
+use crate::trace;
+use osmx::build::ElementType;
+
+#[derive(Parser)]
+/// Build an OSMX database for each of a set of regions, in a single pass over an OSM PBF
+/// file (such as the planet), rather than one pass per region.
+///
+/// Each region gets the nodes located in it, the ways with a node in it, and the
+/// relations with a node or way member in it, so an element is written to every region
+/// it intersects. As with any regional extract, the databases aren't referentially
+/// complete: ways and relations which cross a region's boundary are included, but their
+/// nodes and members outside of it aren't.
+///
+/// The databases are written like `expand` writes them, without any of its optional
+/// tables.
+pub struct CliArgs {
+    /// Path of an .osm.pbf file to read, or an http(s) URL to stream it from
+    input_file: PathBuf,
+    /// GeoJSON FeatureCollection of the regions, each a Polygon or MultiPolygon feature
+    /// named by its `name` property (or its `id`). The database of each region is named
+    /// after it, e.g. `germany.osmx`.
+    #[arg(long)]
+    regions: PathBuf,
+    /// Directory to create the databases in (created if it doesn't exist)
+    #[arg(long)]
+    out_dir: PathBuf,
+    /// Store bitmaps of all node, way and relation IDs in the metadata table
+    #[arg(long)]
+    id_bitmaps: bool,
+    /// Store element values using Cap'n Proto packed encoding (smaller, slightly slower to read)
+    #[arg(long)]
+    packed: bool,
+}
+
+/// A region's database, as it's being written
+struct RegionOutput<'env> {
+    name: String,
+    path: PathBuf,
+    tempdir: PathBuf,
+    area: Area,
+    /// The bounding box of the area, to skip the point-in-polygon test for most nodes
+    bounds: (f64, f64, f64, f64),
+    tables: Tables,
+    txn: lmdb::RwTransaction<'env>,
+    output: Output,
+    /// The nodes and ways written to the region so far, to decide which ways and
+    /// relations refer to it
+    node_ids: RoaringTreemap,
+    way_ids: RoaringTreemap,
+}
+
+impl RegionOutput<'_> {
+    fn contains(&self, record: &Record) -> bool {
+        match record.element_type {
+            ElementType::Node => match record.coordinates {
+                Some((lon, lat)) => {
+                    let (lon, lat) = (lon as f64 / 1e7, lat as f64 / 1e7);
+                    let (west, south, east, north) = self.bounds;
+                    west <= lon
+                        && lon <= east
+                        && south <= lat
+                        && lat <= north
+                        && self.area.contains(lon, lat)
+                }
+                None => false,
+            },
+            ElementType::Way | ElementType::Relation => {
+                record.refs.iter().any(|&(ref_type, id)| match ref_type {
+                    ElementType::Node => self.node_ids.contains(id),
+                    ElementType::Way => self.way_ids.contains(id),
+                    ElementType::Relation => false,
+                })
+            }
+        }
+    }
+
+    fn write(&mut self, record: &Record) {
+        match record.element_type {
+            ElementType::Node => self.node_ids.insert(record.id),
+            ElementType::Way => self.way_ids.insert(record.id),
+            ElementType::Relation => false,
+        };
+        self.output.write_current(&mut self.txn, record);
+    }
+}
+
+fn open_env(path: &Path) -> Result<lmdb::Environment, lmdb::Error> {
+    lmdb::Environment::new()
+        .set_flags(
+            lmdb::EnvironmentFlags::NO_SUB_DIR
+                | lmdb::EnvironmentFlags::NO_READAHEAD
+                | lmdb::EnvironmentFlags::NO_SYNC,
+        )
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
+        .open(path)
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let regions = read_regions(&args.regions)?;
+    std::fs::create_dir_all(&args.out_dir)?;
+    let paths: Vec<PathBuf> = regions
+        .iter()
+        .map(|(name, _)| args.out_dir.join(format!("{}.osmx", name)))
+        .collect();
+    if let Some(path) = paths.iter().find(|path| path.exists()) {
+        return Err(format!("{} already exists", path.display()).into());
+    }
+
+    // every region's environment is opened before any of their transactions begin, since
+    // the transactions borrow them
+    let envs = paths
+        .iter()
+        .map(|path| open_env(path))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let input: Box<dyn Read + Send> = match remote_url(&args.input_file) {
+        Some(url) => Box::new(Download::open(url)?),
+        None => Box::new(File::open(&args.input_file)?),
+    };
+    let checksum = Checksum::default();
+    let mut blobs = osmpbf::BlobReader::new(BufReader::new(checksum.reader(input)));
+    let header = match blobs.next().transpose()? {
+        Some(blob) if blob.get_type() == osmpbf::BlobType::OsmHeader => blob.to_headerblock()?,
+        _ => return Err(format!("{} has no header block", args.input_file.display()).into()),
+    };
+    let encoding = if args.packed {
+        osmx::ValueEncoding::Packed
+    } else {
+        osmx::ValueEncoding::Standard
+    };
+
+    let mut outputs = vec![];
+    for (((name, area), path), env) in regions.into_iter().zip(paths).zip(&envs) {
+        let tables = Tables::create(env)?;
+        let mut txn = env.begin_rw_txn()?;
+        write_header_metadata(
+            &mut txn,
+            tables.metadata,
+            &header,
+            &args.input_file,
+            encoding,
+        )?;
+        let tempdir = PathBuf::from(format!("{}-tmp", path.display()));
+        std::fs::create_dir_all(&tempdir)?;
+        outputs.push(RegionOutput {
+            name,
+            bounds: area.bounds(),
+            area,
+            output: Output::new(&tables, &tempdir, args.id_bitmaps),
+            tables,
+            txn,
+            path,
+            tempdir,
+            node_ids: RoaringTreemap::new(),
+            way_ids: RoaringTreemap::new(),
+        });
+    }
+
+    // read .osm.pbf file and write each element to the regions it's in
+
+    let read_phase = trace::phase("read");
+    let mut negative = None;
+    let mut handle = |elem: osmpbf::Element| {
+        // the rest of the file is skipped once an element can't be imported
+        if negative.is_some() {
+            return;
+        }
+        negative = negative_id(&elem);
+        if negative.is_some() {
+            return;
+        }
+        let record = read_element(&elem, None, None, encoding, false, false);
+        for region in outputs.iter_mut() {
+            if region.contains(&record) {
+                region.write(&record);
+            }
+        }
+    };
+    for blob in blobs {
+        if let osmpbf::BlobDecode::OsmData(block) = blob?.decode()? {
+            block.for_each_element(&mut handle);
+        }
+    }
+    if let Some(description) = negative {
+        return Err(format!(
+            "{} has a negative ID, which OSMX databases can't store",
+            description
+        )
+        .into());
+    }
+    read_phase.end();
+    eprintln!("done reading {}", args.input_file.display());
+
+    let bars = ProgressBars::new();
+    for mut region in outputs {
+        region.txn.put(
+            region.tables.metadata,
+            &osmx::SOURCE_SHA256_KEY.as_bytes(),
+            &checksum.hex().as_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+        let counts = region
+            .output
+            .finish(&mut region.txn, &region.tables, Some(&bars))?;
+        region.txn.commit()?;
+        std::fs::remove_dir_all(&region.tempdir)?;
+        eprintln!(
+            "wrote {} ({}): {} nodes, {} ways, {} relations",
+            region.path.display(),
+            region.name,
+            counts.nodes,
+            counts.ways,
+            counts.relations
+        );
+    }
+
+    Ok(())
+}
@@ -4,6 +4,9 @@ use std::path::PathBuf;
 use clap::Parser;
 use lmdb::Transaction;
 
+use crate::environment::environment_flags;
+use crate::scan::ScanArgs;
+
 const TABLE_NAMES: &[&str] = &[
     "locations",
     "nodes",
@@ -16,21 +19,48 @@ const TABLE_NAMES: &[&str] = &[
     "relation_relation",
 ];
 
+// tables which only exist in databases created with some options
+const OPTIONAL_TABLE_NAMES: &[&str] = &[
+    "strings",
+    "tag_sets",
+    "location_history",
+    "node_history",
+    "way_history",
+    "relation_history",
+    "changesets",
+    "uid_element",
+    "changeset_element",
+    "tag_node",
+    "tag_way",
+    "tag_relation",
+    "cell_way",
+    "cell_relation",
+];
+
 #[derive(Parser)]
 /// Print stats about the contents of an OSMX database
 pub struct CliArgs {
     /// Path to the .osmx file to read
     input_file: PathBuf,
+
+    /// Also read every element and print statistics about their contents, such as how
+    /// many nodes are tagged, the average number of nodes per way, and the largest
+    /// relation. Takes about as long as exporting the database.
+    #[arg(long)]
+    deep: bool,
+
+    #[command(flatten)]
+    scan: ScanArgs,
 }
 
 pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
     let env = lmdb::Environment::new()
         .set_flags(
-            lmdb::EnvironmentFlags::NO_SUB_DIR
+            environment_flags(args.input_file.as_ref())
                 | lmdb::EnvironmentFlags::NO_READAHEAD
                 | lmdb::EnvironmentFlags::NO_SYNC,
         )
-        .set_max_dbs(10)
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
         .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
         .open(args.input_file.as_ref())?;
 
@@ -38,8 +68,12 @@ pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
         "{:<18} {:>10} {:>12} {:>12} {:>9} {:>9} {:>9}",
         "NAME", "ENTRIES", "SIZE (KiB)", "TOTAL PAGES", "BRANCH", "LEAF", "OVERFLOW"
     );
-    for name in TABLE_NAMES {
-        let db = env.open_db(Some(name))?;
+    for name in TABLE_NAMES.iter().chain(OPTIONAL_TABLE_NAMES) {
+        let db = match env.open_db(Some(name)) {
+            Ok(db) => db,
+            Err(lmdb::Error::NotFound) if OPTIONAL_TABLE_NAMES.contains(name) => continue,
+            Err(e) => return Err(e.into()),
+        };
         let txn = env.begin_ro_txn()?;
 
         let mut stat = lmdb_sys::MDB_stat {
@@ -70,5 +104,274 @@ pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
         );
     }
 
+    println!();
+    print_counts(&env)?;
+    print_source(&env)?;
+    print_writer_lock(&env)?;
+    print_usage(&env)?;
+
+    if args.deep {
+        // the environment can't be opened twice in one process
+        drop(env);
+        println!();
+        let db = args.scan.open(&args.input_file)?;
+        print_content_stats(&db)?;
+        args.scan.report_skipped(&db);
+    }
+
+    Ok(())
+}
+
+/// Statistics about the elements of a database, gathered by reading all of them
+#[derive(Default)]
+struct ContentStats {
+    located_nodes: u64,
+    tagged_nodes: u64,
+    node_tags: u64,
+    ways: u64,
+    way_tags: u64,
+    way_nodes: u64,
+    max_way_nodes: u64,
+    closed_ways: u64,
+    relations: u64,
+    relation_tags: u64,
+    relation_members: u64,
+    /// The ID and number of members of the relation with the most members
+    largest_relation: Option<(u64, u64)>,
+}
+
+/// The mean of a total over a count, or 0 if the count is 0
+fn mean(total: u64, count: u64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
+
+/// Read every element of the database and print statistics about their contents
+fn print_content_stats(db: &osmx::Database) -> Result<(), Box<dyn Error>> {
+    let txn = osmx::Transaction::begin(db)?;
+    let mut stats = ContentStats {
+        located_nodes: txn.locations()?.len(),
+        ..Default::default()
+    };
+
+    // the Nodes table also holds untagged nodes which have metadata
+    txn.nodes()?.for_each_ref(|_, node| {
+        let tags = node.tags().count() as u64;
+        if tags > 0 {
+            stats.tagged_nodes += 1;
+            stats.node_tags += tags;
+        }
+    })?;
+    txn.ways()?.for_each_ref(|_, way| {
+        let nodes = way.nodes().count() as u64;
+        stats.ways += 1;
+        stats.way_tags += way.tags().count() as u64;
+        stats.way_nodes += nodes;
+        stats.max_way_nodes = stats.max_way_nodes.max(nodes);
+        if nodes > 1 && way.is_closed() {
+            stats.closed_ways += 1;
+        }
+    })?;
+    txn.relations()?.for_each_ref(|id, relation| {
+        let members = relation.members().count() as u64;
+        stats.relations += 1;
+        stats.relation_tags += relation.tags().count() as u64;
+        stats.relation_members += members;
+        if stats
+            .largest_relation
+            .is_none_or(|(_, most)| members > most)
+        {
+            stats.largest_relation = Some((id, members));
+        }
+    })?;
+
+    println!("{:<18} {:>14}", "LOCATED NODES", stats.located_nodes);
+    println!("{:<18} {:>14}", "TAGGED NODES", stats.tagged_nodes);
+    println!(
+        "{:<18} {:>14}",
+        "UNTAGGED NODES",
+        stats.located_nodes.saturating_sub(stats.tagged_nodes)
+    );
+    println!(
+        "{:<18} {:>14.2}",
+        "TAGS PER NODE",
+        mean(stats.node_tags, stats.tagged_nodes)
+    );
+    println!();
+    println!("{:<18} {:>14}", "WAYS", stats.ways);
+    println!(
+        "{:<18} {:>14.2}",
+        "TAGS PER WAY",
+        mean(stats.way_tags, stats.ways)
+    );
+    println!(
+        "{:<18} {:>14.2}",
+        "NODES PER WAY",
+        mean(stats.way_nodes, stats.ways)
+    );
+    println!("{:<18} {:>14}", "MOST WAY NODES", stats.max_way_nodes);
+    println!(
+        "{:<18} {:>13.2}%",
+        "CLOSED WAYS",
+        mean(stats.closed_ways * 100, stats.ways)
+    );
+    println!();
+    println!("{:<18} {:>14}", "RELATIONS", stats.relations);
+    println!(
+        "{:<18} {:>14.2}",
+        "TAGS PER RELATION",
+        mean(stats.relation_tags, stats.relations)
+    );
+    println!(
+        "{:<18} {:>14.2}",
+        "MEMBERS PER REL.",
+        mean(stats.relation_members, stats.relations)
+    );
+    if let Some((id, members)) = stats.largest_relation {
+        println!(
+            "{:<18} {:>14} ({} members)",
+            "LARGEST RELATION", id, members
+        );
+    }
+    Ok(())
+}
+
+/// Print the number of current elements of each type recorded in the metadata table,
+/// if the database records them. Unlike the entry counts above, the node count includes
+/// untagged nodes.
+fn print_counts(env: &lmdb::Environment) -> Result<(), Box<dyn Error>> {
+    let metadata = env.open_db(Some("metadata"))?;
+    let txn = env.begin_ro_txn()?;
+    let raw_val = match txn.get(metadata, &osmx::COUNTS_KEY.as_bytes()) {
+        Ok(raw_val) => raw_val,
+        Err(lmdb::Error::NotFound) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let counts = osmx::ElementCounts::from_bytes(raw_val)
+        .ok_or_else(|| format!("{} metadata has incorrect length", osmx::COUNTS_KEY))?;
+    println!("{:<18} {:>14}", "NODES", counts.nodes);
+    println!("{:<18} {:>14}", "WAYS", counts.ways);
+    println!("{:<18} {:>14}", "RELATIONS", counts.relations);
+    println!();
+    Ok(())
+}
+
+/// Print the replication state the data is current to and the file it was imported
+/// from, if the database records them
+fn print_source(env: &lmdb::Environment) -> Result<(), Box<dyn Error>> {
+    let metadata = env.open_db(Some("metadata"))?;
+    let txn = env.begin_ro_txn()?;
+    let mut printed = false;
+    for (label, key) in [
+        ("REPLICATION TIME", osmx::interop::REPLICATION_TIMESTAMP_KEY),
+        (
+            "REPLICATION SEQ",
+            osmx::interop::REPLICATION_SEQUENCE_NUMBER_KEY,
+        ),
+        ("REPLICATION URL", osmx::interop::REPLICATION_BASE_URL_KEY),
+        ("IMPORT FILENAME", osmx::interop::IMPORT_FILENAME_KEY),
+    ] {
+        match txn.get(metadata, &key.as_bytes()) {
+            Ok(raw_val) => {
+                println!("{:<18} {}", label, String::from_utf8_lossy(raw_val));
+                printed = true;
+            }
+            Err(lmdb::Error::NotFound) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if printed {
+        println!();
+    }
+    Ok(())
+}
+
+/// Print which process holds the writer lock (see osmx::Database::try_exclusive_writer),
+/// if any
+fn print_writer_lock(env: &lmdb::Environment) -> Result<(), Box<dyn Error>> {
+    let metadata = env.open_db(Some("metadata"))?;
+    let txn = env.begin_ro_txn()?;
+    let raw_val = match txn.get(metadata, &osmx::WRITER_LOCK_KEY.as_bytes()) {
+        Ok(raw_val) => raw_val,
+        Err(lmdb::Error::NotFound) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    match osmx::WriterLock::from_bytes(raw_val) {
+        Some(lock) if lock.is_stale() => {
+            println!(
+                "{:<18} {} (stale: the process has exited)",
+                "WRITER LOCK", lock
+            )
+        }
+        Some(lock) => println!("{:<18} {}", "WRITER LOCK", lock),
+        None => println!("{:<18} (malformed)", "WRITER LOCK"),
+    }
+    println!();
     Ok(())
 }
+
+fn check(rc: std::os::raw::c_int) -> Result<(), lmdb::Error> {
+    match rc {
+        0 => Ok(()),
+        rc => Err(lmdb::Error::from_err_code(rc)),
+    }
+}
+
+/// Print how much of the memory map is in use, and how many pages are free for reuse.
+/// Writes fail with MDB_MAP_FULL once the last page reaches the end of the map and the
+/// freelist is empty. The map size is the larger of the size recorded in the file and
+/// the 50 GiB which osmx opens databases with.
+fn print_usage(env: &lmdb::Environment) -> Result<(), Box<dyn Error>> {
+    let mut info = lmdb_sys::MDB_envinfo {
+        me_mapaddr: std::ptr::null_mut(),
+        me_mapsize: 0,
+        me_last_pgno: 0,
+        me_last_txnid: 0,
+        me_maxreaders: 0,
+        me_numreaders: 0,
+    };
+    unsafe {
+        check(lmdb_sys::mdb_env_info(env.env(), &mut info))?;
+    }
+    let page_size = env.stat()?.page_size() as usize;
+    let used_pages = info.me_last_pgno + 1;
+    let map_pages = info.me_mapsize / page_size;
+
+    println!("{:<18} {:>14}", "MAP SIZE (KiB)", info.me_mapsize / 1024);
+    println!("{:<18} {:>14}", "LAST PAGE", info.me_last_pgno);
+    println!(
+        "{:<18} {:>13.2}%",
+        "MAP USED",
+        used_pages as f64 / map_pages as f64 * 100.0
+    );
+    println!("{:<18} {:>14}", "FREE PAGES", free_pages(env)?);
+
+    Ok(())
+}
+
+/// Count the pages on the freelist, which LMDB stores in its internal table 0. Each
+/// value of that table is a list of page numbers, prefixed by its length.
+fn free_pages(env: &lmdb::Environment) -> Result<usize, Box<dyn Error>> {
+    let txn = env.begin_ro_txn()?;
+    let mut count = 0;
+    unsafe {
+        let mut cursor: *mut lmdb_sys::MDB_cursor = std::ptr::null_mut();
+        check(lmdb_sys::mdb_cursor_open(txn.txn(), 0, &mut cursor))?;
+        let mut key = lmdb_sys::MDB_val {
+            mv_size: 0,
+            mv_data: std::ptr::null_mut(),
+        };
+        let mut data = lmdb_sys::MDB_val {
+            mv_size: 0,
+            mv_data: std::ptr::null_mut(),
+        };
+        while lmdb_sys::mdb_cursor_get(cursor, &mut key, &mut data, lmdb_sys::MDB_NEXT) == 0 {
+            count += std::ptr::read_unaligned(data.mv_data as *const usize);
+        }
+        lmdb_sys::mdb_cursor_close(cursor);
+    }
+    Ok(count)
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Maximum number of tag sets in the tag_sets table
+const MAX_TAG_SETS: usize = 1 << 20;
+/// Only every Nth data blob of the input is read when counting tag set frequencies
+const SAMPLE_INTERVAL: usize = 16;
+/// Tag sets must occur at least this many times in the sample to be shared
+const MIN_COUNT: u64 = 8;
+/// Tag sets with more tags are never shared (they are unlikely to be common)
+const MAX_TAGS: usize = 8;
+/// When the frequency table grows past this size, tag sets seen only once are dropped
+/// from it
+const PRUNE_THRESHOLD: usize = 4_000_000;
+
+/// A table of commonly occurring tag sets (like building=yes), which are stored once in
+/// the tag_sets table and referred to by hash from element values.
+pub struct TagSets {
+    sets: HashMap<u64, Vec<String>>,
+}
+
+impl TagSets {
+    /// Choose which tag sets to share by counting the tag sets of the elements in a
+    /// sample of the data blobs in the given .osm.pbf file.
+    pub fn from_sample(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut counts: HashMap<Vec<String>, u64> = HashMap::new();
+
+        let blobs = osmpbf::BlobReader::from_path(path)?
+            .map(|r| r.unwrap())
+            .filter(|blob| blob.get_type() == osmpbf::BlobType::OsmData)
+            .step_by(SAMPLE_INTERVAL);
+
+        for blob in blobs {
+            let block = blob.to_primitiveblock()?;
+            for elem in block.elements() {
+                let tags: Vec<&str> = match elem {
+                    osmpbf::Element::Node(node) => node.tags().flat_map(|(k, v)| [k, v]).collect(),
+                    osmpbf::Element::DenseNode(node) => {
+                        node.tags().flat_map(|(k, v)| [k, v]).collect()
+                    }
+                    osmpbf::Element::Way(way) => way.tags().flat_map(|(k, v)| [k, v]).collect(),
+                    osmpbf::Element::Relation(rel) => {
+                        rel.tags().flat_map(|(k, v)| [k, v]).collect()
+                    }
+                };
+                if tags.is_empty() || tags.len() > MAX_TAGS * 2 {
+                    continue;
+                }
+                let tags: Vec<String> = tags.into_iter().map(str::to_string).collect();
+                match counts.get_mut(&tags) {
+                    Some(n) => *n += 1,
+                    None => {
+                        if counts.len() >= PRUNE_THRESHOLD {
+                            counts.retain(|_, n| *n > 1);
+                        }
+                        counts.insert(tags, 1);
+                    }
+                }
+            }
+        }
+
+        let mut common: Vec<(Vec<String>, u64)> = counts
+            .into_iter()
+            .filter(|&(_, n)| n >= MIN_COUNT)
+            .collect();
+        common.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        common.truncate(MAX_TAG_SETS);
+
+        let mut sets = HashMap::new();
+        for (tags, _) in common {
+            let hash = osmx::tag_set_hash(&tags.iter().map(String::as_str).collect::<Vec<_>>());
+            // in the unlikely event that two tag sets have the same hash, only the more
+            // common one is shared
+            sets.entry(hash).or_insert(tags);
+        }
+        Ok(Self { sets })
+    }
+
+    /// Get the hash of a shared tag set, or None if it isn't shared.
+    pub fn get(&self, tags: &[&str]) -> Option<u64> {
+        if tags.is_empty() || tags.len() > MAX_TAGS * 2 {
+            return None;
+        }
+        let hash = osmx::tag_set_hash(tags);
+        let set = self.sets.get(&hash)?;
+        set.iter().eq(tags.iter().copied()).then_some(hash)
+    }
+
+    /// Returns the shared tag sets and their hashes, in hash order.
+    pub fn sets(&self) -> impl Iterator<Item = (u64, &[String])> {
+        let mut sets: Vec<(u64, &[String])> = self
+            .sets
+            .iter()
+            .map(|(&hash, tags)| (hash, tags.as_slice()))
+            .collect();
+        sets.sort_unstable_by_key(|&(hash, _)| hash);
+        sets.into_iter()
+    }
+}
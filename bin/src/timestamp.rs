@@ -0,0 +1,42 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::process::exit;
+
+use clap::Parser;
+
+/// Exit code used when the database doesn't record the requested replication state
+const EXIT_MISSING: i32 = 2;
+
+#[derive(Parser)]
+/// Print the replication timestamp of an OSMX database (as ISO 8601), optionally
+/// followed by the sequence number, separated by a space. Exits with status 2 if the
+/// database doesn't record them, or 1 on any other error.
+pub struct CliArgs {
+    /// Path to the .osmx file to read
+    input_file: PathBuf,
+    /// Also print the replication sequence number
+    #[arg(long)]
+    sequence_number: bool,
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let db = osmx::Database::open(&args.input_file)?;
+    let txn = osmx::Transaction::begin(&db)?;
+
+    let Some(timestamp) = txn.replication_timestamp()? else {
+        eprintln!("database has no replication timestamp");
+        exit(EXIT_MISSING);
+    };
+
+    if args.sequence_number {
+        let Some(sequence_number) = txn.replication_sequence_number()? else {
+            eprintln!("database has no replication sequence number");
+            exit(EXIT_MISSING);
+        };
+        println!("{} {}", timestamp, sequence_number);
+    } else {
+        println!("{}", timestamp);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Optional tracing output. With the `tracing` feature enabled, spans and events from the
+//! osmx library and the phases of long-running commands are logged to stderr, filtered
+//! by the RUST_LOG environment variable (which defaults to "info"). Without it, these
+//! helpers compile to nothing.
+
+/// Install a subscriber which logs spans and events to stderr, including the time taken
+/// by each span when it closes
+#[cfg(feature = "tracing")]
+pub fn init() {
+    use tracing_subscriber::fmt::format::FmtSpan;
+    use tracing_subscriber::EnvFilter;
+
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn init() {}
+
+/// A phase of a command (such as reading the input file or committing the transaction),
+/// which lasts until the Phase is dropped
+#[cfg(feature = "tracing")]
+pub struct Phase(#[allow(dead_code)] tracing::span::EnteredSpan);
+
+#[cfg(not(feature = "tracing"))]
+pub struct Phase;
+
+impl Phase {
+    /// End the phase before the end of the scope it was started in
+    pub fn end(self) {}
+}
+
+/// Start a phase. With the `tracing` feature, this enters an info-level span, which logs
+/// its duration when the phase ends.
+#[cfg(feature = "tracing")]
+pub fn phase(name: &str) -> Phase {
+    Phase(tracing::info_span!("phase", name).entered())
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn phase(_name: &str) -> Phase {
+    Phase
+}
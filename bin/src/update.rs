@@ -0,0 +1,591 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use flate2::read::MultiGzDecoder;
+use osmx::build::{ElementType, LocationBuilder, NodeBuilder, RelationBuilder, WayBuilder};
+use osmx::ElementId;
+use quick_xml::events::{BytesStart, Event};
+
+use crate::trace;
+
+#[derive(Parser)]
+/// Apply an osmChange file (such as a minutely or hourly replication diff) to an OSMX
+/// database in place, and record the replication state it brings the database up to.
+/// With --from-dir, the diffs after the database's sequence number in a local copy of a
+/// replication directory are applied in order instead. The indexes of the database's index plugins are updated too. Databases with history,
+/// metadata, compressed values, or the uid or changeset indexes can't be updated, and
+/// other extension tables aren't updated.
+pub struct CliArgs {
+    /// Path to the .osmx file to update
+    file: PathBuf,
+    /// Path to the osmChange file (.osc, or gzipped .osc.gz)
+    #[arg(required_unless_present = "from_dir")]
+    osc_file: Option<PathBuf>,
+    /// Sequence number of the diff, recorded as the database's replication sequence number
+    #[arg(required_unless_present = "from_dir")]
+    sequence_number: Option<u64>,
+    /// Timestamp of the diff's replication state (ISO 8601, e.g. 2024-08-13T00:00:00Z),
+    /// recorded as the database's replication timestamp
+    #[arg(required_unless_present = "from_dir")]
+    timestamp: Option<String>,
+    /// Apply the diffs in a directory laid out like a replication server's (e.g.
+    /// replication/minute, where sequence number 1234567 is 001/234/567.osc.gz with its
+    /// state in 001/234/567.state.txt), starting after the database's sequence number and
+    /// stopping at the first one missing. Each is committed in its own transaction, with
+    /// the replication state it brings the database up to
+    #[arg(long, conflicts_with_all = ["osc_file", "sequence_number", "timestamp"])]
+    from_dir: Option<PathBuf>,
+    /// Commit the changes. Without this, the diff is applied and then discarded, as a dry
+    /// run which checks that it can be read and applied, and reports the changes it makes,
+    /// those which conflict with the database (like modifies of elements which don't
+    /// exist) and the area it affects
+    #[arg(long)]
+    commit: bool,
+    /// With --commit, commit after every N changes (as well as at the end of each diff),
+    /// rather than applying each diff in one transaction, so that catching up on a long
+    /// run of diffs doesn't hold a huge transaction open and readers see its progress.
+    /// Only the transaction finishing a diff records its replication state, so a diff
+    /// interrupted partway through is applied again from the start by the next run
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    batch_size: Option<u64>,
+}
+
+/// An osmChange file to apply, and the replication state it brings the database up to
+struct Diff {
+    path: PathBuf,
+    sequence_number: u64,
+    timestamp: String,
+}
+
+/// The write transaction changes are applied in, which is committed once it has a batch
+/// of changes when --batch-size is given (and discarded without --commit)
+struct Batches<'db> {
+    db: &'db osmx::Database,
+    txn: Option<osmx::WriteTransaction<'db>>,
+    commit: bool,
+    batch_size: Option<u64>,
+    pending: u64,
+    committed: usize,
+}
+
+impl<'db> Batches<'db> {
+    fn new(db: &'db osmx::Database, commit: bool, batch_size: Option<u64>) -> Self {
+        Batches {
+            db,
+            txn: None,
+            commit,
+            batch_size,
+            pending: 0,
+            committed: 0,
+        }
+    }
+
+    /// The transaction to apply the next change in, committing the current one first if
+    /// it has a full batch (which is left until there's another change, so that the
+    /// last batch of a diff is committed with its replication state)
+    fn txn(&mut self) -> Result<&mut osmx::WriteTransaction<'db>, Box<dyn Error>> {
+        if self.commit && self.batch_size.is_some_and(|size| self.pending >= size) {
+            self.commit_txn()?;
+        }
+        if self.txn.is_none() {
+            self.txn = Some(osmx::WriteTransaction::begin(self.db)?);
+        }
+        Ok(self.txn.as_mut().unwrap())
+    }
+
+    /// Count a change applied in the current transaction
+    fn applied(&mut self) {
+        self.pending += 1;
+    }
+
+    /// Commit the current transaction (if changes are kept), recording the replication
+    /// state of the diff it finishes
+    fn finish_diff(&mut self, diff: &Diff) -> Result<(), Box<dyn Error>> {
+        if self.commit {
+            if self.txn.is_none() {
+                self.txn = Some(osmx::WriteTransaction::begin(self.db)?);
+            }
+            let txn = self.txn.as_mut().unwrap();
+            txn.set_replication_state(&diff.timestamp, diff.sequence_number)?;
+            self.commit_txn()?;
+        }
+        Ok(())
+    }
+
+    fn commit_txn(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(txn) = self.txn.take() {
+            let commit_phase = trace::phase("commit");
+            txn.commit()?;
+            commit_phase.end();
+            self.committed += 1;
+        }
+        self.pending = 0;
+        Ok(())
+    }
+}
+
+/// What an osmChange section does to the elements in it
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Create,
+    Modify,
+    Delete,
+}
+
+/// An element of an osmChange file, as it's read
+struct Change {
+    element_type: ElementType,
+    id: u64,
+    longitude: f64,
+    latitude: f64,
+    version: u32,
+    tags: Vec<String>,
+    nodes: Vec<u64>,
+    members: Vec<(ElementType, u64, String)>,
+}
+
+/// The number of conflicts listed in the summary
+const MAX_CONFLICTS_SHOWN: usize = 10;
+
+/// What applying an osmChange file did: the number of elements of each type applied by
+/// each action, the changes which conflict with the database, and the area affected
+#[derive(Default)]
+struct Summary {
+    created: [u64; 3],
+    modified: [u64; 3],
+    deleted: [u64; 3],
+    // creates of elements which already exist, and modifies and deletes of elements
+    // which don't
+    conflicts: Vec<String>,
+    // the bounding box (in degrees) of the old and new locations of the nodes changed,
+    // and of the nodes of the ways created or modified
+    bbox: Option<(f64, f64, f64, f64)>,
+}
+
+impl Summary {
+    /// Grow the affected area to include a location
+    fn extend(&mut self, (lon, lat): (f64, f64)) {
+        let (west, south, east, north) = self.bbox.unwrap_or((lon, lat, lon, lat));
+        self.bbox = Some((west.min(lon), south.min(lat), east.max(lon), north.max(lat)));
+    }
+}
+
+pub fn run(args: &CliArgs) -> Result<(), Box<dyn Error>> {
+    let db = osmx::Database::open(&args.file)?;
+    let Some(_guard) = db.try_exclusive_writer("update")? else {
+        let holder = osmx::Transaction::begin(&db)?.writer_lock()?;
+        return Err(match holder {
+            Some(holder) => format!("{} is locked by {}", args.file.display(), holder),
+            None => format!("{} is locked by another writer", args.file.display()),
+        }
+        .into());
+    };
+
+    let diffs = match &args.from_dir {
+        Some(dir) => {
+            let sequence_number = osmx::Transaction::begin(&db)?
+                .replication_sequence_number()?
+                .ok_or_else(|| {
+                    format!(
+                        "{} has no replication sequence number to start from",
+                        args.file.display()
+                    )
+                })?;
+            let diffs = replication_diffs(dir, sequence_number)?;
+            if diffs.is_empty() {
+                eprintln!(
+                    "{} has no diffs after sequence number {}.",
+                    dir.display(),
+                    sequence_number
+                );
+                return Ok(());
+            }
+            diffs
+        }
+        // clap requires these without --from-dir
+        None => vec![Diff {
+            path: args.osc_file.clone().unwrap(),
+            sequence_number: args.sequence_number.unwrap(),
+            timestamp: args.timestamp.clone().unwrap(),
+        }],
+    };
+    for diff in &diffs {
+        if osmx::interop::parse_timestamp(&diff.timestamp).is_none() {
+            return Err(format!("invalid timestamp {}", diff.timestamp).into());
+        }
+    }
+
+    // without --commit, the diffs are applied one after another in a single transaction,
+    // which is discarded
+    let mut summary = Summary::default();
+    let mut batches = Batches::new(&db, args.commit, args.batch_size);
+    for diff in &diffs {
+        let file = BufReader::new(File::open(&diff.path)?);
+        let input: Box<dyn BufRead> = if is_gzipped(&diff.path)? {
+            Box::new(BufReader::new(MultiGzDecoder::new(file)))
+        } else {
+            Box::new(file)
+        };
+
+        let apply_phase = trace::phase("apply");
+        apply_changes(input, &mut batches, &mut summary)?;
+        apply_phase.end();
+        if args.from_dir.is_some() {
+            eprintln!(
+                "applied {} (sequence number {})",
+                diff.path.display(),
+                diff.sequence_number
+            );
+        }
+        batches.finish_diff(diff)?;
+    }
+
+    for (action, counts) in [
+        ("created", summary.created),
+        ("modified", summary.modified),
+        ("deleted", summary.deleted),
+    ] {
+        eprintln!(
+            "{}: {} nodes, {} ways, {} relations",
+            action, counts[0], counts[1], counts[2]
+        );
+    }
+    if !summary.conflicts.is_empty() {
+        eprintln!("conflicts with the database: {}", summary.conflicts.len());
+        for conflict in summary.conflicts.iter().take(MAX_CONFLICTS_SHOWN) {
+            eprintln!("  {}", conflict);
+        }
+        if summary.conflicts.len() > MAX_CONFLICTS_SHOWN {
+            eprintln!(
+                "  and {} more",
+                summary.conflicts.len() - MAX_CONFLICTS_SHOWN
+            );
+        }
+    }
+    match summary.bbox {
+        // in the form --bbox options take
+        Some((west, south, east, north)) => eprintln!(
+            "affected area: {:.7},{:.7},{:.7},{:.7}",
+            west, south, east, north
+        ),
+        None => eprintln!("affected area: none"),
+    }
+
+    if args.commit && batches.committed > 1 {
+        eprintln!("committed {} transactions.", batches.committed);
+    } else if args.commit {
+        eprintln!("committed transaction.");
+    } else {
+        eprintln!("discarded changes (pass --commit to keep them).");
+    }
+
+    Ok(())
+}
+
+/// The diffs in a replication directory after the given sequence number, up to the first
+/// one which is missing, with the replication states in their state files
+fn replication_diffs(dir: &Path, after: u64) -> Result<Vec<Diff>, Box<dyn Error>> {
+    let mut diffs = vec![];
+    for sequence_number in after + 1.. {
+        // the standard layout has three levels of three digits (more at the top for
+        // sequence numbers of ten digits or more)
+        let digits = format!("{:09}", sequence_number);
+        let (top, rest) = digits.split_at(digits.len() - 6);
+        let base = dir.join(top).join(&rest[..3]).join(&rest[3..]);
+        let path = base.with_extension("osc.gz");
+        if !path.exists() {
+            break;
+        }
+        let state_path = base.with_extension("state.txt");
+        let (state_sequence_number, timestamp) = read_state(&state_path)?;
+        if state_sequence_number != sequence_number {
+            return Err(format!(
+                "{} has sequence number {}, not {}",
+                state_path.display(),
+                state_sequence_number,
+                sequence_number
+            )
+            .into());
+        }
+        diffs.push(Diff {
+            path,
+            sequence_number,
+            timestamp,
+        });
+    }
+    Ok(diffs)
+}
+
+/// Read the sequence number and timestamp of a replication state file, which is a Java
+/// properties file (so the colons of the timestamp are escaped with backslashes)
+fn read_state(path: &Path) -> Result<(u64, String), Box<dyn Error>> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("can't read {}: {}", path.display(), e))?;
+    let (mut sequence_number, mut timestamp) = (None, None);
+    for line in text.lines() {
+        match line.split_once('=') {
+            Some(("sequenceNumber", value)) => sequence_number = Some(value.trim().parse()?),
+            Some(("timestamp", value)) => timestamp = Some(value.trim().replace("\\:", ":")),
+            _ => (),
+        }
+    }
+    match (sequence_number, timestamp) {
+        (Some(sequence_number), Some(timestamp)) => Ok((sequence_number, timestamp)),
+        _ => Err(format!("{} has no sequenceNumber or timestamp", path.display()).into()),
+    }
+}
+
+/// Returns true if the file starts with the gzip magic number
+fn is_gzipped(path: &PathBuf) -> Result<bool, Box<dyn Error>> {
+    let mut magic = [0; 2];
+    let mut file = File::open(path)?;
+    Ok(file.read(&mut magic)? == 2 && magic == [0x1f, 0x8b])
+}
+
+/// Read an osmChange document, applying each element to the database as it ends, and
+/// adding it to the summary
+fn apply_changes(
+    input: impl BufRead,
+    batches: &mut Batches,
+    summary: &mut Summary,
+) -> Result<(), Box<dyn Error>> {
+    let mut reader = quick_xml::Reader::from_reader(input);
+    let mut buf = vec![];
+    let mut action = None;
+    let mut change: Option<Change> = None;
+
+    loop {
+        let (event, empty) = match reader.read_event_into(&mut buf)? {
+            Event::Start(e) => (e.into_owned(), false),
+            Event::Empty(e) => (e.into_owned(), true),
+            Event::End(e) => {
+                match e.name().as_ref() {
+                    b"create" | b"modify" | b"delete" => action = None,
+                    b"node" | b"way" | b"relation" => {
+                        if let Some(change) = change.take() {
+                            apply(batches.txn()?, action, &change, summary)?;
+                            batches.applied();
+                        }
+                    }
+                    _ => (),
+                }
+                buf.clear();
+                continue;
+            }
+            Event::Eof => break,
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+        buf.clear();
+
+        match event.name().as_ref() {
+            b"create" => action = Some(Action::Create),
+            b"modify" => action = Some(Action::Modify),
+            b"delete" => action = Some(Action::Delete),
+            b"node" | b"way" | b"relation" => {
+                let element = read_element(&event, action)?;
+                if empty {
+                    apply(batches.txn()?, action, &element, summary)?;
+                    batches.applied();
+                } else {
+                    change = Some(element);
+                }
+            }
+            b"tag" => {
+                if let Some(change) = change.as_mut() {
+                    change.tags.push(attribute(&event, "k")?);
+                    change.tags.push(attribute(&event, "v")?);
+                }
+            }
+            b"nd" => {
+                if let Some(change) = change.as_mut() {
+                    let id = parse_id(&attribute(&event, "ref")?, |id| {
+                        format!("node {} (in way {})", id, change.id)
+                    })?;
+                    change.nodes.push(id);
+                }
+            }
+            b"member" => {
+                if let Some(change) = change.as_mut() {
+                    let type_name = attribute(&event, "type")?;
+                    let member_type = match type_name.as_str() {
+                        "node" => ElementType::Node,
+                        "way" => ElementType::Way,
+                        "relation" => ElementType::Relation,
+                        other => return Err(format!("unknown member type {}", other).into()),
+                    };
+                    let id = parse_id(&attribute(&event, "ref")?, |id| {
+                        format!("{} {} (in relation {})", type_name, id, change.id)
+                    })?;
+                    change
+                        .members
+                        .push((member_type, id, attribute(&event, "role")?));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// Read the attributes of a node, way or relation element in a section with the given
+/// action. Returns an error if a created or modified node has no location.
+fn read_element(event: &BytesStart, action: Option<Action>) -> Result<Change, Box<dyn Error>> {
+    let element_type = match event.name().as_ref() {
+        b"node" => ElementType::Node,
+        b"way" => ElementType::Way,
+        _ => ElementType::Relation,
+    };
+    let id = parse_id(&attribute(event, "id")?, |id| {
+        format!("{} {}", String::from_utf8_lossy(event.name().as_ref()), id)
+    })?;
+    let (mut longitude, mut latitude) = (0.0, 0.0);
+    // deleted nodes may not have a location
+    if element_type == ElementType::Node && matches!(action, Some(Action::Create | Action::Modify))
+    {
+        let coordinate = |name: &str| -> Result<f64, Box<dyn Error>> {
+            match optional_attribute(event, name)? {
+                Some(value) => Ok(value.parse()?),
+                None => Err(format!("node {} has no {} attribute", id, name).into()),
+            }
+        };
+        longitude = coordinate("lon")?;
+        latitude = coordinate("lat")?;
+    }
+    Ok(Change {
+        element_type,
+        id,
+        longitude,
+        latitude,
+        version: optional_attribute(event, "version")?.map_or(Ok(0), |v| v.parse())?,
+        tags: vec![],
+        nodes: vec![],
+        members: vec![],
+    })
+}
+
+/// Parse the ID of an element, or of a node or member it references. Negative IDs are used
+/// for new elements in files written by editors, but OSMX keys are unsigned, so they're
+/// reported (using `describe`, given the ID) rather than failing to parse.
+fn parse_id(value: &str, describe: impl FnOnce(i64) -> String) -> Result<u64, Box<dyn Error>> {
+    match value.parse::<i64>() {
+        Ok(id) if id < 0 => Err(format!(
+            "{} has a negative ID, which OSMX databases can't store",
+            describe(id)
+        )
+        .into()),
+        Ok(id) => Ok(id as u64),
+        Err(e) => Err(format!("invalid ID {:?} ({})", value, e).into()),
+    }
+}
+
+/// Get the value of an attribute of an element. Returns an error if it's missing.
+fn attribute(event: &BytesStart, name: &str) -> Result<String, Box<dyn Error>> {
+    optional_attribute(event, name)?.ok_or_else(|| {
+        format!(
+            "{} element has no {} attribute",
+            String::from_utf8_lossy(event.name().as_ref()),
+            name
+        )
+        .into()
+    })
+}
+
+/// Get the value of an attribute of an element, if it has one
+fn optional_attribute(event: &BytesStart, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match event.try_get_attribute(name)? {
+        Some(attr) => Ok(Some(attr.unescape_value()?.into_owned())),
+        None => Ok(None),
+    }
+}
+
+/// Apply one element of an osmChange file to the database, and add it to the summary
+fn apply(
+    txn: &mut osmx::WriteTransaction,
+    action: Option<Action>,
+    change: &Change,
+    summary: &mut Summary,
+) -> Result<(), Box<dyn Error>> {
+    let action = action.ok_or("element outside of a create, modify or delete section")?;
+    let (kind, type_name, element) = match change.element_type {
+        ElementType::Node => (0, "node", ElementId::Node(change.id)),
+        ElementType::Way => (1, "way", ElementId::Way(change.id)),
+        ElementType::Relation => (2, "relation", ElementId::Relation(change.id)),
+    };
+    let tags: Vec<&str> = change.tags.iter().map(String::as_str).collect();
+
+    // conflicting changes are still applied: a modify of a missing element creates it
+    let conflict = match (action, txn.contains(element)?) {
+        (Action::Create, true) => Some("create of existing"),
+        (Action::Modify, false) => Some("modify of missing"),
+        (Action::Delete, false) => Some("delete of missing"),
+        _ => None,
+    };
+    if let Some(conflict) = conflict {
+        summary
+            .conflicts
+            .push(format!("{} {} {}", conflict, type_name, change.id));
+    }
+    if change.element_type == ElementType::Node {
+        if let Some(location) = txn.node_location(change.id)? {
+            summary.extend(location);
+        }
+    }
+
+    match (action, change.element_type) {
+        (Action::Delete, ElementType::Node) => {
+            txn.delete_node(change.id)?;
+        }
+        (Action::Delete, ElementType::Way) => {
+            txn.delete_way(change.id)?;
+        }
+        (Action::Delete, ElementType::Relation) => {
+            txn.delete_relation(change.id)?;
+        }
+        (_, ElementType::Node) => {
+            let location = LocationBuilder {
+                longitude: change.longitude,
+                latitude: change.latitude,
+                version: change.version,
+            };
+            // like expand, only nodes with tags have a value in the nodes table
+            let node = (!tags.is_empty()).then(|| {
+                let mut builder = NodeBuilder::new();
+                builder.set_tags(&tags);
+                builder
+            });
+            txn.put_node(change.id, &location, node.as_ref())?;
+            summary.extend((change.longitude, change.latitude));
+        }
+        (_, ElementType::Way) => {
+            let mut builder = WayBuilder::new();
+            builder.set_tags(&tags);
+            builder.set_nodes(&change.nodes);
+            txn.put_way(change.id, &builder)?;
+            for &node_id in &change.nodes {
+                if let Some(location) = txn.node_location(node_id)? {
+                    summary.extend(location);
+                }
+            }
+        }
+        (_, ElementType::Relation) => {
+            let mut builder = RelationBuilder::new();
+            builder.set_tags(&tags);
+            builder.set_members(&change.members);
+            txn.put_relation(change.id, &builder)?;
+        }
+    }
+
+    match action {
+        Action::Create => summary.created[kind] += 1,
+        Action::Modify => summary.modified[kind] += 1,
+        Action::Delete => summary.deleted[kind] += 1,
+    }
+    Ok(())
+}
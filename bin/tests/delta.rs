@@ -0,0 +1,188 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use lmdb::{Cursor, Transaction};
+use osmx::testing::DatabaseBuilder;
+use osmx::ElementId;
+
+/// A directory for the files of one test, removed when it's dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("osmx-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn run_osmx(args: &[&Path]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_osmx-rs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn osmx(args: &[&Path]) {
+    let output = run_osmx(args);
+    assert!(
+        output.status.success(),
+        "osmx {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// The name of a table, and its keys and values
+type Table = (String, Vec<(Vec<u8>, Vec<u8>)>);
+
+/// Every entry of every table of a database, by table name
+fn contents(path: &Path) -> Vec<Table> {
+    let env = lmdb::Environment::new()
+        .set_flags(lmdb::EnvironmentFlags::NO_SUB_DIR | lmdb::EnvironmentFlags::READ_ONLY)
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .open(path)
+        .unwrap();
+    let main = env.open_db(None).unwrap();
+    let names: Vec<String> = {
+        let txn = env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(main).unwrap();
+        cursor
+            .iter()
+            .map(|(raw_key, _)| String::from_utf8(raw_key.to_vec()).unwrap())
+            .collect()
+    };
+    let tables: Vec<_> = names
+        .iter()
+        .map(|name| env.open_db(Some(name)).unwrap())
+        .collect();
+    let txn = env.begin_ro_txn().unwrap();
+    names
+        .into_iter()
+        .zip(tables)
+        .map(|(name, table)| {
+            let mut cursor = txn.open_ro_cursor(table).unwrap();
+            let entries = cursor
+                .iter()
+                .map(|(raw_key, raw_val)| (raw_key.to_vec(), raw_val.to_vec()))
+                .collect();
+            (name, entries)
+        })
+        .collect()
+}
+
+/// Compute the delta from `from` to `to`, apply it to a copy of `from`, and check that
+/// the copy has the same tables as `to`
+fn assert_round_trip(dir: &TempDir, from: &Path, to: &Path) {
+    let delta = dir.join("delta.bin");
+    let copy = dir.join("copy.osmx");
+    std::fs::copy(from, &copy).unwrap();
+    osmx(&[Path::new("delta"), from, to, &delta]);
+    osmx(&[Path::new("apply-delta"), &copy, &delta]);
+
+    let (applied, expected) = (contents(&copy), contents(to));
+    let names = |tables: &[(String, _)]| tables.iter().map(|(n, _)| n.clone()).collect::<Vec<_>>();
+    assert_eq!(names(&applied), names(&expected));
+    for ((name, applied), (_, expected)) in applied.iter().zip(&expected) {
+        assert!(applied == expected, "table {} differs", name);
+    }
+}
+
+#[test]
+fn delta_round_trip() {
+    let dir = TempDir::new("delta-round-trip");
+    let old = dir.join("old.osmx");
+    let new = dir.join("new.osmx");
+
+    DatabaseBuilder::new()
+        .node(1, 10.0, 50.0, &[])
+        .node(2, 10.1, 50.1, &[("amenity", "cafe")])
+        .node(3, 10.2, 50.2, &[])
+        .node(4, 10.3, 50.3, &[])
+        .way(10, &[1, 2, 3], &[("highway", "residential")])
+        .way(11, &[3, 4], &[("highway", "service")])
+        .relation(
+            20,
+            &[(ElementId::Way(10), "outer")],
+            &[("type", "multipolygon")],
+        )
+        .relation(21, &[(ElementId::Node(4), "stop")], &[("type", "route")])
+        .write_to(&old)
+        .unwrap();
+    // node 2 moves and is retagged, node 5 and way 12 are added, node 4 loses its way
+    // and relation, and relation 21 is deleted
+    DatabaseBuilder::new()
+        .node(1, 10.0, 50.0, &[])
+        .node(2, 10.15, 50.05, &[("amenity", "restaurant")])
+        .node(3, 10.2, 50.2, &[])
+        .node(4, 10.3, 50.3, &[])
+        .node(5, 10.4, 50.4, &[])
+        .way(10, &[1, 2, 3], &[("highway", "residential")])
+        .way(11, &[3, 5], &[("highway", "service")])
+        .way(12, &[5, 1], &[])
+        .relation(
+            20,
+            &[(ElementId::Way(10), "outer"), (ElementId::Way(12), "outer")],
+            &[("type", "multipolygon")],
+        )
+        .write_to(&new)
+        .unwrap();
+
+    assert_round_trip(&dir, &old, &new);
+    assert_round_trip(&dir, &new, &old);
+}
+
+#[test]
+fn delta_refuses_other_databases() {
+    let dir = TempDir::new("delta-refuses");
+    let old = dir.join("old.osmx");
+    let new = dir.join("new.osmx");
+    let other = dir.join("other.osmx");
+    let delta = dir.join("delta.bin");
+
+    let builder = || {
+        let mut builder = DatabaseBuilder::new();
+        builder
+            .node(1, 10.0, 50.0, &[])
+            .way(10, &[1, 2], &[("highway", "residential")])
+            .node(2, 10.1, 50.1, &[]);
+        builder
+    };
+    builder().write_to(&old).unwrap();
+    builder().node(3, 10.2, 50.2, &[]).write_to(&new).unwrap();
+    // the same number of entries in each table as old, but a different node
+    DatabaseBuilder::new()
+        .node(1, 10.0, 50.0, &[])
+        .way(10, &[1, 2], &[("highway", "residential")])
+        .node(2, 10.2, 50.2, &[])
+        .write_to(&other)
+        .unwrap();
+    osmx(&[Path::new("delta"), &old, &new, &delta]);
+
+    for base in [&new, &other] {
+        let before = contents(base);
+        let output = run_osmx(&[Path::new("apply-delta"), base, &delta]);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains("isn't the one it was computed from")
+        );
+        assert!(contents(base) == before, "{} was changed", base.display());
+    }
+
+    let missing = dir.join("missing.osmx");
+    let output = run_osmx(&[Path::new("apply-delta"), &missing, &delta]);
+    assert!(!output.status.success());
+    assert!(!missing.exists());
+    assert!(!dir.join("missing.osmx-lock").exists());
+}
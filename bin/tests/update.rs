@@ -0,0 +1,262 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use lmdb::{Cursor, Transaction};
+use osmx::testing::DatabaseBuilder;
+use osmx::ElementId;
+
+/// A directory for the files of one test, removed when it's dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("osmx-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    fn join(&self, name: &str) -> PathBuf {
+        self.0.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn run_osmx(args: &[&Path]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_osmx-rs"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn osmx(args: &[&Path]) {
+    let output = run_osmx(args);
+    assert!(
+        output.status.success(),
+        "osmx {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// The name of a table, and its keys and values
+type Table = (String, Vec<(Vec<u8>, Vec<u8>)>);
+
+/// Every entry of every table of a database, by table name
+fn contents(path: &Path) -> Vec<Table> {
+    let env = lmdb::Environment::new()
+        .set_flags(lmdb::EnvironmentFlags::NO_SUB_DIR | lmdb::EnvironmentFlags::READ_ONLY)
+        .set_max_dbs(osmx::DEFAULT_MAX_TABLES)
+        .open(path)
+        .unwrap();
+    let main = env.open_db(None).unwrap();
+    let names: Vec<String> = {
+        let txn = env.begin_ro_txn().unwrap();
+        let mut cursor = txn.open_ro_cursor(main).unwrap();
+        cursor
+            .iter()
+            .map(|(raw_key, _)| String::from_utf8(raw_key.to_vec()).unwrap())
+            .collect()
+    };
+    let tables: Vec<_> = names
+        .iter()
+        .map(|name| env.open_db(Some(name)).unwrap())
+        .collect();
+    let txn = env.begin_ro_txn().unwrap();
+    names
+        .into_iter()
+        .zip(tables)
+        .map(|(name, table)| {
+            let mut cursor = txn.open_ro_cursor(table).unwrap();
+            let entries = cursor
+                .iter()
+                .map(|(raw_key, raw_val)| (raw_key.to_vec(), raw_val.to_vec()))
+                .collect();
+            (name, entries)
+        })
+        .collect()
+}
+
+/// Check that two databases have the same tables, apart from the metadata table (which
+/// an update adds the replication state to) and the `skipped` ones
+fn assert_same_tables(actual: &Path, expected: &Path, skipped: &[&str]) {
+    let tables = |path| {
+        contents(path)
+            .into_iter()
+            .filter(|(name, _)| name != "metadata" && !skipped.contains(&name.as_str()))
+            .collect::<Vec<_>>()
+    };
+    let (actual, expected) = (tables(actual), tables(expected));
+    let names = |tables: &[(String, _)]| tables.iter().map(|(n, _)| n.clone()).collect::<Vec<_>>();
+    assert_eq!(names(&actual), names(&expected));
+    for ((name, actual), (_, expected)) in actual.iter().zip(&expected) {
+        assert!(actual == expected, "table {} differs", name);
+    }
+}
+
+/// The tags, way nodes and relation members of every element of a database, for
+/// comparing databases whose element values are encoded differently
+fn elements(path: &Path) -> Vec<String> {
+    let db = osmx::Database::open(path).unwrap();
+    let txn = osmx::Transaction::begin(&db).unwrap();
+    let tags = |tags: Vec<(&str, &str)>| format!("{:?}", tags);
+    let nodes = txn.nodes().unwrap();
+    let ways = txn.ways().unwrap();
+    let relations = txn.relations().unwrap();
+    let mut elements: Vec<String> = nodes
+        .iter()
+        .map(|(id, node)| format!("n{} {}", id, tags(node.tags().collect())))
+        .collect();
+    elements.extend(ways.iter().map(|(id, way)| {
+        let nodes: Vec<u64> = way.nodes().collect();
+        format!("w{} {:?} {}", id, nodes, tags(way.tags().collect()))
+    }));
+    elements.extend(relations.iter().map(|(id, relation)| {
+        let members: Vec<String> = relation
+            .members()
+            .map(|member| format!("{:?} {}", member.id(), member.role()))
+            .collect();
+        format!("r{} {:?} {}", id, members, tags(relation.tags().collect()))
+    }));
+    elements
+}
+
+fn before() -> DatabaseBuilder {
+    let mut builder = DatabaseBuilder::new();
+    builder
+        .node(1, 10.0, 50.0, &[])
+        .node(2, 10.1, 50.1, &[("amenity", "cafe")])
+        .node(3, 10.2, 50.2, &[])
+        .node(4, 10.3, 50.3, &[])
+        .way(10, &[1, 2, 3], &[("highway", "residential")])
+        .way(11, &[3, 4], &[("highway", "service")])
+        .relation(
+            20,
+            &[(ElementId::Way(10), "outer")],
+            &[("type", "multipolygon")],
+        );
+    builder
+}
+
+/// Creates node 5, drops all of node 2's tags, moves node 3 and deletes way 11
+const CHANGES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osmChange version="0.6">
+  <create>
+    <node id="5" version="1" lat="50.4" lon="10.4"/>
+  </create>
+  <modify>
+    <node id="2" version="1" lat="50.1" lon="10.1"/>
+    <node id="3" version="1" lat="50.25" lon="10.25"/>
+  </modify>
+  <delete>
+    <way id="11" version="1"/>
+  </delete>
+</osmChange>
+"#;
+
+#[test]
+fn update_applies_changes() {
+    let dir = TempDir::new("update-applies");
+    let db = dir.join("db.osmx");
+    let expected = dir.join("expected.osmx");
+    let osc = dir.join("changes.osc");
+    std::fs::write(&osc, CHANGES).unwrap();
+
+    before().write_to(&db).unwrap();
+    DatabaseBuilder::new()
+        .node(1, 10.0, 50.0, &[])
+        .node(2, 10.1, 50.1, &[])
+        .node(3, 10.25, 50.25, &[])
+        .node(4, 10.3, 50.3, &[])
+        .node(5, 10.4, 50.4, &[])
+        .way(10, &[1, 2, 3], &[("highway", "residential")])
+        .relation(
+            20,
+            &[(ElementId::Way(10), "outer")],
+            &[("type", "multipolygon")],
+        )
+        .write_to(&expected)
+        .unwrap();
+
+    let update = |commit: bool| {
+        let mut args = vec![
+            Path::new("update"),
+            &db,
+            &osc,
+            Path::new("7"),
+            Path::new("2024-08-13T00:00:00Z"),
+        ];
+        if commit {
+            args.push(Path::new("--commit"));
+        }
+        osmx(&args);
+    };
+
+    // without --commit, the changes are discarded
+    let original = contents(&db);
+    update(false);
+    assert!(contents(&db) == original, "a dry run changed the database");
+
+    update(true);
+    assert_same_tables(&db, &expected, &[]);
+
+    let db = osmx::Database::open(&db).unwrap();
+    let txn = osmx::Transaction::begin(&db).unwrap();
+    assert_eq!(txn.replication_sequence_number().unwrap(), Some(7));
+    assert_eq!(
+        txn.replication_timestamp().unwrap(),
+        Some("2024-08-13T00:00:00Z")
+    );
+    assert_eq!(
+        txn.metadata(osmx::KeyByteOrder::METADATA_KEY).unwrap(),
+        Some(osmx::KeyByteOrder::NATIVE.as_str().as_bytes())
+    );
+}
+
+#[test]
+fn update_keys_are_native_endian() {
+    let dir = TempDir::new("update-keys");
+    let db = dir.join("db.osmx");
+    let osc = dir.join("changes.osc");
+    std::fs::write(&osc, CHANGES).unwrap();
+    before().write_to(&db).unwrap();
+    osmx(&[
+        Path::new("update"),
+        &db,
+        &osc,
+        Path::new("7"),
+        Path::new("2024-08-13T00:00:00Z"),
+        Path::new("--commit"),
+    ]);
+
+    // LMDB compares INTEGER_KEY keys as native integers, so the created node's key must
+    // be native-endian to sort after the others
+    let tables = contents(&db);
+    let (_, locations) = tables.iter().find(|(name, _)| name == "locations").unwrap();
+    let ids: Vec<u64> = locations
+        .iter()
+        .map(|(raw_key, _)| u64::from_ne_bytes(raw_key[..].try_into().unwrap()))
+        .collect();
+    assert_eq!(ids, [1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn export_pbf_round_trip() {
+    let dir = TempDir::new("export-pbf");
+    let db = dir.join("db.osmx");
+    let pbf = dir.join("db.osm.pbf");
+    let expanded = dir.join("expanded.osmx");
+
+    before().write_to(&db).unwrap();
+    osmx(&[Path::new("export"), Path::new("pbf"), &db, &pbf]);
+    osmx(&[Path::new("expand"), &pbf, &expanded]);
+    // expand lays out element values differently from DatabaseBuilder, so those are
+    // compared by their contents
+    assert_same_tables(&expanded, &db, &["nodes", "ways", "relations"]);
+    assert_eq!(elements(&expanded), elements(&db));
+}
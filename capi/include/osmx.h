@@ -0,0 +1,91 @@
+/*
+ * C API for reading .osmx files. See capi/src/lib.rs for the implementation.
+ *
+ * The API is read-only. Functions which can fail return a negative value (or NULL) and
+ * record a message which can be read with osmx_last_error().
+ */
+
+#ifndef OSMX_H
+#define OSMX_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define OSMX_NODE 0
+#define OSMX_WAY 1
+#define OSMX_RELATION 2
+
+/* An open database. Freed with osmx_database_free. */
+typedef struct OsmxDatabase OsmxDatabase;
+
+/* A read transaction, which must be freed with osmx_transaction_free before the database
+ * it was started from. */
+typedef struct OsmxTransaction OsmxTransaction;
+
+/* Receives a tag's key and value, which are only valid during the call. */
+typedef void (*OsmxTagCallback)(const char *key, const char *value, void *userdata);
+
+/* Receives a relation member's type, ID and role (only valid during the call). */
+typedef void (*OsmxMemberCallback)(int member_type, uint64_t id, const char *role,
+                                   void *userdata);
+
+/* Receives an element ID. Return true to continue iterating, or false to stop. */
+typedef bool (*OsmxIdCallback)(uint64_t id, void *userdata);
+
+/* Returns the message describing the most recent error on this thread, or NULL. The
+ * string is valid until the next failing call on this thread. */
+const char *osmx_last_error(void);
+
+/* Open the .osmx file at the given path. Returns NULL on error. */
+OsmxDatabase *osmx_database_open(const char *path);
+
+/* Close a database. All transactions started from it must have been freed. */
+void osmx_database_free(OsmxDatabase *db);
+
+/* Start a read transaction. Returns NULL on error. */
+OsmxTransaction *osmx_transaction_begin(const OsmxDatabase *db);
+
+/* End a transaction. */
+void osmx_transaction_free(OsmxTransaction *txn);
+
+/* Get a node's location in degrees. Returns 1 if found, 0 if not, or -1 on error. */
+int osmx_location_get(const OsmxTransaction *txn, uint64_t node_id, double *lon,
+                      double *lat);
+
+/* Call callback with each of an element's tags. Returns 1 if the element is found, 0 if
+ * not (untagged nodes are not found), or -1 on error. */
+int osmx_element_tags(const OsmxTransaction *txn, int element_type, uint64_t id,
+                      OsmxTagCallback callback, void *userdata);
+
+/* Write up to capacity of a way's node IDs to nodes. Returns the way's total number of
+ * nodes (call again with a larger buffer if it exceeds capacity), 0 if the way isn't
+ * found, or -1 on error. */
+int64_t osmx_way_nodes(const OsmxTransaction *txn, uint64_t way_id, uint64_t *nodes,
+                       size_t capacity);
+
+/* Call callback with each of a relation's members, in order. Returns 1 if the relation is
+ * found, 0 if not, or -1 on error. */
+int osmx_relation_members(const OsmxTransaction *txn, uint64_t relation_id,
+                          OsmxMemberCallback callback, void *userdata);
+
+/* Call callback with the ID of every element of the given type, in ID order (for
+ * OSMX_NODE, including untagged nodes). Returns 0 on success, or -1 on error. */
+int osmx_iterate(const OsmxTransaction *txn, int element_type, OsmxIdCallback callback,
+                 void *userdata);
+
+/* Call callback with the ID of each node which may be within the bounding box (in
+ * degrees), using the spatial index. Nodes near the box may also be included. Returns 0
+ * on success, or -1 on error. */
+int osmx_nodes_in_bbox(const OsmxTransaction *txn, double west, double south, double east,
+                       double north, OsmxIdCallback callback, void *userdata);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* OSMX_H */
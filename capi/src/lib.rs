@@ -0,0 +1,295 @@
+//! A C API for reading `.osmx` files, for C and C++ applications (including existing users
+//! of the OSMExpress C++ library). See `include/osmx.h` for the declarations.
+//!
+//! The API is read-only. Functions which can fail return a negative value (or a null
+//! pointer) and record a message which can be read with [osmx_last_error]. Panics are
+//! caught at the API boundary and reported the same way.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+pub const OSMX_NODE: c_int = 0;
+pub const OSMX_WAY: c_int = 1;
+pub const OSMX_RELATION: c_int = 2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+/// Run f, returning its result, or the given value if it fails or panics (after recording
+/// the error)
+fn guard<T>(on_error: T, f: impl FnOnce() -> Result<T, Box<dyn std::error::Error>>) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(e)) => {
+            set_last_error(e.to_string());
+            on_error
+        }
+        Err(_) => {
+            set_last_error("osmx panicked".to_string());
+            on_error
+        }
+    }
+}
+
+/// An open database. Freed with [osmx_database_free].
+pub struct OsmxDatabase(osmx::Database);
+
+/// A read transaction, which must be freed with [osmx_transaction_free] before the
+/// database it was started from.
+pub struct OsmxTransaction(osmx::Transaction<'static>);
+
+/// Returns the message describing the most recent error on this thread, or null if there
+/// hasn't been one. The string is valid until the next failing call on this thread.
+#[no_mangle]
+pub extern "C" fn osmx_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| match &*e.borrow() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Open the .osmx file at the given path. Returns null on error.
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_database_open(path: *const c_char) -> *mut OsmxDatabase {
+    guard(ptr::null_mut(), || {
+        let path = CStr::from_ptr(path).to_str()?;
+        let db = osmx::Database::open(path)?;
+        Ok(Box::into_raw(Box::new(OsmxDatabase(db))))
+    })
+}
+
+/// Close a database opened with [osmx_database_open].
+///
+/// # Safety
+/// `db` must have been returned by [osmx_database_open] (or be null), and all
+/// transactions started from it must have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_database_free(db: *mut OsmxDatabase) {
+    if !db.is_null() {
+        drop(Box::from_raw(db));
+    }
+}
+
+/// Start a read transaction. Returns null on error.
+///
+/// # Safety
+/// `db` must be a valid database, which outlives the transaction.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_transaction_begin(db: *const OsmxDatabase) -> *mut OsmxTransaction {
+    guard(ptr::null_mut(), || {
+        // the caller guarantees that the database outlives the transaction
+        let db: &'static osmx::Database = &(*db).0;
+        let txn = osmx::Transaction::begin(db)?;
+        Ok(Box::into_raw(Box::new(OsmxTransaction(txn))))
+    })
+}
+
+/// End a transaction started with [osmx_transaction_begin].
+///
+/// # Safety
+/// `txn` must have been returned by [osmx_transaction_begin] (or be null).
+#[no_mangle]
+pub unsafe extern "C" fn osmx_transaction_free(txn: *mut OsmxTransaction) {
+    if !txn.is_null() {
+        drop(Box::from_raw(txn));
+    }
+}
+
+/// Get a node's location. Returns 1 and writes the coordinates (in degrees) if the node
+/// is found, 0 if it isn't, or -1 on error.
+///
+/// # Safety
+/// `txn` must be a valid transaction, and `lon` and `lat` valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_location_get(
+    txn: *const OsmxTransaction,
+    node_id: u64,
+    lon: *mut f64,
+    lat: *mut f64,
+) -> c_int {
+    guard(-1, || match (*txn).0.locations()?.get(node_id)? {
+        Some(location) => {
+            *lon = location.lon();
+            *lat = location.lat();
+            Ok(1)
+        }
+        None => Ok(0),
+    })
+}
+
+/// Callback receiving a tag's key and value, as NUL-terminated strings which are only
+/// valid during the call.
+pub type OsmxTagCallback =
+    extern "C" fn(key: *const c_char, value: *const c_char, userdata: *mut c_void);
+
+/// Call `callback` with each of an element's tags. `element_type` is one of OSMX_NODE,
+/// OSMX_WAY or OSMX_RELATION. Returns 1 if the element is found, 0 if it isn't (untagged
+/// nodes are not found), or -1 on error.
+///
+/// # Safety
+/// `txn` must be a valid transaction.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_element_tags(
+    txn: *const OsmxTransaction,
+    element_type: c_int,
+    id: u64,
+    callback: OsmxTagCallback,
+    userdata: *mut c_void,
+) -> c_int {
+    guard(-1, || {
+        let txn = &(*txn).0;
+        let tags = match element_type {
+            OSMX_NODE => txn.nodes()?.get(id)?.map(|e| owned_tags(e.tags())),
+            OSMX_WAY => txn.ways()?.get(id)?.map(|e| owned_tags(e.tags())),
+            OSMX_RELATION => txn.relations()?.get(id)?.map(|e| owned_tags(e.tags())),
+            _ => return Err(format!("invalid element type {}", element_type).into()),
+        };
+        match tags {
+            Some(tags) => {
+                for (key, value) in tags.iter() {
+                    callback(key.as_ptr(), value.as_ptr(), userdata);
+                }
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    })
+}
+
+fn owned_tags<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<(CString, CString)> {
+    let c_string = |s: &str| CString::new(s.replace('\0', " ")).unwrap();
+    tags.map(|(k, v)| (c_string(k), c_string(v))).collect()
+}
+
+/// Get a way's node IDs. Writes up to `capacity` IDs to `nodes`, and returns the way's
+/// total number of nodes (so if it's larger than `capacity`, call again with a larger
+/// buffer), or 0 if the way isn't found, or -1 on error.
+///
+/// # Safety
+/// `txn` must be a valid transaction, and `nodes` must point to space for `capacity` IDs.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_way_nodes(
+    txn: *const OsmxTransaction,
+    way_id: u64,
+    nodes: *mut u64,
+    capacity: usize,
+) -> i64 {
+    guard(-1, || {
+        let Some(way) = (*txn).0.ways()?.get(way_id)? else {
+            return Ok(0);
+        };
+        let mut count = 0;
+        for node_id in way.nodes() {
+            if count < capacity {
+                *nodes.add(count) = node_id;
+            }
+            count += 1;
+        }
+        Ok(count as i64)
+    })
+}
+
+/// Callback receiving a relation member's type (OSMX_NODE, OSMX_WAY or OSMX_RELATION),
+/// ID and role. The role is a NUL-terminated string which is only valid during the call.
+pub type OsmxMemberCallback =
+    extern "C" fn(member_type: c_int, id: u64, role: *const c_char, userdata: *mut c_void);
+
+/// Call `callback` with each of a relation's members, in order. Returns 1 if the relation
+/// is found, 0 if it isn't, or -1 on error.
+///
+/// # Safety
+/// `txn` must be a valid transaction.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_relation_members(
+    txn: *const OsmxTransaction,
+    relation_id: u64,
+    callback: OsmxMemberCallback,
+    userdata: *mut c_void,
+) -> c_int {
+    guard(-1, || {
+        let Some(relation) = (*txn).0.relations()?.get(relation_id)? else {
+            return Ok(0);
+        };
+        for member in relation.members() {
+            let (member_type, id) = match member.id() {
+                osmx::ElementId::Node(id) => (OSMX_NODE, id),
+                osmx::ElementId::Way(id) => (OSMX_WAY, id),
+                osmx::ElementId::Relation(id) => (OSMX_RELATION, id),
+            };
+            let role = CString::new(member.role().replace('\0', " ")).unwrap();
+            callback(member_type, id, role.as_ptr(), userdata);
+        }
+        Ok(1)
+    })
+}
+
+/// Callback receiving an element ID. Return true to continue iterating, or false to stop.
+pub type OsmxIdCallback = extern "C" fn(id: u64, userdata: *mut c_void) -> bool;
+
+/// Call `callback` with the ID of every element of the given type, in ID order. For
+/// OSMX_NODE, this includes untagged nodes. Returns 0 on success, or -1 on error.
+///
+/// # Safety
+/// `txn` must be a valid transaction.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_iterate(
+    txn: *const OsmxTransaction,
+    element_type: c_int,
+    callback: OsmxIdCallback,
+    userdata: *mut c_void,
+) -> c_int {
+    guard(-1, || {
+        let txn = &(*txn).0;
+        let ids: Box<dyn Iterator<Item = u64>> = match element_type {
+            OSMX_NODE => Box::new(txn.locations()?.ids()),
+            OSMX_WAY => Box::new(txn.ways()?.ids()),
+            OSMX_RELATION => Box::new(txn.relations()?.ids()),
+            _ => return Err(format!("invalid element type {}", element_type).into()),
+        };
+        for id in ids {
+            if !callback(id, userdata) {
+                break;
+            }
+        }
+        Ok(0)
+    })
+}
+
+/// Call `callback` with the ID of each node which may be within the bounding box (given
+/// in degrees), using the spatial index. Nodes near the box may also be included, so
+/// callers needing exact results should check each node's location. Returns 0 on success,
+/// or -1 on error.
+///
+/// # Safety
+/// `txn` must be a valid transaction.
+#[no_mangle]
+pub unsafe extern "C" fn osmx_nodes_in_bbox(
+    txn: *const OsmxTransaction,
+    west: f64,
+    south: f64,
+    east: f64,
+    north: f64,
+    callback: OsmxIdCallback,
+    userdata: *mut c_void,
+) -> c_int {
+    guard(-1, || {
+        let region = osmx::Region::from_bbox(west, south, east, north);
+        for node_id in (*txn).0.cell_nodes()?.find_in_region(&region) {
+            if !callback(node_id, userdata) {
+                break;
+            }
+        }
+        Ok(0)
+    })
+}
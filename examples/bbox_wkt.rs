@@ -7,8 +7,7 @@
 /// Ported from this C++ program, and should behave identically:
 /// https://github.com/protomaps/OSMExpress/blob/main/examples/bbox_wkt.cpp
 use std::error::Error;
-
-use itertools::Itertools;
+use std::io::Write;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = std::env::args().collect();
@@ -53,26 +52,29 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     // Print names and WKT geometries for each way
     for way_id in way_ids {
-        let way = ways.get(way_id).unwrap();
+        let way = ways.get(way_id)?.unwrap();
 
         // if the way has a "name" tag, print it
         if let Some(name) = way.tag("name") {
             print!("{}", name);
         }
 
-        // get the way's node refs, and look up each node's location
-        let coords = way.nodes().map(|node_id| {
-            let loc = locations.get(node_id).unwrap();
-            (loc.lon(), loc.lat())
-        });
+        // get the way's node refs, and look up each node's location, skipping the way if
+        // any are missing (as they can be in extracts)
+        let coords = match way.coordinates(&locations, osmx::geometry::MissingNodePolicy::Error) {
+            Ok(coords) => coords,
+            Err(e @ osmx::Error::Geometry(_)) => {
+                eprintln!("skipping way {}: {}", way_id, e);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
 
-        // print the resulting coords as a WKT linestring
-        println!(
-            "\tLINESTRING ({})",
-            coords
-                .map(|(lon, lat)| format!("{:.7} {:.7}", lon, lat))
-                .join(",")
-        );
+        // print the resulting coordinate sequence as a WKT linestring
+        let mut stdout = std::io::stdout();
+        write!(stdout, "\t")?;
+        osmx::wkt::write_linestring(&mut stdout, &coords)?;
+        writeln!(stdout)?;
     }
 
     Ok(())
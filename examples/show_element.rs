@@ -24,8 +24,8 @@ fn main() -> Result<(), Box<dyn Error>> {
             let locations = txn.locations()?;
 
             // look up the location and metadata for the node
-            let location = locations.get(element_id).expect("node not found");
-            let node = nodes.get(element_id); // may be None for untagged nodes
+            let location = locations.get(element_id)?.expect("node not found");
+            let node = nodes.get(element_id)?; // may be None for untagged nodes
 
             println!("Node {}", element_id);
             println!("Location: {:.7} {:.7}", location.lon(), location.lat());
@@ -57,7 +57,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             // get the ways table
             let ways = txn.ways()?;
             // look up the way by its ID
-            let way = ways.get(element_id).expect("way not found");
+            let way = ways.get(element_id)?.expect("way not found");
 
             println!("Way {}", element_id);
 
@@ -85,7 +85,7 @@ fn main() -> Result<(), Box<dyn Error>> {
             // get the relations table
             let relations = txn.relations()?;
             // look up the relation by its ID
-            let relation = relations.get(element_id).expect("relation not found");
+            let relation = relations.get(element_id)?.expect("relation not found");
 
             println!("Relation {}", element_id);
 
@@ -6,8 +6,7 @@
 /// Ported from this C++ program, and should behave identically:
 /// https://github.com/protomaps/OSMExpress/blob/main/examples/way_wkt.cpp
 use std::error::Error;
-
-use itertools::Itertools;
+use std::io::Write;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<String> = std::env::args().collect();
@@ -26,26 +25,22 @@ fn main() -> Result<(), Box<dyn Error>> {
     let locations = txn.locations()?;
 
     // look up the given way ID in the ways table
-    let way = ways.get(way_id).expect("way not found");
+    let way = ways.get(way_id)?.expect("way not found");
 
     // if the way has a "name" tag, print it
     if let Some(name) = way.tag("name") {
         print!("{}", name);
     }
 
-    // get the way's node refs, and look up each node's location
-    let coords = way.nodes().map(|node_id| {
-        let loc = locations.get(node_id).unwrap();
-        (loc.lon(), loc.lat())
-    });
+    // get the way's node refs, and look up each node's location (failing if any are
+    // missing, as they can be in extracts)
+    let coords = way.coordinates(&locations, osmx::geometry::MissingNodePolicy::Error)?;
 
     // print the resulting coordinate sequence as a WKT linestring
-    println!(
-        "\tLINESTRING ({})",
-        coords
-            .map(|(lon, lat)| format!("{:.7} {:.7}", lon, lat))
-            .join(",")
-    );
+    let mut stdout = std::io::stdout();
+    write!(stdout, "\t")?;
+    osmx::wkt::write_linestring(&mut stdout, &coords)?;
+    writeln!(stdout)?;
 
     Ok(())
 }
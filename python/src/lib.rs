@@ -0,0 +1,429 @@
+//! Python bindings for reading `.osmx` files, built into the `osmx` Python module with
+//! maturin (see `pyproject.toml`).
+//!
+//! The bindings are read-only. Elements are read straight from the database's memory map:
+//! looking one up doesn't copy its tags, node list or members, which are only converted to
+//! Python objects when they're accessed. Each element keeps its transaction (and each
+//! transaction its database) alive, so the data it refers to stays mapped for as long as
+//! Python code holds it. Errors are raised as `osmx.OsmxError`.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use pyo3::create_exception;
+use pyo3::exceptions::{PyException, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+
+create_exception!(
+    osmx,
+    OsmxError,
+    PyException,
+    "An error reading an .osmx file"
+);
+
+/// An error from the library, which is raised as OsmxError
+struct Error(osmx::Error);
+
+impl From<osmx::Error> for Error {
+    fn from(e: osmx::Error) -> Self {
+        Self(e)
+    }
+}
+
+impl From<Error> for PyErr {
+    fn from(e: Error) -> Self {
+        OsmxError::new_err(e.0.to_string())
+    }
+}
+
+/// An open database.
+#[pyclass(module = "osmx", frozen)]
+struct Database(osmx::Database);
+
+#[pymethods]
+impl Database {
+    #[new]
+    fn open(path: PathBuf) -> Result<Self, Error> {
+        Ok(Self(osmx::Database::open(path)?))
+    }
+
+    /// Start a read transaction, which sees a consistent snapshot of the database.
+    fn transaction(slf: Bound<'_, Self>) -> Result<Transaction, Error> {
+        Transaction::begin(slf.unbind())
+    }
+}
+
+/// A read transaction. It ends when it (and every element read in it) is garbage
+/// collected.
+#[pyclass(module = "osmx", unsendable)]
+struct Transaction {
+    // borrows from the database, so it's declared first to be dropped first
+    txn: osmx::Transaction<'static>,
+    _db: Py<Database>,
+}
+
+impl Transaction {
+    fn begin(db: Py<Database>) -> Result<Self, Error> {
+        // the database is kept alive by the handle stored with the transaction, and the
+        // contents of a Python object never move
+        let database: &'static osmx::Database = unsafe { &*(&db.get().0 as *const _) };
+        Ok(Self {
+            txn: osmx::Transaction::begin(database)?,
+            _db: db,
+        })
+    }
+}
+
+/// Borrow the transaction of a handle, for as long as the handle is kept.
+///
+/// # Safety
+/// The returned reference, and anything borrowed from it, must not outlive `txn`.
+unsafe fn transaction(
+    py: Python<'_>,
+    txn: &Py<Transaction>,
+) -> &'static osmx::Transaction<'static> {
+    &*(&txn.borrow(py).txn as *const _)
+}
+
+#[pymethods]
+impl Transaction {
+    #[new]
+    fn new(db: Py<Database>) -> Result<Self, Error> {
+        Self::begin(db)
+    }
+
+    /// The locations table, mapping node IDs to (lon, lat) tuples. Unlike the nodes table,
+    /// it has every node.
+    #[getter]
+    fn locations(slf: Bound<'_, Self>) -> Table {
+        Table::new(slf, Kind::Locations)
+    }
+
+    /// The nodes table, which only has nodes with tags or metadata.
+    #[getter]
+    fn nodes(slf: Bound<'_, Self>) -> Table {
+        Table::new(slf, Kind::Nodes)
+    }
+
+    #[getter]
+    fn ways(slf: Bound<'_, Self>) -> Table {
+        Table::new(slf, Kind::Ways)
+    }
+
+    #[getter]
+    fn relations(slf: Bound<'_, Self>) -> Table {
+        Table::new(slf, Kind::Relations)
+    }
+
+    /// The IDs of the nodes in a bounding box (in degrees), found with the spatial index.
+    fn nodes_in_bbox(
+        &self,
+        west: f64,
+        south: f64,
+        east: f64,
+        north: f64,
+    ) -> Result<Vec<u64>, Error> {
+        let region = osmx::Region::from_bbox(west, south, east, north);
+        let locations = self.txn.locations()?;
+        let mut ids = vec![];
+        // the spatial index may return nodes near the box, so check their locations
+        for id in self.txn.cell_nodes()?.find_in_region(&region) {
+            if let Some(location) = locations.get(id)? {
+                let (lon, lat) = (location.lon(), location.lat());
+                if west <= lon && lon <= east && south <= lat && lat <= north {
+                    ids.push(id);
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// The IDs of the ways which may be in a bounding box (in degrees). Ways near the box
+    /// may be included.
+    fn ways_in_bbox(
+        &self,
+        west: f64,
+        south: f64,
+        east: f64,
+        north: f64,
+    ) -> Result<Vec<u64>, Error> {
+        let region = osmx::Region::from_bbox(west, south, east, north);
+        Ok(self.txn.ways_in_region(&region)?)
+    }
+
+    /// The IDs of the relations which may be in a bounding box (in degrees). Relations near
+    /// the box may be included.
+    fn relations_in_bbox(
+        &self,
+        west: f64,
+        south: f64,
+        east: f64,
+        north: f64,
+    ) -> Result<Vec<u64>, Error> {
+        let region = osmx::Region::from_bbox(west, south, east, north);
+        Ok(self.txn.relations_in_region(&region)?)
+    }
+
+    /// Find the elements in a bounding box (in degrees) whose tags match a filter
+    /// expression (such as "highway and not highway=footway"). Returns a dict of the IDs of
+    /// the matching "nodes", "ways" and "relations".
+    fn query(
+        &self,
+        west: f64,
+        south: f64,
+        east: f64,
+        north: f64,
+        filter: &str,
+    ) -> PyResult<HashMap<&'static str, Vec<u64>>> {
+        let filter: osmx::Filter = filter
+            .parse()
+            .map_err(|e: osmx::FilterError| PyValueError::new_err(e.to_string()))?;
+        let region = osmx::Region::from_bbox(west, south, east, north);
+        let ids = self.txn.query(&region, &filter).map_err(Error)?;
+        Ok(HashMap::from([
+            ("nodes", ids.nodes.iter().collect()),
+            ("ways", ids.ways.iter().collect()),
+            ("relations", ids.relations.iter().collect()),
+        ]))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Locations,
+    Nodes,
+    Ways,
+    Relations,
+}
+
+/// One of the element tables of a transaction. Tables can be indexed by ID (raising
+/// KeyError for missing elements), and iterate over their IDs in order.
+#[pyclass(module = "osmx", unsendable)]
+struct Table {
+    txn: Py<Transaction>,
+    kind: Kind,
+}
+
+impl Table {
+    fn new(txn: Bound<'_, Transaction>, kind: Kind) -> Self {
+        Self {
+            txn: txn.unbind(),
+            kind,
+        }
+    }
+}
+
+#[pymethods]
+impl Table {
+    /// Look up an element: a (lon, lat) tuple in the locations table, or a Node, Way or
+    /// Relation. Returns None if it isn't found.
+    fn get(&self, py: Python<'_>, id: u64) -> Result<Option<PyObject>, Error> {
+        // the elements keep a handle to the transaction
+        let txn = unsafe { transaction(py, &self.txn) };
+        let handle = || self.txn.clone_ref(py);
+        Ok(match self.kind {
+            Kind::Locations => txn
+                .locations()?
+                .get(id)?
+                .map(|location| (location.lon(), location.lat()).into_py(py)),
+            Kind::Nodes => txn.nodes()?.get(id)?.map(|node| {
+                Node {
+                    id,
+                    node,
+                    _txn: handle(),
+                }
+                .into_py(py)
+            }),
+            Kind::Ways => txn.ways()?.get(id)?.map(|way| {
+                Way {
+                    id,
+                    way,
+                    _txn: handle(),
+                }
+                .into_py(py)
+            }),
+            Kind::Relations => txn.relations()?.get(id)?.map(|relation| {
+                Relation {
+                    id,
+                    relation,
+                    _txn: handle(),
+                }
+                .into_py(py)
+            }),
+        })
+    }
+
+    fn __getitem__(&self, py: Python<'_>, id: u64) -> PyResult<PyObject> {
+        self.get(py, id)?.ok_or_else(|| PyKeyError::new_err(id))
+    }
+
+    fn __contains__(&self, py: Python<'_>, id: u64) -> Result<bool, Error> {
+        let txn = unsafe { transaction(py, &self.txn) };
+        Ok(match self.kind {
+            Kind::Locations => txn.locations()?.contains(id)?,
+            Kind::Nodes => txn.nodes()?.contains(id)?,
+            Kind::Ways => txn.ways()?.contains(id)?,
+            Kind::Relations => txn.relations()?.contains(id)?,
+        })
+    }
+
+    fn __len__(&self, py: Python<'_>) -> Result<usize, Error> {
+        let txn = unsafe { transaction(py, &self.txn) };
+        let len = match self.kind {
+            Kind::Locations => txn.locations()?.len(),
+            Kind::Nodes => txn.nodes()?.len(),
+            Kind::Ways => txn.ways()?.len(),
+            Kind::Relations => txn.relations()?.len(),
+        };
+        Ok(len as usize)
+    }
+
+    fn __iter__(&self, py: Python<'_>) -> Result<Ids, Error> {
+        // the iterator keeps a handle to the transaction
+        let txn = unsafe { transaction(py, &self.txn) };
+        let ids: Box<dyn Iterator<Item = u64>> = match self.kind {
+            Kind::Locations => Box::new(txn.locations()?.ids()),
+            Kind::Nodes => Box::new(txn.nodes()?.ids()),
+            Kind::Ways => Box::new(txn.ways()?.ids()),
+            Kind::Relations => Box::new(txn.relations()?.ids()),
+        };
+        Ok(Ids {
+            ids,
+            _txn: self.txn.clone_ref(py),
+        })
+    }
+}
+
+/// An iterator over the IDs in a table.
+#[pyclass(module = "osmx", unsendable)]
+struct Ids {
+    // borrows from the transaction, so it's declared first to be dropped first
+    ids: Box<dyn Iterator<Item = u64>>,
+    _txn: Py<Transaction>,
+}
+
+#[pymethods]
+impl Ids {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self) -> Option<u64> {
+        self.ids.next()
+    }
+}
+
+/// A node with tags or metadata. Its location is in the locations table.
+#[pyclass(module = "osmx", unsendable)]
+struct Node {
+    #[pyo3(get)]
+    id: u64,
+    node: osmx::Node<'static>,
+    _txn: Py<Transaction>,
+}
+
+#[pymethods]
+impl Node {
+    /// The node's tags, as a dict.
+    #[getter]
+    fn tags(&self) -> HashMap<&str, &str> {
+        self.node.tags().collect()
+    }
+
+    /// The value of a tag, or None if the node doesn't have it.
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.node.tag(key)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Node({})", self.id)
+    }
+}
+
+#[pyclass(module = "osmx", unsendable)]
+struct Way {
+    #[pyo3(get)]
+    id: u64,
+    way: osmx::Way<'static>,
+    _txn: Py<Transaction>,
+}
+
+#[pymethods]
+impl Way {
+    /// The way's tags, as a dict.
+    #[getter]
+    fn tags(&self) -> HashMap<&str, &str> {
+        self.way.tags().collect()
+    }
+
+    /// The value of a tag, or None if the way doesn't have it.
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.way.tag(key)
+    }
+
+    /// The IDs of the way's nodes, in order.
+    #[getter]
+    fn nodes(&self) -> Vec<u64> {
+        self.way.nodes().collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Way({})", self.id)
+    }
+}
+
+#[pyclass(module = "osmx", unsendable)]
+struct Relation {
+    #[pyo3(get)]
+    id: u64,
+    relation: osmx::Relation<'static>,
+    _txn: Py<Transaction>,
+}
+
+#[pymethods]
+impl Relation {
+    /// The relation's tags, as a dict.
+    #[getter]
+    fn tags(&self) -> HashMap<&str, &str> {
+        self.relation.tags().collect()
+    }
+
+    /// The value of a tag, or None if the relation doesn't have it.
+    fn tag(&self, key: &str) -> Option<&str> {
+        self.relation.tag(key)
+    }
+
+    /// The relation's members, in order, as (type, id, role) tuples, where the type is
+    /// "node", "way" or "relation".
+    #[getter]
+    fn members(&self) -> Vec<(&'static str, u64, String)> {
+        self.relation
+            .members()
+            .map(|member| {
+                let (member_type, id) = match member.id() {
+                    osmx::ElementId::Node(id) => ("node", id),
+                    osmx::ElementId::Way(id) => ("way", id),
+                    osmx::ElementId::Relation(id) => ("relation", id),
+                };
+                (member_type, id, member.role().to_string())
+            })
+            .collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Relation({})", self.id)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "osmx")]
+fn osmx_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Database>()?;
+    m.add_class::<Transaction>()?;
+    m.add_class::<Table>()?;
+    m.add_class::<Node>()?;
+    m.add_class::<Way>()?;
+    m.add_class::<Relation>()?;
+    m.add("OsmxError", m.py().get_type_bound::<OsmxError>())?;
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! Point-in-polygon tests, and (with the `export` feature, which assembles the rings of
+//! relations) finding the administrative boundaries containing a point.
+
+#[cfg(feature = "export")]
+use roaring::RoaringTreemap;
+
+#[cfg(feature = "export")]
+use crate::database::Transaction;
+#[cfg(feature = "export")]
+use crate::geometry::relation_rings;
+#[cfg(feature = "export")]
+use crate::types::Region;
+#[cfg(feature = "export")]
+use crate::Error;
+
+/// Half the height, in degrees of latitude, of the strip searched for boundary ways
+#[cfg(feature = "export")]
+const SEARCH_MARGIN: f64 = 0.05;
+
+/// Width, in degrees of longitude, of each part of the strip queried from the spatial index
+#[cfg(feature = "export")]
+const SEARCH_STEP: f64 = 1.0;
+
+/// An administrative area, read from a `boundary=administrative` relation.
+#[cfg(feature = "export")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Boundary {
+    pub relation_id: u64,
+    /// The relation's admin_level tag, e.g. 2 for countries. None if the tag is missing
+    /// or isn't a number.
+    pub admin_level: Option<u8>,
+    pub name: Option<String>,
+}
+
+/// Whether the point is inside the rings, using the even-odd rule (so that inner rings
+/// are holes in the outer rings which contain them). Coordinates are treated as planar.
+pub(crate) fn rings_contain(rings: &[Vec<(f64, f64)>], (x, y): (f64, f64)) -> bool {
+    let mut inside = false;
+    for ring in rings {
+        for edge in ring.windows(2) {
+            let (a, b) = (edge[0], edge[1]);
+            if (a.1 > y) != (b.1 > y) && x < (b.0 - a.0) * (y - a.1) / (b.1 - a.1) + a.0 {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+#[cfg(feature = "export")]
+impl Transaction<'_> {
+    /// Find the administrative areas (`boundary=administrative` relations) which contain
+    /// the given point, ordered by admin_level (so countries come before the areas within
+    /// them). Boundaries whose member ways or nodes are missing from the database (such as
+    /// those cut by the edge of an extract) are skipped.
+    ///
+    /// Candidate boundaries are found by searching the spatial index for nodes in a strip
+    /// east of the point, since every area containing the point has a boundary crossing
+    /// the ray east from it. Boundaries whose ways have no nodes within SEARCH_MARGIN
+    /// degrees of latitude of the ray may be missed, as may boundaries which cross the
+    /// antimeridian.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub fn boundaries_containing(&self, lon: f64, lat: f64) -> Result<Vec<Boundary>, Error> {
+        let cell_nodes = self.cell_nodes()?;
+        let node_ways = self.node_ways()?;
+        let way_relations = self.way_relations()?;
+        let locations = self.locations()?;
+        let ways = self.ways()?;
+        let relations = self.relations()?;
+
+        let mut seen_ways = RoaringTreemap::new();
+        let mut candidates = RoaringTreemap::new();
+        let mut west = lon;
+        while west < 180.0 {
+            let east = (west + SEARCH_STEP).min(180.0);
+            let region = Region::from_bbox(west, lat - SEARCH_MARGIN, east, lat + SEARCH_MARGIN);
+            for node_id in cell_nodes.find_in_region(&region) {
+                for way_id in node_ways.get(node_id) {
+                    if seen_ways.insert(way_id) {
+                        candidates.extend(way_relations.get(way_id));
+                    }
+                }
+            }
+            west = east;
+        }
+
+        let mut boundaries = vec![];
+        for relation_id in candidates {
+            let Some(relation) = relations.get(relation_id)? else {
+                continue;
+            };
+            if relation.tag("boundary") != Some("administrative") {
+                continue;
+            }
+            let Some(rings) = relation_rings(&relation, &ways, &locations)? else {
+                continue;
+            };
+            if rings_contain(&rings, (lon, lat)) {
+                boundaries.push(Boundary {
+                    relation_id,
+                    admin_level: relation.tag("admin_level").and_then(|l| l.parse().ok()),
+                    name: relation.tag("name").map(|name| name.to_string()),
+                });
+            }
+        }
+
+        // boundaries without an admin_level sort last
+        boundaries.sort_by_key(|b| (b.admin_level.is_none(), b.admin_level, b.relation_id));
+        Ok(boundaries)
+    }
+}
@@ -0,0 +1,411 @@
+//! Builders for the values stored in the element and changeset tables, for applications
+//! which write databases (like `osmx expand`). They produce the same encoded values the
+//! readers in this crate decode, without exposing the Cap'n Proto schema they're
+//! serialized with.
+//!
+//! Each builder makes one value; set its fields and call `build` to get the bytes to
+//! store under the element's key (see [crate::encode_key]). Values are built with the
+//! database's [ValueEncoding], and are not compressed (see [crate::Dictionary] for
+//! databases with compressed values).
+
+use crate::database::ValueEncoding;
+use crate::messages_capnp;
+#[cfg(feature = "update")]
+use crate::types::ElementId;
+use crate::Error;
+
+/// Write a tag list to an element builder, storing interned strings by ID in its
+/// tagIds list and leaving their entries in the tags list empty. (The node, way and
+/// relation builders are distinct types, so this is a macro rather than a function.)
+macro_rules! write_interned_tags {
+    ($root:expr, $tags:expr, $string_id:expr) => {{
+        let ids: Vec<Option<u32>> = $tags.iter().map(|tag| $string_id(tag)).collect();
+        let mut tag_list = $root.reborrow().init_tags($tags.len() as u32);
+        for (idx, tag) in $tags.iter().enumerate() {
+            if ids[idx].is_none() {
+                tag_list.set(idx as u32, *tag);
+            }
+        }
+        let mut id_list = $root.reborrow().init_tag_ids($tags.len() as u32);
+        for (idx, id) in ids.iter().enumerate() {
+            id_list.set(idx as u32, id.unwrap_or(0));
+        }
+    }};
+}
+
+/// Write an element's metadata to an element builder
+macro_rules! write_metadata {
+    ($root:expr, $metadata:expr) => {{
+        let mut builder = $root.reborrow().init_metadata();
+        builder.set_version($metadata.version);
+        builder.set_timestamp($metadata.timestamp);
+        builder.set_changeset($metadata.changeset);
+        builder.set_uid($metadata.uid);
+        builder.set_user($metadata.user.as_str());
+        builder.set_deleted($metadata.deleted);
+    }};
+}
+
+/// Metadata about one version of an element
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElementMetadata {
+    pub version: u32,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub changeset: u32,
+    pub uid: u32,
+    pub user: String,
+    pub deleted: bool,
+}
+
+/// Serialize a Cap'n Proto message using the given encoding
+pub fn encode_message<A: capnp::message::Allocator>(
+    message: &capnp::message::Builder<A>,
+    encoding: ValueEncoding,
+) -> Vec<u8> {
+    let mut buf = vec![];
+    match encoding {
+        ValueEncoding::Standard => capnp::serialize::write_message(&mut buf, message).unwrap(),
+        ValueEncoding::Packed => capnp::serialize_packed::write_message(&mut buf, message).unwrap(),
+    }
+    buf
+}
+
+/// Format a value of the nodes, ways, relations or changesets table (or of one of their
+/// history tables, given the name of the element table) with its fields' names, for
+/// debugging.
+pub fn debug_message<A: capnp::message::Allocator>(
+    table: &str,
+    message: &capnp::message::Builder<A>,
+) -> Result<String, Error> {
+    Ok(match table {
+        "nodes" => format!(
+            "{:?}",
+            message.get_root_as_reader::<messages_capnp::node::Reader>()?
+        ),
+        "ways" => format!(
+            "{:?}",
+            message.get_root_as_reader::<messages_capnp::way::Reader>()?
+        ),
+        "relations" => format!(
+            "{:?}",
+            message.get_root_as_reader::<messages_capnp::relation::Reader>()?
+        ),
+        "changesets" => format!(
+            "{:?}",
+            message.get_root_as_reader::<messages_capnp::changeset::Reader>()?
+        ),
+        _ => return Err(format!("{} doesn't store messages", table).into()),
+    })
+}
+
+/// The type of a relation member
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ElementType {
+    Node,
+    Way,
+    Relation,
+}
+
+/// Builds a value of the locations table
+pub struct LocationBuilder {
+    pub longitude: f64,
+    pub latitude: f64,
+    pub version: u32,
+}
+
+impl LocationBuilder {
+    pub fn build(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend(((self.longitude * 1e7).round() as i32).to_le_bytes());
+        buf.extend(((self.latitude * 1e7).round() as i32).to_le_bytes());
+        buf.extend(self.version.to_le_bytes());
+        buf
+    }
+
+    /// The coordinates as stored, in units of 1e-7 degrees
+    #[cfg(feature = "update")]
+    pub(crate) fn fixed(&self) -> (i32, i32) {
+        (
+            (self.longitude * 1e7).round() as i32,
+            (self.latitude * 1e7).round() as i32,
+        )
+    }
+}
+
+/// Builds a value of the nodes table
+pub struct NodeBuilder {
+    builder: capnp::message::TypedBuilder<messages_capnp::node::Owned>,
+}
+
+impl NodeBuilder {
+    pub fn new() -> Self {
+        Self {
+            builder: capnp::message::TypedBuilder::<messages_capnp::node::Owned>::new_default(),
+        }
+    }
+
+    /// Set the tags, as alternating keys and values
+    pub fn set_tags(&mut self, tags: &[&str]) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_tags(tags).unwrap();
+        self
+    }
+
+    /// Set the tags, storing the strings which `string_id` gives an ID (from the
+    /// database's string table) by ID
+    pub fn set_interned_tags(
+        &mut self,
+        tags: &[&str],
+        string_id: impl Fn(&str) -> Option<u32>,
+    ) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        write_interned_tags!(root, tags, string_id);
+        self
+    }
+
+    /// Refer to a shared tag set by its hash instead of storing the tags
+    pub fn set_tag_set(&mut self, hash: u64) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_tag_set(hash);
+        self
+    }
+
+    pub fn set_metadata(&mut self, metadata: &ElementMetadata) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        write_metadata!(root, metadata);
+        self
+    }
+
+    pub fn build(&self, encoding: ValueEncoding) -> Vec<u8> {
+        encode_message(self.builder.borrow_inner(), encoding)
+    }
+}
+
+impl Default for NodeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds a value of the ways table
+pub struct WayBuilder {
+    builder: capnp::message::TypedBuilder<messages_capnp::way::Owned>,
+}
+
+impl WayBuilder {
+    pub fn new() -> Self {
+        Self {
+            builder: capnp::message::TypedBuilder::<messages_capnp::way::Owned>::new_default(),
+        }
+    }
+
+    /// Set the tags, as alternating keys and values
+    pub fn set_tags(&mut self, tags: &[&str]) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_tags(tags).unwrap();
+        self
+    }
+
+    /// Set the tags, storing the strings which `string_id` gives an ID (from the
+    /// database's string table) by ID
+    pub fn set_interned_tags(
+        &mut self,
+        tags: &[&str],
+        string_id: impl Fn(&str) -> Option<u32>,
+    ) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        write_interned_tags!(root, tags, string_id);
+        self
+    }
+
+    /// Refer to a shared tag set by its hash instead of storing the tags
+    pub fn set_tag_set(&mut self, hash: u64) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_tag_set(hash);
+        self
+    }
+
+    pub fn set_metadata(&mut self, metadata: &ElementMetadata) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        write_metadata!(root, metadata);
+        self
+    }
+
+    pub fn set_nodes(&mut self, nodes: &[u64]) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_nodes(nodes).unwrap();
+        self
+    }
+
+    /// The node IDs set with [Self::set_nodes]
+    #[cfg(feature = "update")]
+    pub(crate) fn nodes(&self) -> Vec<u64> {
+        let root = self.builder.get_root_as_reader().unwrap();
+        root.get_nodes().unwrap().iter().collect()
+    }
+
+    pub fn build(&self, encoding: ValueEncoding) -> Vec<u8> {
+        encode_message(self.builder.borrow_inner(), encoding)
+    }
+}
+
+impl Default for WayBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds a value of the relations table
+pub struct RelationBuilder {
+    builder: capnp::message::TypedBuilder<messages_capnp::relation::Owned>,
+}
+
+impl RelationBuilder {
+    pub fn new() -> Self {
+        Self {
+            builder: capnp::message::TypedBuilder::<messages_capnp::relation::Owned>::new_default(),
+        }
+    }
+
+    /// Set the tags, as alternating keys and values
+    pub fn set_tags(&mut self, tags: &[&str]) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_tags(tags).unwrap();
+        self
+    }
+
+    /// Set the tags, storing the strings which `string_id` gives an ID (from the
+    /// database's string table) by ID
+    pub fn set_interned_tags(
+        &mut self,
+        tags: &[&str],
+        string_id: impl Fn(&str) -> Option<u32>,
+    ) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        write_interned_tags!(root, tags, string_id);
+        self
+    }
+
+    /// Refer to a shared tag set by its hash instead of storing the tags
+    pub fn set_tag_set(&mut self, hash: u64) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_tag_set(hash);
+        self
+    }
+
+    pub fn set_metadata(&mut self, metadata: &ElementMetadata) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        write_metadata!(root, metadata);
+        self
+    }
+
+    /// Set the members, as (type, ID, role)
+    pub fn set_members(&mut self, members: &[(ElementType, u64, String)]) -> &Self {
+        let mut builder = self
+            .builder
+            .get_root()
+            .unwrap()
+            .init_members(members.len() as u32);
+
+        for (idx, (element_type, id, role)) in members.iter().enumerate() {
+            let mut mbuilder = builder.reborrow().get(idx as u32);
+
+            let t = match element_type {
+                ElementType::Node => messages_capnp::relation_member::Type::Node,
+                ElementType::Way => messages_capnp::relation_member::Type::Way,
+                ElementType::Relation => messages_capnp::relation_member::Type::Relation,
+            };
+
+            mbuilder.set_type(t);
+            mbuilder.set_ref(*id);
+            mbuilder.set_role(role.as_str());
+        }
+
+        self
+    }
+
+    /// The elements set with [Self::set_members]
+    #[cfg(feature = "update")]
+    pub(crate) fn members(&self) -> Vec<ElementId> {
+        use messages_capnp::relation_member::Type;
+        let root = self.builder.get_root_as_reader().unwrap();
+        root.get_members()
+            .unwrap()
+            .iter()
+            .map(|member| match member.get_type().unwrap() {
+                Type::Node => ElementId::Node(member.get_ref()),
+                Type::Way => ElementId::Way(member.get_ref()),
+                Type::Relation => ElementId::Relation(member.get_ref()),
+            })
+            .collect()
+    }
+
+    pub fn build(&self, encoding: ValueEncoding) -> Vec<u8> {
+        encode_message(self.builder.borrow_inner(), encoding)
+    }
+}
+
+impl Default for RelationBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builds a value of the changesets table
+pub struct ChangesetBuilder {
+    builder: capnp::message::TypedBuilder<messages_capnp::changeset::Owned>,
+}
+
+impl ChangesetBuilder {
+    pub fn new() -> Self {
+        Self {
+            builder: capnp::message::TypedBuilder::<messages_capnp::changeset::Owned>::new_default(
+            ),
+        }
+    }
+
+    pub fn set_user(&mut self, uid: u32, user: &str) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_uid(uid);
+        root.set_user(user);
+        self
+    }
+
+    /// Set the times of the earliest and latest edits, in seconds since the Unix epoch
+    pub fn set_timestamps(&mut self, min: u64, max: u64) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_min_timestamp(min);
+        root.set_max_timestamp(max);
+        self
+    }
+
+    pub fn set_counts(&mut self, nodes: u32, ways: u32, relations: u32) -> &Self {
+        let mut root = self.builder.get_root().unwrap();
+        root.set_node_count(nodes);
+        root.set_way_count(ways);
+        root.set_relation_count(relations);
+        self
+    }
+
+    /// Set the bounding box, in units of 1e-7 degrees
+    pub fn set_bbox(&mut self, west: i32, south: i32, east: i32, north: i32) -> &Self {
+        let mut bbox = self.builder.get_root().unwrap().init_bbox();
+        bbox.set_west(west);
+        bbox.set_south(south);
+        bbox.set_east(east);
+        bbox.set_north(north);
+        self
+    }
+
+    /// Changesets are always stored using the standard encoding
+    pub fn build(&self) -> Vec<u8> {
+        encode_message(self.builder.borrow_inner(), ValueEncoding::Standard)
+    }
+}
+
+impl Default for ChangesetBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,83 @@
+use std::cell::{Cell, RefCell};
+use std::num::NonZeroUsize;
+
+use lru::LruCache;
+
+use crate::database::Locations;
+use crate::Error;
+
+/// Counters describing how effective a [CachedLocations] cache has been.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of lookups that were answered from the cache.
+    pub hits: u64,
+    /// Number of lookups that had to read from the underlying table.
+    pub misses: u64,
+}
+
+/// A wrapper around the Locations table which keeps the decoded coordinates of
+/// recently accessed nodes in a fixed-size LRU cache. This is useful for workloads
+/// like geometry assembly which look up the same nodes many times (e.g. nodes
+/// shared between adjacent ways).
+pub struct CachedLocations<'txn> {
+    locations: Locations<'txn>,
+    cache: RefCell<LruCache<u64, (f64, f64)>>,
+    stats: Cell<CacheStats>,
+}
+
+impl<'txn> CachedLocations<'txn> {
+    /// Wrap the given Locations table in a cache holding up to `capacity` entries.
+    /// Panics if `capacity` is zero.
+    pub fn new(locations: Locations<'txn>, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).expect("cache capacity must be nonzero");
+        Self {
+            locations,
+            cache: RefCell::new(LruCache::new(capacity)),
+            stats: Cell::new(CacheStats::default()),
+        }
+    }
+
+    /// Get the (lon, lat) coordinates of a node by its ID. Returns Ok(None) if the node
+    /// is not found. Missing nodes are not cached.
+    pub fn get(&self, id: u64) -> Result<Option<(f64, f64)>, Error> {
+        let mut stats = self.stats.get();
+        let mut cache = self.cache.borrow_mut();
+
+        if let Some(&coords) = cache.get(&id) {
+            stats.hits += 1;
+            self.stats.set(stats);
+            return Ok(Some(coords));
+        }
+
+        stats.misses += 1;
+        self.stats.set(stats);
+
+        let Some(loc) = self.locations.get(id)? else {
+            return Ok(None);
+        };
+        let coords = (loc.lon(), loc.lat());
+        cache.put(id, coords);
+        Ok(Some(coords))
+    }
+
+    /// Returns the hit/miss counters accumulated since this cache was created.
+    pub fn stats(&self) -> CacheStats {
+        self.stats.get()
+    }
+
+    /// Returns the number of entries currently held in the cache.
+    pub fn len(&self) -> usize {
+        self.cache.borrow().len()
+    }
+
+    /// Returns true if the cache is currently empty.
+    pub fn is_empty(&self) -> bool {
+        self.cache.borrow().is_empty()
+    }
+
+    /// Remove all entries from the cache and reset the hit/miss counters.
+    pub fn clear(&self) {
+        self.cache.borrow_mut().clear();
+        self.stats.set(CacheStats::default());
+    }
+}
@@ -0,0 +1,315 @@
+//! Assembly of `natural=coastline` ways into land polygons.
+//!
+//! Coastline ways are drawn with the land on their left, so once they're joined end to
+//! end, a ring running counterclockwise is the outline of a landmass (a continent or an
+//! island), and a clockwise ring is a body of water enclosed by land (like the Caspian
+//! Sea), which is a hole in the landmass around it.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+use roaring::RoaringTreemap;
+
+use crate::boundaries::rings_contain;
+use crate::database::{JoinTable, Transaction, Ways};
+use crate::query::region_contains;
+use crate::types::Region;
+use crate::Error;
+
+/// A landmass assembled from coastline ways, returned as part of [Coastline].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LandPolygon {
+    /// The coastline around the land, running counterclockwise, as (longitude, latitude)
+    /// pairs in degrees. The first and last points are the same.
+    pub exterior: Vec<(f64, f64)>,
+    /// The coastlines of the seas enclosed by the land, each running clockwise.
+    pub holes: Vec<Vec<(f64, f64)>>,
+    /// The IDs of the ways the exterior was assembled from, in order.
+    pub way_ids: Vec<u64>,
+}
+
+/// A reason some coastline ways couldn't be assembled into a land polygon, returned as
+/// part of [Coastline].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoastlineProblem {
+    /// The ways, joined in this order, don't form a closed ring, because no coastline way
+    /// continues from the last node (or leads to the first). This happens where a
+    /// coastline runs out of an extract, and where the coastline is broken.
+    Open {
+        way_ids: Vec<u64>,
+        first_node: u64,
+        last_node: u64,
+    },
+    /// A closed ring of these ways includes a node which has no location.
+    MissingNode { way_ids: Vec<u64>, node_id: u64 },
+    /// A closed ring of these ways encloses no area (it has fewer than three distinct
+    /// points, or they're all on a line).
+    Degenerate { way_ids: Vec<u64> },
+}
+
+impl fmt::Display for CoastlineProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ways = |way_ids: &[u64]| match way_ids {
+            [id] => format!("way {}", id),
+            [first, .., last] => format!("{} ways from {} to {}", way_ids.len(), first, last),
+            [] => "no ways".to_string(),
+        };
+        match self {
+            CoastlineProblem::Open {
+                way_ids,
+                first_node,
+                last_node,
+            } => write!(
+                f,
+                "coastline of {} is open: it starts at node {} and ends at node {}",
+                ways(way_ids),
+                first_node,
+                last_node
+            ),
+            CoastlineProblem::MissingNode { way_ids, node_id } => write!(
+                f,
+                "coastline of {} includes node {}, which has no location",
+                ways(way_ids),
+                node_id
+            ),
+            CoastlineProblem::Degenerate { way_ids } => {
+                write!(f, "coastline of {} encloses no area", ways(way_ids))
+            }
+        }
+    }
+}
+
+/// The land polygons assembled by [Transaction::coastline], and the coastline ways which
+/// couldn't be assembled into them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coastline {
+    pub land: Vec<LandPolygon>,
+    pub problems: Vec<CoastlineProblem>,
+}
+
+/// Twice the signed area of a ring (positive if it runs counterclockwise), treating
+/// coordinates as planar
+fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    ring.windows(2)
+        .map(|edge| edge[0].0 * edge[1].1 - edge[1].0 * edge[0].1)
+        .sum()
+}
+
+/// A way's ID and the IDs of its nodes
+type WayNodes = (u64, Vec<u64>);
+
+/// The coastline ways read so far
+struct CoastlineWays<'a, 'txn> {
+    ways: &'a Ways<'txn>,
+    node_ways: &'a JoinTable<'txn>,
+    /// The nodes of each coastline way, by way ID
+    nodes: HashMap<u64, Vec<u64>>,
+    /// The ways which aren't coastline
+    other: RoaringTreemap,
+    /// The coastline ways already joined into a ring or chain
+    used: RoaringTreemap,
+}
+
+impl CoastlineWays<'_, '_> {
+    /// The nodes of a way, if it's a coastline way
+    fn get(&mut self, id: u64) -> Result<Option<Vec<u64>>, Error> {
+        if self.other.contains(id) {
+            return Ok(None);
+        }
+        if let Some(nodes) = self.nodes.get(&id) {
+            return Ok(Some(nodes.clone()));
+        }
+        match self.ways.get(id)? {
+            Some(way) if way.tag("natural") == Some("coastline") => {
+                let nodes: Vec<u64> = way.nodes().collect();
+                self.nodes.insert(id, nodes.clone());
+                Ok(Some(nodes))
+            }
+            _ => {
+                self.other.insert(id);
+                Ok(None)
+            }
+        }
+    }
+
+    /// The unused coastline way which starts (or, if not `starting`, ends) at a node,
+    /// which is marked as used
+    fn adjoining(&mut self, node_id: u64, starting: bool) -> Result<Option<WayNodes>, Error> {
+        let mut candidates: Vec<u64> = self.node_ways.get(node_id).collect();
+        candidates.sort_unstable();
+        for way_id in candidates {
+            if self.used.contains(way_id) {
+                continue;
+            }
+            if let Some(nodes) = self.get(way_id)? {
+                let end = if starting {
+                    nodes.first()
+                } else {
+                    nodes.last()
+                };
+                if nodes.len() >= 2 && end == Some(&node_id) {
+                    self.used.insert(way_id);
+                    return Ok(Some((way_id, nodes)));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Transaction<'_> {
+    /// Join the `natural=coastline` ways end to end into land polygons. With a region,
+    /// only the coastlines with a node in it are assembled, each followed as far as it
+    /// goes (so the polygon of a continent is complete even if only a bit of its coast is
+    /// in the region); without one, every coastline way in the database is.
+    ///
+    /// Ways are only joined in their own direction, since that's what tells land from
+    /// water. Coastlines which don't close into a ring are returned as problems rather
+    /// than being closed along the edge of the region, as are rings which include nodes
+    /// without a location. Where more than one coastline way starts at a node, which is a
+    /// mapping error, the one with the lowest ID is followed.
+    ///
+    /// Seas are assigned as holes to the smallest landmass containing them, tested with
+    /// planar coordinates, so rings crossing the antimeridian aren't handled. Seas which
+    /// aren't inside any of the landmasses assembled are left out.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip(self, region))
+    )]
+    pub fn coastline(&self, region: Option<&Region>) -> Result<Coastline, Error> {
+        let locations = self.locations()?;
+        let ways = self.ways()?;
+        let node_ways = self.node_ways()?;
+
+        let mut coastline_ways = CoastlineWays {
+            ways: &ways,
+            node_ways: &node_ways,
+            nodes: HashMap::new(),
+            other: RoaringTreemap::new(),
+            used: RoaringTreemap::new(),
+        };
+
+        let mut seeds = RoaringTreemap::new();
+        match region {
+            Some(region) => {
+                for node_id in self.cell_nodes()?.find_in_region(region) {
+                    let in_region = locations
+                        .get(node_id)?
+                        .is_some_and(|loc| region_contains(region, loc.lon(), loc.lat()));
+                    if !in_region {
+                        continue;
+                    }
+                    for way_id in node_ways.get(node_id) {
+                        if !seeds.contains(way_id) && coastline_ways.get(way_id)?.is_some() {
+                            seeds.insert(way_id);
+                        }
+                    }
+                }
+            }
+            None => {
+                for (id, way) in ways.iter() {
+                    if way.tag("natural") == Some("coastline") {
+                        seeds.insert(id);
+                    }
+                }
+            }
+        }
+
+        let mut rings = vec![];
+        let mut coastline = Coastline::default();
+        for seed in seeds.iter() {
+            if coastline_ways.used.contains(seed) {
+                continue;
+            }
+            let Some(nodes) = coastline_ways.get(seed)? else {
+                continue;
+            };
+            coastline_ways.used.insert(seed);
+            let mut way_ids = VecDeque::from([seed]);
+            let mut chain = VecDeque::from(nodes);
+
+            // follow the coastline forward, and then (if it doesn't close) backward
+            while chain.front() != chain.back() {
+                let last = *chain.back().unwrap();
+                let Some((way_id, nodes)) = coastline_ways.adjoining(last, true)? else {
+                    break;
+                };
+                way_ids.push_back(way_id);
+                chain.extend(nodes.into_iter().skip(1));
+            }
+            while chain.front() != chain.back() {
+                let first = *chain.front().unwrap();
+                let Some((way_id, nodes)) = coastline_ways.adjoining(first, false)? else {
+                    break;
+                };
+                way_ids.push_front(way_id);
+                for node in nodes.into_iter().rev().skip(1) {
+                    chain.push_front(node);
+                }
+            }
+
+            let way_ids: Vec<u64> = way_ids.into();
+            if chain.front() != chain.back() {
+                coastline.problems.push(CoastlineProblem::Open {
+                    way_ids,
+                    first_node: *chain.front().unwrap(),
+                    last_node: *chain.back().unwrap(),
+                });
+                continue;
+            }
+            let mut points = Vec::with_capacity(chain.len());
+            let mut missing = None;
+            for &node_id in chain.iter() {
+                match locations.get(node_id)? {
+                    Some(location) => points.push((location.lon(), location.lat())),
+                    None => {
+                        missing = Some(node_id);
+                        break;
+                    }
+                }
+            }
+            if let Some(node_id) = missing {
+                coastline
+                    .problems
+                    .push(CoastlineProblem::MissingNode { way_ids, node_id });
+                continue;
+            }
+            let area = signed_area(&points);
+            if area == 0.0 {
+                coastline
+                    .problems
+                    .push(CoastlineProblem::Degenerate { way_ids });
+                continue;
+            }
+            rings.push((points, area, way_ids));
+        }
+
+        // each sea is a hole in the smallest landmass around it
+        let mut land_index = HashMap::new();
+        for (index, (points, area, way_ids)) in rings.iter().enumerate() {
+            if *area > 0.0 {
+                land_index.insert(index, coastline.land.len());
+                coastline.land.push(LandPolygon {
+                    exterior: points.clone(),
+                    holes: vec![],
+                    way_ids: way_ids.clone(),
+                });
+            }
+        }
+        for (points, area, _) in rings.iter().filter(|(_, area, _)| *area < 0.0) {
+            let around = rings
+                .iter()
+                .enumerate()
+                .filter(|(_, (land, land_area, _))| {
+                    *land_area > -area && rings_contain(std::slice::from_ref(land), points[0])
+                })
+                .min_by(|(_, a), (_, b)| a.1.total_cmp(&b.1));
+            if let Some((index, _)) = around {
+                coastline.land[land_index[&index]]
+                    .holes
+                    .push(points.clone());
+            }
+        }
+        Ok(coastline)
+    }
+}
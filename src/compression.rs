@@ -0,0 +1,78 @@
+use std::io::Read;
+
+use crate::Error;
+
+/// How the values in the nodes, ways and relations tables are compressed (on top of
+/// their [crate::ValueEncoding]). Recorded in the metadata table under
+/// [ValueCompression::METADATA_KEY]; databases without that key are uncompressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueCompression {
+    /// Values are stored uncompressed.
+    None,
+    /// Values are compressed with zstd, using a dictionary trained on that table's
+    /// values. Each table's dictionary is stored in the metadata table under the key
+    /// returned by [ValueCompression::dictionary_key].
+    Zstd,
+}
+
+impl ValueCompression {
+    /// The metadata table key under which the value compression is recorded.
+    pub const METADATA_KEY: &'static str = "value_compression";
+
+    /// The name of this compression scheme, as stored in the metadata table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Zstd => "zstd",
+        }
+    }
+
+    /// Parse a compression scheme name as stored in the metadata table.
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, Error> {
+        match raw {
+            b"none" => Ok(Self::None),
+            b"zstd" => Ok(Self::Zstd),
+            _ => Err(format!(
+                "unknown value compression {:?}",
+                String::from_utf8_lossy(raw)
+            )
+            .into()),
+        }
+    }
+
+    /// The metadata table key under which the zstd dictionary for the given table is stored.
+    pub fn dictionary_key(table: &str) -> String {
+        format!("zstd_dictionary.{}", table)
+    }
+}
+
+/// A zstd dictionary used to decompress the values of one table.
+pub struct Dictionary {
+    inner: zstd::dict::DecoderDictionary<'static>,
+}
+
+impl Dictionary {
+    pub(crate) fn new(raw: &[u8]) -> Self {
+        Self {
+            inner: zstd::dict::DecoderDictionary::copy(raw),
+        }
+    }
+
+    /// Decompress a value which was compressed using this dictionary.
+    pub(crate) fn decompress(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut decoder =
+            zstd::stream::read::Decoder::with_prepared_dictionary(bytes, &self.inner)?;
+        let mut buf = vec![];
+        decoder.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Decompress a value into `buf`, replacing its contents but reusing its allocation.
+    pub(crate) fn decompress_into(&self, bytes: &[u8], buf: &mut Vec<u8>) -> std::io::Result<()> {
+        let mut decoder =
+            zstd::stream::read::Decoder::with_prepared_dictionary(bytes, &self.inner)?;
+        buf.clear();
+        decoder.read_to_end(buf)?;
+        Ok(())
+    }
+}
@@ -1,171 +1,4180 @@
-use std::error::Error;
+#[cfg(feature = "update")]
+use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
+use std::ops::{Bound, RangeBounds};
 use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use genawaiter::rc::Gen;
+use capnp::message::ReaderOptions;
 use lmdb::{Cursor, Transaction as LmdbTransaction};
+use roaring::RoaringTreemap;
 
-use crate::types::{Location, Node, Region, Relation, Way};
+#[cfg(feature = "update")]
+use crate::build::{LocationBuilder, NodeBuilder, RelationBuilder, WayBuilder};
+use crate::compression::{Dictionary, ValueCompression};
+#[cfg(feature = "update")]
+use crate::geometry::{assemble_rings, encode_geometry};
+#[cfg(feature = "export")]
+use crate::geometry::{decode_geometry, node_coordinates, relation_rings, Geometry};
+use crate::interop;
+use crate::keys::{
+    decode_element_ref, decode_key, decode_string_id, decode_version_key, encode_element_ref,
+    encode_key, encode_version_key, KeyByteOrder,
+};
+#[cfg(feature = "update")]
+use crate::lock::WriterGuard;
+use crate::lock::WriterLock;
+#[cfg(feature = "update")]
+use crate::metrics::Commit;
+use crate::metrics::MetricsSink;
+#[cfg(feature = "spatial")]
+use crate::metrics::QueryTimer;
+#[cfg(feature = "update")]
+use crate::plugins::{IndexPlugin, PluginElement, PluginRegistry, PluginTable};
+use crate::streets::{
+    decode_street_name_key, decode_street_name_value, normalize_street_name, StreetCompletion,
+};
+#[cfg(feature = "update")]
+use crate::streets::{encode_street_name_key, encode_street_name_value, STREET_NAME_CELL_LEVEL};
+use crate::trace::{self, Scan};
+#[cfg(feature = "spatial")]
+use crate::types::Region;
+use crate::types::{
+    decode_tag_set, tag_set_hash, BoundingBox, Changeset, Decode, DecodeBuffer, DecodeContext,
+    ElementId, Location, Node, Reborrow, Relation, StringTable, TagSetTable, Way, WayGeometry,
+    WithMetadata, DEFAULT_READER_OPTIONS,
+};
+use crate::Error;
 
 pub const CELL_INDEX_LEVEL: u64 = 16;
 
+/// The version of the OSMX format written by this crate. Databases record the version
+/// that created them in the metadata table under [FORMAT_VERSION_KEY] (as a little-endian
+/// u32), and [Database::open] refuses to open databases with a version it doesn't support.
+///
+/// - Version 1 is the original OSMExpress format (databases without a version key).
+/// - Version 2 adds optional packed encoding, zstd compression, string interning, and
+///   history tables.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The oldest format version that this crate can read.
+pub const MIN_FORMAT_VERSION: u32 = 1;
+
+/// The metadata table key under which the format version is recorded.
+pub const FORMAT_VERSION_KEY: &str = "format_version";
+
+/// The metadata table key under which the bounding box of the database's nodes is
+/// recorded, as four little-endian i32s: west, south, east and north, in units of 1e-7
+/// degrees (as in the locations table).
+pub const BOUNDS_KEY: &str = "bounds";
+
+/// The metadata table key under which the SHA-256 digest of the file the database was
+/// imported from is recorded, as a lowercase hex string.
+pub const SOURCE_SHA256_KEY: &str = "source_sha256";
+
+/// The metadata table key under which the tag keys indexed in the tag_node, tag_way and
+/// tag_relation tables are recorded, each followed by a zero byte.
+pub const TAG_INDEX_KEYS_KEY: &str = "tag_index_keys";
+
+/// The metadata table key under which the names of the index plugins (see
+/// [crate::IndexPlugin]) maintaining extension tables of the database are recorded, each
+/// followed by a zero byte.
+pub const INDEX_PLUGINS_KEY: &str = "index_plugins";
+
+/// The metadata table key under which an extract records the absolute path of the
+/// database it was extracted from, as a string. Its other metadata (such as the
+/// replication state and the name of the file it was imported from) is that of the
+/// source database.
+pub const EXTRACT_SOURCE_KEY: &str = "extract_source";
+
+/// The metadata table key under which the number of current nodes, ways and relations
+/// is recorded (see [ElementCounts]).
+pub const COUNTS_KEY: &str = "counts";
+
+/// The metadata table key under which the process currently writing to the database
+/// is recorded (see [WriterLock]).
+pub const WRITER_LOCK_KEY: &str = "writer_lock";
+
+/// The number of current elements of each type in a database, as recorded by the tool
+/// which wrote it. Unlike the number of entries in the element tables, the node count
+/// includes untagged nodes (which are only in the Locations table). Stored under
+/// [COUNTS_KEY] as three little-endian u64s.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ElementCounts {
+    pub nodes: u64,
+    pub ways: u64,
+    pub relations: u64,
+}
+
+impl ElementCounts {
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        if raw.len() != 24 {
+            return None;
+        }
+        let count = |i: usize| u64::from_le_bytes(raw[i * 8..(i + 1) * 8].try_into().unwrap());
+        Some(Self {
+            nodes: count(0),
+            ways: count(1),
+            relations: count(2),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 24] {
+        let mut buf = [0; 24];
+        buf[0..8].copy_from_slice(&self.nodes.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.ways.to_le_bytes());
+        buf[16..24].copy_from_slice(&self.relations.to_le_bytes());
+        buf
+    }
+}
+
+/// The prefix of the metadata table keys which hold data stored by applications (see
+/// [Database::set_user_metadata]), such as the last replication sequence number an
+/// external search index was built from. This crate's own keys never start with it.
+pub const USER_METADATA_PREFIX: &str = "user.";
+
+/// The metadata table key under which an application's value is stored. The namespace
+/// (e.g. the application's name) keeps different applications' keys apart.
+fn user_metadata_key(namespace: &str, key: &str) -> Result<String, Error> {
+    if namespace.is_empty() || namespace.contains('.') {
+        return Err(format!(
+            "invalid metadata namespace {:?} (must be non-empty, without dots)",
+            namespace
+        )
+        .into());
+    }
+    Ok(format!("{}{}.{}", USER_METADATA_PREFIX, namespace, key))
+}
+
+/// The prefix of the names of the tables created by applications (see
+/// [Database::create_extension_table]), to keep them apart from this crate's own tables.
+pub const EXTENSION_TABLE_PREFIX: &str = "ext.";
+
+/// The most tables a database can have while it's open, including extension tables,
+/// unless [OpenOptions::max_tables] is set. The tables of this crate take up to 28 of them.
+pub const DEFAULT_MAX_TABLES: u32 = 128;
+
+/// The full name of an extension table. Names are limited to ASCII letters, digits,
+/// underscores and hyphens, so they can't be confused with this crate's tables.
+fn extension_table_name(name: &str) -> Result<String, Error> {
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if name.is_empty() || !name.chars().all(valid) {
+        return Err(format!(
+            "invalid extension table name {:?} (must be non-empty letters, digits, _ or -)",
+            name
+        )
+        .into());
+    }
+    Ok(format!("{}{}", EXTENSION_TABLE_PREFIX, name))
+}
+
+/// Error returned by [Database::open] when a database's format version is not supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedFormatVersion {
+    /// The format version recorded in the database.
+    pub found: u32,
+}
+
+impl std::fmt::Display for UnsupportedFormatVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.found > FORMAT_VERSION {
+            write!(
+                f,
+                "unsupported OSMX format version {} (produced by a newer version of osmx; \
+                 this version supports up to {})",
+                self.found, FORMAT_VERSION
+            )
+        } else {
+            write!(
+                f,
+                "unsupported OSMX format version {} (produced by an older version of osmx; \
+                 this version supports {} and later)",
+                self.found, MIN_FORMAT_VERSION
+            )
+        }
+    }
+}
+
+impl std::error::Error for UnsupportedFormatVersion {}
+
+/// The tables which every OSMX database has.
+const REQUIRED_TABLES: &[&str] = &[
+    "metadata",
+    "locations",
+    "nodes",
+    "ways",
+    "relations",
+    "cell_node",
+    "node_way",
+    "node_relation",
+    "way_relation",
+    "relation_relation",
+];
+
+/// The tables which only exist in databases created with some options.
+const OPTIONAL_TABLES: &[&str] = &[
+    "strings",
+    "location_history",
+    "node_history",
+    "way_history",
+    "relation_history",
+    "changesets",
+    "uid_element",
+    "changeset_element",
+    "tombstones",
+    "way_bbox",
+    "geometries",
+    "tag_sets",
+    "street_names",
+    "tag_node",
+    "tag_way",
+    "tag_relation",
+    "cell_way",
+    "cell_relation",
+];
+
+/// What reads do with element values which can't be decoded (because they are corrupt,
+/// or exceed the limits of the reader options). Set with [Database::set_decode_mode].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DecodeMode {
+    /// Report them: methods which return Results (like `get` and `try_iter`) return
+    /// an error, and the others panic. For pipelines which must notice corruption.
+    #[default]
+    Strict,
+    /// Treat elements whose values can't be decoded as if they weren't in the table:
+    /// lookups return None and iterators skip them. They are counted by
+    /// [Database::skipped_values]. For best-effort analysis of possibly damaged files.
+    Lenient,
+}
+
+/// When the methods of [Database] which write (like [Database::set_user_metadata]) flush
+/// their changes to disk. Databases are opened with MDB_NOSYNC, which is meant for reads
+/// and bulk loads: a commit which hasn't been synced can be lost if the system crashes
+/// (though the database stays consistent). Set with [OpenOptions::durability] or
+/// [Database::set_durability].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    /// Sync after every commit, so committed changes survive a crash. The default.
+    #[default]
+    SyncEveryCommit,
+    /// Sync after a commit if the last sync was at least this long ago, bounding how much
+    /// can be lost in a crash. Call [Database::sync] before exiting to flush the commits
+    /// since the last sync.
+    Periodic(Duration),
+    /// Never sync, leaving it to the operating system to write changes back.
+    NoSync,
+}
+
+/// Settings for [Database::open_with]. The defaults are those of [Database::open]. Apart
+/// from the durability, they can't be changed once a database is open, because LMDB
+/// applies them when it opens the environment.
+#[derive(Debug, Clone, Default)]
+pub struct OpenOptions {
+    /// Let the operating system read ahead of the pages which are accessed. Off by
+    /// default, which suits lookups and spatial queries (read-ahead would fetch pages
+    /// they don't need), but full scans of tables, such as exports, are much faster with
+    /// it on spinning disks and network storage.
+    pub read_ahead: bool,
+    /// The number of reader slots in the environment, which limits how many read
+    /// transactions can be open at once, across all the processes using the database.
+    /// Defaults to LMDB's default of 126. Only the first process to open the database
+    /// sets it; later processes use the same number.
+    pub max_readers: Option<u32>,
+    /// The size of the memory map, in bytes, which is the most the file can grow to
+    /// while the database is open. Defaults to 50 GiB of address space (not memory),
+    /// which may be more than is allowed on systems which limit virtual memory; a smaller
+    /// map is enough for reading a smaller file, since LMDB never maps less than the
+    /// file's size.
+    pub map_size: Option<usize>,
+    /// Open the environment read-only (MDB_RDONLY), so that the methods which write
+    /// return errors. This allows opening files which the process can't write to, and
+    /// files on read-only filesystems (where LMDB doesn't use the lock file at all).
+    pub read_only: bool,
+    /// When the methods which write flush their changes to disk (see
+    /// [Database::set_durability]).
+    pub durability: Durability,
+    /// The most tables the database can have while it's open, including the extension
+    /// tables created with [Database::create_extension_table]. Defaults to
+    /// [DEFAULT_MAX_TABLES]. LMDB keeps a few words per table in every transaction, so a
+    /// much larger limit makes transactions slightly more expensive.
+    pub max_tables: Option<u32>,
+}
+
+/// The default size of the memory map (see [OpenOptions::map_size])
+const DEFAULT_MAP_SIZE: usize = 50 * 1024 * 1024 * 1024; // 50 GiB
+
+/// Error returned when beginning a read transaction fails because every reader slot of
+/// the database is held by an open transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReaderSlotsExhausted {
+    /// The number of reader slots in the environment.
+    pub max_readers: u32,
+}
+
+impl std::fmt::Display for ReaderSlotsExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "all {} reader slots of the database are in use (end idle transactions, clear \
+             the slots of crashed processes with Database::check_readers, or open the \
+             database with a larger OpenOptions::max_readers)",
+            self.max_readers
+        )
+    }
+}
+
+impl std::error::Error for ReaderSlotsExhausted {}
+
+/// Error returned by [Database::open] when a file doesn't look like an OSMX database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The file isn't an LMDB environment at all.
+    NotLmdb,
+    /// The file is an LMDB environment, but some of the tables which every OSMX database
+    /// has are missing.
+    MissingTables {
+        /// The names of the required tables which are missing.
+        missing: Vec<&'static str>,
+        /// The names of the tables in the file which aren't part of the OSMX format.
+        unknown: Vec<String>,
+    },
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotLmdb => write!(f, "not an LMDB file, so it can't be an OSMX database"),
+            Self::MissingTables { missing, unknown } => {
+                write!(
+                    f,
+                    "missing tables {} (this may not be an OSMX database",
+                    missing.join(", ")
+                )?;
+                if !unknown.is_empty() {
+                    write!(f, "; it has tables {}", unknown.join(", "))?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+/// Check that an LMDB environment has all the tables that an OSMX database should, and
+/// return the names of its tables.
+fn check_tables(env: &lmdb::Environment) -> Result<Vec<String>, Error> {
+    // the names of the tables are the keys of the unnamed main table
+    let main = env.open_db(None)?;
+    let txn = env.begin_ro_txn()?;
+    let mut cursor = txn.open_ro_cursor(main)?;
+    let names: Vec<String> = cursor
+        .iter()
+        .map(|(raw_key, _)| String::from_utf8_lossy(raw_key).into_owned())
+        .collect();
+    drop(cursor);
+    txn.commit()?;
+
+    let missing: Vec<&'static str> = REQUIRED_TABLES
+        .iter()
+        .copied()
+        .filter(|table| !names.iter().any(|name| name == table))
+        .collect();
+    if missing.is_empty() {
+        return Ok(names);
+    }
+    let unknown = names
+        .into_iter()
+        .filter(|name| !REQUIRED_TABLES.contains(&name.as_str()))
+        .filter(|name| !OPTIONAL_TABLES.contains(&name.as_str()))
+        .filter(|name| !name.starts_with(EXTENSION_TABLE_PREFIX))
+        .collect();
+    Err(FormatError::MissingTables { missing, unknown }.into())
+}
+
+/// How the Cap'n Proto messages in the nodes, ways and relations tables are serialized.
+/// Recorded in the metadata table under [ValueEncoding::METADATA_KEY]; databases without
+/// that key use the standard encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueEncoding {
+    /// Standard (unpacked) Cap'n Proto serialization, which can be read in place.
+    Standard,
+    /// Cap'n Proto packed serialization, which is smaller on disk but must be
+    /// unpacked into a temporary buffer when an element is read.
+    Packed,
+}
+
+impl ValueEncoding {
+    /// The metadata table key under which the value encoding is recorded.
+    pub const METADATA_KEY: &'static str = "value_encoding";
+
+    /// The name of this encoding, as stored in the metadata table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Standard => "standard",
+            Self::Packed => "packed",
+        }
+    }
+
+    /// Parse an encoding name as stored in the metadata table.
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, Error> {
+        match raw {
+            b"standard" => Ok(Self::Standard),
+            b"packed" => Ok(Self::Packed),
+            _ => Err(format!("unknown value encoding {:?}", String::from_utf8_lossy(raw)).into()),
+        }
+    }
+}
+
 /// A handle to an OSMX database file
 pub struct Database {
     env: lmdb::Environment,
 
-    // tables that store OSM object data (keyed by ID)
-    locations: lmdb::Database,
-    nodes: lmdb::Database,
-    ways: lmdb::Database,
-    relations: lmdb::Database,
-    // spatial index table for nodes/locations (keyed by S2 cell ID)
-    cell_node: lmdb::Database,
-    // tables that map OSM object IDs to parent IDs
-    node_way: lmdb::Database,
-    node_relation: lmdb::Database,
-    way_relation: lmdb::Database,
-    relation_relation: lmdb::Database,
+    // table of database-wide key/value metadata
+    metadata: lmdb::Database,
+
+    // tables that store OSM object data (keyed by ID)
+    locations: lmdb::Database,
+    nodes: lmdb::Database,
+    ways: lmdb::Database,
+    relations: lmdb::Database,
+    // spatial index table for nodes/locations (keyed by S2 cell ID)
+    #[cfg_attr(not(feature = "spatial"), allow(dead_code))]
+    cell_node: lmdb::Database,
+    // tables that map OSM object IDs to parent IDs
+    node_way: lmdb::Database,
+    node_relation: lmdb::Database,
+    way_relation: lmdb::Database,
+    relation_relation: lmdb::Database,
+    // tables that store every version of each object (keyed by ID and version); these
+    // only exist in databases created with history enabled
+    location_history: Option<lmdb::Database>,
+    node_history: Option<lmdb::Database>,
+    way_history: Option<lmdb::Database>,
+    relation_history: Option<lmdb::Database>,
+    // table that summarizes each changeset (keyed by changeset ID); only exists in
+    // databases created with metadata
+    changesets: Option<lmdb::Database>,
+    // index table that maps user IDs to the elements they edited; only exists in
+    // databases created with the uid index
+    uid_element: Option<lmdb::Database>,
+    // index table that maps changeset IDs to the elements edited in them; only exists in
+    // databases created with the changeset index
+    changeset_element: Option<lmdb::Database>,
+    // table of elements which have been deleted (keyed by element reference, with empty
+    // values); only exists in the edits databases of overlays
+    tombstones: Option<lmdb::Database>,
+    // table of the bounding box of each way (keyed by way ID); only exists in databases
+    // created with it
+    way_bbox: Option<lmdb::Database>,
+    // table of the geometry of each way and multipolygon (keyed by element reference);
+    // only exists in databases created with it
+    #[cfg_attr(not(feature = "export"), allow(dead_code))]
+    geometries: Option<lmdb::Database>,
+    // index of the names of highway ways (keyed by normalized name); only exists in
+    // databases created with it
+    street_names: Option<lmdb::Database>,
+    // indexes of the nodes, ways and relations with each tag of the indexed keys (keyed
+    // by tag_set_hash of the key, and of the key and value); only exist in databases
+    // created with them
+    tag_index: Option<[lmdb::Database; 3]>,
+    // spatial indexes of ways and relations (keyed by the S2 cell IDs covering their
+    // bounding boxes); only exist in databases created with them
+    #[cfg_attr(not(feature = "spatial"), allow(dead_code))]
+    cell_way: Option<lmdb::Database>,
+    #[cfg_attr(not(feature = "spatial"), allow(dead_code))]
+    cell_relation: Option<lmdb::Database>,
+    // tables created by applications, by name (without the prefix)
+    extension_tables: HashMap<String, lmdb::Database>,
+    // the number of tables in the database, and the most it can have while it's open
+    #[cfg_attr(not(feature = "update"), allow(dead_code))]
+    table_count: usize,
+    #[cfg_attr(not(feature = "update"), allow(dead_code))]
+    max_tables: u32,
+
+    format_version: u32,
+
+    // options used when decoding Cap'n Proto element values
+    reader_options: ReaderOptions,
+    value_encoding: ValueEncoding,
+    value_compression: ValueCompression,
+    // zstd dictionaries for compressed tables, keyed by table name
+    dictionaries: HashMap<&'static str, Dictionary>,
+    // interned tag strings, loaded from the `strings` table (if present)
+    strings: StringTable,
+    // shared tag sets, loaded from the `tag_sets` table (if present)
+    tag_sets: TagSetTable,
+
+    // what reads do with values which can't be decoded, and how many have been skipped
+    decode_mode: DecodeMode,
+    skipped_values: AtomicU64,
+
+    // when writes are synced to disk, and when they last were
+    durability: Durability,
+    last_sync: Mutex<Instant>,
+
+    // receives measurements of transactions, queries and commits, if set
+    metrics: Option<Arc<dyn MetricsSink>>,
+}
+
+impl Database {
+    /// Open the given file path as an OSMX Database. The path can also be a directory
+    /// holding a directory-style LMDB environment (`data.mdb` and `lock.mdb`).
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::open_with(path, &OpenOptions::default())
+    }
+
+    /// Open the given file path as an OSMX Database, with the given options.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display()))
+    )]
+    pub fn open_with(path: impl AsRef<Path>, options: &OpenOptions) -> Result<Self, Error> {
+        // LMDB would create a new, empty environment at a path which doesn't exist
+        let data_file = data_file(path.as_ref());
+        if !data_file.exists() {
+            return Err(Error::NotFound(data_file));
+        }
+
+        let mut lock = path.as_ref().as_os_str().to_owned();
+        lock.push("-lock");
+        let lock = if path.as_ref().is_dir() {
+            path.as_ref().join("lock.mdb")
+        } else {
+            lock.into()
+        };
+        let had_lock = lock.exists();
+
+        let read_ahead = if options.read_ahead {
+            lmdb::EnvironmentFlags::empty()
+        } else {
+            lmdb::EnvironmentFlags::NO_READAHEAD
+        };
+        let read_only = if options.read_only {
+            lmdb::EnvironmentFlags::READ_ONLY
+        } else {
+            lmdb::EnvironmentFlags::empty()
+        };
+
+        let max_tables = options.max_tables.unwrap_or(DEFAULT_MAX_TABLES);
+        let mut builder = lmdb::Environment::new();
+        if let Some(max_readers) = options.max_readers {
+            builder.set_max_readers(max_readers);
+        }
+        let env = match builder
+            .set_flags(
+                // NO_TLS ties reader slots to transactions rather than threads, so that a
+                // thread can hold several read transactions and TransactionPool can hand
+                // them to any thread
+                environment_flags(path.as_ref())
+                    | read_ahead
+                    | read_only
+                    | lmdb::EnvironmentFlags::NO_SYNC
+                    | lmdb::EnvironmentFlags::NO_TLS,
+            )
+            .set_max_dbs(max_tables)
+            .set_map_size(options.map_size.unwrap_or(DEFAULT_MAP_SIZE))
+            .open(path.as_ref())
+        {
+            Ok(env) => env,
+            Err(lmdb::Error::Invalid) => {
+                // don't leave a lock file next to a file which isn't a database
+                if !had_lock {
+                    let _ = std::fs::remove_file(&lock);
+                }
+                return Err(FormatError::NotLmdb.into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let table_names = check_tables(&env)?;
+        if table_names.len() > max_tables as usize {
+            return Err(format!(
+                "the database has {} tables, more than the limit of {} (see OpenOptions::max_tables)",
+                table_names.len(),
+                max_tables
+            )
+            .into());
+        }
+
+        let metadata = env.open_db(Some("metadata"))?;
+        let locations = env.open_db(Some("locations"))?;
+        let nodes = env.open_db(Some("nodes"))?;
+        let ways = env.open_db(Some("ways"))?;
+        let relations = env.open_db(Some("relations"))?;
+        let cell_node = env.open_db(Some("cell_node"))?;
+        let node_way = env.open_db(Some("node_way"))?;
+        let node_relation = env.open_db(Some("node_relation"))?;
+        let way_relation = env.open_db(Some("way_relation"))?;
+        let relation_relation = env.open_db(Some("relation_relation"))?;
+
+        // optional tables must be opened before the read transaction below begins, since
+        // opening a table uses a transaction of its own
+        let strings_table = open_optional_table(&env, "strings")?;
+        let tag_sets_table = open_optional_table(&env, "tag_sets")?;
+        let location_history = open_optional_table(&env, "location_history")?;
+        let node_history = open_optional_table(&env, "node_history")?;
+        let way_history = open_optional_table(&env, "way_history")?;
+        let relation_history = open_optional_table(&env, "relation_history")?;
+        let changesets = open_optional_table(&env, "changesets")?;
+        let uid_element = open_optional_table(&env, "uid_element")?;
+        let changeset_element = open_optional_table(&env, "changeset_element")?;
+        let tombstones = open_optional_table(&env, "tombstones")?;
+        let way_bbox = open_optional_table(&env, "way_bbox")?;
+        let geometries = open_optional_table(&env, "geometries")?;
+        let street_names = open_optional_table(&env, "street_names")?;
+        let tag_index = match (
+            open_optional_table(&env, "tag_node")?,
+            open_optional_table(&env, "tag_way")?,
+            open_optional_table(&env, "tag_relation")?,
+        ) {
+            (Some(nodes), Some(ways), Some(relations)) => Some([nodes, ways, relations]),
+            _ => None,
+        };
+        let cell_way = open_optional_table(&env, "cell_way")?;
+        let cell_relation = open_optional_table(&env, "cell_relation")?;
+        let mut extension_tables = HashMap::new();
+        for table_name in table_names.iter() {
+            if let Some(name) = table_name.strip_prefix(EXTENSION_TABLE_PREFIX) {
+                extension_tables.insert(name.to_string(), env.open_db(Some(table_name))?);
+            }
+        }
+
+        let txn = env.begin_ro_txn()?;
+
+        let format_version = match txn.get(metadata, &FORMAT_VERSION_KEY.as_bytes()) {
+            Ok(raw_val) => u32::from_le_bytes(
+                raw_val
+                    .try_into()
+                    .map_err(|_| "format version with incorrect length")?,
+            ),
+            Err(lmdb::Error::NotFound) => 1,
+            Err(e) => return Err(e.into()),
+        };
+
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&format_version) {
+            return Err(UnsupportedFormatVersion {
+                found: format_version,
+            }
+            .into());
+        }
+
+        // databases without a recorded byte order were written in the host's byte order
+        let key_byte_order = match txn.get(metadata, &KeyByteOrder::METADATA_KEY.as_bytes()) {
+            Ok(raw_val) => KeyByteOrder::from_bytes(raw_val)?,
+            Err(lmdb::Error::NotFound) => KeyByteOrder::NATIVE,
+            Err(e) => return Err(e.into()),
+        };
+
+        if key_byte_order != KeyByteOrder::NATIVE {
+            return Err(format!(
+                "database was created on a {}-endian host and cannot be read on this {}-endian host",
+                key_byte_order.as_str(),
+                KeyByteOrder::NATIVE.as_str()
+            )
+            .into());
+        }
+
+        let value_encoding = match txn.get(metadata, &ValueEncoding::METADATA_KEY.as_bytes()) {
+            Ok(raw_val) => ValueEncoding::from_bytes(raw_val)?,
+            Err(lmdb::Error::NotFound) => ValueEncoding::Standard,
+            Err(e) => return Err(e.into()),
+        };
+
+        let value_compression = match txn.get(metadata, &ValueCompression::METADATA_KEY.as_bytes())
+        {
+            Ok(raw_val) => ValueCompression::from_bytes(raw_val)?,
+            Err(lmdb::Error::NotFound) => ValueCompression::None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut dictionaries = HashMap::new();
+        if value_compression == ValueCompression::Zstd {
+            for table in ["nodes", "ways", "relations"] {
+                let key = ValueCompression::dictionary_key(table);
+                let raw_val = txn.get(metadata, &key.as_bytes())?;
+                dictionaries.insert(table, Dictionary::new(raw_val));
+            }
+        }
+
+        let strings = match strings_table {
+            Some(table) => load_string_table(&txn, table)?,
+            None => StringTable::default(),
+        };
+        let tag_sets = match tag_sets_table {
+            Some(table) => load_tag_set_table(&txn, table)?,
+            None => TagSetTable::default(),
+        };
+
+        txn.commit()?;
+
+        Ok(Self {
+            env,
+            metadata,
+            locations,
+            nodes,
+            ways,
+            relations,
+            cell_node,
+            node_way,
+            node_relation,
+            way_relation,
+            relation_relation,
+            location_history,
+            node_history,
+            way_history,
+            relation_history,
+            changesets,
+            uid_element,
+            changeset_element,
+            tombstones,
+            way_bbox,
+            geometries,
+            street_names,
+            tag_index,
+            cell_way,
+            cell_relation,
+            extension_tables,
+            table_count: table_names.len(),
+            max_tables,
+            format_version,
+            reader_options: DEFAULT_READER_OPTIONS,
+            value_encoding,
+            value_compression,
+            dictionaries,
+            strings,
+            tag_sets,
+            decode_mode: DecodeMode::Strict,
+            skipped_values: AtomicU64::new(0),
+            durability: options.durability,
+            last_sync: Mutex::new(Instant::now()),
+            metrics: None,
+        })
+    }
+
+    /// Open a copy of the OSMX Database at the given path which is held entirely in
+    /// memory. The file is copied into a tmpfs (`/dev/shm`) and unlinked once opened, so
+    /// the copy is freed when the Database is dropped, and reads never touch the disk.
+    /// This is useful for tests and for small datasets which are read many times.
+    ///
+    /// On systems without `/dev/shm`, the copy is made in the temporary directory
+    /// instead (which may or may not be backed by memory).
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(path = %path.as_ref().display()))
+    )]
+    pub fn open_in_memory(path: impl AsRef<Path>) -> Result<Self, Error> {
+        static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+        let shm = Path::new("/dev/shm");
+        let dir = if shm.is_dir() {
+            shm.to_path_buf()
+        } else {
+            std::env::temp_dir()
+        };
+        let copy = dir.join(format!(
+            "osmx-{}-{}.osmx",
+            std::process::id(),
+            COPIES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut lock = copy.clone().into_os_string();
+        lock.push("-lock");
+
+        std::fs::copy(data_file(path.as_ref()), &copy)?;
+        let db = Self::open(&copy);
+        // the memory map keeps the data alive after the files are unlinked
+        std::fs::remove_file(&copy)?;
+        if let Err(e) = std::fs::remove_file(&lock) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(e.into());
+            }
+        }
+        db
+    }
+
+    /// Get the compression scheme used for element values in this database.
+    pub fn value_compression(&self) -> ValueCompression {
+        self.value_compression
+    }
+
+    /// Get the table of interned tag strings. Empty unless the database was created
+    /// with string interning enabled.
+    pub fn strings(&self) -> &StringTable {
+        &self.strings
+    }
+
+    /// Get the table of shared tag sets. Empty unless the database was created with
+    /// shared tag sets enabled.
+    pub fn tag_sets(&self) -> &TagSetTable {
+        &self.tag_sets
+    }
+
+    /// Returns true if the database stores the history of each element (every version
+    /// that was imported), which can be read with [ElementTable::versions] and
+    /// [ElementTable::get_as_of].
+    pub fn has_history(&self) -> bool {
+        self.node_history.is_some()
+    }
+
+    /// Get the format version recorded in this database (1 if it has none).
+    pub fn format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    /// Get the encoding used for element values in this database.
+    pub fn value_encoding(&self) -> ValueEncoding {
+        self.value_encoding
+    }
+
+    /// Get the Cap'n Proto reader options that new Transactions will use to decode element values.
+    pub fn reader_options(&self) -> ReaderOptions {
+        self.reader_options
+    }
+
+    /// Set the Cap'n Proto reader options (traversal limit and nesting limit) that new
+    /// Transactions will use to decode element values. Defaults to [DEFAULT_READER_OPTIONS].
+    pub fn set_reader_options(&mut self, options: ReaderOptions) {
+        self.reader_options = options;
+    }
+
+    /// Report the transactions, spatial queries and commits on this Database to a
+    /// [MetricsSink], replacing any set before.
+    pub fn set_metrics(&mut self, sink: Arc<dyn MetricsSink>) {
+        self.metrics = Some(sink);
+    }
+
+    /// Get what reads do with element values which can't be decoded.
+    pub fn decode_mode(&self) -> DecodeMode {
+        self.decode_mode
+    }
+
+    /// Set what reads from Transactions on this Database do with element values which
+    /// can't be decoded. Defaults to [DecodeMode::Strict].
+    pub fn set_decode_mode(&mut self, mode: DecodeMode) {
+        self.decode_mode = mode;
+    }
+
+    /// The number of element values which couldn't be decoded and were skipped by reads
+    /// in [DecodeMode::Lenient], since the Database was opened.
+    pub fn skipped_values(&self) -> u64 {
+        self.skipped_values.load(Ordering::Relaxed)
+    }
+
+    /// Get the policy for syncing writes to disk.
+    pub fn durability(&self) -> Durability {
+        self.durability
+    }
+
+    /// Set when the methods which write flush their changes to disk. Defaults to
+    /// [Durability::SyncEveryCommit].
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    /// Flush all committed changes to disk, whatever the durability policy.
+    pub fn sync(&self) -> Result<(), Error> {
+        let mut last_sync = self.last_sync.lock().unwrap();
+        self.env.sync(true)?;
+        *last_sync = Instant::now();
+        Ok(())
+    }
+
+    /// Sync after a commit, if the durability policy calls for it.
+    #[cfg(feature = "update")]
+    fn committed(&self) -> Result<(), Error> {
+        match self.durability {
+            Durability::SyncEveryCommit => self.sync(),
+            Durability::Periodic(interval) => {
+                let elapsed = self.last_sync.lock().unwrap().elapsed();
+                if elapsed >= interval {
+                    self.sync()?;
+                }
+                Ok(())
+            }
+            Durability::NoSync => Ok(()),
+        }
+    }
+
+    /// Store a value in the metadata table under the given namespace and key, replacing
+    /// any existing value. The value is written in a transaction of its own, which is
+    /// committed (and synced according to the [Durability] policy) before this returns;
+    /// Transactions which began earlier don't see it. Returns an error if the namespace is empty or contains a dot.
+    #[cfg(feature = "update")]
+    pub fn set_user_metadata(&self, namespace: &str, key: &str, value: &[u8]) -> Result<(), Error> {
+        let key = user_metadata_key(namespace, key)?;
+        let mut txn = self.env.begin_rw_txn()?;
+        txn.put(
+            self.metadata,
+            &key.as_bytes(),
+            &value,
+            lmdb::WriteFlags::empty(),
+        )?;
+        txn.commit()?;
+        self.committed()?;
+        Ok(())
+    }
+
+    /// Remove a value stored with [Self::set_user_metadata]. Returns false if there was
+    /// no value under the namespace and key.
+    #[cfg(feature = "update")]
+    pub fn delete_user_metadata(&self, namespace: &str, key: &str) -> Result<bool, Error> {
+        let key = user_metadata_key(namespace, key)?;
+        let mut txn = self.env.begin_rw_txn()?;
+        match txn.del(self.metadata, &key.as_bytes(), None) {
+            Ok(()) => (),
+            Err(lmdb::Error::NotFound) => return Ok(false),
+            Err(e) => return Err(e.into()),
+        }
+        txn.commit()?;
+        self.committed()?;
+        Ok(true)
+    }
+
+    /// Create a table for an application's own data (such as a custom index, or a cache
+    /// of derived values), which is stored in the database alongside the OSM data, so it
+    /// can be read in the same [Transaction] (see [Transaction::extension_table]) and
+    /// stays consistent with it. Its name is prefixed with [EXTENSION_TABLE_PREFIX].
+    /// Keys are byte strings (of at most 511 bytes), sorted bytewise.
+    ///
+    /// Does nothing if the table already exists. Returns an error if the name is invalid,
+    /// or if the database already has as many tables as it was opened with room for (see
+    /// [OpenOptions::max_tables]).
+    #[cfg(feature = "update")]
+    pub fn create_extension_table(&mut self, name: &str) -> Result<(), Error> {
+        let table_name = extension_table_name(name)?;
+        if self.extension_tables.contains_key(name) {
+            return Ok(());
+        }
+        if self.table_count >= self.max_tables as usize {
+            return Err(format!(
+                "cannot create extension table {}: the database already has {} tables (see OpenOptions::max_tables)",
+                name, self.max_tables
+            )
+            .into());
+        }
+        let table = self
+            .env
+            .create_db(Some(&table_name), lmdb::DatabaseFlags::empty())?;
+        self.committed()?;
+        self.extension_tables.insert(name.to_string(), table);
+        self.table_count += 1;
+        Ok(())
+    }
+
+    /// The names of the extension tables, in order.
+    pub fn extension_tables(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.extension_tables.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    /// Make changes to an extension table: for each key, store the value, or with None,
+    /// remove the key (if it's there). The changes are written in a single transaction,
+    /// which is committed (and synced according to the [Durability] policy) before this
+    /// returns, so readers see all of them or none; Transactions which began earlier
+    /// don't see them. Returns an error if the table doesn't exist.
+    #[cfg(feature = "update")]
+    pub fn update_extension_table<K, V>(
+        &self,
+        name: &str,
+        changes: impl IntoIterator<Item = (K, Option<V>)>,
+    ) -> Result<(), Error>
+    where
+        K: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let table = *self
+            .extension_tables
+            .get(name)
+            .ok_or_else(|| format!("database has no extension table {}", name))?;
+        let mut txn = self.env.begin_rw_txn()?;
+        for (key, value) in changes {
+            match value {
+                Some(value) => txn.put(table, &key, &value, lmdb::WriteFlags::empty())?,
+                None => match txn.del(table, &key, None) {
+                    Ok(()) | Err(lmdb::Error::NotFound) => (),
+                    Err(e) => return Err(e.into()),
+                },
+            }
+        }
+        txn.commit()?;
+        self.committed()?;
+        Ok(())
+    }
+
+    /// Take the database's writer lock for the given operation (a description shown to
+    /// other processes, such as the name of a command), which is held until the returned
+    /// guard is dropped. Returns None if another writer holds the lock, which can be read
+    /// with [Transaction::writer_lock]. A lock left behind by a process on this host which
+    /// has exited is replaced.
+    ///
+    /// The lock is advisory (see [WriterLock]); the methods of this crate which write,
+    /// like [Self::set_user_metadata], don't check it.
+    #[cfg(feature = "update")]
+    pub fn try_exclusive_writer(&self, operation: &str) -> Result<Option<WriterGuard<'_>>, Error> {
+        let lock = WriterLock::for_current_process(operation);
+        let mut txn = self.env.begin_rw_txn()?;
+        match txn.get(self.metadata, &WRITER_LOCK_KEY.as_bytes()) {
+            Ok(raw_val) => match WriterLock::from_bytes(raw_val) {
+                Some(holder) if !holder.is_stale() => return Ok(None),
+                _ => (),
+            },
+            Err(lmdb::Error::NotFound) => (),
+            Err(e) => return Err(e.into()),
+        }
+        txn.put(
+            self.metadata,
+            &WRITER_LOCK_KEY.as_bytes(),
+            &lock.to_bytes(),
+            lmdb::WriteFlags::empty(),
+        )?;
+        txn.commit()?;
+        self.committed()?;
+        Ok(Some(WriterGuard { db: self, lock }))
+    }
+
+    /// Remove the writer lock, whoever holds it, and return it. Use this to clear a lock
+    /// left behind by a process which crashed on another host. Returns None if the
+    /// database isn't locked.
+    #[cfg(feature = "update")]
+    pub fn break_writer_lock(&self) -> Result<Option<WriterLock>, Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        let holder = match txn.get(self.metadata, &WRITER_LOCK_KEY.as_bytes()) {
+            Ok(raw_val) => WriterLock::from_bytes(raw_val),
+            Err(lmdb::Error::NotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        txn.del(self.metadata, &WRITER_LOCK_KEY.as_bytes(), None)?;
+        txn.commit()?;
+        self.committed()?;
+        Ok(holder)
+    }
+
+    /// Remove the writer lock if it is still the given one.
+    #[cfg(feature = "update")]
+    pub(crate) fn release_writer_lock(&self, lock: &WriterLock) -> Result<(), Error> {
+        let mut txn = self.env.begin_rw_txn()?;
+        match txn.get(self.metadata, &WRITER_LOCK_KEY.as_bytes()) {
+            Ok(raw_val) if WriterLock::from_bytes(raw_val).as_ref() == Some(lock) => (),
+            Ok(_) | Err(lmdb::Error::NotFound) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        txn.del(self.metadata, &WRITER_LOCK_KEY.as_bytes(), None)?;
+        txn.commit()?;
+        self.committed()?;
+        Ok(())
+    }
+
+    /// Begin an LMDB read transaction, reporting a [ReaderSlotsExhausted] error if there
+    /// are no free reader slots.
+    fn begin_ro_txn(&self) -> Result<lmdb::RoTransaction<'_>, Error> {
+        self.env
+            .begin_ro_txn()
+            .map_err(|e| readers_error(self.env.env(), e))
+    }
+
+    /// Clear the reader slots held by processes which exited without ending their
+    /// transactions (for example because they crashed), and return how many were
+    /// cleared. Stale slots keep the pages of old snapshots from being reused, which
+    /// makes the file grow without bound as it is updated.
+    pub fn check_readers(&self) -> Result<usize, Error> {
+        let mut cleared = 0;
+        let rc = unsafe { lmdb_sys::mdb_reader_check(self.env.env(), &mut cleared) };
+        if rc != 0 {
+            return Err(lmdb::Error::from_err_code(rc).into());
+        }
+        Ok(cleared as usize)
+    }
+}
+
+/// Convert an LMDB error from beginning a read transaction into the error to report,
+/// which is [ReaderSlotsExhausted] if the environment's reader slots are all in use.
+fn readers_error(env: *mut lmdb_sys::MDB_env, error: lmdb::Error) -> Error {
+    if error != lmdb::Error::ReadersFull {
+        return error.into();
+    }
+    let mut info = lmdb_sys::MDB_envinfo {
+        me_mapaddr: ptr::null_mut(),
+        me_mapsize: 0,
+        me_last_pgno: 0,
+        me_last_txnid: 0,
+        me_maxreaders: 0,
+        me_numreaders: 0,
+    };
+    match unsafe { lmdb_sys::mdb_env_info(env, &mut info) } {
+        lmdb_sys::MDB_SUCCESS => ReaderSlotsExhausted {
+            max_readers: info.me_maxreaders,
+        }
+        .into(),
+        _ => error.into(),
+    }
+}
+
+/// The flags to open an environment at the given path with. OSMX databases are usually
+/// single files, opened with `NO_SUB_DIR`, but directory-style environments (which keep
+/// their data in `data.mdb` and their lock in `lock.mdb`, inside a directory) are
+/// produced by some LMDB tools, so a directory is opened as one of those.
+fn environment_flags(path: &Path) -> lmdb::EnvironmentFlags {
+    if path.is_dir() {
+        lmdb::EnvironmentFlags::empty()
+    } else {
+        lmdb::EnvironmentFlags::NO_SUB_DIR
+    }
+}
+
+/// The file which holds the data of the environment at the given path.
+fn data_file(path: &Path) -> std::path::PathBuf {
+    if path.is_dir() {
+        path.join("data.mdb")
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Open a table which only exists in some databases. Returns None if it doesn't exist.
+fn open_optional_table(
+    env: &lmdb::Environment,
+    name: &str,
+) -> Result<Option<lmdb::Database>, Error> {
+    match env.open_db(Some(name)) {
+        Ok(table) => Ok(Some(table)),
+        Err(lmdb::Error::NotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Decode a bounding box stored as four little-endian i32s (west, south, east and north,
+/// in units of 1e-7 degrees). Returns None if the value has the wrong length.
+fn decode_bbox(raw_val: &[u8]) -> Option<BoundingBox> {
+    if raw_val.len() != 16 {
+        return None;
+    }
+    let degrees = |i: usize| {
+        let raw = raw_val[i * 4..(i + 1) * 4].try_into().unwrap();
+        i32::from_le_bytes(raw) as f64 / 1e7
+    };
+    Some((degrees(0), degrees(1), degrees(2), degrees(3)))
+}
+
+/// Read the `strings` table (which maps u32 IDs, starting at 1, to UTF-8 strings) into memory
+fn load_string_table(
+    txn: &lmdb::RoTransaction,
+    table: lmdb::Database,
+) -> Result<StringTable, Error> {
+    let mut strings = vec![];
+    let mut cursor = txn.open_ro_cursor(table)?;
+    for (raw_key, raw_val) in cursor.iter() {
+        let id = decode_string_id(raw_key).ok_or("strings table key with incorrect length")?;
+        if id as usize != strings.len() + 1 {
+            return Err(format!("strings table is missing ID {}", strings.len() + 1).into());
+        }
+        strings.push(std::str::from_utf8(raw_val)?.to_string());
+    }
+    Ok(StringTable::new(strings))
+}
+
+/// Read the `tag_sets` table (which maps the hashes of tag sets to their keys and values)
+/// into memory
+fn load_tag_set_table(
+    txn: &lmdb::RoTransaction,
+    table: lmdb::Database,
+) -> Result<TagSetTable, Error> {
+    let mut sets = BTreeMap::new();
+    let mut cursor = txn.open_ro_cursor(table)?;
+    for (raw_key, raw_val) in cursor.iter() {
+        let hash = decode_key(raw_key).ok_or("tag_sets table key with incorrect length")?;
+        let tags = decode_tag_set(raw_val).ok_or("malformed value in tag_sets table")?;
+        sets.insert(hash, tags);
+    }
+    Ok(TagSetTable::new(sets))
+}
+
+/// A handle which can be used to read from the Database. The handle
+/// ensures that all reads see the same snapshot of the data, even if
+/// it is being modified simultaneously by another process.
+pub struct Transaction<'db> {
+    db: &'db Database,
+    txn: lmdb::RoTransaction<'db>, // TODO support write txns?
+    reader_options: ReaderOptions,
+    started: Instant,
+    prefetchers: Arc<Prefetchers>,
+}
+
+impl<'db> Transaction<'db> {
+    /// Create a new Transaction from the given Database.
+    pub fn begin(db: &'db Database) -> Result<Self, Error> {
+        Ok(Self::from_txn(db, db.begin_ro_txn()?))
+    }
+
+    fn from_txn(db: &'db Database, txn: lmdb::RoTransaction<'db>) -> Self {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("transaction began");
+        if let Some(metrics) = &db.metrics {
+            metrics.transaction_began();
+        }
+        Self {
+            db,
+            txn,
+            reader_options: db.reader_options,
+            started: Instant::now(),
+            prefetchers: Arc::default(),
+        }
+    }
+
+    /// Begin a new Transaction by renewing one which was ended with
+    /// [Transaction::reset], reusing its handle and reader slot. If it can't be renewed,
+    /// a new one is begun instead.
+    pub(crate) fn renew(
+        db: &'db Database,
+        inactive: lmdb::InactiveTransaction<'db>,
+    ) -> Result<Self, Error> {
+        let txn = match inactive.renew() {
+            Ok(txn) => txn,
+            Err(_) => db.begin_ro_txn()?,
+        };
+        Ok(Self::from_txn(db, txn))
+    }
+
+    /// End the Transaction, releasing its snapshot but keeping its handle so that it can
+    /// be renewed with [Transaction::renew].
+    pub(crate) fn reset(self) -> lmdb::InactiveTransaction<'db> {
+        // Transaction implements Drop, so its LMDB transaction can't be moved out directly
+        let this = std::mem::ManuallyDrop::new(self);
+        this.ended();
+        // SAFETY: `this` is never dropped, so the transaction is only owned by the copy
+        unsafe { std::ptr::read(&this.txn) }.reset()
+    }
+
+    /// Override the Cap'n Proto reader options used by tables obtained from this Transaction
+    /// after this call. By default, the Database's reader options are used.
+    pub fn set_reader_options(&mut self, options: ReaderOptions) {
+        self.reader_options = options;
+    }
+
+    fn decode_context(&self, table: &str) -> DecodeContext<'db> {
+        DecodeContext {
+            options: self.reader_options,
+            encoding: self.db.value_encoding,
+            compression: self.db.value_compression,
+            dictionary: self.db.dictionaries.get(table),
+            strings: &self.db.strings,
+            tag_sets: &self.db.tag_sets,
+        }
+    }
+
+    /// The counter of skipped values to give tables, if values which can't be decoded
+    /// are skipped
+    fn skipped_values(&self) -> Option<&'db AtomicU64> {
+        match self.db.decode_mode {
+            DecodeMode::Strict => None,
+            DecodeMode::Lenient => Some(&self.db.skipped_values),
+        }
+    }
+
+    /// Get the Locations table, which maps OSM Node IDs to locations.
+    pub fn locations(&self) -> Result<Locations, Error> {
+        Ok(Locations::new(
+            &self.txn,
+            self.db.locations,
+            self.db.location_history,
+            self.decode_context("locations"),
+            self.skipped_values(),
+            &self.prefetchers,
+        ))
+    }
+
+    /// Get the Nodes table, which maps OSM Node IDs to their metadata and tags.
+    pub fn nodes(&self) -> Result<Nodes, Error> {
+        Ok(Nodes::new(
+            &self.txn,
+            self.db.nodes,
+            self.db.node_history,
+            self.decode_context("nodes"),
+            self.skipped_values(),
+            &self.prefetchers,
+        ))
+    }
+
+    /// Get the Ways table, which maps OSM Way IDs to their metadata, tags, and node refs.
+    pub fn ways(&self) -> Result<Ways, Error> {
+        Ok(Ways::new(
+            &self.txn,
+            self.db.ways,
+            self.db.way_history,
+            self.decode_context("ways"),
+            self.skipped_values(),
+            &self.prefetchers,
+        ))
+    }
+
+    /// Get the Relations table, which maps OSM Relation IDs to their metadata, tags, and member refs.
+    pub fn relations(&self) -> Result<Relations, Error> {
+        Ok(Relations::new(
+            &self.txn,
+            self.db.relations,
+            self.db.relation_history,
+            self.decode_context("relations"),
+            self.skipped_values(),
+            &self.prefetchers,
+        ))
+    }
+
+    /// Get the Changesets table, which maps changeset IDs to a summary of the element
+    /// versions created in each changeset. Returns None if the database was created
+    /// without metadata.
+    pub fn changesets(&self) -> Result<Option<Changesets<'_>>, Error> {
+        // changesets are always stored uncompressed, using the standard encoding
+        let context = DecodeContext {
+            options: self.reader_options,
+            ..Default::default()
+        };
+        Ok(self.db.changesets.map(|table| {
+            Changesets::new(
+                &self.txn,
+                table,
+                None,
+                context,
+                self.skipped_values(),
+                &self.prefetchers,
+            )
+        }))
+    }
+
+    /// Get the cell_nodes spatial index table which maps S2 Cell IDs to OSM Node IDs.
+    #[cfg(feature = "spatial")]
+    pub fn cell_nodes(&self) -> Result<SpatialIndexTable<'_>, Error> {
+        Ok(SpatialIndexTable::new(
+            &self.txn,
+            self.db.cell_node,
+            "cell_node",
+            self.db.metrics.as_deref(),
+        ))
+    }
+
+    /// Get the join table which maps OSM Nodes to the Ways that the Node is part of.
+    pub fn node_ways(&self) -> Result<JoinTable<'_>, Error> {
+        Ok(JoinTable::new(&self.txn, self.db.node_way))
+    }
+
+    /// Get the join table which maps OSM Nodes to the Relations that the Node is a member of.
+    pub fn node_relations(&self) -> Result<JoinTable<'_>, Error> {
+        Ok(JoinTable::new(&self.txn, self.db.node_relation))
+    }
+
+    /// Get the join table which maps OSM Ways to the Relations that the Way is a member of.
+    pub fn way_relations(&self) -> Result<JoinTable<'_>, Error> {
+        Ok(JoinTable::new(&self.txn, self.db.way_relation))
+    }
+
+    /// Get the join table which maps OSM Relations to other Relations that they are members of.
+    pub fn relation_relations(&self) -> Result<JoinTable<'_>, Error> {
+        Ok(JoinTable::new(&self.txn, self.db.relation_relation))
+    }
+
+    /// Returns the elements which were edited by the given user (by user ID). For databases
+    /// with history, this includes every element the user created a version of; otherwise,
+    /// only elements whose current version is by the user. Returns an error if the database
+    /// was created without the uid index. The index is only built by expand: databases
+    /// with it can't be written to with WriteTransaction.
+    pub fn elements_by_uid(&self, uid: u32) -> Result<impl Iterator<Item = ElementId> + '_, Error> {
+        let table = self
+            .db
+            .uid_element
+            .ok_or("database has no uid_element index")?;
+        self.elements_in_index(table, uid as u64)
+    }
+
+    /// Returns the elements which were edited in the given changeset. For databases with
+    /// history, this includes every element the changeset created a version of;
+    /// otherwise, only elements whose current version is from the changeset. Returns an
+    /// error if the database was created without the changeset index. The index is only
+    /// built by expand: databases with it can't be written to with WriteTransaction.
+    pub fn elements_in_changeset(
+        &self,
+        changeset: u32,
+    ) -> Result<impl Iterator<Item = ElementId> + '_, Error> {
+        let table = self
+            .db
+            .changeset_element
+            .ok_or("database has no changeset_element index")?;
+        self.elements_in_index(table, changeset as u64)
+    }
+
+    /// Get the tag keys which the tag index covers (see the --index-tags option of
+    /// expand). Returns an empty list if the database has no tag index.
+    pub fn indexed_tag_keys(&self) -> Result<Vec<&str>, Error> {
+        if self.db.tag_index.is_none() {
+            return Ok(vec![]);
+        }
+        let raw_val = match self
+            .txn
+            .get(self.db.metadata, &TAG_INDEX_KEYS_KEY.as_bytes())
+        {
+            Ok(raw_val) => raw_val,
+            Err(lmdb::Error::NotFound) => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let keys = std::str::from_utf8(raw_val)?;
+        Ok(keys.split_terminator('\0').collect())
+    }
+
+    /// Find the elements with a tag: the given key and value, or the key with any value
+    /// if `value` is None. Nodes come first, then ways, then relations, each in
+    /// ascending order of ID. If the key is indexed (see [Self::indexed_tag_keys]), the
+    /// candidates are looked up in the tag index and checked against their tags, and
+    /// otherwise every element of the nodes, ways and relations tables is read.
+    pub fn find_by_tag(&self, key: &str, value: Option<&str>) -> Result<Vec<ElementId>, Error> {
+        let matches = |tag: Option<&str>| tag.is_some_and(|tag| value.is_none_or(|v| tag == v));
+        let (nodes, ways, relations) = (self.nodes()?, self.ways()?, self.relations()?);
+
+        let indexed = self.indexed_tag_keys()?.contains(&key);
+        let Some([node_table, way_table, relation_table]) = self.db.tag_index.filter(|_| indexed)
+        else {
+            let mut elements: Vec<ElementId> = vec![];
+            elements.extend(
+                nodes
+                    .iter()
+                    .filter(|(_, node)| matches(node.tag(key)))
+                    .map(|(id, _)| ElementId::Node(id)),
+            );
+            elements.extend(
+                ways.iter()
+                    .filter(|(_, way)| matches(way.tag(key)))
+                    .map(|(id, _)| ElementId::Way(id)),
+            );
+            elements.extend(
+                relations
+                    .iter()
+                    .filter(|(_, relation)| matches(relation.tag(key)))
+                    .map(|(id, _)| ElementId::Relation(id)),
+            );
+            return Ok(elements);
+        };
+
+        let hash = match value {
+            Some(value) => tag_set_hash(&[key, value]),
+            None => tag_set_hash(&[key]),
+        };
+        // the candidates include any elements whose other tags have the same hash
+        let mut elements = vec![];
+        for id in self.index_ids(node_table, hash)? {
+            if nodes.get(id)?.is_some_and(|node| matches(node.tag(key))) {
+                elements.push(ElementId::Node(id));
+            }
+        }
+        for id in self.index_ids(way_table, hash)? {
+            if ways.get(id)?.is_some_and(|way| matches(way.tag(key))) {
+                elements.push(ElementId::Way(id));
+            }
+        }
+        for id in self.index_ids(relation_table, hash)? {
+            if relations
+                .get(id)?
+                .is_some_and(|relation| matches(relation.tag(key)))
+            {
+                elements.push(ElementId::Relation(id));
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Find the street names which start with a prefix, for completing what's typed into
+    /// a search box. The prefix and names are compared after normalizing them with
+    /// [crate::streets::normalize_street_name], so "kais" finds "Kaiserstraße" and
+    /// "Kaiser-Wilhelm-Ring". Returns up to `limit` names, those shared by the most ways
+    /// first, each with the location of one of its ways. A name used in several places
+    /// (like Main Street) is returned once for each place, and a prefix without any
+    /// letters or digits matches nothing. Returns an error if the database was created
+    /// without the street_names index.
+    pub fn complete_street(
+        &self,
+        prefix: &str,
+        limit: usize,
+    ) -> Result<Vec<StreetCompletion>, Error> {
+        let table = self
+            .db
+            .street_names
+            .ok_or("database has no street_names index")?;
+        let prefix = normalize_street_name(prefix);
+        let mut completions = vec![];
+        // LMDB has no empty keys to search from, and everything would match anyway
+        if prefix.is_empty() {
+            return Ok(completions);
+        }
+        let mut cursor = self.txn.open_ro_cursor(table)?;
+        let iter = match cursor.get(Some(prefix.as_bytes()), None, lmdb_sys::MDB_SET_RANGE) {
+            Ok(_) => cursor.iter_from(prefix.as_bytes()),
+            Err(lmdb::Error::NotFound) => return Ok(completions),
+            Err(e) => return Err(e.into()),
+        };
+        for (raw_key, raw_val) in iter {
+            if !raw_key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let (_, name, _) =
+                decode_street_name_key(raw_key).ok_or("malformed street_names key")?;
+            let (lon, lat, way_count) =
+                decode_street_name_value(raw_val).ok_or("malformed street_names value")?;
+            completions.push(StreetCompletion {
+                name: name.to_string(),
+                lon: lon as f64 / 1e7,
+                lat: lat as f64 / 1e7,
+                way_count,
+            });
+        }
+        completions.sort_by(|a, b| b.way_count.cmp(&a.way_count).then(a.name.cmp(&b.name)));
+        completions.truncate(limit);
+        Ok(completions)
+    }
+
+    /// Returns whether the given element is recorded as deleted in the `tombstones`
+    /// table, which edits databases use to hide elements of the base database they're
+    /// overlaid on (see [crate::OverlayDatabase]). Databases without the table have no
+    /// deleted elements.
+    pub fn is_deleted(&self, element: &ElementId) -> Result<bool, Error> {
+        let Some(table) = self.db.tombstones else {
+            return Ok(false);
+        };
+        match self.txn.get(table, &encode_element_ref(element)) {
+            Ok(_) => Ok(true),
+            Err(lmdb::Error::NotFound) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The IDs stored under a key of an index table whose values are IDs, in ascending
+    /// order
+    fn index_ids(&self, table: lmdb::Database, key: u64) -> Result<Vec<u64>, Error> {
+        let mut cursor = self.txn.open_ro_cursor(table)?;
+        let Some(entries) = duplicates_of(&mut cursor, key) else {
+            return Ok(vec![]);
+        };
+        entries
+            .map(|(_, raw_val)| Ok(decode_key(raw_val).ok_or("val with incorrect length")?))
+            .collect()
+    }
+
+    fn elements_in_index(
+        &self,
+        table: lmdb::Database,
+        key: u64,
+    ) -> Result<impl Iterator<Item = ElementId> + '_, Error> {
+        let cursor = self.txn.open_ro_cursor(table)?;
+
+        Ok(
+            CursorEntries::new(cursor, |cursor| duplicates_of(cursor, key)).map(|(_, raw_val)| {
+                decode_element_ref(raw_val).expect("val with incorrect length")
+            }),
+        )
+    }
+
+    /// Load the bitmap of all Node IDs in the database, if one was stored in the
+    /// metadata table at expand time. Returns None if the database has no bitmap.
+    pub fn node_id_bitmap(&self) -> Result<Option<RoaringTreemap>, Error> {
+        self.load_id_bitmap("node_ids")
+    }
+
+    /// Load the bitmap of all Way IDs in the database, if one was stored in the
+    /// metadata table at expand time. Returns None if the database has no bitmap.
+    pub fn way_id_bitmap(&self) -> Result<Option<RoaringTreemap>, Error> {
+        self.load_id_bitmap("way_ids")
+    }
+
+    /// Load the bitmap of all Relation IDs in the database, if one was stored in the
+    /// metadata table at expand time. Returns None if the database has no bitmap.
+    pub fn relation_id_bitmap(&self) -> Result<Option<RoaringTreemap>, Error> {
+        self.load_id_bitmap("relation_ids")
+    }
+
+    /// Resolve the geometries of many ways at once, keyed by way ID. Nodes missing from
+    /// the Locations table are None, and ways which aren't found are omitted.
+    ///
+    /// This collects the nodes of all the ways first and looks them up in sorted order
+    /// with a single cursor, which is much faster than looking up each way's nodes
+    /// separately when the ways share nodes or are near each other.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(ways = ids.len()))
+    )]
+    pub fn materialize_ways(&self, ids: &[u64]) -> Result<HashMap<u64, WayGeometry>, Error> {
+        let ways = self.ways()?;
+        let locations = self.locations()?;
+
+        let mut way_nodes: Vec<(u64, Vec<u64>)> = vec![];
+        for &id in ids {
+            if let Some(way) = ways.get(id)? {
+                way_nodes.push((id, way.nodes().collect()));
+            }
+        }
+
+        let mut node_ids: Vec<u64> = way_nodes
+            .iter()
+            .flat_map(|(_, nodes)| nodes.iter().copied())
+            .collect();
+        node_ids.sort_unstable();
+        node_ids.dedup();
+        let coordinates: HashMap<u64, (f64, f64)> = node_ids
+            .iter()
+            .zip(locations.get_many(&node_ids)?)
+            .filter_map(|(&id, loc)| loc.map(|loc| (id, (loc.lon(), loc.lat()))))
+            .collect();
+
+        Ok(way_nodes
+            .into_iter()
+            .map(|(id, nodes)| {
+                let geometry = nodes.iter().map(|n| coordinates.get(n).copied()).collect();
+                (id, geometry)
+            })
+            .collect())
+    }
+
+    /// Get the bounding box of a way's nodes. Uses the way_bbox table if the database has
+    /// one (see the --way-bbox option of expand), and otherwise looks up the way and its
+    /// nodes like [Way::bbox]. Returns None if the way isn't found or none of its nodes
+    /// are.
+    pub fn way_bbox(&self, id: u64) -> Result<Option<BoundingBox>, Error> {
+        let Some(table) = self.db.way_bbox else {
+            let locations = self.locations()?;
+            return match self.ways()?.get(id)? {
+                Some(way) => way.bbox(&locations),
+                None => Ok(None),
+            };
+        };
+        match self.txn.get(table, &encode_key(id)) {
+            Ok(raw_val) => Ok(Some(
+                decode_bbox(raw_val).ok_or("way_bbox value with incorrect length")?,
+            )),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the geometry of an element, as a list of parts which are lists of (longitude,
+    /// latitude) pairs in degrees: a node's location, a way's nodes in order, or the rings
+    /// of a multipolygon or boundary relation. Uses the geometries table if the database
+    /// has one (see the --with-geometry option of expand), and otherwise looks up the
+    /// element and assembles its geometry in the same way. Returns None if the element
+    /// isn't found, if any of its nodes or member ways are missing, or if it is some other
+    /// kind of relation.
+    #[cfg(feature = "export")]
+    pub fn geometry(&self, element: ElementId) -> Result<Option<Geometry>, Error> {
+        let locations = self.locations()?;
+        if let ElementId::Node(id) = element {
+            return Ok(locations
+                .get(id)?
+                .map(|location| vec![vec![(location.lon(), location.lat())]]));
+        }
+
+        let Some(table) = self.db.geometries else {
+            return Ok(match element {
+                ElementId::Way(id) => match self.ways()?.get(id)? {
+                    Some(way) => node_coordinates(&locations, way.nodes())?
+                        .map(|coordinates| vec![coordinates]),
+                    None => None,
+                },
+                ElementId::Relation(id) => match self.relations()?.get(id)? {
+                    Some(relation)
+                        if matches!(relation.tag("type"), Some("multipolygon" | "boundary")) =>
+                    {
+                        relation_rings(&relation, &self.ways()?, &locations)?
+                    }
+                    _ => None,
+                },
+                ElementId::Node(_) => unreachable!(),
+            });
+        };
+        match self.txn.get(table, &encode_element_ref(&element)) {
+            Ok(raw_val) => {
+                let parts = decode_geometry(raw_val).ok_or("malformed geometries value")?;
+                Ok(Some(
+                    parts
+                        .into_iter()
+                        .map(|part| {
+                            part.into_iter()
+                                .map(|(lon, lat)| (lon as f64 / 1e7, lat as f64 / 1e7))
+                                .collect()
+                        })
+                        .collect(),
+                ))
+            }
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Find the ways with a node in the given bounding box (west, south, east, north, in
+    /// degrees), in ascending order of ID. The candidates found with the spatial index
+    /// include ways near the edge of the box, which are filtered out by comparing their
+    /// bounding boxes with it. This is much faster in databases with a way_bbox table.
+    #[cfg(feature = "spatial")]
+    pub fn find_ways_in_bbox(&self, bbox: BoundingBox) -> Result<Vec<u64>, Error> {
+        let (west, south, east, north) = bbox;
+        let region = Region::from_bbox(west, south, east, north);
+        let node_ways = self.node_ways()?;
+        let mut candidates = RoaringTreemap::new();
+        for node_id in self.cell_nodes()?.find_in_region(&region) {
+            candidates.extend(node_ways.get(node_id));
+        }
+
+        let mut way_ids = vec![];
+        for id in candidates {
+            if let Some((w, s, e, n)) = self.way_bbox(id)? {
+                if w <= east && west <= e && s <= north && south <= n {
+                    way_ids.push(id);
+                }
+            }
+        }
+        Ok(way_ids)
+    }
+
+    /// Find the ways which may overlap a region, in ascending order of ID. In databases
+    /// with a cell_way table (see the --cell-index option of expand), these are the ways
+    /// whose bounding boxes overlap the region's cells, and otherwise those with a node in
+    /// them (found with the cell_node table), which misses ways crossing the region
+    /// without a node in it. As with `find_in_region`, there may be false positives near
+    /// the edge of the region.
+    #[cfg(feature = "spatial")]
+    pub fn ways_in_region(&self, region: &Region) -> Result<Vec<u64>, Error> {
+        Ok(self.way_candidates(region)?.into_iter().collect())
+    }
+
+    /// Find the relations which may overlap a region, in ascending order of ID. In
+    /// databases with a cell_relation table (see the --cell-index option of expand), these
+    /// are the relations whose members' bounding box overlaps the region's cells, and
+    /// otherwise those with a member node or way found like [Self::ways_in_region], or a
+    /// member relation found this way. There may be false positives, as with
+    /// `ways_in_region`.
+    #[cfg(feature = "spatial")]
+    pub fn relations_in_region(&self, region: &Region) -> Result<Vec<u64>, Error> {
+        if let Some(table) = self.db.cell_relation {
+            let index = SpatialIndexTable::new(
+                &self.txn,
+                table,
+                "cell_relation",
+                self.db.metrics.as_deref(),
+            );
+            return Ok(index.find_overlapping(region)?.into_iter().collect());
+        }
+
+        let mut relation_ids = RoaringTreemap::new();
+        let node_relations = self.node_relations()?;
+        for node_id in self.cell_nodes()?.find_in_region(region) {
+            relation_ids.extend(node_relations.get(node_id));
+        }
+        let way_relations = self.way_relations()?;
+        for way_id in self.way_candidates(region)? {
+            relation_ids.extend(way_relations.get(way_id));
+        }
+        // add the relations containing those, and so on (relations can contain each
+        // other, so only those not seen before are followed)
+        let relation_relations = self.relation_relations()?;
+        let mut pending: Vec<u64> = relation_ids.iter().collect();
+        while let Some(id) = pending.pop() {
+            for parent_id in relation_relations.get(id) {
+                if relation_ids.insert(parent_id) {
+                    pending.push(parent_id);
+                }
+            }
+        }
+        Ok(relation_ids.into_iter().collect())
+    }
+
+    /// The ways which may overlap a region (see [Self::ways_in_region])
+    #[cfg(feature = "spatial")]
+    fn way_candidates(&self, region: &Region) -> Result<RoaringTreemap, Error> {
+        if let Some(table) = self.db.cell_way {
+            let index =
+                SpatialIndexTable::new(&self.txn, table, "cell_way", self.db.metrics.as_deref());
+            return index.find_overlapping(region);
+        }
+        let node_ways = self.node_ways()?;
+        let mut way_ids = RoaringTreemap::new();
+        for node_id in self.cell_nodes()?.find_in_region(region) {
+            way_ids.extend(node_ways.get(node_id));
+        }
+        Ok(way_ids)
+    }
+
+    /// Get the location of a Node as it was at the given time (in seconds since the Unix
+    /// epoch). Returns None if the Node didn't exist at that time, or the database doesn't
+    /// store history.
+    pub fn location_as_of(&self, id: u64, timestamp: u64) -> Result<Option<Location<'_>>, Error> {
+        let version = match self.nodes()?.get_as_of(id, timestamp)? {
+            Some(node) => match node.metadata() {
+                Some(metadata) => metadata.version(),
+                None => return Ok(None),
+            },
+            None => return Ok(None),
+        };
+        self.locations()?.get_version(id, version)
+    }
+
+    /// Get the timestamp of the replication state the data is current to, as an ISO 8601
+    /// string. Returns None if the database doesn't record one.
+    pub fn replication_timestamp(&self) -> Result<Option<&str>, Error> {
+        self.metadata_str(interop::REPLICATION_TIMESTAMP_KEY)
+    }
+
+    /// Get the replication sequence number the data is current to. Returns None if the
+    /// database doesn't record one.
+    pub fn replication_sequence_number(&self) -> Result<Option<u64>, Error> {
+        match self.metadata_str(interop::REPLICATION_SEQUENCE_NUMBER_KEY)? {
+            Some(s) => Ok(Some(s.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the base URL of the replication server the data is updated from. Returns None
+    /// if the database doesn't record one.
+    pub fn replication_base_url(&self) -> Result<Option<&str>, Error> {
+        self.metadata_str(interop::REPLICATION_BASE_URL_KEY)
+    }
+
+    /// Get the name of the file the database was imported from. Returns None if the
+    /// database doesn't record one.
+    pub fn import_filename(&self) -> Result<Option<&str>, Error> {
+        self.metadata_str(interop::IMPORT_FILENAME_KEY)
+    }
+
+    /// Get the SHA-256 digest of the file the database was imported from, as a lowercase
+    /// hex string. Returns None if the database doesn't record one (databases created by
+    /// older versions, or by other tools).
+    pub fn source_sha256(&self) -> Result<Option<&str>, Error> {
+        self.metadata_str(SOURCE_SHA256_KEY)
+    }
+
+    /// Get the path of the database this one was extracted from. Returns None if the
+    /// database isn't an extract.
+    pub fn extract_source(&self) -> Result<Option<&str>, Error> {
+        self.metadata_str(EXTRACT_SOURCE_KEY)
+    }
+
+    /// Get the bounding box of the nodes in the database, as (west, south, east, north) in
+    /// degrees. Returns None if the database doesn't record one, which is the case for
+    /// databases without nodes and those created by older versions.
+    pub fn bounds(&self) -> Result<Option<BoundingBox>, Error> {
+        let raw_val = match self.txn.get(self.db.metadata, &BOUNDS_KEY.as_bytes()) {
+            Ok(raw_val) => raw_val,
+            Err(lmdb::Error::NotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match decode_bbox(raw_val) {
+            Some(bbox) => Ok(Some(bbox)),
+            None => Err(format!("{} metadata has incorrect length", BOUNDS_KEY).into()),
+        }
+    }
+
+    /// Get the number of current nodes, ways and relations in the database, as recorded
+    /// when it was written. Returns None if the database doesn't record them (databases
+    /// created by older versions, or by other tools).
+    pub fn counts(&self) -> Result<Option<ElementCounts>, Error> {
+        match self.txn.get(self.db.metadata, &COUNTS_KEY.as_bytes()) {
+            Ok(raw_val) => match ElementCounts::from_bytes(raw_val) {
+                Some(counts) => Ok(Some(counts)),
+                None => Err(format!("{} metadata has incorrect length", COUNTS_KEY).into()),
+            },
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the writer lock of the database (see [Database::try_exclusive_writer]), if a
+    /// process holds it.
+    pub fn writer_lock(&self) -> Result<Option<WriterLock>, Error> {
+        match self.txn.get(self.db.metadata, &WRITER_LOCK_KEY.as_bytes()) {
+            Ok(raw_val) => match WriterLock::from_bytes(raw_val) {
+                Some(lock) => Ok(Some(lock)),
+                None => Err(format!("{} metadata is malformed", WRITER_LOCK_KEY).into()),
+            },
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get the raw value stored under a key of the metadata table, such as one of the
+    /// keys in [crate::interop] or [BOUNDS_KEY]. The typed accessors (like
+    /// [Self::replication_timestamp]) are easier to use for the keys they cover. Returns
+    /// None if there is no value under the key.
+    pub fn metadata(&self, key: &str) -> Result<Option<&[u8]>, Error> {
+        match self.txn.get(self.db.metadata, &key.as_bytes()) {
+            Ok(raw_val) => Ok(Some(raw_val)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get a value stored with [Database::set_user_metadata]. Returns None if there is
+    /// no value under the namespace and key.
+    pub fn user_metadata(&self, namespace: &str, key: &str) -> Result<Option<&[u8]>, Error> {
+        let key = user_metadata_key(namespace, key)?;
+        match self.txn.get(self.db.metadata, &key.as_bytes()) {
+            Ok(raw_val) => Ok(Some(raw_val)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get an extension table (see [Database::create_extension_table]), to read in this
+    /// transaction's snapshot of the database. Returns an error if there's no table with
+    /// the name.
+    pub fn extension_table(&self, name: &str) -> Result<ExtensionTable<'_>, Error> {
+        extension_table_name(name)?;
+        match self.db.extension_tables.get(name) {
+            Some(&table) => Ok(ExtensionTable {
+                txn: &self.txn,
+                table,
+            }),
+            None => Err(format!("database has no extension table {}", name).into()),
+        }
+    }
+
+    fn metadata_str(&self, key: &str) -> Result<Option<&str>, Error> {
+        match self.txn.get(self.db.metadata, &key.as_bytes()) {
+            Ok(raw_val) => Ok(Some(std::str::from_utf8(raw_val)?)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn load_id_bitmap(&self, key: &str) -> Result<Option<RoaringTreemap>, Error> {
+        match self.txn.get(self.db.metadata, &key.as_bytes()) {
+            Ok(raw_val) => Ok(Some(RoaringTreemap::deserialize_from(raw_val)?)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Transaction<'_> {
+    fn ended(&self) {
+        // the helper threads of iter_prefetch read with this transaction
+        self.prefetchers.stop();
+        let elapsed = self.started.elapsed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            elapsed_ms = elapsed.as_secs_f64() * 1000.0,
+            "transaction ended"
+        );
+        if let Some(metrics) = &self.db.metrics {
+            metrics.transaction_ended(elapsed);
+        }
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        self.ended();
+    }
+}
+
+/// A read-write transaction, which changes the elements of a Database. Besides the
+/// element tables, it keeps the tables derived from them up to date: the `cell_node`
+/// spatial index, the join tables, the element counts and bounds, and the ID bitmaps, the
+/// tag index, the way bounding boxes, the `cell_way` and `cell_relation` spatial indexes,
+/// the geometries, the street names and the indexes of plugins (if the database has
+/// them). The bounding boxes, the cells covering them, the geometries and the street
+/// names are recomputed on commit, for the ways and relations which changed or whose
+/// nodes or members did.
+///
+/// Changes are only visible to Transactions which begin after [Self::commit]. Dropping
+/// a WriteTransaction without committing it discards its changes. LMDB allows one write
+/// transaction at a time, so beginning one blocks until any other has ended.
+///
+/// Databases with tables which this can't keep up to date (history, changesets, the uid,
+/// changeset indexes), or with compressed values, can't be written to.
+#[cfg(feature = "update")]
+pub struct WriteTransaction<'db> {
+    db: &'db Database,
+    txn: lmdb::RwTransaction<'db>,
+    counts: Option<ElementCounts>,
+    // the node, way and relation ID bitmaps, if the database has them
+    id_bitmaps: [Option<RoaringTreemap>; 3],
+    // the bounds of the nodes written, in units of 1e-7 degrees
+    bounds: Option<(i32, i32, i32, i32)>,
+    // the tag_node, tag_way and tag_relation tables and the keys they index, if the
+    // database has a tag index
+    tag_index: Option<([lmdb::Database; 3], HashSet<String>)>,
+    // the ways and relations whose bounding boxes, cells, geometries and street names are
+    // recomputed on commit
+    stale: StaleElements,
+    // the database's index plugins, and their extension tables
+    plugins: Vec<(Box<dyn IndexPlugin>, lmdb::Database)>,
+    // the callback registered with on_change, and the changes to give it on commit
+    on_change: Option<ChangeCallback<'db>>,
+    element_changes: Vec<ElementChange>,
+    // for the database's MetricsSink: the number of elements written or deleted, when
+    // the transaction began, and the replication state recorded
+    changes: u64,
+    started: Instant,
+    replication_state: Option<(String, u64)>,
+}
+
+/// Error returned by the `put_*_if_version` and `delete_*_if_version` methods of
+/// [WriteTransaction] when an element doesn't have the expected version.
+#[cfg(feature = "update")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionConflict {
+    /// The element which was to be written or deleted.
+    pub element: ElementId,
+    /// The version it was expected to have, or None if it was expected not to exist.
+    pub expected: Option<u32>,
+    /// Whether it exists.
+    pub exists: bool,
+    /// Its stored version, if it exists and has one (ways and relations only have one if
+    /// their values have metadata).
+    pub found: Option<u32>,
+}
+
+#[cfg(feature = "update")]
+impl std::fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (kind, id) = match self.element {
+            ElementId::Node(id) => ("node", id),
+            ElementId::Way(id) => ("way", id),
+            ElementId::Relation(id) => ("relation", id),
+        };
+        match (self.expected, self.exists, self.found) {
+            (None, _, _) => write!(f, "{} {} already exists", kind, id),
+            (Some(expected), false, _) => write!(
+                f,
+                "{} {} doesn't exist, but was expected to have version {}",
+                kind, id, expected
+            ),
+            (Some(expected), true, None) => write!(
+                f,
+                "{} {} has no version to compare with the expected version {}",
+                kind, id, expected
+            ),
+            (Some(expected), true, Some(found)) => write!(
+                f,
+                "{} {} has version {}, not the expected version {}",
+                kind, id, found, expected
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "update")]
+impl std::error::Error for VersionConflict {}
+
+/// A change to an element made by a [WriteTransaction], as given to the callback
+/// registered with [WriteTransaction::on_change].
+#[cfg(feature = "update")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementChange {
+    pub element: ElementId,
+    /// The element before the change, or None if it was created.
+    pub old: Option<ElementState>,
+    /// The element after the change, or None if it was deleted.
+    pub new: Option<ElementState>,
+}
+
+/// The contents of an element before or after an [ElementChange].
+#[cfg(feature = "update")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElementState {
+    pub tags: Vec<(String, String)>,
+    /// A node's location (longitude, latitude), or None for ways and relations.
+    pub location: Option<(f64, f64)>,
+    /// A way's nodes, or empty for nodes and relations.
+    pub nodes: Vec<u64>,
+    /// A relation's members and their roles, or empty for nodes and ways.
+    pub members: Vec<(ElementId, String)>,
+}
+
+#[cfg(feature = "update")]
+type ChangeCallback<'db> = Box<dyn FnMut(&ElementChange) + 'db>;
+
+#[cfg(feature = "update")]
+const ID_BITMAP_KEYS: [&str; 3] = ["node_ids", "way_ids", "relation_ids"];
+
+#[cfg(feature = "update")]
+impl<'db> WriteTransaction<'db> {
+    /// Begin a new WriteTransaction on the given Database. Returns an error if the
+    /// database has tables or values which it can't keep up to date, including the
+    /// indexes of plugins other than the built-in ones (see
+    /// [Self::begin_with_plugins]).
+    ///
+    /// Like the other methods which write, this doesn't check the writer lock (see
+    /// [Database::try_exclusive_writer]).
+    pub fn begin(db: &'db Database) -> Result<Self, Error> {
+        Self::begin_with_plugins(db, &PluginRegistry::new())
+    }
+
+    /// Begin a new WriteTransaction, which runs the database's index plugins (see
+    /// [INDEX_PLUGINS_KEY]) on the elements written and deleted, making them from the
+    /// given registry. Returns an error if the registry doesn't have one of them.
+    pub fn begin_with_plugins(db: &'db Database, registry: &PluginRegistry) -> Result<Self, Error> {
+        let unsupported = [
+            ("history", db.node_history.is_some()),
+            ("changesets", db.changesets.is_some()),
+            // replacing an element's entries needs the user and changeset of its old
+            // version, which values only have in databases with metadata (and so
+            // changesets)
+            ("the uid index", db.uid_element.is_some()),
+            ("the changeset index", db.changeset_element.is_some()),
+            // the cells a way covered before it changed are found from its bounding box
+            (
+                "way and relation spatial indexes but no way bounding boxes",
+                (db.cell_way.is_some() || db.cell_relation.is_some()) && db.way_bbox.is_none(),
+            ),
+            (
+                "compressed values",
+                db.value_compression != ValueCompression::None,
+            ),
+        ];
+        if let Some((name, _)) = unsupported.iter().find(|(_, present)| *present) {
+            return Err(format!("can't write to a database with {}", name).into());
+        }
+
+        let txn = db.env.begin_rw_txn()?;
+        let counts = match txn.get(db.metadata, &COUNTS_KEY.as_bytes()) {
+            Ok(raw_val) => Some(
+                ElementCounts::from_bytes(raw_val)
+                    .ok_or_else(|| format!("{} metadata has incorrect length", COUNTS_KEY))?,
+            ),
+            Err(lmdb::Error::NotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+        let mut id_bitmaps = [None, None, None];
+        for (bitmap, key) in id_bitmaps.iter_mut().zip(ID_BITMAP_KEYS) {
+            *bitmap = match txn.get(db.metadata, &key.as_bytes()) {
+                Ok(raw_val) => Some(RoaringTreemap::deserialize_from(raw_val)?),
+                Err(lmdb::Error::NotFound) => None,
+                Err(e) => return Err(e.into()),
+            };
+        }
+        let tag_index = match db.tag_index {
+            Some(tables) => {
+                let keys = match txn.get(db.metadata, &TAG_INDEX_KEYS_KEY.as_bytes()) {
+                    Ok(raw_val) => std::str::from_utf8(raw_val)?
+                        .split_terminator('\0')
+                        .map(String::from)
+                        .collect(),
+                    Err(lmdb::Error::NotFound) => HashSet::new(),
+                    Err(e) => return Err(e.into()),
+                };
+                Some((tables, keys))
+            }
+            None => None,
+        };
+        let plugin_names: Vec<String> = match txn.get(db.metadata, &INDEX_PLUGINS_KEY.as_bytes()) {
+            Ok(raw_val) => std::str::from_utf8(raw_val)?
+                .split_terminator('\0')
+                .map(String::from)
+                .collect(),
+            Err(lmdb::Error::NotFound) => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        let mut plugins = vec![];
+        for name in plugin_names {
+            let plugin = registry.make(&name).ok_or_else(|| {
+                format!(
+                    "can't write to a database with the {} index plugin, which isn't registered",
+                    name
+                )
+            })?;
+            let table = *db.extension_tables.get(&name).ok_or_else(|| {
+                format!(
+                    "database has no extension table for the {} index plugin",
+                    name
+                )
+            })?;
+            plugins.push((plugin, table));
+        }
+
+        Ok(Self {
+            db,
+            txn,
+            counts,
+            id_bitmaps,
+            bounds: None,
+            tag_index,
+            stale: StaleElements::default(),
+            plugins,
+            on_change: None,
+            element_changes: vec![],
+            changes: 0,
+            started: Instant::now(),
+            replication_state: None,
+        })
+    }
+
+    /// Write a node: its location, and its value in the nodes table, which holds its
+    /// tags and metadata. Pass None for the value to store the node in the locations
+    /// table only (as `osmx expand` does for nodes without tags or metadata). Replaces
+    /// the node if it exists.
+    pub fn put_node(
+        &mut self,
+        id: u64,
+        location: &LocationBuilder,
+        node: Option<&NodeBuilder>,
+    ) -> Result<(), Error> {
+        self.node_changing(id)?;
+        let old = self.state_before_change(ElementId::Node(id))?;
+        let existed = self.remove_node_cell(id)?;
+        let old_tags = self.indexed_tags(ElementId::Node(id))?;
+        let (x, y) = location.fixed();
+        let flags = lmdb::WriteFlags::empty();
+        self.txn
+            .put(self.db.locations, &encode_key(id), &location.build(), flags)?;
+        self.txn.put(
+            self.db.cell_node,
+            &encode_key(cell_of(x, y)),
+            &encode_key(id),
+            flags,
+        )?;
+        let value = node.map(|node| node.build(self.db.value_encoding));
+        match &value {
+            Some(value) => self.txn.put(self.db.nodes, &encode_key(id), value, flags)?,
+            None => self.delete_value(self.db.nodes, id)?,
+        }
+        if !self.plugins.is_empty() || self.tag_index.is_some() {
+            let tags = match &value {
+                Some(value) => tag_list(Node::decode(value, self.decode_context("nodes"))?.tags()),
+                None => vec![],
+            };
+            self.index_tags(ElementId::Node(id), &old_tags, &tags)?;
+            self.run_plugins(ElementId::Node(id), &tags, existed, false)?;
+        }
+
+        self.bounds = Some(extend_bbox(self.bounds, (x, y)));
+        self.record_change(ElementId::Node(id), old)?;
+        self.added(0, id, existed);
+        Ok(())
+    }
+
+    /// Write a way, updating the node_way table for the nodes it had before and has
+    /// now. Replaces the way if it exists.
+    pub fn put_way(&mut self, id: u64, way: &WayBuilder) -> Result<(), Error> {
+        self.way_changing(id)?;
+        let old = self.state_before_change(ElementId::Way(id))?;
+        let existed = self.remove_way_parents(id)?;
+        let old_tags = self.indexed_tags(ElementId::Way(id))?;
+        let value = way.build(self.db.value_encoding);
+        self.txn.put(
+            self.db.ways,
+            &encode_key(id),
+            &value,
+            lmdb::WriteFlags::empty(),
+        )?;
+        for node_id in way.nodes() {
+            self.put_pair(self.db.node_way, node_id, id)?;
+        }
+        if !self.plugins.is_empty() || self.tag_index.is_some() {
+            let tags = tag_list(Way::decode(&value, self.decode_context("ways"))?.tags());
+            self.index_tags(ElementId::Way(id), &old_tags, &tags)?;
+            self.run_plugins(ElementId::Way(id), &tags, existed, false)?;
+        }
+        self.record_change(ElementId::Way(id), old)?;
+        self.added(1, id, existed);
+        Ok(())
+    }
+
+    /// Write a relation, updating the node_relation, way_relation and relation_relation
+    /// tables for the members it had before and has now. Replaces the relation if it
+    /// exists.
+    pub fn put_relation(&mut self, id: u64, relation: &RelationBuilder) -> Result<(), Error> {
+        self.relation_changing(id)?;
+        let old = self.state_before_change(ElementId::Relation(id))?;
+        let existed = self.remove_relation_parents(id)?;
+        let old_tags = self.indexed_tags(ElementId::Relation(id))?;
+        let value = relation.build(self.db.value_encoding);
+        self.txn.put(
+            self.db.relations,
+            &encode_key(id),
+            &value,
+            lmdb::WriteFlags::empty(),
+        )?;
+        for member in relation.members() {
+            let (table, member_id) = self.member_table(member);
+            self.put_pair(table, member_id, id)?;
+        }
+        if !self.plugins.is_empty() || self.tag_index.is_some() {
+            let tags = tag_list(Relation::decode(&value, self.decode_context("relations"))?.tags());
+            self.index_tags(ElementId::Relation(id), &old_tags, &tags)?;
+            self.run_plugins(ElementId::Relation(id), &tags, existed, false)?;
+        }
+        self.record_change(ElementId::Relation(id), old)?;
+        self.added(2, id, existed);
+        Ok(())
+    }
+
+    /// Delete a node (its location and value). Returns false if it didn't exist. The
+    /// ways and relations it's a member of are left unchanged.
+    pub fn delete_node(&mut self, id: u64) -> Result<bool, Error> {
+        self.node_changing(id)?;
+        let old = self.state_before_change(ElementId::Node(id))?;
+        let existed = self.remove_node_cell(id)?;
+        let old_tags = self.indexed_tags(ElementId::Node(id))?;
+        self.index_tags(ElementId::Node(id), &old_tags, &[])?;
+        self.delete_value(self.db.locations, id)?;
+        self.delete_value(self.db.nodes, id)?;
+        if existed {
+            self.run_plugins(ElementId::Node(id), &[], true, true)?;
+        }
+        self.record_change(ElementId::Node(id), old)?;
+        self.removed(0, id, existed);
+        Ok(existed)
+    }
+
+    /// Delete a way, and its entries in the node_way table. Returns false if it didn't
+    /// exist.
+    pub fn delete_way(&mut self, id: u64) -> Result<bool, Error> {
+        self.way_changing(id)?;
+        let old = self.state_before_change(ElementId::Way(id))?;
+        let existed = self.remove_way_parents(id)?;
+        let old_tags = self.indexed_tags(ElementId::Way(id))?;
+        self.index_tags(ElementId::Way(id), &old_tags, &[])?;
+        self.delete_value(self.db.ways, id)?;
+        if existed {
+            self.run_plugins(ElementId::Way(id), &[], true, true)?;
+        }
+        self.record_change(ElementId::Way(id), old)?;
+        self.removed(1, id, existed);
+        Ok(existed)
+    }
+
+    /// Delete a relation, and its entries in the join tables of its members. Returns
+    /// false if it didn't exist.
+    pub fn delete_relation(&mut self, id: u64) -> Result<bool, Error> {
+        self.relation_changing(id)?;
+        let old = self.state_before_change(ElementId::Relation(id))?;
+        let existed = self.remove_relation_parents(id)?;
+        let old_tags = self.indexed_tags(ElementId::Relation(id))?;
+        self.index_tags(ElementId::Relation(id), &old_tags, &[])?;
+        self.delete_value(self.db.relations, id)?;
+        if existed {
+            self.run_plugins(ElementId::Relation(id), &[], true, true)?;
+        }
+        self.record_change(ElementId::Relation(id), old)?;
+        self.removed(2, id, existed);
+        Ok(existed)
+    }
+
+    /// Register a callback to be given each element created, modified or deleted by this
+    /// transaction, with its contents before and after the change, once the transaction
+    /// has committed (so that, for example, a search index kept alongside the database
+    /// only sees changes which were kept). The changes are given in the order they were
+    /// made, and an element written more than once is given once for each write. To
+    /// receive them on another thread, send them to a channel from the callback.
+    pub fn on_change(&mut self, callback: impl FnMut(&ElementChange) + 'db) {
+        self.on_change = Some(Box::new(callback));
+    }
+
+    /// Returns true if an element exists, including the changes made so far by this
+    /// transaction.
+    pub fn contains(&self, element: ElementId) -> Result<bool, Error> {
+        let (table, id) = match element {
+            ElementId::Node(id) => (self.db.locations, id),
+            ElementId::Way(id) => (self.db.ways, id),
+            ElementId::Relation(id) => (self.db.relations, id),
+        };
+        Ok(self.get(table, id)?.is_some())
+    }
+
+    /// Get a node's location as a (longitude, latitude) pair in degrees, including the
+    /// changes made so far by this transaction. Returns None if the node doesn't exist.
+    pub fn node_location(&self, id: u64) -> Result<Option<(f64, f64)>, Error> {
+        Ok(self
+            .location(id)?
+            .map(|(x, y)| (x as f64 / 1e7, y as f64 / 1e7)))
+    }
+
+    /// Write a node like [Self::put_node], if its stored version (in its location) is
+    /// `expected_version`, or if it doesn't exist and `expected_version` is None.
+    /// Otherwise nothing is written and [Error::Conflict] is returned, so that writers can
+    /// avoid overwriting each other's changes.
+    pub fn put_node_if_version(
+        &mut self,
+        id: u64,
+        expected_version: Option<u32>,
+        location: &LocationBuilder,
+        node: Option<&NodeBuilder>,
+    ) -> Result<(), Error> {
+        self.check_version(ElementId::Node(id), expected_version)?;
+        self.put_node(id, location, node)
+    }
+
+    /// Write a way like [Self::put_way], if its stored version is `expected_version`, or
+    /// if it doesn't exist and `expected_version` is None. Otherwise nothing is written
+    /// and [Error::Conflict] is returned. The version is read from the metadata of the
+    /// way's value, so a way written without metadata (see [WayBuilder::set_metadata])
+    /// always conflicts.
+    pub fn put_way_if_version(
+        &mut self,
+        id: u64,
+        expected_version: Option<u32>,
+        way: &WayBuilder,
+    ) -> Result<(), Error> {
+        self.check_version(ElementId::Way(id), expected_version)?;
+        self.put_way(id, way)
+    }
+
+    /// Write a relation like [Self::put_relation], if its stored version is
+    /// `expected_version`, or if it doesn't exist and `expected_version` is None.
+    /// Otherwise nothing is written and [Error::Conflict] is returned. Like that of ways,
+    /// the version is read from the metadata of the relation's value.
+    pub fn put_relation_if_version(
+        &mut self,
+        id: u64,
+        expected_version: Option<u32>,
+        relation: &RelationBuilder,
+    ) -> Result<(), Error> {
+        self.check_version(ElementId::Relation(id), expected_version)?;
+        self.put_relation(id, relation)
+    }
+
+    /// Delete a node like [Self::delete_node], if its stored version is
+    /// `expected_version`. Otherwise (including if it doesn't exist) nothing is deleted
+    /// and [Error::Conflict] is returned.
+    pub fn delete_node_if_version(&mut self, id: u64, expected_version: u32) -> Result<(), Error> {
+        self.check_version(ElementId::Node(id), Some(expected_version))?;
+        self.delete_node(id)?;
+        Ok(())
+    }
+
+    /// Delete a way like [Self::delete_way], if its stored version is `expected_version`.
+    /// Otherwise nothing is deleted and [Error::Conflict] is returned.
+    pub fn delete_way_if_version(&mut self, id: u64, expected_version: u32) -> Result<(), Error> {
+        self.check_version(ElementId::Way(id), Some(expected_version))?;
+        self.delete_way(id)?;
+        Ok(())
+    }
+
+    /// Delete a relation like [Self::delete_relation], if its stored version is
+    /// `expected_version`. Otherwise nothing is deleted and [Error::Conflict] is returned.
+    pub fn delete_relation_if_version(
+        &mut self,
+        id: u64,
+        expected_version: u32,
+    ) -> Result<(), Error> {
+        self.check_version(ElementId::Relation(id), Some(expected_version))?;
+        self.delete_relation(id)?;
+        Ok(())
+    }
+
+    /// Record the replication state (see [Transaction::replication_timestamp]) which the
+    /// changes bring the database up to.
+    pub fn set_replication_state(
+        &mut self,
+        timestamp: &str,
+        sequence_number: u64,
+    ) -> Result<(), Error> {
+        let flags = lmdb::WriteFlags::empty();
+        self.txn.put(
+            self.db.metadata,
+            &interop::REPLICATION_TIMESTAMP_KEY.as_bytes(),
+            &timestamp.as_bytes(),
+            flags,
+        )?;
+        self.txn.put(
+            self.db.metadata,
+            &interop::REPLICATION_SEQUENCE_NUMBER_KEY.as_bytes(),
+            &sequence_number.to_string().as_bytes(),
+            flags,
+        )?;
+        self.replication_state = Some((timestamp.to_string(), sequence_number));
+        Ok(())
+    }
+
+    /// Commit the changes (and sync them according to the [Durability] policy), along
+    /// with the updated element counts, bounds and ID bitmaps, and the recomputed
+    /// bounding boxes, cells, geometries and street names of the ways and relations
+    /// affected by them.
+    pub fn commit(mut self) -> Result<(), Error> {
+        self.refresh_stale()?;
+        let flags = lmdb::WriteFlags::empty();
+        if let Some(counts) = self.counts {
+            self.txn.put(
+                self.db.metadata,
+                &COUNTS_KEY.as_bytes(),
+                &counts.to_bytes(),
+                flags,
+            )?;
+        }
+        for (bitmap, key) in self.id_bitmaps.iter().zip(ID_BITMAP_KEYS) {
+            if let Some(bitmap) = bitmap {
+                let mut raw_val = vec![];
+                bitmap.serialize_into(&mut raw_val)?;
+                self.txn
+                    .put(self.db.metadata, &key.as_bytes(), &raw_val, flags)?;
+            }
+        }
+        if let Some((mut west, mut south, mut east, mut north)) = self.bounds {
+            // the bounds only grow, since shrinking them would mean reading every node
+            match self.txn.get(self.db.metadata, &BOUNDS_KEY.as_bytes()) {
+                Ok(raw_val) if raw_val.len() == 16 => {
+                    let fixed = |i: usize| {
+                        i32::from_le_bytes(raw_val[i * 4..(i + 1) * 4].try_into().unwrap())
+                    };
+                    west = west.min(fixed(0));
+                    south = south.min(fixed(1));
+                    east = east.max(fixed(2));
+                    north = north.max(fixed(3));
+                }
+                Ok(_) => return Err(format!("{} metadata has incorrect length", BOUNDS_KEY).into()),
+                Err(lmdb::Error::NotFound) => (),
+                Err(e) => return Err(e.into()),
+            }
+            let raw_val: Vec<u8> = [west, south, east, north]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect();
+            self.txn
+                .put(self.db.metadata, &BOUNDS_KEY.as_bytes(), &raw_val, flags)?;
+        }
+        self.txn.commit()?;
+        self.db.committed()?;
+        if let Some(callback) = &mut self.on_change {
+            for change in &self.element_changes {
+                callback(change);
+            }
+        }
+        if let Some(metrics) = &self.db.metrics {
+            metrics.write_committed(&Commit {
+                changes: self.changes,
+                duration: self.started.elapsed(),
+                replication_state: self
+                    .replication_state
+                    .as_ref()
+                    .map(|(timestamp, sequence_number)| (timestamp.as_str(), *sequence_number)),
+            });
+        }
+        Ok(())
+    }
+
+    fn decode_context(&self, table: &str) -> DecodeContext<'_> {
+        DecodeContext {
+            options: self.db.reader_options,
+            encoding: self.db.value_encoding,
+            compression: self.db.value_compression,
+            dictionary: self.db.dictionaries.get(table),
+            strings: &self.db.strings,
+            tag_sets: &self.db.tag_sets,
+        }
+    }
+
+    /// An element's contents before it's written or deleted, for the on_change callback
+    /// (so None if there's no callback, or Some(None) if the element doesn't exist)
+    fn state_before_change(
+        &self,
+        element: ElementId,
+    ) -> Result<Option<Option<ElementState>>, Error> {
+        match self.on_change {
+            Some(_) => Ok(Some(self.element_state(element)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Record a change for the on_change callback, given the element's contents before it
+    fn record_change(
+        &mut self,
+        element: ElementId,
+        old: Option<Option<ElementState>>,
+    ) -> Result<(), Error> {
+        if let Some(old) = old {
+            let new = self.element_state(element)?;
+            if old.is_some() || new.is_some() {
+                self.element_changes
+                    .push(ElementChange { element, old, new });
+            }
+        }
+        Ok(())
+    }
+
+    /// An element's contents, or None if it doesn't exist
+    fn element_state(&self, element: ElementId) -> Result<Option<ElementState>, Error> {
+        let mut state = ElementState {
+            tags: vec![],
+            location: None,
+            nodes: vec![],
+            members: vec![],
+        };
+        match element {
+            ElementId::Node(id) => {
+                state.location = match self.node_location(id)? {
+                    Some(location) => Some(location),
+                    None => return Ok(None),
+                };
+                if let Some(raw_val) = self.get(self.db.nodes, id)? {
+                    state.tags =
+                        tag_list(Node::decode(raw_val, self.decode_context("nodes"))?.tags());
+                }
+            }
+            ElementId::Way(id) => {
+                let Some(raw_val) = self.get(self.db.ways, id)? else {
+                    return Ok(None);
+                };
+                let way = Way::decode(raw_val, self.decode_context("ways"))?;
+                state.tags = tag_list(way.tags());
+                state.nodes = way.nodes().collect();
+            }
+            ElementId::Relation(id) => {
+                let Some(raw_val) = self.get(self.db.relations, id)? else {
+                    return Ok(None);
+                };
+                let relation = Relation::decode(raw_val, self.decode_context("relations"))?;
+                state.tags = tag_list(relation.tags());
+                state.members = relation
+                    .members()
+                    .map(|member| (member.id(), member.role().to_string()))
+                    .collect();
+            }
+        }
+        Ok(Some(state))
+    }
+
+    /// Run the index plugins on an element which was written with the given tags, or
+    /// deleted
+    fn run_plugins(
+        &mut self,
+        id: ElementId,
+        tags: &[(String, String)],
+        replacing: bool,
+        deleted: bool,
+    ) -> Result<(), Error> {
+        let element = PluginElement {
+            id,
+            tags,
+            replacing,
+            deleted,
+        };
+        for (plugin, table) in self.plugins.iter_mut() {
+            plugin.index(&element, &mut PluginTable::new(&mut self.txn, *table))?;
+        }
+        Ok(())
+    }
+
+    /// Check that an element has the expected version, or doesn't exist if None is
+    /// expected, returning [Error::Conflict] if not
+    fn check_version(&self, element: ElementId, expected: Option<u32>) -> Result<(), Error> {
+        let (exists, found) = match element {
+            ElementId::Node(id) => match self.get(self.db.locations, id)? {
+                Some(raw_val) => (
+                    true,
+                    Location::try_from(raw_val).ok().and_then(|l| l.version()),
+                ),
+                None => (false, None),
+            },
+            ElementId::Way(id) => match self.get(self.db.ways, id)? {
+                Some(raw_val) => (
+                    true,
+                    Way::decode(raw_val, self.decode_context("ways"))?.version(),
+                ),
+                None => (false, None),
+            },
+            ElementId::Relation(id) => match self.get(self.db.relations, id)? {
+                Some(raw_val) => (
+                    true,
+                    Relation::decode(raw_val, self.decode_context("relations"))?.version(),
+                ),
+                None => (false, None),
+            },
+        };
+        let matches = match expected {
+            Some(_) => exists && found == expected,
+            None => !exists,
+        };
+        if !matches {
+            return Err(Error::Conflict(VersionConflict {
+                element,
+                expected,
+                exists,
+                found,
+            }));
+        }
+        Ok(())
+    }
+
+    /// The tags of an element before it's replaced or deleted, if the database has a tag
+    /// index (and otherwise none)
+    fn indexed_tags(&self, id: ElementId) -> Result<Vec<(String, String)>, Error> {
+        if self.tag_index.is_none() {
+            return Ok(vec![]);
+        }
+        let tags = match id {
+            ElementId::Node(id) => match self.get(self.db.nodes, id)? {
+                Some(raw_val) => {
+                    tag_list(Node::decode(raw_val, self.decode_context("nodes"))?.tags())
+                }
+                None => vec![],
+            },
+            ElementId::Way(id) => match self.get(self.db.ways, id)? {
+                Some(raw_val) => {
+                    tag_list(Way::decode(raw_val, self.decode_context("ways"))?.tags())
+                }
+                None => vec![],
+            },
+            ElementId::Relation(id) => match self.get(self.db.relations, id)? {
+                Some(raw_val) => {
+                    tag_list(Relation::decode(raw_val, self.decode_context("relations"))?.tags())
+                }
+                None => vec![],
+            },
+        };
+        Ok(tags)
+    }
+
+    /// Replace an element's entries in the tag index (if the database has one): those of
+    /// the indexed keys of its old tags with those of its new tags
+    fn index_tags(
+        &mut self,
+        id: ElementId,
+        old_tags: &[(String, String)],
+        tags: &[(String, String)],
+    ) -> Result<(), Error> {
+        let Some(([node_table, way_table, relation_table], keys)) = &self.tag_index else {
+            return Ok(());
+        };
+        let (table, id) = match id {
+            ElementId::Node(id) => (*node_table, id),
+            ElementId::Way(id) => (*way_table, id),
+            ElementId::Relation(id) => (*relation_table, id),
+        };
+        // the hashes of each indexed key, and of each tag with one, as expand indexes them
+        let hashes = |tags: &[(String, String)]| -> Vec<u64> {
+            tags.iter()
+                .filter(|(key, _)| keys.contains(key))
+                .flat_map(|(key, value)| [tag_set_hash(&[key]), tag_set_hash(&[key, value])])
+                .collect()
+        };
+        let (old_hashes, hashes) = (hashes(old_tags), hashes(tags));
+        for &hash in old_hashes.iter().filter(|hash| !hashes.contains(hash)) {
+            self.delete_pair(table, hash, id)?;
+        }
+        for hash in hashes {
+            self.put_pair(table, hash, id)?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, table: lmdb::Database, id: u64) -> Result<Option<&[u8]>, Error> {
+        match self.txn.get(table, &encode_key(id)) {
+            Ok(raw_val) => Ok(Some(raw_val)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete_value(&mut self, table: lmdb::Database, id: u64) -> Result<(), Error> {
+        match self.txn.del(table, &encode_key(id), None) {
+            Ok(()) | Err(lmdb::Error::NotFound) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put_pair(&mut self, table: lmdb::Database, key: u64, id: u64) -> Result<(), Error> {
+        self.txn.put(
+            table,
+            &encode_key(key),
+            &encode_key(id),
+            lmdb::WriteFlags::empty(),
+        )?;
+        Ok(())
+    }
+
+    /// Delete one value of a key in an index table, if it's there. Goes through a
+    /// cursor, because mdb_del with a value can crash on DUP_FIXED tables.
+    fn delete_pair(&mut self, table: lmdb::Database, key: u64, id: u64) -> Result<(), Error> {
+        let mut cursor = self.txn.open_rw_cursor(table)?;
+        match cursor.get(
+            Some(&encode_key(key)),
+            Some(&encode_key(id)),
+            lmdb_sys::MDB_GET_BOTH,
+        ) {
+            Ok(_) => Ok(cursor.del(lmdb::WriteFlags::empty())?),
+            Err(lmdb::Error::NotFound) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The values of a key in an index table
+    fn index_values(&self, table: lmdb::Database, key: u64) -> Result<Vec<u64>, Error> {
+        let mut cursor = self.txn.open_ro_cursor(table)?;
+        let values = match cursor.iter_dup_of(&encode_key(key)) {
+            Ok(iter) => iter
+                .map(|(_, raw_val)| {
+                    decode_key(raw_val).ok_or(Error::CorruptKey {
+                        length: raw_val.len(),
+                    })
+                })
+                .collect::<Result<_, _>>()?,
+            Err(lmdb::Error::NotFound) => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        Ok(values)
+    }
+
+    /// A node's location, in units of 1e-7 degrees, if it exists
+    fn location(&self, id: u64) -> Result<Option<(i32, i32)>, Error> {
+        match self.get(self.db.locations, id)? {
+            Some(raw_val) if raw_val.len() >= 8 => Ok(Some((
+                i32::from_le_bytes(raw_val[0..4].try_into().unwrap()),
+                i32::from_le_bytes(raw_val[4..8].try_into().unwrap()),
+            ))),
+            Some(raw_val) => {
+                Err(format!("location of node {} has length {}", id, raw_val.len()).into())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Remove a node's entry in the cell_node table. Returns false if the node doesn't
+    /// exist.
+    fn remove_node_cell(&mut self, id: u64) -> Result<bool, Error> {
+        let Some((x, y)) = self.location(id)? else {
+            return Ok(false);
+        };
+        self.delete_pair(self.db.cell_node, cell_of(x, y), id)?;
+        Ok(true)
+    }
+
+    /// Whether the ways affected by changes are recomputed on commit
+    fn tracks_ways(&self) -> bool {
+        self.db.way_bbox.is_some()
+            || self.db.cell_way.is_some()
+            || self.db.geometries.is_some()
+            || self.db.street_names.is_some()
+    }
+
+    /// Whether the relations affected by changes are recomputed on commit
+    fn tracks_relations(&self) -> bool {
+        self.db.cell_relation.is_some() || self.db.geometries.is_some()
+    }
+
+    /// Record that a node is about to be written or deleted, so that the ways and
+    /// relations it's in are recomputed on commit
+    fn node_changing(&mut self, id: u64) -> Result<(), Error> {
+        if self.tracks_ways() {
+            for way_id in self.index_values(self.db.node_way, id)? {
+                self.way_changing(way_id)?;
+            }
+        }
+        if self.tracks_relations() {
+            for relation_id in self.index_values(self.db.node_relation, id)? {
+                self.relation_changing(relation_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that a way is about to be written or deleted, or that one of its nodes is,
+    /// so that it and the relations it's in are recomputed on commit. Its street name is
+    /// kept, to find the entry of the street_names table which counted it.
+    fn way_changing(&mut self, id: u64) -> Result<(), Error> {
+        if !self.tracks_ways() || self.stale.ways.contains_key(&id) {
+            return Ok(());
+        }
+        let street_name = match self.db.street_names {
+            Some(_) => self.street_name(id)?.map(|(key, _)| key),
+            None => None,
+        };
+        self.stale.ways.insert(id, street_name);
+        if self.tracks_relations() {
+            for relation_id in self.index_values(self.db.way_relation, id)? {
+                self.relation_changing(relation_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Record that a relation is about to be written or deleted, or that one of its
+    /// members is, so that it and the relations it's in are recomputed on commit. Its
+    /// bounding box is kept, to find the cells which it covered.
+    fn relation_changing(&mut self, id: u64) -> Result<(), Error> {
+        if !self.tracks_relations() || self.stale.relations.contains_key(&id) {
+            return Ok(());
+        }
+        // anything which changes the bounding box marks the relation first, so this is
+        // the one it had when the transaction began
+        let bbox = match self.db.cell_relation {
+            Some(_) => self.relation_bbox(id)?,
+            None => None,
+        };
+        self.stale.relations.insert(id, bbox);
+        for parent_id in self.index_values(self.db.relation_relation, id)? {
+            self.relation_changing(parent_id)?;
+        }
+        Ok(())
+    }
+
+    /// The bounding box of the nodes of a way which exist, or None if none do
+    fn nodes_bbox(&self, nodes: &[u64]) -> Result<Option<(i32, i32, i32, i32)>, Error> {
+        let mut bbox = None;
+        for &node_id in nodes {
+            if let Some(location) = self.location(node_id)? {
+                bbox = Some(extend_bbox(bbox, location));
+            }
+        }
+        Ok(bbox)
+    }
+
+    /// A way's bounding box in the way_bbox table, if it has one
+    fn stored_way_bbox(&self, id: u64) -> Result<Option<(i32, i32, i32, i32)>, Error> {
+        let Some(table) = self.db.way_bbox else {
+            return Ok(None);
+        };
+        match self.get(table, id)? {
+            Some(raw_val) if raw_val.len() == 16 => {
+                let fixed =
+                    |i: usize| i32::from_le_bytes(raw_val[i * 4..(i + 1) * 4].try_into().unwrap());
+                Ok(Some((fixed(0), fixed(1), fixed(2), fixed(3))))
+            }
+            Some(_) => Err("way_bbox value with incorrect length".into()),
+            None => Ok(None),
+        }
+    }
+
+    /// The bounding box of a relation's members, as expand computes it: the locations of
+    /// its nodes, the bounding boxes of its ways (in the way_bbox table), and those of the
+    /// members of its member relations, recursively
+    fn relation_bbox(&self, id: u64) -> Result<Option<(i32, i32, i32, i32)>, Error> {
+        let mut bbox = None;
+        let mut visited = HashSet::from([id]);
+        let mut queue = vec![id];
+        while let Some(relation_id) = queue.pop() {
+            let members: Vec<ElementId> = match self.get(self.db.relations, relation_id)? {
+                Some(raw_val) => Relation::decode(raw_val, self.decode_context("relations"))?
+                    .members()
+                    .map(|member| member.id())
+                    .collect(),
+                None => continue,
+            };
+            for member in members {
+                match member {
+                    ElementId::Node(node_id) => {
+                        if let Some(location) = self.location(node_id)? {
+                            bbox = Some(extend_bbox(bbox, location));
+                        }
+                    }
+                    ElementId::Way(way_id) => {
+                        if let Some((west, south, east, north)) = self.stored_way_bbox(way_id)? {
+                            bbox = Some(extend_bbox(bbox, (west, south)));
+                            bbox = Some(extend_bbox(bbox, (east, north)));
+                        }
+                    }
+                    ElementId::Relation(member_id) => {
+                        if visited.insert(member_id) {
+                            queue.push(member_id);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(bbox)
+    }
+
+    /// The locations of a way's nodes, or None if any of them don't exist
+    fn node_locations(&self, nodes: &[u64]) -> Result<Option<Vec<(i32, i32)>>, Error> {
+        let mut locations = Vec::with_capacity(nodes.len());
+        for &node_id in nodes {
+            match self.location(node_id)? {
+                Some(location) => locations.push(location),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(locations))
+    }
+
+    /// The rings of a multipolygon or boundary relation, assembled from the geometries of
+    /// its outer and inner ways (in the geometries table) as expand assembles them.
+    /// Returns None if it's some other kind of relation, or if its rings aren't complete.
+    fn relation_rings(
+        &self,
+        table: lmdb::Database,
+        id: u64,
+    ) -> Result<Option<FixedGeometry>, Error> {
+        let way_ids: Vec<u64> = match self.get(self.db.relations, id)? {
+            Some(raw_val) => {
+                let relation = Relation::decode(raw_val, self.decode_context("relations"))?;
+                if !matches!(relation.tag("type"), Some("multipolygon" | "boundary")) {
+                    return Ok(None);
+                }
+                relation
+                    .members()
+                    .filter_map(|member| match (member.id(), member.role()) {
+                        (ElementId::Way(way_id), "outer" | "inner" | "") => Some(way_id),
+                        _ => None,
+                    })
+                    .collect()
+            }
+            None => return Ok(None),
+        };
+        if way_ids.is_empty() {
+            return Ok(None);
+        }
+        let mut member_ways = Vec::with_capacity(way_ids.len());
+        for way_id in way_ids {
+            let raw_val = match self
+                .txn
+                .get(table, &encode_element_ref(&ElementId::Way(way_id)))
+            {
+                Ok(raw_val) => raw_val,
+                Err(lmdb::Error::NotFound) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            let mut parts = decode_geometry(raw_val)
+                .ok_or_else(|| format!("geometry of way {} can't be decoded", way_id))?;
+            match parts.pop() {
+                Some(part) => member_ways.push(part),
+                None => return Ok(None),
+            }
+        }
+        Ok(assemble_rings(member_ways))
+    }
+
+    /// Replace an element's value in the geometries table, or delete it if the element
+    /// has no geometry
+    fn put_geometry(
+        &mut self,
+        table: lmdb::Database,
+        id: ElementId,
+        parts: Option<FixedGeometry>,
+    ) -> Result<(), Error> {
+        let key = encode_element_ref(&id);
+        match parts {
+            Some(parts) => {
+                let value = encode_geometry(&parts);
+                self.txn
+                    .put(table, &key, &value, lmdb::WriteFlags::empty())?;
+            }
+            None => match self.txn.del(table, &key, None) {
+                Ok(()) | Err(lmdb::Error::NotFound) => (),
+                Err(e) => return Err(e.into()),
+            },
+        }
+        Ok(())
+    }
+
+    /// The key of a way's entry in the street_names table, as expand counts it (under its
+    /// name and the cell of its middle node), and the location of the middle node. Returns
+    /// None if it isn't a named highway, or if its middle node doesn't exist.
+    fn street_name(&self, id: u64) -> Result<Option<StreetName>, Error> {
+        let (name, node_id) = match self.get(self.db.ways, id)? {
+            Some(raw_val) => {
+                let way = Way::decode(raw_val, self.decode_context("ways"))?;
+                let (Some(_), Some(name)) = (way.tag("highway"), way.tag("name")) else {
+                    return Ok(None);
+                };
+                let nodes: Vec<u64> = way.nodes().collect();
+                let Some(&node_id) = nodes.get(nodes.len() / 2) else {
+                    return Ok(None);
+                };
+                (name.to_string(), node_id)
+            }
+            None => return Ok(None),
+        };
+        let Some((x, y)) = self.location(node_id)? else {
+            return Ok(None);
+        };
+        let latlng = s2::latlng::LatLng::from_degrees(y as f64 / 1e7, x as f64 / 1e7);
+        let cell = s2::cellid::CellID::from(latlng).parent(STREET_NAME_CELL_LEVEL);
+        Ok(encode_street_name_key(&name, cell.0).map(|key| (key, (x, y))))
+    }
+
+    /// Move a way from one entry of the street_names table to another: decrement the
+    /// count of its old entry (deleting it when no ways are left), and increment that of
+    /// its new one (which is added, at the way's location, if it's the first way with the
+    /// name in the cell). An entry's location is kept while it has ways, even if the way
+    /// it was taken from has moved or been renamed.
+    fn move_street_name(
+        &mut self,
+        table: lmdb::Database,
+        old_key: Option<Vec<u8>>,
+        street_name: Option<StreetName>,
+    ) -> Result<(), Error> {
+        if old_key.as_ref() == street_name.as_ref().map(|(key, _)| key) {
+            return Ok(());
+        }
+        let flags = lmdb::WriteFlags::empty();
+        if let Some(key) = old_key {
+            match self.street_name_value(table, &key)? {
+                Some((lon, lat, count)) if count > 1 => {
+                    let value = encode_street_name_value(lon, lat, count - 1);
+                    self.txn.put(table, &key, &value, flags)?;
+                }
+                Some(_) => self.txn.del(table, &key, None)?,
+                None => (),
+            }
+        }
+        if let Some((key, (x, y))) = street_name {
+            let value = match self.street_name_value(table, &key)? {
+                Some((lon, lat, count)) => {
+                    encode_street_name_value(lon, lat, count.saturating_add(1))
+                }
+                None => encode_street_name_value(x, y, 1),
+            };
+            self.txn.put(table, &key, &value, flags)?;
+        }
+        Ok(())
+    }
+
+    /// The location and way count of an entry of the street_names table, if it exists
+    fn street_name_value(
+        &self,
+        table: lmdb::Database,
+        key: &[u8],
+    ) -> Result<Option<(i32, i32, u32)>, Error> {
+        match self.txn.get(table, &key) {
+            Ok(raw_val) => Ok(Some(
+                decode_street_name_value(raw_val)
+                    .ok_or("street_names value with incorrect length")?,
+            )),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Recompute the bounding boxes, geometries and street names of the ways and
+    /// relations which changed, or whose nodes or members did, and the cells covering
+    /// them in the cell_way and cell_relation tables
+    fn refresh_stale(&mut self) -> Result<(), Error> {
+        let stale = std::mem::take(&mut self.stale);
+        // the bounding boxes and geometries of relations are made from those of their
+        // ways, so ways come first
+        for (way_id, old_street_name) in stale.ways {
+            let old_bbox = self.stored_way_bbox(way_id)?;
+            let nodes: Option<Vec<u64>> = match self.get(self.db.ways, way_id)? {
+                Some(raw_val) => Some(
+                    Way::decode(raw_val, self.decode_context("ways"))?
+                        .nodes()
+                        .collect(),
+                ),
+                None => None,
+            };
+            let bbox = self.nodes_bbox(nodes.as_deref().unwrap_or_default())?;
+            if let Some(table) = self.db.way_bbox {
+                match bbox {
+                    Some((west, south, east, north)) => {
+                        let raw_val: Vec<u8> = [west, south, east, north]
+                            .iter()
+                            .flat_map(|v| v.to_le_bytes())
+                            .collect();
+                        self.txn.put(
+                            table,
+                            &encode_key(way_id),
+                            &raw_val,
+                            lmdb::WriteFlags::empty(),
+                        )?;
+                    }
+                    None => self.delete_value(table, way_id)?,
+                }
+            }
+            if let Some(table) = self.db.cell_way {
+                self.replace_cells(table, way_id, old_bbox, bbox)?;
+            }
+            if let Some(table) = self.db.geometries {
+                let coordinates = match &nodes {
+                    Some(nodes) => self.node_locations(nodes)?,
+                    None => None,
+                };
+                let parts = coordinates.map(|coordinates| vec![coordinates]);
+                self.put_geometry(table, ElementId::Way(way_id), parts)?;
+            }
+            if let Some(table) = self.db.street_names {
+                let street_name = self.street_name(way_id)?;
+                self.move_street_name(table, old_street_name, street_name)?;
+            }
+        }
+        for (relation_id, old_bbox) in stale.relations {
+            if let Some(table) = self.db.cell_relation {
+                let bbox = self.relation_bbox(relation_id)?;
+                self.replace_cells(table, relation_id, old_bbox, bbox)?;
+            }
+            if let Some(table) = self.db.geometries {
+                let rings = self.relation_rings(table, relation_id)?;
+                self.put_geometry(table, ElementId::Relation(relation_id), rings)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the entries of a way or relation in the cell_way or cell_relation table:
+    /// the cells covering its old bounding box with those covering its new one
+    fn replace_cells(
+        &mut self,
+        table: lmdb::Database,
+        id: u64,
+        old_bbox: Option<(i32, i32, i32, i32)>,
+        bbox: Option<(i32, i32, i32, i32)>,
+    ) -> Result<(), Error> {
+        if old_bbox == bbox {
+            return Ok(());
+        }
+        let cells = bbox.map(covering_cells).unwrap_or_default();
+        for cell in old_bbox.map(covering_cells).unwrap_or_default() {
+            if !cells.contains(&cell) {
+                self.delete_pair(table, cell, id)?;
+            }
+        }
+        for cell in cells {
+            self.put_pair(table, cell, id)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a way's entries in the node_way table. Returns false if the way doesn't
+    /// exist.
+    fn remove_way_parents(&mut self, id: u64) -> Result<bool, Error> {
+        let nodes: Vec<u64> = match self.get(self.db.ways, id)? {
+            Some(raw_val) => {
+                let way = Way::decode(raw_val, self.decode_context("ways"))?;
+                way.nodes().collect()
+            }
+            None => return Ok(false),
+        };
+        for node_id in nodes {
+            self.delete_pair(self.db.node_way, node_id, id)?;
+        }
+        Ok(true)
+    }
+
+    /// Remove a relation's entries in the join tables of its members. Returns false if
+    /// the relation doesn't exist.
+    fn remove_relation_parents(&mut self, id: u64) -> Result<bool, Error> {
+        let members: Vec<ElementId> = match self.get(self.db.relations, id)? {
+            Some(raw_val) => {
+                let relation = Relation::decode(raw_val, self.decode_context("relations"))?;
+                relation.members().map(|member| member.id()).collect()
+            }
+            None => return Ok(false),
+        };
+        for member in members {
+            let (table, member_id) = self.member_table(member);
+            self.delete_pair(table, member_id, id)?;
+        }
+        Ok(true)
+    }
+
+    /// The join table which maps a member to the relations it's in, and its ID
+    fn member_table(&self, member: ElementId) -> (lmdb::Database, u64) {
+        match member {
+            ElementId::Node(id) => (self.db.node_relation, id),
+            ElementId::Way(id) => (self.db.way_relation, id),
+            ElementId::Relation(id) => (self.db.relation_relation, id),
+        }
+    }
+
+    /// Count an element which was written, given its index in the ID bitmaps
+    fn added(&mut self, kind: usize, id: u64, existed: bool) {
+        self.changes += 1;
+        if let Some(bitmap) = self.id_bitmaps[kind].as_mut() {
+            bitmap.insert(id);
+        }
+        if let (Some(counts), false) = (self.counts.as_mut(), existed) {
+            *count_of(counts, kind) += 1;
+        }
+    }
+
+    /// Count an element which was deleted, given its index in the ID bitmaps
+    fn removed(&mut self, kind: usize, id: u64, existed: bool) {
+        if existed {
+            self.changes += 1;
+        }
+        if let Some(bitmap) = self.id_bitmaps[kind].as_mut() {
+            bitmap.remove(id);
+        }
+        if let (Some(counts), true) = (self.counts.as_mut(), existed) {
+            *count_of(counts, kind) -= 1;
+        }
+    }
+}
+
+/// The count of nodes, ways or relations, by index in the ID bitmaps
+#[cfg(feature = "update")]
+fn count_of(counts: &mut ElementCounts, kind: usize) -> &mut u64 {
+    match kind {
+        0 => &mut counts.nodes,
+        1 => &mut counts.ways,
+        _ => &mut counts.relations,
+    }
+}
+
+/// Copy an element's tags, for the index plugins
+#[cfg(feature = "update")]
+fn tag_list<'a>(tags: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<(String, String)> {
+    tags.map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+/// The ways and relations whose bounding boxes, cells, geometries and street names a
+/// WriteTransaction recomputes on commit, since they changed or their nodes or members did
+#[cfg(feature = "update")]
+#[derive(Default)]
+struct StaleElements {
+    // with the key of the street_names entry each was counted in when the transaction
+    // began
+    ways: HashMap<u64, Option<Vec<u8>>>,
+    // with the bounding box each had when the transaction began
+    relations: HashMap<u64, Option<(i32, i32, i32, i32)>>,
+}
+
+/// The parts of a geometry in the geometries table, in units of 1e-7 degrees
+#[cfg(feature = "update")]
+type FixedGeometry = Vec<Vec<(i32, i32)>>;
+
+/// The key of a way's entry in the street_names table, and the location of its middle
+/// node in units of 1e-7 degrees
+#[cfg(feature = "update")]
+type StreetName = (Vec<u8>, (i32, i32));
+
+/// Grow a bounding box (in units of 1e-7 degrees) to include a location
+#[cfg(feature = "update")]
+fn extend_bbox(bbox: Option<(i32, i32, i32, i32)>, (x, y): (i32, i32)) -> (i32, i32, i32, i32) {
+    let (west, south, east, north) = bbox.unwrap_or((x, y, x, y));
+    (west.min(x), south.min(y), east.max(x), north.max(y))
+}
+
+/// The cells covering a bounding box (in units of 1e-7 degrees) in the cell_way and
+/// cell_relation tables, as expand indexes them
+#[cfg(feature = "update")]
+fn covering_cells((west, south, east, north): (i32, i32, i32, i32)) -> Vec<u64> {
+    let region = Region::from_bbox(
+        west as f64 / 1e7,
+        south as f64 / 1e7,
+        east as f64 / 1e7,
+        north as f64 / 1e7,
+    );
+    region.cell_ids().collect()
+}
+
+/// The cell_node index cell containing a location, in units of 1e-7 degrees
+#[cfg(feature = "update")]
+fn cell_of(x: i32, y: i32) -> u64 {
+    let latlng = s2::latlng::LatLng::from_degrees(y as f64 / 1e7, x as f64 / 1e7);
+    s2::cellid::CellID::from(latlng).parent(CELL_INDEX_LEVEL).0
+}
+
+/// A table that stores data associated with OSM elements, keyed by the element's ID.
+/// The value type depends on what element is being stored. In an OSMX database, the
+/// values are usually Cap'n Proto messages describing the element's properties.
+pub struct ElementTable<'txn, E: Decode<'txn> + 'txn> {
+    txn: &'txn lmdb::RoTransaction<'txn>,
+    table: lmdb::Database,
+    history: Option<lmdb::Database>,
+    context: DecodeContext<'txn>,
+    // counts the values skipped because they can't be decoded, in DecodeMode::Lenient
+    skipped: Option<&'txn AtomicU64>,
+    prefetchers: &'txn Arc<Prefetchers>,
+    phantom: PhantomData<E>,
+}
+
+impl<'txn, E: Decode<'txn>> ElementTable<'txn, E> {
+    fn new(
+        txn: &'txn lmdb::RoTransaction<'txn>,
+        table: lmdb::Database,
+        history: Option<lmdb::Database>,
+        context: DecodeContext<'txn>,
+        skipped: Option<&'txn AtomicU64>,
+        prefetchers: &'txn Arc<Prefetchers>,
+    ) -> Self {
+        Self {
+            txn,
+            table,
+            history,
+            context,
+            skipped,
+            prefetchers,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Get an element by its ID. Returns Ok(None) if the element is not found, or
+    /// an error if the element's value cannot be decoded (for example because it
+    /// exceeds the traversal limit in the Transaction's reader options), unless the
+    /// Database's [DecodeMode] is lenient.
+    pub fn get(&self, id: u64) -> Result<Option<E>, Error> {
+        match self.txn.get(self.table, &encode_key(id)) {
+            Ok(raw_val) => Ok(skip_failed(E::decode(raw_val, self.context), self.skipped)?),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Get an element by its ID, like [Self::get].
+    #[deprecated(note = "use get, which now returns a Result")]
+    pub fn try_get(&self, id: u64) -> Result<Option<E>, Error> {
+        self.get(id)
+    }
+
+    /// Get several elements by their IDs, returning them in the same order as the IDs
+    /// (with None for elements which aren't found). This is faster than calling
+    /// [Self::get] for each ID, since the IDs are looked up in sorted order using a
+    /// single cursor. Returns an error if an element's value cannot be decoded (unless
+    /// the [DecodeMode] is lenient).
+    pub fn get_many(&self, ids: &[u64]) -> Result<Vec<Option<E>>, Error> {
+        let cursor = self.txn.open_ro_cursor(self.table)?;
+        let mut order: Vec<usize> = (0..ids.len()).collect();
+        order.sort_unstable_by_key(|&i| ids[i]);
+
+        let mut elements: Vec<Option<E>> = (0..ids.len()).map(|_| None).collect();
+        for i in order {
+            match cursor.get(Some(&encode_key(ids[i])), None, lmdb_sys::MDB_SET_KEY) {
+                Ok((_, raw_val)) => {
+                    elements[i] = skip_failed(E::decode(raw_val, self.context), self.skipped)?
+                }
+                Err(lmdb::Error::NotFound) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(elements)
+    }
+
+    /// Check whether an element with the given ID is in the table, without decoding it.
+    pub fn contains(&self, id: u64) -> Result<bool, Error> {
+        match self.txn.get(self.table, &encode_key(id)) {
+            Ok(_) => Ok(true),
+            Err(lmdb::Error::NotFound) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The number of elements in the table (from LMDB's statistics, so without reading
+    /// them).
+    pub fn len(&self) -> u64 {
+        let mut stat = lmdb_sys::MDB_stat {
+            ms_psize: 0,
+            ms_depth: 0,
+            ms_branch_pages: 0,
+            ms_leaf_pages: 0,
+            ms_overflow_pages: 0,
+            ms_entries: 0,
+        };
+        unsafe {
+            lmdb_sys::mdb_stat(self.txn.txn(), self.table.dbi(), &mut stat);
+        }
+        stat.ms_entries as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get a specific version of an element from the history table. Returns Ok(None) if
+    /// that version is not found (or was a deletion, for the Locations table), or the
+    /// database doesn't store history.
+    pub fn get_version(&self, id: u64, version: u32) -> Result<Option<E>, Error> {
+        let history = match self.history {
+            Some(history) => history,
+            None => return Ok(None),
+        };
+        match self.txn.get(history, &encode_version_key(id, version)) {
+            Ok(raw_val) => Ok(skip_failed(E::decode(raw_val, self.context), self.skipped)?),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Iterate over all the stored versions of an element, in version order. Yields
+    /// nothing if the database doesn't store history. For the Nodes, Ways and Relations
+    /// tables, this includes versions which deleted the element (see [crate::Metadata::deleted]).
+    pub fn versions(&self, id: u64) -> impl Iterator<Item = (u32, E)> + 'txn {
+        let entries = self.history.map(|history| {
+            let cursor = self.txn.open_ro_cursor(history).unwrap();
+            CursorEntries::new(cursor, |cursor| {
+                entries_from(cursor, &encode_version_key(id, 0))
+            })
+        });
+        let (context, skipped) = (self.context, self.skipped);
+        entries
+            .into_iter()
+            .flatten()
+            .map(|(raw_key, raw_val)| {
+                let key = decode_version_key(raw_key).expect("key with incorrect length");
+                (key, raw_val)
+            })
+            .take_while(move |&((key_id, _), _)| key_id == id)
+            .filter_map(move |((_, version), raw_val)| {
+                let elem = skip_failed(E::decode(raw_val, context), skipped).unwrap()?;
+                Some((version, elem))
+            })
+    }
+
+    /// Iterate over the IDs of all the elements in the table, without decoding their values.
+    pub fn ids(&self) -> impl Iterator<Item = u64> + 'txn {
+        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        let mut scan = Scan::new("id scan", trace::type_name::<E>());
+        CursorEntries::new(cursor, |cursor| Some(cursor.iter()))
+            .map(|(raw_key, _)| decode_key(raw_key).expect("key with incorrect length"))
+            .inspect(move |_| scan.yielded())
+    }
+
+    /// Returns the smallest ID in the table which is greater than or equal to the given ID,
+    /// without decoding the element, or None if there isn't one.
+    pub fn next_id_from(&self, id: u64) -> Option<u64> {
+        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        match cursor.get(Some(&encode_key(id)), None, lmdb_sys::MDB_SET_RANGE) {
+            Ok((raw_key, _)) => {
+                Some(decode_key(raw_key.unwrap()).expect("key with incorrect length"))
+            }
+            Err(lmdb::Error::NotFound) => None,
+            Err(e) => unreachable!("Unexpected LMDB error: {:?}", e),
+        }
+    }
+
+    /// Returns the smallest ID in the table, or None if the table is empty.
+    pub fn first_id(&self) -> Option<u64> {
+        end_entry(self.txn, self.table, lmdb_sys::MDB_FIRST)
+            .map(|(raw_key, _)| decode_key(raw_key).expect("key with incorrect length"))
+    }
+
+    /// Returns the largest ID in the table, or None if the table is empty. This reads a
+    /// single key, so it's a cheap way to bound the IDs in the table (for example to
+    /// split it into ranges, or to estimate progress through a scan).
+    pub fn last_id(&self) -> Option<u64> {
+        end_entry(self.txn, self.table, lmdb_sys::MDB_LAST)
+            .map(|(raw_key, _)| decode_key(raw_key).expect("key with incorrect length"))
+    }
+
+    /// Pick up to `count` distinct IDs from the table, in ascending order, by seeking to
+    /// evenly spaced IDs between the first and last ID rather than scanning the table.
+    /// Where IDs are unevenly spread, the elements after large gaps are more likely to
+    /// be picked.
+    pub fn sample_ids(&self, count: u64) -> Vec<u64> {
+        if count >= self.len() {
+            return self.ids().collect();
+        }
+        let (Some(first), Some(last)) = (self.first_id(), self.last_id()) else {
+            return vec![];
+        };
+        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        let span = (last - first) as u128 + 1;
+        let mut ids: Vec<u64> = Vec::with_capacity(count.min(1 << 20) as usize);
+        for i in 0..count {
+            let offset = span * (2 * i as u128 + 1) / (2 * count as u128);
+            let mut target = first + offset as u64;
+            // seeking to an ID already picked would pick it again
+            if let Some(&previous) = ids.last() {
+                match previous.checked_add(1) {
+                    Some(next) => target = target.max(next),
+                    None => break,
+                }
+            }
+            match cursor.get(Some(&encode_key(target)), None, lmdb_sys::MDB_SET_RANGE) {
+                Ok((raw_key, _)) => {
+                    ids.push(decode_key(raw_key.unwrap()).expect("key with incorrect length"))
+                }
+                Err(lmdb::Error::NotFound) => break,
+                Err(e) => unreachable!("Unexpected LMDB error: {:?}", e),
+            }
+        }
+        ids
+    }
+
+    /// Get the element with the smallest ID in the table, or None if the table is empty.
+    /// Panics if the element's value cannot be decoded (unless the [DecodeMode] is
+    /// lenient, in which case it returns None).
+    pub fn first(&self) -> Option<(u64, E)> {
+        let (raw_key, raw_val) = end_entry(self.txn, self.table, lmdb_sys::MDB_FIRST)?;
+        decode_entry(raw_key, raw_val, self.context, self.skipped).unwrap()
+    }
+
+    /// Get the element with the largest ID in the table, or None if the table is empty.
+    /// Panics if the element's value cannot be decoded (unless the [DecodeMode] is
+    /// lenient, in which case it returns None).
+    pub fn last(&self) -> Option<(u64, E)> {
+        let (raw_key, raw_val) = end_entry(self.txn, self.table, lmdb_sys::MDB_LAST)?;
+        decode_entry(raw_key, raw_val, self.context, self.skipped).unwrap()
+    }
+
+    /// Build a bitmap of the IDs of all the elements in the table. This scans the whole
+    /// table, so prefer loading a stored bitmap (e.g. [Transaction::node_id_bitmap])
+    /// when the database has one.
+    pub fn id_bitmap(&self) -> RoaringTreemap {
+        RoaringTreemap::from_sorted_iter(self.ids()).expect("table keys out of order")
+    }
+
+    /// Iterate over all the elements in the table.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, E)> + 'txn {
+        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        let (context, skipped) = (self.context, self.skipped);
+        let mut scan = Scan::new("table scan", trace::type_name::<E>());
+        CursorEntries::new(cursor, |cursor| Some(cursor.iter()))
+            .filter_map(move |(raw_key, raw_val)| {
+                let id = decode_key(raw_key).expect("key with incorrect length");
+                let elem = skip_failed(E::decode(raw_val, context), skipped).unwrap()?;
+                Some((id, elem))
+            })
+            .inspect(move |_| scan.yielded())
+    }
+
+    /// Iterate over the elements with IDs at or after `start_id`, in ID order. Like
+    /// [Self::range], this seeks to the start rather than scanning from the beginning of
+    /// the table.
+    pub fn iter_from(&self, start_id: u64) -> impl Iterator<Item = (u64, E)> + 'txn {
+        self.range(start_id..)
+    }
+
+    /// Iterate over the elements with IDs in a range (such as `1000..2000`), in ID order.
+    /// The cursor seeks to the start of the range, so only the elements in it are read.
+    /// Splitting the IDs from [Self::first_id] to [Self::last_id] into ranges lets
+    /// several threads, each with a Transaction of its own, scan a table in parallel.
+    pub fn range(&self, ids: impl RangeBounds<u64>) -> impl Iterator<Item = (u64, E)> + 'txn {
+        let start = match ids.start_bound() {
+            Bound::Included(&id) => Some(id),
+            Bound::Excluded(&id) => id.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+        let end = ids.end_bound().cloned();
+        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        let (context, skipped) = (self.context, self.skipped);
+        let mut scan = Scan::new("table scan", trace::type_name::<E>());
+        CursorEntries::new(cursor, |cursor| entries_from(cursor, &encode_key(start?)))
+            .map(|(raw_key, raw_val)| {
+                let id = decode_key(raw_key).expect("key with incorrect length");
+                (id, raw_val)
+            })
+            .take_while(move |&(id, _)| match end {
+                Bound::Included(end) => id <= end,
+                Bound::Excluded(end) => id < end,
+                Bound::Unbounded => true,
+            })
+            .filter_map(move |(id, raw_val)| {
+                let elem = skip_failed(E::decode(raw_val, context), skipped).unwrap()?;
+                Some((id, elem))
+            })
+            .inspect(move |_| scan.yielded())
+    }
+
+    /// Iterate over all the elements in the table, like [Self::iter], but yield an error
+    /// (and then stop) if reading from the table fails or a key or value cannot be
+    /// decoded, instead of stopping silently or panicking. Use this when a truncated
+    /// scan would go unnoticed.
+    pub fn try_iter(&self) -> impl Iterator<Item = Result<(u64, E), Error>> + 'txn {
+        let cursor = self.txn.open_ro_cursor(self.table);
+        let (context, skipped) = (self.context, self.skipped);
+        let mut scan = Scan::new("table scan", trace::type_name::<E>());
+        let mut failed = false;
+        std::iter::from_fn(move || {
+            if failed {
+                return None;
+            }
+            let entry = match &cursor {
+                Ok(cursor) => loop {
+                    match cursor.get(None, None, lmdb_sys::MDB_NEXT) {
+                        Ok((raw_key, raw_val)) => {
+                            let raw_key = raw_key.unwrap_or_default();
+                            match decode_entry(raw_key, raw_val, context, skipped) {
+                                Ok(Some(entry)) => break Ok(entry),
+                                Ok(None) => continue,
+                                Err(e) => break Err(e),
+                            }
+                        }
+                        Err(lmdb::Error::NotFound) => return None,
+                        Err(e) => break Err(e.into()),
+                    }
+                },
+                Err(e) => Err((*e).into()),
+            };
+            failed = entry.is_err();
+
+            scan.yielded();
+            Some(entry)
+        })
+    }
+
+    /// Get a page of at most `limit` elements, in ID order, starting after the element
+    /// with ID `after_id` (or at the start of the table if it's None). The page's
+    /// [Page::next] token is passed as `after_id` to get the following page, which
+    /// continues from the same place even if elements have been added or removed in
+    /// between. Returns an error if the table can't be read or a value can't be decoded.
+    pub fn iter_page(&self, after_id: Option<u64>, limit: usize) -> Result<Page<E>, Error> {
+        if limit == 0 {
+            return Err("page limit must be at least 1".into());
+        }
+        let cursor = self.txn.open_ro_cursor(self.table)?;
+        let mut entry = match after_id.map(|id| id.checked_add(1)) {
+            None => cursor.get(None, None, lmdb_sys::MDB_FIRST),
+            Some(Some(start)) => {
+                cursor.get(Some(&encode_key(start)), None, lmdb_sys::MDB_SET_RANGE)
+            }
+            Some(None) => Err(lmdb::Error::NotFound),
+        };
+        let mut elements = Vec::with_capacity(limit.min(1024));
+        loop {
+            let (raw_key, raw_val) = match entry {
+                Ok(entry) => entry,
+                Err(lmdb::Error::NotFound) => {
+                    return Ok(Page {
+                        elements,
+                        next: None,
+                    })
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if elements.len() == limit {
+                // there are more elements after this page
+                let next = elements.last().map(|(id, _)| *id);
+                return Ok(Page { elements, next });
+            }
+            let raw_key = raw_key.unwrap_or_default();
+            elements.extend(decode_entry(raw_key, raw_val, self.context, self.skipped)?);
+            entry = cursor.get(None, None, lmdb_sys::MDB_NEXT);
+        }
+    }
 }
 
-impl Database {
-    /// Open the given file path as an OSMX Database
-    pub fn open(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
-        let env = lmdb::Environment::new()
-            .set_flags(
-                lmdb::EnvironmentFlags::NO_SUB_DIR
-                    | lmdb::EnvironmentFlags::NO_READAHEAD
-                    | lmdb::EnvironmentFlags::NO_SYNC,
-            )
-            .set_max_dbs(10)
-            .set_map_size(50 * 1024 * 1024 * 1024) // 50 GiB
-            .open(path.as_ref())?;
+/// A page of the elements of a table, returned by `ElementTable::iter_page`
+pub struct Page<E> {
+    /// The elements of the page, as (ID, element) pairs in ID order
+    pub elements: Vec<(u64, E)>,
+    /// The continuation token: the `after_id` to pass to `ElementTable::iter_page` to get
+    /// the next page, or None if this is the last page.
+    pub next: Option<u64>,
+}
 
-        let locations = env.open_db(Some("locations"))?;
-        let nodes = env.open_db(Some("nodes"))?;
-        let ways = env.open_db(Some("ways"))?;
-        let relations = env.open_db(Some("relations"))?;
-        let cell_node = env.open_db(Some("cell_node"))?;
-        let node_way = env.open_db(Some("node_way"))?;
-        let node_relation = env.open_db(Some("node_relation"))?;
-        let way_relation = env.open_db(Some("way_relation"))?;
-        let relation_relation = env.open_db(Some("relation_relation"))?;
+impl<'txn, E: Decode<'txn> + Reborrow> ElementTable<'txn, E> {
+    /// Call a function with each element in the table, in ID order. This is faster than
+    /// [Self::iter] for full scans of large tables: each value is decoded into the same
+    /// reusable buffer (if it has to be decompressed or unpacked at all), so the element
+    /// passed to the function is only borrowed for the duration of the call. Returns an
+    /// error if the table can't be read or a value can't be decoded.
+    pub fn for_each_ref<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: for<'b> FnMut(u64, &E::At<'b>),
+    {
+        let cursor = self.txn.open_ro_cursor(self.table)?;
+        let mut buffer = DecodeBuffer::default();
+        let mut scan = Scan::new("table scan", trace::type_name::<E>());
+        loop {
+            let (raw_key, raw_val) = match cursor.get(None, None, lmdb_sys::MDB_NEXT) {
+                Ok(entry) => entry,
+                Err(lmdb::Error::NotFound) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let id = decode_key(raw_key.unwrap_or_default()).ok_or("key with incorrect length")?;
+            let decoded = E::At::decode_in(raw_val, self.context, &mut buffer);
+            let Some(elem) = skip_failed(decoded, self.skipped)? else {
+                continue;
+            };
 
-        Ok(Self {
-            env,
-            locations,
-            nodes,
-            ways,
-            relations,
-            cell_node,
-            node_way,
-            node_relation,
-            way_relation,
-            relation_relation,
+            scan.yielded();
+            f(id, &elem);
+        }
+        Ok(())
+    }
+
+    /// Iterate over all the elements in the table in batches of up to `batch_size`
+    /// elements, which a helper thread reads ahead of the consumer. The helper walks the
+    /// table with a cursor of this transaction, copying raw values into batches while
+    /// the consumer decodes earlier ones, so on a cold page cache most of the time spent
+    /// waiting for pages to be read from disk overlaps with decoding. Returns an error if
+    /// the helper can't be started.
+    pub fn iter_prefetch(&self, batch_size: usize) -> Result<Prefetch<'txn, E>, Error> {
+        let cursor = PrefetchCursor::open(self.txn, self.table)?;
+        let batch_size = batch_size.max(1);
+        let (sender, receiver) = mpsc::sync_channel(PREFETCH_BATCHES_AHEAD);
+        let prefetchers = Arc::clone(self.prefetchers);
+        let running = prefetchers.started();
+        let helper = thread::Builder::new()
+            .name("osmx prefetch".to_string())
+            .spawn(move || {
+                let _running = running;
+                if let Err(e) = cursor.read_batches(batch_size, &sender, &prefetchers) {
+                    send_batch(&sender, Err(e.to_string()), &prefetchers);
+                }
+            })?;
+        Ok(Prefetch {
+            receiver: Some(receiver),
+            helper: Some(helper),
+            context: self.context,
+            skipped: self.skipped,
+            scan: Scan::new("prefetched table scan", trace::type_name::<E>()),
+            phantom: PhantomData,
         })
     }
 }
 
-/// A handle which can be used to read from the Database. The handle
-/// ensures that all reads see the same snapshot of the data, even if
-/// it is being modified simultaneously by another process.
-pub struct Transaction<'db> {
-    db: &'db Database,
-    txn: lmdb::RoTransaction<'db>, // TODO support write txns?
+/// Number of batches the helper thread of [ElementTable::iter_prefetch] can read ahead
+/// of the consumer
+const PREFETCH_BATCHES_AHEAD: usize = 4;
+
+/// The IDs and raw values of consecutive elements of a table, or the error which
+/// stopped the helper thread reading them
+type RawBatch = Result<Vec<(u64, Vec<u8>)>, String>;
+
+/// The helper threads of [ElementTable::iter_prefetch], which read with the LMDB
+/// transaction of a [Transaction] and so must stop before it ends. A [Prefetch] stops its
+/// helper when dropped, but it may be leaked, so the Transaction waits for them too.
+#[derive(Default)]
+struct Prefetchers {
+    stopping: AtomicBool,
+    running: Mutex<usize>,
+    exited: Condvar,
 }
 
-impl<'db> Transaction<'db> {
-    /// Create a new Transaction from the given Database.
-    pub fn begin(db: &'db Database) -> Result<Self, Box<dyn Error>> {
-        let txn = db.env.begin_ro_txn()?;
-        Ok(Self { db, txn })
+impl Prefetchers {
+    /// Count a helper as running until the returned guard is dropped
+    fn started(self: &Arc<Self>) -> PrefetcherRunning {
+        *self.running.lock().unwrap() += 1;
+        PrefetcherRunning(Arc::clone(self))
     }
 
-    /// Get the Locations table, which maps OSM Node IDs to locations.
-    pub fn locations(&self) -> Result<Locations, Box<dyn Error>> {
-        Ok(Locations::new(&self.txn, self.db.locations))
+    fn stopping(&self) -> bool {
+        self.stopping.load(Ordering::Relaxed)
     }
 
-    /// Get the Nodes table, which maps OSM Node IDs to their metadata and tags.
-    pub fn nodes(&self) -> Result<Nodes, Box<dyn Error>> {
-        Ok(Nodes::new(&self.txn, self.db.nodes))
+    /// Tell the helpers to stop, and wait for them to exit
+    fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        let mut running = self.running.lock().unwrap();
+        while *running > 0 {
+            running = self.exited.wait(running).unwrap();
+        }
     }
+}
 
-    /// Get the Ways table, which maps OSM Way IDs to their metadata, tags, and node refs.
-    pub fn ways(&self) -> Result<Ways, Box<dyn Error>> {
-        Ok(Ways::new(&self.txn, self.db.ways))
+struct PrefetcherRunning(Arc<Prefetchers>);
+
+impl Drop for PrefetcherRunning {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap() -= 1;
+        self.0.exited.notify_all();
     }
+}
 
-    /// Get the Relations table, which maps OSM Relation IDs to their metadata, tags, and member refs.
-    pub fn relations(&self) -> Result<Relations, Box<dyn Error>> {
-        Ok(Relations::new(&self.txn, self.db.relations))
+/// A cursor opened with the LMDB C API on a read-only transaction, which can be moved to
+/// a helper thread. LMDB allows a read-only transaction to be used from other threads
+/// because databases are opened with NO_TLS; the cursor is opened on the caller's thread,
+/// which refreshes the table's record in the transaction, so the helper only reads from
+/// it.
+struct PrefetchCursor(*mut lmdb_sys::MDB_cursor);
+
+unsafe impl Send for PrefetchCursor {}
+
+impl PrefetchCursor {
+    fn open(txn: &lmdb::RoTransaction, table: lmdb::Database) -> Result<Self, Error> {
+        let mut cursor = ptr::null_mut();
+        lmdb_result(unsafe { lmdb_sys::mdb_cursor_open(txn.txn(), table.dbi(), &mut cursor) })?;
+        Ok(Self(cursor))
     }
 
-    /// Get the cell_nodes spatial index table which maps S2 Cell IDs to OSM Node IDs.
-    pub fn cell_nodes(&self) -> Result<SpatialIndexTable, Box<dyn Error>> {
-        Ok(SpatialIndexTable::new(&self.txn, self.db.cell_node))
+    /// Send the entries of the table to `sender` in batches, until the table ends, the
+    /// receiver hangs up or the transaction is ending.
+    fn read_batches(
+        &self,
+        batch_size: usize,
+        sender: &mpsc::SyncSender<RawBatch>,
+        prefetchers: &Prefetchers,
+    ) -> Result<(), Error> {
+        let mut batch = Vec::with_capacity(batch_size);
+        loop {
+            let mut key = lmdb_sys::MDB_val {
+                mv_size: 0,
+                mv_data: ptr::null_mut(),
+            };
+            let mut val = lmdb_sys::MDB_val {
+                mv_size: 0,
+                mv_data: ptr::null_mut(),
+            };
+            match unsafe {
+                lmdb_sys::mdb_cursor_get(self.0, &mut key, &mut val, lmdb_sys::MDB_NEXT)
+            } {
+                lmdb_sys::MDB_SUCCESS => {}
+                lmdb_sys::MDB_NOTFOUND => break,
+                code => return lmdb_result(code),
+            }
+            let (raw_key, raw_val) = unsafe {
+                (
+                    std::slice::from_raw_parts(key.mv_data as *const u8, key.mv_size),
+                    std::slice::from_raw_parts(val.mv_data as *const u8, val.mv_size),
+                )
+            };
+            let id = decode_key(raw_key).ok_or("key with incorrect length")?;
+            batch.push((id, raw_val.to_vec()));
+            if batch.len() == batch_size {
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+                if !send_batch(sender, Ok(full), prefetchers) {
+                    return Ok(());
+                }
+            }
+        }
+        if !batch.is_empty() {
+            send_batch(sender, Ok(batch), prefetchers);
+        }
+        Ok(())
     }
+}
 
-    /// Get the join table which maps OSM Nodes to the Ways that the Node is part of.
-    pub fn node_ways(&self) -> Result<JoinTable, Box<dyn Error>> {
-        Ok(JoinTable::new(&self.txn, self.db.node_way))
+impl Drop for PrefetchCursor {
+    fn drop(&mut self) {
+        unsafe { lmdb_sys::mdb_cursor_close(self.0) };
     }
+}
 
-    /// Get the join table which maps OSM Nodes to the Relations that the Node is a member of.
-    pub fn node_relations(&self) -> Result<JoinTable, Box<dyn Error>> {
-        Ok(JoinTable::new(&self.txn, self.db.node_relation))
+/// How often a prefetch helper whose batches aren't being consumed checks whether its
+/// transaction is ending
+const PREFETCH_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Send a batch to the consumer of a prefetch helper, waiting for room in the channel.
+/// Returns false if the consumer hung up or the transaction is ending.
+fn send_batch(
+    sender: &mpsc::SyncSender<RawBatch>,
+    mut batch: RawBatch,
+    prefetchers: &Prefetchers,
+) -> bool {
+    loop {
+        match sender.try_send(batch) {
+            Ok(()) => return true,
+            Err(mpsc::TrySendError::Full(unsent)) if !prefetchers.stopping() => {
+                batch = unsent;
+                thread::sleep(PREFETCH_POLL_INTERVAL);
+            }
+            Err(_) => return false,
+        }
     }
+}
 
-    /// Get the join table which maps OSM Ways to the Relations that the Way is a member of.
-    pub fn way_relations(&self) -> Result<JoinTable, Box<dyn Error>> {
-        Ok(JoinTable::new(&self.txn, self.db.way_relation))
+/// Convert the return code of an LMDB C API call into a Result
+fn lmdb_result(code: std::ffi::c_int) -> Result<(), Error> {
+    match code {
+        lmdb_sys::MDB_SUCCESS => Ok(()),
+        code => Err(lmdb::Error::from_err_code(code).into()),
     }
+}
 
-    /// Get the join table which maps OSM Relations to other Relations that they are members of.
-    pub fn relation_relations(&self) -> Result<JoinTable, Box<dyn Error>> {
-        Ok(JoinTable::new(&self.txn, self.db.relation_relation))
+/// The batches of a table read ahead by a helper thread, returned by
+/// `ElementTable::iter_prefetch`. Yields an error (and then stops) if reading from the
+/// table fails. Dropping the iterator stops the helper thread.
+pub struct Prefetch<'txn, E> {
+    receiver: Option<mpsc::Receiver<RawBatch>>,
+    helper: Option<thread::JoinHandle<()>>,
+    context: DecodeContext<'txn>,
+    skipped: Option<&'txn AtomicU64>,
+    scan: Scan,
+    phantom: PhantomData<E>,
+}
+
+impl<E> Prefetch<'_, E> {
+    /// Wait for the helper thread to exit, returning an error if it panicked.
+    fn join(&mut self) -> Result<(), Error> {
+        self.receiver = None;
+        match self.helper.take().map(thread::JoinHandle::join) {
+            Some(Err(_)) => Err("the prefetch thread panicked".into()),
+            _ => Ok(()),
+        }
     }
 }
 
-/// A table that stores data associated with OSM elements, keyed by the element's ID.
-/// The value type depends on what element is being stored. In an OSMX database, the
-/// values are usually Cap'n Proto messages describing the element's properties.
-pub struct ElementTable<'txn, E: TryFrom<&'txn [u8]> + 'txn> {
+impl<'txn, E> Iterator for Prefetch<'txn, E> {
+    type Item = Result<PrefetchedBatch<'txn, E>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let received = self.receiver.as_ref()?.recv();
+        match received {
+            Ok(Ok(entries)) => {
+                for _ in 0..entries.len() {
+                    self.scan.yielded();
+                }
+                Some(Ok(PrefetchedBatch {
+                    entries,
+                    context: self.context,
+                    skipped: self.skipped,
+                    phantom: PhantomData,
+                }))
+            }
+            Ok(Err(e)) => {
+                self.receiver = None;
+                Some(Err(e.into()))
+            }
+            Err(mpsc::RecvError) => self.join().err().map(Err),
+        }
+    }
+}
+
+impl<E> Drop for Prefetch<'_, E> {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+/// A batch of raw values read ahead by `ElementTable::iter_prefetch`, which are decoded
+/// as they're iterated over. The elements borrow from the batch.
+pub struct PrefetchedBatch<'txn, E> {
+    entries: Vec<(u64, Vec<u8>)>,
+    context: DecodeContext<'txn>,
+    skipped: Option<&'txn AtomicU64>,
+    phantom: PhantomData<E>,
+}
+
+impl<E: Reborrow> PrefetchedBatch<'_, E> {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The IDs of the elements in the batch, in order
+    pub fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.entries.iter().map(|(id, _)| *id)
+    }
+
+    /// Iterate over the elements in the batch, in ID order. Panics if a value cannot be
+    /// decoded (unless the [DecodeMode] is lenient); see [Self::try_iter].
+    pub fn iter(&self) -> impl Iterator<Item = (u64, E::At<'_>)> + '_ {
+        self.try_iter().map(Result::unwrap)
+    }
+
+    /// Iterate over the elements in the batch, in ID order, yielding an error for each
+    /// value which cannot be decoded (or skipping it, if the [DecodeMode] is lenient).
+    pub fn try_iter(&self) -> impl Iterator<Item = Result<(u64, E::At<'_>), Error>> + '_ {
+        self.entries.iter().filter_map(|(id, raw_val)| {
+            match skip_failed(E::At::decode(raw_val, self.context), self.skipped) {
+                Ok(elem) => elem.map(|elem| Ok((*id, elem))),
+                Err(e) => Some(Err(e.into())),
+            }
+        })
+    }
+}
+
+/// Get the first or last entry of a table (with MDB_FIRST or MDB_LAST), or None if the
+/// table is empty. In tables with duplicate keys, this is the first value of the first
+/// key or the last value of the last key.
+fn end_entry<'txn>(
     txn: &'txn lmdb::RoTransaction<'txn>,
     table: lmdb::Database,
-    phantom: PhantomData<E>,
+    op: lmdb_sys::MDB_cursor_op,
+) -> Option<(&'txn [u8], &'txn [u8])> {
+    let cursor = txn.open_ro_cursor(table).unwrap();
+    match cursor.get(None, None, op) {
+        Ok((raw_key, raw_val)) => Some((raw_key.unwrap(), raw_val)),
+        Err(lmdb::Error::NotFound) => None,
+        Err(e) => unreachable!("Unexpected LMDB error: {:?}", e),
+    }
 }
 
-impl<'txn, E: TryFrom<&'txn [u8]>> ElementTable<'txn, E> {
-    fn new(txn: &'txn lmdb::RoTransaction<'txn>, table: lmdb::Database) -> Self {
+/// The entries of a table read with a cursor, from where `entries` starts. lmdb's
+/// iterators use their cursor without borrowing it, so this keeps the cursor open for as
+/// long as its iterator.
+struct CursorEntries<'txn> {
+    entries: Option<lmdb::Iter<'txn>>,
+    _cursor: lmdb::RoCursor<'txn>,
+}
+
+impl<'txn> CursorEntries<'txn> {
+    fn new(
+        mut cursor: lmdb::RoCursor<'txn>,
+        entries: impl FnOnce(&mut lmdb::RoCursor<'txn>) -> Option<lmdb::Iter<'txn>>,
+    ) -> Self {
         Self {
-            txn,
-            table,
-            phantom: PhantomData,
+            entries: entries(&mut cursor),
+            _cursor: cursor,
         }
     }
+}
+
+impl<'txn> Iterator for CursorEntries<'txn> {
+    type Item = (&'txn [u8], &'txn [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.as_mut()?.next()
+    }
+}
 
-    /// Get an element by its ID. Returns None if the element is not found.
-    pub fn get(&self, id: u64) -> Option<E> {
-        match self.txn.get(self.table, &id.to_le_bytes()) {
-            Ok(raw_val) => Some(E::try_from(raw_val).ok().unwrap()),
-            Err(lmdb::Error::NotFound) => None,
-            Err(e) => unreachable!("Unexpected LMDB error: {:?}", e),
+/// The entries of a table from the first key at or after `key`, or None if there isn't
+/// one (in which case iter_from would panic)
+fn entries_from<'txn>(cursor: &mut lmdb::RoCursor<'txn>, key: &[u8]) -> Option<lmdb::Iter<'txn>> {
+    match cursor.get(Some(key), None, lmdb_sys::MDB_SET_RANGE) {
+        Ok(_) => Some(cursor.iter_from(key)),
+        Err(lmdb::Error::NotFound) => None,
+        Err(e) => unreachable!("Unexpected LMDB error: {:?}", e),
+    }
+}
+
+/// The entries of a table with duplicate keys which have the given key
+fn duplicates_of<'txn>(cursor: &mut lmdb::RoCursor<'txn>, key: u64) -> Option<lmdb::Iter<'txn>> {
+    match cursor.iter_dup_of(&encode_key(key)) {
+        Ok(iter) => Some(iter),
+        Err(lmdb::Error::NotFound) => None,
+        Err(e) => unreachable!("Unexpected LMDB error: {:?}", e),
+    }
+}
+
+/// Decode a key and value of an element table, returning None if the value is skipped
+/// (see [skip_failed])
+fn decode_entry<'txn, E: Decode<'txn>>(
+    raw_key: &[u8],
+    raw_val: &'txn [u8],
+    context: DecodeContext<'txn>,
+    skipped: Option<&AtomicU64>,
+) -> Result<Option<(u64, E)>, Error> {
+    let id = decode_key(raw_key).ok_or(Error::CorruptKey {
+        length: raw_key.len(),
+    })?;
+    Ok(skip_failed(E::decode(raw_val, context), skipped)?.map(|elem| (id, elem)))
+}
+
+/// Apply the decode mode to the result of decoding a value: in [DecodeMode::Lenient]
+/// (when there's a counter of skipped values), count a value which can't be decoded and
+/// return None instead of the error.
+fn skip_failed<T>(
+    decoded: Result<T, capnp::Error>,
+    skipped: Option<&AtomicU64>,
+) -> Result<Option<T>, capnp::Error> {
+    match (decoded, skipped) {
+        (Ok(elem), _) => Ok(Some(elem)),
+        (Err(_), Some(skipped)) => {
+            skipped.fetch_add(1, Ordering::Relaxed);
+            Ok(None)
         }
+        (Err(e), None) => Err(e),
     }
+}
 
-    /// Iterate over all the elements in the table.
-    pub fn iter(&self) -> impl Iterator<Item = (u64, E)> + 'txn {
-        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
-        Gen::new(|co| async move {
-            let mut cursor = cursor;
-            for (raw_key, raw_val) in cursor.iter_start() {
-                let id = u64::from_le_bytes(raw_key.try_into().expect("key with incorrect length"));
-                let elem = E::try_from(raw_val).ok().unwrap();
+/// Decode an entry of a table whose keys and values are both IDs
+fn decode_pair((raw_key, raw_val): (&[u8], &[u8])) -> (u64, u64) {
+    (
+        decode_key(raw_key).expect("key with incorrect length"),
+        decode_key(raw_val).expect("val with incorrect length"),
+    )
+}
+
+/// Iterates over all the elements in the table, like [ElementTable::iter].
+impl<'txn, E: Decode<'txn> + 'txn> IntoIterator for ElementTable<'txn, E> {
+    type Item = (u64, E);
+    // the iterator returned by iter() can't be named, so it is boxed
+    type IntoIter = Box<dyn Iterator<Item = (u64, E)> + 'txn>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/// Iterates over all the elements in the table, like [ElementTable::iter].
+impl<'txn, E: Decode<'txn> + 'txn> IntoIterator for &ElementTable<'txn, E> {
+    type Item = (u64, E);
+    type IntoIter = Box<dyn Iterator<Item = (u64, E)> + 'txn>;
 
-                co.yield_((id, elem)).await;
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+impl<'txn, E: Decode<'txn> + WithMetadata> ElementTable<'txn, E> {
+    /// Get an element as it was at the given time (in seconds since the Unix epoch): the
+    /// latest version created at or before that time. Returns Ok(None) if the element
+    /// didn't exist (or had been deleted) at that time, or the database doesn't store
+    /// history.
+    pub fn get_as_of(&self, id: u64, timestamp: u64) -> Result<Option<E>, Error> {
+        let mut found = None;
+        for (_, elem) in self.versions(id) {
+            let (created, deleted) = match elem.metadata() {
+                Some(metadata) => (metadata.timestamp(), metadata.deleted()),
+                None => continue,
+            };
+            if created > timestamp {
+                break;
             }
-        })
-        .into_iter()
+            found = if deleted { None } else { Some(elem) };
+        }
+        Ok(found)
     }
 }
 
@@ -184,49 +4193,184 @@ pub type Ways<'txn> = ElementTable<'txn, Way<'txn>>;
 /// metadata, and the IDs, types, and roles of the Relation's members.
 pub type Relations<'txn> = ElementTable<'txn, Relation<'txn>>;
 
+/// A table which maps changeset IDs to structs summarizing the changeset: its author,
+/// time range, bounding box, and the number of element versions it created.
+pub type Changesets<'txn> = ElementTable<'txn, Changeset<'txn>>;
+
 /// A spatial index that permits fast spatial lookups of elements. Under the hood,
 /// this is implemented as a table that maps S2 Cell IDs to OSM element IDs.
+#[cfg(feature = "spatial")]
 pub struct SpatialIndexTable<'txn> {
     txn: &'txn lmdb::RoTransaction<'txn>,
     table: lmdb::Database,
+    name: &'static str,
+    metrics: Option<&'txn dyn MetricsSink>,
 }
 
+#[cfg(feature = "spatial")]
 impl<'txn> SpatialIndexTable<'txn> {
-    fn new(txn: &'txn lmdb::RoTransaction<'txn>, table: lmdb::Database) -> Self {
-        Self { txn, table }
+    fn new(
+        txn: &'txn lmdb::RoTransaction<'txn>,
+        table: lmdb::Database,
+        name: &'static str,
+        metrics: Option<&'txn dyn MetricsSink>,
+    ) -> Self {
+        Self {
+            txn,
+            table,
+            name,
+            metrics,
+        }
+    }
+
+    /// Iterate over every entry in the index, as (S2 cell ID, element ID) pairs. Cell IDs
+    /// are at CELL_INDEX_LEVEL.
+    pub fn iter(&self) -> impl Iterator<Item = (u64, u64)> + 'txn {
+        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        let mut scan = Scan::new("table scan", self.name);
+        CursorEntries::new(cursor, |cursor| Some(cursor.iter()))
+            .map(decode_pair)
+            .inspect(move |_| scan.yielded())
+    }
+
+    /// The entry with the smallest cell ID (and smallest element ID in that cell), as a
+    /// (cell ID, element ID) pair, or None if the index is empty.
+    pub fn first(&self) -> Option<(u64, u64)> {
+        end_entry(self.txn, self.table, lmdb_sys::MDB_FIRST).map(decode_pair)
+    }
+
+    /// The entry with the largest cell ID (and largest element ID in that cell), as a
+    /// (cell ID, element ID) pair, or None if the index is empty.
+    pub fn last(&self) -> Option<(u64, u64)> {
+        end_entry(self.txn, self.table, lmdb_sys::MDB_LAST).map(decode_pair)
     }
 
     /// Given a Region, returns an iterator of IDs of elements that may fall within
     /// the region. There may be false positives (elements that are near, but not
     /// not truly within the given region) due to how the spatial index works.
     pub fn find_in_region(&self, region: &'txn Region) -> impl Iterator<Item = u64> + 'txn {
-        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
-
-        Gen::new(|co| async move {
-            let mut cursor = cursor;
-            for cell_id in region.cells.0.clone() {
+        let mut cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        let mut scan = Scan::new("spatial query", self.name);
+        let mut timer = self.metrics.map(|sink| QueryTimer::new(sink, self.name));
+        region
+            .cells
+            .0
+            .iter()
+            .flat_map(move |cell_id| {
                 let start = cell_id.child_begin_at_level(CELL_INDEX_LEVEL);
                 let end = cell_id.child_end_at_level(CELL_INDEX_LEVEL);
+                entries_from(&mut cursor, &encode_key(start.0))
+                    .into_iter()
+                    .flatten()
+                    .map(decode_pair)
+                    .take_while(move |&(key, _)| end.0 > key)
+                    .map(|(_, node_id)| node_id)
+            })
+            .inspect(move |_| {
+                scan.yielded();
+                if let Some(timer) = timer.as_mut() {
+                    timer.yielded();
+                }
+            })
+    }
 
-                for (_, node_id) in cursor
-                    .iter_dup_from(&start.0.to_le_bytes())
+    /// The IDs of the elements in an index whose cells may be at any level up to
+    /// CELL_INDEX_LEVEL (like cell_way and cell_relation), with a cell overlapping the
+    /// region: one within a cell of the region, or containing one.
+    fn find_overlapping(&self, region: &Region) -> Result<RoaringTreemap, Error> {
+        let mut cursor = self.txn.open_ro_cursor(self.table)?;
+        let mut scan = Scan::new("spatial query", self.name);
+        let mut timer = self.metrics.map(|sink| QueryTimer::new(sink, self.name));
+        let mut ids = RoaringTreemap::new();
+        for cell_id in region.cells.0.iter() {
+            let (start, end) = (cell_id.range_min().0, cell_id.range_max().0);
+            for (_, id) in entries_from(&mut cursor, &encode_key(start))
+                .into_iter()
+                .flatten()
+                .map(decode_pair)
+                .take_while(|&(cell, _)| cell <= end)
+            {
+                scan.yielded();
+                ids.insert(id);
+            }
+            for level in 0..cell_id.level().min(CELL_INDEX_LEVEL + 1) {
+                let parent = cell_id.parent(level).0;
+                for (_, id) in entries_from(&mut cursor, &encode_key(parent))
+                    .into_iter()
                     .flatten()
-                    .map(|(raw_key, raw_val)| {
-                        let cell_id = u64::from_le_bytes(
-                            raw_key.try_into().expect("key with incorrect length"),
-                        );
-                        let node_id = u64::from_le_bytes(
-                            raw_val.try_into().expect("val with incorrect length"),
-                        );
-                        (cell_id, node_id)
-                    })
-                    .take_while(|&(key, _)| end.0 > key)
+                    .map(decode_pair)
+                    .take_while(|&(cell, _)| cell == parent)
                 {
-                    co.yield_(node_id).await;
+                    scan.yielded();
+                    ids.insert(id);
                 }
             }
+        }
+        if let Some(timer) = timer.as_mut() {
+            timer.found(ids.len());
+        }
+        Ok(ids)
+    }
+}
+
+/// A table created by an application (see [Database::create_extension_table]), which maps
+/// byte string keys to byte string values.
+pub struct ExtensionTable<'txn> {
+    txn: &'txn lmdb::RoTransaction<'txn>,
+    table: lmdb::Database,
+}
+
+impl<'txn> ExtensionTable<'txn> {
+    /// The value stored under a key, or None if there isn't one.
+    pub fn get(&self, key: &[u8]) -> Result<Option<&'txn [u8]>, Error> {
+        match self.txn.get(self.table, &key) {
+            Ok(raw_val) => Ok(Some(raw_val)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Iterate over the keys and values of the table, in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&'txn [u8], &'txn [u8])> + 'txn {
+        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        CursorEntries::new(cursor, |cursor| Some(cursor.iter()))
+    }
+
+    /// Iterate over the keys and values of the table which start with a prefix, in key
+    /// order.
+    pub fn iter_prefix<'a>(
+        &self,
+        prefix: &'a [u8],
+    ) -> impl Iterator<Item = (&'txn [u8], &'txn [u8])> + 'a
+    where
+        'txn: 'a,
+    {
+        let cursor = self.txn.open_ro_cursor(self.table).unwrap();
+        CursorEntries::new(cursor, |cursor| match prefix {
+            [] => Some(cursor.iter()),
+            _ => entries_from(cursor, prefix),
         })
-        .into_iter()
+        .take_while(move |(raw_key, _)| raw_key.starts_with(prefix))
+    }
+
+    /// The number of entries in the table.
+    pub fn len(&self) -> u64 {
+        let mut stat = lmdb_sys::MDB_stat {
+            ms_psize: 0,
+            ms_depth: 0,
+            ms_branch_pages: 0,
+            ms_leaf_pages: 0,
+            ms_overflow_pages: 0,
+            ms_entries: 0,
+        };
+        unsafe {
+            lmdb_sys::mdb_stat(self.txn.txn(), self.table.dbi(), &mut stat);
+        }
+        stat.ms_entries as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
@@ -243,27 +4387,28 @@ impl<'txn> JoinTable<'txn> {
         Self { txn, table }
     }
 
+    /// The first entry of the table, as a pair of the smallest element ID and the
+    /// smallest ID related to it, or None if the table is empty.
+    pub fn first(&self) -> Option<(u64, u64)> {
+        end_entry(self.txn, self.table, lmdb_sys::MDB_FIRST).map(decode_pair)
+    }
+
+    /// The last entry of the table, as a pair of the largest element ID and the largest
+    /// ID related to it, or None if the table is empty.
+    pub fn last(&self) -> Option<(u64, u64)> {
+        end_entry(self.txn, self.table, lmdb_sys::MDB_LAST).map(decode_pair)
+    }
+
+    /// Returns the largest element ID in the table, or None if the table is empty.
+    pub fn last_id(&self) -> Option<u64> {
+        self.last().map(|(id, _)| id)
+    }
+
     /// Given an element ID, returns the IDs of elements it is related to in this table.
     /// Returns an iterator since there may be multiple values for a given key.
     pub fn get(&self, id: u64) -> impl Iterator<Item = u64> + 'txn {
         let cursor = self.txn.open_ro_cursor(self.table).unwrap();
-
-        Gen::new(|co| async move {
-            let mut cursor = cursor;
-            match cursor.iter_dup_of(&id.to_le_bytes()) {
-                Ok(iter) => {
-                    for (_, raw_val) in iter {
-                        let val = u64::from_le_bytes(
-                            raw_val.try_into().expect("key with incorrect length"),
-                        );
-
-                        co.yield_(val).await;
-                    }
-                }
-                Err(lmdb::Error::NotFound) => (),
-                Err(e) => unreachable!("Unexpected LMDB error: {:?}", e),
-            }
-        })
-        .into_iter()
+        CursorEntries::new(cursor, |cursor| duplicates_of(cursor, id))
+            .map(|(_, raw_val)| decode_key(raw_val).expect("key with incorrect length"))
     }
 }
@@ -0,0 +1,252 @@
+//! Statistics about the edits which made the elements in a database what they are: when
+//! the current version of each element was created, by whom, and where, from the element
+//! metadata stored by `expand --metadata`.
+//!
+//! Only the latest version of each element is known (unless the database has history),
+//! so these count the elements last edited in each month or by each user, not every edit
+//! ever made. Like [crate::stats::TagFrequencies], counts are collected in an
+//! [EditStats] accumulator which can be filled in one element at a time and merged with
+//! others.
+
+use std::collections::{BTreeMap, HashMap};
+
+use roaring::RoaringTreemap;
+
+use crate::database::Transaction;
+use crate::interop::civil_date;
+use crate::spatial_join::SpatialJoin;
+use crate::stats::{for_each_element, region_element_ids};
+use crate::types::{Metadata, Node, Region, Relation, Way, WithMetadata};
+use crate::Error;
+
+/// Which elements [edit_stats] counts, and how it groups them by location.
+pub struct EditStatsOptions<'a> {
+    pub nodes: bool,
+    pub ways: bool,
+    pub relations: bool,
+    /// Only count nodes which may be in this region (see [Region]), ways with a node in
+    /// it, and relations with one of those nodes or ways as a member.
+    pub region: Option<&'a Region>,
+    /// Count the elements in each S2 cell at this level (see [EditStats::cells]).
+    pub cell_level: Option<u64>,
+    /// Count the elements in each of these polygons (see [EditStats::regions]).
+    pub regions: Option<&'a SpatialJoin>,
+}
+
+impl Default for EditStatsOptions<'_> {
+    fn default() -> Self {
+        Self {
+            nodes: true,
+            ways: true,
+            relations: true,
+            region: None,
+            cell_level: None,
+            regions: None,
+        }
+    }
+}
+
+/// The number of elements last edited in a month or place, and the distinct changesets
+/// and users which edited them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EditCounts {
+    pub elements: u64,
+    pub changesets: RoaringTreemap,
+    pub users: RoaringTreemap,
+}
+
+impl EditCounts {
+    fn add(&mut self, metadata: &Metadata) {
+        self.elements += 1;
+        self.changesets.insert(metadata.changeset() as u64);
+        self.users.insert(metadata.uid() as u64);
+    }
+
+    fn merge(&mut self, other: EditCounts) {
+        self.elements += other.elements;
+        self.changesets |= other.changesets;
+        self.users |= other.users;
+    }
+}
+
+/// The elements last edited by a user.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserEdits {
+    /// The user's name, as of the latest of their edits counted.
+    pub user: String,
+    pub elements: u64,
+    pub changesets: RoaringTreemap,
+    /// The times of the earliest and latest of their edits counted, in seconds since the
+    /// Unix epoch.
+    pub first_timestamp: u64,
+    pub last_timestamp: u64,
+}
+
+impl UserEdits {
+    fn merge(&mut self, other: UserEdits) {
+        if self.elements == 0 || other.last_timestamp > self.last_timestamp {
+            self.user = other.user;
+        }
+        self.first_timestamp = match self.elements {
+            0 => other.first_timestamp,
+            _ => self.first_timestamp.min(other.first_timestamp),
+        };
+        self.last_timestamp = self.last_timestamp.max(other.last_timestamp);
+        self.elements += other.elements;
+        self.changesets |= other.changesets;
+    }
+}
+
+/// Counts of the elements last edited in each month, by each user, and in each place.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EditStats {
+    /// The number of elements counted which have metadata.
+    pub elements: u64,
+    /// The number of elements which don't have metadata, which are left out of the other
+    /// counts.
+    pub without_metadata: u64,
+    /// The elements last edited in each month, by (year, month) in UTC.
+    pub months: BTreeMap<(i64, i64), EditCounts>,
+    /// The elements last edited by each user, by user ID. Edits made anonymously (before
+    /// 2007) have user ID 0.
+    pub users: HashMap<u32, UserEdits>,
+    /// The elements in each S2 cell (at the level given to [EditStats::new]), by cell
+    /// ID. Nodes are in the cell containing them, ways in the cell containing their
+    /// first node, and relations aren't counted, since they have no single location.
+    pub cells: BTreeMap<u64, EditCounts>,
+    /// The elements in each region, by the regions' indexes (see [EditStats::add]).
+    pub regions: BTreeMap<usize, EditCounts>,
+    cell_level: Option<u64>,
+}
+
+impl EditStats {
+    /// Make an empty accumulator, which counts the elements in the S2 cells at
+    /// `cell_level`, if given.
+    pub fn new(cell_level: Option<u64>) -> Self {
+        Self {
+            cell_level,
+            ..Default::default()
+        }
+    }
+
+    /// Count one element, given its metadata (if any), its location in degrees (if it
+    /// has one), and the indexes of the regions containing it.
+    pub fn add(
+        &mut self,
+        metadata: Option<&Metadata>,
+        location: Option<(f64, f64)>,
+        regions: &[usize],
+    ) {
+        let Some(metadata) = metadata else {
+            self.without_metadata += 1;
+            return;
+        };
+        self.elements += 1;
+
+        let timestamp = metadata.timestamp();
+        let (year, month, _) = civil_date(timestamp as i64);
+        self.months.entry((year, month)).or_default().add(metadata);
+
+        let user = self.users.entry(metadata.uid()).or_default();
+        user.merge(UserEdits {
+            user: metadata.user().to_string(),
+            elements: 1,
+            changesets: RoaringTreemap::from_iter([metadata.changeset() as u64]),
+            first_timestamp: timestamp,
+            last_timestamp: timestamp,
+        });
+
+        if let (Some(level), Some((lon, lat))) = (self.cell_level, location) {
+            let latlng = s2::latlng::LatLng::from_degrees(lat, lon);
+            let cell = s2::cellid::CellID::from(latlng).parent(level);
+            self.cells.entry(cell.0).or_default().add(metadata);
+        }
+        for &region in regions {
+            self.regions.entry(region).or_default().add(metadata);
+        }
+    }
+
+    /// Add the counts of another accumulator to this one. Their cells should be at the
+    /// same level, and their regions' indexes should refer to the same regions.
+    pub fn merge(&mut self, other: EditStats) {
+        self.elements += other.elements;
+        self.without_metadata += other.without_metadata;
+        for (month, counts) in other.months {
+            self.months.entry(month).or_default().merge(counts);
+        }
+        for (uid, edits) in other.users {
+            self.users.entry(uid).or_default().merge(edits);
+        }
+        for (cell, counts) in other.cells {
+            self.cells.entry(cell).or_default().merge(counts);
+        }
+        for (region, counts) in other.regions {
+            self.regions.entry(region).or_default().merge(counts);
+        }
+    }
+
+    /// The elements last edited in each year, in order.
+    pub fn years(&self) -> BTreeMap<i64, EditCounts> {
+        let mut years: BTreeMap<i64, EditCounts> = BTreeMap::new();
+        for (&(year, _), counts) in self.months.iter() {
+            years.entry(year).or_default().merge(counts.clone());
+        }
+        years
+    }
+}
+
+/// Count the elements selected by the options by when, by whom and where they were last
+/// edited. Only nodes with tags are counted, since untagged nodes aren't stored in the
+/// Nodes table, so their metadata isn't kept.
+pub fn edit_stats(txn: &Transaction, options: &EditStatsOptions) -> Result<EditStats, Error> {
+    let locations = txn.locations()?;
+    let mut stats = EditStats::new(options.cell_level);
+    let mut count = |element: &dyn WithMetadata, location: Option<(f64, f64)>| {
+        let regions = match (options.regions, location) {
+            (Some(join), Some((lon, lat))) => join.containing(lon, lat),
+            _ => vec![],
+        };
+        stats.add(element.metadata().as_ref(), location, &regions);
+    };
+    let location = |node_id: u64| -> Result<Option<(f64, f64)>, Error> {
+        Ok(locations
+            .get(node_id)?
+            .map(|location| (location.lon(), location.lat())))
+    };
+
+    let (node_ids, way_ids, relation_ids) = match options.region {
+        Some(region) => {
+            let (nodes, ways, relations) =
+                region_element_ids(txn, region, options.ways, options.relations)?;
+            (Some(nodes), Some(ways), Some(relations))
+        }
+        None => (None, None, None),
+    };
+
+    if options.nodes {
+        for_each_element(&txn.nodes()?, None, node_ids.as_ref(), |id, node: &Node| {
+            count(node, location(id)?);
+            Ok(())
+        })?;
+    }
+    if options.ways {
+        for_each_element(&txn.ways()?, None, way_ids.as_ref(), |_, way: &Way| {
+            let first_node = way.nodes().next();
+            count(way, first_node.map(location).transpose()?.flatten());
+            Ok(())
+        })?;
+    }
+    if options.relations {
+        let relations = txn.relations()?;
+        for_each_element(
+            &relations,
+            None,
+            relation_ids.as_ref(),
+            |_, relation: &Relation| {
+                count(relation, None);
+                Ok(())
+            },
+        )?;
+    }
+    Ok(stats)
+}
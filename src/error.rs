@@ -0,0 +1,220 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[cfg(feature = "update")]
+use crate::database::VersionConflict;
+use crate::database::{FormatError, ReaderSlotsExhausted, UnsupportedFormatVersion};
+#[cfg(feature = "export")]
+use crate::geometry::GeometryError;
+use crate::restrictions::InvalidTurnRestriction;
+
+/// The error type of this crate, returned by opening a [crate::Database], beginning a
+/// [crate::Transaction], looking up elements, queries, and writing. It converts into
+/// `Box<dyn Error>`, so it can be returned with `?` from functions using that.
+#[derive(Debug)]
+pub enum Error {
+    /// The database file doesn't exist.
+    NotFound(PathBuf),
+    /// The file isn't an OSMX database.
+    Format(FormatError),
+    /// The database's format version isn't supported by this version of osmx.
+    UnsupportedFormatVersion(UnsupportedFormatVersion),
+    /// Every reader slot of the database is held by an open transaction.
+    ReaderSlotsExhausted(ReaderSlotsExhausted),
+    /// An error from LMDB, other than a key not being found (which lookups return as
+    /// None).
+    Lmdb(lmdb::Error),
+    /// An element's value can't be decoded, because it's corrupt or reading it exceeds
+    /// the limits of the reader options.
+    Decode(capnp::Error),
+    /// A key of an element table doesn't have the length of an ID, so the table is
+    /// corrupt.
+    CorruptKey {
+        /// The length of the key, in bytes.
+        length: usize,
+    },
+    /// A `type=restriction` relation isn't a valid turn restriction.
+    InvalidTurnRestriction(InvalidTurnRestriction),
+    /// A way's or relation's geometry can't be assembled.
+    #[cfg(feature = "export")]
+    Geometry(GeometryError),
+    /// An element written or deleted with one of the `*_if_version` methods of
+    /// [crate::WriteTransaction] doesn't have the expected version.
+    #[cfg(feature = "update")]
+    Conflict(VersionConflict),
+    /// An error reading or writing a file other than the database (such as an OSM PBF
+    /// file), or a serialized ID bitmap.
+    Io(std::io::Error),
+    /// Some other problem, described by the message (such as a malformed metadata value).
+    Other(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(path) => write!(f, "{} does not exist", path.display()),
+            Self::Format(e) => e.fmt(f),
+            Self::UnsupportedFormatVersion(e) => e.fmt(f),
+            Self::ReaderSlotsExhausted(e) => e.fmt(f),
+            Self::Lmdb(e) => e.fmt(f),
+            Self::Decode(e) => write!(f, "element value can't be decoded: {}", e),
+            Self::CorruptKey { length } => write!(f, "key with incorrect length {}", length),
+            Self::InvalidTurnRestriction(e) => e.fmt(f),
+            #[cfg(feature = "export")]
+            Self::Geometry(e) => e.fmt(f),
+            #[cfg(feature = "update")]
+            Self::Conflict(e) => e.fmt(f),
+            Self::Io(e) => e.fmt(f),
+            Self::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Format(e) => Some(e),
+            Self::UnsupportedFormatVersion(e) => Some(e),
+            Self::ReaderSlotsExhausted(e) => Some(e),
+            Self::Lmdb(e) => Some(e),
+            Self::Decode(e) => Some(e),
+            Self::InvalidTurnRestriction(e) => Some(e),
+            #[cfg(feature = "export")]
+            Self::Geometry(e) => Some(e),
+            #[cfg(feature = "update")]
+            Self::Conflict(e) => Some(e),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<lmdb::Error> for Error {
+    fn from(e: lmdb::Error) -> Self {
+        Self::Lmdb(e)
+    }
+}
+
+impl From<capnp::Error> for Error {
+    fn from(e: capnp::Error) -> Self {
+        Self::Decode(e)
+    }
+}
+
+impl From<FormatError> for Error {
+    fn from(e: FormatError) -> Self {
+        Self::Format(e)
+    }
+}
+
+impl From<UnsupportedFormatVersion> for Error {
+    fn from(e: UnsupportedFormatVersion) -> Self {
+        Self::UnsupportedFormatVersion(e)
+    }
+}
+
+impl From<ReaderSlotsExhausted> for Error {
+    fn from(e: ReaderSlotsExhausted) -> Self {
+        Self::ReaderSlotsExhausted(e)
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Self::Other(message.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<InvalidTurnRestriction> for Error {
+    fn from(e: InvalidTurnRestriction) -> Self {
+        Self::InvalidTurnRestriction(e)
+    }
+}
+
+#[cfg(feature = "export")]
+impl From<GeometryError> for Error {
+    fn from(e: GeometryError) -> Self {
+        Self::Geometry(e)
+    }
+}
+
+#[cfg(feature = "update")]
+impl From<VersionConflict> for Error {
+    fn from(e: VersionConflict) -> Self {
+        Self::Conflict(e)
+    }
+}
+
+impl From<std::str::Utf8Error> for Error {
+    fn from(e: std::str::Utf8Error) -> Self {
+        Self::Other(format!("string isn't valid UTF-8: {}", e))
+    }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(e: std::num::ParseIntError) -> Self {
+        Self::Other(format!("invalid number: {}", e))
+    }
+}
+
+/// Converts the errors which this crate's own variants wrap back into them, so they can
+/// still be matched on, and describes any others with [Error::Other].
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(e: Box<dyn std::error::Error>) -> Self {
+        let e = match e.downcast::<Error>() {
+            Ok(e) => return *e,
+            Err(e) => e,
+        };
+        let e = match e.downcast::<lmdb::Error>() {
+            Ok(e) => return Self::Lmdb(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<capnp::Error>() {
+            Ok(e) => return Self::Decode(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<std::io::Error>() {
+            Ok(e) => return Self::Io(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<FormatError>() {
+            Ok(e) => return Self::Format(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<UnsupportedFormatVersion>() {
+            Ok(e) => return Self::UnsupportedFormatVersion(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<ReaderSlotsExhausted>() {
+            Ok(e) => return Self::ReaderSlotsExhausted(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<InvalidTurnRestriction>() {
+            Ok(e) => return Self::InvalidTurnRestriction(*e),
+            Err(e) => e,
+        };
+        #[cfg(feature = "export")]
+        let e = match e.downcast::<GeometryError>() {
+            Ok(e) => return Self::Geometry(*e),
+            Err(e) => e,
+        };
+        #[cfg(feature = "update")]
+        let e = match e.downcast::<VersionConflict>() {
+            Ok(e) => return Self::Conflict(*e),
+            Err(e) => e,
+        };
+        Self::Other(e.to_string())
+    }
+}
@@ -0,0 +1,238 @@
+//! Approximate counts of the elements which match a filter, from a sample of each element
+//! table, for showing roughly how big a result will be before running the query.
+
+use std::fmt;
+
+use crate::database::Transaction;
+use crate::filter::Filter;
+use crate::query::region_contains;
+use crate::types::{ElementId, Region, Way};
+use crate::Error;
+
+/// The z-score of a two-sided 95% confidence interval
+const Z_95: f64 = 1.96;
+
+/// An estimate of the number of elements of one type which match, returned as part of a
+/// [CountEstimate].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Estimate {
+    /// The estimated number of matching elements.
+    pub count: u64,
+    /// The lower bound of the 95% confidence interval of the count.
+    pub low: u64,
+    /// The upper bound of the 95% confidence interval of the count.
+    pub high: u64,
+    /// The number of elements which were sampled.
+    pub sampled: u64,
+    /// The number of sampled elements which matched.
+    pub matched: u64,
+    /// The number of elements the sample was taken from.
+    pub population: u64,
+}
+
+impl Estimate {
+    /// Extrapolate from a sample, with a Wilson score interval for the fraction of the
+    /// population which matches. The count is exact when the whole population was
+    /// sampled.
+    fn from_sample(population: u64, sampled: u64, matched: u64) -> Self {
+        if sampled >= population {
+            return Self {
+                count: matched,
+                low: matched,
+                high: matched,
+                sampled,
+                matched,
+                population,
+            };
+        }
+        if sampled == 0 {
+            return Self {
+                high: population,
+                population,
+                ..Self::default()
+            };
+        }
+        let n = sampled as f64;
+        let p = matched as f64 / n;
+        let z2 = Z_95 * Z_95;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let margin = Z_95 / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+
+        // the sampled elements which matched (or didn't) are known, whatever the rest are
+        let unmatched = sampled - matched;
+        let scale = |fraction: f64| (fraction.clamp(0.0, 1.0) * population as f64).round() as u64;
+        Self {
+            count: scale(p).clamp(matched, population - unmatched),
+            low: scale(center - margin).clamp(matched, population - unmatched),
+            high: scale(center + margin).clamp(matched, population - unmatched),
+            sampled,
+            matched,
+            population,
+        }
+    }
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.low == self.high {
+            write!(f, "{}", self.count)
+        } else {
+            write!(f, "~{} ({}–{})", self.count, self.low, self.high)
+        }
+    }
+}
+
+/// Estimated numbers of matching nodes, ways and relations, returned by
+/// [Transaction::estimate_count].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CountEstimate {
+    pub nodes: Estimate,
+    pub ways: Estimate,
+    pub relations: Estimate,
+}
+
+impl CountEstimate {
+    /// The estimated number of matching elements of all types.
+    pub fn count(&self) -> u64 {
+        self.nodes.count + self.ways.count + self.relations.count
+    }
+
+    /// Bounds for the number of matching elements of all types, from the sums of the
+    /// bounds for each type (so the interval is at least as wide as a 95% one).
+    pub fn interval(&self) -> (u64, u64) {
+        (
+            self.nodes.low + self.ways.low + self.relations.low,
+            self.nodes.high + self.ways.high + self.relations.high,
+        )
+    }
+}
+
+/// The number of elements to sample from a table of `population` elements
+fn sample_size(population: u64, fraction: f64) -> u64 {
+    ((population as f64 * fraction).ceil() as u64).clamp(population.min(1), population)
+}
+
+impl Transaction<'_> {
+    /// Estimate how many elements match a filter (and, if a region is given, how many
+    /// of those [Transaction::query] would find in the region) by checking a sample of
+    /// `fraction` (between 0 and 1) of each element table, picked with
+    /// `ElementTable::sample_ids`. The sampled elements are spread evenly over
+    /// each table's range of IDs, so the estimate is biased where matching elements are
+    /// clustered in a part of the ID range with unusually sparse or dense IDs.
+    ///
+    /// Untagged nodes are only sampled (from the Locations table) if the filter matches
+    /// untagged elements; otherwise only the Nodes table is sampled.
+    pub fn estimate_count(
+        &self,
+        region: Option<&Region>,
+        filter: &Filter,
+        fraction: f64,
+    ) -> Result<CountEstimate, Error> {
+        if !(fraction > 0.0 && fraction <= 1.0) {
+            return Err(format!("sample fraction {} is not between 0 and 1", fraction).into());
+        }
+        let locations = self.locations()?;
+        let nodes = self.nodes()?;
+        let ways = self.ways()?;
+        let relations = self.relations()?;
+        let node_in_region = |id| -> Result<bool, Error> {
+            Ok(match region {
+                Some(region) => locations.get(id)?.is_some_and(|location| {
+                    region_contains(region, location.lon(), location.lat())
+                }),
+                None => true,
+            })
+        };
+        // whether any of a way's nodes is in the region
+        let way_nodes_in_region = |way: &Way| -> Result<bool, Error> {
+            if region.is_none() {
+                return Ok(true);
+            }
+            for id in way.nodes() {
+                if node_in_region(id)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+        let way_in_region = |id| -> Result<bool, Error> {
+            Ok(region.is_none()
+                || match ways.get(id)? {
+                    Some(way) => way_nodes_in_region(&way)?,
+                    None => false,
+                })
+        };
+
+        let matches_untagged = filter.matches_untagged();
+        let (population, node_ids) = if matches_untagged {
+            let population = locations.len();
+            (
+                population,
+                locations.sample_ids(sample_size(population, fraction)),
+            )
+        } else {
+            let population = nodes.len();
+            (
+                population,
+                nodes.sample_ids(sample_size(population, fraction)),
+            )
+        };
+        let mut matched = 0;
+        for &id in node_ids.iter() {
+            let tags_match = match nodes.get(id)? {
+                Some(node) => filter.matches(&node.tags().collect::<Vec<_>>()),
+                None => matches_untagged,
+            };
+            if tags_match && node_in_region(id)? {
+                matched += 1;
+            }
+        }
+        let node_estimate = Estimate::from_sample(population, node_ids.len() as u64, matched);
+
+        let population = ways.len();
+        let way_ids = ways.sample_ids(sample_size(population, fraction));
+        let mut matched = 0;
+        for &id in way_ids.iter() {
+            if let Some(way) = ways.get(id)? {
+                if filter.matches(&way.tags().collect::<Vec<_>>()) && way_nodes_in_region(&way)? {
+                    matched += 1;
+                }
+            }
+        }
+        let way_estimate = Estimate::from_sample(population, way_ids.len() as u64, matched);
+
+        let population = relations.len();
+        let relation_ids = relations.sample_ids(sample_size(population, fraction));
+        let mut matched = 0;
+        for &id in relation_ids.iter() {
+            let Some(relation) = relations.get(id)? else {
+                continue;
+            };
+            if !filter.matches(&relation.tags().collect::<Vec<_>>()) {
+                continue;
+            }
+            let mut in_region = region.is_none();
+            for member in relation.members() {
+                if in_region {
+                    break;
+                }
+                in_region = match member.id() {
+                    ElementId::Node(node_id) => node_in_region(node_id)?,
+                    ElementId::Way(way_id) => way_in_region(way_id)?,
+                    ElementId::Relation(_) => false,
+                };
+            }
+            if in_region {
+                matched += 1;
+            }
+        }
+        let relation_estimate =
+            Estimate::from_sample(population, relation_ids.len() as u64, matched);
+
+        Ok(CountEstimate {
+            nodes: node_estimate,
+            ways: way_estimate,
+            relations: relation_estimate,
+        })
+    }
+}
@@ -0,0 +1,251 @@
+use std::str::FromStr;
+
+/// A tag filter expression, parsed from a string with [FromStr] (`"...".parse()`). The CLI
+/// commands accept them with --filter.
+///
+/// An expression is made of tag tests, combined with `and`, `or` and `not` (in order of
+/// increasing precedence) and grouped with parentheses. A test is a key (the element has
+/// the key), `KEY=VALUE` (the element has the tag), `KEY=VALUE1,VALUE2` (any of the
+/// values), or `KEY!=VALUE` (the element doesn't have the tag). Keys and values which
+/// contain spaces, parentheses or the characters `=!,` can be double-quoted.
+///
+/// For example: `highway=primary,secondary and not (access=no or area=yes)`.
+#[derive(Debug, Clone)]
+pub enum Filter {
+    Has(String),
+    Equals(String, Vec<String>),
+    Not(Box<Filter>),
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+}
+
+/// Error returned when a [Filter] expression can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterError(String);
+
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid filter expression: {}", self.0)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl Filter {
+    /// Whether an element with the given tags matches the filter.
+    pub fn matches(&self, tags: &[(&str, &str)]) -> bool {
+        match self {
+            Filter::Has(key) => tags.iter().any(|(k, _)| k == key),
+            Filter::Equals(key, values) => tags
+                .iter()
+                .any(|(k, v)| k == key && values.iter().any(|value| value == v)),
+            Filter::Not(filter) => !filter.matches(tags),
+            Filter::And(a, b) => a.matches(tags) && b.matches(tags),
+            Filter::Or(a, b) => a.matches(tags) || b.matches(tags),
+        }
+    }
+
+    /// Whether an element without any tags matches the filter. If not, elements can be
+    /// skipped without decoding them when they are known to be untagged (such as nodes
+    /// which aren't in the Nodes table).
+    pub fn matches_untagged(&self) -> bool {
+        self.matches(&[])
+    }
+}
+
+enum Token {
+    Open,
+    Close,
+    /// A word, and whether it was (partly) quoted, in which case it isn't a keyword
+    Word(String, bool),
+}
+
+/// Split an expression into parentheses and words. Quotes are removed from words, but
+/// the characters which separate keys and values are kept (escaped with a backslash if
+/// they were quoted).
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = expr.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' {
+                        break;
+                    }
+                    chars.next();
+                    if c != '"' {
+                        word.push(c);
+                        continue;
+                    }
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some(c @ ('=' | '!' | ',' | '\\')) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            Some(c) => word.push(c),
+                            None => return Err(format!("unterminated quote in {:?}", expr)),
+                        }
+                    }
+                }
+                tokens.push(Token::Word(word, quoted));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Split a word at unescaped occurrences of a separator, keeping the escapes
+fn split_unescaped<'a>(word: &'a str, separator: &str) -> Vec<&'a str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut chars = word.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if word[i..].starts_with(separator) {
+            parts.push(&word[start..i]);
+            start = i + separator.len();
+            // skip the rest of the separator
+            for _ in 1..separator.chars().count() {
+                chars.next();
+            }
+        }
+    }
+    parts.push(&word[start..]);
+    parts
+}
+
+fn unescape(word: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.extend(chars.next()),
+            c => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn parse_test(word: &str) -> Result<Filter, String> {
+    let (negated, parts) = match split_unescaped(word, "!=") {
+        parts if parts.len() == 2 => (true, parts),
+        _ => (false, split_unescaped(word, "=")),
+    };
+    let (key, values) = match parts.as_slice() {
+        [key] => (unescape(key), None),
+        [key, values] => (unescape(key), Some(values)),
+        _ => return Err(format!("invalid tag test {:?}", unescape(word))),
+    };
+    if key.is_empty() {
+        return Err(format!("tag test {:?} has no key", unescape(word)));
+    }
+    let filter = match values {
+        None => Filter::Has(key),
+        Some(values) => Filter::Equals(
+            key,
+            split_unescaped(values, ",")
+                .into_iter()
+                .map(unescape)
+                .collect(),
+        ),
+    };
+    Ok(if negated {
+        Filter::Not(Box::new(filter))
+    } else {
+        filter
+    })
+}
+
+/// A recursive descent parser over the tokens of an expression
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.get(self.position), Some(Token::Word(word, false)) if word == keyword)
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filter = self.and()?;
+        while self.keyword("or") {
+            self.position += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.not()?;
+        while self.keyword("and") {
+            self.position += 1;
+            filter = Filter::And(Box::new(filter), Box::new(self.not()?));
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<Filter, String> {
+        if self.keyword("not") {
+            self.position += 1;
+            return Ok(Filter::Not(Box::new(self.not()?)));
+        }
+        let token = self.tokens.get(self.position);
+        self.position += 1;
+        match token {
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Close) => {
+                        self.position += 1;
+                        Ok(filter)
+                    }
+                    _ => Err("missing closing parenthesis".to_string()),
+                }
+            }
+            Some(Token::Word(word, quoted)) => match word.as_str() {
+                "and" | "or" if !quoted => Err(format!("unexpected {:?}", word)),
+                _ => parse_test(word),
+            },
+            Some(Token::Close) => Err("unexpected closing parenthesis".to_string()),
+            None => Err("expression ends too soon".to_string()),
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(expr: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(expr).map_err(FilterError)?,
+            position: 0,
+        };
+        let filter = parser.or().map_err(FilterError)?;
+        let unexpected = match parser.tokens.get(parser.position) {
+            None => return Ok(filter),
+            Some(Token::Open) => "unexpected opening parenthesis".to_string(),
+            Some(Token::Close) => "unexpected closing parenthesis".to_string(),
+            Some(Token::Word(word, _)) => format!("unexpected {:?} (missing and/or?)", word),
+        };
+        Err(FilterError(unexpected))
+    }
+}
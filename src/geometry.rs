@@ -0,0 +1,379 @@
+//! The packed coordinate arrays stored in the optional `geometries` table, which holds
+//! the geometry of each way and of each multipolygon relation, so that they can be read
+//! without looking up the location of every node.
+//!
+//! Keys are element references (see [crate::encode_element_ref]). A value is a list of
+//! parts: a way has one part (its nodes' coordinates, in order), and a multipolygon has
+//! one part per ring. Coordinates are (longitude, latitude) pairs in units of 1e-7
+//! degrees. The value is a u32 count of parts, followed by each part as a u32 count of
+//! points and then the points' coordinates as i32s, all little-endian.
+
+use std::fmt;
+
+#[cfg(feature = "geo")]
+use crate::database::Transaction;
+use crate::database::{Locations, Ways};
+use crate::types::{ElementId, Relation};
+use crate::Error;
+
+/// The parts of a geometry, each a list of (longitude, latitude) pairs in degrees, as
+/// returned by [crate::Transaction::geometry]
+pub type Geometry = Vec<Vec<(f64, f64)>>;
+
+/// Error returned when an element's geometry can't be resolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeometryError {
+    /// The node with this ID is missing from the Locations table, as happens to the
+    /// nodes outside the region of an extract which ways crossing its edge refer to.
+    MissingNode(u64),
+}
+
+impl fmt::Display for GeometryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeometryError::MissingNode(id) => write!(f, "node {} has no location", id),
+        }
+    }
+}
+
+impl std::error::Error for GeometryError {}
+
+/// What to do about the nodes of a way which are missing from the Locations table when
+/// resolving its coordinates with [crate::Way::coordinates].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingNodePolicy {
+    /// Return [GeometryError::MissingNode] for the first missing node.
+    #[default]
+    Error,
+    /// Leave the missing nodes out, joining the nodes on either side of each gap with a
+    /// straight line.
+    Skip,
+}
+
+/// Join ways (given as lists of node IDs, or of coordinates) end to end into closed
+/// rings. Returns None if they don't form closed rings, for example because some ways
+/// are missing.
+pub fn assemble_rings<T: PartialEq + Copy>(mut ways: Vec<Vec<T>>) -> Option<Vec<Vec<T>>> {
+    ways.retain(|way| way.len() >= 2);
+
+    let mut rings = vec![];
+    while let Some(mut ring) = ways.pop() {
+        while ring.first() != ring.last() {
+            let end = *ring.last()?;
+            let index = ways
+                .iter()
+                .position(|way| way.first() == Some(&end) || way.last() == Some(&end))?;
+            let mut next = ways.swap_remove(index);
+            if next.first() != Some(&end) {
+                next.reverse();
+            }
+            ring.extend(next.into_iter().skip(1));
+        }
+        rings.push(ring);
+    }
+    Some(rings)
+}
+
+/// Simplify a linestring or ring of (longitude, latitude) points with the
+/// Douglas–Peucker algorithm, leaving out the points which are within `tolerance`
+/// (in degrees, treated as planar coordinates) of the line through the points kept on
+/// either side of them. The first and last points are always kept, so rings stay
+/// closed, but may be left with fewer than four points.
+pub fn simplify(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    // ranges of points whose ends are kept, and whose middle points are still to check
+    let mut ranges = vec![(0, points.len() - 1)];
+    while let Some((start, end)) = ranges.pop() {
+        let (farthest, distance) = (start + 1..end)
+            .map(|i| (i, segment_distance(points[i], points[start], points[end])))
+            .fold(
+                (start, 0.0),
+                |max, next| if next.1 > max.1 { next } else { max },
+            );
+        if distance > tolerance {
+            keep[farthest] = true;
+            ranges.push((start, farthest));
+            ranges.push((farthest, end));
+        }
+    }
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(point, _)| *point)
+        .collect()
+}
+
+/// The distance from a point to the segment between two others
+fn segment_distance((x, y): (f64, f64), (x1, y1): (f64, f64), (x2, y2): (f64, f64)) -> f64 {
+    let (dx, dy) = (x2 - x1, y2 - y1);
+    let length = dx * dx + dy * dy;
+    // the closest point of the segment, as a fraction of the way along it
+    let t = if length == 0.0 {
+        0.0
+    } else {
+        (((x - x1) * dx + (y - y1) * dy) / length).clamp(0.0, 1.0)
+    };
+    let (px, py) = (x1 + t * dx, y1 + t * dy);
+    ((x - px).powi(2) + (y - py).powi(2)).sqrt()
+}
+
+/// Look up the (longitude, latitude) coordinates of nodes. Returns None if any of them
+/// has no location.
+pub(crate) fn node_coordinates(
+    locations: &Locations,
+    nodes: impl IntoIterator<Item = u64>,
+) -> Result<Option<Vec<(f64, f64)>>, Error> {
+    let mut coordinates = vec![];
+    for node in nodes {
+        match locations.get(node)? {
+            Some(loc) => coordinates.push((loc.lon(), loc.lat())),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(coordinates))
+}
+
+/// Assemble the outer and inner ways of a multipolygon relation into rings of
+/// coordinates. Returns None if the rings aren't complete.
+pub(crate) fn relation_rings(
+    relation: &Relation,
+    ways: &Ways,
+    locations: &Locations,
+) -> Result<Option<Geometry>, Error> {
+    let mut member_ways = vec![];
+    for member in relation.members() {
+        if let (ElementId::Way(id), "outer" | "inner" | "") = (member.id(), member.role()) {
+            let Some(way) = ways.get(id)? else {
+                return Ok(None);
+            };
+            member_ways.push(way.nodes().collect());
+        }
+    }
+
+    let Some(rings) = assemble_rings(member_ways) else {
+        return Ok(None);
+    };
+    let mut geometry = Vec::with_capacity(rings.len());
+    for ring in rings {
+        let Some(ring) = node_coordinates(locations, ring)? else {
+            return Ok(None);
+        };
+        geometry.push(ring);
+    }
+    Ok(Some(geometry))
+}
+
+/// Encode the parts of a geometry as a value of the `geometries` table.
+pub fn encode_geometry(parts: &[Vec<(i32, i32)>]) -> Vec<u8> {
+    let points: usize = parts.iter().map(Vec::len).sum();
+    let mut buf = Vec::with_capacity(4 + parts.len() * 4 + points * 8);
+    buf.extend((parts.len() as u32).to_le_bytes());
+    for part in parts {
+        buf.extend((part.len() as u32).to_le_bytes());
+        for (lon, lat) in part {
+            buf.extend(lon.to_le_bytes());
+            buf.extend(lat.to_le_bytes());
+        }
+    }
+    buf
+}
+
+/// Decode a value of the `geometries` table into its parts. Returns None if the value
+/// is malformed.
+pub fn decode_geometry(raw: &[u8]) -> Option<Vec<Vec<(i32, i32)>>> {
+    if !raw.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut words = raw.chunks(4).map(|word| word.try_into().unwrap());
+    let mut next = || -> Option<[u8; 4]> { words.next() };
+
+    let count = u32::from_le_bytes(next()?);
+    let mut parts = Vec::with_capacity(count.min(1024) as usize);
+    for _ in 0..count {
+        let len = u32::from_le_bytes(next()?);
+        let mut part = Vec::with_capacity(len.min(1 << 16) as usize);
+        for _ in 0..len {
+            let lon = i32::from_le_bytes(next()?);
+            let lat = i32::from_le_bytes(next()?);
+            part.push((lon, lat));
+        }
+        parts.push(part);
+    }
+    if next().is_some() {
+        return None;
+    }
+    Some(parts)
+}
+
+/// A ring of (longitude, latitude) pairs in degrees
+#[cfg(feature = "geo")]
+type Ring = Vec<(f64, f64)>;
+
+/// Twice the signed area of a ring of (longitude, latitude) pairs: positive if it's
+/// counterclockwise.
+#[cfg(feature = "geo")]
+fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    ring.windows(2)
+        .map(|edge| edge[0].0 * edge[1].1 - edge[1].0 * edge[0].1)
+        .sum()
+}
+
+/// Whether a point is inside a ring, treating coordinates as planar.
+#[cfg(feature = "geo")]
+fn ring_contains(ring: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+    let mut inside = false;
+    for edge in ring.windows(2) {
+        let (a, b) = (edge[0], edge[1]);
+        if (a.1 > y) != (b.1 > y) && x < (b.0 - a.0) * (y - a.1) / (b.1 - a.1) + a.0 {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Group outer and inner rings into polygons. Each inner ring becomes a hole in the
+/// smallest outer ring containing it, and inner rings outside every outer ring are
+/// dropped. Outer rings are oriented counterclockwise and holes clockwise.
+#[cfg(feature = "geo")]
+fn group_rings(outer: Vec<Ring>, inner: Vec<Ring>) -> geo_types::MultiPolygon {
+    let area = |ring: &Ring| signed_area(ring).abs();
+    let mut polygons: Vec<(Ring, Vec<Ring>)> = outer
+        .into_iter()
+        .map(|mut ring| {
+            if signed_area(&ring) < 0.0 {
+                ring.reverse();
+            }
+            (ring, vec![])
+        })
+        .collect();
+    for mut ring in inner {
+        let Some(&point) = ring.first() else {
+            continue;
+        };
+        let Some(polygon) = polygons
+            .iter_mut()
+            .filter(|(outer, _)| ring_contains(outer, point))
+            .min_by(|(a, _), (b, _)| area(a).total_cmp(&area(b)))
+        else {
+            continue;
+        };
+        if signed_area(&ring) > 0.0 {
+            ring.reverse();
+        }
+        polygon.1.push(ring);
+    }
+
+    polygons
+        .into_iter()
+        .map(|(outer, holes)| {
+            geo_types::Polygon::new(
+                geo_types::LineString::from(outer),
+                holes.into_iter().map(geo_types::LineString::from).collect(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(feature = "geo")]
+impl Transaction<'_> {
+    /// Get the geometry of a way as a [geo_types::LineString] of (longitude, latitude)
+    /// coordinates in degrees. Returns None if the way isn't found, and
+    /// [GeometryError::MissingNode] if one of its nodes has no location.
+    pub fn way_geometry(&self, id: u64) -> Result<Option<geo_types::LineString>, Error> {
+        let Some(way) = self.ways()?.get(id)? else {
+            return Ok(None);
+        };
+        let coordinates = way.coordinates(&self.locations()?, MissingNodePolicy::Error)?;
+        Ok(Some(geo_types::LineString::from(coordinates)))
+    }
+
+    /// Get the geometry of a multipolygon or boundary relation as a
+    /// [geo_types::MultiPolygon] of (longitude, latitude) coordinates in degrees. Its
+    /// member ways are joined end to end into rings, and the inner rings become holes in
+    /// the outer rings containing them. A ring is inner if its ways have the inner role;
+    /// if it has ways without a role, or the ways with each role don't form closed rings
+    /// by themselves, it's inner if it's inside an odd number of the other rings. Returns
+    /// None if the relation isn't found, if it's
+    /// some other kind of relation, or if its rings aren't complete (because member ways
+    /// or their nodes are missing, or the ways don't join up).
+    pub fn relation_geometry(&self, id: u64) -> Result<Option<geo_types::MultiPolygon>, Error> {
+        let Some(relation) = self.relations()?.get(id)? else {
+            return Ok(None);
+        };
+        if !matches!(relation.tag("type"), Some("multipolygon" | "boundary")) {
+            return Ok(None);
+        }
+        let ways = self.ways()?;
+        let locations = self.locations()?;
+
+        // the member ways with the outer, inner and empty roles
+        let mut role_ways: [Vec<Vec<u64>>; 3] = Default::default();
+        for member in relation.members() {
+            let ElementId::Way(way_id) = member.id() else {
+                continue;
+            };
+            let index = match member.role() {
+                "outer" => 0,
+                "inner" => 1,
+                "" => 2,
+                _ => continue,
+            };
+            let Some(way) = ways.get(way_id)? else {
+                return Ok(None);
+            };
+            role_ways[index].push(way.nodes().collect());
+        }
+
+        // rings and whether they're inner, if that's known from their role
+        let mut rings: Vec<(Vec<u64>, Option<bool>)> = vec![];
+        let assembled: Option<Vec<_>> = role_ways.iter().cloned().map(assemble_rings).collect();
+        match assembled {
+            Some(assembled) => {
+                for (ring_set, is_inner) in
+                    assembled.into_iter().zip([Some(false), Some(true), None])
+                {
+                    rings.extend(ring_set.into_iter().map(|ring| (ring, is_inner)));
+                }
+            }
+            None => {
+                let Some(all) = assemble_rings(role_ways.concat()) else {
+                    return Ok(None);
+                };
+                rings.extend(all.into_iter().map(|ring| (ring, None)));
+            }
+        }
+
+        let mut resolved = Vec::with_capacity(rings.len());
+        for (ring, is_inner) in rings {
+            let Some(ring) = node_coordinates(&locations, ring)? else {
+                return Ok(None);
+            };
+            resolved.push((ring, is_inner));
+        }
+
+        let (mut outer, mut inner) = (vec![], vec![]);
+        for (i, (ring, is_inner)) in resolved.iter().enumerate() {
+            let is_inner = is_inner.unwrap_or_else(|| {
+                resolved
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, (other, _))| i != j && ring_contains(other, ring[0]))
+                    .count()
+                    % 2
+                    == 1
+            });
+            if is_inner {
+                inner.push(ring.clone());
+            } else {
+                outer.push(ring.clone());
+            }
+        }
+        Ok(Some(group_rings(outer, inner)))
+    }
+}
@@ -0,0 +1,105 @@
+//! Constants and helpers describing the file layout used by the reference
+//! [OSMExpress](https://github.com/protomaps/OSMExpress) implementation (written in C++).
+//! Databases which only use these tables, key encodings and metadata keys (and none of
+//! the optional format extensions, such as packed encoding or string interning) can be
+//! read and updated by either implementation.
+
+/// Name of the table which stores database-wide key/value metadata.
+pub const METADATA_TABLE: &str = "metadata";
+
+/// Names of the tables which store OSM element data, keyed by element ID.
+pub const ELEMENT_TABLES: &[&str] = &["locations", "nodes", "ways", "relations"];
+
+/// Names of the index tables, which map IDs (or S2 cell IDs) to multiple element IDs.
+pub const INDEX_TABLES: &[&str] = &[
+    "cell_node",
+    "node_way",
+    "node_relation",
+    "way_relation",
+    "relation_relation",
+];
+
+/// Size in bytes of a value in the locations table: longitude and latitude as i32
+/// (in units of 1e-7 degrees) followed by the node's version as a u32.
+pub const LOCATION_VALUE_SIZE: usize = 12;
+
+/// Metadata key for the timestamp of the replication state the data is current to,
+/// stored as an ISO 8601 string (e.g. `2024-08-13T00:00:00Z`).
+pub const REPLICATION_TIMESTAMP_KEY: &str = "osmosis_replication_timestamp";
+
+/// Metadata key for the replication sequence number the data is current to, stored as a
+/// decimal string.
+pub const REPLICATION_SEQUENCE_NUMBER_KEY: &str = "osmosis_replication_sequence_number";
+
+/// Metadata key for the base URL of the replication server, stored as a string.
+pub const REPLICATION_BASE_URL_KEY: &str = "osmosis_replication_base_url";
+
+/// Metadata key for the name of the file that the database was created from.
+pub const IMPORT_FILENAME_KEY: &str = "import_filename";
+
+/// The UTC date (year, month and day) of a Unix timestamp in seconds.
+pub(crate) fn civil_date(timestamp: i64) -> (i64, i64, i64) {
+    let days = timestamp.div_euclid(86400);
+
+    // convert days since the epoch to a civil date (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Format a Unix timestamp (in seconds) as an ISO 8601 UTC string, as used by the
+/// replication timestamp metadata key.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let secs = timestamp.rem_euclid(86400);
+    let (year, month, day) = civil_date(timestamp);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Parse an ISO 8601 UTC timestamp of the form written by [format_timestamp] (with
+/// optional fractional seconds) into a Unix timestamp in seconds.
+pub fn parse_timestamp(s: &str) -> Option<i64> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+
+    let mut date_parts = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let year = date_parts.next()??;
+    let month = date_parts.next()??;
+    let day = date_parts.next()??;
+
+    let time = time.split('.').next()?;
+    let mut time_parts = time.splitn(3, ':').map(|p| p.parse::<i64>().ok());
+    let hour = time_parts.next()??;
+    let minute = time_parts.next()??;
+    let second = time_parts.next()??;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // convert a civil date to days since the epoch (Howard Hinnant's algorithm)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
@@ -0,0 +1,119 @@
+use crate::types::ElementId;
+use crate::Error;
+
+/// The byte order of the integer keys (and index table values) in a database.
+///
+/// All tables are created with LMDB's `INTEGER_KEY` (and, for index tables,
+/// `INTEGER_DUP`) flags, which make LMDB compare keys as native unsigned integers. Keys
+/// must therefore be stored in the byte order of the host which writes them, which is
+/// also what the reference C++ implementation does. Since LMDB's own file format is
+/// native-endian too, a database can only be used on hosts with the same byte order as
+/// the one that created it; this is recorded in the metadata table under
+/// [KeyByteOrder::METADATA_KEY] so that a mismatch is reported by [crate::Database::open]
+/// rather than producing garbage IDs. Databases without that key were written in the
+/// byte order of the host that created them, so it is assumed to be [KeyByteOrder::NATIVE].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyByteOrder {
+    Little,
+    Big,
+}
+
+impl KeyByteOrder {
+    /// The metadata table key under which the key byte order is recorded.
+    pub const METADATA_KEY: &'static str = "key_byte_order";
+
+    /// The byte order of the current host.
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: Self = Self::Little;
+    /// The byte order of the current host.
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: Self = Self::Big;
+
+    /// The name of this byte order, as stored in the metadata table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Little => "little",
+            Self::Big => "big",
+        }
+    }
+
+    /// Parse a byte order name as stored in the metadata table.
+    pub fn from_bytes(raw: &[u8]) -> Result<Self, Error> {
+        match raw {
+            b"little" => Ok(Self::Little),
+            b"big" => Ok(Self::Big),
+            _ => Err(format!("unknown key byte order {:?}", String::from_utf8_lossy(raw)).into()),
+        }
+    }
+}
+
+/// Encode an element ID (or S2 cell ID) as a key or index table value.
+pub fn encode_key(id: u64) -> [u8; 8] {
+    id.to_ne_bytes()
+}
+
+/// Decode a key or index table value into an element ID (or S2 cell ID). Returns None
+/// if the value has the wrong length.
+pub fn decode_key(raw: &[u8]) -> Option<u64> {
+    raw.try_into().ok().map(u64::from_ne_bytes)
+}
+
+/// Encode the ID of an interned string as a key in the `strings` table.
+pub fn encode_string_id(id: u32) -> [u8; 4] {
+    id.to_ne_bytes()
+}
+
+/// Decode a key in the `strings` table into the ID of an interned string. Returns None
+/// if the key has the wrong length.
+pub fn decode_string_id(raw: &[u8]) -> Option<u32> {
+    raw.try_into().ok().map(u32::from_ne_bytes)
+}
+
+/// Encode an element ID and version as a key in one of the history tables. Unlike the
+/// other tables, history tables use LMDB's default (bytewise) key comparison, so the ID
+/// and version are stored big-endian to sort versions of the same element together,
+/// in version order. The version is widened to 8 bytes so that values stay 8-byte
+/// aligned, as Cap'n Proto messages must be to be read in place.
+pub fn encode_version_key(id: u64, version: u32) -> [u8; 16] {
+    let mut key = [0; 16];
+    key[0..8].copy_from_slice(&id.to_be_bytes());
+    key[8..16].copy_from_slice(&(version as u64).to_be_bytes());
+    key
+}
+
+/// Decode a key in one of the history tables into an element ID and version. Returns
+/// None if the key has the wrong length.
+pub fn decode_version_key(raw: &[u8]) -> Option<(u64, u32)> {
+    if raw.len() != 16 {
+        return None;
+    }
+    let id = u64::from_be_bytes(raw[0..8].try_into().unwrap());
+    let version = u64::from_be_bytes(raw[8..16].try_into().unwrap());
+    Some((id, version as u32))
+}
+
+/// Encode a reference to an element as a value in the `uid_element` or
+/// `changeset_element` index tables. The
+/// element type is stored in the low two bits, and the ID in the remaining bits.
+pub fn encode_element_ref(element: &ElementId) -> [u8; 8] {
+    let value = match *element {
+        ElementId::Node(id) => id << 2,
+        ElementId::Way(id) => id << 2 | 1,
+        ElementId::Relation(id) => id << 2 | 2,
+    };
+    encode_key(value)
+}
+
+/// Decode a value in the `uid_element` or `changeset_element` index tables into a
+/// reference to an element.
+/// Returns None if the value has the wrong length or an unknown element type.
+pub fn decode_element_ref(raw: &[u8]) -> Option<ElementId> {
+    let value = decode_key(raw)?;
+    let id = value >> 2;
+    match value & 3 {
+        0 => Some(ElementId::Node(id)),
+        1 => Some(ElementId::Way(id)),
+        2 => Some(ElementId::Relation(id)),
+        _ => None,
+    }
+}
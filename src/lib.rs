@@ -1,13 +1,114 @@
-#[macro_use]
-extern crate lazy_static;
-
+#[cfg(feature = "spatial")]
+mod boundaries;
+#[cfg(feature = "import")]
+pub mod build;
+mod cache;
+#[cfg(feature = "spatial")]
+mod coastline;
+mod compression;
 mod database;
+#[cfg(feature = "spatial")]
+pub mod edits;
+mod error;
+#[cfg(feature = "spatial")]
+mod estimate;
+mod filter;
+#[cfg(feature = "export")]
+pub mod geometry;
+pub mod interop;
+mod keys;
+mod lock;
+mod metrics;
+mod multi;
+#[cfg(feature = "spatial")]
+mod nearest;
+mod overlay;
+#[cfg(feature = "export")]
+pub mod pbf;
+#[cfg(feature = "import")]
+mod plugins;
+mod pool;
+mod progress;
+#[cfg(feature = "spatial")]
+mod query;
+mod restrictions;
+#[cfg(feature = "spatial")]
+mod spatial_join;
+#[cfg(feature = "spatial")]
+pub mod stats;
+pub mod streets;
+pub mod tags;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod trace;
+mod traversal;
 mod types;
+mod validation;
+#[cfg(feature = "export")]
+pub mod wkt;
 
-pub mod messages_capnp {
-    // TODO should not be pub
+mod messages_capnp {
     include!(concat!(env!("OUT_DIR"), "/messages_capnp.rs"));
 }
 
-pub use database::{Database, Locations, Nodes, Relations, Transaction, Ways, CELL_INDEX_LEVEL};
-pub use types::{Location, Node, Region, Relation, RelationMember, Way};
+#[cfg(all(feature = "spatial", feature = "export"))]
+pub use boundaries::Boundary;
+pub use cache::{CacheStats, CachedLocations};
+#[cfg(feature = "spatial")]
+pub use coastline::{Coastline, CoastlineProblem, LandPolygon};
+pub use compression::{Dictionary, ValueCompression};
+#[cfg(feature = "spatial")]
+pub use database::SpatialIndexTable;
+pub use database::{
+    Changesets, Database, DecodeMode, Durability, ElementCounts, ExtensionTable, FormatError,
+    JoinTable, Locations, Nodes, OpenOptions, Page, Prefetch, PrefetchedBatch,
+    ReaderSlotsExhausted, Relations, Transaction, UnsupportedFormatVersion, ValueEncoding, Ways,
+    BOUNDS_KEY, CELL_INDEX_LEVEL, COUNTS_KEY, DEFAULT_MAX_TABLES, EXTENSION_TABLE_PREFIX,
+    EXTRACT_SOURCE_KEY, FORMAT_VERSION, FORMAT_VERSION_KEY, INDEX_PLUGINS_KEY, MIN_FORMAT_VERSION,
+    SOURCE_SHA256_KEY, TAG_INDEX_KEYS_KEY, USER_METADATA_PREFIX, WRITER_LOCK_KEY,
+};
+#[cfg(feature = "update")]
+pub use database::{ElementChange, ElementState, VersionConflict, WriteTransaction};
+pub use error::Error;
+#[cfg(feature = "spatial")]
+pub use estimate::{CountEstimate, Estimate};
+pub use filter::{Filter, FilterError};
+pub use keys::{
+    decode_element_ref, decode_key, decode_string_id, decode_version_key, encode_element_ref,
+    encode_key, encode_string_id, encode_version_key, KeyByteOrder,
+};
+#[cfg(feature = "update")]
+pub use lock::WriterGuard;
+pub use lock::WriterLock;
+pub use metrics::{Commit, MetricsSink};
+pub use multi::{MultiDatabase, MultiTransaction};
+#[cfg(feature = "spatial")]
+pub use nearest::NearestWay;
+pub use overlay::{OverlayDatabase, OverlayTransaction};
+#[cfg(feature = "import")]
+pub use plugins::{IndexPlugin, PluginElement, PluginRegistry, PluginTable};
+pub use pool::{PooledTransaction, TransactionPool};
+pub use progress::ProgressSink;
+#[cfg(feature = "spatial")]
+pub use query::{QueryPlan, QueryStrategy};
+pub use restrictions::{
+    InvalidTurnRestriction, RestrictionKind, RestrictionVia, TurnRestriction, TurnRestrictions,
+};
+#[cfg(feature = "spatial")]
+pub use spatial_join::SpatialJoin;
+pub use streets::StreetCompletion;
+pub use traversal::{ClosureStrategy, ElementIds, MAX_RELATION_DEPTH};
+#[cfg(feature = "spatial")]
+pub use types::Region;
+pub use types::{
+    decode_tag_set, encode_tag_set, tag_set_hash, BoundingBox, Changeset, Decode, DecodeBuffer,
+    DecodeContext, Element, ElementId, Location, Metadata, Node, Reborrow, Relation,
+    RelationMember, StringTable, TagSetTable, Way, WayGeometry, WithMetadata,
+    DEFAULT_READER_OPTIONS,
+};
+pub use validation::{
+    GeometryProblem, InvalidMember, InvalidRelationMembers, MemberProblem, MissingNodeRefs,
+    NestingProblem, Validator, WayGeometryProblems,
+};
+
+pub use capnp::message::ReaderOptions;
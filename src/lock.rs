@@ -0,0 +1,132 @@
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "update")]
+use crate::database::Database;
+use crate::interop;
+#[cfg(feature = "update")]
+use crate::Error;
+
+/// A record of the process which is writing to a database, stored in the metadata table
+/// under [crate::WRITER_LOCK_KEY] for as long as it writes. The lock is advisory: it
+/// keeps writers which check for it (like [Database::try_exclusive_writer] and the osmx
+/// commands which write to databases) from interleaving their changes, and tells users
+/// who is writing, but doesn't stop other writes.
+///
+/// Stored as a UTF-8 string of the fields separated by tabs: the process ID, hostname,
+/// start time (in seconds since the Unix epoch) and operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriterLock {
+    pub pid: u32,
+    pub hostname: String,
+    /// What the writer is doing, e.g. the name of the command.
+    pub operation: String,
+    /// When the lock was taken, in seconds since the Unix epoch.
+    pub since: i64,
+}
+
+/// The name of this host, or "unknown" if it can't be determined.
+fn hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+impl WriterLock {
+    /// A lock held by the current process.
+    pub fn for_current_process(operation: &str) -> Self {
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        Self {
+            pid: std::process::id(),
+            hostname: hostname(),
+            operation: operation.replace(['\t', '\n'], " "),
+            since,
+        }
+    }
+
+    /// Whether the lock is held by the current process.
+    pub fn is_held_by_current_process(&self) -> bool {
+        self.pid == std::process::id() && self.hostname == hostname()
+    }
+
+    /// Whether the lock was left behind by a process on this host which has exited
+    /// (for example because it crashed). Always false on systems without /proc, and for
+    /// locks held by other hosts, since whether their processes are running can't be
+    /// checked.
+    pub fn is_stale(&self) -> bool {
+        self.hostname == hostname()
+            && Path::new("/proc/self").exists()
+            && !Path::new(&format!("/proc/{}", self.pid)).exists()
+    }
+
+    pub fn from_bytes(raw: &[u8]) -> Option<Self> {
+        let s = std::str::from_utf8(raw).ok()?;
+        let mut fields = s.splitn(4, '\t');
+        Some(Self {
+            pid: fields.next()?.parse().ok()?,
+            hostname: fields.next()?.to_string(),
+            since: fields.next()?.parse().ok()?,
+            operation: fields.next()?.to_string(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format!(
+            "{}\t{}\t{}\t{}",
+            self.pid, self.hostname, self.since, self.operation
+        )
+        .into_bytes()
+    }
+}
+
+impl fmt::Display for WriterLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (process {} on {}, since {})",
+            self.operation,
+            self.pid,
+            self.hostname,
+            interop::format_timestamp(self.since)
+        )
+    }
+}
+
+/// The writer lock of a database, held by the current process until this is dropped.
+/// Returned by [Database::try_exclusive_writer].
+#[cfg(feature = "update")]
+pub struct WriterGuard<'db> {
+    pub(crate) db: &'db Database,
+    pub(crate) lock: WriterLock,
+}
+
+#[cfg(feature = "update")]
+impl WriterGuard<'_> {
+    /// The record stored in the metadata table.
+    pub fn lock(&self) -> &WriterLock {
+        &self.lock
+    }
+
+    /// Release the lock, returning an error if it can't be removed from the metadata
+    /// table. Dropping the guard releases it too, but ignores errors.
+    pub fn release(self) -> Result<(), Error> {
+        let result = self.db.release_writer_lock(&self.lock);
+        std::mem::forget(self);
+        result
+    }
+}
+
+#[cfg(feature = "update")]
+impl Drop for WriterGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.db.release_writer_lock(&self.lock);
+    }
+}
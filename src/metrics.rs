@@ -0,0 +1,79 @@
+use std::time::Duration;
+#[cfg(feature = "spatial")]
+use std::time::Instant;
+
+/// Receives measurements of how a [crate::Database] is used, so that applications which
+/// serve queries or apply updates can export them for monitoring (e.g. as Prometheus
+/// metrics). Register one with [crate::Database::set_metrics].
+///
+/// The methods are called on the threads doing the work, often while a transaction is
+/// open, so implementations should be cheap (e.g. updating atomic counters). They all do
+/// nothing by default. Values which aren't events, such as how far the database lags
+/// behind replication or the size of its file, can be read when the metrics are exported
+/// (with [crate::Transaction::replication_timestamp] and the file's metadata).
+pub trait MetricsSink: Send + Sync {
+    /// A read transaction was begun.
+    fn transaction_began(&self) {}
+
+    /// A read transaction ended, after being open for `duration`.
+    fn transaction_ended(&self, _duration: Duration) {}
+
+    /// A query of the named spatial index (such as "cell_node") finished, having found
+    /// `count` element IDs in `duration`. Queries which return an iterator finish when it
+    /// is exhausted or dropped.
+    fn spatial_query(&self, _index: &str, _count: u64, _duration: Duration) {}
+
+    /// A [crate::WriteTransaction] was committed.
+    fn write_committed(&self, _commit: &Commit) {}
+}
+
+/// A committed write transaction, as reported to [MetricsSink::write_committed]
+pub struct Commit<'a> {
+    /// The number of elements written or deleted
+    pub changes: u64,
+    /// How long the transaction was open, including the commit
+    pub duration: Duration,
+    /// The replication timestamp and sequence number recorded in the transaction (see
+    /// [crate::WriteTransaction::set_replication_state]), if it recorded them
+    pub replication_state: Option<(&'a str, u64)>,
+}
+
+/// Measures a spatial query, which may happen lazily as its iterator is consumed, and
+/// reports it to a MetricsSink when dropped
+#[cfg(feature = "spatial")]
+pub(crate) struct QueryTimer<'a> {
+    sink: &'a dyn MetricsSink,
+    index: &'static str,
+    start: Instant,
+    count: u64,
+}
+
+#[cfg(feature = "spatial")]
+impl<'a> QueryTimer<'a> {
+    pub(crate) fn new(sink: &'a dyn MetricsSink, index: &'static str) -> Self {
+        Self {
+            sink,
+            index,
+            start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    pub(crate) fn yielded(&mut self) {
+        self.count += 1;
+    }
+
+    /// Set the number of IDs found, for queries which collect them rather than yielding
+    /// them one by one
+    pub(crate) fn found(&mut self, count: u64) {
+        self.count = count;
+    }
+}
+
+#[cfg(feature = "spatial")]
+impl Drop for QueryTimer<'_> {
+    fn drop(&mut self) {
+        self.sink
+            .spatial_query(self.index, self.count, self.start.elapsed());
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::database::{Database, JoinTable, Transaction};
+#[cfg(feature = "spatial")]
+use crate::types::Region;
+use crate::types::{Location, Node, Relation, Way};
+use crate::Error;
+
+/// Several OSMX databases (for example, extracts of neighbouring regions) which are
+/// queried as one dataset. Elements near the edges of extracts are often in more than
+/// one of them; lookups by ID return the copy from the first database which has the
+/// element, and queries which return IDs merge the results of all the databases.
+pub struct MultiDatabase {
+    databases: Vec<Database>,
+}
+
+impl MultiDatabase {
+    /// Open each of the given paths as an OSMX Database.
+    pub fn open<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> Result<Self, Error> {
+        let databases = paths
+            .into_iter()
+            .map(Database::open)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::new(databases))
+    }
+
+    /// Combine Databases which have already been opened. Lookups by ID probe them in the
+    /// given order.
+    pub fn new(databases: Vec<Database>) -> Self {
+        Self { databases }
+    }
+
+    /// Get the underlying Databases, in the order they are probed.
+    pub fn databases(&self) -> &[Database] {
+        &self.databases
+    }
+}
+
+/// A read transaction on each of the databases in a [MultiDatabase]. Each transaction
+/// sees a consistent snapshot of its own database, but the snapshots of different
+/// databases aren't coordinated.
+pub struct MultiTransaction<'db> {
+    txns: Vec<Transaction<'db>>,
+}
+
+impl<'db> MultiTransaction<'db> {
+    /// Begin a Transaction on each of the MultiDatabase's databases.
+    pub fn begin(db: &'db MultiDatabase) -> Result<Self, Error> {
+        let txns = db
+            .databases
+            .iter()
+            .map(Transaction::begin)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { txns })
+    }
+
+    /// Get the underlying Transactions, for queries which aren't provided here.
+    pub fn transactions(&self) -> &[Transaction<'db>] {
+        &self.txns
+    }
+
+    /// Get a node's location from the first database which has the node.
+    pub fn location(&self, id: u64) -> Result<Option<Location<'_>>, Error> {
+        for txn in self.txns.iter() {
+            if let Some(location) = txn.locations()?.get(id)? {
+                return Ok(Some(location));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get a node from the first database which has it. Like [crate::Nodes], this only
+    /// finds nodes with tags or metadata; see [Self::location].
+    pub fn node(&self, id: u64) -> Result<Option<Node<'_>>, Error> {
+        for txn in self.txns.iter() {
+            if let Some(node) = txn.nodes()?.get(id)? {
+                return Ok(Some(node));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get a way from the first database which has it.
+    pub fn way(&self, id: u64) -> Result<Option<Way<'_>>, Error> {
+        for txn in self.txns.iter() {
+            if let Some(way) = txn.ways()?.get(id)? {
+                return Ok(Some(way));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get a relation from the first database which has it.
+    pub fn relation(&self, id: u64) -> Result<Option<Relation<'_>>, Error> {
+        for txn in self.txns.iter() {
+            if let Some(relation) = txn.relations()?.get(id)? {
+                return Ok(Some(relation));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the IDs of the ways which contain a node, in any of the databases.
+    pub fn node_ways(&self, id: u64) -> Result<Vec<u64>, Error> {
+        self.related(id, Transaction::node_ways)
+    }
+
+    /// Get the IDs of the relations which have a node as a member, in any of the databases.
+    pub fn node_relations(&self, id: u64) -> Result<Vec<u64>, Error> {
+        self.related(id, Transaction::node_relations)
+    }
+
+    /// Get the IDs of the relations which have a way as a member, in any of the databases.
+    pub fn way_relations(&self, id: u64) -> Result<Vec<u64>, Error> {
+        self.related(id, Transaction::way_relations)
+    }
+
+    /// Get the IDs of the relations which have a relation as a member, in any of the
+    /// databases.
+    pub fn relation_relations(&self, id: u64) -> Result<Vec<u64>, Error> {
+        self.related(id, Transaction::relation_relations)
+    }
+
+    /// Get the IDs of the nodes which may fall within a region, in any of the databases,
+    /// in ascending order. As with the cell_nodes table's `find_in_region`, there may be
+    /// false positives near the edge of the region.
+    #[cfg(feature = "spatial")]
+    pub fn find_nodes_in_region(&self, region: &Region) -> Result<Vec<u64>, Error> {
+        let mut ids = BTreeSet::new();
+        for txn in self.txns.iter() {
+            ids.extend(txn.cell_nodes()?.find_in_region(region));
+        }
+        Ok(ids.into_iter().collect())
+    }
+
+    /// Look up an ID in the same join table of every database, and merge the results
+    fn related<'a>(
+        &'a self,
+        id: u64,
+        table: impl Fn(&'a Transaction<'db>) -> Result<JoinTable<'a>, Error>,
+    ) -> Result<Vec<u64>, Error> {
+        let mut ids = BTreeSet::new();
+        for txn in self.txns.iter() {
+            ids.extend(table(txn)?.get(id));
+        }
+        Ok(ids.into_iter().collect())
+    }
+}
@@ -0,0 +1,118 @@
+use roaring::RoaringTreemap;
+
+use crate::database::Transaction;
+use crate::types::Region;
+use crate::Error;
+
+/// Mean radius of the Earth in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Radius in meters of the first area searched around the point, which is doubled until
+/// a way is found
+const INITIAL_SEARCH_RADIUS: f64 = 50.0;
+
+/// The closest named way to a point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NearestWay {
+    pub way_id: u64,
+    /// The way's name tag.
+    pub name: String,
+    /// Distance in meters from the point to the closest segment of the way.
+    pub distance: f64,
+}
+
+/// Distance from the origin to the segment (a, b), in the same units as the coordinates
+fn segment_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (-(a.0 * dx + a.1 * dy) / length_squared).clamp(0.0, 1.0)
+    };
+    (a.0 + t * dx).hypot(a.1 + t * dy)
+}
+
+impl Transaction<'_> {
+    /// Find the closest way with a name tag to the given point, within max_distance meters
+    /// of it, e.g. to tell which street a location is on. Returns None if there is no named
+    /// way that close.
+    ///
+    /// The area searched starts small and is doubled in size until a way is found within
+    /// it. A way is only found if one of its nodes is within the area, so a long segment
+    /// passing near the point may be missed in favor of a farther way with a nearby node.
+    /// Distances are computed in a flat projection centered on the point, which is
+    /// accurate for distances of up to a few kilometers.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub fn nearest_named_way(
+        &self,
+        lon: f64,
+        lat: f64,
+        max_distance: f64,
+    ) -> Result<Option<NearestWay>, Error> {
+        let cell_nodes = self.cell_nodes()?;
+        let node_ways = self.node_ways()?;
+        let locations = self.locations()?;
+        let ways = self.ways()?;
+
+        // meters per degree of latitude and longitude near the point
+        let meters_per_lat = EARTH_RADIUS.to_radians();
+        let meters_per_lon = meters_per_lat * lat.to_radians().cos().max(1e-6);
+        let project =
+            |lon2: f64, lat2: f64| ((lon2 - lon) * meters_per_lon, (lat2 - lat) * meters_per_lat);
+
+        let mut seen = RoaringTreemap::new();
+        let mut nearest: Option<NearestWay> = None;
+        let mut radius = INITIAL_SEARCH_RADIUS.min(max_distance);
+        loop {
+            let (dlon, dlat) = (radius / meters_per_lon, radius / meters_per_lat);
+            let region = Region::from_bbox(lon - dlon, lat - dlat, lon + dlon, lat + dlat);
+
+            for node_id in cell_nodes.find_in_region(&region) {
+                for way_id in node_ways.get(node_id) {
+                    if !seen.insert(way_id) {
+                        continue;
+                    }
+                    let Some(way) = ways.get(way_id)? else {
+                        continue;
+                    };
+                    let Some(name) = way.tag("name") else {
+                        continue;
+                    };
+
+                    // segments with a node missing from the locations table are skipped
+                    let mut points: Vec<Option<(f64, f64)>> = vec![];
+                    for node in way.nodes() {
+                        points.push(
+                            locations
+                                .get(node)?
+                                .map(|loc| project(loc.lon(), loc.lat())),
+                        );
+                    }
+                    let distance = points
+                        .windows(2)
+                        .filter_map(|pair| Some(segment_distance(pair[0]?, pair[1]?)))
+                        .chain(points.iter().flatten().map(|p| p.0.hypot(p.1)))
+                        .fold(f64::INFINITY, f64::min);
+
+                    if distance <= max_distance
+                        && nearest.as_ref().is_none_or(|n| distance < n.distance)
+                    {
+                        nearest = Some(NearestWay {
+                            way_id,
+                            name: name.to_string(),
+                            distance,
+                        });
+                    }
+                }
+            }
+
+            // ways outside the area are farther than the radius, so can't be closer than a
+            // way found within it
+            if nearest.as_ref().is_some_and(|n| n.distance <= radius) || radius >= max_distance {
+                return Ok(nearest);
+            }
+            radius = (radius * 2.0).min(max_distance);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use crate::database::{Database, JoinTable, Transaction};
+#[cfg(feature = "spatial")]
+use crate::types::Region;
+use crate::types::{ElementId, Location, Node, Relation, Way};
+use crate::Error;
+
+/// A large, read-only base database with a small "edits" database laid over it. Reads
+/// consult the edits first and fall back to the base, so local changes can be made (and
+/// thrown away) without copying the base file.
+///
+/// The edits database is an ordinary OSMX database holding the new versions of the
+/// elements which were created or modified. Elements which were deleted are recorded in
+/// its `tombstones` table, whose keys are element references (encoded as in the
+/// `uid_element` index) and whose values are empty.
+pub struct OverlayDatabase {
+    base: Database,
+    edits: Database,
+}
+
+impl OverlayDatabase {
+    /// Open the base and edits databases at the given paths.
+    pub fn open(base: impl AsRef<Path>, edits: impl AsRef<Path>) -> Result<Self, Error> {
+        Ok(Self::new(Database::open(base)?, Database::open(edits)?))
+    }
+
+    /// Overlay Databases which have already been opened.
+    pub fn new(base: Database, edits: Database) -> Self {
+        Self { base, edits }
+    }
+
+    /// Get the base Database.
+    pub fn base(&self) -> &Database {
+        &self.base
+    }
+
+    /// Get the edits Database.
+    pub fn edits(&self) -> &Database {
+        &self.edits
+    }
+}
+
+/// A read transaction on both databases of an [OverlayDatabase].
+pub struct OverlayTransaction<'db> {
+    base: Transaction<'db>,
+    edits: Transaction<'db>,
+}
+
+impl<'db> OverlayTransaction<'db> {
+    /// Begin Transactions on the OverlayDatabase's base and edits databases.
+    pub fn begin(db: &'db OverlayDatabase) -> Result<Self, Error> {
+        Ok(Self {
+            base: Transaction::begin(&db.base)?,
+            edits: Transaction::begin(&db.edits)?,
+        })
+    }
+
+    /// Get the Transaction on the base database, which doesn't reflect the edits.
+    pub fn base(&self) -> &Transaction<'db> {
+        &self.base
+    }
+
+    /// Get the Transaction on the edits database.
+    pub fn edits(&self) -> &Transaction<'db> {
+        &self.edits
+    }
+
+    /// Returns whether the edits delete or replace the base database's version of the
+    /// given element.
+    pub fn is_edited(&self, element: &ElementId) -> Result<bool, Error> {
+        if self.edits.is_deleted(element)? {
+            return Ok(true);
+        }
+        match *element {
+            // every node has a location, but only some have a value in the nodes table
+            ElementId::Node(id) => self.edits.locations()?.contains(id),
+            ElementId::Way(id) => self.edits.ways()?.contains(id),
+            ElementId::Relation(id) => self.edits.relations()?.contains(id),
+        }
+    }
+
+    /// Get a node's location, or None if the node doesn't exist or was deleted.
+    pub fn location(&self, id: u64) -> Result<Option<Location<'_>>, Error> {
+        if self.edits.is_deleted(&ElementId::Node(id))? {
+            return Ok(None);
+        }
+        match self.edits.locations()?.get(id)? {
+            Some(location) => Ok(Some(location)),
+            None => Ok(self.base.locations()?.get(id)?),
+        }
+    }
+
+    /// Get a node, or None if the node doesn't exist or was deleted. Like
+    /// [crate::Nodes], this only finds nodes with tags or metadata.
+    pub fn node(&self, id: u64) -> Result<Option<Node<'_>>, Error> {
+        // an edited node without tags hides the base version, which may have had tags
+        if self.is_edited(&ElementId::Node(id))? {
+            return self.edits.nodes()?.get(id);
+        }
+        self.base.nodes()?.get(id)
+    }
+
+    /// Get a way, or None if the way doesn't exist or was deleted.
+    pub fn way(&self, id: u64) -> Result<Option<Way<'_>>, Error> {
+        if self.is_edited(&ElementId::Way(id))? {
+            return self.edits.ways()?.get(id);
+        }
+        self.base.ways()?.get(id)
+    }
+
+    /// Get a relation, or None if the relation doesn't exist or was deleted.
+    pub fn relation(&self, id: u64) -> Result<Option<Relation<'_>>, Error> {
+        if self.is_edited(&ElementId::Relation(id))? {
+            return self.edits.relations()?.get(id);
+        }
+        self.base.relations()?.get(id)
+    }
+
+    /// Get the IDs of the ways which contain a node, in ascending order.
+    pub fn node_ways(&self, id: u64) -> Result<Vec<u64>, Error> {
+        self.related(id, Transaction::node_ways, ElementId::Way)
+    }
+
+    /// Get the IDs of the relations which have a node as a member, in ascending order.
+    pub fn node_relations(&self, id: u64) -> Result<Vec<u64>, Error> {
+        self.related(id, Transaction::node_relations, ElementId::Relation)
+    }
+
+    /// Get the IDs of the relations which have a way as a member, in ascending order.
+    pub fn way_relations(&self, id: u64) -> Result<Vec<u64>, Error> {
+        self.related(id, Transaction::way_relations, ElementId::Relation)
+    }
+
+    /// Get the IDs of the relations which have a relation as a member, in ascending
+    /// order.
+    pub fn relation_relations(&self, id: u64) -> Result<Vec<u64>, Error> {
+        self.related(id, Transaction::relation_relations, ElementId::Relation)
+    }
+
+    /// Get the IDs of the nodes which may fall within a region, in ascending order. As
+    /// with the cell_nodes table's `find_in_region`, there may be false positives near
+    /// the edge of the region.
+    #[cfg(feature = "spatial")]
+    pub fn find_nodes_in_region(&self, region: &Region) -> Result<Vec<u64>, Error> {
+        let mut ids: BTreeSet<u64> = self.edits.cell_nodes()?.find_in_region(region).collect();
+        // base nodes which were edited are in the edits' index if they're still in the
+        // region, so they are only taken from there
+        for id in self.base.cell_nodes()?.find_in_region(region) {
+            if !self.is_edited(&ElementId::Node(id))? {
+                ids.insert(id);
+            }
+        }
+        Ok(ids.into_iter().collect())
+    }
+
+    /// Look up an ID in the same join table of both databases. The base database's
+    /// parents are left out if they were edited, since the edits' index covers them.
+    fn related<'a>(
+        &'a self,
+        id: u64,
+        table: impl Fn(&'a Transaction<'db>) -> Result<JoinTable<'a>, Error>,
+        parent: fn(u64) -> ElementId,
+    ) -> Result<Vec<u64>, Error> {
+        let mut ids: BTreeSet<u64> = table(&self.edits)?.get(id).collect();
+        for parent_id in table(&self.base)?.get(id) {
+            if !self.is_edited(&parent(parent_id))? {
+                ids.insert(parent_id);
+            }
+        }
+        Ok(ids.into_iter().collect())
+    }
+}
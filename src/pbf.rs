@@ -0,0 +1,480 @@
+//! A writer for the OSM PBF format (`.osm.pbf`), for exporting the contents of a
+//! database to files which other tools can read. Nodes are written as dense nodes, and
+//! blocks are zlib-compressed. Elements must be written sorted by type (nodes, then ways,
+//! then relations) and then by ID, as the files declare (`Sort.Type_then_ID`).
+//!
+//! The protobuf messages are encoded by hand, since only a few fields of the format's
+//! schema are written.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::database::{ElementCounts, Transaction};
+use crate::interop;
+use crate::traversal::ElementIds;
+use crate::types::{BoundingBox, ElementId, Location, Node, Relation, Way, WithMetadata};
+use crate::Error;
+
+/// The most elements written to one block. Readers are only required to accept blocks
+/// of up to 32 MiB; this is what osmium writes, and keeps them well under that.
+const MAX_BLOCK_ELEMENTS: usize = 8000;
+
+/// The size of the way or relation messages in a block at which it's written early,
+/// for blocks of elements with many tags, nodes or members
+const MAX_BLOCK_BYTES: usize = 16 * 1024 * 1024;
+
+/// The contents of a file's header block
+#[derive(Debug, Clone, Default)]
+pub struct PbfHeader {
+    /// The bounds of the data, as west, south, east and north in degrees
+    pub bbox: Option<BoundingBox>,
+    /// Seconds since the Unix epoch
+    pub replication_timestamp: Option<i64>,
+    pub replication_sequence_number: Option<u64>,
+    pub replication_base_url: Option<String>,
+    /// The name of the program which wrote the file
+    pub writing_program: Option<String>,
+}
+
+impl PbfHeader {
+    /// The header of a file exported from a database: its bounds and replication state.
+    pub fn from_transaction(txn: &Transaction) -> Result<Self, Error> {
+        Ok(Self {
+            bbox: txn.bounds()?,
+            replication_timestamp: txn
+                .replication_timestamp()?
+                .and_then(interop::parse_timestamp),
+            replication_sequence_number: txn.replication_sequence_number()?,
+            replication_base_url: txn.replication_base_url()?.map(str::to_string),
+            writing_program: None,
+        })
+    }
+}
+
+/// Append a varint
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Append a field with an unsigned (or non-negative) integer value
+fn put_uint(buf: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(buf, (field as u64) << 3);
+    put_varint(buf, value);
+}
+
+/// Append a field with a signed (zigzag-encoded) integer value
+fn put_sint(buf: &mut Vec<u8>, field: u32, value: i64) {
+    put_uint(buf, field, zigzag(value));
+}
+
+/// Append a length-delimited field: bytes, a string, an embedded message or a packed
+/// list
+fn put_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_varint(buf, ((field as u64) << 3) | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Append a packed list of varints. Empty lists are left out.
+fn put_packed(buf: &mut Vec<u8>, field: u32, values: impl IntoIterator<Item = u64>) {
+    let mut packed = vec![];
+    for value in values {
+        put_varint(&mut packed, value);
+    }
+    if !packed.is_empty() {
+        put_bytes(buf, field, &packed);
+    }
+}
+
+/// Append a packed list of signed integers, each stored as the difference from the
+/// previous one
+fn put_delta_packed(buf: &mut Vec<u8>, field: u32, values: impl IntoIterator<Item = i64>) {
+    let mut last = 0;
+    put_packed(
+        buf,
+        field,
+        values.into_iter().map(|value| {
+            let delta = value.wrapping_sub(last);
+            last = value;
+            zigzag(delta)
+        }),
+    );
+}
+
+/// Write a blob (a header or data block, zlib-compressed) with its blob header
+fn write_blob(out: &mut impl Write, blob_type: &str, data: &[u8]) -> Result<(), Error> {
+    let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+    encoder.write_all(data)?;
+    let compressed = encoder.finish()?;
+
+    let mut blob = vec![];
+    put_uint(&mut blob, 2, data.len() as u64); // raw_size
+    put_bytes(&mut blob, 3, &compressed); // zlib_data
+    let mut header = vec![];
+    put_bytes(&mut header, 1, blob_type.as_bytes()); // type
+    put_uint(&mut header, 3, blob.len() as u64); // datasize
+
+    out.write_all(&(header.len() as u32).to_be_bytes())?;
+    out.write_all(&header)?;
+    out.write_all(&blob)?;
+    Ok(())
+}
+
+/// The metadata of an element, as written to a file
+#[derive(Default)]
+struct Info {
+    version: u32,
+    timestamp: i64,
+    changeset: i64,
+    uid: i32,
+    user_sid: u32,
+}
+
+/// The elements of a block which hasn't been written yet
+#[derive(Default)]
+struct Block {
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    elements: usize,
+    // dense nodes, by field
+    ids: Vec<i64>,
+    lats: Vec<i64>,
+    lons: Vec<i64>,
+    keys_vals: Vec<u64>,
+    infos: Vec<Option<Info>>,
+    // encoded Way or Relation messages, each with its field key and length
+    group: Vec<u8>,
+}
+
+impl Block {
+    fn new() -> Self {
+        let mut block = Self::default();
+        // string 0 is reserved, as the delimiter of dense node tags
+        block.string_id("");
+        block
+    }
+
+    fn string_id(&mut self, s: &str) -> u32 {
+        if let Some(&id) = self.string_ids.get(s) {
+            return id;
+        }
+        let id = self.strings.len() as u32;
+        self.strings.push(s.to_string());
+        self.string_ids.insert(s.to_string(), id);
+        id
+    }
+
+    fn info(&mut self, element: &impl WithMetadata) -> Option<Info> {
+        let metadata = element.metadata()?;
+        Some(Info {
+            version: metadata.version(),
+            timestamp: metadata.timestamp() as i64,
+            changeset: metadata.changeset() as i64,
+            uid: metadata.uid() as i32,
+            user_sid: self.string_id(metadata.user()),
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        self.elements >= MAX_BLOCK_ELEMENTS || self.group.len() >= MAX_BLOCK_BYTES
+    }
+
+    /// Encode the block as a PrimitiveBlock message
+    fn encode(&self) -> Vec<u8> {
+        let mut group = vec![];
+        if !self.ids.is_empty() {
+            let mut dense = vec![];
+            put_delta_packed(&mut dense, 1, self.ids.iter().copied());
+            if self.infos.iter().any(Option::is_some) {
+                // every node needs an entry, so those without metadata get zeros
+                let default = Info::default();
+                let infos: Vec<&Info> = self
+                    .infos
+                    .iter()
+                    .map(|info| info.as_ref().unwrap_or(&default))
+                    .collect();
+                let mut dense_info = vec![];
+                put_packed(&mut dense_info, 1, infos.iter().map(|i| i.version as u64));
+                put_delta_packed(&mut dense_info, 2, infos.iter().map(|i| i.timestamp));
+                put_delta_packed(&mut dense_info, 3, infos.iter().map(|i| i.changeset));
+                put_delta_packed(&mut dense_info, 4, infos.iter().map(|i| i.uid as i64));
+                put_delta_packed(&mut dense_info, 5, infos.iter().map(|i| i.user_sid as i64));
+                put_bytes(&mut dense, 5, &dense_info);
+            }
+            put_delta_packed(&mut dense, 8, self.lats.iter().copied());
+            put_delta_packed(&mut dense, 9, self.lons.iter().copied());
+            put_packed(&mut dense, 10, self.keys_vals.iter().copied());
+            put_bytes(&mut group, 2, &dense);
+        } else {
+            group.extend_from_slice(&self.group);
+        }
+
+        let mut string_table = vec![];
+        for s in self.strings.iter() {
+            put_bytes(&mut string_table, 1, s.as_bytes());
+        }
+        let mut block = vec![];
+        put_bytes(&mut block, 1, &string_table);
+        put_bytes(&mut block, 2, &group);
+        block
+    }
+}
+
+/// Encode an Info message
+fn encode_info(info: &Info) -> Vec<u8> {
+    let mut buf = vec![];
+    put_uint(&mut buf, 1, info.version as u64);
+    put_uint(&mut buf, 2, info.timestamp as u64);
+    put_uint(&mut buf, 3, info.changeset as u64);
+    put_uint(&mut buf, 4, info.uid as u64);
+    put_uint(&mut buf, 5, info.user_sid as u64);
+    buf
+}
+
+/// Writes elements to an OSM PBF file. Call [Self::finish] after writing the last one,
+/// to write the last block.
+pub struct PbfWriter<W: Write> {
+    out: W,
+    block: Block,
+    // the type (0 for nodes, 1 for ways and 2 for relations) and ID of the last element
+    last: Option<(u8, u64)>,
+}
+
+impl<W: Write> PbfWriter<W> {
+    /// Begin a file, writing its header block.
+    pub fn new(mut out: W, header: &PbfHeader) -> Result<Self, Error> {
+        let mut block = vec![];
+        if let Some((west, south, east, north)) = header.bbox {
+            let nano = |degrees: f64| (degrees * 1e9).round() as i64;
+            let mut bbox = vec![];
+            put_sint(&mut bbox, 1, nano(west));
+            put_sint(&mut bbox, 2, nano(east));
+            put_sint(&mut bbox, 3, nano(north));
+            put_sint(&mut bbox, 4, nano(south));
+            put_bytes(&mut block, 1, &bbox);
+        }
+        put_bytes(&mut block, 4, b"OsmSchema-V0.6");
+        put_bytes(&mut block, 4, b"DenseNodes");
+        put_bytes(&mut block, 5, b"Sort.Type_then_ID");
+        if let Some(program) = &header.writing_program {
+            put_bytes(&mut block, 16, program.as_bytes());
+        }
+        if let Some(timestamp) = header.replication_timestamp {
+            put_uint(&mut block, 32, timestamp as u64);
+        }
+        if let Some(sequence_number) = header.replication_sequence_number {
+            put_uint(&mut block, 33, sequence_number);
+        }
+        if let Some(base_url) = &header.replication_base_url {
+            put_bytes(&mut block, 34, base_url.as_bytes());
+        }
+        write_blob(&mut out, "OSMHeader", &block)?;
+
+        Ok(Self {
+            out,
+            block: Block::new(),
+            last: None,
+        })
+    }
+
+    /// Write a node, given its location and its value in the nodes table (if it has one).
+    pub fn write_node(
+        &mut self,
+        id: u64,
+        location: &Location,
+        node: Option<&Node>,
+    ) -> Result<(), Error> {
+        self.begin_element(0, id)?;
+        let block = &mut self.block;
+        block.ids.push(id as i64);
+        block.lats.push((location.lat() * 1e7).round() as i64);
+        block.lons.push((location.lon() * 1e7).round() as i64);
+        let info = match node {
+            Some(node) => {
+                for (key, value) in node.tags() {
+                    let key = block.string_id(key) as u64;
+                    let value = block.string_id(value) as u64;
+                    block.keys_vals.extend([key, value]);
+                }
+                block.info(node)
+            }
+            None => None,
+        };
+        block.keys_vals.push(0);
+        block.infos.push(info.or_else(|| {
+            location.version().map(|version| Info {
+                version,
+                ..Default::default()
+            })
+        }));
+        Ok(())
+    }
+
+    /// Write a way.
+    pub fn write_way(&mut self, id: u64, way: &Way) -> Result<(), Error> {
+        self.begin_element(1, id)?;
+        let block = &mut self.block;
+        let mut message = vec![];
+        put_uint(&mut message, 1, id);
+        let (keys, values) = tag_ids(block, way.tags());
+        put_packed(&mut message, 2, keys);
+        put_packed(&mut message, 3, values);
+        if let Some(info) = block.info(way) {
+            put_bytes(&mut message, 4, &encode_info(&info));
+        }
+        put_delta_packed(&mut message, 8, way.nodes().map(|id| id as i64));
+        put_bytes(&mut block.group, 3, &message);
+        Ok(())
+    }
+
+    /// Write a relation.
+    pub fn write_relation(&mut self, id: u64, relation: &Relation) -> Result<(), Error> {
+        self.begin_element(2, id)?;
+        let block = &mut self.block;
+        let mut message = vec![];
+        put_uint(&mut message, 1, id);
+        let (keys, values) = tag_ids(block, relation.tags());
+        put_packed(&mut message, 2, keys);
+        put_packed(&mut message, 3, values);
+        if let Some(info) = block.info(relation) {
+            put_bytes(&mut message, 4, &encode_info(&info));
+        }
+        let (mut roles, mut ids, mut types) = (vec![], vec![], vec![]);
+        for member in relation.members() {
+            roles.push(block.string_id(member.role()) as u64);
+            let (member_type, member_id) = match member.id() {
+                ElementId::Node(id) => (0, id),
+                ElementId::Way(id) => (1, id),
+                ElementId::Relation(id) => (2, id),
+            };
+            types.push(member_type);
+            ids.push(member_id as i64);
+        }
+        put_packed(&mut message, 8, roles);
+        put_delta_packed(&mut message, 9, ids);
+        put_packed(&mut message, 10, types);
+        put_bytes(&mut block.group, 4, &message);
+        Ok(())
+    }
+
+    /// Write the last block, and return the output.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.flush_block()?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Check that an element comes after the last one, and start a new block if it's of
+    /// a different type or the current block is full
+    fn begin_element(&mut self, element_type: u8, id: u64) -> Result<(), Error> {
+        if let Some((last_type, last_id)) = self.last {
+            if (element_type, id) <= (last_type, last_id) {
+                return Err(
+                    "elements must be written as nodes, ways and then relations, \
+                     each in ascending order of ID"
+                        .into(),
+                );
+            }
+            if element_type != last_type || self.block.is_full() {
+                self.flush_block()?;
+            }
+        }
+        self.last = Some((element_type, id));
+        self.block.elements += 1;
+        Ok(())
+    }
+
+    fn flush_block(&mut self) -> Result<(), Error> {
+        if self.block.elements > 0 {
+            write_blob(&mut self.out, "OSMData", &self.block.encode())?;
+            self.block = Block::new();
+        }
+        Ok(())
+    }
+}
+
+/// The string IDs of an element's tag keys and values
+fn tag_ids<'a>(
+    block: &mut Block,
+    tags: impl Iterator<Item = (&'a str, &'a str)>,
+) -> (Vec<u64>, Vec<u64>) {
+    tags.map(|(key, value)| (block.string_id(key) as u64, block.string_id(value) as u64))
+        .unzip()
+}
+
+/// Write the elements of a database to a PBF file, sorted by type and ID: all of them,
+/// or only those in `elements` (such as the closure of the nodes in a region, from
+/// [Transaction::closure]). The header records the database's replication state, and its
+/// bounds if all the elements are written. Returns the output and the number of elements
+/// written.
+pub fn write_database<W: Write>(
+    txn: &Transaction,
+    out: W,
+    elements: Option<&ElementIds>,
+    writing_program: Option<&str>,
+) -> Result<(W, ElementCounts), Error> {
+    let mut header = PbfHeader::from_transaction(txn)?;
+    header.writing_program = writing_program.map(str::to_string);
+    if elements.is_some() {
+        header.bbox = None;
+    }
+    let mut writer = PbfWriter::new(out, &header)?;
+    let mut counts = ElementCounts::default();
+
+    let locations = txn.locations()?;
+    let nodes = txn.nodes()?;
+    let ways = txn.ways()?;
+    let relations = txn.relations()?;
+    match elements {
+        Some(elements) => {
+            for id in elements.nodes.iter() {
+                if let Some(location) = locations.get(id)? {
+                    writer.write_node(id, &location, nodes.get(id)?.as_ref())?;
+                    counts.nodes += 1;
+                }
+            }
+            for id in elements.ways.iter() {
+                if let Some(way) = ways.get(id)? {
+                    writer.write_way(id, &way)?;
+                    counts.ways += 1;
+                }
+            }
+            for id in elements.relations.iter() {
+                if let Some(relation) = relations.get(id)? {
+                    writer.write_relation(id, &relation)?;
+                    counts.relations += 1;
+                }
+            }
+        }
+        None => {
+            for entry in locations.try_iter() {
+                let (id, location) = entry?;
+                writer.write_node(id, &location, nodes.get(id)?.as_ref())?;
+                counts.nodes += 1;
+            }
+            for entry in ways.try_iter() {
+                let (id, way) = entry?;
+                writer.write_way(id, &way)?;
+                counts.ways += 1;
+            }
+            for entry in relations.try_iter() {
+                let (id, relation) = entry?;
+                writer.write_relation(id, &relation)?;
+                counts.relations += 1;
+            }
+        }
+    }
+
+    Ok((writer.finish()?, counts))
+}
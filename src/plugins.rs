@@ -0,0 +1,190 @@
+//! Custom indexes which are maintained alongside the element tables. Each plugin is
+//! given every element as it's written, and keeps its index in an extension table (see
+//! [crate::Database::create_extension_table]) named after it, which it writes to in the
+//! same transaction as the elements, so the index is always consistent with them. The
+//! names of a database's plugins are recorded under [crate::INDEX_PLUGINS_KEY], so that
+//! `expand --append` and [crate::WriteTransaction] keep their indexes up to date.
+
+use lmdb::Transaction;
+
+use crate::{encode_element_ref, ElementId, Error};
+
+/// An element being written, as given to an [IndexPlugin]
+pub struct PluginElement<'a> {
+    pub id: ElementId,
+    pub tags: &'a [(String, String)],
+    /// Whether an earlier version of the element may already have been indexed, so the
+    /// plugin should remove its entries before adding the new ones
+    pub replacing: bool,
+    /// Whether the element was deleted (by a [crate::WriteTransaction]), in which case it
+    /// has no tags and the plugin should only remove its entries
+    pub deleted: bool,
+}
+
+impl PluginElement<'_> {
+    /// The value of a tag, if the element has it
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// A plugin's extension table, in the transaction which is writing the elements
+pub struct PluginTable<'a, 'txn> {
+    txn: &'a mut lmdb::RwTransaction<'txn>,
+    table: lmdb::Database,
+}
+
+impl<'a, 'txn> PluginTable<'a, 'txn> {
+    /// Wrap an extension table opened in a write transaction of one's own (as `osmx
+    /// expand` does)
+    pub fn new(txn: &'a mut lmdb::RwTransaction<'txn>, table: lmdb::Database) -> Self {
+        Self { txn, table }
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<&[u8]>, Error> {
+        match self.txn.get(self.table, &key) {
+            Ok(value) => Ok(Some(value)),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.txn
+            .put(self.table, &key, &value, lmdb::WriteFlags::empty())?;
+        Ok(())
+    }
+
+    /// Remove a key, if it's in the table
+    pub fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        match self.txn.del(self.table, &key, None) {
+            Ok(()) | Err(lmdb::Error::NotFound) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// A custom index, kept in the extension table of the same name
+pub trait IndexPlugin {
+    /// The name of the plugin, which is also the name of its extension table
+    fn name(&self) -> &str;
+
+    /// Index the current version of an element, or remove a deleted one from the index
+    /// (deleted versions in the history of an input file aren't given to plugins)
+    fn index(&mut self, element: &PluginElement, table: &mut PluginTable) -> Result<(), Error>;
+}
+
+/// A function which makes a new instance of a plugin
+type MakePlugin = fn() -> Box<dyn IndexPlugin>;
+
+/// The index plugins which can be enabled by name (as with `osmx expand --index`). It
+/// starts out with the plugins built into this crate, and applications can register
+/// their own.
+pub struct PluginRegistry {
+    plugins: Vec<(&'static str, MakePlugin)>,
+}
+
+impl PluginRegistry {
+    /// A registry of the built-in plugins: `phone`, which indexes elements by their phone
+    /// numbers
+    pub fn new() -> Self {
+        Self {
+            plugins: vec![("phone", || Box::new(PhoneIndex))],
+        }
+    }
+
+    /// Register a plugin, replacing any with the same name. `make` is called for each
+    /// transaction which runs the plugin.
+    pub fn register(&mut self, name: &'static str, make: MakePlugin) {
+        self.plugins.retain(|(n, _)| *n != name);
+        self.plugins.push((name, make));
+    }
+
+    /// The names of the registered plugins, in the order they were registered
+    pub fn names(&self) -> Vec<&'static str> {
+        self.plugins.iter().map(|(name, _)| *name).collect()
+    }
+
+    /// Make the plugin with the given name, if there is one
+    pub fn make(&self, name: &str) -> Option<Box<dyn IndexPlugin>> {
+        self.plugins
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, make)| make())
+    }
+}
+
+impl Default for PluginRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The prefix of the phone index's keys which list an element's numbers
+const PHONE_ELEMENT_PREFIX: u8 = 0xff;
+
+/// An index of the phone numbers of elements (from the phone and contact:phone tags).
+/// There's an entry for each number of each element, keyed by the number (its digits,
+/// after a + if it has one), a zero byte and the element's reference (see
+/// [encode_element_ref]), with an empty value, so the elements with a number can be
+/// found by prefix. Each element with numbers also has an entry keyed by 0xFF and its
+/// reference, listing its numbers separated by zero bytes, so they can be removed when
+/// the element is replaced.
+struct PhoneIndex;
+
+/// Normalize a phone number to its digits (after a + if it has one), or None if it has
+/// too few digits to be a phone number
+fn normalize_phone(number: &str) -> Option<String> {
+    let number = number.trim();
+    let digits: String = number.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digits.len() < 3 {
+        return None;
+    }
+    Some(match number.starts_with('+') {
+        true => format!("+{}", digits),
+        false => digits,
+    })
+}
+
+fn phone_key(number: &[u8], element_ref: &[u8]) -> Vec<u8> {
+    [number, &[0], element_ref].concat()
+}
+
+impl IndexPlugin for PhoneIndex {
+    fn name(&self) -> &str {
+        "phone"
+    }
+
+    fn index(&mut self, element: &PluginElement, table: &mut PluginTable) -> Result<(), Error> {
+        let element_ref = encode_element_ref(&element.id);
+        let element_key = [&[PHONE_ELEMENT_PREFIX], &element_ref[..]].concat();
+
+        if element.replacing {
+            if let Some(old) = table.get(&element_key)?.map(|value| value.to_vec()) {
+                for number in old.split(|&b| b == 0) {
+                    table.delete(&phone_key(number, &element_ref))?;
+                }
+                table.delete(&element_key)?;
+            }
+        }
+
+        let mut numbers: Vec<String> = ["phone", "contact:phone"]
+            .iter()
+            .filter_map(|key| element.tag(key))
+            .flat_map(|value| value.split(';'))
+            .filter_map(normalize_phone)
+            .collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+        if numbers.is_empty() {
+            return Ok(());
+        }
+        for number in numbers.iter() {
+            table.put(&phone_key(number.as_bytes(), &element_ref), &[])?;
+        }
+        table.put(&element_key, numbers.join("\0").as_bytes())
+    }
+}
@@ -0,0 +1,133 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex};
+
+use crate::database::{Database, Transaction};
+use crate::Error;
+
+/// An idle transaction handle in a [TransactionPool].
+struct Idle<'db>(lmdb::InactiveTransaction<'db>);
+
+// SAFETY: databases are opened with MDB_NOTLS, so a read transaction isn't tied to the
+// thread which began it, and an idle one is only used by the thread which checks it out.
+unsafe impl Send for Idle<'_> {}
+
+struct State<'db> {
+    idle: Vec<Idle<'db>>,
+    /// The number of transactions which are idle or checked out
+    size: usize,
+}
+
+/// A fixed set of read transactions which are reused instead of being begun for each
+/// request, for servers which embed a Database. A checked out transaction is renewed, so
+/// it sees the latest committed data; checking it back in releases its snapshot, so idle
+/// transactions don't keep old pages from being reused.
+///
+/// The pool can be shared between threads. Each transaction holds one of the LMDB
+/// environment's reader slots (126 by default; see [crate::OpenOptions::max_readers])
+/// for as long as the pool exists.
+pub struct TransactionPool<'db> {
+    db: &'db Database,
+    state: Mutex<State<'db>>,
+    available: Condvar,
+}
+
+impl<'db> TransactionPool<'db> {
+    /// Create a pool of `size` transactions on the given Database.
+    pub fn new(db: &'db Database, size: usize) -> Result<Self, Error> {
+        let idle = (0..size)
+            .map(|_| Ok(Idle(Transaction::begin(db)?.reset())))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(Self {
+            db,
+            state: Mutex::new(State { idle, size }),
+            available: Condvar::new(),
+        })
+    }
+
+    /// Check out a transaction, waiting for one to be checked in if they are all in use.
+    /// The transaction is checked back in when the returned [PooledTransaction] is
+    /// dropped.
+    pub fn checkout(&self) -> Result<PooledTransaction<'_, 'db>, Error> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(idle) = state.idle.pop() {
+                drop(state);
+                return self.renew(idle);
+            }
+            if state.size == 0 {
+                return Err("transaction pool is empty".into());
+            }
+            state = self.available.wait(state).unwrap();
+        }
+    }
+
+    /// Check out a transaction if one is idle, without waiting. Returns None if they are
+    /// all in use.
+    pub fn try_checkout(&self) -> Result<Option<PooledTransaction<'_, 'db>>, Error> {
+        let idle = self.state.lock().unwrap().idle.pop();
+        match idle {
+            Some(idle) => Ok(Some(self.renew(idle)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The number of transactions in the pool, whether idle or checked out.
+    pub fn size(&self) -> usize {
+        self.state.lock().unwrap().size
+    }
+
+    /// The number of transactions which are idle.
+    pub fn idle(&self) -> usize {
+        self.state.lock().unwrap().idle.len()
+    }
+
+    fn renew(&self, idle: Idle<'db>) -> Result<PooledTransaction<'_, 'db>, Error> {
+        match Transaction::renew(self.db, idle.0) {
+            Ok(txn) => Ok(PooledTransaction {
+                pool: self,
+                txn: Some(txn),
+            }),
+            Err(e) => {
+                // the transaction is lost, so wake any waiters in case it was the last one
+                self.state.lock().unwrap().size -= 1;
+                self.available.notify_all();
+                Err(e)
+            }
+        }
+    }
+
+    fn checkin(&self, txn: Transaction<'db>) {
+        let idle = Idle(txn.reset());
+        self.state.lock().unwrap().idle.push(idle);
+        self.available.notify_one();
+    }
+}
+
+/// A transaction checked out of a [TransactionPool], which is used like a [Transaction]
+/// and checked back in when dropped.
+pub struct PooledTransaction<'pool, 'db> {
+    pool: &'pool TransactionPool<'db>,
+    txn: Option<Transaction<'db>>,
+}
+
+impl<'db> Deref for PooledTransaction<'_, 'db> {
+    type Target = Transaction<'db>;
+
+    fn deref(&self) -> &Self::Target {
+        self.txn.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledTransaction<'_, '_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.txn.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledTransaction<'_, '_> {
+    fn drop(&mut self) {
+        if let Some(txn) = self.txn.take() {
+            self.pool.checkin(txn);
+        }
+    }
+}
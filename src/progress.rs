@@ -0,0 +1,16 @@
+/// Receives progress reports from long-running operations, such as building an index
+/// while importing a database, so that applications can display progress however they
+/// like (e.g. in a GUI, or in a server's status endpoint).
+///
+/// Operations report progress through an optional `&dyn ProgressSink`; passing None
+/// disables reporting. An operation may have several phases, which are reported one
+/// after another.
+pub trait ProgressSink {
+    /// Report that `done` items of the named phase have been processed, out of `total`
+    /// (if the total is known in advance). Called often, so implementations should be
+    /// cheap.
+    fn progress(&self, phase: &str, done: u64, total: Option<u64>);
+
+    /// Report that the named phase has finished.
+    fn finish(&self, _phase: &str) {}
+}
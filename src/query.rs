@@ -0,0 +1,275 @@
+use std::fmt;
+
+use roaring::RoaringTreemap;
+
+use crate::database::{Transaction, CELL_INDEX_LEVEL};
+use crate::filter::Filter;
+use crate::traversal::ElementIds;
+use crate::types::{ElementId, Region, Way};
+use crate::Error;
+
+/// How [Transaction::query] finds the elements which match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryStrategy {
+    /// Find the nodes in the region with the spatial index, and their ways and
+    /// relations with the join tables, then check the tags of each.
+    SpatialFirst,
+    /// Scan the element tables, checking the tags of every element, then check whether
+    /// the matches are in the region. Only possible for filters which don't match
+    /// untagged elements, since untagged nodes aren't in the Nodes table.
+    TagFirst,
+}
+
+/// The strategy chosen for a query by [Transaction::plan_query], and the estimates it
+/// was chosen from. Its Display implementation describes the plan, like EXPLAIN in SQL.
+///
+/// Costs are estimated numbers of index entries and elements read. The database's nodes
+/// are assumed to be spread evenly over its bounds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+    pub strategy: QueryStrategy,
+    /// The number of S2 cells covering the region.
+    pub cells: usize,
+    /// The estimated fraction of the database's nodes which are in the region.
+    pub region_fraction: f64,
+    /// The estimated cost of the spatial-first strategy.
+    pub spatial_cost: u64,
+    /// The estimated cost of the tag-first strategy, or None if the filter matches
+    /// untagged elements.
+    pub tag_cost: Option<u64>,
+    /// The number of entries in the Locations, Nodes, Ways and Relations tables.
+    pub table_sizes: [u64; 4],
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [locations, nodes, ways, relations] = self.table_sizes;
+        let strategy = match self.strategy {
+            QueryStrategy::SpatialFirst => "spatial-first",
+            QueryStrategy::TagFirst => "tag-first",
+        };
+        writeln!(f, "strategy: {}", strategy)?;
+        writeln!(
+            f,
+            "region: {} cells, about {:.2}% of the database's {} located nodes",
+            self.cells,
+            self.region_fraction * 100.0,
+            locations
+        )?;
+        writeln!(
+            f,
+            "spatial-first: scan cell_node for about {} nodes, then their ways and relations (cost {})",
+            (self.region_fraction * locations as f64).round(),
+            self.spatial_cost
+        )?;
+        match self.tag_cost {
+            Some(cost) => write!(
+                f,
+                "tag-first: check the tags of {} nodes, {} ways and {} relations, then the locations of the matches (cost {})",
+                nodes, ways, relations, cost
+            ),
+            None => write!(f, "tag-first: not possible, since the filter matches untagged elements"),
+        }
+    }
+}
+
+/// Whether a location is in a region, in the same way as the spatial index: if the cell
+/// which it is indexed under is in one of the region's cells.
+pub(crate) fn region_contains(region: &Region, lon: f64, lat: f64) -> bool {
+    let latlng = s2::latlng::LatLng::from_degrees(lat, lon);
+    let cell = s2::cellid::CellID::from(latlng).parent(CELL_INDEX_LEVEL);
+    region.cells.contains_cellid(&cell)
+}
+
+impl Transaction<'_> {
+    /// Choose how to run [Transaction::query], from the size of the element tables and
+    /// how much of the database's bounds the region covers. Scanning the element tables
+    /// is cheaper than using the spatial index when the region covers most of the data.
+    pub fn plan_query(&self, region: &Region, filter: &Filter) -> Result<QueryPlan, Error> {
+        let table_sizes = [
+            self.locations()?.len(),
+            self.nodes()?.len(),
+            self.ways()?.len(),
+            self.relations()?.len(),
+        ];
+        let [locations, nodes, ways, relations] = table_sizes;
+
+        let region_area: f64 = region
+            .cells
+            .0
+            .iter()
+            .map(|cell_id| s2::cell::Cell::from(cell_id).approx_area())
+            .sum();
+        let region_fraction = match self.bounds()? {
+            Some((west, south, east, north)) => {
+                let bounds_area = s2::rect::Rect::from_degrees(south, west, north, east).area();
+                if bounds_area > 0.0 {
+                    (region_area / bounds_area).min(1.0)
+                } else {
+                    1.0
+                }
+            }
+            // without bounds, assume the region is small, so the spatial index is used
+            None => 0.0,
+        };
+
+        let spatial_cost = (region_fraction * (locations + ways + relations) as f64) as u64;
+        let tag_cost = (!filter.matches_untagged()).then_some(nodes + ways + relations);
+        let strategy = match tag_cost {
+            Some(tag_cost) if tag_cost < spatial_cost => QueryStrategy::TagFirst,
+            _ => QueryStrategy::SpatialFirst,
+        };
+        Ok(QueryPlan {
+            strategy,
+            cells: region.cells.0.len(),
+            region_fraction,
+            spatial_cost,
+            tag_cost,
+            table_sizes,
+        })
+    }
+
+    /// Find the elements in a region whose tags match a filter: nodes in the region, ways
+    /// with a node in it, and relations with a node or way member in it (as found with
+    /// the spatial index, so elements just outside the region may be included).
+    ///
+    /// The strategy is chosen with [Transaction::plan_query]. With the spatial-first
+    /// strategy, tags are checked while the candidates are collected, before anything
+    /// else is decoded. When the filter doesn't match untagged elements, nodes are looked
+    /// up in the Nodes table (which only holds tagged nodes) and skipped without decoding
+    /// if they aren't in it, so a query like `amenity=cafe` over a region costs about as
+    /// much as listing its tagged nodes.
+    pub fn query(&self, region: &Region, filter: &Filter) -> Result<ElementIds, Error> {
+        let plan = self.plan_query(region, filter)?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(%plan, "query plan");
+        self.query_with_strategy(region, filter, plan.strategy)
+    }
+
+    /// Like [Transaction::query], but with the given strategy instead of the one the
+    /// planner would choose. Both strategies return the same elements. Returns an error
+    /// for the tag-first strategy if the filter matches untagged elements.
+    pub fn query_with_strategy(
+        &self,
+        region: &Region,
+        filter: &Filter,
+        strategy: QueryStrategy,
+    ) -> Result<ElementIds, Error> {
+        match strategy {
+            QueryStrategy::SpatialFirst => self.query_spatial_first(region, filter),
+            QueryStrategy::TagFirst if filter.matches_untagged() => {
+                Err("a tag-first query needs a filter which doesn't match untagged elements".into())
+            }
+            QueryStrategy::TagFirst => self.query_tag_first(region, filter),
+        }
+    }
+
+    fn query_spatial_first(&self, region: &Region, filter: &Filter) -> Result<ElementIds, Error> {
+        let nodes = self.nodes()?;
+        let ways = self.ways()?;
+        let relations = self.relations()?;
+        let matches_untagged = filter.matches_untagged();
+
+        let mut results = ElementIds::new();
+        let candidates: RoaringTreemap = self.cell_nodes()?.find_in_region(region).collect();
+        for node_id in candidates.iter() {
+            let matched = match nodes.get(node_id)? {
+                Some(node) => filter.matches(&node.tags().collect::<Vec<_>>()),
+                None => matches_untagged,
+            };
+            if matched {
+                results.nodes.insert(node_id);
+            }
+        }
+
+        // every way in the region is a candidate parent of relations, whether or not it
+        // matches
+        let node_ways = self.node_ways()?;
+        let mut way_ids = RoaringTreemap::new();
+        for node_id in candidates.iter() {
+            way_ids.extend(node_ways.get(node_id));
+        }
+        for way_id in way_ids.iter() {
+            if let Some(way) = ways.get(way_id)? {
+                if filter.matches(&way.tags().collect::<Vec<_>>()) {
+                    results.ways.insert(way_id);
+                }
+            }
+        }
+
+        let node_relations = self.node_relations()?;
+        let way_relations = self.way_relations()?;
+        let mut relation_ids = RoaringTreemap::new();
+        for node_id in candidates.iter() {
+            relation_ids.extend(node_relations.get(node_id));
+        }
+        for way_id in way_ids.iter() {
+            relation_ids.extend(way_relations.get(way_id));
+        }
+        for relation_id in relation_ids.iter() {
+            if let Some(relation) = relations.get(relation_id)? {
+                if filter.matches(&relation.tags().collect::<Vec<_>>()) {
+                    results.relations.insert(relation_id);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn query_tag_first(&self, region: &Region, filter: &Filter) -> Result<ElementIds, Error> {
+        let locations = self.locations()?;
+        let ways = self.ways()?;
+        let node_in_region = |id| -> Result<bool, Error> {
+            Ok(locations
+                .get(id)?
+                .is_some_and(|location| region_contains(region, location.lon(), location.lat())))
+        };
+        // whether any of a way's nodes is in the region
+        let way_nodes_in_region = |way: &Way| -> Result<bool, Error> {
+            for id in way.nodes() {
+                if node_in_region(id)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        };
+        let way_in_region = |id| -> Result<bool, Error> {
+            Ok(match ways.get(id)? {
+                Some(way) => way_nodes_in_region(&way)?,
+                None => false,
+            })
+        };
+
+        let mut results = ElementIds::new();
+        for entry in self.nodes()?.try_iter() {
+            let (id, node) = entry?;
+            if filter.matches(&node.tags().collect::<Vec<_>>()) && node_in_region(id)? {
+                results.nodes.insert(id);
+            }
+        }
+        for entry in ways.try_iter() {
+            let (id, way) = entry?;
+            if filter.matches(&way.tags().collect::<Vec<_>>()) && way_nodes_in_region(&way)? {
+                results.ways.insert(id);
+            }
+        }
+        for entry in self.relations()?.try_iter() {
+            let (id, relation) = entry?;
+            if !filter.matches(&relation.tags().collect::<Vec<_>>()) {
+                continue;
+            }
+            for member in relation.members() {
+                let in_region = match member.id() {
+                    ElementId::Node(node_id) => node_in_region(node_id)?,
+                    ElementId::Way(way_id) => way_in_region(way_id)?,
+                    ElementId::Relation(_) => false,
+                };
+                if in_region {
+                    results.relations.insert(id);
+                    break;
+                }
+            }
+        }
+        Ok(results)
+    }
+}
@@ -0,0 +1,262 @@
+use crate::database::{Relations, Ways};
+use crate::types::{ElementId, Relation};
+use crate::Error;
+
+/// Whether a turn restriction forbids a turn, or requires it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestrictionKind {
+    /// A `no_*` restriction: travel from the `from` way to the `to` way is forbidden.
+    Prohibitory,
+    /// An `only_*` restriction: travel from the `from` way must continue onto the `to` way.
+    Mandatory,
+}
+
+/// The `via` member(s) of a turn restriction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RestrictionVia {
+    /// The restriction applies at a single node.
+    Node(u64),
+    /// The restriction applies across a sequence of ways, in the order they're traversed.
+    Ways(Vec<u64>),
+}
+
+/// A validated turn restriction, read from a `type=restriction` relation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TurnRestriction {
+    /// ID of the relation the restriction was read from.
+    pub relation_id: u64,
+    /// The restriction tag's value, e.g. `no_left_turn` or `only_straight_on`.
+    pub restriction: String,
+    pub kind: RestrictionKind,
+    /// The transport mode the restriction applies to, for restrictions tagged
+    /// `restriction:<mode>` (e.g. `hgv`), or None if it applies to all vehicles.
+    pub mode: Option<String>,
+    /// Transport modes exempt from the restriction (from the `except` tag).
+    pub except: Vec<String>,
+    /// ID of the way the restricted turn starts from.
+    pub from: u64,
+    pub via: RestrictionVia,
+    /// ID of the way the restricted turn ends on.
+    pub to: u64,
+    /// ID of the node where the `from` way meets the via node or ways.
+    pub from_node: u64,
+    /// ID of the node where the `to` way leaves the via node or ways. The same as
+    /// `from_node` for restrictions with a via node.
+    pub to_node: u64,
+}
+
+/// The reason a `type=restriction` relation couldn't be read as a turn restriction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTurnRestriction {
+    /// ID of the relation.
+    pub relation_id: u64,
+    /// A description of the problem.
+    pub reason: String,
+}
+
+impl std::fmt::Display for InvalidTurnRestriction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid turn restriction (relation {}): {}",
+            self.relation_id, self.reason
+        )
+    }
+}
+
+impl std::error::Error for InvalidTurnRestriction {}
+
+/// Reads turn restrictions from the `type=restriction` relations in a database,
+/// resolving their members against the Ways table and checking that the ways connect
+/// as the restriction describes.
+pub struct TurnRestrictions<'txn> {
+    relations: Relations<'txn>,
+    ways: Ways<'txn>,
+}
+
+impl<'txn> TurnRestrictions<'txn> {
+    pub fn new(relations: Relations<'txn>, ways: Ways<'txn>) -> Self {
+        Self { relations, ways }
+    }
+
+    /// Read the turn restriction in a single relation. Returns Ok(None) if the relation
+    /// doesn't exist or isn't tagged `type=restriction`, and
+    /// [Error::InvalidTurnRestriction] if it isn't a valid turn restriction.
+    pub fn get(&self, relation_id: u64) -> Result<Option<TurnRestriction>, Error> {
+        let Some(relation) = self.relations.get(relation_id)? else {
+            return Ok(None);
+        };
+        if relation.tag("type") != Some("restriction") {
+            return Ok(None);
+        }
+        self.resolve(relation_id, &relation).map(Some)
+    }
+
+    /// Iterate over all the turn restrictions in the database, yielding
+    /// [Error::InvalidTurnRestriction] for each `type=restriction` relation which isn't a
+    /// valid turn restriction. This scans the whole Relations table.
+    pub fn iter(&self) -> impl Iterator<Item = Result<TurnRestriction, Error>> + '_ {
+        self.relations.try_iter().filter_map(|entry| {
+            let (id, relation) = match entry {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+            if relation.tag("type") != Some("restriction") {
+                return None;
+            }
+            Some(self.resolve(id, &relation))
+        })
+    }
+
+    fn resolve(&self, relation_id: u64, relation: &Relation) -> Result<TurnRestriction, Error> {
+        let invalid = |reason: String| {
+            Error::InvalidTurnRestriction(InvalidTurnRestriction {
+                relation_id,
+                reason,
+            })
+        };
+
+        // the restriction may be specific to a transport mode (e.g. restriction:hgv)
+        let (restriction, mode) = match relation.tag("restriction") {
+            Some(value) => (value.to_string(), None),
+            None => relation
+                .tags()
+                .find_map(|(k, v)| {
+                    let mode = k.strip_prefix("restriction:")?;
+                    (mode != "conditional").then(|| (v.to_string(), Some(mode.to_string())))
+                })
+                .ok_or_else(|| invalid("missing restriction tag".to_string()))?,
+        };
+        let kind = if restriction.starts_with("no_") {
+            RestrictionKind::Prohibitory
+        } else if restriction.starts_with("only_") {
+            RestrictionKind::Mandatory
+        } else {
+            return Err(invalid(format!("unknown restriction type {}", restriction)));
+        };
+        let except = match relation.tag("except") {
+            Some(value) => value.split(';').map(|m| m.trim().to_string()).collect(),
+            None => vec![],
+        };
+
+        let mut from = vec![];
+        let mut to = vec![];
+        let mut via_nodes = vec![];
+        let mut via_ways = vec![];
+        for member in relation.members() {
+            match (member.role(), member.id()) {
+                ("from", ElementId::Way(id)) => from.push(id),
+                ("to", ElementId::Way(id)) => to.push(id),
+                ("via", ElementId::Node(id)) => via_nodes.push(id),
+                ("via", ElementId::Way(id)) => via_ways.push(id),
+                ("from" | "to" | "via", _) => {
+                    return Err(invalid(format!(
+                        "{} member has an unsupported element type",
+                        member.role()
+                    )))
+                }
+                // other roles (e.g. location_hint) don't affect routing
+                _ => (),
+            }
+        }
+
+        let (from, to) = match (&from[..], &to[..]) {
+            (&[from], &[to]) => (from, to),
+            _ => {
+                return Err(invalid(format!(
+                    "expected one from and one to way, found {} and {}",
+                    from.len(),
+                    to.len()
+                )))
+            }
+        };
+
+        let endpoints = |id: u64| -> Result<(u64, u64), Error> {
+            let way = self
+                .ways
+                .get(id)?
+                .ok_or_else(|| invalid(format!("way {} not found", id)))?;
+            let mut nodes = way.nodes();
+            let first = nodes
+                .next()
+                .ok_or_else(|| invalid(format!("way {} has no nodes", id)))?;
+            let last = nodes.last().unwrap_or(first);
+            Ok((first, last))
+        };
+        let from_ends = endpoints(from)?;
+        let to_ends = endpoints(to)?;
+        let touches = |ends: (u64, u64), node: u64| ends.0 == node || ends.1 == node;
+
+        let (via, from_node, to_node) = match (&via_nodes[..], &via_ways[..]) {
+            (&[node], []) => {
+                if !touches(from_ends, node) {
+                    return Err(invalid(format!(
+                        "from way {} doesn't start or end at via node {}",
+                        from, node
+                    )));
+                }
+                if !touches(to_ends, node) {
+                    return Err(invalid(format!(
+                        "to way {} doesn't start or end at via node {}",
+                        to, node
+                    )));
+                }
+                (RestrictionVia::Node(node), node, node)
+            }
+            ([], [first, ..]) => {
+                // walk along the via ways in order, entering each at the node where the
+                // previous way ended and leaving at its other end
+                let first_ends = endpoints(*first)?;
+                let from_node = [from_ends.0, from_ends.1]
+                    .into_iter()
+                    .find(|&node| touches(first_ends, node))
+                    .ok_or_else(|| {
+                        invalid(format!(
+                            "from way {} isn't connected to via way {}",
+                            from, first
+                        ))
+                    })?;
+
+                let mut current = from_node;
+                for &id in via_ways.iter() {
+                    let ends = endpoints(id)?;
+                    current = if ends.0 == current {
+                        ends.1
+                    } else if ends.1 == current {
+                        ends.0
+                    } else {
+                        return Err(invalid(format!("via way {} isn't connected", id)));
+                    };
+                }
+
+                if !touches(to_ends, current) {
+                    return Err(invalid(format!(
+                        "to way {} isn't connected to the last via way",
+                        to
+                    )));
+                }
+                (RestrictionVia::Ways(via_ways), from_node, current)
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "expected one via node or at least one via way, found {} nodes and {} ways",
+                    via_nodes.len(),
+                    via_ways.len()
+                )))
+            }
+        };
+
+        Ok(TurnRestriction {
+            relation_id,
+            restriction,
+            kind,
+            mode,
+            except,
+            from,
+            via,
+            to,
+            from_node,
+            to_node,
+        })
+    }
+}
@@ -0,0 +1,149 @@
+//! Assignment of points to the polygons which contain them, such as nodes to the
+//! districts they're in, for computing statistics per area.
+
+use std::collections::HashMap;
+
+use crate::boundaries::rings_contain;
+use crate::database::Transaction;
+use crate::types::{ElementId, Region};
+use crate::Error;
+
+/// The levels of the S2 cells which polygons' coverings may contain (see
+/// `Region::from_bbox`)
+const COVERING_LEVELS: std::ops::RangeInclusive<u64> = 4..=16;
+
+/// Polygons to assign points to, indexed by the S2 cells covering them so that only the
+/// polygons near a point are tested. Polygons are identified by their index in the list
+/// they were given in. Use [SpatialJoin::join_points] for any points (such as the
+/// centroids of ways), and [Transaction::join_nodes] to find the nodes in the polygons
+/// with the spatial index.
+pub struct SpatialJoin {
+    polygons: Vec<Vec<Vec<(f64, f64)>>>,
+    bounds: Vec<(f64, f64, f64, f64)>,
+    regions: Vec<Region>,
+    /// The polygons whose coverings include each cell
+    cells: HashMap<u64, Vec<usize>>,
+}
+
+impl SpatialJoin {
+    /// Index polygons, each given as its rings of (longitude, latitude) points in degrees.
+    /// A point is in a polygon if it's inside an odd number of its rings, so holes can be
+    /// given as rings inside the exterior (in either orientation). Coordinates are treated
+    /// as planar, so polygons crossing the antimeridian aren't supported.
+    pub fn new(polygons: Vec<Vec<Vec<(f64, f64)>>>) -> Self {
+        let bounds: Vec<(f64, f64, f64, f64)> = polygons
+            .iter()
+            .map(|rings| {
+                rings.iter().flatten().fold(
+                    (f64::MAX, f64::MAX, f64::MIN, f64::MIN),
+                    |(west, south, east, north), &(lon, lat)| {
+                        (west.min(lon), south.min(lat), east.max(lon), north.max(lat))
+                    },
+                )
+            })
+            .collect();
+        let regions: Vec<Region> = bounds
+            .iter()
+            .map(|&(west, south, east, north)| Region::from_bbox(west, south, east, north))
+            .collect();
+        let mut cells: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (index, region) in regions.iter().enumerate() {
+            for cell in region.cell_ids() {
+                cells.entry(cell).or_default().push(index);
+            }
+        }
+        Self {
+            polygons,
+            bounds,
+            regions,
+            cells,
+        }
+    }
+
+    /// The number of polygons.
+    pub fn len(&self) -> usize {
+        self.polygons.len()
+    }
+
+    /// Whether there are no polygons.
+    pub fn is_empty(&self) -> bool {
+        self.polygons.is_empty()
+    }
+
+    /// The rings of the polygon with the given index.
+    pub fn polygon(&self, index: usize) -> &[Vec<(f64, f64)>] {
+        &self.polygons[index]
+    }
+
+    fn polygon_contains(&self, index: usize, (lon, lat): (f64, f64)) -> bool {
+        let (west, south, east, north) = self.bounds[index];
+        west <= lon
+            && lon <= east
+            && south <= lat
+            && lat <= north
+            && rings_contain(&self.polygons[index], (lon, lat))
+    }
+
+    /// The indexes of the polygons which contain a point, in ascending order.
+    pub fn containing(&self, lon: f64, lat: f64) -> Vec<usize> {
+        let latlng = s2::latlng::LatLng::from_degrees(lat, lon);
+        let leaf = s2::cellid::CellID::from(latlng);
+        let mut indexes: Vec<usize> = COVERING_LEVELS
+            .filter_map(|level| self.cells.get(&leaf.parent(level).0))
+            .flatten()
+            .copied()
+            .filter(|&index| self.polygon_contains(index, (lon, lat)))
+            .collect();
+        // coverings are normalized, but a point may be in a polygon's bounding box and
+        // so in more than one of its cells at different levels
+        indexes.sort_unstable();
+        indexes.dedup();
+        indexes
+    }
+
+    /// Pair each point (such as the location of a node, or the centroid of a way) with
+    /// the index of each polygon containing it. Points outside every polygon are left
+    /// out, and points in overlapping polygons are yielded once for each.
+    pub fn join_points<'a, I>(&'a self, points: I) -> impl Iterator<Item = (ElementId, usize)> + 'a
+    where
+        I: IntoIterator<Item = (ElementId, (f64, f64))>,
+        I::IntoIter: 'a,
+    {
+        points.into_iter().flat_map(move |(element, (lon, lat))| {
+            self.containing(lon, lat)
+                .into_iter()
+                .map(move |index| (element, index))
+        })
+    }
+}
+
+impl Transaction<'_> {
+    /// Find the nodes in each polygon of a spatial join, as pairs of node ID and polygon
+    /// index, polygon by polygon. Candidate nodes are found with the spatial index (in
+    /// the cells covering each polygon's bounding box), and then tested against the
+    /// polygon. Nodes in overlapping polygons are yielded once for each. Yields an error
+    /// if a node's location can't be read.
+    pub fn join_nodes<'a>(
+        &'a self,
+        join: &'a SpatialJoin,
+    ) -> Result<impl Iterator<Item = Result<(u64, usize), Error>> + 'a, Error> {
+        let cell_nodes = self.cell_nodes()?;
+        let locations = self.locations()?;
+        Ok(join
+            .regions
+            .iter()
+            .enumerate()
+            .flat_map(move |(index, region)| {
+                cell_nodes
+                    .find_in_region(region)
+                    .map(move |node_id| (node_id, index))
+            })
+            .filter_map(move |(node_id, index)| match locations.get(node_id) {
+                Ok(Some(location)) => join
+                    .polygon_contains(index, (location.lon(), location.lat()))
+                    .then_some(Ok((node_id, index))),
+                Ok(None) => None,
+                Err(e) => Some(Err(e)),
+            }))
+    }
+}
@@ -0,0 +1,276 @@
+//! Statistics about the tags used in a database, such as how many elements have each key
+//! and each key=value pair.
+//!
+//! Counts are collected in a [TagFrequencies] accumulator, which can also be filled in
+//! one element at a time, and merged with the counts of other accumulators. To count a
+//! large database in parallel, give each thread its own Transaction and a different
+//! range of IDs (see [TagStatsOptions::ids]), and merge the results.
+
+use std::collections::{BTreeSet, HashMap};
+use std::ops::Range;
+
+use crate::database::{ElementTable, Transaction};
+use crate::types::{Decode, Node, Region, Relation, Way};
+use crate::Error;
+
+/// A function which decides whether to count an element, given its tags
+pub type TagFilter<'a> = &'a dyn Fn(&[(&str, &str)]) -> bool;
+
+/// Which elements [tag_frequencies] counts.
+pub struct TagStatsOptions<'a> {
+    pub nodes: bool,
+    pub ways: bool,
+    pub relations: bool,
+    /// Only count nodes which may be in this region (see [Region]), ways with a node
+    /// in it, and relations with one of those nodes or ways as a member.
+    pub region: Option<&'a Region>,
+    /// Only count elements in this range of IDs.
+    pub ids: Option<Range<u64>>,
+    /// Only count elements whose tags this function returns true for.
+    pub filter: Option<TagFilter<'a>>,
+    /// Count key=value pairs as well as keys. Keys with many distinct values (like name)
+    /// use a lot of memory, so this can be turned off when only keys are needed.
+    pub values: bool,
+}
+
+impl Default for TagStatsOptions<'_> {
+    fn default() -> Self {
+        Self {
+            nodes: true,
+            ways: true,
+            relations: true,
+            region: None,
+            ids: None,
+            filter: None,
+            values: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct KeyFrequency {
+    count: u64,
+    values: HashMap<String, u64>,
+}
+
+/// Counts of the elements with each tag key and key=value pair.
+#[derive(Debug, Clone, Default)]
+pub struct TagFrequencies {
+    elements: u64,
+    values: bool,
+    keys: HashMap<String, KeyFrequency>,
+}
+
+impl TagFrequencies {
+    /// Make an empty accumulator, which counts key=value pairs if `values` is true.
+    pub fn new(values: bool) -> Self {
+        Self {
+            values,
+            ..Default::default()
+        }
+    }
+
+    /// Count the tags of one element.
+    pub fn add<'a>(&mut self, tags: impl IntoIterator<Item = (&'a str, &'a str)>) {
+        self.elements += 1;
+        for (key, value) in tags {
+            let frequency = match self.keys.get_mut(key) {
+                Some(frequency) => frequency,
+                None => self.keys.entry(key.to_string()).or_default(),
+            };
+            frequency.count += 1;
+            if self.values {
+                match frequency.values.get_mut(value) {
+                    Some(count) => *count += 1,
+                    None => {
+                        frequency.values.insert(value.to_string(), 1);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Add the counts of another accumulator to this one.
+    pub fn merge(&mut self, other: TagFrequencies) {
+        self.elements += other.elements;
+        for (key, other_frequency) in other.keys {
+            let frequency = self.keys.entry(key).or_default();
+            frequency.count += other_frequency.count;
+            if self.values {
+                for (value, count) in other_frequency.values {
+                    *frequency.values.entry(value).or_default() += count;
+                }
+            }
+        }
+    }
+
+    /// The number of elements counted, including elements without tags.
+    pub fn elements(&self) -> u64 {
+        self.elements
+    }
+
+    /// The number of elements with the given key.
+    pub fn key_count(&self, key: &str) -> u64 {
+        self.keys.get(key).map_or(0, |frequency| frequency.count)
+    }
+
+    /// The number of elements with the given key=value pair. Always 0 if values aren't
+    /// being counted.
+    pub fn tag_count(&self, key: &str, value: &str) -> u64 {
+        self.keys
+            .get(key)
+            .and_then(|frequency| frequency.values.get(value))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The keys and their counts, most frequent first (and in key order for equal counts).
+    pub fn keys(&self) -> Vec<(&str, u64)> {
+        let mut keys: Vec<_> = self
+            .keys
+            .iter()
+            .map(|(key, frequency)| (key.as_str(), frequency.count))
+            .collect();
+        keys.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        keys
+    }
+
+    /// The key=value pairs and their counts, most frequent first (and in key and value
+    /// order for equal counts).
+    pub fn tags(&self) -> Vec<(&str, &str, u64)> {
+        let mut tags: Vec<_> = self
+            .keys
+            .iter()
+            .flat_map(|(key, frequency)| {
+                frequency
+                    .values
+                    .iter()
+                    .map(move |(value, &count)| (key.as_str(), value.as_str(), count))
+            })
+            .collect();
+        tags.sort_unstable_by(|a, b| b.2.cmp(&a.2).then((a.0, a.1).cmp(&(b.0, b.1))));
+        tags
+    }
+}
+
+/// Count the tags of the elements selected by the options. Only nodes with tags (or
+/// metadata) are counted, since other nodes aren't stored in the Nodes table.
+pub fn tag_frequencies(
+    txn: &Transaction,
+    options: &TagStatsOptions,
+) -> Result<TagFrequencies, Error> {
+    let mut frequencies = TagFrequencies::new(options.values);
+    let mut count = |tags: &[(&str, &str)]| {
+        if options.filter.is_none_or(|filter| filter(tags)) {
+            frequencies.add(tags.iter().copied());
+        }
+    };
+
+    // with a region, the elements in it are found from the index tables, rather than by
+    // scanning the element tables
+    let (node_ids, way_ids, relation_ids) = match options.region {
+        Some(region) => {
+            let (nodes, ways, relations) =
+                region_element_ids(txn, region, options.ways, options.relations)?;
+            (Some(nodes), Some(ways), Some(relations))
+        }
+        None => (None, None, None),
+    };
+
+    let ids = options.ids.as_ref();
+    if options.nodes {
+        for_each_element(&txn.nodes()?, ids, node_ids.as_ref(), |_, node: &Node| {
+            count(&node.tags().collect::<Vec<_>>());
+            Ok(())
+        })?;
+    }
+    if options.ways {
+        for_each_element(&txn.ways()?, ids, way_ids.as_ref(), |_, way: &Way| {
+            count(&way.tags().collect::<Vec<_>>());
+            Ok(())
+        })?;
+    }
+    if options.relations {
+        let relations = txn.relations()?;
+        for_each_element(
+            &relations,
+            ids,
+            relation_ids.as_ref(),
+            |_, relation: &Relation| {
+                count(&relation.tags().collect::<Vec<_>>());
+                Ok(())
+            },
+        )?;
+    }
+    Ok(frequencies)
+}
+
+/// The IDs of nodes, ways and relations
+pub(crate) type ElementIdSets = (BTreeSet<u64>, BTreeSet<u64>, BTreeSet<u64>);
+
+/// Find the nodes which may be in a region (see [Region]), and (if asked for) the ways
+/// with a node in it and the relations with one of those nodes or ways as a member
+pub(crate) fn region_element_ids(
+    txn: &Transaction,
+    region: &Region,
+    ways: bool,
+    relations: bool,
+) -> Result<ElementIdSets, Error> {
+    let node_ids: BTreeSet<u64> = txn.cell_nodes()?.find_in_region(region).collect();
+    let mut way_ids = BTreeSet::new();
+    let mut relation_ids = BTreeSet::new();
+    if ways || relations {
+        let node_ways = txn.node_ways()?;
+        for &node_id in node_ids.iter() {
+            way_ids.extend(node_ways.get(node_id));
+        }
+    }
+    if relations {
+        let node_relations = txn.node_relations()?;
+        for &node_id in node_ids.iter() {
+            relation_ids.extend(node_relations.get(node_id));
+        }
+        let way_relations = txn.way_relations()?;
+        for &way_id in way_ids.iter() {
+            relation_ids.extend(way_relations.get(way_id));
+        }
+    }
+    Ok((node_ids, way_ids, relation_ids))
+}
+
+/// Call f with the ID of each element of a table whose ID is in the range (if any) and
+/// the set of IDs (if any), and the element
+pub(crate) fn for_each_element<'txn, E: Decode<'txn>>(
+    table: &ElementTable<'txn, E>,
+    range: Option<&Range<u64>>,
+    ids: Option<&BTreeSet<u64>>,
+    mut f: impl FnMut(u64, &E) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let in_range = |id: &u64| range.is_none_or(|range| range.contains(id));
+    match (ids, range) {
+        (Some(ids), _) => {
+            for &id in ids.iter().filter(|id| in_range(id)) {
+                if let Some(element) = table.get(id)? {
+                    f(id, &element)?;
+                }
+            }
+        }
+        // seek to each ID in the range, rather than decoding the elements before it
+        (None, Some(range)) => {
+            let mut next = table.next_id_from(range.start);
+            while let Some(id) = next.filter(in_range) {
+                if let Some(element) = table.get(id)? {
+                    f(id, &element)?;
+                }
+                next = id.checked_add(1).and_then(|id| table.next_id_from(id));
+            }
+        }
+        (None, None) => {
+            for entry in table.try_iter() {
+                let (id, element) = entry?;
+                f(id, &element)?;
+            }
+        }
+    }
+    Ok(())
+}
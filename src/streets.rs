@@ -0,0 +1,104 @@
+//! The optional `street_names` table, an index of the names of highway ways for
+//! search-as-you-type completion (see [crate::Transaction::complete_street]).
+//!
+//! Keys are the normalized name (see [normalize_street_name]), a zero byte, the name as
+//! tagged, another zero byte, and the ID of the S2 cell at [STREET_NAME_CELL_LEVEL]
+//! containing the street as a big-endian u64, so that a prefix of a normalized name is a
+//! prefix of the keys of every name which starts with it. Streets with the same name in
+//! different cells (like the Main Streets of different towns) have separate entries.
+//!
+//! Values are the longitude and latitude of a node of the first way with the name in the
+//! cell, in units of 1e-7 degrees, and the number of ways with the name in the cell, as
+//! little-endian i32, i32 and u32.
+
+/// The level of the S2 cells which street names are grouped by, whose cells are roughly
+/// 10 km across.
+pub const STREET_NAME_CELL_LEVEL: u64 = 10;
+
+/// The longest key LMDB can store with its default settings
+const MAX_KEY_SIZE: usize = 511;
+
+/// A street name which starts with the prefix given to
+/// [crate::Transaction::complete_street].
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreetCompletion {
+    /// The name, as tagged.
+    pub name: String,
+    /// The location of a node of one of the ways with the name, in degrees.
+    pub lon: f64,
+    pub lat: f64,
+    /// The number of ways with the name near that location (in the same S2 cell at
+    /// [STREET_NAME_CELL_LEVEL]).
+    pub way_count: u32,
+}
+
+/// Normalize a street name (or the prefix of one) for matching: lowercase it, and replace
+/// each run of characters other than letters and digits (like spaces and punctuation)
+/// with a single space, so that "St. Mary's Rd" matches "st mary s rd".
+pub fn normalize_street_name(name: &str) -> String {
+    let mut normalized = String::with_capacity(name.len());
+    let mut separated = false;
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            if separated && !normalized.is_empty() {
+                normalized.push(' ');
+            }
+            separated = false;
+            normalized.extend(c.to_lowercase());
+        } else {
+            separated = true;
+        }
+    }
+    normalized
+}
+
+/// Encode a key of the `street_names` table. Returns None if the name has no letters or
+/// digits, or is too long to be a key.
+pub fn encode_street_name_key(name: &str, cell: u64) -> Option<Vec<u8>> {
+    let normalized = normalize_street_name(name);
+    if normalized.is_empty() || name.contains('\0') {
+        return None;
+    }
+    let mut key = Vec::with_capacity(normalized.len() + name.len() + 10);
+    key.extend_from_slice(normalized.as_bytes());
+    key.push(0);
+    key.extend_from_slice(name.as_bytes());
+    key.push(0);
+    key.extend_from_slice(&cell.to_be_bytes());
+    (key.len() <= MAX_KEY_SIZE).then_some(key)
+}
+
+/// Decode a key of the `street_names` table into the normalized name, the name and the
+/// cell ID. Returns None if the key is malformed.
+pub fn decode_street_name_key(raw_key: &[u8]) -> Option<(&str, &str, u64)> {
+    let (names, cell) = raw_key.split_at_checked(raw_key.len().checked_sub(9)?)?;
+    let cell = u64::from_be_bytes(cell[1..].try_into().ok()?);
+    let separator = names.iter().position(|&b| b == 0)?;
+    let normalized = std::str::from_utf8(&names[..separator]).ok()?;
+    let name = std::str::from_utf8(&names[separator + 1..]).ok()?;
+    Some((normalized, name, cell))
+}
+
+/// Encode a value of the `street_names` table, from a location (in units of 1e-7
+/// degrees) and the number of ways.
+pub fn encode_street_name_value(lon: i32, lat: i32, way_count: u32) -> [u8; 12] {
+    let mut value = [0; 12];
+    value[0..4].copy_from_slice(&lon.to_le_bytes());
+    value[4..8].copy_from_slice(&lat.to_le_bytes());
+    value[8..12].copy_from_slice(&way_count.to_le_bytes());
+    value
+}
+
+/// Decode a value of the `street_names` table into a location (in units of 1e-7 degrees)
+/// and the number of ways. Returns None if the value has the wrong length.
+pub fn decode_street_name_value(raw_val: &[u8]) -> Option<(i32, i32, u32)> {
+    if raw_val.len() != 12 {
+        return None;
+    }
+    let field = |i: usize| raw_val[i * 4..(i + 1) * 4].try_into().unwrap();
+    Some((
+        i32::from_le_bytes(field(0)),
+        i32::from_le_bytes(field(1)),
+        u32::from_le_bytes(field(2)),
+    ))
+}
@@ -0,0 +1,3 @@
+//! Helpers for interpreting tag values.
+
+pub mod parse;
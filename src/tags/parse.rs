@@ -0,0 +1,139 @@
+//! Parsers for common OSM tag value syntaxes. They are lenient about whitespace and
+//! letter case, and return None for values they can't interpret rather than guessing.
+
+/// Kilometers per hour in one mile per hour
+const KMH_PER_MPH: f64 = 1.609344;
+
+/// Kilometers per hour in one knot
+const KMH_PER_KNOT: f64 = 1.852;
+
+/// A speed limit, as given by a `maxspeed` tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaxSpeed<'a> {
+    /// A numeric limit, converted to kilometers per hour.
+    Kmh(f64),
+    /// Walking pace (`walk`), as on some living streets.
+    Walk,
+    /// No speed limit (`none`).
+    Unlimited,
+    /// The limit is shown on variable message signs (`signals`).
+    Signals,
+    /// An implicit limit given by a zone code such as `DE:urban` or `GB:nsl_single`,
+    /// which depends on the country's rules.
+    Zone(&'a str),
+}
+
+/// Parse a `maxspeed` value: a number of km/h (e.g. `50`), a number with a unit (`30 mph`,
+/// `10 knots`, `50 km/h`), `walk`, `none`, `signals`, or a zone code (`DE:urban`).
+pub fn maxspeed(value: &str) -> Option<MaxSpeed<'_>> {
+    let value = value.trim();
+    match value.to_ascii_lowercase().as_str() {
+        "walk" => return Some(MaxSpeed::Walk),
+        "none" => return Some(MaxSpeed::Unlimited),
+        "signals" => return Some(MaxSpeed::Signals),
+        _ => (),
+    }
+    if let Some((country, zone)) = value.split_once(':') {
+        if country.len() == 2 && !zone.is_empty() {
+            return Some(MaxSpeed::Zone(value));
+        }
+        return None;
+    }
+
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok().filter(|n: &f64| n.is_finite())?;
+    let factor = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "km/h" | "kmh" | "kph" => 1.0,
+        "mph" => KMH_PER_MPH,
+        "knots" | "kn" => KMH_PER_KNOT,
+        _ => return None,
+    };
+    Some(MaxSpeed::Kmh(number * factor))
+}
+
+/// Whether a feature is open, as far as can be told from its `opening_hours` tag without
+/// evaluating the schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpeningHours {
+    /// Always open (`24/7`).
+    AlwaysOpen,
+    /// Permanently closed (`closed` or `off`).
+    Closed,
+    /// Open according to a schedule, such as `Mo-Fr 09:00-17:00`.
+    Schedule,
+}
+
+/// Parse an `opening_hours` value, telling whether it gives the hours at all. Returns
+/// None for empty and placeholder values such as `unknown`. Schedules are not validated.
+pub fn opening_hours(value: &str) -> Option<OpeningHours> {
+    let value = value.trim();
+    match value.to_ascii_lowercase().as_str() {
+        "" | "unknown" | "fixme" | "?" => None,
+        "24/7" => Some(OpeningHours::AlwaysOpen),
+        "closed" | "off" => Some(OpeningHours::Closed),
+        _ => Some(OpeningHours::Schedule),
+    }
+}
+
+/// Parse an integer value such as a `layer` (e.g. `-1` or `+2`). Returns None for
+/// non-integer values, including lists like `0;1`.
+pub fn integer(value: &str) -> Option<i64> {
+    let value = value.trim();
+    value.strip_prefix('+').unwrap_or(value).parse().ok()
+}
+
+/// Parse a `level` value, which may be a single level (`1`, `-1`, `0.5`), a list of
+/// levels (`0;1`), or a range of levels (`0-3`, `-2--1`). Returns the levels in the order
+/// given, with ranges expanded to the whole levels within them, or None if any part
+/// isn't a level.
+pub fn levels(value: &str) -> Option<Vec<f64>> {
+    let mut levels = vec![];
+    for part in list(value) {
+        let level = |s: &str| s.trim().parse::<f64>().ok().filter(|l| l.is_finite());
+        // a range separator is a hyphen which doesn't begin a number
+        let separator = part
+            .char_indices()
+            .skip(1)
+            .find(|&(i, c)| c == '-' && part[..i].ends_with(|c: char| c.is_ascii_digit()));
+        match separator {
+            Some((i, _)) => {
+                let (start, end) = (level(&part[..i])?, level(&part[i + 1..])?);
+                if start > end {
+                    return None;
+                }
+                levels.push(start);
+                let mut next = start.floor() + 1.0;
+                while next < end {
+                    levels.push(next);
+                    next += 1.0;
+                }
+                levels.push(end);
+            }
+            None => levels.push(level(part)?),
+        }
+    }
+    (!levels.is_empty()).then_some(levels)
+}
+
+/// Parse a boolean value: `yes`, `true` or `1` for true, and `no`, `false` or `0` for
+/// false. Other values (such as `designated` or `limited`) return None, since their
+/// meaning depends on the key.
+pub fn boolean(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "yes" | "true" | "1" => Some(true),
+        "no" | "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Split a semicolon-separated list value (e.g. `cuisine=pizza;pasta`) into its items,
+/// trimming whitespace and skipping empty items.
+pub fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(';')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+}
@@ -0,0 +1,275 @@
+//! Utilities for building small OSMX databases in tests, without needing a PBF file and
+//! the CLI's `expand` command.
+//!
+//! ```no_run
+//! use osmx::testing::DatabaseBuilder;
+//! use osmx::ElementId;
+//!
+//! let db = DatabaseBuilder::new()
+//!     .node(1, -122.0, 47.0, &[])
+//!     .node(2, -122.1, 47.1, &[("amenity", "cafe")])
+//!     .way(10, &[1, 2], &[("highway", "footway")])
+//!     .relation(20, &[(ElementId::Way(10), "")], &[("type", "route")])
+//!     .build()
+//!     .unwrap();
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use lmdb::Transaction as LmdbTransaction;
+
+use crate::database::{Database, ValueEncoding, BOUNDS_KEY, CELL_INDEX_LEVEL};
+use crate::database::{FORMAT_VERSION, FORMAT_VERSION_KEY};
+use crate::keys::{encode_element_ref, encode_key, KeyByteOrder};
+use crate::messages_capnp;
+use crate::types::ElementId;
+use crate::Error;
+
+type Tags = Vec<(String, String)>;
+
+fn to_tags(tags: &[(&str, &str)]) -> Tags {
+    tags.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn flatten_tags(tags: &Tags) -> Vec<&str> {
+    tags.iter()
+        .flat_map(|(k, v)| [k.as_str(), v.as_str()])
+        .collect()
+}
+
+fn to_fixed(degrees: f64) -> i32 {
+    (degrees * 1e7).round() as i32
+}
+
+/// Describes the nodes, ways and relations of a database, and writes them to a
+/// temporary OSMX file with [DatabaseBuilder::build]. Elements are written at version
+/// 1, without metadata, and the index tables are filled in from them, as `expand`
+/// would. References to elements which weren't added are allowed.
+#[derive(Default)]
+pub struct DatabaseBuilder {
+    nodes: BTreeMap<u64, (f64, f64, Tags)>,
+    ways: BTreeMap<u64, (Vec<u64>, Tags)>,
+    relations: BTreeMap<u64, (Vec<(ElementId, String)>, Tags)>,
+    deleted: Vec<ElementId>,
+}
+
+impl DatabaseBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node at the given coordinates (in degrees), replacing any node with the
+    /// same ID.
+    pub fn node(&mut self, id: u64, lon: f64, lat: f64, tags: &[(&str, &str)]) -> &mut Self {
+        self.nodes.insert(id, (lon, lat, to_tags(tags)));
+        self
+    }
+
+    /// Add a way with the given node IDs, replacing any way with the same ID.
+    pub fn way(&mut self, id: u64, nodes: &[u64], tags: &[(&str, &str)]) -> &mut Self {
+        self.ways.insert(id, (nodes.to_vec(), to_tags(tags)));
+        self
+    }
+
+    /// Add a relation with the given members and their roles, replacing any relation
+    /// with the same ID.
+    pub fn relation(
+        &mut self,
+        id: u64,
+        members: &[(ElementId, &str)],
+        tags: &[(&str, &str)],
+    ) -> &mut Self {
+        let members = members
+            .iter()
+            .map(|(member, role)| (*member, role.to_string()))
+            .collect();
+        self.relations.insert(id, (members, to_tags(tags)));
+        self
+    }
+
+    /// Record an element as deleted in the `tombstones` table, so that the database can
+    /// be used as the edits of an [crate::OverlayDatabase].
+    pub fn delete(&mut self, element: ElementId) -> &mut Self {
+        self.deleted.push(element);
+        self
+    }
+
+    /// Write the elements to a new database in the temporary directory and open it.
+    /// The file is removed once it's open, so it is cleaned up when the Database is
+    /// dropped.
+    pub fn build(&self) -> Result<Database, Error> {
+        static FIXTURES: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "osmx-fixture-{}-{}.osmx",
+            std::process::id(),
+            FIXTURES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut lock = path.clone().into_os_string();
+        lock.push("-lock");
+
+        let written = self.write_to(&path);
+        let db = written.and_then(|()| Database::open(&path));
+        // the memory map keeps the data alive after the files are unlinked
+        for file in [path.as_os_str(), lock.as_os_str()] {
+            if let Err(e) = std::fs::remove_file(file) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    return Err(e.into());
+                }
+            }
+        }
+        db
+    }
+
+    /// Write the elements to a new database at the given path, without opening it. For
+    /// tests which run commands on the file.
+    pub fn write_to(&self, path: &std::path::Path) -> Result<(), Error> {
+        // the environment is closed (when it is dropped) before the database is opened,
+        // since LMDB doesn't allow a process to open the same file twice
+        let env = lmdb::Environment::new()
+            .set_flags(lmdb::EnvironmentFlags::NO_SUB_DIR | lmdb::EnvironmentFlags::NO_SYNC)
+            .set_max_dbs(crate::DEFAULT_MAX_TABLES)
+            .set_map_size(64 * 1024 * 1024) // 64 MiB
+            .open(path)?;
+
+        let element_flags = lmdb::DatabaseFlags::INTEGER_KEY;
+        let index_flags = lmdb::DatabaseFlags::INTEGER_KEY
+            | lmdb::DatabaseFlags::INTEGER_DUP
+            | lmdb::DatabaseFlags::DUP_SORT
+            | lmdb::DatabaseFlags::DUP_FIXED;
+
+        let metadata = env.create_db(Some("metadata"), lmdb::DatabaseFlags::empty())?;
+        let locations = env.create_db(Some("locations"), element_flags)?;
+        let nodes = env.create_db(Some("nodes"), element_flags)?;
+        let ways = env.create_db(Some("ways"), element_flags)?;
+        let relations = env.create_db(Some("relations"), element_flags)?;
+        let cell_node = env.create_db(Some("cell_node"), index_flags)?;
+        let node_way = env.create_db(Some("node_way"), index_flags)?;
+        let node_relation = env.create_db(Some("node_relation"), index_flags)?;
+        let way_relation = env.create_db(Some("way_relation"), index_flags)?;
+        let relation_relation = env.create_db(Some("relation_relation"), index_flags)?;
+        // like the other optional tables, the tombstones table only exists when needed
+        let tombstones = if self.deleted.is_empty() {
+            None
+        } else {
+            Some(env.create_db(Some("tombstones"), element_flags)?)
+        };
+
+        let mut txn = env.begin_rw_txn()?;
+        let flags = lmdb::WriteFlags::empty();
+
+        txn.put(
+            metadata,
+            &FORMAT_VERSION_KEY.as_bytes(),
+            &FORMAT_VERSION.to_le_bytes(),
+            flags,
+        )?;
+        txn.put(
+            metadata,
+            &KeyByteOrder::METADATA_KEY.as_bytes(),
+            &KeyByteOrder::NATIVE.as_str().as_bytes(),
+            flags,
+        )?;
+        txn.put(
+            metadata,
+            &ValueEncoding::METADATA_KEY.as_bytes(),
+            &ValueEncoding::Standard.as_str().as_bytes(),
+            flags,
+        )?;
+
+        let mut bounds: Option<(i32, i32, i32, i32)> = None;
+        for (&id, (lon, lat, tags)) in self.nodes.iter() {
+            let (x, y) = (to_fixed(*lon), to_fixed(*lat));
+            let mut location = vec![];
+            location.extend(x.to_le_bytes());
+            location.extend(y.to_le_bytes());
+            location.extend(1u32.to_le_bytes());
+            txn.put(locations, &encode_key(id), &location, flags)?;
+
+            let latlng = s2::latlng::LatLng::from_degrees(*lat, *lon);
+            let cell = s2::cellid::CellID::from(latlng).parent(CELL_INDEX_LEVEL);
+            txn.put(cell_node, &encode_key(cell.0), &encode_key(id), flags)?;
+
+            let (west, south, east, north) = bounds.unwrap_or((x, y, x, y));
+            bounds = Some((west.min(x), south.min(y), east.max(x), north.max(y)));
+
+            // like expand, only nodes with tags have a value in the nodes table
+            if !tags.is_empty() {
+                let mut message = capnp::message::Builder::new_default();
+                let mut node = message.init_root::<messages_capnp::node::Builder>();
+                node.set_tags(&flatten_tags(tags)[..])?;
+                txn.put(nodes, &encode_key(id), &encode_message(&message), flags)?;
+            }
+        }
+
+        if let Some((west, south, east, north)) = bounds {
+            let raw_val: Vec<u8> = [west, south, east, north]
+                .iter()
+                .flat_map(|v| v.to_le_bytes())
+                .collect();
+            txn.put(metadata, &BOUNDS_KEY.as_bytes(), &raw_val, flags)?;
+        }
+
+        for (&id, (node_ids, tags)) in self.ways.iter() {
+            let mut message = capnp::message::Builder::new_default();
+            let mut way = message.init_root::<messages_capnp::way::Builder>();
+            way.set_nodes(&node_ids[..])?;
+            way.set_tags(&flatten_tags(tags)[..])?;
+            txn.put(ways, &encode_key(id), &encode_message(&message), flags)?;
+
+            // putting a key/value pair which is already in a table has no effect, so
+            // closed ways only index their first node once
+            for &node_id in node_ids {
+                txn.put(node_way, &encode_key(node_id), &encode_key(id), flags)?;
+            }
+        }
+
+        for (&id, (members, tags)) in self.relations.iter() {
+            let mut message = capnp::message::Builder::new_default();
+            let mut relation = message.init_root::<messages_capnp::relation::Builder>();
+            relation.set_tags(&flatten_tags(tags)[..])?;
+            let mut builder = relation.init_members(members.len() as u32);
+            for (idx, (member, role)) in members.iter().enumerate() {
+                let mut mbuilder = builder.reborrow().get(idx as u32);
+                let (member_type, member_id, join_table) = match *member {
+                    ElementId::Node(member_id) => (
+                        messages_capnp::relation_member::Type::Node,
+                        member_id,
+                        node_relation,
+                    ),
+                    ElementId::Way(member_id) => (
+                        messages_capnp::relation_member::Type::Way,
+                        member_id,
+                        way_relation,
+                    ),
+                    ElementId::Relation(member_id) => (
+                        messages_capnp::relation_member::Type::Relation,
+                        member_id,
+                        relation_relation,
+                    ),
+                };
+                mbuilder.set_type(member_type);
+                mbuilder.set_ref(member_id);
+                mbuilder.set_role(role.as_str());
+                txn.put(join_table, &encode_key(member_id), &encode_key(id), flags)?;
+            }
+            txn.put(relations, &encode_key(id), &encode_message(&message), flags)?;
+        }
+
+        if let Some(tombstones) = tombstones {
+            for element in self.deleted.iter() {
+                txn.put(tombstones, &encode_element_ref(element), &[], flags)?;
+            }
+        }
+
+        txn.commit()?;
+        Ok(())
+    }
+}
+
+fn encode_message(message: &capnp::message::Builder<capnp::message::HeapAllocator>) -> Vec<u8> {
+    capnp::serialize::write_message_to_words(message)
+}
@@ -0,0 +1,65 @@
+//! Helpers for the optional `tracing` instrumentation. When the `tracing` feature is
+//! disabled, these compile to nothing.
+
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+/// Measures a table scan or spatial query, which happens lazily as its iterator is
+/// consumed. When dropped (i.e. when the iterator is exhausted or dropped early), emits a
+/// debug event with the number of items yielded and the time taken.
+#[cfg(feature = "tracing")]
+pub(crate) struct Scan {
+    name: &'static str,
+    table: &'static str,
+    start: Instant,
+    count: u64,
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Scan;
+
+#[cfg(feature = "tracing")]
+impl Scan {
+    pub(crate) fn new(name: &'static str, table: &'static str) -> Self {
+        Self {
+            name,
+            table,
+            start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    pub(crate) fn yielded(&mut self) {
+        self.count += 1;
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl Scan {
+    pub(crate) fn new(_name: &'static str, _table: &'static str) -> Self {
+        Self
+    }
+
+    pub(crate) fn yielded(&mut self) {}
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Scan {
+    fn drop(&mut self) {
+        tracing::debug!(
+            table = self.table,
+            count = self.count,
+            elapsed_ms = self.start.elapsed().as_secs_f64() * 1000.0,
+            "{} finished",
+            self.name
+        );
+    }
+}
+
+/// The name of an element type, for use as a table name in events (e.g. "Way" for
+/// `osmx::types::Way<'_>`)
+pub(crate) fn type_name<T>() -> &'static str {
+    let name = std::any::type_name::<T>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
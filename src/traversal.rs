@@ -0,0 +1,280 @@
+use roaring::RoaringTreemap;
+
+use crate::database::Transaction;
+use crate::types::ElementId;
+use crate::Error;
+
+/// The deepest relations are followed into when recursing through members where there's
+/// no other limit (as in [crate::Relation::bbox]), so that pathological data can't
+/// overflow the stack. Real relations are rarely nested more than a few levels deep (a
+/// route master containing routes, say); see [crate::Validator::nesting_problems] to
+/// find the ones which are.
+pub const MAX_RELATION_DEPTH: usize = 32;
+
+/// A set of element IDs, with a bitmap for each element type.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ElementIds {
+    pub nodes: RoaringTreemap,
+    pub ways: RoaringTreemap,
+    pub relations: RoaringTreemap,
+}
+
+impl ElementIds {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an element to the set. Returns true if it wasn't already in the set.
+    pub fn insert(&mut self, id: ElementId) -> bool {
+        match id {
+            ElementId::Node(id) => self.nodes.insert(id),
+            ElementId::Way(id) => self.ways.insert(id),
+            ElementId::Relation(id) => self.relations.insert(id),
+        }
+    }
+
+    pub fn contains(&self, id: ElementId) -> bool {
+        match id {
+            ElementId::Node(id) => self.nodes.contains(id),
+            ElementId::Way(id) => self.ways.contains(id),
+            ElementId::Relation(id) => self.relations.contains(id),
+        }
+    }
+
+    /// The total number of elements in the set.
+    pub fn len(&self) -> u64 {
+        self.nodes.len() + self.ways.len() + self.relations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Which related elements to add when computing a referential closure with
+/// [Transaction::closure]. The steps are applied in the order of the fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClosureStrategy {
+    /// Add the ways which the nodes are part of.
+    pub ways_of_nodes: bool,
+    /// Add all the nodes of the ways, so that their geometry is complete.
+    pub complete_ways: bool,
+    /// Add the relations which the nodes and ways are members of, and the relations which
+    /// those are members of, and so on.
+    pub parent_relations: bool,
+    /// Add the members of the relations, recursively (and their nodes, with
+    /// complete_ways). The parent relations of the elements added by this step are not
+    /// added, since that would usually pull in most of the database.
+    pub relation_members: bool,
+}
+
+impl ClosureStrategy {
+    /// The ways of the nodes and the relations of both, but not the nodes needed to
+    /// complete the ways, so way geometries may be cut off.
+    pub const SIMPLE: Self = Self {
+        ways_of_nodes: true,
+        complete_ways: false,
+        parent_relations: true,
+        relation_members: false,
+    };
+
+    /// Like SIMPLE, but with complete way geometries.
+    pub const COMPLETE_WAYS: Self = Self {
+        complete_ways: true,
+        ..Self::SIMPLE
+    };
+
+    /// Like COMPLETE_WAYS, but with all the members of the relations, so that every
+    /// relation (e.g. a multipolygon crossing the edge of a region) is complete.
+    pub const COMPLETE_RELATIONS: Self = Self {
+        relation_members: true,
+        ..Self::COMPLETE_WAYS
+    };
+
+    /// Only the elements which the elements refer to: the nodes of the ways, and the
+    /// members of the relations, recursively. Suited to seeds which are a list of chosen
+    /// elements, rather than the contents of a region.
+    pub const REFERENCES: Self = Self {
+        ways_of_nodes: false,
+        complete_ways: true,
+        parent_relations: false,
+        relation_members: true,
+    };
+}
+
+impl Transaction<'_> {
+    /// Find the elements which make up a relation: its members, the members of its member
+    /// relations (recursively), and the nodes of all of those ways. The relation itself is
+    /// not included unless it is a member of one of its descendants.
+    ///
+    /// Each relation is only expanded once, so cycles of relations which are members of
+    /// each other terminate. If max_depth is given, relations nested more than max_depth
+    /// levels below this one are included but not expanded (so a max_depth of 0 gives
+    /// just the direct members and their nodes). Members missing from the database are
+    /// included, but can't be expanded.
+    pub fn relation_descendants(
+        &self,
+        relation_id: u64,
+        max_depth: Option<usize>,
+    ) -> Result<ElementIds, Error> {
+        let ways = self.ways()?;
+        let relations = self.relations()?;
+
+        let mut descendants = ElementIds::new();
+        let mut expanded = RoaringTreemap::new();
+        let mut level = vec![relation_id];
+        let mut depth = 0;
+        while !level.is_empty() {
+            let mut next = vec![];
+            for id in level {
+                if !expanded.insert(id) {
+                    continue;
+                }
+                let Some(relation) = relations.get(id)? else {
+                    continue;
+                };
+                for member in relation.members() {
+                    let member_id = member.id();
+                    if !descendants.insert(member_id) {
+                        continue;
+                    }
+                    match member_id {
+                        ElementId::Node(_) => (),
+                        ElementId::Way(way_id) => {
+                            if let Some(way) = ways.get(way_id)? {
+                                descendants.nodes.extend(way.nodes());
+                            }
+                        }
+                        ElementId::Relation(member_relation_id) => next.push(member_relation_id),
+                    }
+                }
+            }
+            if max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                break;
+            }
+            level = next;
+            depth += 1;
+        }
+        Ok(descendants)
+    }
+
+    /// Find the elements which contain the given element, directly or indirectly: for a
+    /// node, the ways it is part of, and for any element, the relations it is a member of,
+    /// the relations those are members of, and so on. This is the set of elements which
+    /// may be affected if the element is changed or deleted. The element itself is not
+    /// included unless it is part of a cycle of relations.
+    pub fn ancestors(&self, id: ElementId) -> Result<ElementIds, Error> {
+        let node_ways = self.node_ways()?;
+        let node_relations = self.node_relations()?;
+        let way_relations = self.way_relations()?;
+        let relation_relations = self.relation_relations()?;
+
+        let mut ancestors = ElementIds::new();
+        let mut pending = vec![id];
+        while let Some(id) = pending.pop() {
+            let parents: Vec<ElementId> = match id {
+                ElementId::Node(node_id) => node_ways
+                    .get(node_id)
+                    .map(ElementId::Way)
+                    .chain(node_relations.get(node_id).map(ElementId::Relation))
+                    .collect(),
+                ElementId::Way(way_id) => {
+                    way_relations.get(way_id).map(ElementId::Relation).collect()
+                }
+                ElementId::Relation(relation_id) => relation_relations
+                    .get(relation_id)
+                    .map(ElementId::Relation)
+                    .collect(),
+            };
+            for parent in parents {
+                if ancestors.insert(parent) {
+                    pending.push(parent);
+                }
+            }
+        }
+        Ok(ancestors)
+    }
+
+    /// Compute the referential closure of a set of elements (e.g. all the nodes in a
+    /// region) under the given strategy: the elements plus the related elements which the
+    /// strategy adds. This is the set of elements to copy when making an extract.
+    pub fn closure(
+        &self,
+        seeds: &ElementIds,
+        strategy: ClosureStrategy,
+    ) -> Result<ElementIds, Error> {
+        let ways = self.ways()?;
+        let relations = self.relations()?;
+        let node_ways = self.node_ways()?;
+        let node_relations = self.node_relations()?;
+        let way_relations = self.way_relations()?;
+        let relation_relations = self.relation_relations()?;
+
+        let mut closure = seeds.clone();
+
+        if strategy.ways_of_nodes {
+            for node_id in seeds.nodes.iter() {
+                closure.ways.extend(node_ways.get(node_id));
+            }
+        }
+
+        if strategy.complete_ways {
+            let mut way_nodes = RoaringTreemap::new();
+            for way_id in closure.ways.iter() {
+                if let Some(way) = ways.get(way_id)? {
+                    way_nodes.extend(way.nodes());
+                }
+            }
+            closure.nodes |= way_nodes;
+        }
+
+        if strategy.parent_relations {
+            let mut pending: Vec<u64> = closure.relations.iter().collect();
+            for node_id in closure.nodes.iter() {
+                pending.extend(node_relations.get(node_id));
+            }
+            for way_id in closure.ways.iter() {
+                pending.extend(way_relations.get(way_id));
+            }
+            let mut visited = RoaringTreemap::new();
+            while let Some(relation_id) = pending.pop() {
+                if visited.insert(relation_id) {
+                    closure.relations.insert(relation_id);
+                    pending.extend(relation_relations.get(relation_id));
+                }
+            }
+        }
+
+        if strategy.relation_members {
+            let mut expanded = RoaringTreemap::new();
+            let mut pending: Vec<u64> = closure.relations.iter().collect();
+            while let Some(relation_id) = pending.pop() {
+                if !expanded.insert(relation_id) {
+                    continue;
+                }
+                let Some(relation) = relations.get(relation_id)? else {
+                    continue;
+                };
+                for member in relation.members() {
+                    let member_id = member.id();
+                    if !closure.insert(member_id) {
+                        continue;
+                    }
+                    match member_id {
+                        ElementId::Node(_) => (),
+                        ElementId::Way(way_id) => {
+                            if strategy.complete_ways {
+                                if let Some(way) = ways.get(way_id)? {
+                                    closure.nodes.extend(way.nodes());
+                                }
+                            }
+                        }
+                        ElementId::Relation(member_relation_id) => pending.push(member_relation_id),
+                    }
+                }
+            }
+        }
+
+        Ok(closure)
+    }
+}
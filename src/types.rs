@@ -1,17 +1,85 @@
-use std::error::Error;
+use std::collections::{BTreeMap, HashSet};
 
+use crate::compression::{Dictionary, ValueCompression};
+use crate::database::{Locations, Relations, Transaction, ValueEncoding, Ways};
+#[cfg(feature = "export")]
+use crate::geometry::{GeometryError, MissingNodePolicy};
 use crate::messages_capnp;
-use capnp::message::{ReaderOptions, TypedReader};
-use capnp::serialize::BufferSegments;
-use itertools::Itertools;
+use crate::traversal::MAX_RELATION_DEPTH;
+use crate::Error;
+use capnp::message::{ReaderOptions, ReaderSegments, TypedReader};
+use capnp::serialize::{BufferSegments, NoAllocBufferSegments, OwnedSegments};
+use itertools::{Either, Itertools};
 
-#[derive(Debug, PartialEq, Eq)]
+/// A bounding box, as (west, south, east, north) in degrees.
+pub type BoundingBox = (f64, f64, f64, f64);
+
+/// The coordinates of a way's nodes, as (lon, lat) in node order, with None for nodes
+/// whose location is unknown.
+pub type WayGeometry = Vec<Option<(f64, f64)>>;
+
+/// Grow a bounding box (or None, for an empty one) to include another bounding box.
+fn extend_bbox(bbox: Option<BoundingBox>, other: BoundingBox) -> Option<BoundingBox> {
+    let (west, south, east, north) = bbox.unwrap_or(other);
+    Some((
+        west.min(other.0),
+        south.min(other.1),
+        east.max(other.2),
+        north.max(other.3),
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ElementId {
     Node(u64),
     Way(u64),
     Relation(u64),
 }
 
+impl std::str::FromStr for ElementId {
+    type Err = Error;
+
+    /// Parse a typed element ID, written either with a one-letter type prefix (`n123`,
+    /// `w123`, `r123`) or as a type and ID separated by a slash (`node/123`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, id) = match s.split_once('/') {
+            Some((kind, id)) => (kind, id),
+            None if s.is_char_boundary(1) => s.split_at(1),
+            None => return Err(format!("invalid element ID {:?}", s).into()),
+        };
+        let id: u64 = id
+            .parse()
+            .map_err(|_| format!("invalid element ID {:?}", s))?;
+        match kind {
+            "n" | "node" => Ok(ElementId::Node(id)),
+            "w" | "way" => Ok(ElementId::Way(id)),
+            "r" | "relation" => Ok(ElementId::Relation(id)),
+            _ => Err(format!("invalid element ID {:?} (unknown type)", s).into()),
+        }
+    }
+}
+
+/// An element fetched from the database, as yielded by [Relation::resolved_members].
+pub enum Element<'a> {
+    /// A node and its location. The Node is None if the node has no tags, since
+    /// untagged nodes are only stored in the Locations table.
+    Node {
+        id: u64,
+        location: Location<'a>,
+        node: Option<Node<'a>>,
+    },
+    Way {
+        id: u64,
+        way: Way<'a>,
+    },
+    Relation {
+        id: u64,
+        relation: Relation<'a>,
+    },
+    /// An element which isn't in the database, such as a member outside of an extract.
+    Missing(ElementId),
+}
+
 /// A reader for values in the `locations` table, which store the coordinates of OSM Nodes.
 pub struct Location<'a> {
     buf: &'a [u8],
@@ -19,6 +87,425 @@ pub struct Location<'a> {
 
 const COORDINATE_PRECISION: i32 = 10000000;
 
+/// The Cap'n Proto reader options used when decoding element values, unless
+/// overridden with [crate::Database::set_reader_options]. The traversal limit
+/// is much higher than capnp's default, since very large relations and ways
+/// can otherwise exceed it when their accessors are called several times.
+pub const DEFAULT_READER_OPTIONS: ReaderOptions = ReaderOptions {
+    traversal_limit_in_words: Some(512 * 1024 * 1024),
+    nesting_limit: 64,
+};
+
+/// A table of interned strings, which tag keys and values in element values may
+/// refer to by ID instead of storing the string inline. Databases created without
+/// string interning have an empty table.
+#[derive(Debug, Default)]
+pub struct StringTable {
+    strings: Vec<String>,
+}
+
+pub(crate) static EMPTY_STRING_TABLE: StringTable = StringTable {
+    strings: Vec::new(),
+};
+
+impl StringTable {
+    pub(crate) fn new(strings: Vec<String>) -> Self {
+        Self { strings }
+    }
+
+    /// Get an interned string by its ID. IDs start at 1 (zero means "not interned").
+    pub fn get(&self, id: u32) -> Option<&str> {
+        let idx = (id as usize).checked_sub(1)?;
+        self.strings.get(idx).map(|s| s.as_str())
+    }
+
+    /// Returns the number of strings in the table.
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    /// Returns true if the table contains no strings.
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
+
+/// A table of tag sets shared by many elements, which element values may refer to by
+/// hash (see [tag_set_hash]) instead of storing their tags. Databases created without
+/// shared tag sets have an empty table.
+#[derive(Debug, Default)]
+pub struct TagSetTable {
+    sets: BTreeMap<u64, Vec<String>>,
+}
+
+pub(crate) static EMPTY_TAG_SET_TABLE: TagSetTable = TagSetTable {
+    sets: BTreeMap::new(),
+};
+
+impl TagSetTable {
+    pub(crate) fn new(sets: BTreeMap<u64, Vec<String>>) -> Self {
+        Self { sets }
+    }
+
+    /// Get a tag set by its hash, as a flat list of keys and values.
+    pub fn get(&self, hash: u64) -> Option<&[String]> {
+        self.sets.get(&hash).map(|set| set.as_slice())
+    }
+
+    /// Returns the number of tag sets in the table.
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    /// Returns true if the table contains no tag sets.
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+}
+
+/// The key of a tag set (a flat list of keys and values, in order) in the `tag_sets`
+/// table: the 64-bit FNV-1a hash of its strings, each followed by a zero byte. Never
+/// zero, since zero means "no tag set" in element values.
+pub fn tag_set_hash(tags: &[&str]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in tags.iter().flat_map(|tag| tag.bytes().chain([0])) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash.max(1)
+}
+
+/// Encode a tag set as a value of the `tag_sets` table: its keys and values, each
+/// followed by a zero byte.
+pub fn encode_tag_set(tags: &[&str]) -> Vec<u8> {
+    tags.iter().flat_map(|tag| tag.bytes().chain([0])).collect()
+}
+
+/// Decode a value of the `tag_sets` table. Returns None if it isn't valid UTF-8 or
+/// doesn't hold an even number of strings.
+pub fn decode_tag_set(raw: &[u8]) -> Option<Vec<String>> {
+    let raw = std::str::from_utf8(raw.strip_suffix(&[0])?).ok()?;
+    let tags: Vec<String> = raw.split('\0').map(str::to_string).collect();
+    tags.len().is_multiple_of(2).then_some(tags)
+}
+
+/// The settings needed to decode an element value, besides the value itself.
+#[derive(Clone, Copy)]
+pub struct DecodeContext<'a> {
+    /// Limits applied while reading Cap'n Proto messages.
+    pub options: ReaderOptions,
+    /// How Cap'n Proto messages are serialized.
+    pub encoding: ValueEncoding,
+    /// How values are compressed.
+    pub compression: ValueCompression,
+    /// The dictionary needed to decompress values, if they are compressed with zstd.
+    pub dictionary: Option<&'a Dictionary>,
+    /// The table used to resolve interned tag strings.
+    pub strings: &'a StringTable,
+    /// The table used to resolve shared tag sets.
+    pub tag_sets: &'a TagSetTable,
+}
+
+impl Default for DecodeContext<'_> {
+    fn default() -> Self {
+        Self {
+            options: DEFAULT_READER_OPTIONS,
+            encoding: ValueEncoding::Standard,
+            compression: ValueCompression::None,
+            dictionary: None,
+            strings: &EMPTY_STRING_TABLE,
+            tag_sets: &EMPTY_TAG_SET_TABLE,
+        }
+    }
+}
+
+/// A type which can be decoded from a raw value stored in one of the element tables.
+pub trait Decode<'a>: Sized {
+    /// Decode a value from the given bytes. Returns an error if the value is malformed,
+    /// or if reading it exceeds the limits set in the context's reader options.
+    fn decode(bytes: &'a [u8], context: DecodeContext<'a>) -> Result<Self, capnp::Error>;
+
+    /// Decode a value like [Self::decode], but keep any decompressed or unpacked copy of
+    /// it in `buffer` instead of allocating a new one. The value borrows the buffer, so
+    /// the buffer can be reused once the value is dropped. The default implementation
+    /// doesn't use the buffer.
+    fn decode_in(
+        bytes: &'a [u8],
+        context: DecodeContext<'a>,
+        buffer: &'a mut DecodeBuffer,
+    ) -> Result<Self, capnp::Error> {
+        let _ = buffer;
+        Self::decode(bytes, context)
+    }
+}
+
+/// An element type whose lifetime can be shortened, so that values decoded into a
+/// [DecodeBuffer] can be lent out for as long as the buffer is borrowed (see
+/// `for_each_ref` on the element tables). Implemented by all the element types.
+pub trait Reborrow {
+    /// The same type, borrowing from data with the lifetime `'b`.
+    type At<'b>: Decode<'b>;
+}
+
+/// Reusable buffers for values which have to be decompressed or unpacked before they
+/// can be read. See [Decode::decode_in].
+#[derive(Default)]
+pub struct DecodeBuffer {
+    decompressed: Vec<u8>,
+    words: Vec<capnp::Word>,
+}
+
+/// Unpack a message serialized with Cap'n Proto's packed encoding into `words`,
+/// replacing its contents but reusing its allocation.
+fn unpack_into(mut packed: &[u8], words: &mut Vec<capnp::Word>) -> Result<(), capnp::Error> {
+    let truncated = || capnp::Error::failed("truncated packed message".to_string());
+    words.clear();
+    while let Some((&tag, rest)) = packed.split_first() {
+        packed = rest;
+        let mut word = [0u8; 8];
+        for (bit, byte) in word.iter_mut().enumerate() {
+            if tag & (1 << bit) != 0 {
+                let (&b, rest) = packed.split_first().ok_or_else(truncated)?;
+                *byte = b;
+                packed = rest;
+            }
+        }
+        words.push(capnp::word(
+            word[0], word[1], word[2], word[3], word[4], word[5], word[6], word[7],
+        ));
+
+        // a zero word is followed by a count of further zero words, and a word with no
+        // zero bytes is followed by a count of further words which are stored verbatim
+        if tag == 0x00 || tag == 0xff {
+            let (&count, rest) = packed.split_first().ok_or_else(truncated)?;
+            packed = rest;
+            let count = count as usize;
+            if tag == 0x00 {
+                words.resize(words.len() + count, capnp::word(0, 0, 0, 0, 0, 0, 0, 0));
+            } else {
+                if packed.len() < count * 8 {
+                    return Err(truncated());
+                }
+                let (raw, rest) = packed.split_at(count * 8);
+                packed = rest;
+                words.extend(
+                    raw.chunks_exact(8)
+                        .map(|b| capnp::word(b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7])),
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The segments of a Cap'n Proto element value. Unpacked values are read in place
+/// from the database, while packed values must be unpacked into an owned buffer (or a
+/// reusable [DecodeBuffer]).
+enum Segments<'a> {
+    Borrowed(BufferSegments<&'a [u8]>),
+    Owned(OwnedSegments),
+    Buffered(NoAllocBufferSegments<&'a [u8]>),
+}
+
+impl<'a> Segments<'a> {
+    fn read(bytes: &'a [u8], context: DecodeContext<'a>) -> Result<Self, capnp::Error> {
+        match context.compression {
+            ValueCompression::None => match context.encoding {
+                ValueEncoding::Standard => {
+                    Ok(Self::Borrowed(BufferSegments::new(bytes, context.options)?))
+                }
+                ValueEncoding::Packed => Self::read_owned(bytes, context),
+            },
+            ValueCompression::Zstd => {
+                let dictionary = context
+                    .dictionary
+                    .ok_or_else(|| capnp::Error::failed("missing zstd dictionary".to_string()))?;
+                let buf = dictionary
+                    .decompress(bytes)
+                    .map_err(|e| capnp::Error::failed(e.to_string()))?;
+                Self::read_owned(&buf, context)
+            }
+        }
+    }
+
+    fn read_owned(mut bytes: &[u8], context: DecodeContext<'a>) -> Result<Self, capnp::Error> {
+        let message = match context.encoding {
+            ValueEncoding::Standard => capnp::serialize::read_message(&mut bytes, context.options)?,
+            ValueEncoding::Packed => {
+                capnp::serialize_packed::read_message(&mut bytes, context.options)?
+            }
+        };
+        Ok(Self::Owned(message.into_segments()))
+    }
+
+    fn read_in(
+        bytes: &'a [u8],
+        context: DecodeContext<'a>,
+        buffer: &'a mut DecodeBuffer,
+    ) -> Result<Self, capnp::Error> {
+        let DecodeBuffer {
+            decompressed,
+            words,
+        } = buffer;
+        let bytes: &[u8] = match context.compression {
+            ValueCompression::None => bytes,
+            ValueCompression::Zstd => {
+                let dictionary = context
+                    .dictionary
+                    .ok_or_else(|| capnp::Error::failed("missing zstd dictionary".to_string()))?;
+                dictionary
+                    .decompress_into(bytes, decompressed)
+                    .map_err(|e| capnp::Error::failed(e.to_string()))?;
+                decompressed
+            }
+        };
+        match (context.compression, context.encoding) {
+            (ValueCompression::None, ValueEncoding::Standard) => {
+                return Ok(Self::Borrowed(BufferSegments::new(bytes, context.options)?));
+            }
+            (_, ValueEncoding::Standard) => {
+                // copied into words so that the message is aligned
+                words.clear();
+                words.extend(
+                    bytes
+                        .chunks_exact(8)
+                        .map(|b| capnp::word(b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7])),
+                );
+            }
+            (_, ValueEncoding::Packed) => unpack_into(bytes, words)?,
+        }
+        let words: &'a [capnp::Word] = words;
+        Ok(Self::Buffered(NoAllocBufferSegments::from_buffer(
+            capnp::Word::words_to_bytes(words),
+            context.options,
+        )?))
+    }
+}
+
+/// Returns an iterator of key-value pairs from an element's tag list, looking up
+/// interned strings (those with a nonzero entry in `tag_ids`) in the string table, or
+/// from the shared tag set with the given hash, if it's nonzero.
+fn resolve_tags<'a>(
+    tags: capnp::text_list::Reader<'a>,
+    tag_ids: capnp::primitive_list::Reader<'a, u32>,
+    tag_set: u64,
+    strings: &'a StringTable,
+    tag_sets: &'a TagSetTable,
+) -> impl Iterator<Item = (&'a str, &'a str)> {
+    if tag_set != 0 {
+        let set = tag_sets.get(tag_set).expect("tag set not found");
+        return Either::Left(set.iter().map(String::as_str).tuples());
+    }
+    let tags = tags
+        .iter()
+        .enumerate()
+        .map(move |(idx, v)| {
+            let idx = idx as u32;
+            match if idx < tag_ids.len() {
+                tag_ids.get(idx)
+            } else {
+                0
+            } {
+                0 => v.unwrap().to_str().unwrap(),
+                id => strings.get(id).expect("interned string not found"),
+            }
+        })
+        .tuples::<(&'a str, &'a str)>();
+    Either::Right(tags)
+}
+
+/// Check that the tag set an element value refers to (if any) is in the table, so that
+/// values referring to missing tag sets are reported when they're decoded.
+fn check_tag_set(tag_set: u64, tag_sets: &TagSetTable) -> Result<(), capnp::Error> {
+    if tag_set != 0 && tag_sets.get(tag_set).is_none() {
+        return Err(capnp::Error::failed(format!(
+            "tag set {} not found",
+            tag_set
+        )));
+    }
+    Ok(())
+}
+
+/// Check that every tag string in an element value is valid UTF-8 or refers to an
+/// interned string that's in the table, so that [resolve_tags] can't fail.
+fn check_tags(
+    tags: capnp::text_list::Reader<'_>,
+    tag_ids: capnp::primitive_list::Reader<'_, u32>,
+    strings: &StringTable,
+) -> Result<(), capnp::Error> {
+    for idx in 0..tags.len() {
+        match if idx < tag_ids.len() {
+            tag_ids.get(idx)
+        } else {
+            0
+        } {
+            0 => {
+                tags.get(idx)?.to_str()?;
+            }
+            id if strings.get(id).is_none() => {
+                return Err(capnp::Error::failed(format!(
+                    "interned string {} not found",
+                    id
+                )));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Check the metadata of an element value, if it has any, so that reading it later
+/// can't fail.
+fn check_metadata<'a>(
+    has_metadata: bool,
+    metadata: impl FnOnce() -> capnp::Result<messages_capnp::metadata::Reader<'a>>,
+) -> Result<(), capnp::Error> {
+    if has_metadata {
+        metadata()?.get_user()?.to_str()?;
+    }
+    Ok(())
+}
+
+/// Choose an element's name for display to a reader of the given languages (in order of
+/// preference), from its tags. See [Node::name].
+fn localized_name<'a>(
+    tags: impl Iterator<Item = (&'a str, &'a str)>,
+    languages: &[&str],
+) -> Option<&'a str> {
+    // rank each name tag by its position in the fallback chain, and keep the best
+    let rank = |key: &str| -> Option<usize> {
+        match key {
+            "int_name" => Some(languages.len()),
+            "name" => Some(languages.len() + 1),
+            _ => key
+                .strip_prefix("name:")
+                .and_then(|lang| languages.iter().position(|l| *l == lang)),
+        }
+    };
+    tags.filter(|(_, v)| !v.is_empty())
+        .filter_map(|(k, v)| Some((rank(k)?, v)))
+        .min_by_key(|&(rank, _)| rank)
+        .map(|(_, v)| v)
+}
+
+impl ReaderSegments for Segments<'_> {
+    fn get_segment(&self, idx: u32) -> Option<&[u8]> {
+        match self {
+            Self::Borrowed(segments) => segments.get_segment(idx),
+            Self::Owned(segments) => segments.get_segment(idx),
+            Self::Buffered(segments) => segments.get_segment(idx),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Borrowed(segments) => segments.len(),
+            Self::Owned(segments) => segments.len(),
+            Self::Buffered(segments) => segments.len(),
+        }
+    }
+}
+
 impl<'a> Location<'a> {
     pub fn lon(&self) -> f64 {
         let as_i32 = i32::from_le_bytes(self.buf[0..4].try_into().unwrap());
@@ -29,6 +516,29 @@ impl<'a> Location<'a> {
         let as_i32 = i32::from_le_bytes(self.buf[4..8].try_into().unwrap());
         as_i32 as f64 / COORDINATE_PRECISION as f64
     }
+
+    /// The version of the node, or None if the value doesn't store one (locations are
+    /// only required to have coordinates).
+    pub fn version(&self) -> Option<u32> {
+        let raw = self.buf.get(8..12)?;
+        Some(u32::from_le_bytes(raw.try_into().unwrap()))
+    }
+}
+
+impl<'a> Decode<'a> for Location<'a> {
+    fn decode(bytes: &'a [u8], _context: DecodeContext<'a>) -> Result<Self, capnp::Error> {
+        if bytes.len() < 8 {
+            return Err(capnp::Error::failed(format!(
+                "location value has length {} (expected at least 8)",
+                bytes.len()
+            )));
+        }
+        Ok(Self { buf: bytes })
+    }
+}
+
+impl Reborrow for Location<'_> {
+    type At<'b> = Location<'b>;
 }
 
 impl<'a> TryFrom<&'a [u8]> for Location<'a> {
@@ -39,9 +549,83 @@ impl<'a> TryFrom<&'a [u8]> for Location<'a> {
     }
 }
 
+/// A reader for the metadata of one version of an OSM element: its version number, when
+/// and in which changeset it was created, and by whom. Only stored by databases created
+/// with metadata or history enabled.
+pub struct Metadata<'a> {
+    reader: messages_capnp::metadata::Reader<'a>,
+}
+
+impl<'a> Metadata<'a> {
+    /// The version number of the element.
+    pub fn version(&self) -> u32 {
+        self.reader.get_version()
+    }
+
+    /// When this version was created, in seconds since the Unix epoch.
+    pub fn timestamp(&self) -> u64 {
+        self.reader.get_timestamp()
+    }
+
+    /// The ID of the changeset which created this version.
+    pub fn changeset(&self) -> u32 {
+        self.reader.get_changeset()
+    }
+
+    /// The user ID of the author of this version.
+    pub fn uid(&self) -> u32 {
+        self.reader.get_uid()
+    }
+
+    /// The username of the author of this version.
+    pub fn user(&self) -> &'a str {
+        self.reader.get_user().unwrap().to_str().unwrap()
+    }
+
+    /// True if this version deleted the element.
+    pub fn deleted(&self) -> bool {
+        self.reader.get_deleted()
+    }
+}
+
+/// An element type whose values may carry [Metadata]. The other methods return the
+/// fields of the metadata, or None if the value doesn't have any (as in databases
+/// created without the --metadata or --history options of expand).
+pub trait WithMetadata {
+    /// Returns the element's metadata, or None if its value doesn't have any.
+    fn metadata(&self) -> Option<Metadata<'_>>;
+
+    /// The version number of the element.
+    fn version(&self) -> Option<u32> {
+        self.metadata().map(|metadata| metadata.version())
+    }
+
+    /// When this version of the element was created, in seconds since the Unix epoch.
+    fn timestamp(&self) -> Option<u64> {
+        self.metadata().map(|metadata| metadata.timestamp())
+    }
+
+    /// The ID of the changeset which created this version of the element.
+    fn changeset(&self) -> Option<u32> {
+        self.metadata().map(|metadata| metadata.changeset())
+    }
+
+    /// The user ID of the author of this version of the element.
+    fn uid(&self) -> Option<u32> {
+        self.metadata().map(|metadata| metadata.uid())
+    }
+
+    /// The username of the author of this version of the element.
+    fn user(&self) -> Option<&str> {
+        self.metadata().map(|metadata| metadata.user())
+    }
+}
+
 /// A reader for a value in the `nodes` table, which stores the tags and metadata for OSM Nodes.
 pub struct Node<'a> {
-    reader: TypedReader<BufferSegments<&'a [u8]>, messages_capnp::node::Owned>,
+    reader: TypedReader<Segments<'a>, messages_capnp::node::Owned>,
+    strings: &'a StringTable,
+    tag_sets: &'a TagSetTable,
 }
 
 impl<'a> Node<'a> {
@@ -50,35 +634,95 @@ impl<'a> Node<'a> {
         self.tags().find(|(k, _)| k == &key).map(|(_, v)| v)
     }
 
+    /// Get the element's name in the first of the given languages (ISO 639 codes, in order
+    /// of preference) that it has a `name:xx` tag for. Falls back to `int_name` (the
+    /// international name, usually in Latin script), then to `name` (the name in the local
+    /// language). Returns None if the element has none of these tags.
+    pub fn name(&'a self, languages: &[&str]) -> Option<&'a str> {
+        localized_name(self.tags(), languages)
+    }
+
     /// Returns an iterator of key-value pairs for all of the tags on this element.
     pub fn tags(&'a self) -> impl Iterator<Item = (&'a str, &'a str)> {
-        self.reader
-            .get()
-            .unwrap()
-            .get_tags()
-            .unwrap()
-            .iter()
-            .map(|v| v.unwrap().to_str().unwrap())
-            .tuples::<(&'a str, &'a str)>()
+        let root = self.reader.get().unwrap();
+        resolve_tags(
+            root.get_tags().unwrap(),
+            root.get_tag_ids().unwrap(),
+            root.get_tag_set(),
+            self.strings,
+            self.tag_sets,
+        )
     }
 }
 
-impl<'a> TryFrom<&'a [u8]> for Node<'a> {
-    type Error = Box<dyn Error>;
+impl<'a> Node<'a> {
+    fn from_segments(
+        segments: Segments<'a>,
+        context: DecodeContext<'a>,
+    ) -> Result<Self, capnp::Error> {
+        let reader: TypedReader<_, messages_capnp::node::Owned> =
+            capnp::message::Reader::new(segments, context.options).into_typed();
 
-    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
-        let options = ReaderOptions::new();
-        let segments = BufferSegments::new(bytes, options)?;
+        // read the root, its lists and the strings in them once up front, so that
+        // malformed values (or values that exceed the traversal limit) are reported
+        // here rather than making the accessors panic
+        let root = reader.get()?;
+        check_tags(root.get_tags()?, root.get_tag_ids()?, context.strings)?;
+        check_tag_set(root.get_tag_set(), context.tag_sets)?;
+        check_metadata(root.has_metadata(), || root.get_metadata())?;
 
         Ok(Self {
-            reader: capnp::message::Reader::new(segments, options).into_typed(),
+            reader,
+            strings: context.strings,
+            tag_sets: context.tag_sets,
         })
     }
 }
 
+impl WithMetadata for Node<'_> {
+    fn metadata(&self) -> Option<Metadata<'_>> {
+        let root = self.reader.get().unwrap();
+        if root.has_metadata() {
+            Some(Metadata {
+                reader: root.get_metadata().unwrap(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Decode<'a> for Node<'a> {
+    fn decode(bytes: &'a [u8], context: DecodeContext<'a>) -> Result<Self, capnp::Error> {
+        Self::from_segments(Segments::read(bytes, context)?, context)
+    }
+
+    fn decode_in(
+        bytes: &'a [u8],
+        context: DecodeContext<'a>,
+        buffer: &'a mut DecodeBuffer,
+    ) -> Result<Self, capnp::Error> {
+        Self::from_segments(Segments::read_in(bytes, context, buffer)?, context)
+    }
+}
+
+impl Reborrow for Node<'_> {
+    type At<'b> = Node<'b>;
+}
+
+impl<'a> TryFrom<&'a [u8]> for Node<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        Ok(Self::decode(bytes, DecodeContext::default())?)
+    }
+}
+
 /// A reader for an OSM Way stored in the `ways` table, including its tags, metadata, and list of constituent Nodes.
 pub struct Way<'a> {
-    reader: TypedReader<BufferSegments<&'a [u8]>, messages_capnp::way::Owned>,
+    reader: TypedReader<Segments<'a>, messages_capnp::way::Owned>,
+    strings: &'a StringTable,
+    tag_sets: &'a TagSetTable,
 }
 
 impl<'a> Way<'a> {
@@ -87,16 +731,21 @@ impl<'a> Way<'a> {
         self.tags().find(|(k, _)| k == &key).map(|(_, v)| v)
     }
 
+    /// Get the element's name in the preferred languages. See [Node::name].
+    pub fn name(&'a self, languages: &[&str]) -> Option<&'a str> {
+        localized_name(self.tags(), languages)
+    }
+
     /// Returns an iterator of key-value pairs for all of the tags on this element.
     pub fn tags(&'a self) -> impl Iterator<Item = (&'a str, &'a str)> {
-        self.reader
-            .get()
-            .unwrap()
-            .get_tags()
-            .unwrap()
-            .iter()
-            .map(|v| v.unwrap().to_str().unwrap())
-            .tuples::<(&'a str, &'a str)>()
+        let root = self.reader.get().unwrap();
+        resolve_tags(
+            root.get_tags().unwrap(),
+            root.get_tag_ids().unwrap(),
+            root.get_tag_set(),
+            self.strings,
+            self.tag_sets,
+        )
     }
 
     /// Returns the IDs of the Nodes that make up this Way
@@ -104,6 +753,43 @@ impl<'a> Way<'a> {
         self.reader.get().unwrap().get_nodes().unwrap().iter()
     }
 
+    /// Returns the bounding box of the Way's nodes, looking up their locations in the given
+    /// table. Nodes missing from the table are ignored; returns None if none are found.
+    /// [crate::Transaction::way_bbox] is faster in databases with a way_bbox table.
+    pub fn bbox(&'a self, locations: &Locations) -> Result<Option<BoundingBox>, Error> {
+        let nodes: Vec<u64> = self.nodes().collect();
+        Ok(locations
+            .get_many(&nodes)?
+            .into_iter()
+            .flatten()
+            .map(|loc| (loc.lon(), loc.lat(), loc.lon(), loc.lat()))
+            .fold(None, extend_bbox))
+    }
+
+    /// Returns the coordinates of the Way's nodes in order, as (lon, lat) in degrees,
+    /// looking up their locations in the given table. Nodes missing from the table are
+    /// handled according to the policy: either the way's geometry is an error
+    /// ([Error::Geometry]), or they are left out.
+    #[cfg(feature = "export")]
+    pub fn coordinates(
+        &'a self,
+        locations: &Locations,
+        missing: MissingNodePolicy,
+    ) -> Result<Vec<(f64, f64)>, Error> {
+        let nodes: Vec<u64> = self.nodes().collect();
+        let mut coordinates = Vec::with_capacity(nodes.len());
+        for (&id, location) in nodes.iter().zip(locations.get_many(&nodes)?) {
+            match (location, missing) {
+                (Some(loc), _) => coordinates.push((loc.lon(), loc.lat())),
+                (None, MissingNodePolicy::Error) => {
+                    return Err(GeometryError::MissingNode(id).into())
+                }
+                (None, MissingNodePolicy::Skip) => {}
+            }
+        }
+        Ok(coordinates)
+    }
+
     /// Returns if the way is a closed ring (i.e. its first and last node have the same ID)
     pub fn is_closed(&self) -> bool {
         // TODO: haven't considered if this is correct when way contains zero or one nodes
@@ -114,22 +800,75 @@ impl<'a> Way<'a> {
     }
 }
 
-impl<'a> TryFrom<&'a [u8]> for Way<'a> {
-    type Error = Box<dyn Error>;
+impl<'a> Way<'a> {
+    fn from_segments(
+        segments: Segments<'a>,
+        context: DecodeContext<'a>,
+    ) -> Result<Self, capnp::Error> {
+        let reader: TypedReader<_, messages_capnp::way::Owned> =
+            capnp::message::Reader::new(segments, context.options).into_typed();
 
-    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
-        let options = ReaderOptions::new();
-        let segments = BufferSegments::new(bytes, options)?;
+        // read the root, its lists and the strings in them once up front, so that
+        // malformed values (or values that exceed the traversal limit) are reported
+        // here rather than making the accessors panic
+        let root = reader.get()?;
+        root.get_nodes()?;
+        check_tags(root.get_tags()?, root.get_tag_ids()?, context.strings)?;
+        check_tag_set(root.get_tag_set(), context.tag_sets)?;
+        check_metadata(root.has_metadata(), || root.get_metadata())?;
 
         Ok(Self {
-            reader: capnp::message::Reader::new(segments, options).into_typed(),
+            reader,
+            strings: context.strings,
+            tag_sets: context.tag_sets,
         })
     }
 }
 
+impl WithMetadata for Way<'_> {
+    fn metadata(&self) -> Option<Metadata<'_>> {
+        let root = self.reader.get().unwrap();
+        if root.has_metadata() {
+            Some(Metadata {
+                reader: root.get_metadata().unwrap(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Decode<'a> for Way<'a> {
+    fn decode(bytes: &'a [u8], context: DecodeContext<'a>) -> Result<Self, capnp::Error> {
+        Self::from_segments(Segments::read(bytes, context)?, context)
+    }
+
+    fn decode_in(
+        bytes: &'a [u8],
+        context: DecodeContext<'a>,
+        buffer: &'a mut DecodeBuffer,
+    ) -> Result<Self, capnp::Error> {
+        Self::from_segments(Segments::read_in(bytes, context, buffer)?, context)
+    }
+}
+
+impl Reborrow for Way<'_> {
+    type At<'b> = Way<'b>;
+}
+
+impl<'a> TryFrom<&'a [u8]> for Way<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        Ok(Self::decode(bytes, DecodeContext::default())?)
+    }
+}
+
 /// A reader for an OSM Relation in the `relations` table, including its tags, metadata, and list of members.
 pub struct Relation<'a> {
-    reader: TypedReader<BufferSegments<&'a [u8]>, messages_capnp::relation::Owned>,
+    reader: TypedReader<Segments<'a>, messages_capnp::relation::Owned>,
+    strings: &'a StringTable,
+    tag_sets: &'a TagSetTable,
 }
 
 impl<'a> Relation<'a> {
@@ -138,43 +877,210 @@ impl<'a> Relation<'a> {
         self.tags().find(|(k, _)| k == &key).map(|(_, v)| v)
     }
 
+    /// Get the element's name in the preferred languages. See [Node::name].
+    pub fn name(&'a self, languages: &[&str]) -> Option<&'a str> {
+        localized_name(self.tags(), languages)
+    }
+
     /// Returns an iterator of key-value pairs for all of the tags on this element.
     pub fn tags(&'a self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        let root = self.reader.get().unwrap();
+        resolve_tags(
+            root.get_tags().unwrap(),
+            root.get_tag_ids().unwrap(),
+            root.get_tag_set(),
+            self.strings,
+            self.tag_sets,
+        )
+    }
+
+    /// Returns the members of this Relation. See [RelationMember].
+    pub fn members(&'a self) -> impl Iterator<Item = RelationMember<'a>> {
         self.reader
             .get()
             .unwrap()
-            .get_tags()
+            .get_members()
             .unwrap()
             .iter()
-            .map(|v| v.unwrap().to_str().unwrap())
-            .tuples::<(&'a str, &'a str)>()
+            .map(|v| RelationMember { reader: v })
     }
 
-    /// Returns the members of this Relation. See [RelationMember].
-    pub fn members(&'a self) -> impl Iterator<Item = RelationMember<'a>> {
-        self.reader
+    /// Returns the members of this Relation as (role, element) pairs, fetching each
+    /// member from the appropriate table of the given Transaction as the iterator
+    /// advances. Members which aren't found are yielded as [Element::Missing], and
+    /// members which can't be read as errors.
+    pub fn resolved_members<'txn: 'a>(
+        &'a self,
+        txn: &'txn Transaction,
+    ) -> Result<impl Iterator<Item = Result<(&'a str, Element<'txn>), Error>> + 'a, Error> {
+        let locations = txn.locations()?;
+        let nodes = txn.nodes()?;
+        let ways = txn.ways()?;
+        let relations = txn.relations()?;
+
+        Ok(self
+            .reader
             .get()
             .unwrap()
             .get_members()
             .unwrap()
             .iter()
-            .map(|v| RelationMember { reader: v })
+            .map(move |reader| {
+                let member = RelationMember { reader };
+                let id = member.id();
+                let element = match id {
+                    ElementId::Node(node_id) => match locations.get(node_id)? {
+                        Some(location) => Element::Node {
+                            id: node_id,
+                            location,
+                            node: nodes.get(node_id)?,
+                        },
+                        None => Element::Missing(id),
+                    },
+                    ElementId::Way(way_id) => match ways.get(way_id)? {
+                        Some(way) => Element::Way { id: way_id, way },
+                        None => Element::Missing(id),
+                    },
+                    ElementId::Relation(relation_id) => match relations.get(relation_id)? {
+                        Some(relation) => Element::Relation {
+                            id: relation_id,
+                            relation,
+                        },
+                        None => Element::Missing(id),
+                    },
+                };
+                Ok((member.reader.get_role().unwrap().to_str().unwrap(), element))
+            }))
+    }
+
+    /// Returns the bounding box of the Relation's members, including the members of member
+    /// relations (recursively), looking up their nodes and ways in the given tables.
+    /// Members missing from the tables are ignored, and each relation is only visited
+    /// once (so cycles of relations which are members of each other terminate). Relations
+    /// nested more than [MAX_RELATION_DEPTH] levels below this one are ignored too.
+    /// Returns None if no member nodes are found.
+    pub fn bbox(
+        &'a self,
+        relations: &Relations,
+        ways: &Ways,
+        locations: &Locations,
+    ) -> Result<Option<BoundingBox>, Error> {
+        self.bbox_visiting(relations, ways, locations, &mut HashSet::new(), 0)
+    }
+
+    fn bbox_visiting(
+        &'a self,
+        relations: &Relations,
+        ways: &Ways,
+        locations: &Locations,
+        visited: &mut HashSet<u64>,
+        depth: usize,
+    ) -> Result<Option<BoundingBox>, Error> {
+        let mut nodes = vec![];
+        let mut bbox = None;
+        for member in self.members() {
+            let other = match member.id() {
+                ElementId::Node(id) => {
+                    nodes.push(id);
+                    continue;
+                }
+                ElementId::Way(id) => match ways.get(id)? {
+                    Some(way) => way.bbox(locations)?,
+                    None => None,
+                },
+                ElementId::Relation(id) => {
+                    if depth >= MAX_RELATION_DEPTH || !visited.insert(id) {
+                        continue;
+                    }
+                    match relations.get(id)? {
+                        Some(rel) => {
+                            rel.bbox_visiting(relations, ways, locations, visited, depth + 1)?
+                        }
+                        None => None,
+                    }
+                }
+            };
+            if let Some(other) = other {
+                bbox = extend_bbox(bbox, other);
+            }
+        }
+
+        Ok(locations
+            .get_many(&nodes)?
+            .into_iter()
+            .flatten()
+            .map(|loc| (loc.lon(), loc.lat(), loc.lon(), loc.lat()))
+            .fold(bbox, extend_bbox))
     }
 }
 
-impl<'a> TryFrom<&'a [u8]> for Relation<'a> {
-    type Error = Box<dyn Error>;
+impl<'a> Relation<'a> {
+    fn from_segments(
+        segments: Segments<'a>,
+        context: DecodeContext<'a>,
+    ) -> Result<Self, capnp::Error> {
+        let reader: TypedReader<_, messages_capnp::relation::Owned> =
+            capnp::message::Reader::new(segments, context.options).into_typed();
 
-    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
-        let options = ReaderOptions::new();
-        let segments = BufferSegments::new(bytes, options)?;
+        // read the root, its lists and the strings in them once up front, so that
+        // malformed values (or values that exceed the traversal limit) are reported
+        // here rather than making the accessors panic
+        let root = reader.get()?;
+        check_tags(root.get_tags()?, root.get_tag_ids()?, context.strings)?;
+        for member in root.get_members()?.iter() {
+            member.get_type()?;
+            member.get_role()?.to_str()?;
+        }
+        check_tag_set(root.get_tag_set(), context.tag_sets)?;
+        check_metadata(root.has_metadata(), || root.get_metadata())?;
 
         Ok(Self {
-            reader: capnp::message::Reader::new(segments, options).into_typed(),
+            reader,
+            strings: context.strings,
+            tag_sets: context.tag_sets,
         })
     }
 }
 
+impl WithMetadata for Relation<'_> {
+    fn metadata(&self) -> Option<Metadata<'_>> {
+        let root = self.reader.get().unwrap();
+        if root.has_metadata() {
+            Some(Metadata {
+                reader: root.get_metadata().unwrap(),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> Decode<'a> for Relation<'a> {
+    fn decode(bytes: &'a [u8], context: DecodeContext<'a>) -> Result<Self, capnp::Error> {
+        Self::from_segments(Segments::read(bytes, context)?, context)
+    }
+
+    fn decode_in(
+        bytes: &'a [u8],
+        context: DecodeContext<'a>,
+        buffer: &'a mut DecodeBuffer,
+    ) -> Result<Self, capnp::Error> {
+        Self::from_segments(Segments::read_in(bytes, context, buffer)?, context)
+    }
+}
+
+impl Reborrow for Relation<'_> {
+    type At<'b> = Relation<'b>;
+}
+
+impl<'a> TryFrom<&'a [u8]> for Relation<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        Ok(Self::decode(bytes, DecodeContext::default())?)
+    }
+}
+
 /// A reader for a member reference of an OSM Relation. Created by calling [Relation::members]
 pub struct RelationMember<'a> {
     reader: messages_capnp::relation_member::Reader<'a>,
@@ -199,27 +1105,163 @@ impl<'a> RelationMember<'a> {
     }
 }
 
-pub struct Region {
-    pub(crate) cells: s2::cellunion::CellUnion,
+/// A reader for a value in the `changesets` table, which summarizes the element versions
+/// created in one changeset.
+pub struct Changeset<'a> {
+    reader: TypedReader<Segments<'a>, messages_capnp::changeset::Owned>,
+}
+
+impl<'a> Changeset<'a> {
+    /// The user ID of the author of the changeset.
+    pub fn uid(&self) -> u32 {
+        self.reader.get().unwrap().get_uid()
+    }
+
+    /// The username of the author of the changeset.
+    pub fn user(&'a self) -> &'a str {
+        self.reader
+            .get()
+            .unwrap()
+            .get_user()
+            .unwrap()
+            .to_str()
+            .unwrap()
+    }
+
+    /// The earliest timestamp of the element versions created in the changeset, in
+    /// seconds since the Unix epoch.
+    pub fn min_timestamp(&self) -> u64 {
+        self.reader.get().unwrap().get_min_timestamp()
+    }
+
+    /// The latest timestamp of the element versions created in the changeset, in
+    /// seconds since the Unix epoch.
+    pub fn max_timestamp(&self) -> u64 {
+        self.reader.get().unwrap().get_max_timestamp()
+    }
+
+    /// The number of node versions created in the changeset.
+    pub fn node_count(&self) -> u32 {
+        self.reader.get().unwrap().get_node_count()
+    }
+
+    /// The number of way versions created in the changeset.
+    pub fn way_count(&self) -> u32 {
+        self.reader.get().unwrap().get_way_count()
+    }
+
+    /// The number of relation versions created in the changeset.
+    pub fn relation_count(&self) -> u32 {
+        self.reader.get().unwrap().get_relation_count()
+    }
+
+    /// The bounding box of the node versions created in the changeset, as (west, south,
+    /// east, north) in degrees. Returns None if the changeset didn't create any nodes.
+    pub fn bbox(&self) -> Option<BoundingBox> {
+        let root = self.reader.get().unwrap();
+        if !root.has_bbox() {
+            return None;
+        }
+        let bbox = root.get_bbox().unwrap();
+        let degrees = |v: i32| v as f64 / COORDINATE_PRECISION as f64;
+        Some((
+            degrees(bbox.get_west()),
+            degrees(bbox.get_south()),
+            degrees(bbox.get_east()),
+            degrees(bbox.get_north()),
+        ))
+    }
 }
 
-lazy_static! {
-    static ref COVERER: s2::region::RegionCoverer = {
-        s2::region::RegionCoverer {
-            min_level: 4,
-            max_level: 16,
-            level_mod: 1,
-            max_cells: 8,
+impl<'a> Decode<'a> for Changeset<'a> {
+    fn decode(bytes: &'a [u8], context: DecodeContext<'a>) -> Result<Self, capnp::Error> {
+        let reader: TypedReader<_, messages_capnp::changeset::Owned> =
+            capnp::message::Reader::new(Segments::read(bytes, context)?, context.options)
+                .into_typed();
+
+        // read the root and its fields once up front, so that malformed values are
+        // reported here
+        let root = reader.get()?;
+        root.get_user()?.to_str()?;
+        if root.has_bbox() {
+            root.get_bbox()?;
         }
-    };
+
+        Ok(Self { reader })
+    }
 }
 
+impl Reborrow for Changeset<'_> {
+    type At<'b> = Changeset<'b>;
+}
+
+#[cfg(feature = "spatial")]
+pub struct Region {
+    pub(crate) cells: s2::cellunion::CellUnion,
+}
+
+#[cfg(feature = "spatial")]
+static COVERER: s2::region::RegionCoverer = s2::region::RegionCoverer {
+    min_level: 4,
+    max_level: 16,
+    level_mod: 1,
+    max_cells: 8,
+};
+
+#[cfg(feature = "spatial")]
 impl Region {
     pub fn from_bbox(west: f64, south: f64, east: f64, north: f64) -> Self {
         let rect = s2::rect::Rect::from_degrees(south, west, north, east);
         let cells = COVERER.covering(&rect);
         Self { cells }
     }
+
+    /// Returns the IDs of the S2 cells covering the region, in ascending order. Spatial
+    /// queries return the nodes in the cells at CELL_INDEX_LEVEL within these cells.
+    pub fn cell_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.cells.0.iter().map(|cell_id| cell_id.0)
+    }
+
+    /// Make a Region covering a web map tile, given its zoom level, column and row in the
+    /// XYZ (a.k.a. slippy map) scheme used by most tile servers. Returns an error if the
+    /// tile doesn't exist at that zoom level.
+    pub fn from_tile(z: u8, x: u32, y: u32) -> Result<Self, Error> {
+        let (west, south, east, north) = tile_bounds(z, x, y)?;
+        Ok(Self::from_bbox(west, south, east, north))
+    }
+
+    /// Make a Region covering several web map tiles (see [Region::from_tile]).
+    pub fn from_tiles(tiles: &[(u8, u32, u32)]) -> Result<Self, Error> {
+        let mut cells = vec![];
+        for &(z, x, y) in tiles {
+            cells.extend(Self::from_tile(z, x, y)?.cells.0);
+        }
+        let mut cells = s2::cellunion::CellUnion(cells);
+        cells.normalize();
+        Ok(Self { cells })
+    }
+}
+
+/// The bounds of a web map tile, as west, south, east and north in degrees
+#[cfg(feature = "spatial")]
+fn tile_bounds(z: u8, x: u32, y: u32) -> Result<(f64, f64, f64, f64), Error> {
+    if z > 30 || x >= 1 << z || y >= 1 << z {
+        return Err(format!("tile {}/{}/{} doesn't exist", z, x, y).into());
+    }
+    let n = (1u32 << z) as f64;
+    let lon = |x: f64| x / n * 360.0 - 180.0;
+    let lat = |y: f64| {
+        (std::f64::consts::PI * (1.0 - 2.0 * y / n))
+            .sinh()
+            .atan()
+            .to_degrees()
+    };
+    Ok((
+        lon(x as f64),
+        lat(y as f64 + 1.0),
+        lon(x as f64 + 1.0),
+        lat(y as f64),
+    ))
 }
 
 // pub struct Tag<'a>(&'a str, &'a str);
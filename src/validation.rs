@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+
+use crate::database::{Locations, Relations, Transaction, Ways};
+use crate::types::{ElementId, Way};
+use crate::Error;
+
+/// Maximum number of missing IDs recorded for each element in a validation report.
+pub const SAMPLE_SIZE: usize = 10;
+
+/// A way which references nodes that are missing from the Locations table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingNodeRefs {
+    pub way_id: u64,
+    /// Total number of node refs in the way.
+    pub node_count: usize,
+    /// Number of node refs which are missing from the Locations table.
+    pub missing_count: usize,
+    /// IDs of up to [SAMPLE_SIZE] of the missing nodes.
+    pub sample: Vec<u64>,
+}
+
+/// What's wrong with a relation member.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemberProblem {
+    /// There's no element with the member's type and ID.
+    Missing,
+    /// There's no element with the member's type and ID, but there are elements of other
+    /// types with that ID, so the member's type may have been recorded incorrectly.
+    /// (IDs are only unique within each element type, so this is a hint, not a certainty.)
+    TypeMismatch { found: Vec<ElementId> },
+}
+
+/// A relation member which doesn't refer to an element in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidMember {
+    /// Position of the member in the relation's member list.
+    pub index: usize,
+    pub member: ElementId,
+    pub role: String,
+    pub problem: MemberProblem,
+}
+
+/// A relation with members which don't refer to elements in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidRelationMembers {
+    pub relation_id: u64,
+    /// Total number of members in the relation.
+    pub member_count: usize,
+    /// Number of members which don't refer to elements in the database.
+    pub invalid_count: usize,
+    /// Up to [SAMPLE_SIZE] of the invalid members.
+    pub sample: Vec<InvalidMember>,
+}
+
+/// A problem with the geometry of a way.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeometryProblem {
+    /// The way has fewer nodes than its shape needs: two for a line, or four (including
+    /// the repeated first node) for a closed ring.
+    TooFewNodes,
+    /// The node at this position in the way's node list is the same as the one before it.
+    RepeatedNode { index: usize, node_id: u64 },
+    /// The segment starting at this position joins two distinct nodes with exactly the
+    /// same coordinates.
+    ZeroLengthSegment { index: usize },
+    /// The segments starting at these positions cross or overlap, at about this (lon, lat)
+    /// location. For closed ways, this makes the ring invalid as a polygon boundary.
+    SelfIntersection {
+        segments: (usize, usize),
+        location: (f64, f64),
+    },
+}
+
+/// A way with problems in its geometry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WayGeometryProblems {
+    pub way_id: u64,
+    pub problems: Vec<GeometryProblem>,
+}
+
+/// A problem with how relations are nested as members of each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NestingProblem {
+    /// These relations are members of each other, directly or through the others, so
+    /// following the members of any of them leads back to it. A relation which is a
+    /// member of itself is a cycle of one. The IDs are in ascending order.
+    Cycle { relation_ids: Vec<u64> },
+    /// The relation has relations nested this many levels below it (a relation whose
+    /// members include a relation whose members include a relation is nested two levels
+    /// deep), more than the maximum checked for. Only the outermost of the relations
+    /// which are too deep is reported, not the relations it is nested in.
+    TooDeep { relation_id: u64, depth: usize },
+}
+
+/// A point, in units of 1e-7 degrees as stored in the Locations table
+type Point = (i64, i64);
+
+/// Orientation of the triangle (a, b, c): positive if counterclockwise, negative if
+/// clockwise, and zero if the points are collinear.
+fn orientation(a: Point, b: Point, c: Point) -> i64 {
+    ((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)).signum()
+}
+
+/// Whether c lies within the bounding box of the segment (a, b). For a point collinear
+/// with the segment, this means it lies on the segment.
+fn within(a: Point, b: Point, c: Point) -> bool {
+    a.0.min(b.0) <= c.0 && c.0 <= a.0.max(b.0) && a.1.min(b.1) <= c.1 && c.1 <= a.1.max(b.1)
+}
+
+/// Find where the segments (a, b) and (c, d) intersect, if they do. Coordinates are
+/// integers (in units of 1e-7 degrees), so the test is exact. Returns the intersection
+/// point, or a point of the overlap for collinear segments.
+fn intersection((a, b): (Point, Point), (c, d): (Point, Point)) -> Option<(f64, f64)> {
+    let (o1, o2) = (orientation(a, b, c), orientation(a, b, d));
+    let (o3, o4) = (orientation(c, d, a), orientation(c, d, b));
+
+    if o1 != o2 && o3 != o4 && o1 != 0 && o2 != 0 && o3 != 0 && o4 != 0 {
+        // a proper crossing
+        let (x1, y1, x2, y2) = (a.0 as f64, a.1 as f64, b.0 as f64, b.1 as f64);
+        let (x3, y3, x4, y4) = (c.0 as f64, c.1 as f64, d.0 as f64, d.1 as f64);
+        let t = ((x1 - x3) * (y3 - y4) - (y1 - y3) * (x3 - x4))
+            / ((x1 - x2) * (y3 - y4) - (y1 - y2) * (x3 - x4));
+        return Some((x1 + t * (x2 - x1), y1 + t * (y2 - y1)));
+    }
+
+    // an endpoint of one segment touching the other
+    let touching = [(o1, a, b, c), (o2, a, b, d), (o3, c, d, a), (o4, c, d, b)];
+    touching
+        .into_iter()
+        .find(|&(o, p, q, r)| o == 0 && within(p, q, r))
+        .map(|(_, _, _, r)| (r.0 as f64, r.1 as f64))
+}
+
+/// Checks the references between elements in a database, and the geometry of ways.
+/// Missing references are expected at the edges of an extract which was clipped to a
+/// region, but elsewhere usually indicate a bug in whatever produced the data.
+pub struct Validator<'txn> {
+    locations: Locations<'txn>,
+    ways: Ways<'txn>,
+    relations: Relations<'txn>,
+}
+
+impl<'txn> Validator<'txn> {
+    pub fn new(txn: &'txn Transaction) -> Result<Self, Error> {
+        Ok(Self {
+            locations: txn.locations()?,
+            ways: txn.ways()?,
+            relations: txn.relations()?,
+        })
+    }
+
+    /// Check the node refs of a single way. Returns None if the way isn't found or all
+    /// its nodes are present.
+    pub fn way_missing_node_refs(&self, way_id: u64) -> Result<Option<MissingNodeRefs>, Error> {
+        let Some(way) = self.ways.get(way_id)? else {
+            return Ok(None);
+        };
+
+        let mut node_count = 0;
+        let mut missing_count = 0;
+        let mut sample = vec![];
+        for node_id in way.nodes() {
+            node_count += 1;
+            if !self.locations.contains(node_id)? {
+                missing_count += 1;
+                if sample.len() < SAMPLE_SIZE {
+                    sample.push(node_id);
+                }
+            }
+        }
+
+        Ok((missing_count > 0).then_some(MissingNodeRefs {
+            way_id,
+            node_count,
+            missing_count,
+            sample,
+        }))
+    }
+
+    /// Iterate over the ways which reference nodes missing from the Locations table. This
+    /// scans the whole Ways table.
+    pub fn missing_node_refs(&self) -> impl Iterator<Item = Result<MissingNodeRefs, Error>> + '_ {
+        self.ways
+            .ids()
+            .filter_map(|way_id| self.way_missing_node_refs(way_id).transpose())
+    }
+
+    /// Check whether an element exists. Nodes are looked up in the Locations table, since
+    /// untagged nodes aren't stored in the Nodes table.
+    fn exists(&self, element: ElementId) -> Result<bool, Error> {
+        match element {
+            ElementId::Node(id) => self.locations.contains(id),
+            ElementId::Way(id) => self.ways.contains(id),
+            ElementId::Relation(id) => self.relations.contains(id),
+        }
+    }
+
+    /// Check the members of a single relation. Returns None if the relation isn't found
+    /// or all its members exist.
+    pub fn relation_invalid_members(
+        &self,
+        relation_id: u64,
+    ) -> Result<Option<InvalidRelationMembers>, Error> {
+        let Some(relation) = self.relations.get(relation_id)? else {
+            return Ok(None);
+        };
+
+        let mut member_count = 0;
+        let mut invalid_count = 0;
+        let mut sample = vec![];
+        for (index, member) in relation.members().enumerate() {
+            member_count += 1;
+            let element = member.id();
+            if self.exists(element)? {
+                continue;
+            }
+
+            invalid_count += 1;
+            if sample.len() < SAMPLE_SIZE {
+                let id = match element {
+                    ElementId::Node(id) | ElementId::Way(id) | ElementId::Relation(id) => id,
+                };
+                let mut found = vec![];
+                for other in [
+                    ElementId::Node(id),
+                    ElementId::Way(id),
+                    ElementId::Relation(id),
+                ] {
+                    if other != element && self.exists(other)? {
+                        found.push(other);
+                    }
+                }
+                let problem = if found.is_empty() {
+                    MemberProblem::Missing
+                } else {
+                    MemberProblem::TypeMismatch { found }
+                };
+
+                sample.push(InvalidMember {
+                    index,
+                    member: element,
+                    role: member.role().to_string(),
+                    problem,
+                });
+            }
+        }
+
+        Ok((invalid_count > 0).then_some(InvalidRelationMembers {
+            relation_id,
+            member_count,
+            invalid_count,
+            sample,
+        }))
+    }
+
+    /// Iterate over the relations with members which don't refer to elements in the
+    /// database. This scans the whole Relations table.
+    pub fn invalid_relation_members(
+        &self,
+    ) -> impl Iterator<Item = Result<InvalidRelationMembers, Error>> + '_ {
+        self.relations
+            .ids()
+            .filter_map(|relation_id| self.relation_invalid_members(relation_id).transpose())
+    }
+
+    /// Check the geometry of a single way. Returns None if the way isn't found, has no
+    /// problems, or references nodes missing from the Locations table (see
+    /// [Self::way_missing_node_refs]).
+    pub fn way_geometry_problems(&self, way_id: u64) -> Result<Option<WayGeometryProblems>, Error> {
+        let Some(way) = self.ways.get(way_id)? else {
+            return Ok(None);
+        };
+        let problems = self.geometry_problems_of(&way)?;
+        Ok(problems
+            .filter(|problems| !problems.is_empty())
+            .map(|problems| WayGeometryProblems { way_id, problems }))
+    }
+
+    fn geometry_problems_of(&self, way: &Way) -> Result<Option<Vec<GeometryProblem>>, Error> {
+        let nodes: Vec<u64> = way.nodes().collect();
+        // coordinates in units of 1e-7 degrees, as stored
+        let mut coords = Vec::with_capacity(nodes.len());
+        for &node_id in nodes.iter() {
+            let Some(location) = self.locations.get(node_id)? else {
+                return Ok(None);
+            };
+            let lon = (location.lon() * 1e7).round() as i64;
+            let lat = (location.lat() * 1e7).round() as i64;
+            coords.push((lon, lat));
+        }
+
+        let mut problems = vec![];
+        let closed = nodes.len() > 1 && nodes.first() == nodes.last();
+        if nodes.len() < 2 || (closed && nodes.len() < 4) {
+            problems.push(GeometryProblem::TooFewNodes);
+        }
+
+        for index in 1..nodes.len() {
+            if nodes[index] == nodes[index - 1] {
+                problems.push(GeometryProblem::RepeatedNode {
+                    index,
+                    node_id: nodes[index],
+                });
+            } else if coords[index] == coords[index - 1] {
+                problems.push(GeometryProblem::ZeroLengthSegment { index: index - 1 });
+            }
+        }
+
+        // compare every pair of non-adjacent segments, skipping zero-length segments
+        // (which are reported above)
+        let segments: Vec<(usize, (Point, Point))> = (1..coords.len())
+            .filter(|&i| coords[i] != coords[i - 1])
+            .map(|i| (i - 1, (coords[i - 1], coords[i])))
+            .collect();
+        for (n, &(i, first)) in segments.iter().enumerate() {
+            for (m, &(j, second)) in segments.iter().enumerate().skip(n + 1) {
+                // adjacent segments share an endpoint, as do the first and last segments
+                // of a closed way
+                if m == n + 1 || (closed && n == 0 && m == segments.len() - 1) {
+                    continue;
+                }
+                if let Some((lon, lat)) = intersection(first, second) {
+                    problems.push(GeometryProblem::SelfIntersection {
+                        segments: (i, j),
+                        location: (lon / 1e7, lat / 1e7),
+                    });
+                }
+            }
+        }
+
+        Ok(Some(problems))
+    }
+
+    /// Iterate over the ways with problems in their geometry. This scans the whole Ways
+    /// table, and checks every pair of segments in each way.
+    pub fn geometry_problems(
+        &self,
+    ) -> impl Iterator<Item = Result<WayGeometryProblems, Error>> + '_ {
+        self.ways.try_iter().filter_map(|entry| {
+            let problems = entry.and_then(|(way_id, way)| {
+                let problems = self.geometry_problems_of(&way)?;
+                Ok(problems
+                    .filter(|problems| !problems.is_empty())
+                    .map(|problems| WayGeometryProblems { way_id, problems }))
+            });
+            problems.transpose()
+        })
+    }
+
+    /// Find the cycles of relations which are members of each other, and the relations
+    /// with other relations nested more than max_depth levels below them. Members which
+    /// are missing from the database are ignored, and the depth of a relation in a cycle
+    /// only counts the relations nested below the cycle. This scans the whole Relations
+    /// table, and keeps the relation members of every relation in memory.
+    pub fn nesting_problems(&self, max_depth: usize) -> Result<Vec<NestingProblem>, Error> {
+        let ids: Vec<u64> = self.relations.ids().collect();
+        let positions: HashMap<u64, usize> = ids
+            .iter()
+            .enumerate()
+            .map(|(position, &id)| (id, position))
+            .collect();
+        let mut members: Vec<Vec<usize>> = Vec::with_capacity(ids.len());
+        for &id in ids.iter() {
+            let Some(relation) = self.relations.get(id)? else {
+                members.push(vec![]);
+                continue;
+            };
+            members.push(
+                relation
+                    .members()
+                    .filter_map(|member| match member.id() {
+                        ElementId::Relation(member_id) => positions.get(&member_id).copied(),
+                        _ => None,
+                    })
+                    .collect(),
+            );
+        }
+
+        let components = strongly_connected_components(&members);
+        let mut problems = vec![];
+        let mut component_of = vec![0; ids.len()];
+        for (index, component) in components.iter().enumerate() {
+            for &position in component {
+                component_of[position] = index;
+            }
+            let cycle = component.len() > 1 || members[component[0]].contains(&component[0]);
+            if cycle {
+                let mut relation_ids: Vec<u64> = component.iter().map(|&p| ids[p]).collect();
+                relation_ids.sort_unstable();
+                problems.push(NestingProblem::Cycle { relation_ids });
+            }
+        }
+
+        // components are found in reverse topological order, so the depths of the
+        // components nested in each one are known before it's reached
+        let mut depths = vec![0; components.len()];
+        for (index, component) in components.iter().enumerate() {
+            depths[index] = component
+                .iter()
+                .flat_map(|&position| &members[position])
+                .filter(|&&member| component_of[member] != index)
+                .map(|&member| depths[component_of[member]] + 1)
+                .max()
+                .unwrap_or(0);
+        }
+        let depth = |position: usize| depths[component_of[position]];
+
+        let mut nested_in_deeper = vec![false; ids.len()];
+        for (position, relation_members) in members.iter().enumerate() {
+            for &member in relation_members {
+                if depth(position) > depth(member) && depth(position) > max_depth {
+                    nested_in_deeper[member] = true;
+                }
+            }
+        }
+        for (position, &relation_id) in ids.iter().enumerate() {
+            if depth(position) > max_depth && !nested_in_deeper[position] {
+                problems.push(NestingProblem::TooDeep {
+                    relation_id,
+                    depth: depth(position),
+                });
+            }
+        }
+        Ok(problems)
+    }
+}
+
+/// Find the strongly connected components of a graph given as the edges from each
+/// vertex, with Tarjan's algorithm (iteratively, since the graph may be deep). Each
+/// component is found after every component reachable from it.
+fn strongly_connected_components(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    const UNVISITED: usize = usize::MAX;
+    let mut index = vec![UNVISITED; edges.len()];
+    let mut low_link = vec![0; edges.len()];
+    let mut on_stack = vec![false; edges.len()];
+    let mut stack = vec![];
+    let mut next_index = 0;
+    let mut components = vec![];
+
+    for root in 0..edges.len() {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // vertices being visited, with the position of the next edge to follow
+        let mut path = vec![(root, 0)];
+        index[root] = next_index;
+        low_link[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some((vertex, edge)) = path.last_mut() {
+            let vertex = *vertex;
+            if let Some(&next) = edges[vertex].get(*edge) {
+                *edge += 1;
+                if index[next] == UNVISITED {
+                    index[next] = next_index;
+                    low_link[next] = next_index;
+                    next_index += 1;
+                    stack.push(next);
+                    on_stack[next] = true;
+                    path.push((next, 0));
+                } else if on_stack[next] {
+                    low_link[vertex] = low_link[vertex].min(index[next]);
+                }
+                continue;
+            }
+
+            path.pop();
+            if let Some(&(parent, _)) = path.last() {
+                low_link[parent] = low_link[parent].min(low_link[vertex]);
+            }
+            if low_link[vertex] == index[vertex] {
+                let mut component = vec![];
+                loop {
+                    let member = stack.pop().unwrap();
+                    on_stack[member] = false;
+                    component.push(member);
+                    if member == vertex {
+                        break;
+                    }
+                }
+                components.push(component);
+            }
+        }
+    }
+    components
+}
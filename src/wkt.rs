@@ -0,0 +1,72 @@
+//! Writers for the Well-Known Text (WKT) representation of geometries. Coordinates are
+//! (longitude, latitude) pairs in degrees, written with 7 decimal places (the precision
+//! of OSM coordinates).
+
+use std::io::{self, Write};
+
+/// A (longitude, latitude) pair
+pub type Point = (f64, f64);
+
+fn write_coordinates(writer: &mut impl Write, points: &[Point]) -> io::Result<()> {
+    write!(writer, "(")?;
+    for (i, (lon, lat)) in points.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        write!(writer, "{:.7} {:.7}", lon, lat)?;
+    }
+    write!(writer, ")")
+}
+
+fn write_rings(writer: &mut impl Write, rings: &[Vec<Point>]) -> io::Result<()> {
+    write!(writer, "(")?;
+    for (i, ring) in rings.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        write_coordinates(writer, ring)?;
+    }
+    write!(writer, ")")
+}
+
+/// Write a point, e.g. `POINT (13.3777000 52.5162000)`.
+pub fn write_point(writer: &mut impl Write, (lon, lat): Point) -> io::Result<()> {
+    write!(writer, "POINT ({:.7} {:.7})", lon, lat)
+}
+
+/// Write a linestring, such as the geometry of an open way. An empty slice is written as
+/// `LINESTRING EMPTY`.
+pub fn write_linestring(writer: &mut impl Write, points: &[Point]) -> io::Result<()> {
+    if points.is_empty() {
+        return write!(writer, "LINESTRING EMPTY");
+    }
+    write!(writer, "LINESTRING ")?;
+    write_coordinates(writer, points)
+}
+
+/// Write a polygon, given its exterior ring followed by any interior rings (holes). Each
+/// ring should be closed (its last point the same as its first). An empty slice is
+/// written as `POLYGON EMPTY`.
+pub fn write_polygon(writer: &mut impl Write, rings: &[Vec<Point>]) -> io::Result<()> {
+    if rings.is_empty() {
+        return write!(writer, "POLYGON EMPTY");
+    }
+    write!(writer, "POLYGON ")?;
+    write_rings(writer, rings)
+}
+
+/// Write a multipolygon, given the rings of each polygon as for [write_polygon]. An
+/// empty slice is written as `MULTIPOLYGON EMPTY`.
+pub fn write_multipolygon(writer: &mut impl Write, polygons: &[Vec<Vec<Point>>]) -> io::Result<()> {
+    if polygons.is_empty() {
+        return write!(writer, "MULTIPOLYGON EMPTY");
+    }
+    write!(writer, "MULTIPOLYGON (")?;
+    for (i, rings) in polygons.iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        write_rings(writer, rings)?;
+    }
+    write!(writer, ")")
+}